@@ -0,0 +1,71 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compare verifying a block's worth of input signatures on one thread and on every core.
+//! Run with `cargo bench --bench sig_verify`.
+
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use blockstack_lib::burnchains::bitcoin::keys::{verify_batch, BitcoinPublicKey};
+use blockstack_lib::burnchains::{PrivateKey, PublicKey};
+use stacks_common::util::hash::Sha256Sum;
+use stacks_common::util::secp256k1::Secp256k1PrivateKey;
+
+const NUM_SIGS: u32 = 4_000;
+
+/// 4,000 (public key, hash, DER signature) checks, about as many as a full block's inputs
+fn make_sig_checks() -> Vec<(BitcoinPublicKey, Vec<u8>, Vec<u8>)> {
+    let mut checks = vec![];
+    for i in 0..NUM_SIGS {
+        let privkey = Secp256k1PrivateKey::new();
+        let hash = Sha256Sum::from_data(&i.to_be_bytes());
+        let sig = privkey
+            .sign(hash.as_bytes())
+            .unwrap()
+            .to_secp256k1_recoverable()
+            .unwrap()
+            .to_standard()
+            .serialize_der()
+            .to_vec();
+        checks.push((
+            BitcoinPublicKey::from_private(&privkey),
+            hash.as_bytes().to_vec(),
+            sig,
+        ));
+    }
+    checks
+}
+
+fn bench_verify_batch(c: &mut Criterion) {
+    let checks = make_sig_checks();
+    let num_cores = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+
+    let mut group = c.benchmark_group("verify_4k_sigs");
+    group.sample_size(10);
+    for num_threads in [1, num_cores].iter() {
+        group.bench_function(format!("{}_threads", num_threads), |b| {
+            b.iter(|| assert!(verify_batch(&checks, *num_threads).is_empty()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_verify_batch);
+criterion_main!(benches);
@@ -0,0 +1,85 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compare checking header hashes against their targets on one thread and on every core.
+//! Run with `cargo bench --bench spv_pow`.
+
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use blockstack_lib::burnchains::bitcoin::spv::{SpvClient, BLOCK_DIFFICULTY_CHUNK_SIZE};
+use blockstack_lib::burnchains::bitcoin::BitcoinNetworkType;
+use stacks_common::deps_common::bitcoin::blockdata::block::BlockHeader;
+use stacks_common::deps_common::bitcoin::network::serialize::BitcoinHash;
+use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+use stacks_common::util::uint::Uint256;
+
+const NUM_HEADERS: u64 = 100_000;
+
+/// A chain of 100,000 headers, in difficulty-interval-sized batches as the SPV client checks
+/// them.  Every target is the maximum, so every header is checked.
+fn make_pow_batches() -> Vec<Vec<(u64, BlockHeader, Uint256)>> {
+    let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Mainnet);
+    let mut prev_blockhash = genesis.bitcoin_hash();
+    let mut batches = vec![];
+    let mut batch = vec![];
+    for height in 1..(NUM_HEADERS + 1) {
+        let header = BlockHeader {
+            version: 0x20000000,
+            prev_blockhash,
+            merkle_root: Sha256dHash::from_data(&height.to_be_bytes()),
+            time: genesis.time + (height as u32) * 600,
+            bits: genesis.bits,
+            nonce: height as u32,
+        };
+        prev_blockhash = header.bitcoin_hash();
+        batch.push((height, header, Uint256::max()));
+        if (batch.len() as u64) == BLOCK_DIFFICULTY_CHUNK_SIZE {
+            batches.push(batch);
+            batch = vec![];
+        }
+    }
+    batches.push(batch);
+    batches
+}
+
+fn bench_find_insufficient_work(c: &mut Criterion) {
+    let batches = make_pow_batches();
+    let num_cores = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+
+    let mut group = c.benchmark_group("spv_pow_100k_headers");
+    group.sample_size(10);
+    for num_threads in [1, num_cores].iter() {
+        group.bench_function(format!("{}_threads", num_threads), |b| {
+            b.iter_batched(
+                || batches.clone(),
+                |batches| {
+                    for batch in batches.into_iter() {
+                        assert!(SpvClient::find_insufficient_work(batch, *num_threads).is_none());
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_find_insufficient_work);
+criterion_main!(benches);
@@ -19,6 +19,7 @@ use crate::burnchains::bitcoin::Error as btc_error;
 use crate::burnchains::Address;
 use stacks_common::address::b58 as base58;
 use stacks_common::address::c32::c32_address;
+use stacks_common::address::Error as b58_error;
 use stacks_common::deps_common::bech32;
 use stacks_common::deps_common::bech32::{u5, FromBase32, ToBase32};
 use stacks_common::deps_common::bitcoin::blockdata::opcodes::All as BtcOp;
@@ -82,6 +83,7 @@ pub const ADDRESS_VERSION_TESTNET_MULTISIG: u8 = 196;
 // segwit hrps
 pub const SEGWIT_MAINNET_HRP: &'static str = "bc";
 pub const SEGWIT_TESTNET_HRP: &'static str = "tb";
+pub const SEGWIT_REGTEST_HRP: &'static str = "bcrt";
 
 // segwit witnes versions
 pub const SEGWIT_V0: u8 = 0;
@@ -99,11 +101,13 @@ pub fn legacy_address_type_to_version_byte(
             ADDRESS_VERSION_MAINNET_MULTISIG
         }
         (LegacyBitcoinAddressType::PublicKeyHash, BitcoinNetworkType::Testnet)
-        | (LegacyBitcoinAddressType::PublicKeyHash, BitcoinNetworkType::Regtest) => {
+        | (LegacyBitcoinAddressType::PublicKeyHash, BitcoinNetworkType::Regtest)
+        | (LegacyBitcoinAddressType::PublicKeyHash, BitcoinNetworkType::Signet) => {
             ADDRESS_VERSION_TESTNET_SINGLESIG
         }
         (LegacyBitcoinAddressType::ScriptHash, BitcoinNetworkType::Testnet)
-        | (LegacyBitcoinAddressType::ScriptHash, BitcoinNetworkType::Regtest) => {
+        | (LegacyBitcoinAddressType::ScriptHash, BitcoinNetworkType::Regtest)
+        | (LegacyBitcoinAddressType::ScriptHash, BitcoinNetworkType::Signet) => {
             ADDRESS_VERSION_TESTNET_MULTISIG
         }
     }
@@ -165,6 +169,15 @@ pub fn segwit_hrp(mainnet: bool) -> &'static str {
     }
 }
 
+/// Get the HRP for a segwit address on the given network
+pub fn segwit_network_hrp(network_id: BitcoinNetworkType) -> &'static str {
+    match network_id {
+        BitcoinNetworkType::Mainnet => SEGWIT_MAINNET_HRP,
+        BitcoinNetworkType::Testnet | BitcoinNetworkType::Signet => SEGWIT_TESTNET_HRP,
+        BitcoinNetworkType::Regtest => SEGWIT_REGTEST_HRP,
+    }
+}
+
 impl LegacyBitcoinAddress {
     fn to_versioned_bytes(&self) -> [u8; 21] {
         let mut ret = [0; 21];
@@ -184,6 +197,14 @@ impl LegacyBitcoinAddress {
         base58::check_encode_slice(&versioned_bytes)
     }
 
+    /// Encode as b58check with the given version byte instead of the network's, e.g. for a
+    /// private network with its own address versions
+    pub fn to_b58_with_version(&self, version: u8) -> String {
+        let mut versioned_bytes = vec![version];
+        versioned_bytes.extend_from_slice(&self.bytes.0);
+        base58::check_encode_slice(&versioned_bytes)
+    }
+
     pub fn to_p2pkh_tx_out(bytes: &Hash160, value: u64) -> TxOut {
         let script_pubkey = BtcScriptBuilder::new()
             .push_opcode(BtcOp::OP_DUP)
@@ -212,8 +233,13 @@ impl LegacyBitcoinAddress {
 
     /// Instantiate a legacy address from a b58check string
     /// Note that the network type will be 'testnet' if there is a testnet or regtest version byte
+    /// Returns btc_error::InvalidChecksum if the string decodes but fails its checksum, and
+    /// btc_error::InvalidByteSequence if it is otherwise malformed.
     pub fn from_b58(addrb58: &str) -> Result<LegacyBitcoinAddress, btc_error> {
-        let bytes = base58::from_check(addrb58).map_err(|_e| btc_error::InvalidByteSequence)?;
+        let bytes = base58::from_check(addrb58).map_err(|e| match e {
+            b58_error::BadChecksum(..) => btc_error::InvalidChecksum,
+            _ => btc_error::InvalidByteSequence,
+        })?;
 
         if bytes.len() != 21 {
             test_debug!("Invalid address: {} bytes", bytes.len());
@@ -302,6 +328,20 @@ impl SegwitBitcoinAddress {
     }
 
     pub fn from_bech32(s: &str) -> Option<SegwitBitcoinAddress> {
+        let (hrp, addr) = SegwitBitcoinAddress::decode_bech32(s)?;
+        if hrp != SEGWIT_MAINNET_HRP && hrp != SEGWIT_TESTNET_HRP {
+            test_debug!("Unrecognized hrp '{:?}'", &hrp);
+            return None;
+        }
+        Some(addr)
+    }
+
+    /// Decode a bech32 or bech32m segwit address, and return its (lowercase) HRP along with the
+    /// address.  The HRP must be one of "bc", "tb", or "bcrt", but is not checked against any
+    /// particular network.  The witness version, program length, and checksum variant are all
+    /// checked (per BIP173 and BIP350).
+    /// The resulting address is a mainnet address only if the HRP is "bc".
+    pub fn decode_bech32(s: &str) -> Option<(String, SegwitBitcoinAddress)> {
         let (hrp, quintets, variant) = bech32::decode(s)
             .map_err(|e| {
                 test_debug!("Failed to decode '{}': {:?}", s, &e);
@@ -309,14 +349,12 @@ impl SegwitBitcoinAddress {
             })
             .ok()?;
 
-        let mainnet = if hrp == SEGWIT_MAINNET_HRP {
-            Some(true)
-        } else if hrp == SEGWIT_TESTNET_HRP {
-            Some(false)
-        } else {
+        if hrp != SEGWIT_MAINNET_HRP && hrp != SEGWIT_TESTNET_HRP && hrp != SEGWIT_REGTEST_HRP {
             test_debug!("Unrecognized hrp '{:?}'", &hrp);
-            None
-        }?;
+            return None;
+        }
+
+        let mainnet = hrp == SEGWIT_MAINNET_HRP;
 
         if quintets.len() == 0 || quintets.len() > 65 {
             test_debug!("Invalid prog length: {}", quintets.len());
@@ -334,21 +372,21 @@ impl SegwitBitcoinAddress {
             })
             .ok()?;
 
-        match (variant, version, bytes.len()) {
+        let addr = match (variant, version, bytes.len()) {
             (bech32::Variant::Bech32, SEGWIT_V0, 20) => {
                 let mut bytes_20 = [0u8; 20];
                 bytes_20.copy_from_slice(&bytes[0..20]);
-                Some(SegwitBitcoinAddress::P2WPKH(mainnet, bytes_20))
+                SegwitBitcoinAddress::P2WPKH(mainnet, bytes_20)
             }
             (bech32::Variant::Bech32, SEGWIT_V0, 32) => {
                 let mut bytes_32 = [0u8; 32];
                 bytes_32.copy_from_slice(&bytes[0..32]);
-                Some(SegwitBitcoinAddress::P2WSH(mainnet, bytes_32))
+                SegwitBitcoinAddress::P2WSH(mainnet, bytes_32)
             }
             (bech32::Variant::Bech32m, SEGWIT_V1, 32) => {
                 let mut bytes_32 = [0u8; 32];
                 bytes_32.copy_from_slice(&bytes[0..32]);
-                Some(SegwitBitcoinAddress::P2TR(mainnet, bytes_32))
+                SegwitBitcoinAddress::P2TR(mainnet, bytes_32)
             }
             (_, _, _) => {
                 test_debug!(
@@ -358,9 +396,10 @@ impl SegwitBitcoinAddress {
                     version,
                     bytes.len()
                 );
-                None
+                return None;
             }
-        }
+        };
+        Some((hrp, addr))
     }
 
     pub fn to_p2wpkh_tx_out(bytes: &[u8; 20], value: u64) -> TxOut {
@@ -462,6 +501,109 @@ impl BitcoinAddress {
         )))
     }
 
+    /// Instantiate a segwit address from a bech32 (or bech32m) string.
+    /// The string's HRP must match the given network: "bc" for mainnet, "tb" for testnet and
+    /// signet, and "bcrt" for regtest.  Returns btc_error::WrongNetwork if the address is
+    /// well-formed but for a different network, and btc_error::InvalidByteSequence if it is not
+    /// well-formed.
+    pub fn from_bech32(
+        network_id: BitcoinNetworkType,
+        s: &str,
+    ) -> Result<BitcoinAddress, btc_error> {
+        let (hrp, addr) =
+            SegwitBitcoinAddress::decode_bech32(s).ok_or(btc_error::InvalidByteSequence)?;
+        if hrp != segwit_network_hrp(network_id) {
+            test_debug!(
+                "Address {} has hrp '{}', but network {:?} expects '{}'",
+                s,
+                &hrp,
+                network_id,
+                segwit_network_hrp(network_id)
+            );
+            return Err(btc_error::WrongNetwork);
+        }
+        Ok(BitcoinAddress::Segwit(addr))
+    }
+
+    /// Instantiate a legacy address from a b58check string.
+    /// The version byte must be one of the given network's: mainnet addresses are only valid on
+    /// mainnet, and testnet addresses on testnet, regtest and signet.  Returns
+    /// btc_error::WrongNetwork if the address is well-formed but for a different network.
+    pub fn from_b58(network_id: BitcoinNetworkType, s: &str) -> Result<BitcoinAddress, btc_error> {
+        let addr = LegacyBitcoinAddress::from_b58(s)?;
+        let expected_network_id = match network_id {
+            BitcoinNetworkType::Mainnet => BitcoinNetworkType::Mainnet,
+            BitcoinNetworkType::Testnet
+            | BitcoinNetworkType::Regtest
+            | BitcoinNetworkType::Signet => BitcoinNetworkType::Testnet,
+        };
+        if addr.network_id != expected_network_id {
+            test_debug!(
+                "Address {} is for {:?}, but network is {:?}",
+                s,
+                addr.network_id,
+                network_id
+            );
+            return Err(btc_error::WrongNetwork);
+        }
+        Ok(BitcoinAddress::Legacy(addr))
+    }
+
+    /// Instantiate an address on the given network from either a b58check or a bech32 (or
+    /// bech32m) string.
+    pub fn from_string_network(
+        network_id: BitcoinNetworkType,
+        s: &str,
+    ) -> Result<BitcoinAddress, btc_error> {
+        if SegwitBitcoinAddress::decode_bech32(s).is_some() {
+            BitcoinAddress::from_bech32(network_id, s)
+        } else {
+            BitcoinAddress::from_b58(network_id, s)
+        }
+    }
+
+    /// Is this a well-formed address for the given network?
+    pub fn is_valid(network_id: BitcoinNetworkType, s: &str) -> bool {
+        BitcoinAddress::from_string_network(network_id, s).is_ok()
+    }
+
+    /// Encode this address as a bech32 (or bech32m) string.
+    /// Returns None if this is not a segwit address.
+    /// Note that regtest addresses are encoded with the testnet HRP; use
+    /// to_bech32_network() to produce a "bcrt" address.
+    pub fn to_bech32(&self) -> Option<String> {
+        match *self {
+            BitcoinAddress::Segwit(ref addr) => Some(addr.to_bech32()),
+            BitcoinAddress::Legacy(_) => None,
+        }
+    }
+
+    /// Encode this address as a bech32 (or bech32m) string with the given network's HRP, so
+    /// that an address decoded with from_bech32() round-trips on any network.
+    /// Returns None if this is not a segwit address.
+    pub fn to_bech32_network(&self, network_id: BitcoinNetworkType) -> Option<String> {
+        match *self {
+            BitcoinAddress::Segwit(ref addr) => {
+                Some(addr.to_bech32_hrp(segwit_network_hrp(network_id)))
+            }
+            BitcoinAddress::Legacy(_) => None,
+        }
+    }
+
+    /// Encode this address as a bech32m string.
+    /// Only witness v1+ programs (i.e. taproot addresses) are encoded with bech32m (BIP350), so
+    /// this returns btc_error::InvalidByteSequence for any other address.
+    pub fn to_bech32m(&self) -> Result<String, btc_error> {
+        match *self {
+            BitcoinAddress::Segwit(ref addr)
+                if addr.bech32_variant() == bech32::Variant::Bech32m =>
+            {
+                Ok(addr.to_bech32())
+            }
+            _ => Err(btc_error::InvalidByteSequence),
+        }
+    }
+
     /// Instantiate an address from a scriptpubkey
     /// If we don't recognize it, then return None.
     /// WARNING: cannot differentiate between p2sh and segwit-p2sh
@@ -559,6 +701,12 @@ impl BitcoinAddress {
         return false;
     }
 
+    /// Is this a taproot (segwit v1) address?  Its witness program is the 32-byte x-only
+    /// output key.
+    pub fn is_taproot(&self) -> bool {
+        self.is_segwit_p2tr()
+    }
+
     #[cfg(test)]
     pub fn expect_legacy(self) -> LegacyBitcoinAddress {
         match self {
@@ -707,6 +855,7 @@ mod tests {
     use super::{
         BitcoinAddress, LegacyBitcoinAddress, LegacyBitcoinAddressType, SegwitBitcoinAddress,
     };
+    use crate::burnchains::bitcoin::Error as btc_error;
 
     struct AddressFixture {
         addr: String,
@@ -793,6 +942,102 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_from_b58_network() {
+        let fixtures: Vec<(&str, BitcoinNetworkType, Result<(), btc_error>)> = vec![
+            (
+                "1B5xoFjSwAB3DUum7dxXgj3brnYsXibLbc",
+                BitcoinNetworkType::Mainnet,
+                Ok(()),
+            ),
+            (
+                "35idohuiQNndP1xR3FhNVHXgKF9YYPhWo4",
+                BitcoinNetworkType::Mainnet,
+                Ok(()),
+            ),
+            (
+                "mr6nrMvvh44sR5MiX929mMXP5hqgaTr6fx",
+                BitcoinNetworkType::Testnet,
+                Ok(()),
+            ),
+            (
+                "mr6nrMvvh44sR5MiX929mMXP5hqgaTr6fx",
+                BitcoinNetworkType::Regtest,
+                Ok(()),
+            ),
+            (
+                "2Mxh5a9QxP5jgABfzATLpmFVofbzDeFRJyt",
+                BitcoinNetworkType::Signet,
+                Ok(()),
+            ),
+            // mainnet address on testnet and regtest
+            (
+                "1B5xoFjSwAB3DUum7dxXgj3brnYsXibLbc",
+                BitcoinNetworkType::Testnet,
+                Err(btc_error::WrongNetwork),
+            ),
+            (
+                "35idohuiQNndP1xR3FhNVHXgKF9YYPhWo4",
+                BitcoinNetworkType::Regtest,
+                Err(btc_error::WrongNetwork),
+            ),
+            // testnet address on mainnet
+            (
+                "mr6nrMvvh44sR5MiX929mMXP5hqgaTr6fx",
+                BitcoinNetworkType::Mainnet,
+                Err(btc_error::WrongNetwork),
+            ),
+            // bad checksum
+            (
+                "1B5xoFjSwAB3DUum7dxXgj3brnYsXibLbd",
+                BitcoinNetworkType::Mainnet,
+                Err(btc_error::InvalidChecksum),
+            ),
+            // truncated payload (valid checksum)
+            (
+                "Couv2wqrdtpEqrS1vQZZ9zb7WgUf6Z3e",
+                BitcoinNetworkType::Mainnet,
+                Err(btc_error::InvalidByteSequence),
+            ),
+            // not base58
+            (
+                "0B5xoFjSwAB3DUum7dxXgj3brnYsXibLbc",
+                BitcoinNetworkType::Mainnet,
+                Err(btc_error::InvalidByteSequence),
+            ),
+        ];
+
+        for (addr_str, network_id, expected) in fixtures.into_iter() {
+            let res = BitcoinAddress::from_b58(network_id, addr_str);
+            match (&res, &expected) {
+                (Ok(addr), Ok(())) => assert_eq!(addr.to_string(), addr_str),
+                (Err(btc_error::WrongNetwork), Err(btc_error::WrongNetwork)) => {}
+                (Err(btc_error::InvalidChecksum), Err(btc_error::InvalidChecksum)) => {}
+                (Err(btc_error::InvalidByteSequence), Err(btc_error::InvalidByteSequence)) => {}
+                (res, expected) => {
+                    panic!(
+                        "Decoding '{}' on {:?}: expected {:?}, got {:?}",
+                        addr_str, network_id, expected, res
+                    );
+                }
+            }
+            assert_eq!(
+                BitcoinAddress::is_valid(network_id, addr_str),
+                expected.is_ok()
+            );
+        }
+
+        // is_valid() also takes segwit addresses
+        assert!(BitcoinAddress::is_valid(
+            BitcoinNetworkType::Mainnet,
+            "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4"
+        ));
+        assert!(!BitcoinAddress::is_valid(
+            BitcoinNetworkType::Testnet,
+            "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4"
+        ));
+    }
+
     #[test]
     fn test_from_bech32() {
         let fixtures = vec![
@@ -923,6 +1168,255 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_from_bech32_network() {
+        let p2wpkh_bytes = [
+            0x75, 0x1e, 0x76, 0xe8, 0x19, 0x91, 0x96, 0xd4, 0x54, 0x94, 0x1c, 0x45, 0xd1, 0xb3,
+            0xa3, 0x23, 0xf1, 0x43, 0x3b, 0xd6,
+        ];
+        let p2wsh_bytes = [
+            0x18, 0x63, 0x14, 0x3c, 0x14, 0xc5, 0x16, 0x68, 0x04, 0xbd, 0x19, 0x20, 0x33, 0x56,
+            0xda, 0x13, 0x6c, 0x98, 0x56, 0x78, 0xcd, 0x4d, 0x27, 0xa1, 0xb8, 0xc6, 0x32, 0x96,
+            0x04, 0x90, 0x32, 0x62,
+        ];
+
+        // (address, network, expected result)
+        let fixtures: Vec<(&str, BitcoinNetworkType, Result<BitcoinAddress, btc_error>)> = vec![
+            // taken from bip-0173
+            (
+                "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+                BitcoinNetworkType::Mainnet,
+                Ok(BitcoinAddress::Segwit(SegwitBitcoinAddress::P2WPKH(
+                    true,
+                    p2wpkh_bytes.clone(),
+                ))),
+            ),
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                BitcoinNetworkType::Testnet,
+                Ok(BitcoinAddress::Segwit(SegwitBitcoinAddress::P2WSH(
+                    false,
+                    p2wsh_bytes.clone(),
+                ))),
+            ),
+            (
+                "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+                BitcoinNetworkType::Regtest,
+                Ok(BitcoinAddress::Segwit(SegwitBitcoinAddress::P2WPKH(
+                    false,
+                    p2wpkh_bytes.clone(),
+                ))),
+            ),
+            (
+                "bcrt1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qzf4jry",
+                BitcoinNetworkType::Regtest,
+                Ok(BitcoinAddress::Segwit(SegwitBitcoinAddress::P2WSH(
+                    false,
+                    p2wsh_bytes.clone(),
+                ))),
+            ),
+            // mainnet address on testnet and regtest
+            (
+                "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+                BitcoinNetworkType::Testnet,
+                Err(btc_error::WrongNetwork),
+            ),
+            (
+                "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+                BitcoinNetworkType::Regtest,
+                Err(btc_error::WrongNetwork),
+            ),
+            // testnet address on mainnet and regtest
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                BitcoinNetworkType::Mainnet,
+                Err(btc_error::WrongNetwork),
+            ),
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                BitcoinNetworkType::Regtest,
+                Err(btc_error::WrongNetwork),
+            ),
+            // signet shares testnet's hrp, and only testnet's
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                BitcoinNetworkType::Signet,
+                Ok(BitcoinAddress::Segwit(SegwitBitcoinAddress::P2WSH(
+                    false,
+                    p2wsh_bytes.clone(),
+                ))),
+            ),
+            (
+                "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+                BitcoinNetworkType::Signet,
+                Err(btc_error::WrongNetwork),
+            ),
+            (
+                "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+                BitcoinNetworkType::Signet,
+                Err(btc_error::WrongNetwork),
+            ),
+            // regtest address on testnet
+            (
+                "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+                BitcoinNetworkType::Testnet,
+                Err(btc_error::WrongNetwork),
+            ),
+            // bad checksum
+            (
+                "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5",
+                BitcoinNetworkType::Mainnet,
+                Err(btc_error::InvalidByteSequence),
+            ),
+            // mixed case
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sL5k7",
+                BitcoinNetworkType::Testnet,
+                Err(btc_error::InvalidByteSequence),
+            ),
+            // invalid program length for segwit v0
+            (
+                "BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P",
+                BitcoinNetworkType::Mainnet,
+                Err(btc_error::InvalidByteSequence),
+            ),
+            // bad hrp
+            (
+                "tc1qw508d6qejxtdg4y5r3zarvary0c5xw7kg3g4ty",
+                BitcoinNetworkType::Testnet,
+                Err(btc_error::InvalidByteSequence),
+            ),
+            // empty data section
+            (
+                "bc1gmk9yu",
+                BitcoinNetworkType::Mainnet,
+                Err(btc_error::InvalidByteSequence),
+            ),
+        ];
+
+        for (addr_str, network_id, expected) in fixtures.into_iter() {
+            test_debug!("Test '{}' on {:?}", addr_str, network_id);
+            let res = BitcoinAddress::from_bech32(network_id, addr_str);
+            match (res, expected) {
+                (Ok(addr), Ok(expected_addr)) => {
+                    assert_eq!(addr, expected_addr);
+
+                    // round-trips
+                    assert_eq!(
+                        addr.to_bech32_network(network_id).unwrap(),
+                        addr_str.to_lowercase()
+                    );
+                }
+                (Err(btc_error::WrongNetwork), Err(btc_error::WrongNetwork)) => {}
+                (Err(btc_error::InvalidByteSequence), Err(btc_error::InvalidByteSequence)) => {}
+                (res, expected) => {
+                    panic!(
+                        "Decoding '{}' on {:?}: expected {:?}, got {:?}",
+                        addr_str, network_id, &expected, &res
+                    );
+                }
+            }
+        }
+
+        // a regtest address is stored as a testnet address
+        let regtest_addr = BitcoinAddress::from_bech32(
+            BitcoinNetworkType::Regtest,
+            "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+        )
+        .unwrap();
+        assert_eq!(
+            regtest_addr.to_bech32().unwrap(),
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"
+        );
+
+        // legacy addresses have no bech32 encoding
+        let legacy_addr =
+            BitcoinAddress::from_string("1B5xoFjSwAB3DUum7dxXgj3brnYsXibLbc").unwrap();
+        assert!(legacy_addr.to_bech32().is_none());
+        assert!(legacy_addr
+            .to_bech32_network(BitcoinNetworkType::Regtest)
+            .is_none());
+    }
+
+    #[test]
+    fn test_from_bech32m() {
+        let output_key = [
+            0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87,
+            0x0b, 0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b,
+            0x16, 0xf8, 0x17, 0x98,
+        ];
+        let fixtures = vec![
+            // taken from bip-0350
+            AddressFixture {
+                addr: "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0".to_owned(),
+                result: Some(BitcoinAddress::Segwit(SegwitBitcoinAddress::P2TR(
+                    true, output_key,
+                ))),
+            },
+            AddressFixture {
+                addr: "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c".to_owned(),
+                result: Some(BitcoinAddress::Segwit(SegwitBitcoinAddress::P2TR(
+                    false,
+                    [
+                        0x00, 0x00, 0x00, 0xc4, 0xa5, 0xca, 0xd4, 0x62, 0x21, 0xb2, 0xa1, 0x87,
+                        0x90, 0x5e, 0x52, 0x66, 0x36, 0x2b, 0x99, 0xd5, 0xe9, 0x1c, 0x6c, 0xe2,
+                        0x4d, 0x16, 0x5d, 0xab, 0x93, 0xe8, 0x64, 0x33,
+                    ],
+                ))),
+            },
+            AddressFixture {
+                // witness v0 program encoded with bech32m
+                addr: "tb1q0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq24jc47".to_owned(),
+                result: None,
+            },
+            AddressFixture {
+                // witness v1 program encoded with bech32
+                addr: "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd".to_owned(),
+                result: None,
+            },
+            AddressFixture {
+                // witness v1 program is not 32 bytes (20 bytes)
+                addr: "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxmcc2tqp".to_owned(),
+                result: None,
+            },
+            AddressFixture {
+                // witness v1 program is not 32 bytes (33 bytes)
+                addr: "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqqtq3na4".to_owned(),
+                result: None,
+            },
+        ];
+
+        for fixture in fixtures.iter() {
+            test_debug!("Test '{}'", &fixture.addr);
+            let addr_opt = BitcoinAddress::from_string(&fixture.addr);
+            match (addr_opt, fixture.result.as_ref()) {
+                (Some(addr), Some(res)) => {
+                    assert_eq!(addr, *res);
+                    assert!(addr.is_taproot());
+                    assert_eq!(addr.to_bech32m().unwrap(), fixture.addr);
+                }
+                (None, None) => {}
+                (None, Some(_r)) => {
+                    panic!("Failed to decode an address when we should have");
+                }
+                (Some(_a), None) => {
+                    panic!("Decoded an address when we should not have");
+                }
+            }
+        }
+
+        // only taproot addresses are bech32m-encoded
+        let p2wpkh_addr =
+            BitcoinAddress::from_string("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4").unwrap();
+        assert!(!p2wpkh_addr.is_taproot());
+        assert!(p2wpkh_addr.to_bech32m().is_err());
+
+        let legacy_addr =
+            BitcoinAddress::from_string("1B5xoFjSwAB3DUum7dxXgj3brnYsXibLbc").unwrap();
+        assert!(!legacy_addr.is_taproot());
+        assert!(legacy_addr.to_bech32m().is_err());
+    }
+
     #[test]
     fn test_from_scriptpubkey() {
         let fixtures = vec![
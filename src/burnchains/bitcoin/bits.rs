@@ -17,12 +17,16 @@
 use sha2::Digest;
 use sha2::Sha256;
 
-use crate::burnchains::bitcoin::address::{BitcoinAddress, LegacyBitcoinAddressType};
+use crate::burnchains::bitcoin::address::{
+    BitcoinAddress, LegacyBitcoinAddressType, SegwitBitcoinAddress,
+};
+use crate::burnchains::bitcoin::blocks::ScriptType;
 use crate::burnchains::bitcoin::keys::BitcoinPublicKey;
 use crate::burnchains::bitcoin::BitcoinNetworkType;
 use crate::burnchains::bitcoin::Error as btc_error;
 use crate::burnchains::bitcoin::{
-    BitcoinInputType, BitcoinTxInput, BitcoinTxInputRaw, BitcoinTxInputStructured, BitcoinTxOutput,
+    BitcoinInputType, BitcoinMultisigScript, BitcoinTxInput, BitcoinTxInputRaw,
+    BitcoinTxInputStructured, BitcoinTxOutput,
 };
 use crate::burnchains::PublicKey;
 use crate::burnchains::Txid;
@@ -31,6 +35,7 @@ use stacks_common::address::AddressHashMode;
 use stacks_common::deps_common::bitcoin::blockdata::opcodes::All as btc_opcodes;
 use stacks_common::deps_common::bitcoin::blockdata::opcodes::Class;
 use stacks_common::deps_common::bitcoin::blockdata::script::{Builder, Instruction, Script};
+use stacks_common::deps_common::bitcoin::blockdata::transaction::Transaction;
 use stacks_common::deps_common::bitcoin::blockdata::transaction::TxIn as BtcTxIn;
 use stacks_common::deps_common::bitcoin::blockdata::transaction::TxOut as BtcTxOut;
 use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
@@ -50,6 +55,42 @@ pub fn parse_script<'a>(script: &'a Script) -> Vec<Instruction<'a>> {
     script.iter(false).collect()
 }
 
+/// Get what the signature in a P2PKH input must verify against, for `keys::verify_batch()`:  the
+/// signer's public key, the legacy sighash of the input, and the DER signature without its
+/// sighash type byte.  The spent output is taken to be the P2PKH output for the public key, since
+/// that is what a `<sig> <pubkey>` scriptSig spends.  Returns None if the input's scriptSig isn't
+/// one (e.g. it spends a segwit output).
+pub fn p2pkh_signature_check(
+    tx: &Transaction,
+    input_index: usize,
+) -> Option<(BitcoinPublicKey, Vec<u8>, Vec<u8>)> {
+    let instructions = parse_script(&tx.input.get(input_index)?.script_sig);
+    let (sig, pubkey_bytes) = match instructions.as_slice() {
+        [Instruction::PushBytes(sig), Instruction::PushBytes(pubkey_bytes)] => {
+            (*sig, *pubkey_bytes)
+        }
+        _ => {
+            return None;
+        }
+    };
+    let pubkey = BitcoinPublicKey::from_slice(pubkey_bytes).ok()?;
+    let (sighash_type, der_sig) = match sig.split_last() {
+        Some((sighash_type, der_sig)) => (*sighash_type as u32, der_sig.to_vec()),
+        None => (0, vec![]),
+    };
+
+    // the hash is of the exact key bytes pushed, compressed or not
+    let script_pubkey = Builder::new()
+        .push_opcode(btc_opcodes::OP_DUP)
+        .push_opcode(btc_opcodes::OP_HASH160)
+        .push_slice(&Hash160::from_data(pubkey_bytes).0)
+        .push_opcode(btc_opcodes::OP_EQUALVERIFY)
+        .push_opcode(btc_opcodes::OP_CHECKSIG)
+        .into_script();
+    let sighash = tx.signature_hash(input_index, &script_pubkey, sighash_type);
+    Some((pubkey, sighash.0.to_vec(), der_sig))
+}
+
 impl BitcoinTxInputStructured {
     /// Parse a script instruction stream encoding a p2pkh scritpsig into a BitcoinTxInput
     fn from_bitcoin_p2pkh_script_sig(
@@ -89,68 +130,6 @@ impl BitcoinTxInputStructured {
         }
     }
 
-    /// given the number of sigs required (m) and an array of pubkey pushbytes instructions, extract
-    /// a burnchain tx input.  If segwit is True, then it means these pushbytes came from a witness
-    /// program instead of a script-sig
-    fn from_bitcoin_pubkey_pushbytes(
-        num_sigs: usize,
-        pubkey_pushbytes: &[Instruction],
-        segwit: bool,
-        input_txid: (Txid, u32),
-    ) -> Option<BitcoinTxInputStructured> {
-        if num_sigs < 1 || pubkey_pushbytes.len() < 1 || pubkey_pushbytes.len() < num_sigs {
-            test_debug!(
-                "Not a multisig script: num_sigs = {}, num_pubkeys <= {}",
-                num_sigs,
-                pubkey_pushbytes.len()
-            );
-            return None;
-        }
-
-        // this script looks like a multisig script.  See if the
-        // intermediate values are all pushdatas and that they are all
-        // public keys.
-        let mut keys: Vec<BitcoinPublicKey> = Vec::with_capacity(pubkey_pushbytes.len());
-
-        for i in 0..pubkey_pushbytes.len() {
-            let payload = match &pubkey_pushbytes[i] {
-                Instruction::PushBytes(payload) => payload,
-                _ => {
-                    // not pushbytes, so this can't be a multisig script
-                    test_debug!(
-                        "Not a multisig script: Instruction {} is not a PushBytes",
-                        i
-                    );
-                    return None;
-                }
-            };
-
-            let pubk = BitcoinPublicKey::from_slice(payload)
-                .map_err(|e| {
-                    // not a public key
-                    warn!(
-                        "Not a multisig script: pushbytes {} is not a public key ({:?})",
-                        i, e
-                    );
-                    e
-                })
-                .ok()?;
-
-            keys.push(pubk);
-        }
-
-        Some(BitcoinTxInputStructured {
-            tx_ref: input_txid,
-            keys: keys,
-            num_required: num_sigs,
-            in_type: if segwit {
-                BitcoinInputType::SegwitP2SH
-            } else {
-                BitcoinInputType::Standard
-            },
-        })
-    }
-
     /// Given the number of signatures required (m) and an array of Vec<u8>'s encoding public keys
     /// (both taken from a segwit program), extract a burnchain tx input.
     fn from_bitcoin_witness_pubkey_vecs(
@@ -205,73 +184,17 @@ impl BitcoinTxInputStructured {
     ) -> Option<BitcoinTxInputStructured> {
         match multisig_script {
             Instruction::PushBytes(multisig_script_bytes) => {
-                let multisig_script = Script::from(multisig_script_bytes.to_vec());
-                let multisig_instructions = parse_script(&multisig_script);
-
-                if multisig_instructions.len() < 4 {
-                    // can't be a multisig script
-                    test_debug!(
-                        "Not a multisig script: keys pushdata has only {} instructions",
-                        multisig_instructions.len()
-                    );
-                    return None;
-                }
-
-                match (
-                    &multisig_instructions[0],
-                    &multisig_instructions[multisig_instructions.len() - 2],
-                    &multisig_instructions[multisig_instructions.len() - 1],
-                ) {
-                    (
-                        Instruction::Op(op1),
-                        Instruction::Op(op2),
-                        Instruction::Op(btc_opcodes::OP_CHECKMULTISIG),
-                    ) => {
-                        // op1 and op2 must be integers
-                        match (
-                            btc_opcodes::from(*op1).classify(),
-                            btc_opcodes::from(*op2).classify(),
-                        ) {
-                            (Class::PushNum(num_sigs), Class::PushNum(num_pubkeys)) => {
-                                // the "#instructions - 3" comes from the OP_m, OP_n, and OP_CHECKMULTISIG
-                                if num_sigs < 1
-                                    || num_pubkeys < 1
-                                    || num_pubkeys < num_sigs
-                                    || num_pubkeys != (multisig_instructions.len() - 3) as i32
-                                {
-                                    test_debug!("Not a multisig script: num_sigs = {}, num_pubkeys = {}, num instructions = {}", num_sigs, num_pubkeys, multisig_instructions.len());
-                                    return None;
-                                }
-
-                                let pubkey_pushbytes =
-                                    &multisig_instructions[1..multisig_instructions.len() - 2];
-                                if pubkey_pushbytes.len() as i32 != num_pubkeys {
-                                    test_debug!("Not a multisig script: num_pubkeys = {}, num pushbytes = {}", num_sigs, num_pubkeys);
-                                    return None;
-                                }
-
-                                BitcoinTxInputStructured::from_bitcoin_pubkey_pushbytes(
-                                    num_sigs as usize,
-                                    pubkey_pushbytes,
-                                    segwit,
-                                    input_txid,
-                                )
-                            }
-                            (_, _) => {
-                                test_debug!(
-                                    "Not a multisig script: missing num_sigs and/or num_pubkeys"
-                                );
-                                None
-                            }
-                        }
-                    }
-                    (_, _, _) => {
-                        test_debug!(
-                            "Not a multisig script: missing OP_m, OP_n, and/or OP_CHECKMULTISIG"
-                        );
-                        None
-                    }
-                }
+                let multisig = BitcoinMultisigScript::from_bytes(multisig_script_bytes)?;
+                Some(BitcoinTxInputStructured {
+                    tx_ref: input_txid,
+                    keys: multisig.keys,
+                    num_required: multisig.num_required,
+                    in_type: if segwit {
+                        BitcoinInputType::SegwitP2SH
+                    } else {
+                        BitcoinInputType::Standard
+                    },
+                })
             }
             _ => {
                 test_debug!("Not a multisig script: not a PushBytes");
@@ -485,6 +408,113 @@ impl BitcoinTxInputStructured {
     }
 }
 
+impl BitcoinMultisigScript {
+    /// Parse a script instruction stream encoding OP_m <pubkey1> ... <pubkey_n> OP_n
+    /// OP_CHECKMULTISIG.  n must match the number of public keys, and m must be between 1 and n.
+    fn from_instructions(instructions: &[Instruction]) -> Option<BitcoinMultisigScript> {
+        if instructions.len() < 4 {
+            // can't be a multisig script
+            test_debug!(
+                "Not a multisig script: keys pushdata has only {} instructions",
+                instructions.len()
+            );
+            return None;
+        }
+
+        let (num_sigs, num_pubkeys) = match (
+            &instructions[0],
+            &instructions[instructions.len() - 2],
+            &instructions[instructions.len() - 1],
+        ) {
+            (
+                Instruction::Op(op1),
+                Instruction::Op(op2),
+                Instruction::Op(btc_opcodes::OP_CHECKMULTISIG),
+            ) => {
+                // op1 and op2 must be integers
+                match (
+                    btc_opcodes::from(*op1).classify(),
+                    btc_opcodes::from(*op2).classify(),
+                ) {
+                    (Class::PushNum(num_sigs), Class::PushNum(num_pubkeys)) => {
+                        (num_sigs, num_pubkeys)
+                    }
+                    (_, _) => {
+                        test_debug!("Not a multisig script: missing num_sigs and/or num_pubkeys");
+                        return None;
+                    }
+                }
+            }
+            (_, _, _) => {
+                test_debug!("Not a multisig script: missing OP_m, OP_n, and/or OP_CHECKMULTISIG");
+                return None;
+            }
+        };
+
+        // the "#instructions - 3" comes from the OP_m, OP_n, and OP_CHECKMULTISIG
+        if num_sigs < 1
+            || num_pubkeys < 1
+            || num_pubkeys < num_sigs
+            || num_pubkeys != (instructions.len() - 3) as i32
+        {
+            test_debug!(
+                "Not a multisig script: num_sigs = {}, num_pubkeys = {}, num instructions = {}",
+                num_sigs,
+                num_pubkeys,
+                instructions.len()
+            );
+            return None;
+        }
+
+        // the intermediate values must all be pushdatas of public keys
+        let pubkey_pushbytes = &instructions[1..instructions.len() - 2];
+        let mut keys: Vec<BitcoinPublicKey> = Vec::with_capacity(pubkey_pushbytes.len());
+
+        for i in 0..pubkey_pushbytes.len() {
+            let payload = match &pubkey_pushbytes[i] {
+                Instruction::PushBytes(payload) => payload,
+                _ => {
+                    // not pushbytes, so this can't be a multisig script
+                    test_debug!(
+                        "Not a multisig script: Instruction {} is not a PushBytes",
+                        i
+                    );
+                    return None;
+                }
+            };
+
+            let pubk = BitcoinPublicKey::from_slice(payload)
+                .map_err(|e| {
+                    // not a public key
+                    warn!(
+                        "Not a multisig script: pushbytes {} is not a public key ({:?})",
+                        i, e
+                    );
+                    e
+                })
+                .ok()?;
+
+            keys.push(pubk);
+        }
+
+        Some(BitcoinMultisigScript {
+            num_required: num_sigs as usize,
+            keys,
+        })
+    }
+
+    /// Parse a bare multisig scriptPubKey, or a p2sh or p2wsh multisig redeem script.
+    /// Returns None if the script is not a well-formed m-of-n multisig script.
+    pub fn from_script(script: &Script) -> Option<BitcoinMultisigScript> {
+        BitcoinMultisigScript::from_instructions(&parse_script(script))
+    }
+
+    /// Parse a serialized bare multisig scriptPubKey or multisig redeem script
+    pub fn from_bytes(script_bytes: &[u8]) -> Option<BitcoinMultisigScript> {
+        BitcoinMultisigScript::from_script(&Script::from(script_bytes.to_vec()))
+    }
+}
+
 impl BitcoinTxInputRaw {
     #[cfg(test)]
     pub fn from_hex_parts(scriptsig: &str, witness: &[&str]) -> BitcoinTxInputRaw {
@@ -507,6 +537,121 @@ impl BitcoinTxInputRaw {
             tx_ref: input_txid,
         }
     }
+
+    /// Recover the public key that signed this input, if it spends a p2wpkh output, either
+    /// natively or wrapped in p2sh.  `spent_script_pubkey` is the scriptPubKey of the output
+    /// this input spends.
+    /// The public key (the second witness item) must hash to the spent witness program.  For
+    /// p2sh-p2wpkh, the witness program is the redeem script in the scriptSig, which must in turn
+    /// hash to the spent script hash.
+    /// Returns btc_error::InvalidByteSequence if this input isn't a p2wpkh or p2sh-p2wpkh spend,
+    /// and btc_error::WitnessProgramMismatch if it is, but doesn't hash to what it spends.
+    pub fn get_p2wpkh_public_key(
+        &self,
+        spent_script_pubkey: &[u8],
+    ) -> Result<BitcoinPublicKey, btc_error> {
+        // witness format: <sig> <pubkey>
+        if self.witness.len() != 2 {
+            test_debug!("Not a p2wpkh witness: {} items", self.witness.len());
+            return Err(btc_error::InvalidByteSequence);
+        }
+        let pubkey = BitcoinPublicKey::from_slice(&self.witness[1]).map_err(|e| {
+            test_debug!("Not a p2wpkh witness: not a public key ({:?})", e);
+            btc_error::InvalidByteSequence
+        })?;
+
+        let witness_program = if spent_script_pubkey.len() == 22
+            && spent_script_pubkey[0] == 0
+            && spent_script_pubkey[1] == 20
+        {
+            // native p2wpkh: OP_0 <20-byte witness program>, and an empty scriptSig
+            if !self.scriptSig.is_empty() {
+                test_debug!("Not a p2wpkh spend: scriptSig is not empty");
+                return Err(btc_error::InvalidByteSequence);
+            }
+            spent_script_pubkey[2..22].to_vec()
+        } else if Script::from(spent_script_pubkey.to_vec()).is_p2sh() {
+            // p2sh-p2wpkh: the scriptSig pushes the redeem script OP_0 <20-byte witness program>
+            let script_sig = Script::from(self.scriptSig.clone());
+            let instructions = parse_script(&script_sig);
+            let redeem_script = match instructions.as_slice() {
+                [Instruction::PushBytes(redeem_script)] => redeem_script.to_vec(),
+                _ => {
+                    test_debug!("Not a p2sh-p2wpkh spend: scriptSig is not one pushdata");
+                    return Err(btc_error::InvalidByteSequence);
+                }
+            };
+            if redeem_script.len() != 22 || redeem_script[0] != 0 || redeem_script[1] != 20 {
+                test_debug!("Not a p2sh-p2wpkh spend: redeem script is not a p2wpkh program");
+                return Err(btc_error::InvalidByteSequence);
+            }
+            if Hash160::from_data(&redeem_script).as_bytes()[..] != spent_script_pubkey[2..22] {
+                test_debug!("p2sh-p2wpkh redeem script does not match the spent script hash");
+                return Err(btc_error::WitnessProgramMismatch);
+            }
+            redeem_script[2..22].to_vec()
+        } else {
+            test_debug!("Not a p2wpkh or p2sh-p2wpkh output");
+            return Err(btc_error::InvalidByteSequence);
+        };
+
+        if Hash160::from_data(&self.witness[1]).as_bytes()[..] != witness_program[..] {
+            test_debug!("p2wpkh public key does not match the spent witness program");
+            return Err(btc_error::WitnessProgramMismatch);
+        }
+        Ok(pubkey)
+    }
+
+    /// Get the output key and BIP340 signature of a taproot key-path spend, so the signature can
+    /// be checked with `keys::verify_schnorr()` against the spend's BIP341 sighash.
+    /// `spent_script_pubkey` is the scriptPubKey of the output this input spends, which must be
+    /// OP_1 <32-byte output key>.  Also returns the signature's sighash type (0x00, i.e.
+    /// SIGHASH_DEFAULT, if the signature has none).
+    /// Returns btc_error::InvalidByteSequence if this input isn't a taproot key-path spend.
+    pub fn get_taproot_key_path_signature(
+        &self,
+        spent_script_pubkey: &[u8],
+    ) -> Result<([u8; 32], [u8; 64], u8), btc_error> {
+        if spent_script_pubkey.len() != 34
+            || spent_script_pubkey[0] != btc_opcodes::OP_PUSHNUM_1 as u8
+            || spent_script_pubkey[1] != btc_opcodes::OP_PUSHBYTES_32 as u8
+        {
+            test_debug!("Not a p2tr output");
+            return Err(btc_error::InvalidByteSequence);
+        }
+        if !self.scriptSig.is_empty() {
+            test_debug!("Not a taproot spend: scriptSig is not empty");
+            return Err(btc_error::InvalidByteSequence);
+        }
+
+        // witness format: <sig> [annex], where the annex starts with 0x50
+        let sig_bytes = match self.witness.as_slice() {
+            [sig] => sig,
+            [sig, annex] if annex.first() == Some(&0x50) => sig,
+            _ => {
+                test_debug!(
+                    "Not a taproot key-path witness: {} items",
+                    self.witness.len()
+                );
+                return Err(btc_error::InvalidByteSequence);
+            }
+        };
+        let sighash_type = match sig_bytes.len() {
+            64 => 0x00,
+            // an explicit SIGHASH_DEFAULT is not allowed
+            65 if sig_bytes[64] != 0x00 => sig_bytes[64],
+            _ => {
+                test_debug!("Not a BIP340 signature: {} bytes", sig_bytes.len());
+                return Err(btc_error::InvalidByteSequence);
+            }
+        };
+
+        let mut output_key = [0u8; 32];
+        output_key.copy_from_slice(&spent_script_pubkey[2..34]);
+        let mut sig = [0u8; 64];
+        sig.copy_from_slice(&sig_bytes[0..64]);
+        Ok((output_key, sig, sighash_type))
+    }
 }
 
 /// All of the parsing code in this implementation is for use in Stacks 2.05 or earlier.
@@ -564,6 +709,19 @@ fn to_txid(txin: &BtcTxIn) -> (Txid, u32) {
 }
 
 impl BitcoinTxOutput {
+    /// Get the standard script template this output's scriptPubKey matched
+    pub fn script_type(&self) -> ScriptType {
+        match self.address {
+            BitcoinAddress::Legacy(ref addr) => match addr.addrtype {
+                LegacyBitcoinAddressType::PublicKeyHash => ScriptType::P2PKH,
+                LegacyBitcoinAddressType::ScriptHash => ScriptType::P2SH,
+            },
+            BitcoinAddress::Segwit(SegwitBitcoinAddress::P2WPKH(..)) => ScriptType::P2WPKH,
+            BitcoinAddress::Segwit(SegwitBitcoinAddress::P2WSH(..)) => ScriptType::P2WSH,
+            BitcoinAddress::Segwit(SegwitBitcoinAddress::P2TR(..)) => ScriptType::P2TR,
+        }
+    }
+
     /// Parse a BitcoinTxOutput from a Bitcoin scriptpubkey and its value in satoshis.
     /// Only supports legacy (p2pkh, p2sh) addresses.
     /// WARNING: Cannot distinguish between p2sh and segwit-p2sh
@@ -642,9 +800,11 @@ mod tests {
     use crate::burnchains::bitcoin::address::{
         BitcoinAddress, LegacyBitcoinAddress, LegacyBitcoinAddressType, SegwitBitcoinAddress,
     };
-    use crate::burnchains::bitcoin::keys::BitcoinPublicKey;
+    use crate::burnchains::bitcoin::keys::{verify_schnorr, BitcoinPublicKey};
     use crate::burnchains::bitcoin::BitcoinInputType;
+    use crate::burnchains::bitcoin::BitcoinMultisigScript;
     use crate::burnchains::bitcoin::BitcoinNetworkType;
+    use crate::burnchains::bitcoin::Error as btc_error;
     use crate::burnchains::Txid;
     use stacks_common::deps_common::bitcoin::blockdata::script::{Builder, Script};
     use stacks_common::deps_common::bitcoin::blockdata::transaction::Transaction;
@@ -813,6 +973,205 @@ mod tests {
         }
     }
 
+    #[test]
+    fn tx_input_p2wpkh_public_key() {
+        // native p2wpkh and p2sh-p2wpkh examples from BIP143
+        let native_tx: Transaction = bitcoinlib_deserialize(&hex_bytes("01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000").unwrap()).unwrap();
+        let native_spent_script =
+            hex_bytes("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1").unwrap();
+        let native_pubkey = BitcoinPublicKey::from_hex(
+            "025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357",
+        )
+        .unwrap();
+
+        let p2sh_tx: Transaction = bitcoinlib_deserialize(&hex_bytes("01000000000101db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a5477010000001716001479091972186c449eb1ded22b78e40d009bdf0089feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac02473044022047ac8e878352d3ebbde1c94ce3a10d057c24175747116f8288e5d794d12d482f0220217f36a485cae903c713331d877c1f64677e3622ad4010726870540656fe9dcb012103ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a2687392040000").unwrap()).unwrap();
+        let p2sh_spent_script =
+            hex_bytes("a9144733f37cf4db86fbc2efed2500b4f4e49f31202387").unwrap();
+        let p2sh_pubkey = BitcoinPublicKey::from_hex(
+            "03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873",
+        )
+        .unwrap();
+
+        let raw_input = |txin: &_| match BitcoinTxInput::from_bitcoin_txin_raw(txin) {
+            BitcoinTxInput::Raw(raw) => raw,
+            BitcoinTxInput::Structured(_) => unreachable!(),
+        };
+        let native_input = raw_input(&native_tx.input[1]);
+        let p2sh_input = raw_input(&p2sh_tx.input[0]);
+
+        assert_eq!(
+            native_input
+                .get_p2wpkh_public_key(&native_spent_script)
+                .unwrap(),
+            native_pubkey
+        );
+        assert_eq!(
+            p2sh_input
+                .get_p2wpkh_public_key(&p2sh_spent_script)
+                .unwrap(),
+            p2sh_pubkey
+        );
+
+        // the key must hash to the spent program
+        match native_input.get_p2wpkh_public_key(
+            &hex_bytes("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a2").unwrap(),
+        ) {
+            Err(btc_error::WitnessProgramMismatch) => {}
+            res => panic!("Expected WitnessProgramMismatch, got {:?}", res),
+        }
+
+        // the redeem script must hash to the spent script hash
+        match p2sh_input.get_p2wpkh_public_key(
+            &hex_bytes("a9144733f37cf4db86fbc2efed2500b4f4e49f31202487").unwrap(),
+        ) {
+            Err(btc_error::WitnessProgramMismatch) => {}
+            res => panic!("Expected WitnessProgramMismatch, got {:?}", res),
+        }
+
+        // not p2wpkh spends
+        let legacy_input = raw_input(&native_tx.input[0]);
+        match legacy_input.get_p2wpkh_public_key(&native_spent_script) {
+            Err(btc_error::InvalidByteSequence) => {}
+            res => panic!("Expected InvalidByteSequence, got {:?}", res),
+        }
+        match p2sh_input.get_p2wpkh_public_key(&native_spent_script) {
+            Err(btc_error::InvalidByteSequence) => {}
+            res => panic!("Expected InvalidByteSequence, got {:?}", res),
+        }
+        match native_input.get_p2wpkh_public_key(&p2sh_spent_script) {
+            Err(btc_error::InvalidByteSequence) => {}
+            res => panic!("Expected InvalidByteSequence, got {:?}", res),
+        }
+        match native_input.get_p2wpkh_public_key(
+            &hex_bytes("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").unwrap(),
+        ) {
+            Err(btc_error::InvalidByteSequence) => {}
+            res => panic!("Expected InvalidByteSequence, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn tx_input_taproot_key_path_signature() {
+        // output key, message, and signature from the BIP340 test vectors
+        let output_key =
+            hex_bytes("dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659").unwrap();
+        let msg =
+            hex_bytes("243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89").unwrap();
+        let sig = hex_bytes("6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de33418906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a").unwrap();
+
+        let mut spent_script = vec![0x51, 0x20];
+        spent_script.extend_from_slice(&output_key);
+        let mut sig_all = sig.clone();
+        sig_all.push(0x01);
+        let make_input = |script_sig: Vec<u8>, witness: Vec<Vec<u8>>| BitcoinTxInputRaw {
+            scriptSig: script_sig,
+            witness,
+            tx_ref: (Txid([0; 32]), 0),
+        };
+
+        for (witness, expected_sighash_type) in [
+            (vec![sig.clone()], 0x00),
+            (vec![sig_all.clone()], 0x01),
+            (vec![sig.clone(), vec![0x50, 0x01, 0x02]], 0x00),
+        ]
+        .iter()
+        {
+            let (key, signature, sighash_type) = make_input(vec![], witness.clone())
+                .get_taproot_key_path_signature(&spent_script)
+                .unwrap();
+            assert_eq!(key.to_vec(), output_key);
+            assert_eq!(signature.to_vec(), sig);
+            assert_eq!(sighash_type, *expected_sighash_type);
+
+            let mut msg_bytes = [0u8; 32];
+            msg_bytes.copy_from_slice(&msg);
+            assert!(verify_schnorr(&key, &msg_bytes, &signature).unwrap());
+        }
+
+        let mut explicit_default = sig.clone();
+        explicit_default.push(0x00);
+        let bad_cases = vec![
+            // not a p2tr output
+            (vec![], vec![sig.clone()], spent_script[1..].to_vec()),
+            (vec![], vec![sig.clone()], {
+                let mut p2wsh = spent_script.clone();
+                p2wsh[0] = 0x00;
+                p2wsh
+            }),
+            // not a key-path spend
+            (vec![0x00], vec![sig.clone()], spent_script.clone()),
+            (vec![], vec![], spent_script.clone()),
+            (vec![], vec![sig.clone(), vec![0x51]], spent_script.clone()),
+            // not a BIP340 signature
+            (vec![], vec![sig[0..63].to_vec()], spent_script.clone()),
+            (vec![], vec![explicit_default], spent_script.clone()),
+        ];
+        for (script_sig, witness, spent) in bad_cases.into_iter() {
+            match make_input(script_sig, witness).get_taproot_key_path_signature(&spent) {
+                Err(btc_error::InvalidByteSequence) => {}
+                res => panic!("Expected InvalidByteSequence, got {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn multisig_script_parse() {
+        // 2-of-3 multisig redeem script, compressed keys
+        let redeem_script = hex_bytes("522103310188e911026cf18c3ce274e0ebb5f95b007f230d8cb7d09879d96dbeab1aff210243930746e6ed6552e03359db521b088134652905bd2d1541fa9124303a41e95621029e03a901b85534ff1e92c43c74431f7ce72046060fcf7a95c37e148f78c7725553ae").unwrap();
+        let expected_keys = vec![
+            BitcoinPublicKey::from_hex(
+                "03310188e911026cf18c3ce274e0ebb5f95b007f230d8cb7d09879d96dbeab1aff",
+            )
+            .unwrap(),
+            BitcoinPublicKey::from_hex(
+                "0243930746e6ed6552e03359db521b088134652905bd2d1541fa9124303a41e956",
+            )
+            .unwrap(),
+            BitcoinPublicKey::from_hex(
+                "029e03a901b85534ff1e92c43c74431f7ce72046060fcf7a95c37e148f78c77255",
+            )
+            .unwrap(),
+        ];
+
+        let multisig = BitcoinMultisigScript::from_bytes(&redeem_script).unwrap();
+        assert_eq!(multisig.num_required, 2);
+        assert_eq!(multisig.keys, expected_keys);
+
+        // a bare multisig scriptPubKey is the same script
+        let script_pubkey = Builder::from(redeem_script.clone()).into_script();
+        assert_eq!(
+            BitcoinMultisigScript::from_script(&script_pubkey).unwrap(),
+            multisig
+        );
+
+        // 4-of-3
+        let mut bad_threshold = redeem_script.clone();
+        bad_threshold[0] = 0x54;
+        assert!(BitcoinMultisigScript::from_bytes(&bad_threshold).is_none());
+
+        // 0-of-3
+        let mut zero_threshold = redeem_script.clone();
+        zero_threshold[0] = 0x00;
+        assert!(BitcoinMultisigScript::from_bytes(&zero_threshold).is_none());
+
+        // n is 4, but there are 3 keys
+        let mut bad_key_count = redeem_script.clone();
+        let n_index = bad_key_count.len() - 2;
+        bad_key_count[n_index] = 0x54;
+        assert!(BitcoinMultisigScript::from_bytes(&bad_key_count).is_none());
+
+        // not OP_CHECKMULTISIG
+        let mut bad_opcode = redeem_script.clone();
+        let op_index = bad_opcode.len() - 1;
+        bad_opcode[op_index] = 0xac;
+        assert!(BitcoinMultisigScript::from_bytes(&bad_opcode).is_none());
+
+        // a key is not a valid public key
+        let mut bad_key = redeem_script.clone();
+        bad_key[2] = 0x05;
+        assert!(BitcoinMultisigScript::from_bytes(&bad_key).is_none());
+    }
+
     #[test]
     fn tx_input_segwit_p2wpkh_p2sh_structured() {
         // should extract keys from segwit p2wpkh-over-p2sh witness script
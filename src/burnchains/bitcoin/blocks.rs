@@ -14,13 +14,20 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cmp;
+use std::collections::{HashSet, VecDeque};
+use std::io::Read;
 use std::ops::Deref;
+use std::time::{Duration, Instant};
 
 use crate::burnchains::bitcoin::address::BitcoinAddress;
 use crate::burnchains::bitcoin::bits;
-use crate::burnchains::bitcoin::indexer::BitcoinIndexer;
+use crate::burnchains::bitcoin::bloom;
+use crate::burnchains::bitcoin::indexer::{BitcoinIndexer, SyncMode};
+use crate::burnchains::bitcoin::keys;
 use crate::burnchains::bitcoin::keys::BitcoinPublicKey;
 use crate::burnchains::bitcoin::messages::BitcoinMessageHandler;
+use crate::burnchains::bitcoin::rpc::BitcoinRpcClient;
 use crate::burnchains::bitcoin::BitcoinInputType;
 use crate::burnchains::bitcoin::BitcoinNetworkType;
 use crate::burnchains::bitcoin::Error as btc_error;
@@ -36,13 +43,18 @@ use crate::burnchains::{
     BurnchainBlock, BurnchainTransaction, MagicBytes, Txid, MAGIC_BYTES_LENGTH,
 };
 use crate::deps;
-use stacks_common::deps_common::bitcoin::blockdata::block::{Block, LoneBlockHeader};
+use stacks_common::deps_common::bitcoin::blockdata::block::{Block, BlockHeader, LoneBlockHeader};
 use stacks_common::deps_common::bitcoin::blockdata::opcodes::All as btc_opcodes;
 use stacks_common::deps_common::bitcoin::blockdata::script::{Instruction, Script};
 use stacks_common::deps_common::bitcoin::blockdata::transaction::Transaction;
+use stacks_common::deps_common::bitcoin::network::encodable::{ConsensusDecodable, VarInt};
 use stacks_common::deps_common::bitcoin::network::message as btc_message;
-use stacks_common::deps_common::bitcoin::network::serialize::BitcoinHash;
-use stacks_common::deps_common::bitcoin::util::hash::bitcoin_merkle_root;
+use stacks_common::deps_common::bitcoin::network::message_bloom::MerkleBlock;
+use stacks_common::deps_common::bitcoin::network::serialize::{
+    deserialize, BitcoinHash, RawDecoder,
+};
+use stacks_common::deps_common::bitcoin::util::hash::{bitcoin_merkle_root, Sha256dHash};
+use stacks_common::util::get_epoch_time_secs;
 use stacks_common::util::hash::to_hex;
 use stacks_common::util::log;
 
@@ -76,6 +88,9 @@ impl BurnHeaderIPC for BitcoinHeaderIPC {
 pub struct BitcoinBlockIPC {
     pub header_data: BitcoinHeaderIPC,
     pub block_message: PeerMessage,
+    /// If `block_message` is a filtered block (a `merkleblock`), the transactions it matched,
+    /// in block order.  Empty for whole blocks.
+    pub matched_txs: Vec<Transaction>,
 }
 
 impl BurnBlockIPC for BitcoinBlockIPC {
@@ -95,23 +110,354 @@ impl BurnBlockIPC for BitcoinBlockIPC {
     }
 }
 
+/// Standard output script (scriptPubKey) templates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScriptType {
+    /// OP_DUP OP_HASH160 <20 bytes> OP_EQUALVERIFY OP_CHECKSIG
+    P2PKH,
+    /// OP_HASH160 <20 bytes> OP_EQUAL
+    P2SH,
+    /// OP_0 <20 bytes>
+    P2WPKH,
+    /// OP_0 <32 bytes>
+    P2WSH,
+    /// OP_1 <32 bytes>
+    P2TR,
+    /// Anything else
+    NonStandard,
+}
+
+/// Classify an output script by matching it against the standard script templates.
+/// Note that a P2SH output could be a segwit-P2SH output; there's no way to tell from the
+/// output script alone.
+pub fn classify_output_script(script: &Script) -> ScriptType {
+    if script.is_p2pkh() {
+        ScriptType::P2PKH
+    } else if script.is_p2sh() {
+        ScriptType::P2SH
+    } else if script.is_v0_p2wpkh() {
+        ScriptType::P2WPKH
+    } else if script.is_v0_p2wsh() {
+        ScriptType::P2WSH
+    } else if script.len() == 34
+        && script.as_bytes()[0] == btc_opcodes::OP_PUSHNUM_1 as u8
+        && script.as_bytes()[1] == btc_opcodes::OP_PUSHBYTES_32 as u8
+    {
+        ScriptType::P2TR
+    } else {
+        ScriptType::NonStandard
+    }
+}
+
+/// Most data bitcoind will relay in an OP_RETURN output (`-datacarriersize` default)
+pub const MAX_OP_RETURN_DATA_LEN: usize = 80;
+
+/// Find the data payload carried by the transaction's OP_RETURN output.
+/// Returns Ok(None) if the transaction has no OP_RETURN output, and
+/// Err(MalformedOpReturn) if its OP_RETURN is nonstandard: more than one OP_RETURN output, an
+/// OP_RETURN not followed by a single data push, or a payload over MAX_OP_RETURN_DATA_LEN bytes.
+/// A bare OP_RETURN carries an empty payload.
+pub fn find_op_return(tx: &Transaction) -> Result<Option<&[u8]>, btc_error> {
+    let mut op_returns = tx
+        .output
+        .iter()
+        .filter(|out| out.script_pubkey.is_op_return());
+    let data_output = match op_returns.next() {
+        Some(out) => &out.script_pubkey,
+        None => return Ok(None),
+    };
+    if op_returns.next().is_some() {
+        return Err(btc_error::MalformedOpReturn(
+            "transaction has multiple OP_RETURN outputs".to_string(),
+        ));
+    }
+
+    let script_pieces = bits::parse_script(data_output);
+    let data = match script_pieces.as_slice() {
+        [Instruction::Op(_)] => &[][..],
+        [Instruction::Op(_), Instruction::PushBytes(data)] => *data,
+        _ => {
+            return Err(btc_error::MalformedOpReturn(
+                "OP_RETURN is not followed by a single data push".to_string(),
+            ));
+        }
+    };
+    if data.len() > MAX_OP_RETURN_DATA_LEN {
+        return Err(btc_error::MalformedOpReturn(format!(
+            "OP_RETURN payload is {} bytes (max {})",
+            data.len(),
+            MAX_OP_RETURN_DATA_LEN
+        )));
+    }
+    Ok(Some(data))
+}
+
+/// Get the merkle branch of the transaction at `position` among a block's `txids` (e.g. from
+/// `getblock`):  the hashes it's paired with on the way up to the merkle root, lowest first (see
+/// `verify_merkle_proof()`).  Fails with InvalidMerkleProof if there's no transaction at
+/// `position`, or if the txids make a mutated tree (see
+/// `BitcoinBlockParser::checked_merkle_root()`).
+pub fn merkle_branch(txids: &[Sha256dHash], position: u32) -> Result<Vec<Sha256dHash>, btc_error> {
+    if (position as usize) >= txids.len() {
+        return Err(btc_error::InvalidMerkleProof(format!(
+            "no transaction at {} of {}",
+            position,
+            txids.len()
+        )));
+    }
+    let mut level = txids.to_vec();
+    let mut pos = position as usize;
+    let mut branch = vec![];
+    while level.len() > 1 {
+        if level
+            .chunks(2)
+            .any(|pair| pair.len() == 2 && pair[0] == pair[1])
+        {
+            return Err(btc_error::InvalidMerkleProof(
+                "duplicate hashes in the merkle tree".to_string(),
+            ));
+        }
+        // the last node of a level with an odd number of nodes is paired with itself
+        branch.push(level[cmp::min(pos ^ 1, level.len() - 1)]);
+        level = level
+            .chunks(2)
+            .map(|pair| bloom::merkle_parent(&pair[0], &pair[pair.len() - 1]))
+            .collect();
+        pos >>= 1;
+    }
+    Ok(branch)
+}
+
+/// Check a merkle proof that the transaction `txid` is at `position` in the block with `header`,
+/// without trusting whoever sent the proof:  hash `txid` up the merkle `branch` (see
+/// `merkle_branch()`), and compare the result to the header's merkle root.  Use a header we
+/// validated ourselves, such as the one the SPV client has at the block's height.
+/// Returns whether or not the branch leads to the merkle root.  A branch with a hash paired with
+/// an equal one on its right, which only a mutated tree (CVE-2012-2459) has, or with a `position`
+/// past the end of a tree the depth of `branch`, is malformed, and fails with InvalidMerkleProof.
+pub fn verify_merkle_proof(
+    txid: &Sha256dHash,
+    branch: &[Sha256dHash],
+    position: u32,
+    header: &BlockHeader,
+) -> Result<bool, btc_error> {
+    if branch.len() < 32 && (position >> branch.len()) != 0 {
+        return Err(btc_error::InvalidMerkleProof(format!(
+            "position {} is past the end of a merkle tree of depth {}",
+            position,
+            branch.len()
+        )));
+    }
+    if branch.len() > 32 {
+        return Err(btc_error::InvalidMerkleProof(format!(
+            "merkle branch has {} hashes",
+            branch.len()
+        )));
+    }
+
+    let mut hash = *txid;
+    let mut pos = position;
+    for sibling in branch.iter() {
+        if pos & 1 == 1 {
+            if *sibling == hash {
+                // the right-hand copy of a duplicated node
+                return Err(btc_error::InvalidMerkleProof(
+                    "duplicate hashes in the merkle branch".to_string(),
+                ));
+            }
+            hash = bloom::merkle_parent(sibling, &hash);
+        } else {
+            hash = bloom::merkle_parent(&hash, sibling);
+        }
+        pos >>= 1;
+    }
+    Ok(hash == header.merkle_root)
+}
+
+/// A transaction matched by a filtered block (BIP37), with the merkle proof that it's in the
+/// block
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProvenTransaction {
+    pub tx: Transaction,
+    /// Position of the transaction in the whole block
+    pub position: u32,
+    /// Merkle branch from the transaction up to the block's merkle root (see `merkle_branch()`)
+    pub branch: Vec<Sha256dHash>,
+}
+
+impl ProvenTransaction {
+    /// Check the proof against `header` (see `verify_merkle_proof()`)
+    pub fn verify(&self, header: &BlockHeader) -> Result<bool, btc_error> {
+        verify_merkle_proof(&self.tx.txid(), &self.branch, self.position, header)
+    }
+}
+
+/// Pair up the transactions that a filtered block (BIP37) matched with their merkle proofs:
+/// `txs` must be the transactions that the partial merkle tree of `merkle_block` matched, in
+/// block order, as the peer sends them.  Fails with InvalidMerkleBlock if the tree is malformed,
+/// or if `txs` aren't the transactions it matched.  The proofs lead to the merkle root of
+/// `merkle_block`'s header, which still has to be checked against the header we have at its
+/// height.
+pub fn extract_proven_txs(
+    merkle_block: &MerkleBlock,
+    txs: &[Transaction],
+) -> Result<Vec<ProvenTransaction>, btc_error> {
+    let matches = bloom::extract_match_branches(merkle_block)?;
+    if matches.len() != txs.len() {
+        return Err(btc_error::InvalidMerkleBlock(format!(
+            "{} transactions for {} matches",
+            txs.len(),
+            matches.len()
+        )));
+    }
+    matches
+        .into_iter()
+        .zip(txs.iter())
+        .map(|((position, txid, branch), tx)| {
+            if tx.txid() != txid {
+                return Err(btc_error::InvalidMerkleBlock(format!(
+                    "transaction {} is not the match {} at {}",
+                    tx.txid().be_hex_string(),
+                    txid.be_hex_string(),
+                    position
+                )));
+            }
+            Ok(ProvenTransaction {
+                tx: tx.clone(),
+                position,
+                branch,
+            })
+        })
+        .collect()
+}
+
 pub struct BitcoinBlockDownloader {
     cur_request: Option<BitcoinHeaderIPC>,
     cur_block: Option<BitcoinBlockIPC>,
+    /// Ask for filtered blocks instead of whole ones (see `new_filtered()`)
+    filtered: bool,
+    /// The filtered block of `cur_request` whose transactions are still arriving
+    filtered_block: Option<PendingFilteredBlock>,
     indexer: Option<BitcoinIndexer>,
+    /// Where blocks come from in `SyncMode::Rpc`, once we've downloaded any
+    rpc_client: Option<BitcoinRpcClient>,
 }
 
 pub struct BitcoinBlockParser {
     network_id: BitcoinNetworkType,
     magic_bytes: MagicBytes,
+    /// If nonzero, check burnchain transactions' P2PKH input signatures on up to this many
+    /// threads (see `set_sig_threads()`)
+    sig_threads: usize,
+}
+
+/// The signature checks for the P2PKH inputs of a block's burnchain transactions, accumulated as
+/// the block is parsed and verified together once it's done (see `keys::verify_batch()`)
+#[derive(Default)]
+struct BlockSigChecks {
+    checks: Vec<(BitcoinPublicKey, Vec<u8>, Vec<u8>)>,
+    /// For each check, the index of its transaction in the block's burnchain transactions
+    tx_indexes: Vec<usize>,
+}
+
+/// Reads a serialized block one transaction at a time, so that only the transaction being read
+/// (and not the whole block) has to be in memory
+pub struct BitcoinBlockReader<R: Read> {
+    decoder: RawDecoder<R>,
+    header: BlockHeader,
+    num_txs: u64,
+    /// IDs of the transactions read so far, for the merkle root
+    txids: Vec<Sha256dHash>,
+}
+
+/// A filtered block (BIP37) on its way from the peer:  its `merkleblock` has arrived, and the
+/// `tx` messages of the transactions it matched follow it, in block order
+struct PendingFilteredBlock {
+    header: BitcoinHeaderIPC,
+    merkle_block: MerkleBlock,
+    /// Positions in the block and txids of the matched transactions
+    matches: Vec<(u32, Sha256dHash)>,
+    txs: Vec<Transaction>,
+}
+
+impl PendingFilteredBlock {
+    /// Start on the filtered block of `header`.  Fails with InvalidMerkleBlock if the partial
+    /// merkle tree of `merkle_block` is malformed or doesn't match the header.
+    fn new(
+        header: BitcoinHeaderIPC,
+        merkle_block: MerkleBlock,
+    ) -> Result<PendingFilteredBlock, btc_error> {
+        let matches = bloom::extract_matches(&merkle_block)?;
+        Ok(PendingFilteredBlock {
+            header,
+            merkle_block,
+            matches,
+            txs: vec![],
+        })
+    }
+
+    /// Have all the matched transactions arrived?
+    fn is_complete(&self) -> bool {
+        self.txs.len() == self.matches.len()
+    }
+
+    /// Take in `tx` if it's the next matched transaction, or hand it back if it isn't (the
+    /// peer also relays transactions that match our filter as they enter its mempool)
+    fn add_tx(&mut self, tx: Transaction) -> Result<(), Transaction> {
+        match self.matches.get(self.txs.len()) {
+            Some((_, txid)) if *txid == tx.txid() => {
+                self.txs.push(tx);
+                Ok(())
+            }
+            _ => Err(tx),
+        }
+    }
+
+    fn into_ipc_block(self) -> BitcoinBlockIPC {
+        BitcoinBlockIPC {
+            header_data: self.header,
+            block_message: btc_message::NetworkMessage::MerkleBlock(self.merkle_block),
+            matched_txs: self.txs,
+        }
+    }
+}
+
+/// Ask the peer for the blocks of `block_hashes`:  as filtered blocks if `filtered` (see
+/// `BitcoinBlockDownloader::new_filtered()`), and whole otherwise
+fn request_blocks(
+    indexer: &mut BitcoinIndexer,
+    block_hashes: &Vec<Sha256dHash>,
+    filtered: bool,
+) -> Result<(), btc_error> {
+    if filtered {
+        indexer.send_filtered_getdata(block_hashes)
+    } else {
+        indexer.send_getdata(block_hashes)
+    }
 }
 
 impl BitcoinBlockDownloader {
+    /// Make a downloader of whole blocks, for the burnchain sync
     pub fn new(indexer: BitcoinIndexer) -> BitcoinBlockDownloader {
         BitcoinBlockDownloader {
             cur_request: None,
             cur_block: None,
+            filtered: false,
+            filtered_block: None,
             indexer: Some(indexer),
+            rpc_client: None,
+        }
+    }
+
+    /// Make a downloader of filtered blocks (BIP37) from peers that serve bloom filters, holding
+    /// only the transactions that match the indexer's `filter_scripts` and `filter_outpoints`
+    /// (see `BitcoinIndexer::load_bloom_filter()` and `extract_proven_txs()`).  These are for
+    /// watching particular addresses only:  a filter of our own watch targets can't match the
+    /// burn operations anyone else sends, so `BitcoinBlockParser` refuses filtered blocks.
+    pub fn new_filtered(indexer: BitcoinIndexer) -> BitcoinBlockDownloader {
+        BitcoinBlockDownloader {
+            filtered: true,
+            ..BitcoinBlockDownloader::new(indexer)
         }
     }
 
@@ -122,6 +468,13 @@ impl BitcoinBlockDownloader {
         // due to &mut self.
         let mut indexer = self.indexer.take().unwrap();
 
+        if indexer.config.sync_mode == SyncMode::Rpc {
+            let res = self.download_rpc(&mut indexer, header);
+            self.indexer = Some(indexer);
+            self.cur_request = None;
+            return res;
+        }
+
         indexer.peer_communicate(self, false)?;
 
         self.indexer = Some(indexer);
@@ -133,6 +486,135 @@ impl BitcoinBlockDownloader {
         let ipc_block = self.cur_block.take().unwrap();
         Ok(ipc_block)
     }
+
+    /// Download the blocks of `headers` with up to `config.block_download_window` of them
+    /// requested from the peer at once, and hand them to `deliver` in the order of their headers.
+    /// Blocks that arrive ahead of the ones before them are held until those arrive, so at most
+    /// `block_download_window` blocks are in memory at once.  A block that hasn't arrived
+    /// `config.block_request_timeout` after it was requested is asked for again on its own,
+    /// without disturbing the rest of the window; after a reconnect, every outstanding block is
+    /// asked for again.  In `SyncMode::Rpc`, the blocks are instead fetched over JSON-RPC one at a
+    /// time.
+    pub fn run_window(
+        &mut self,
+        headers: &mut dyn Iterator<Item = BitcoinHeaderIPC>,
+        deliver: &mut dyn FnMut(BitcoinBlockIPC) -> Result<(), burnchain_error>,
+    ) -> Result<(), burnchain_error> {
+        // should always work, since at most one thread can call this method at once
+        // due to &mut self.
+        let mut indexer = self.indexer.take().unwrap();
+
+        if indexer.config.sync_mode == SyncMode::Rpc {
+            let res = self.run_rpc(&mut indexer, headers, deliver);
+            self.indexer = Some(indexer);
+            return res;
+        }
+
+        let mut window = BlockWindow {
+            headers,
+            deliver,
+            window: cmp::max(indexer.config.block_download_window, 1),
+            request_timeout: indexer.config.block_request_timeout,
+            slots: VecDeque::new(),
+            no_more_headers: false,
+            deliver_error: None,
+            last_block_time: Instant::now(),
+            throttled_time: indexer.throttled_time(),
+            filtered: self.filtered,
+            filtered_block: None,
+        };
+        let res = indexer.peer_communicate(&mut window, false);
+        let res = indexer.with_peer_context(res);
+        indexer.runtime.last_getdata_send_time = 0;
+        self.indexer = Some(indexer);
+
+        if let Some(e) = window.deliver_error {
+            return Err(e);
+        }
+        res.map_err(|e| {
+            warn!("Failed to download blocks: {}", &e);
+            BitcoinBlockDownloader::download_error(e.error)
+        })?;
+
+        assert!(
+            window.no_more_headers && window.slots.is_empty(),
+            "BUG: stopped downloading blocks before delivering them all"
+        );
+        Ok(())
+    }
+
+    /// Download the blocks of `headers` over JSON-RPC, and hand them to `deliver` in order
+    fn run_rpc(
+        &mut self,
+        indexer: &mut BitcoinIndexer,
+        headers: &mut dyn Iterator<Item = BitcoinHeaderIPC>,
+        deliver: &mut dyn FnMut(BitcoinBlockIPC) -> Result<(), burnchain_error>,
+    ) -> Result<(), burnchain_error> {
+        while let Some(header) = headers.next() {
+            let ipc_block = self
+                .download_rpc(indexer, &header)
+                .map_err(BitcoinBlockDownloader::download_error)?;
+            deliver(ipc_block)?;
+        }
+        Ok(())
+    }
+
+    /// Get the block of `header` with `getblock`, and check it against the header like a block
+    /// from the peer would be.  bitcoind's errors (and a block that doesn't match) come back as
+    /// RpcError.
+    fn download_rpc(
+        &mut self,
+        indexer: &mut BitcoinIndexer,
+        header: &BitcoinHeaderIPC,
+    ) -> Result<BitcoinBlockIPC, btc_error> {
+        indexer.check_shutdown_deadline()?;
+        if self.rpc_client.is_none() {
+            let mut rpc_client = BitcoinRpcClient::from_config(
+                &indexer.config,
+                indexer.runtime.rpc_credentials.clone(),
+            )?;
+            indexer.check_rpc_node(&mut rpc_client)?;
+            self.rpc_client = Some(rpc_client);
+        }
+        let rpc_client = self
+            .rpc_client
+            .as_mut()
+            .expect("BUG: no RPC client after creating one");
+
+        let block_hash = header.block_header.header.bitcoin_hash();
+        let block_bytes = rpc_client.get_raw_block(&block_hash)?;
+        let num_bytes = block_bytes.len() as u64;
+        indexer.update_sync_stats(|stats| stats.record_bytes_downloaded(num_bytes));
+
+        let block: Block = deserialize(&block_bytes)?;
+        if !BitcoinBlockParser::check_block(&block, &header.block_header) {
+            return Err(btc_error::RpcError(format!(
+                "getblock returned block {}, which does not match header {} at height {}",
+                &to_hex(block.bitcoin_hash().as_bytes()),
+                &to_hex(block_hash.as_bytes()),
+                header.block_height
+            )));
+        }
+        indexer.throttle_block_download(num_bytes)?;
+
+        debug!(
+            "Got block {}: {}",
+            header.block_height,
+            &to_hex(BurnchainHeaderHash::from_bitcoin_hash(&block_hash).as_bytes())
+        );
+        Ok(BitcoinBlockIPC {
+            header_data: header.clone(),
+            block_message: btc_message::NetworkMessage::Block(block),
+            matched_txs: vec![],
+        })
+    }
+
+    fn download_error(e: btc_error) -> burnchain_error {
+        match e {
+            btc_error::TimedOut => burnchain_error::TrySyncAgain,
+            x => burnchain_error::DownloadError(x),
+        }
+    }
 }
 
 impl BurnchainBlockDownloader for BitcoinBlockDownloader {
@@ -140,10 +622,16 @@ impl BurnchainBlockDownloader for BitcoinBlockDownloader {
     type B = BitcoinBlockIPC;
 
     fn download(&mut self, header: &BitcoinHeaderIPC) -> Result<BitcoinBlockIPC, burnchain_error> {
-        self.run(header).map_err(|e| match e {
-            btc_error::TimedOut => burnchain_error::TrySyncAgain,
-            x => burnchain_error::DownloadError(x),
-        })
+        self.run(header)
+            .map_err(BitcoinBlockDownloader::download_error)
+    }
+
+    fn download_all(
+        &mut self,
+        headers: &mut dyn Iterator<Item = BitcoinHeaderIPC>,
+        deliver: &mut dyn FnMut(BitcoinBlockIPC) -> Result<(), burnchain_error>,
+    ) -> Result<(), burnchain_error> {
+        self.run_window(headers, deliver)
     }
 }
 
@@ -154,10 +642,13 @@ impl BitcoinMessageHandler for BitcoinBlockDownloader {
         match self.cur_request {
             None => panic!("No block header set"),
             Some(ref ipc_header) => {
+                indexer.check_peer_serves_block(ipc_header.block_height)?;
+                self.filtered_block = None;
+                if self.filtered {
+                    indexer.load_bloom_filter()?;
+                }
                 let block_hash = ipc_header.block_header.header.bitcoin_hash().clone();
-                indexer
-                    .send_getdata(&vec![block_hash])
-                    .and_then(|_r| Ok(true))
+                request_blocks(indexer, &vec![block_hash], self.filtered).and_then(|_r| Ok(true))
             }
         }
     }
@@ -183,14 +674,10 @@ impl BitcoinMessageHandler for BitcoinBlockDownloader {
 
         let ipc_header = self.cur_request.as_ref().unwrap();
 
-        let height;
-        let header;
-        let block_hash;
-
-        match msg {
-            btc_message::NetworkMessage::Block(ref block) => {
+        let ipc_block = match msg {
+            btc_message::NetworkMessage::Block(block) => {
                 // make sure this block matches
-                if !BitcoinBlockParser::check_block(block, &ipc_header.block_header) {
+                if block.bitcoin_hash() != ipc_header.block_header.header.bitcoin_hash() {
                     debug!(
                         "Requested block {}, got block {}",
                         &to_hex(ipc_header.block_header.header.bitcoin_hash().as_bytes()),
@@ -198,23 +685,131 @@ impl BitcoinMessageHandler for BitcoinBlockDownloader {
                     );
 
                     // try again
-                    indexer.send_getdata(&vec![ipc_header.block_header.header.bitcoin_hash()])?;
+                    request_blocks(
+                        indexer,
+                        &vec![ipc_header.block_header.header.bitcoin_hash()],
+                        self.filtered,
+                    )?;
                     return Ok(true);
                 }
+                if !BitcoinBlockParser::check_block(&block, &ipc_header.block_header) {
+                    let reason = format!(
+                        "{}:{} sent block {} ({}) with transactions that do not match its merkle root",
+                        indexer.peer_host(),
+                        indexer.peer_port(),
+                        ipc_header.block_height,
+                        block.bitcoin_hash().be_hex_string()
+                    );
+                    return Err(indexer.peer_sent_bad_block(reason));
+                }
+
+                BitcoinBlockIPC {
+                    header_data: ipc_header.clone(),
+                    block_message: btc_message::NetworkMessage::Block(block),
+                    matched_txs: vec![],
+                }
+            }
+            btc_message::NetworkMessage::MerkleBlock(merkle_block) if self.filtered => {
+                let block_hash = merkle_block.header.bitcoin_hash();
+                if let Some(ref filtered_block) = self.filtered_block {
+                    let reason = format!(
+                        "{}:{} sent filtered block {} before all the transactions of block {}",
+                        indexer.peer_host(),
+                        indexer.peer_port(),
+                        block_hash.be_hex_string(),
+                        filtered_block.header.block_height
+                    );
+                    return Err(indexer.peer_sent_bad_block(reason));
+                }
+                if block_hash != ipc_header.block_header.header.bitcoin_hash() {
+                    debug!(
+                        "Requested block {}, got filtered block {}",
+                        &to_hex(ipc_header.block_header.header.bitcoin_hash().as_bytes()),
+                        &to_hex(block_hash.as_bytes())
+                    );
 
-                // clear timeout
-                indexer.runtime.last_getdata_send_time = 0;
+                    // try again
+                    request_blocks(
+                        indexer,
+                        &vec![ipc_header.block_header.header.bitcoin_hash()],
+                        self.filtered,
+                    )?;
+                    return Ok(true);
+                }
 
-                // got valid data!
-                height = ipc_header.block_height;
-                header = self.cur_request.clone().unwrap();
-                block_hash = ipc_header.block_header.header.bitcoin_hash();
+                let filtered_block =
+                    match PendingFilteredBlock::new(ipc_header.clone(), merkle_block) {
+                        Ok(filtered_block) => filtered_block,
+                        Err(e) => {
+                            let reason = format!(
+                                "{}:{} sent filtered block {} ({}): {}",
+                                indexer.peer_host(),
+                                indexer.peer_port(),
+                                ipc_header.block_height,
+                                block_hash.be_hex_string(),
+                                e
+                            );
+                            return Err(indexer.peer_sent_bad_block(reason));
+                        }
+                    };
+                if !filtered_block.is_complete() {
+                    // wait for its transactions
+                    self.filtered_block = Some(filtered_block);
+                    return Ok(true);
+                }
+                filtered_block.into_ipc_block()
+            }
+            btc_message::NetworkMessage::Tx(tx) => {
+                let tx = match self.filtered_block {
+                    Some(ref mut filtered_block) => filtered_block.add_tx(tx).err(),
+                    None => Some(tx),
+                };
+                if let Some(tx) = tx {
+                    return Err(btc_error::UnhandledMessage(
+                        btc_message::NetworkMessage::Tx(tx),
+                    ));
+                }
+                if !self
+                    .filtered_block
+                    .as_ref()
+                    .map(|filtered_block| filtered_block.is_complete())
+                    .unwrap_or(false)
+                {
+                    return Ok(true);
+                }
+                self.filtered_block
+                    .take()
+                    .expect("BUG: no filtered block")
+                    .into_ipc_block()
+            }
+            btc_message::NetworkMessage::NotFound(ref invs) => {
+                // the peer doesn't have the block, so asking it again won't help
+                let block_hash = ipc_header.block_header.header.bitcoin_hash();
+                if !invs.iter().any(|inv| inv.hash == block_hash) {
+                    debug!("Got notfound for blocks we did not ask for");
+                    return Ok(true);
+                }
+                let reason = format!(
+                    "{}:{} sent notfound for block {} ({})",
+                    indexer.peer_host(),
+                    indexer.peer_port(),
+                    ipc_header.block_height,
+                    block_hash.be_hex_string()
+                );
+                return Err(indexer.peer_lacks_block(reason));
             }
             _ => {
                 return Err(btc_error::UnhandledMessage(msg));
             }
-        }
+        };
+
+        // clear timeout
+        indexer.runtime.last_getdata_send_time = 0;
+        indexer.runtime.pruned_failovers = 0;
 
+        // got valid data!
+        let height = ipc_block.header_data.block_height;
+        let block_hash = ipc_block.header_data.block_header.header.bitcoin_hash();
         debug!(
             "Got block {}: {}",
             height,
@@ -222,25 +817,462 @@ impl BitcoinMessageHandler for BitcoinBlockDownloader {
         );
 
         // store response. we're done.
-        let ipc_block = BitcoinBlockIPC {
-            header_data: header,
-            block_message: msg,
-        };
-
         self.cur_block = Some(ipc_block);
         Ok(false)
     }
 }
 
+/// A block that `BlockWindow` has asked the peer for, and the block itself once it arrives
+struct WindowSlot {
+    header: BitcoinHeaderIPC,
+    block: Option<BitcoinBlockIPC>,
+    /// When the block was last asked for
+    requested_at: Instant,
+}
+
+/// Message handler that keeps up to `window` blocks requested from the peer at once, and hands
+/// them to `deliver` in height order (see `BitcoinBlockDownloader::run_window()`)
+struct BlockWindow<'a> {
+    headers: &'a mut dyn Iterator<Item = BitcoinHeaderIPC>,
+    deliver: &'a mut dyn FnMut(BitcoinBlockIPC) -> Result<(), burnchain_error>,
+    window: usize,
+    request_timeout: Duration,
+    /// Blocks requested but not yet delivered, in height order
+    slots: VecDeque<WindowSlot>,
+    /// Set once `headers` runs out
+    no_more_headers: bool,
+    /// Set if `deliver` fails, which ends the download
+    deliver_error: Option<burnchain_error>,
+    /// When the last block we asked for arrived (or the conversation began)
+    last_block_time: Instant,
+    /// The indexer's `throttled_time()` when we last checked it
+    throttled_time: Duration,
+    /// Ask for filtered blocks instead of whole ones (see `BitcoinBlockDownloader::new_filtered()`)
+    filtered: bool,
+    /// The filtered block whose transactions are still arriving, if any
+    filtered_block: Option<PendingFilteredBlock>,
+}
+
+impl<'a> BlockWindow<'a> {
+    /// Deliver the blocks at the front of the window that have arrived, and request the blocks
+    /// of the next headers until the window is full again.  Returns false once every block has
+    /// been delivered, or `deliver` fails.
+    fn advance(&mut self, indexer: &mut BitcoinIndexer) -> Result<bool, btc_error> {
+        while self
+            .slots
+            .front()
+            .map(|slot| slot.block.is_some())
+            .unwrap_or(false)
+        {
+            let ipc_block = self
+                .slots
+                .pop_front()
+                .and_then(|slot| slot.block)
+                .expect("BUG: front slot has no block");
+            if let Err(e) = (self.deliver)(ipc_block) {
+                self.deliver_error = Some(e);
+                return Ok(false);
+            }
+        }
+
+        let mut block_hashes = vec![];
+        let mut lowest_height = None;
+        while !self.no_more_headers && self.slots.len() < self.window {
+            let header = match self.headers.next() {
+                Some(header) => header,
+                None => {
+                    self.no_more_headers = true;
+                    break;
+                }
+            };
+            block_hashes.push(header.block_header.header.bitcoin_hash());
+            lowest_height = lowest_height.or(Some(header.block_height));
+            self.slots.push_back(WindowSlot {
+                header,
+                block: None,
+                requested_at: Instant::now(),
+            });
+        }
+        if let Some(height) = lowest_height {
+            // the slots stay put, so they're asked for again if we fail over to another peer
+            indexer.check_peer_serves_block(height)?;
+            request_blocks(indexer, &block_hashes, self.filtered)?;
+        }
+
+        Ok(!self.slots.is_empty())
+    }
+
+    /// Don't count the time the indexer spent throttling block downloads since we last checked
+    /// towards how long we've been waiting on the peer
+    fn discount_throttled_time(&mut self, indexer: &BitcoinIndexer) {
+        let throttled_time = indexer.throttled_time();
+        let paused = throttled_time.saturating_sub(self.throttled_time);
+        self.throttled_time = throttled_time;
+        if paused == Duration::from_secs(0) {
+            return;
+        }
+        for slot in self.slots.iter_mut() {
+            slot.requested_at += paused;
+        }
+        self.last_block_time += paused;
+    }
+
+    /// Index of the slot waiting on the block with hash `block_hash`, if any
+    fn waiting_slot(&self, block_hash: &Sha256dHash) -> Option<usize> {
+        self.slots.iter().position(|slot| {
+            slot.block.is_none() && slot.header.block_header.header.bitcoin_hash() == *block_hash
+        })
+    }
+
+    /// Ask for the blocks that were asked for at least `max_age` ago and haven't arrived yet,
+    /// leaving the rest of the window alone
+    fn rerequest_missing(
+        &mut self,
+        indexer: &mut BitcoinIndexer,
+        max_age: Duration,
+    ) -> Result<(), btc_error> {
+        let now = Instant::now();
+        let mut block_hashes = vec![];
+        let mut lowest_height = None;
+        for slot in self.slots.iter_mut() {
+            if slot.block.is_none() && now.duration_since(slot.requested_at) >= max_age {
+                block_hashes.push(slot.header.block_header.header.bitcoin_hash());
+                lowest_height = lowest_height.or(Some(slot.header.block_height));
+                slot.requested_at = now;
+            }
+        }
+        if let Some(height) = lowest_height {
+            debug!("Request {} missing block(s) again", block_hashes.len());
+            indexer.check_peer_serves_block(height)?;
+            request_blocks(indexer, &block_hashes, self.filtered)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> BitcoinMessageHandler for BlockWindow<'a> {
+    /// Trait message handler
+    /// (re)start the conversation with the bitcoin peer, asking it for every block we're still
+    /// waiting on
+    fn begin_session(&mut self, indexer: &mut BitcoinIndexer) -> Result<bool, btc_error> {
+        self.last_block_time = Instant::now();
+        self.filtered_block = None;
+        if self.filtered {
+            indexer.load_bloom_filter()?;
+        }
+        self.rerequest_missing(indexer, Duration::from_secs(0))?;
+        self.advance(indexer)
+    }
+
+    /// Trait message handler
+    /// Take in a block we asked for, and deliver whatever blocks are now in order
+    fn handle_message(
+        &mut self,
+        indexer: &mut BitcoinIndexer,
+        msg: PeerMessage,
+    ) -> Result<bool, btc_error> {
+        self.discount_throttled_time(indexer);
+        let (slot_index, block_message, matched_txs) = match msg {
+            btc_message::NetworkMessage::Block(block) => {
+                let block_hash = block.bitcoin_hash();
+                let slot_index = match self.waiting_slot(&block_hash) {
+                    Some(i) => i,
+                    None => {
+                        debug!(
+                            "Got block {}, which we are not waiting for",
+                            &to_hex(block_hash.as_bytes())
+                        );
+                        return Ok(true);
+                    }
+                };
+
+                // it has the header's hash, so only its transactions can be wrong
+                let slot = &self.slots[slot_index];
+                if !BitcoinBlockParser::check_block(&block, &slot.header.block_header) {
+                    let reason = format!(
+                        "{}:{} sent block {} ({}) with transactions that do not match its merkle root",
+                        indexer.peer_host(),
+                        indexer.peer_port(),
+                        slot.header.block_height,
+                        block_hash.be_hex_string()
+                    );
+                    return Err(indexer.peer_sent_bad_block(reason));
+                }
+                (
+                    slot_index,
+                    btc_message::NetworkMessage::Block(block),
+                    vec![],
+                )
+            }
+            btc_message::NetworkMessage::MerkleBlock(merkle_block) if self.filtered => {
+                let block_hash = merkle_block.header.bitcoin_hash();
+                if let Some(ref filtered_block) = self.filtered_block {
+                    let reason = format!(
+                        "{}:{} sent filtered block {} before all the transactions of block {}",
+                        indexer.peer_host(),
+                        indexer.peer_port(),
+                        block_hash.be_hex_string(),
+                        filtered_block.header.block_height
+                    );
+                    return Err(indexer.peer_sent_bad_block(reason));
+                }
+                let slot_index = match self.waiting_slot(&block_hash) {
+                    Some(i) => i,
+                    None => {
+                        debug!(
+                            "Got filtered block {}, which we are not waiting for",
+                            &to_hex(block_hash.as_bytes())
+                        );
+                        return Ok(true);
+                    }
+                };
+
+                // it has the header's hash, so only its partial merkle tree can be wrong
+                let header = self.slots[slot_index].header.clone();
+                let filtered_block = match PendingFilteredBlock::new(header, merkle_block) {
+                    Ok(filtered_block) => filtered_block,
+                    Err(e) => {
+                        let reason = format!(
+                            "{}:{} sent filtered block {} ({}): {}",
+                            indexer.peer_host(),
+                            indexer.peer_port(),
+                            self.slots[slot_index].header.block_height,
+                            block_hash.be_hex_string(),
+                            e
+                        );
+                        return Err(indexer.peer_sent_bad_block(reason));
+                    }
+                };
+                if !filtered_block.is_complete() {
+                    // wait for its transactions
+                    self.filtered_block = Some(filtered_block);
+                    return Ok(true);
+                }
+                (
+                    slot_index,
+                    btc_message::NetworkMessage::MerkleBlock(filtered_block.merkle_block),
+                    filtered_block.txs,
+                )
+            }
+            btc_message::NetworkMessage::Tx(tx) => {
+                let tx = match self.filtered_block {
+                    Some(ref mut filtered_block) => filtered_block.add_tx(tx).err(),
+                    None => Some(tx),
+                };
+                if let Some(tx) = tx {
+                    return Err(btc_error::UnhandledMessage(
+                        btc_message::NetworkMessage::Tx(tx),
+                    ));
+                }
+                if !self
+                    .filtered_block
+                    .as_ref()
+                    .map(|filtered_block| filtered_block.is_complete())
+                    .unwrap_or(false)
+                {
+                    return Ok(true);
+                }
+                let filtered_block = self.filtered_block.take().expect("BUG: no filtered block");
+                let block_hash = filtered_block.header.block_header.header.bitcoin_hash();
+                let slot_index = match self.waiting_slot(&block_hash) {
+                    Some(i) => i,
+                    None => {
+                        debug!(
+                            "Got filtered block {}, which we are no longer waiting for",
+                            &to_hex(block_hash.as_bytes())
+                        );
+                        return Ok(true);
+                    }
+                };
+                (
+                    slot_index,
+                    btc_message::NetworkMessage::MerkleBlock(filtered_block.merkle_block),
+                    filtered_block.txs,
+                )
+            }
+            btc_message::NetworkMessage::NotFound(ref invs) => {
+                // the peer doesn't have the block, so asking it again won't help
+                let slot = self.slots.iter().find(|slot| {
+                    slot.block.is_none()
+                        && invs
+                            .iter()
+                            .any(|inv| inv.hash == slot.header.block_header.header.bitcoin_hash())
+                });
+                let reason = match slot {
+                    Some(slot) => format!(
+                        "{}:{} sent notfound for block {} ({})",
+                        indexer.peer_host(),
+                        indexer.peer_port(),
+                        slot.header.block_height,
+                        slot.header
+                            .block_header
+                            .header
+                            .bitcoin_hash()
+                            .be_hex_string()
+                    ),
+                    None => {
+                        debug!("Got notfound for blocks we are not waiting for");
+                        return Ok(true);
+                    }
+                };
+                return Err(indexer.peer_lacks_block(reason));
+            }
+            _ => {
+                return Err(btc_error::UnhandledMessage(msg));
+            }
+        };
+
+        let slot = &mut self.slots[slot_index];
+        let height = slot.header.block_height;
+        let block_hash = slot.header.block_header.header.bitcoin_hash();
+        debug!(
+            "Got block {}: {}",
+            height,
+            &to_hex(BurnchainHeaderHash::from_bitcoin_hash(&block_hash).as_bytes())
+        );
+
+        slot.block = Some(BitcoinBlockIPC {
+            header_data: slot.header.clone(),
+            block_message,
+            matched_txs,
+        });
+        self.last_block_time = Instant::now();
+        indexer.runtime.pruned_failovers = 0;
+
+        self.rerequest_missing(indexer, self.request_timeout)?;
+        let keep_going = self.advance(indexer)?;
+
+        // the peer is still sending us blocks, so don't time out while we wait for the rest
+        indexer.runtime.last_getdata_send_time = if keep_going { get_epoch_time_secs() } else { 0 };
+        Ok(keep_going)
+    }
+
+    /// Trait message handler
+    /// Don't wait longer than the request timeout to ask for a block again
+    fn idle_timeout(&self) -> Option<Duration> {
+        Some(self.request_timeout)
+    }
+
+    /// Trait message handler
+    /// Ask for the blocks that are overdue again, unless the peer has stopped sending us blocks
+    /// altogether, in which case we time out
+    fn handle_idle(&mut self, indexer: &mut BitcoinIndexer) -> Result<bool, btc_error> {
+        self.discount_throttled_time(indexer);
+        if self.last_block_time.elapsed() >= indexer.config.read_timeout {
+            warn!(
+                "No blocks from {}:{} in {:?}",
+                indexer.peer_host(),
+                indexer.peer_port(),
+                &indexer.config.read_timeout
+            );
+            return Err(btc_error::TimedOut);
+        }
+        self.rerequest_missing(indexer, self.request_timeout)?;
+        Ok(true)
+    }
+}
+
+impl<R: Read> BitcoinBlockReader<R> {
+    /// Read the block header and transaction count off of `reader`
+    pub fn new(reader: R) -> Result<BitcoinBlockReader<R>, btc_error> {
+        let mut decoder = RawDecoder::new(reader);
+        let header: BlockHeader = ConsensusDecodable::consensus_decode(&mut decoder)?;
+        let num_txs: VarInt = ConsensusDecodable::consensus_decode(&mut decoder)?;
+        Ok(BitcoinBlockReader {
+            decoder,
+            header,
+            num_txs: num_txs.0,
+            txids: vec![],
+        })
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn num_txs(&self) -> u64 {
+        self.num_txs
+    }
+
+    /// Read the next transaction, or get None once they've all been read
+    pub fn next_tx(&mut self) -> Result<Option<Transaction>, btc_error> {
+        if self.txids.len() as u64 >= self.num_txs {
+            return Ok(None);
+        }
+        let tx: Transaction = ConsensusDecodable::consensus_decode(&mut self.decoder)?;
+        self.txids.push(tx.txid());
+        Ok(Some(tx))
+    }
+
+    /// Merkle root of the transactions read so far (which is the block's, once `next_tx()`
+    /// returns None), or None if they make a mutated tree (see
+    /// `BitcoinBlockParser::checked_merkle_root()`)
+    pub fn merkle_root(&self) -> Option<Sha256dHash> {
+        BitcoinBlockParser::checked_merkle_root(self.txids.clone())
+    }
+}
+
+impl BlockSigChecks {
+    /// Add the checks for `tx`'s P2PKH inputs.  `tx_index` is its index in the block's burnchain
+    /// transactions.
+    fn add_tx(&mut self, tx: &Transaction, tx_index: usize) {
+        for input_index in 0..tx.input.len() {
+            if let Some(check) = bits::p2pkh_signature_check(tx, input_index) {
+                self.checks.push(check);
+                self.tx_indexes.push(tx_index);
+            }
+        }
+    }
+
+    /// Verify the signatures on up to `num_threads` threads, and drop the transactions with a
+    /// signature that doesn't verify from `txs`.
+    fn filter_txs(
+        self,
+        txs: Vec<BitcoinTransaction>,
+        block_hash: &Sha256dHash,
+        num_threads: usize,
+    ) -> Vec<BitcoinTransaction> {
+        let failed = keys::verify_batch(&self.checks, num_threads);
+        if failed.len() == 0 {
+            return txs;
+        }
+
+        let bad_txs: HashSet<usize> = failed.iter().map(|i| self.tx_indexes[*i]).collect();
+        txs.into_iter()
+            .enumerate()
+            .filter_map(|(i, tx)| {
+                if bad_txs.contains(&i) {
+                    warn!(
+                        "Dropping tx {} in block {}: an input signature does not verify",
+                        &tx.txid, block_hash
+                    );
+                    None
+                } else {
+                    Some(tx)
+                }
+            })
+            .collect()
+    }
+}
+
 impl BitcoinBlockParser {
     /// New block parser
     pub fn new(network_id: BitcoinNetworkType, magic_bytes: MagicBytes) -> BitcoinBlockParser {
         BitcoinBlockParser {
             network_id: network_id,
             magic_bytes: magic_bytes.clone(),
+            sig_threads: 0,
         }
     }
 
+    /// Check the signatures of the P2PKH inputs of each block's burnchain transactions on up to
+    /// `num_threads` threads, and drop the transactions whose signatures don't verify.  The
+    /// checks for a block are verified together once it's parsed.  0 (the default) turns this
+    /// off, since the bitcoind we read blocks from has already checked them.
+    pub fn set_sig_threads(&mut self, num_threads: usize) {
+        self.sig_threads = num_threads;
+    }
+
     /// Allow raw inputs?
     fn allow_raw_inputs(epoch_id: StacksEpochId) -> bool {
         epoch_id >= StacksEpochId::Epoch21
@@ -251,6 +1283,37 @@ impl BitcoinBlockParser {
         epoch_id >= StacksEpochId::Epoch21
     }
 
+    /// Compute the merkle root of a block's transaction IDs, or get None if the tree is mutated
+    /// (CVE-2012-2459).  A level with an odd number of hashes pairs the last one with itself,
+    /// so repeating the last transaction(s) of a block gives the same merkle root.  Such a
+    /// block is invalid, but it has the valid block's header, so any two equal hashes paired
+    /// at any level make the tree mutated, like in Bitcoin Core.  Witnesses don't matter here,
+    /// since a segwit transaction's ID doesn't cover its witness.
+    pub fn checked_merkle_root(txids: Vec<Sha256dHash>) -> Option<Sha256dHash> {
+        if txids.is_empty() {
+            return Some(bitcoin_merkle_root(txids));
+        }
+        let mut level = txids;
+        let mut pair_bytes = [0u8; 64];
+        while level.len() > 1 {
+            if level
+                .chunks(2)
+                .any(|pair| pair.len() == 2 && pair[0] == pair[1])
+            {
+                return None;
+            }
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                    pair_bytes[..32].copy_from_slice(pair[0].as_bytes());
+                    pair_bytes[32..].copy_from_slice(pair[pair.len() - 1].as_bytes());
+                    Sha256dHash::from_data(&pair_bytes)
+                })
+                .collect();
+        }
+        Some(level[0])
+    }
+
     /// Verify that a block matches a header
     pub fn check_block(block: &Block, header: &LoneBlockHeader) -> bool {
         if header.header.bitcoin_hash() != block.bitcoin_hash() {
@@ -258,10 +1321,11 @@ impl BitcoinBlockParser {
         }
 
         // block transactions must match header merkle root
-        let tx_merkle_root =
-            bitcoin_merkle_root(block.txdata.iter().map(|ref tx| tx.txid()).collect());
+        let tx_merkle_root = BitcoinBlockParser::checked_merkle_root(
+            block.txdata.iter().map(|ref tx| tx.txid()).collect(),
+        );
 
-        if block.header.merkle_root != tx_merkle_root {
+        if tx_merkle_root != Some(block.header.merkle_root) {
             return false;
         }
 
@@ -470,10 +1534,14 @@ impl BitcoinBlockParser {
         epoch_id: StacksEpochId,
     ) -> BitcoinBlock {
         let mut accepted_txs = vec![];
+        let mut sig_checks = BlockSigChecks::default();
         for i in 0..block.txdata.len() {
             let tx = &block.txdata[i];
             match self.parse_tx(tx, i, epoch_id) {
                 Some(bitcoin_tx) => {
+                    if self.sig_threads > 0 {
+                        sig_checks.add_tx(tx, accepted_txs.len());
+                    }
                     accepted_txs.push(bitcoin_tx);
                 }
                 None => {
@@ -481,6 +1549,8 @@ impl BitcoinBlockParser {
                 }
             }
         }
+        let accepted_txs =
+            sig_checks.filter_txs(accepted_txs, &block.bitcoin_hash(), self.sig_threads);
 
         BitcoinBlock {
             block_height: block_height,
@@ -517,6 +1587,62 @@ impl BitcoinBlockParser {
         let burn_block = self.parse_block(&block, height, epoch_id);
         Some(burn_block)
     }
+
+    /// Like `process_block()`, but read the block off of `reader` one transaction at a time
+    /// (see `BitcoinBlockReader`), so memory use is bounded by the largest transaction instead
+    /// of the whole block.  Returns Ok(None) if the block does not match the header, and an
+    /// error if the block can't be read.
+    pub fn process_block_stream<R: Read>(
+        &self,
+        reader: R,
+        header: &LoneBlockHeader,
+        height: u64,
+        epoch_id: StacksEpochId,
+    ) -> Result<Option<BitcoinBlock>, btc_error> {
+        let mut block_reader = BitcoinBlockReader::new(reader)?;
+        let block_hash = block_reader.header().bitcoin_hash();
+        if header.header.bitcoin_hash() != block_hash {
+            error!(
+                "Expected block {} does not match received block {}",
+                header.header.bitcoin_hash(),
+                block_hash
+            );
+            return Ok(None);
+        }
+
+        let mut accepted_txs = vec![];
+        let mut sig_checks = BlockSigChecks::default();
+        let mut vtxindex = 0;
+        while let Some(tx) = block_reader.next_tx()? {
+            if let Some(bitcoin_tx) = self.parse_tx(&tx, vtxindex, epoch_id) {
+                if self.sig_threads > 0 {
+                    sig_checks.add_tx(&tx, accepted_txs.len());
+                }
+                accepted_txs.push(bitcoin_tx);
+            }
+            vtxindex += 1;
+        }
+
+        // block transactions must match header merkle root
+        if block_reader.merkle_root() != Some(block_reader.header().merkle_root) {
+            error!(
+                "Transactions of block {} do not match its merkle root",
+                block_hash
+            );
+            return Ok(None);
+        }
+        let accepted_txs = sig_checks.filter_txs(accepted_txs, &block_hash, self.sig_threads);
+
+        Ok(Some(BitcoinBlock {
+            block_height: height,
+            block_hash: BurnchainHeaderHash::from_bitcoin_hash(&block_hash),
+            parent_block_hash: BurnchainHeaderHash::from_bitcoin_hash(
+                &block_reader.header().prev_blockhash,
+            ),
+            txs: accepted_txs,
+            timestamp: block_reader.header().time as u64,
+        }))
+    }
 }
 
 impl BurnchainBlockParser for BitcoinBlockParser {
@@ -539,6 +1665,15 @@ impl BurnchainBlockParser for BitcoinBlockParser {
                     Some(block_data) => Ok(BurnchainBlock::Bitcoin(block_data)),
                 }
             }
+            btc_message::NetworkMessage::MerkleBlock(ref merkle_block) => {
+                // its filter only matched our own watch targets, so it may lack burn operations
+                error!(
+                    "Refusing to process filtered block {} ({}): it may be missing burn operations",
+                    ipc_block.header_data.block_height,
+                    merkle_block.header.bitcoin_hash()
+                );
+                Err(burnchain_error::ParseError)
+            }
             _ => {
                 panic!("Did not receive a Block message"); // should never happen
             }
@@ -548,26 +1683,48 @@ impl BurnchainBlockParser for BitcoinBlockParser {
 
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
+    use std::io;
+    use std::io::Read;
+    use std::rc::Rc;
+
     use crate::burnchains::bitcoin::address::{BitcoinAddress, LegacyBitcoinAddressType};
     use crate::burnchains::bitcoin::keys::BitcoinPublicKey;
     use crate::burnchains::bitcoin::BitcoinNetworkType;
+    use crate::burnchains::bitcoin::Error as btc_error;
     use crate::burnchains::bitcoin::{
         BitcoinBlock, BitcoinInputType, BitcoinTransaction, BitcoinTxInput, BitcoinTxInputRaw,
         BitcoinTxInputStructured, BitcoinTxOutput,
     };
     use crate::burnchains::{BurnchainBlock, BurnchainTransaction, MagicBytes, Txid};
     use crate::core::StacksEpochId;
-    use stacks_common::deps_common::bitcoin::blockdata::block::{Block, LoneBlockHeader};
-    use stacks_common::deps_common::bitcoin::blockdata::transaction::Transaction;
+    use stacks_common::deps_common::bitcoin::blockdata::block::{
+        Block, BlockHeader, LoneBlockHeader,
+    };
+    use stacks_common::deps_common::bitcoin::blockdata::transaction::{Transaction, TxOut};
     use stacks_common::deps_common::bitcoin::network::encodable::VarInt;
-    use stacks_common::deps_common::bitcoin::network::serialize::deserialize;
+    use stacks_common::deps_common::bitcoin::network::serialize::{
+        deserialize, serialize, BitcoinHash,
+    };
+    use stacks_common::deps_common::bitcoin::util::hash::{bitcoin_merkle_root, Sha256dHash};
     use stacks_common::types::Address;
     use stacks_common::util::hash::hex_bytes;
     use stacks_common::util::log;
 
     use crate::types::chainstate::BurnchainHeaderHash;
 
-    use super::BitcoinBlockParser;
+    use super::{
+        classify_output_script, find_op_return, merkle_branch, verify_merkle_proof,
+        BitcoinBlockIPC, BitcoinBlockParser, BitcoinBlockReader, BitcoinHeaderIPC, ScriptType,
+        MAX_OP_RETURN_DATA_LEN,
+    };
+    use crate::burnchains::bitcoin::bits::parse_script;
+    use crate::burnchains::bitcoin::bloom;
+    use crate::burnchains::indexer::BurnchainBlockParser;
+    use crate::burnchains::Error as burnchain_error;
+    use stacks_common::deps_common::bitcoin::blockdata::opcodes::All as btc_opcodes;
+    use stacks_common::deps_common::bitcoin::blockdata::script::{Builder, Instruction, Script};
+    use stacks_common::deps_common::bitcoin::network::message::NetworkMessage;
 
     struct TxFixture {
         txstr: String,
@@ -608,6 +1765,21 @@ mod tests {
         })
     }
 
+    /// The (segwit) NAME_REGISTRATION block from parse_block(), with `fillers` copies of its
+    /// coinbase transaction appended
+    fn make_filler_block(fillers: u32) -> Block {
+        let mut block = make_block("000000209cef4ccd19f4294dd5c762aab6d9577fb4412cd4c0a662a953a8b7969697bc1ddab52e6f053758022fb92f04388eb5fdd87046776e9c406880e728b48e6930aff462fc5bffff7f200000000002020000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff0502b5020101ffffffff024018a41200000000232103f51f0c868fd99a4a3a14fe2153fba3c5f635c31bf0a588545627134b49609097ac0000000000000000266a24aa21a9ed18a09ae86261d6802bff7fa705afa558764ed3750c2273bfae5b5136c44d14d6012000000000000000000000000000000000000000000000000000000000000000000000000001000000000101a7ef2b09722ad786c569c0812005a731ce19290bb0a2afc16cb91056c2e4c19e0100000017160014393ffec4f09b38895b8502377693f23c6ae00f19ffffffff0300000000000000000d6a0b69643a666f6f2e746573747c1500000000000017a9144b85301ba8e42bf98472b8ed4939d5f76b98fcea87144d9c290100000017a91431f8968eb1730c83fb58409a9a560a0a0835027f8702483045022100fc82815edf1c0ef0c601cf1e26494626d7b01597be5ab83df025ff1ee67730130220016c4c29d77aadb5ff57c0c9272a43950ca29b84d8adfaed95ac69db90b35d5b012102d341f728783eb93e6fb5921a1ebe9d149e941de31e403cd69afa2f0f1e698e8100000000").unwrap();
+        for i in 0..fillers {
+            let mut filler = block.txdata[0].clone();
+            filler.output[0].script_pubkey = Builder::new().push_slice(&[0xab; 2000]).into_script();
+            filler.lock_time = i;
+            block.txdata.push(filler);
+        }
+        block.header.merkle_root =
+            bitcoin_merkle_root(block.txdata.iter().map(|tx| tx.txid()).collect());
+        block
+    }
+
     fn to_txid(inp: &Vec<u8>) -> Txid {
         let mut ret = [0; 32];
         let bytes = &inp[..inp.len()];
@@ -1236,7 +2408,12 @@ mod tests {
             }
         ];
 
+        let transfer_block = make_block(&block_fixtures[1].block).unwrap();
+
         let parser = BitcoinBlockParser::new(BitcoinNetworkType::Testnet, MagicBytes([105, 100])); // "id"
+        let mut checking_parser =
+            BitcoinBlockParser::new(BitcoinNetworkType::Testnet, MagicBytes([105, 100])); // "id"
+        checking_parser.set_sig_threads(4);
         for block_fixture in block_fixtures {
             let block = make_block(&block_fixture.block).unwrap();
             let header = make_block_header(&block_fixture.header).unwrap();
@@ -1245,6 +2422,507 @@ mod tests {
             let parsed_block_opt =
                 parser.process_block(&block, &header, height, StacksEpochId::Epoch2_05);
             assert_eq!(parsed_block_opt, block_fixture.result);
+
+            // every signature in a real block verifies
+            let parsed_block_opt =
+                checking_parser.process_block(&block, &header, height, StacksEpochId::Epoch2_05);
+            assert_eq!(parsed_block_opt, block_fixture.result);
+        }
+
+        // a TOKEN_TRANSFER whose P2PKH input signature doesn't verify is dropped, but only if
+        // signatures are checked
+        let mut block = transfer_block;
+        let script_sig = &block.txdata[2].input[0].script_sig;
+        let (mut sig, pubkey) = match parse_script(script_sig).as_slice() {
+            [Instruction::PushBytes(sig), Instruction::PushBytes(pubkey)] => {
+                (sig.to_vec(), pubkey.to_vec())
+            }
+            x => panic!("Not a P2PKH scriptSig: {:?}", x),
+        };
+        sig[10] ^= 0x01;
+        block.txdata[2].input[0].script_sig = Builder::new()
+            .push_slice(&sig)
+            .push_slice(&pubkey)
+            .into_script();
+
+        let parsed_block = parser.parse_block(&block, 32, StacksEpochId::Epoch2_05);
+        assert_eq!(parsed_block.txs.len(), 5);
+        assert_eq!(parsed_block.txs[0].vtxindex, 2);
+
+        let parsed_block = checking_parser.parse_block(&block, 32, StacksEpochId::Epoch2_05);
+        assert_eq!(parsed_block.txs.len(), 4);
+        assert!(parsed_block.txs.iter().all(|tx| tx.vtxindex != 2));
+    }
+
+    /// Reader that counts how many bytes have been read through it
+    struct CountingReader<'a> {
+        bytes: &'a [u8],
+        count: Rc<Cell<usize>>,
+    }
+
+    impl<'a> Read for CountingReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let nread = self.bytes.read(buf)?;
+            self.count.set(self.count.get() + nread);
+            Ok(nread)
+        }
+    }
+
+    #[test]
+    fn process_block_stream() {
+        let block = make_filler_block(2000);
+        let block_bytes = serialize(&block).unwrap();
+        assert!(block_bytes.len() > 4_000_000);
+
+        let header = LoneBlockHeader {
+            header: block.header,
+            tx_count: VarInt(0),
+        };
+        let parser = BitcoinBlockParser::new(BitcoinNetworkType::Testnet, MagicBytes([105, 100])); // "id"
+        let expected = parser
+            .process_block(&block, &header, 32, StacksEpochId::Epoch2_05)
+            .unwrap();
+        assert_eq!(expected.txs.len(), 1);
+        assert_eq!(expected.txs[0].vtxindex, 1);
+        assert_eq!(
+            parser
+                .process_block_stream(&block_bytes[..], &header, 32, StacksEpochId::Epoch2_05)
+                .unwrap(),
+            Some(expected)
+        );
+
+        // the block is read one transaction at a time
+        let count = Rc::new(Cell::new(0));
+        let mut block_reader = BitcoinBlockReader::new(CountingReader {
+            bytes: &block_bytes,
+            count: count.clone(),
+        })
+        .unwrap();
+        assert_eq!(block_reader.header(), &block.header);
+        assert_eq!(block_reader.num_txs(), block.txdata.len() as u64);
+        assert_eq!(count.get(), 80 + 3);
+        for tx in block.txdata.iter() {
+            let count_before = count.get();
+            assert_eq!(block_reader.next_tx().unwrap().as_ref(), Some(tx));
+            assert_eq!(count.get() - count_before, serialize(tx).unwrap().len());
+        }
+        assert!(block_reader.next_tx().unwrap().is_none());
+        assert_eq!(count.get(), block_bytes.len());
+        assert_eq!(block_reader.merkle_root(), Some(block.header.merkle_root));
+
+        // a header for another block, a tampered transaction, and a truncated block
+        let mut other_header = header.clone();
+        other_header.header.nonce += 1;
+        assert_eq!(
+            parser
+                .process_block_stream(
+                    &block_bytes[..],
+                    &other_header,
+                    32,
+                    StacksEpochId::Epoch2_05
+                )
+                .unwrap(),
+            None
+        );
+
+        let mut tampered_block = block.clone();
+        tampered_block.txdata[5].lock_time += 1;
+        let tampered_bytes = serialize(&tampered_block).unwrap();
+        assert_eq!(
+            parser
+                .process_block_stream(&tampered_bytes[..], &header, 32, StacksEpochId::Epoch2_05)
+                .unwrap(),
+            None
+        );
+
+        match parser.process_block_stream(
+            &block_bytes[..block_bytes.len() - 1],
+            &header,
+            32,
+            StacksEpochId::Epoch2_05,
+        ) {
+            Err(btc_error::SerializationError(_)) => {}
+            x => panic!("Expected SerializationError, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn parse_refuses_filtered_block() {
+        // the NAME_REGISTRATION is the fifth transaction
+        let mut block = make_filler_block(4);
+        block.txdata.swap(1, 4);
+        block.header.merkle_root =
+            bitcoin_merkle_root(block.txdata.iter().map(|tx| tx.txid()).collect());
+        let header_data = BitcoinHeaderIPC {
+            block_header: LoneBlockHeader {
+                header: block.header,
+                tx_count: VarInt(block.txdata.len() as u64),
+            },
+            block_height: 123,
+        };
+        let mut parser =
+            BitcoinBlockParser::new(BitcoinNetworkType::Testnet, MagicBytes([105, 100])); // "id"
+
+        let whole = BitcoinBlockIPC {
+            header_data: header_data.clone(),
+            block_message: NetworkMessage::Block(block.clone()),
+            matched_txs: vec![],
+        };
+        match parser.parse(&whole, StacksEpochId::Epoch2_05).unwrap() {
+            BurnchainBlock::Bitcoin(burn_block) => {
+                assert_eq!(burn_block.txs.len(), 1);
+                assert_eq!(burn_block.txs[0].vtxindex, 4);
+            }
+            x => panic!("Expected a bitcoin block, got {:?}", x),
+        }
+
+        // a filtered block is refused, even though its filter matched the burn operation
+        let filtered = BitcoinBlockIPC {
+            header_data,
+            block_message: NetworkMessage::MerkleBlock(bloom::build_merkle_block(
+                &block,
+                &[false, false, false, false, true, false],
+            )),
+            matched_txs: vec![block.txdata[4].clone()],
+        };
+        match parser.parse(&filtered, StacksEpochId::Epoch2_05) {
+            Err(burnchain_error::ParseError) => {}
+            x => panic!("Expected ParseError, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn check_block_merkle_root() {
+        let block = make_filler_block(4);
+        let header = LoneBlockHeader {
+            header: block.header,
+            tx_count: VarInt(0),
+        };
+        assert!(BitcoinBlockParser::check_block(&block, &header));
+
+        // same root as bitcoin_merkle_root() for untampered trees of any shape
+        let txids: Vec<Sha256dHash> = block.txdata.iter().map(|tx| tx.txid()).collect();
+        for i in 0..=txids.len() {
+            assert_eq!(
+                BitcoinBlockParser::checked_merkle_root(txids[..i].to_vec()),
+                Some(bitcoin_merkle_root(txids[..i].to_vec()))
+            );
+        }
+
+        // witnesses are not part of the merkle root, so the segwit block survives a round-trip
+        assert!(!block.txdata[1].input[0].witness.is_empty());
+        let block_copy: Block = deserialize(&serialize(&block).unwrap()).unwrap();
+        assert_eq!(block_copy, block);
+        assert!(BitcoinBlockParser::check_block(&block_copy, &header));
+
+        // a tampered transaction
+        let mut tampered_block = block.clone();
+        tampered_block.txdata[3].lock_time += 1;
+        assert!(!BitcoinBlockParser::check_block(&tampered_block, &header));
+
+        // a mutated tree (CVE-2012-2459): with 6 transactions, repeating the last two gives the
+        // same root, since the third level pairs the last hash with itself
+        let mut mutated_block = block.clone();
+        mutated_block.txdata.push(block.txdata[4].clone());
+        mutated_block.txdata.push(block.txdata[5].clone());
+        let mutated_txids: Vec<Sha256dHash> =
+            mutated_block.txdata.iter().map(|tx| tx.txid()).collect();
+        assert_eq!(
+            bitcoin_merkle_root(mutated_txids.clone()),
+            block.header.merkle_root
+        );
+        assert_eq!(BitcoinBlockParser::checked_merkle_root(mutated_txids), None);
+        assert!(!BitcoinBlockParser::check_block(&mutated_block, &header));
+
+        // likewise with an odd number of transactions, repeating the last one
+        let mut odd_block = make_filler_block(3);
+        let odd_header = LoneBlockHeader {
+            header: odd_block.header,
+            tx_count: VarInt(0),
+        };
+        assert!(BitcoinBlockParser::check_block(&odd_block, &odd_header));
+        let last_tx = odd_block.txdata.last().unwrap().clone();
+        odd_block.txdata.push(last_tx);
+        assert_eq!(
+            bitcoin_merkle_root(odd_block.txdata.iter().map(|tx| tx.txid()).collect()),
+            odd_header.header.merkle_root
+        );
+        assert!(!BitcoinBlockParser::check_block(&odd_block, &odd_header));
+
+        // the streaming parser rejects it too
+        let parser = BitcoinBlockParser::new(BitcoinNetworkType::Testnet, MagicBytes([105, 100])); // "id"
+        let odd_bytes = serialize(&odd_block).unwrap();
+        assert_eq!(
+            parser
+                .process_block_stream(&odd_bytes[..], &odd_header, 32, StacksEpochId::Epoch2_05)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_classify_output_script() {
+        let hash_20 = [0x11u8; 20];
+        let hash_32 = [0x22u8; 32];
+
+        let fixtures = vec![
+            (
+                Builder::new()
+                    .push_opcode(btc_opcodes::OP_DUP)
+                    .push_opcode(btc_opcodes::OP_HASH160)
+                    .push_slice(&hash_20)
+                    .push_opcode(btc_opcodes::OP_EQUALVERIFY)
+                    .push_opcode(btc_opcodes::OP_CHECKSIG)
+                    .into_script(),
+                ScriptType::P2PKH,
+            ),
+            (
+                Builder::new()
+                    .push_opcode(btc_opcodes::OP_HASH160)
+                    .push_slice(&hash_20)
+                    .push_opcode(btc_opcodes::OP_EQUAL)
+                    .into_script(),
+                ScriptType::P2SH,
+            ),
+            (
+                Builder::new()
+                    .push_opcode(btc_opcodes::OP_PUSHBYTES_0)
+                    .push_slice(&hash_20)
+                    .into_script(),
+                ScriptType::P2WPKH,
+            ),
+            (
+                Builder::new()
+                    .push_opcode(btc_opcodes::OP_PUSHBYTES_0)
+                    .push_slice(&hash_32)
+                    .into_script(),
+                ScriptType::P2WSH,
+            ),
+            (
+                Builder::new()
+                    .push_opcode(btc_opcodes::OP_PUSHNUM_1)
+                    .push_slice(&hash_32)
+                    .into_script(),
+                ScriptType::P2TR,
+            ),
+            // witness v1 program that isn't 32 bytes
+            (
+                Builder::new()
+                    .push_opcode(btc_opcodes::OP_PUSHNUM_1)
+                    .push_slice(&hash_20)
+                    .into_script(),
+                ScriptType::NonStandard,
+            ),
+            // p2pkh without the OP_CHECKSIG
+            (
+                Builder::new()
+                    .push_opcode(btc_opcodes::OP_DUP)
+                    .push_opcode(btc_opcodes::OP_HASH160)
+                    .push_slice(&hash_20)
+                    .push_opcode(btc_opcodes::OP_EQUALVERIFY)
+                    .into_script(),
+                ScriptType::NonStandard,
+            ),
+            // OP_RETURN
+            (
+                Builder::new()
+                    .push_opcode(btc_opcodes::OP_RETURN)
+                    .push_slice(&hash_20)
+                    .into_script(),
+                ScriptType::NonStandard,
+            ),
+            // empty script
+            (Builder::new().into_script(), ScriptType::NonStandard),
+        ];
+
+        for (script, expected) in fixtures.into_iter() {
+            assert_eq!(
+                classify_output_script(&script),
+                expected,
+                "Misclassified {:?}",
+                &script
+            );
+
+            // outputs we can parse report the same script type
+            let txout = BitcoinTxOutput::from_bitcoin_txout(
+                BitcoinNetworkType::Mainnet,
+                &stacks_common::deps_common::bitcoin::blockdata::transaction::TxOut {
+                    value: 123,
+                    script_pubkey: script.clone(),
+                },
+            );
+            match txout {
+                Some(txout) => assert_eq!(txout.script_type(), expected),
+                None => assert_eq!(expected, ScriptType::NonStandard),
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_op_return() {
+        let p2pkh = Builder::new()
+            .push_opcode(btc_opcodes::OP_DUP)
+            .push_opcode(btc_opcodes::OP_HASH160)
+            .push_slice(&[0x11; 20])
+            .push_opcode(btc_opcodes::OP_EQUALVERIFY)
+            .push_opcode(btc_opcodes::OP_CHECKSIG)
+            .into_script();
+        let tx_with_outputs = |scripts: Vec<Script>| Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: scripts
+                .into_iter()
+                .map(|script_pubkey| TxOut {
+                    value: 0,
+                    script_pubkey,
+                })
+                .collect(),
+        };
+
+        // 40-byte payload
+        let payload = [0x69u8; 40];
+        let tx = tx_with_outputs(vec![
+            Builder::new()
+                .push_opcode(btc_opcodes::OP_RETURN)
+                .push_slice(&payload)
+                .into_script(),
+            p2pkh.clone(),
+        ]);
+        assert_eq!(find_op_return(&tx).unwrap(), Some(&payload[..]));
+
+        // bare OP_RETURN carries no data
+        let tx = tx_with_outputs(vec![
+            p2pkh.clone(),
+            Builder::new()
+                .push_opcode(btc_opcodes::OP_RETURN)
+                .into_script(),
+        ]);
+        assert_eq!(find_op_return(&tx).unwrap(), Some(&[][..]));
+
+        // no OP_RETURN at all
+        let tx = tx_with_outputs(vec![p2pkh.clone()]);
+        assert_eq!(find_op_return(&tx).unwrap(), None);
+
+        // two OP_RETURNs are nonstandard
+        let tx = tx_with_outputs(vec![
+            Builder::new()
+                .push_opcode(btc_opcodes::OP_RETURN)
+                .push_slice(&payload)
+                .into_script(),
+            Builder::new()
+                .push_opcode(btc_opcodes::OP_RETURN)
+                .push_slice(&payload)
+                .into_script(),
+        ]);
+        match find_op_return(&tx) {
+            Err(btc_error::MalformedOpReturn(_)) => {}
+            x => panic!("Expected MalformedOpReturn, got {:?}", x),
+        }
+
+        // payload over the standard limit
+        let tx = tx_with_outputs(vec![Builder::new()
+            .push_opcode(btc_opcodes::OP_RETURN)
+            .push_slice(&[0x69u8; MAX_OP_RETURN_DATA_LEN + 1])
+            .into_script()]);
+        match find_op_return(&tx) {
+            Err(btc_error::MalformedOpReturn(_)) => {}
+            x => panic!("Expected MalformedOpReturn, got {:?}", x),
+        }
+
+        // OP_RETURN followed by something other than a data push
+        let tx = tx_with_outputs(vec![Builder::new()
+            .push_opcode(btc_opcodes::OP_RETURN)
+            .push_opcode(btc_opcodes::OP_CHECKSIG)
+            .into_script()]);
+        match find_op_return(&tx) {
+            Err(btc_error::MalformedOpReturn(_)) => {}
+            x => panic!("Expected MalformedOpReturn, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_verify_merkle_proof() {
+        // mainnet block 100000
+        let header = BlockHeader {
+            version: 1,
+            prev_blockhash: Sha256dHash::from_hex(
+                "000000000002d01c1fccc21636b607dfd930d31d01c3a62104612a1719011250",
+            )
+            .unwrap(),
+            merkle_root: Sha256dHash::from_hex(
+                "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766",
+            )
+            .unwrap(),
+            time: 1293623863,
+            bits: 0x1b04864c,
+            nonce: 274148111,
+        };
+        assert_eq!(
+            header.bitcoin_hash().be_hex_string(),
+            "000000000003ba27aa200b1cecaad478d2b00432346c3f1f3986da1afd33e506"
+        );
+        let txids: Vec<Sha256dHash> = [
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+        ]
+        .iter()
+        .map(|h| Sha256dHash::from_hex(h).unwrap())
+        .collect();
+
+        for (i, txid) in txids.iter().enumerate() {
+            let branch = merkle_branch(&txids, i as u32).unwrap();
+            assert_eq!(branch.len(), 2);
+            assert!(verify_merkle_proof(txid, &branch, i as u32, &header).unwrap());
+        }
+
+        let branch = merkle_branch(&txids, 2).unwrap();
+        assert_eq!(branch[0], txids[3]);
+
+        // tampered branch
+        let mut tampered = branch.clone();
+        tampered[1] = Sha256dHash::from_data(&[1]);
+        assert!(!verify_merkle_proof(&txids[2], &tampered, 2, &header).unwrap());
+
+        // wrong position, wrong transaction, and a shortened branch
+        assert!(!verify_merkle_proof(&txids[2], &branch, 3, &header).unwrap());
+        assert!(!verify_merkle_proof(&txids[1], &branch, 2, &header).unwrap());
+        assert!(!verify_merkle_proof(&txids[2], &branch[..1], 0, &header).unwrap());
+
+        // position past the end of the tree
+        match verify_merkle_proof(&txids[2], &branch, 4, &header) {
+            Err(btc_error::InvalidMerkleProof(_)) => {}
+            x => panic!("Expected InvalidMerkleProof, got {:?}", x),
+        }
+        match merkle_branch(&txids, 4) {
+            Err(btc_error::InvalidMerkleProof(_)) => {}
+            x => panic!("Expected InvalidMerkleProof, got {:?}", x),
+        }
+
+        // the last transaction of a block with 3 transactions is paired with itself...
+        let txids = &txids[..3];
+        let header = BlockHeader {
+            merkle_root: bitcoin_merkle_root(txids.to_vec()),
+            ..header
+        };
+        let branch = merkle_branch(txids, 2).unwrap();
+        assert_eq!(branch[0], txids[2]);
+        assert!(verify_merkle_proof(&txids[2], &branch, 2, &header).unwrap());
+
+        // ...but a proof of its duplicate in the mutated tree with 4 transactions is malformed,
+        // even though that tree has the same merkle root
+        match verify_merkle_proof(&txids[2], &branch, 3, &header) {
+            Err(btc_error::InvalidMerkleProof(msg)) => assert!(msg.contains("duplicate")),
+            x => panic!("Expected InvalidMerkleProof, got {:?}", x),
+        }
+        let mut mutated = txids.to_vec();
+        mutated.push(txids[2]);
+        assert_eq!(bitcoin_merkle_root(mutated.clone()), header.merkle_root);
+        match merkle_branch(&mutated, 0) {
+            Err(btc_error::InvalidMerkleProof(msg)) => assert!(msg.contains("duplicate")),
+            x => panic!("Expected InvalidMerkleProof, got {:?}", x),
         }
     }
 }
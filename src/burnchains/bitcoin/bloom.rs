@@ -0,0 +1,902 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! BIP37 connection bloom filtering.
+//!
+//! Once we load a bloom filter into a peer with `filterload`, it answers a `getdata` for a
+//! filtered block with a `merkleblock` -- the block's header and a partial merkle tree proving
+//! which of its transactions matched the filter -- followed by a `tx` for each transaction
+//! that matched.  A transaction matches if the filter contains its txid, a data push in one of
+//! its output scripts, or one of its inputs' outpoints or scriptSig data pushes.  Matching is
+//! by whole data push, so a filter can't select transactions by an OP_RETURN payload prefix
+//! such as our magic bytes; it can only select them by the addresses they pay to or spend from.
+
+use std::cmp;
+use std::collections::HashMap;
+
+use stacks_common::deps_common::bitcoin::blockdata::block::Block;
+use stacks_common::deps_common::bitcoin::blockdata::opcodes::All as btc_opcodes;
+use stacks_common::deps_common::bitcoin::blockdata::script::{Instruction, Script};
+use stacks_common::deps_common::bitcoin::blockdata::transaction::{OutPoint, Transaction};
+use stacks_common::deps_common::bitcoin::network::message_bloom::{FilterLoad, MerkleBlock};
+use stacks_common::deps_common::bitcoin::network::serialize::BitcoinHash;
+use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+
+use crate::burnchains::bitcoin::bits;
+use crate::burnchains::bitcoin::Error as btc_error;
+
+/// Largest filter a peer will load, in bytes
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+/// Most hash functions a peer will use per element
+pub const MAX_BLOOM_HASH_FUNCS: u32 = 50;
+
+/// Don't add anything to the filter as transactions match it
+pub const BLOOM_UPDATE_NONE: u8 = 0;
+/// Add the outpoint of every output whose script matches the filter, so that the transactions
+/// that later spend it match too
+pub const BLOOM_UPDATE_ALL: u8 = 1;
+/// Like `BLOOM_UPDATE_ALL`, but only for pay-to-pubkey and bare multisig outputs
+pub const BLOOM_UPDATE_P2PUBKEY_ONLY: u8 = 2;
+const BLOOM_UPDATE_MASK: u8 = 3;
+
+/// False-positive rate of the filters we load into peers
+pub const DEFAULT_BLOOM_FP_RATE: f64 = 0.0001;
+/// Fewest elements we size a filter for.  With `BLOOM_UPDATE_ALL`, the peer adds an outpoint to
+/// the filter for every output that matches it, so a filter sized for just its scripts' data
+/// pushes would soon match everything.
+pub const BLOOM_FILTER_MIN_ELEMENTS: usize = 1000;
+
+/// Most transactions a block can have (its weight limit over the smallest transaction's weight)
+const MAX_BLOCK_TXS: u32 = 4_000_000 / 240;
+
+/// 32-bit MurmurHash3 (x86 variant) of `data`
+pub fn murmur3_32(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mut h1 = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k1 = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k1 = k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k1 = 0u32;
+        for (i, byte) in tail.iter().enumerate() {
+            k1 ^= (*byte as u32) << (8 * i);
+        }
+        k1 = k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h1 ^= k1;
+    }
+
+    h1 ^= data.len() as u32;
+    h1 ^= h1 >> 16;
+    h1 = h1.wrapping_mul(0x85ebca6b);
+    h1 ^= h1 >> 13;
+    h1 = h1.wrapping_mul(0xc2b2ae35);
+    h1 ^= h1 >> 16;
+    h1
+}
+
+/// The non-empty data pushes of a script, which are what a BIP37 filter matches scripts by
+fn script_data_pushes(script: &Script) -> Vec<&[u8]> {
+    bits::parse_script(script)
+        .into_iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::PushBytes(data) if !data.is_empty() => Some(data),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Is this a bare multisig output script (`m <pubkeys> n OP_CHECKMULTISIG`)?
+fn is_bare_multisig(script: &Script) -> bool {
+    script.as_bytes().last() == Some(&(btc_opcodes::OP_CHECKMULTISIG as u8))
+}
+
+/// A BIP37 bloom filter, as loaded into a peer with `filterload`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bip37Filter {
+    data: Vec<u8>,
+    hash_funcs: u32,
+    tweak: u32,
+    flags: u8,
+    /// Number of elements the filter was sized for at its false-positive rate
+    capacity: usize,
+    /// Number of elements inserted so far
+    num_elements: usize,
+}
+
+impl Bip37Filter {
+    /// Make an empty filter sized for `num_elements` elements at the given false-positive rate
+    /// (as BIP37 prescribes), with a random `tweak` to vary its hash functions and the given
+    /// update `flags`
+    pub fn new(num_elements: usize, fp_rate: f64, tweak: u32, flags: u8) -> Bip37Filter {
+        let num_elements = num_elements.max(1);
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-1.0 / (ln2 * ln2) * (num_elements as f64) * fp_rate.ln())
+            .min((MAX_BLOOM_FILTER_SIZE * 8) as f64);
+        let num_bytes = ((num_bits as usize) / 8).max(1);
+        // (integer division, as in Bitcoin Core, so we size filters the same way it does)
+        let hash_funcs =
+            ((((num_bytes * 8) / num_elements) as f64 * ln2) as u32).clamp(1, MAX_BLOOM_HASH_FUNCS);
+        Bip37Filter {
+            data: vec![0; num_bytes],
+            hash_funcs,
+            tweak,
+            flags,
+            capacity: num_elements,
+            num_elements: 0,
+        }
+    }
+
+    /// The filter a peer has once it takes in `filterload`.  Since we don't know what it was
+    /// sized for, it's never saturated.
+    pub fn from_filterload(filterload: &FilterLoad) -> Bip37Filter {
+        Bip37Filter {
+            data: filterload.filter.clone(),
+            hash_funcs: filterload.hash_funcs,
+            tweak: filterload.tweak,
+            flags: filterload.flags,
+            capacity: usize::MAX,
+            num_elements: 0,
+        }
+    }
+
+    /// Make a filter that matches every transaction that pays to or (once the peer has matched
+    /// the output it spends) spends from one of `scripts`.  The filter holds each script's data
+    /// pushes (e.g. the hash of a P2PKH or P2SH script, or the program of a segwit one), and
+    /// has the peer add the outpoints of matching outputs to it as it goes.
+    pub fn for_scripts(scripts: &[Vec<u8>], fp_rate: f64, tweak: u32) -> Bip37Filter {
+        Bip37Filter::for_watch_targets(scripts, &[], fp_rate, tweak)
+    }
+
+    /// Like `for_scripts()`, but also match every transaction that spends one of `outpoints`
+    pub fn for_watch_targets(
+        scripts: &[Vec<u8>],
+        outpoints: &[OutPoint],
+        fp_rate: f64,
+        tweak: u32,
+    ) -> Bip37Filter {
+        let mut elements: Vec<Vec<u8>> = scripts
+            .iter()
+            .flat_map(|script| Bip37Filter::script_elements(script))
+            .collect();
+        elements.extend(outpoints.iter().map(Bip37Filter::outpoint_element));
+
+        let mut filter = Bip37Filter::new(
+            cmp::max(elements.len(), BLOOM_FILTER_MIN_ELEMENTS),
+            fp_rate,
+            tweak,
+            BLOOM_UPDATE_ALL,
+        );
+        for element in elements.iter() {
+            filter.insert(element);
+        }
+        filter
+    }
+
+    /// The elements that make a filter match the transactions paying to or spending from
+    /// `script`:  its data pushes
+    pub fn script_elements(script: &[u8]) -> Vec<Vec<u8>> {
+        let script = Script::from(script.to_vec());
+        let pushes = script_data_pushes(&script);
+        if pushes.is_empty() {
+            warn!(
+                "Script {} has no data pushes, so a bloom filter can't match it",
+                script
+            );
+        }
+        pushes.into_iter().map(|data| data.to_vec()).collect()
+    }
+
+    /// The element that makes a filter match the transactions spending `outpoint`:  its txid
+    /// followed by its little-endian output index
+    pub fn outpoint_element(outpoint: &OutPoint) -> Vec<u8> {
+        let mut bytes = outpoint.txid.as_bytes().to_vec();
+        bytes.extend_from_slice(&outpoint.vout.to_le_bytes());
+        bytes
+    }
+
+    /// Have more elements been inserted than the filter was sized for?  Past that point, its
+    /// false-positive rate climbs above the one it was made for, and it should be replaced with
+    /// a bigger one.
+    pub fn is_saturated(&self) -> bool {
+        self.num_elements > self.capacity
+    }
+
+    fn bit_index(&self, hash_num: u32, element: &[u8]) -> usize {
+        let seed = hash_num.wrapping_mul(0xfba4c795).wrapping_add(self.tweak);
+        (murmur3_32(seed, element) as usize) % (self.data.len() * 8)
+    }
+
+    /// Add an element to the filter
+    pub fn insert(&mut self, element: &[u8]) {
+        for i in 0..self.hash_funcs {
+            let index = self.bit_index(i, element);
+            self.data[index >> 3] |= 1 << (index & 7);
+        }
+        self.num_elements += 1;
+    }
+
+    /// Might the filter contain this element?  False positives are possible; false negatives
+    /// are not.
+    pub fn contains(&self, element: &[u8]) -> bool {
+        (0..self.hash_funcs).all(|i| {
+            let index = self.bit_index(i, element);
+            self.data[index >> 3] & (1 << (index & 7)) != 0
+        })
+    }
+
+    fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&Bip37Filter::outpoint_element(outpoint));
+    }
+
+    fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&Bip37Filter::outpoint_element(outpoint))
+    }
+
+    /// Does `tx` match the filter, the way a peer would decide it?  Like the peer, this adds
+    /// the outpoints of matching outputs to the filter, according to its update flags.
+    pub fn matches_tx(&mut self, tx: &Transaction) -> bool {
+        let txid = tx.txid();
+        let mut found = self.contains(txid.as_bytes());
+        for (vout, output) in tx.output.iter().enumerate() {
+            let pushes = script_data_pushes(&output.script_pubkey);
+            if !pushes.iter().any(|data| self.contains(data)) {
+                continue;
+            }
+            found = true;
+            let update = match self.flags & BLOOM_UPDATE_MASK {
+                BLOOM_UPDATE_ALL => true,
+                BLOOM_UPDATE_P2PUBKEY_ONLY => {
+                    output.script_pubkey.is_p2pk() || is_bare_multisig(&output.script_pubkey)
+                }
+                _ => false,
+            };
+            if update {
+                self.insert_outpoint(&OutPoint {
+                    txid,
+                    vout: vout as u32,
+                });
+            }
+        }
+        if found {
+            return true;
+        }
+        tx.input.iter().any(|input| {
+            self.contains_outpoint(&input.previous_output)
+                || script_data_pushes(&input.script_sig)
+                    .iter()
+                    .any(|data| self.contains(data))
+        })
+    }
+
+    /// The `filterload` message that loads this filter into a peer
+    pub fn to_filterload(&self) -> FilterLoad {
+        FilterLoad {
+            filter: self.data.clone(),
+            hash_funcs: self.hash_funcs,
+            tweak: self.tweak,
+            flags: self.flags,
+        }
+    }
+}
+
+/// Hash of the merkle tree node whose children have hashes `left` and `right`
+pub fn merkle_parent(left: &Sha256dHash, right: &Sha256dHash) -> Sha256dHash {
+    let mut pair_bytes = [0u8; 64];
+    pair_bytes[..32].copy_from_slice(left.as_bytes());
+    pair_bytes[32..].copy_from_slice(right.as_bytes());
+    Sha256dHash::from_data(&pair_bytes)
+}
+
+/// Number of nodes at `height` (0 being the leaves) of the merkle tree of `num_txs` transactions
+fn tree_width(num_txs: u32, height: u32) -> u32 {
+    ((num_txs as u64 + (1u64 << height) - 1) >> height) as u32
+}
+
+/// Height of the merkle tree of `num_txs` transactions
+fn tree_height(num_txs: u32) -> u32 {
+    let mut height = 0;
+    while tree_width(num_txs, height) > 1 {
+        height += 1;
+    }
+    height
+}
+
+fn node_hash(txids: &[Sha256dHash], height: u32, pos: u32) -> Sha256dHash {
+    if height == 0 {
+        return txids[pos as usize];
+    }
+    let left = node_hash(txids, height - 1, pos * 2);
+    let right = if pos * 2 + 1 < tree_width(txids.len() as u32, height - 1) {
+        node_hash(txids, height - 1, pos * 2 + 1)
+    } else {
+        left
+    };
+    merkle_parent(&left, &right)
+}
+
+fn build_partial_tree(
+    txids: &[Sha256dHash],
+    matches: &[bool],
+    height: u32,
+    pos: u32,
+    bits: &mut Vec<bool>,
+    hashes: &mut Vec<Sha256dHash>,
+) {
+    let first = (pos as usize) << height;
+    let last = cmp::min((first as u64 + (1u64 << height)) as usize, txids.len());
+    let parent_of_match = matches[first..last].iter().any(|m| *m);
+    bits.push(parent_of_match);
+    if height == 0 || !parent_of_match {
+        hashes.push(node_hash(txids, height, pos));
+        return;
+    }
+    build_partial_tree(txids, matches, height - 1, pos * 2, bits, hashes);
+    if pos * 2 + 1 < tree_width(txids.len() as u32, height - 1) {
+        build_partial_tree(txids, matches, height - 1, pos * 2 + 1, bits, hashes);
+    }
+}
+
+/// Make the `merkleblock` a peer would send for `block` if the transactions flagged in
+/// `matches` matched its filter
+pub fn build_merkle_block(block: &Block, matches: &[bool]) -> MerkleBlock {
+    assert_eq!(block.txdata.len(), matches.len());
+    assert!(!block.txdata.is_empty());
+    let txids: Vec<Sha256dHash> = block.txdata.iter().map(|tx| tx.txid()).collect();
+    let mut bits = vec![];
+    let mut hashes = vec![];
+    build_partial_tree(
+        &txids,
+        matches,
+        tree_height(txids.len() as u32),
+        0,
+        &mut bits,
+        &mut hashes,
+    );
+
+    let mut flags = vec![0u8; (bits.len() + 7) / 8];
+    for (i, bit) in bits.iter().enumerate() {
+        flags[i / 8] |= (*bit as u8) << (i % 8);
+    }
+    MerkleBlock {
+        header: block.header.clone(),
+        total_transactions: txids.len() as u32,
+        hashes,
+        flags,
+    }
+}
+
+/// Walks a `merkleblock`'s partial merkle tree
+struct PartialTreeReader<'a> {
+    merkle_block: &'a MerkleBlock,
+    bits_used: usize,
+    hashes_used: usize,
+    matches: Vec<(u32, Sha256dHash)>,
+    /// Hash of each node we've seen, by height and position
+    nodes: HashMap<(u32, u32), Sha256dHash>,
+}
+
+impl<'a> PartialTreeReader<'a> {
+    fn next_bit(&mut self) -> Result<bool, btc_error> {
+        let byte = self
+            .merkle_block
+            .flags
+            .get(self.bits_used / 8)
+            .ok_or_else(|| btc_error::InvalidMerkleBlock("ran out of flag bits".to_string()))?;
+        let bit = (byte >> (self.bits_used % 8)) & 1 == 1;
+        self.bits_used += 1;
+        Ok(bit)
+    }
+
+    fn next_hash(&mut self) -> Result<Sha256dHash, btc_error> {
+        let hash = self
+            .merkle_block
+            .hashes
+            .get(self.hashes_used)
+            .cloned()
+            .ok_or_else(|| btc_error::InvalidMerkleBlock("ran out of hashes".to_string()))?;
+        self.hashes_used += 1;
+        Ok(hash)
+    }
+
+    /// Get the hash of the node at `height` and `pos`, noting the matched transactions under it
+    fn traverse(&mut self, height: u32, pos: u32) -> Result<Sha256dHash, btc_error> {
+        let hash = self.traverse_node(height, pos)?;
+        self.nodes.insert((height, pos), hash);
+        Ok(hash)
+    }
+
+    fn traverse_node(&mut self, height: u32, pos: u32) -> Result<Sha256dHash, btc_error> {
+        let parent_of_match = self.next_bit()?;
+        if height == 0 || !parent_of_match {
+            let hash = self.next_hash()?;
+            if height == 0 && parent_of_match {
+                self.matches.push((pos, hash));
+            }
+            return Ok(hash);
+        }
+
+        let left = self.traverse(height - 1, pos * 2)?;
+        let right = if pos * 2 + 1 < tree_width(self.merkle_block.total_transactions, height - 1) {
+            let right = self.traverse(height - 1, pos * 2 + 1)?;
+            if right == left {
+                // a mutated tree (CVE-2012-2459); see `BitcoinBlockParser::checked_merkle_root()`
+                return Err(btc_error::InvalidMerkleBlock(
+                    "duplicate hashes in the partial merkle tree".to_string(),
+                ));
+            }
+            right
+        } else {
+            left
+        };
+        Ok(merkle_parent(&left, &right))
+    }
+}
+
+/// Check a `merkleblock`'s partial merkle tree against the merkle root in its header, and get
+/// the positions in the block and txids of the transactions it matched, in block order.  The
+/// tree must use up every hash and flag byte, and must not be mutated.
+pub fn extract_matches(merkle_block: &MerkleBlock) -> Result<Vec<(u32, Sha256dHash)>, btc_error> {
+    Ok(read_partial_tree(merkle_block)?.matches)
+}
+
+/// Get the position in the block and the merkle branch (see `blocks::merkle_branch()`) of the
+/// transaction `txid` from a `merkleblock`'s partial merkle tree, which is checked the same way
+/// as by `extract_matches()`.  bitcoind's `gettxoutproof` gives its proofs as `merkleblock`s.
+/// Returns None if the tree doesn't match `txid`.
+pub fn merkle_block_branch(
+    merkle_block: &MerkleBlock,
+    txid: &Sha256dHash,
+) -> Result<Option<(u32, Vec<Sha256dHash>)>, btc_error> {
+    let reader = read_partial_tree(merkle_block)?;
+    let position = match reader.matches.iter().find(|(_, hash)| hash == txid) {
+        Some((position, _)) => *position,
+        None => {
+            return Ok(None);
+        }
+    };
+    let branch = partial_tree_branch(&reader, merkle_block.total_transactions, position)?;
+    Ok(Some((position, branch)))
+}
+
+/// Like `extract_matches()`, but also get the merkle branch of each matched transaction (see
+/// `merkle_block_branch()`)
+pub fn extract_match_branches(
+    merkle_block: &MerkleBlock,
+) -> Result<Vec<(u32, Sha256dHash, Vec<Sha256dHash>)>, btc_error> {
+    let reader = read_partial_tree(merkle_block)?;
+    reader
+        .matches
+        .iter()
+        .map(|(position, txid)| {
+            let branch = partial_tree_branch(&reader, merkle_block.total_transactions, *position)?;
+            Ok((*position, *txid, branch))
+        })
+        .collect()
+}
+
+/// The merkle branch of the matched transaction at `position` in a checked partial merkle tree
+fn partial_tree_branch(
+    reader: &PartialTreeReader<'_>,
+    num_txs: u32,
+    position: u32,
+) -> Result<Vec<Sha256dHash>, btc_error> {
+    // every node on the path to the root has both its children in the tree
+    let mut branch = vec![];
+    let mut pos = position;
+    for height in 0..tree_height(num_txs) {
+        let sibling = cmp::min(pos ^ 1, tree_width(num_txs, height) - 1);
+        let hash = reader
+            .nodes
+            .get(&(height, sibling))
+            .cloned()
+            .ok_or_else(|| {
+                btc_error::InvalidMerkleBlock(format!(
+                    "no node {} at height {} of the partial merkle tree",
+                    sibling, height
+                ))
+            })?;
+        branch.push(hash);
+        pos >>= 1;
+    }
+    Ok(branch)
+}
+
+/// Walk and check a `merkleblock`'s partial merkle tree (see `extract_matches()`)
+fn read_partial_tree(merkle_block: &MerkleBlock) -> Result<PartialTreeReader<'_>, btc_error> {
+    let num_txs = merkle_block.total_transactions;
+    if num_txs == 0 || num_txs > MAX_BLOCK_TXS {
+        return Err(btc_error::InvalidMerkleBlock(format!(
+            "block has {} transactions",
+            num_txs
+        )));
+    }
+    if merkle_block.hashes.len() as u64 > num_txs as u64 {
+        return Err(btc_error::InvalidMerkleBlock(format!(
+            "{} hashes for {} transactions",
+            merkle_block.hashes.len(),
+            num_txs
+        )));
+    }
+
+    let mut reader = PartialTreeReader {
+        merkle_block,
+        bits_used: 0,
+        hashes_used: 0,
+        matches: vec![],
+        nodes: HashMap::new(),
+    };
+    let root = reader.traverse(tree_height(num_txs), 0)?;
+    if (reader.bits_used + 7) / 8 != merkle_block.flags.len() {
+        return Err(btc_error::InvalidMerkleBlock(format!(
+            "used {} of {} flag bytes",
+            (reader.bits_used + 7) / 8,
+            merkle_block.flags.len()
+        )));
+    }
+    if reader.hashes_used != merkle_block.hashes.len() {
+        return Err(btc_error::InvalidMerkleBlock(format!(
+            "used {} of {} hashes",
+            reader.hashes_used,
+            merkle_block.hashes.len()
+        )));
+    }
+    if root != merkle_block.header.merkle_root {
+        return Err(btc_error::InvalidMerkleBlock(format!(
+            "partial merkle tree of block {} has root {}, not {}",
+            merkle_block.header.bitcoin_hash().be_hex_string(),
+            root.be_hex_string(),
+            merkle_block.header.merkle_root.be_hex_string()
+        )));
+    }
+    Ok(reader)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::burnchains::bitcoin::blocks::{merkle_branch, verify_merkle_proof};
+
+    use stacks_common::deps_common::bitcoin::blockdata::block::BlockHeader;
+    use stacks_common::deps_common::bitcoin::blockdata::transaction::{TxIn, TxOut};
+    use stacks_common::deps_common::bitcoin::network::serialize::{deserialize, serialize};
+    use stacks_common::deps_common::bitcoin::util::hash::bitcoin_merkle_root;
+    use stacks_common::util::hash::hex_bytes;
+
+    /// NAME_PREORDER with a burn output (from `blocks.rs`)
+    const PREORDER_TX: &str = "01000000000101e411dc967b8503a27450c614a5cd984698762a6b4bf547293ffdf846ed4ebd22010000002322002067091a41e9871c5ae20b0c69a786f02df5d3c7aa632689b608069181b43a28a2ffffffff030000000000000000296a2769643f9fab7f294936ddb6524a48feff691ecbd0ca9e8f107d845c417a5438d1cb441e827c5126b01ba0290100000017a91487a0487869af70b6b1cc79bd374b75ba1be5cff98700a86100000000001976a914000000000000000000000000000000000000000088ac0400473044022064c5b5f61baad8bb8ecad98666b99e09f1777ef805df41a1c7926f8468b6b6df02205eac177c77f274acb670cd24d504f01b27de767e0241c818c91e479cb0ddcf18014730440220053ce777bc7bb842d8eef83769a027797567624ab9eed5722889ed3192f431b30220256e8aaef8de2a571198acde708fcbca02fb18780ac470c0d7f811734af729af0169522102d341f728783eb93e6fb5921a1ebe9d149e941de31e403cd69afa2f0f1e698e812102f21b29694df4c2188bee97103d10d017d1865fb40528f25589af9db6e0786b6521028791dc45c049107fb99e673265a38a096536aacdf78aa90710a32fff7750f9f953ae00000000";
+
+    /// USER_BURN_SUPPORT with a burn output (from `user_burn_support.rs`)
+    const USER_BURN_SUPPORT_TX: &str = "01000000011111111111111111111111111111111111111111111111111111111111111111000000006a47304402204c51707ac34b6dcbfc518ba40c5fc4ef737bf69cc21a9f8a8e6f621f511f78e002200caca0f102d5df509c045c4fe229d957aa7ef833dc8103dc2fe4db15a22bab9e012102d8015134d9db8178ac93acbc43170a2f20febba5087a5b0437058765ad5133d000000000030000000000000000536a4c5069645f2222222222222222222222222222222222222222a366b51292bef4edd64063d9145c617fec373bceb0758e98cd72becd84d54c7a3333333333333333333333333333333333333333010203040539300000000000001976a914000000000000000000000000000000000000000088aca05b0000000000001976a9140be3e286a15ea85882761618e366586b5574100d88ac00000000";
+
+    /// NAME_REGISTRATION without a burn output (from `blocks.rs`)
+    const NAME_REGISTRATION_TX: &str = "01000000000101a7ef2b09722ad786c569c0812005a731ce19290bb0a2afc16cb91056c2e4c19e0100000017160014393ffec4f09b38895b8502377693f23c6ae00f19ffffffff0300000000000000000d6a0b69643a666f6f2e746573747c1500000000000017a9144b85301ba8e42bf98472b8ed4939d5f76b98fcea87144d9c290100000017a91431f8968eb1730c83fb58409a9a560a0a0835027f8702483045022100fc82815edf1c0ef0c601cf1e26494626d7b01597be5ab83df025ff1ee67730130220016c4c29d77aadb5ff57c0c9272a43950ca29b84d8adfaed95ac69db90b35d5b012102d341f728783eb93e6fb5921a1ebe9d149e941de31e403cd69afa2f0f1e698e8100000000";
+
+    const BURN_SCRIPT: &str = "76a914000000000000000000000000000000000000000088ac";
+
+    fn make_tx(hex: &str) -> Transaction {
+        deserialize(&hex_bytes(hex).unwrap()).unwrap()
+    }
+
+    /// A transaction that spends `outpoint` to a p2pkh script
+    fn make_spend(outpoint: OutPoint) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 1000,
+                script_pubkey: Script::from(
+                    hex_bytes("76a914333333333333333333333333333333333333333388ac").unwrap(),
+                ),
+            }],
+        }
+    }
+
+    /// A block of `num_txs` distinct transactions
+    fn make_block(num_txs: u32) -> Block {
+        let tx = make_tx(NAME_REGISTRATION_TX);
+        let txdata: Vec<Transaction> = (0..num_txs)
+            .map(|i| {
+                let mut tx = tx.clone();
+                tx.lock_time = i;
+                tx
+            })
+            .collect();
+        Block {
+            header: BlockHeader {
+                version: 0x20000000,
+                prev_blockhash: Sha256dHash::from_data(&[0]),
+                merkle_root: bitcoin_merkle_root(txdata.iter().map(|tx| tx.txid()).collect()),
+                time: 1541604597,
+                bits: 0x207fffff,
+                nonce: 0,
+            },
+            txdata,
+        }
+    }
+
+    #[test]
+    fn test_murmur3_32() {
+        let vectors: Vec<(u32, &str, u32)> = vec![
+            (0, "", 0),
+            (0xfba4c795, "", 0x6a396f08),
+            (0xffffffff, "", 0x81f16f39),
+            (0, "00", 0x514e28b7),
+            (0xfba4c795, "00", 0xea3f0b17),
+            (0, "ff", 0xfd6cf10d),
+            (0, "0011", 0x16c6b7ab),
+            (0, "001122", 0x8eb51c3d),
+            (0, "00112233", 0xb4471bf8),
+            (0, "0011223344", 0xe2301fa8),
+            (0, "001122334455", 0xfc2e4a15),
+            (0, "00112233445566", 0xb074502c),
+            (0, "0011223344556677", 0x8034d2a0),
+            (0, "001122334455667788", 0xb4698def),
+        ];
+        for (seed, data, hash) in vectors {
+            assert_eq!(murmur3_32(seed, &hex_bytes(data).unwrap()), hash);
+        }
+    }
+
+    #[test]
+    fn test_bip37_filter_vectors() {
+        // from Bitcoin Core's bloom_tests.cpp
+        for (tweak, filterload_hex) in [
+            (0, "03614e9b050000000000000001"),
+            (2147483649, "03ce4299050000000100008001"),
+        ]
+        .iter()
+        {
+            let mut filter = Bip37Filter::new(3, 0.01, *tweak, BLOOM_UPDATE_ALL);
+            let first = hex_bytes("99108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap();
+            filter.insert(&first);
+            assert!(filter.contains(&first));
+            assert!(
+                !filter.contains(&hex_bytes("19108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap())
+            );
+
+            filter.insert(&hex_bytes("b5a2c786d9ef4658287ced5914b37a1b4aa32eee").unwrap());
+            filter.insert(&hex_bytes("b9300670b4c5366e95b2699e8b18bc75e5f729c5").unwrap());
+            assert_eq!(
+                serialize(&filter.to_filterload()).unwrap(),
+                hex_bytes(filterload_hex).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_bip37_filter_burn_ops() {
+        let burn_script = hex_bytes(BURN_SCRIPT).unwrap();
+        let preorder = make_tx(PREORDER_TX);
+        let user_burn_support = make_tx(USER_BURN_SUPPORT_TX);
+        let name_registration = make_tx(NAME_REGISTRATION_TX);
+        let spend = make_spend(OutPoint {
+            txid: preorder.txid(),
+            vout: 2,
+        });
+
+        let filterload = Bip37Filter::for_scripts(&[burn_script.clone()], DEFAULT_BLOOM_FP_RATE, 0)
+            .to_filterload();
+        assert_eq!(filterload.filter.len(), 2396);
+        assert_eq!(filterload.hash_funcs, 13);
+        assert_eq!(filterload.flags, BLOOM_UPDATE_ALL);
+
+        for tweak in 0..32 {
+            let mut filter =
+                Bip37Filter::for_scripts(&[burn_script.clone()], DEFAULT_BLOOM_FP_RATE, tweak);
+            assert!(filter.matches_tx(&preorder));
+            assert!(filter.matches_tx(&user_burn_support));
+            assert!(!filter.matches_tx(&name_registration));
+
+            // the filter picked up the burn output, so it matches its spend
+            assert!(filter.matches_tx(&spend));
+
+            // ...but only if it updates itself
+            let mut filter = Bip37Filter::new(1, DEFAULT_BLOOM_FP_RATE, tweak, BLOOM_UPDATE_NONE);
+            filter.insert(&[0u8; 20]);
+            assert!(filter.matches_tx(&preorder));
+            assert!(!filter.matches_tx(&spend));
+
+            // a p2sh output matches by its script hash
+            let p2sh_script = hex_bytes("a9144b85301ba8e42bf98472b8ed4939d5f76b98fcea87").unwrap();
+            let mut filter = Bip37Filter::for_scripts(
+                &[burn_script.clone(), p2sh_script],
+                DEFAULT_BLOOM_FP_RATE,
+                tweak,
+            );
+            assert!(filter.matches_tx(&name_registration));
+            assert!(filter.matches_tx(&preorder));
+        }
+    }
+
+    #[test]
+    fn test_bip37_filter_watch_targets() {
+        let burn_script = hex_bytes(BURN_SCRIPT).unwrap();
+        let name_registration = make_tx(NAME_REGISTRATION_TX);
+        let watched = OutPoint {
+            txid: name_registration.txid(),
+            vout: 1,
+        };
+        let spend = make_spend(watched);
+        let other_spend = make_spend(OutPoint {
+            txid: name_registration.txid(),
+            vout: 2,
+        });
+
+        for tweak in 0..32 {
+            // an outpoint matches its spend, and nothing else
+            let mut filter = Bip37Filter::for_watch_targets(
+                &[burn_script.clone()],
+                &[watched],
+                DEFAULT_BLOOM_FP_RATE,
+                tweak,
+            );
+            assert!(filter.matches_tx(&spend));
+            assert!(!filter.matches_tx(&other_spend));
+            assert!(!filter.matches_tx(&name_registration));
+
+            // the same goes for one added later, as with filteradd
+            let mut filter =
+                Bip37Filter::for_scripts(&[burn_script.clone()], DEFAULT_BLOOM_FP_RATE, tweak);
+            assert!(!filter.matches_tx(&spend));
+            filter.insert(&Bip37Filter::outpoint_element(&watched));
+            assert!(filter.matches_tx(&spend));
+            assert!(!filter.matches_tx(&other_spend));
+        }
+
+        // a filter is saturated once it has more elements than it was sized for
+        let mut filter = Bip37Filter::for_scripts(&[burn_script], DEFAULT_BLOOM_FP_RATE, 0);
+        for i in 1..BLOOM_FILTER_MIN_ELEMENTS {
+            filter.insert(&(i as u64).to_le_bytes());
+        }
+        assert!(!filter.is_saturated());
+        filter.insert(&[0xff; 8]);
+        assert!(filter.is_saturated());
+
+        // the filter a peer loads from us is the same as ours, but never saturated
+        let mut peer_filter = Bip37Filter::from_filterload(&filter.to_filterload());
+        assert_eq!(peer_filter.to_filterload(), filter.to_filterload());
+        assert!(!peer_filter.is_saturated());
+        peer_filter.insert(&Bip37Filter::outpoint_element(&watched));
+        assert!(peer_filter.matches_tx(&spend));
+    }
+
+    #[test]
+    fn test_partial_merkle_tree_round_trip() {
+        for num_txs in [1, 2, 3, 4, 5, 7, 8, 9, 17, 33].iter() {
+            let block = make_block(*num_txs);
+            let n = *num_txs as usize;
+            let patterns: Vec<Vec<bool>> = vec![
+                vec![false; n],
+                vec![true; n],
+                (0..n).map(|i| i == 0).collect(),
+                (0..n).map(|i| i == n - 1).collect(),
+                (0..n).map(|i| i % 2 == 1).collect(),
+                (0..n).map(|i| i % 3 == 0).collect(),
+            ];
+            for matches in patterns.iter() {
+                let merkle_block = build_merkle_block(&block, matches);
+                assert!(merkle_block.hashes.len() <= n);
+                let expected: Vec<(u32, Sha256dHash)> = block
+                    .txdata
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| matches[*i])
+                    .map(|(i, tx)| (i as u32, tx.txid()))
+                    .collect();
+                assert_eq!(extract_matches(&merkle_block).unwrap(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_merkle_block_branch() {
+        for num_txs in [1, 2, 3, 5, 8, 9, 17].iter() {
+            let block = make_block(*num_txs);
+            let n = *num_txs as usize;
+            let txids: Vec<Sha256dHash> = block.txdata.iter().map(|tx| tx.txid()).collect();
+            let matches: Vec<bool> = (0..n).map(|i| i % 3 == 0 || i == n - 1).collect();
+            let merkle_block = build_merkle_block(&block, &matches);
+            for (i, txid) in txids.iter().enumerate() {
+                let proof = merkle_block_branch(&merkle_block, txid).unwrap();
+                if !matches[i] {
+                    assert!(proof.is_none());
+                    continue;
+                }
+                let (position, branch) = proof.unwrap();
+                assert_eq!(position, i as u32);
+                assert_eq!(branch, merkle_branch(&txids, position).unwrap());
+                assert!(verify_merkle_proof(txid, &branch, position, &block.header).unwrap());
+            }
+
+            // all the matches' branches at once
+            let expected: Vec<(u32, Sha256dHash, Vec<Sha256dHash>)> = txids
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| matches[*i])
+                .map(|(i, txid)| (i as u32, *txid, merkle_branch(&txids, i as u32).unwrap()))
+                .collect();
+            assert_eq!(extract_match_branches(&merkle_block).unwrap(), expected);
+        }
+
+        // a tampered partial merkle tree yields no branch at all
+        let block = make_block(7);
+        let txid = block.txdata[2].txid();
+        let mut merkle_block =
+            build_merkle_block(&block, &[false, false, true, false, false, false, false]);
+        merkle_block.hashes[0] = Sha256dHash::from_data(&[1]);
+        assert!(merkle_block_branch(&merkle_block, &txid).is_err());
+    }
+
+    #[test]
+    fn test_partial_merkle_tree_rejects() {
+        let block = make_block(7);
+        let mut matches = vec![false; 7];
+        matches[2] = true;
+        matches[5] = true;
+        let merkle_block = build_merkle_block(&block, &matches);
+        assert!(extract_matches(&merkle_block).is_ok());
+
+        let mut bad = merkle_block.clone();
+        bad.total_transactions = 0;
+        assert!(extract_matches(&bad).is_err());
+
+        let mut bad = merkle_block.clone();
+        bad.flags.push(0);
+        assert!(extract_matches(&bad).is_err());
+
+        let mut bad = merkle_block.clone();
+        bad.hashes.pop();
+        assert!(extract_matches(&bad).is_err());
+
+        let mut bad = merkle_block.clone();
+        bad.hashes.push(Sha256dHash::from_data(&[1]));
+        assert!(extract_matches(&bad).is_err());
+
+        let mut bad = merkle_block.clone();
+        bad.hashes[0] = Sha256dHash::from_data(&[1]);
+        assert!(extract_matches(&bad).is_err());
+
+        let mut bad = merkle_block.clone();
+        bad.header.merkle_root = Sha256dHash::from_data(&[1]);
+        assert!(extract_matches(&bad).is_err());
+
+        // a block of 3 transactions has the same merkle root as that block with its last
+        // transaction duplicated, but that tree is mutated
+        let block = make_block(3);
+        let mut mutated = block.clone();
+        mutated.txdata.push(block.txdata[2].clone());
+        let merkle_block = build_merkle_block(&mutated, &[false, false, false, true]);
+        assert_eq!(merkle_block.header.merkle_root, block.header.merkle_root);
+        match extract_matches(&merkle_block) {
+            Err(btc_error::InvalidMerkleBlock(msg)) => assert!(msg.contains("duplicate")),
+            x => panic!("expected a mutated tree to be rejected, got {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,478 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! BIP158 compact block filters.
+//!
+//! A basic block filter is a Golomb-coded set of every output script in a block (except
+//! OP_RETURN outputs) and of every output script the block's transactions spend.  It can tell
+//! us for certain that a block does *not* contain any of a given set of scripts, so we don't
+//! have to download it.  Filters are committed to by a chain of filter headers, which peers
+//! advertise checkpoints of (BIP157).
+
+use std::hash::Hasher;
+use std::io::Cursor;
+
+use siphasher::sip::SipHasher; // this is SipHash-2-4
+
+use stacks_common::deps_common::bitcoin::blockdata::block::Block;
+use stacks_common::deps_common::bitcoin::network::encodable::{ConsensusDecodable, VarInt};
+use stacks_common::deps_common::bitcoin::network::serialize::{serialize, BitcoinHash, RawDecoder};
+use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+
+use crate::burnchains::bitcoin::Error as btc_error;
+
+/// Filter type of BIP158 basic filters
+pub const BASIC_FILTER_TYPE: u8 = 0;
+
+/// Golomb-Rice coding parameters of basic filters
+const BASIC_FILTER_P: u8 = 19;
+const BASIC_FILTER_M: u64 = 784931;
+
+/// Peers advertise the filter header at every multiple of this height (BIP157)
+pub const FILTER_CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// Most filter headers a peer will send in reply to one `getcfheaders`
+pub const MAX_CFHEADERS_PER_REQUEST: u64 = 2000;
+
+/// Most filters a peer will send in reply to one `getcfilters`
+pub const MAX_CFILTERS_PER_REQUEST: u64 = 1000;
+
+/// A serialized BIP158 basic block filter
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockFilter {
+    pub content: Vec<u8>,
+}
+
+/// Reads a bit stream, most significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, offset: 0 }
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.data.get(self.offset / 8)?;
+        let bit = (byte >> (7 - (self.offset % 8))) & 1;
+        self.offset += 1;
+        Some(bit == 1)
+    }
+
+    fn read_bits(&mut self, count: u8) -> Option<u64> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = (value << 1) | (self.read_bit()? as u64);
+        }
+        Some(value)
+    }
+
+    /// Read a Golomb-Rice coded value:  a unary quotient, and a `p`-bit remainder
+    fn read_golomb_rice(&mut self, p: u8) -> Option<u64> {
+        let mut quotient = 0u64;
+        while self.read_bit()? {
+            quotient += 1;
+        }
+        let remainder = self.read_bits(p)?;
+        Some((quotient << p) | remainder)
+    }
+}
+
+/// Writes a bit stream, most significant bit first
+struct BitWriter {
+    data: Vec<u8>,
+    offset: usize,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            data: vec![],
+            offset: 0,
+        }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        if self.offset % 8 == 0 {
+            self.data.push(0);
+        }
+        if bit {
+            let last = self.data.len() - 1;
+            self.data[last] |= 1 << (7 - (self.offset % 8));
+        }
+        self.offset += 1;
+    }
+
+    fn write_bits(&mut self, value: u64, count: u8) {
+        for i in (0..count).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    /// Write a Golomb-Rice coded value:  a unary quotient, and a `p`-bit remainder
+    fn write_golomb_rice(&mut self, value: u64, p: u8) {
+        for _ in 0..(value >> p) {
+            self.write_bit(true);
+        }
+        self.write_bit(false);
+        self.write_bits(value & ((1 << p) - 1), p);
+    }
+
+    /// Get the bytes written, with the last one padded with 0 bits
+    fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Compute the filter header that commits to a filter with the given hash, on top of the
+/// previous block's filter header.  The filter header before the genesis block is all 0's.
+pub fn filter_header(filter_hash: &Sha256dHash, prev_filter_header: &Sha256dHash) -> Sha256dHash {
+    let mut bytes = [0u8; 64];
+    bytes[0..32].copy_from_slice(filter_hash.as_bytes());
+    bytes[32..64].copy_from_slice(prev_filter_header.as_bytes());
+    Sha256dHash::from_data(&bytes)
+}
+
+impl BlockFilter {
+    pub fn new(content: Vec<u8>) -> BlockFilter {
+        BlockFilter { content }
+    }
+
+    /// Build the basic filter of a block.  Besides the block's output scripts, the filter holds
+    /// the output scripts its transactions spend, which aren't in the block, so they must be
+    /// given as `spent_scripts`.  Empty scripts and OP_RETURN outputs are left out.
+    pub fn from_block(block: &Block, spent_scripts: &[Vec<u8>]) -> BlockFilter {
+        let mut elements = vec![];
+        for tx in block.txdata.iter() {
+            for output in tx.output.iter() {
+                if output.script_pubkey.is_empty() || output.script_pubkey.is_op_return() {
+                    continue;
+                }
+                elements.push(output.script_pubkey.to_bytes());
+            }
+        }
+        for script in spent_scripts.iter() {
+            if !script.is_empty() {
+                elements.push(script.clone());
+            }
+        }
+        BlockFilter::from_elements(&block.bitcoin_hash(), &elements)
+    }
+
+    /// Build a filter over a set of elements (duplicates are ignored) for the block with the
+    /// given hash
+    pub fn from_elements(block_hash: &Sha256dHash, elements: &[Vec<u8>]) -> BlockFilter {
+        let mut elements: Vec<&Vec<u8>> = elements.iter().collect();
+        elements.sort();
+        elements.dedup();
+
+        let num_elements = elements.len() as u64;
+        let range = num_elements * BASIC_FILTER_M;
+        let mut values: Vec<u64> = elements
+            .iter()
+            .map(|element| BlockFilter::hash_to_range(block_hash, range, element))
+            .collect();
+        values.sort();
+
+        // stored as sorted deltas
+        let mut writer = BitWriter::new();
+        let mut last_value = 0;
+        for value in values.into_iter() {
+            writer.write_golomb_rice(value - last_value, BASIC_FILTER_P);
+            last_value = value;
+        }
+
+        let mut content =
+            serialize(&VarInt(num_elements)).expect("FATAL: failed to serialize a varint");
+        content.append(&mut writer.into_bytes());
+        BlockFilter { content }
+    }
+
+    /// Hash of the filter, as committed to by its filter header
+    pub fn filter_hash(&self) -> Sha256dHash {
+        Sha256dHash::from_data(&self.content)
+    }
+
+    /// This filter's header, given the previous block's filter header
+    pub fn filter_header(&self, prev_filter_header: &Sha256dHash) -> Sha256dHash {
+        filter_header(&self.filter_hash(), prev_filter_header)
+    }
+
+    /// Map a script into the filter's range [0, num_elements * M), keyed by the block hash
+    fn hash_to_range(block_hash: &Sha256dHash, range: u64, script: &[u8]) -> u64 {
+        let bytes = block_hash.as_bytes();
+        let mut k0 = [0u8; 8];
+        let mut k1 = [0u8; 8];
+        k0.copy_from_slice(&bytes[0..8]);
+        k1.copy_from_slice(&bytes[8..16]);
+
+        let mut hasher = SipHasher::new_with_keys(u64::from_le_bytes(k0), u64::from_le_bytes(k1));
+        hasher.write(script);
+        (((hasher.finish() as u128) * (range as u128)) >> 64) as u64
+    }
+
+    /// Does this filter (for the block with the given hash) possibly contain any of `scripts`?
+    /// False positives happen with probability about 1/M per script, but there are no false
+    /// negatives.
+    /// Returns InvalidFilter if the filter can't be decoded.
+    pub fn match_any(
+        &self,
+        block_hash: &Sha256dHash,
+        scripts: &[Vec<u8>],
+    ) -> Result<bool, btc_error> {
+        let mut decoder = RawDecoder::new(Cursor::new(&self.content[..]));
+        let VarInt(num_elements) = ConsensusDecodable::consensus_decode(&mut decoder)
+            .map_err(|e| btc_error::InvalidFilter(format!("bad element count: {}", e)))?;
+        let data = &self.content[(decoder.into_inner().position() as usize)..];
+
+        // each element takes at least P+1 bits
+        if num_elements > ((data.len() as u64) * 8) / (BASIC_FILTER_P as u64 + 1) {
+            return Err(btc_error::InvalidFilter(format!(
+                "{} elements do not fit in {} bytes",
+                num_elements,
+                data.len()
+            )));
+        }
+        if num_elements == 0 || scripts.len() == 0 {
+            return Ok(false);
+        }
+
+        let range = num_elements * BASIC_FILTER_M;
+        let mut queries: Vec<u64> = scripts
+            .iter()
+            .map(|script| BlockFilter::hash_to_range(block_hash, range, script))
+            .collect();
+        queries.sort();
+        queries.dedup();
+
+        // the set is stored as sorted deltas, so walk it and the queries in step
+        let mut reader = BitReader::new(data);
+        let mut value = 0u64;
+        let mut next_query = 0;
+        for _ in 0..num_elements {
+            let delta = reader
+                .read_golomb_rice(BASIC_FILTER_P)
+                .ok_or_else(|| btc_error::InvalidFilter("truncated filter".to_string()))?;
+            value = value
+                .checked_add(delta)
+                .ok_or_else(|| btc_error::InvalidFilter("element out of range".to_string()))?;
+
+            while next_query < queries.len() && queries[next_query] < value {
+                next_query += 1;
+            }
+            if next_query >= queries.len() {
+                return Ok(false);
+            }
+            if queries[next_query] == value {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use stacks_common::deps_common::bitcoin::blockdata::constants::genesis_block;
+    use stacks_common::deps_common::bitcoin::blockdata::script::Script;
+    use stacks_common::deps_common::bitcoin::network::constants::Network;
+    use stacks_common::util::hash::hex_bytes;
+
+    /// Canned vectors:  (block hash, filter, filter hash, filter header, previous filter
+    /// header).  The first is testnet3's genesis block, from BIP158.  The second is a filter
+    /// over a burn address output, a p2wpkh output, and a p2pkh and a p2sh prevout.
+    fn filter_vectors() -> Vec<(
+        &'static str,
+        &'static str,
+        &'static str,
+        &'static str,
+        &'static str,
+    )> {
+        vec![
+            (
+                "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+                "019dfca8",
+                "c03705b2d6fb76a59664f1d63fe8fdbb2dc076d18175fdc51d11c43afaf78a4c",
+                "21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+            ),
+            (
+                "000000006c02c8ea6e4ff69651f7fcde348fb9d557a06e6957b65552002a7820",
+                "043deedc4a68a3915d2464d0",
+                "a315fe99f56eb6e214e08a28f2388b377fe53c2ac9216b6bbc3b8038ba14bb02",
+                "d42abf4eeb2bc1797577161bed8e065a86bd403a8a0c26de604195dfd946f7ec",
+                "21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750",
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_filter_headers() {
+        for (_, filter_hex, filter_hash_hex, header_hex, prev_header_hex) in filter_vectors() {
+            let filter = BlockFilter::new(hex_bytes(filter_hex).unwrap());
+            let prev_header = Sha256dHash::from_hex(prev_header_hex).unwrap();
+            assert_eq!(
+                filter.filter_hash(),
+                Sha256dHash::from_hex(filter_hash_hex).unwrap()
+            );
+            assert_eq!(
+                filter.filter_header(&prev_header),
+                Sha256dHash::from_hex(header_hex).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_filter_match_any() {
+        let vectors = filter_vectors();
+
+        // testnet3 genesis block's coinbase output
+        let genesis_hash = Sha256dHash::from_hex(vectors[0].0).unwrap();
+        let genesis_filter = BlockFilter::new(hex_bytes(vectors[0].1).unwrap());
+        let genesis_script = hex_bytes("4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac").unwrap();
+        let op_return_script = hex_bytes("6a0c69645b000102030405060708").unwrap();
+        assert!(genesis_filter
+            .match_any(&genesis_hash, &[genesis_script.clone()])
+            .unwrap());
+        assert!(!genesis_filter
+            .match_any(&genesis_hash, &[op_return_script.clone()])
+            .unwrap());
+        assert!(!genesis_filter.match_any(&genesis_hash, &[]).unwrap());
+
+        let block_hash = Sha256dHash::from_hex(vectors[1].0).unwrap();
+        let filter = BlockFilter::new(hex_bytes(vectors[1].1).unwrap());
+        let burn_script = hex_bytes("76a914000000000000000000000000000000000000000088ac").unwrap();
+        let p2wpkh_script = hex_bytes("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        let p2pkh_script = hex_bytes("76a914111111111111111111111111111111111111111188ac").unwrap();
+        let p2sh_script = hex_bytes("a914222222222222222222222222222222222222222287").unwrap();
+        let other_script = hex_bytes("76a914333333333333333333333333333333333333333388ac").unwrap();
+
+        for script in [&burn_script, &p2wpkh_script, &p2pkh_script, &p2sh_script].iter() {
+            assert!(filter.match_any(&block_hash, &[(*script).clone()]).unwrap());
+        }
+        assert!(filter
+            .match_any(&block_hash, &[other_script.clone(), p2sh_script.clone()])
+            .unwrap());
+        assert!(!filter
+            .match_any(
+                &block_hash,
+                &[other_script.clone(), op_return_script.clone()]
+            )
+            .unwrap());
+
+        // the filter is keyed to its block
+        assert!(!filter
+            .match_any(
+                &genesis_hash,
+                &[burn_script, p2wpkh_script, p2pkh_script, p2sh_script]
+            )
+            .unwrap());
+    }
+
+    #[test]
+    fn test_filter_construction() {
+        let vectors = filter_vectors();
+
+        // testnet3's genesis block has one output, and spends nothing
+        let genesis = genesis_block(Network::Testnet);
+        assert_eq!(
+            genesis.bitcoin_hash(),
+            Sha256dHash::from_hex(vectors[0].0).unwrap()
+        );
+        assert_eq!(
+            BlockFilter::from_block(&genesis, &[]),
+            BlockFilter::new(hex_bytes(vectors[0].1).unwrap())
+        );
+
+        let burn_script = hex_bytes("76a914000000000000000000000000000000000000000088ac").unwrap();
+        let p2wpkh_script = hex_bytes("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        let p2pkh_script = hex_bytes("76a914111111111111111111111111111111111111111188ac").unwrap();
+        let p2sh_script = hex_bytes("a914222222222222222222222222222222222222222287").unwrap();
+        let scripts = vec![
+            burn_script.clone(),
+            p2wpkh_script.clone(),
+            p2pkh_script.clone(),
+            p2sh_script.clone(),
+        ];
+        assert_eq!(
+            BlockFilter::from_elements(&Sha256dHash::from_hex(vectors[1].0).unwrap(), &scripts),
+            BlockFilter::new(hex_bytes(vectors[1].1).unwrap())
+        );
+
+        // OP_RETURN outputs, empty scripts, and duplicates are left out
+        let mut block = genesis.clone();
+        let template = block.txdata[0].output[0].clone();
+        block.txdata[0].output.clear();
+        for script in [
+            burn_script.clone(),
+            hex_bytes("6a0c69645b000102030405060708").unwrap(),
+            vec![],
+            p2wpkh_script.clone(),
+            burn_script.clone(),
+        ]
+        .iter()
+        {
+            let mut output = template.clone();
+            output.script_pubkey = Script::from(script.clone());
+            block.txdata[0].output.push(output);
+        }
+        let filter = BlockFilter::from_block(&block, &[p2pkh_script, vec![], p2sh_script]);
+        assert_eq!(
+            filter,
+            BlockFilter::from_elements(&block.bitcoin_hash(), &scripts)
+        );
+        assert!(filter
+            .match_any(&block.bitcoin_hash(), &scripts[0..1])
+            .unwrap());
+    }
+
+    #[test]
+    fn test_filter_false_positive() {
+        let vectors = filter_vectors();
+        let genesis_hash = Sha256dHash::from_hex(vectors[0].0).unwrap();
+        let genesis_filter = BlockFilter::new(hex_bytes(vectors[0].1).unwrap());
+
+        // this script is not in the genesis block, but hashes to the same filter element as its
+        // output, so a block downloader looking for it would still (harmlessly) fetch the block
+        let script = hex_bytes("76a91400000000000000000000000000000000000e2bb788ac").unwrap();
+        assert!(genesis_filter.match_any(&genesis_hash, &[script]).unwrap());
+
+        // ...unlike its neighbors
+        let script = hex_bytes("76a91400000000000000000000000000000000000e2bb688ac").unwrap();
+        assert!(!genesis_filter.match_any(&genesis_hash, &[script]).unwrap());
+    }
+
+    #[test]
+    fn test_filter_invalid() {
+        let block_hash = Sha256dHash::from_hex(filter_vectors()[1].0).unwrap();
+        let script = vec![0x51];
+
+        // empty filters match nothing
+        let empty = BlockFilter::new(vec![0x00]);
+        assert!(!empty.match_any(&block_hash, &[script.clone()]).unwrap());
+
+        for bad_filter in [vec![], vec![0xfd, 0x01], vec![0x04, 0x3d, 0xee, 0xdc]].iter() {
+            match BlockFilter::new(bad_filter.clone()).match_any(&block_hash, &[script.clone()]) {
+                Err(btc_error::InvalidFilter(..)) => {}
+                res => panic!("Filter {:?} not rejected: {:?}", bad_filter, res),
+            }
+        }
+    }
+}
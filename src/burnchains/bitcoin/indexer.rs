@@ -16,43 +16,68 @@
 
 use rand::{thread_rng, Rng};
 use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::fs;
+use std::io;
 use std::net;
 use std::net::Shutdown;
 use std::ops::Deref;
 use std::ops::DerefMut;
 use std::path;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use crate::burnchains::bitcoin::address::{LegacyBitcoinAddress, LegacyBitcoinAddressType};
 use crate::burnchains::bitcoin::blocks::BitcoinHeaderIPC;
-use crate::burnchains::bitcoin::messages::BitcoinMessageHandler;
+use crate::burnchains::bitcoin::bloom::{Bip37Filter, DEFAULT_BLOOM_FP_RATE};
+use crate::burnchains::bitcoin::mempool::{MempoolWatcher, UnconfirmedBitcoinTx};
+use crate::burnchains::bitcoin::messages::{
+    BitcoinMessageHandler, DEFAULT_MAX_BLOCK_SIZE, DEFAULT_MAX_MESSAGE_SIZE,
+};
+use crate::burnchains::bitcoin::network::RequestLimiter;
+use crate::burnchains::bitcoin::rpc::{
+    bitcoind_chain_name, BitcoinRpcClient, BlockchainInfo, RpcCredentials,
+    DEFAULT_RPC_MAX_CONNECTIONS, DEFAULT_RPC_RETRIES,
+};
+use crate::burnchains::bitcoin::socks;
 use crate::burnchains::bitcoin::spv::*;
 use crate::burnchains::bitcoin::Error as btc_error;
-use crate::burnchains::db::BurnchainHeaderReader;
+use crate::burnchains::bitcoin::{PeerContext, PeerError};
+use crate::burnchains::db::{BurnchainDB, BurnchainHeaderReader};
 use crate::burnchains::indexer::BurnchainIndexer;
 use crate::burnchains::indexer::*;
 use crate::burnchains::Burnchain;
 use crate::util_lib::db::Error as DBError;
 
 use crate::burnchains::bitcoin::blocks::{BitcoinBlockDownloader, BitcoinBlockParser};
-use crate::burnchains::bitcoin::BitcoinNetworkType;
+use crate::burnchains::bitcoin::{BitcoinBlock, BitcoinNetworkType};
 
 use crate::burnchains::BurnchainBlockHeader;
+use crate::burnchains::DryRunReport;
 use crate::burnchains::Error as burnchain_error;
 use crate::burnchains::MagicBytes;
 use crate::burnchains::BLOCKSTACK_MAGIC_MAINNET;
+use crate::chainstate::coordinator::comm::CoordinatorChannels;
 use crate::types::chainstate::BurnchainHeaderHash;
 
 use stacks_common::deps_common::bitcoin::blockdata::block::{BlockHeader, LoneBlockHeader};
+use stacks_common::deps_common::bitcoin::blockdata::transaction::OutPoint;
 use stacks_common::deps_common::bitcoin::network::encodable::VarInt;
 use stacks_common::deps_common::bitcoin::network::message::NetworkMessage;
+use stacks_common::deps_common::bitcoin::network::serialize::deserialize;
 use stacks_common::deps_common::bitcoin::network::serialize::BitcoinHash;
 use stacks_common::deps_common::bitcoin::network::serialize::Error as btc_serialization_err;
 use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
 use stacks_common::util::get_epoch_time_secs;
+use stacks_common::util::hash::hex_bytes;
 use stacks_common::util::log;
+use stacks_common::util::uint::Uint256;
 
 use crate::core::{
     StacksEpoch, STACKS_EPOCHS_MAINNET, STACKS_EPOCHS_REGTEST, STACKS_EPOCHS_TESTNET,
@@ -64,10 +89,53 @@ pub const USER_AGENT: &'static str = "Stacks/2.1";
 pub const BITCOIN_MAINNET: u32 = 0xD9B4BEF9;
 pub const BITCOIN_TESTNET: u32 = 0x0709110B;
 pub const BITCOIN_REGTEST: u32 = 0xDAB5BFFA;
+pub const BITCOIN_SIGNET: u32 = 0x40CF030A;
 
 pub const BITCOIN_MAINNET_NAME: &'static str = "mainnet";
 pub const BITCOIN_TESTNET_NAME: &'static str = "testnet";
 pub const BITCOIN_REGTEST_NAME: &'static str = "regtest";
+pub const BITCOIN_SIGNET_NAME: &'static str = "signet";
+
+/// Oldest protocol version we'll sync from
+pub const MIN_PEER_PROTOCOL_VERSION: u32 = 70001;
+/// Service bit of a pruned peer, which only serves the blocks near its chain tip (BIP 159)
+pub const NODE_NETWORK_LIMITED: u64 = 1 << 10;
+/// Number of blocks up to its chain tip that a `NODE_NETWORK_LIMITED` peer serves
+pub const NODE_NETWORK_LIMITED_MIN_BLOCKS: u64 = 288;
+
+/// Default number of seconds to go without a headers announcement before we ask for new headers
+/// again (about three blocks)
+pub const DEFAULT_SENDHEADERS_GRACE: u64 = 30 * 60;
+
+/// Default number of peers to download header chunks from at once
+pub const DEFAULT_HEADER_SYNC_NUM_PEERS: usize = 4;
+/// Default minimum number of headers in a parallel-download chunk (one `headers` reply's worth)
+pub const DEFAULT_HEADER_SYNC_CHUNK_SIZE: u64 = 2000;
+/// Default number of seconds to wait for a `headers` reply before giving up on the peer
+pub const DEFAULT_HEADER_STALL_TIMEOUT: u64 = 60;
+/// Default number of blocks to have requested from the peer at once
+pub const DEFAULT_BLOCK_DOWNLOAD_WINDOW: usize = 8;
+/// Default number of threads to parse downloaded blocks on
+pub const DEFAULT_BLOCK_PARSE_THREADS: usize = 4;
+/// Default number of seconds to wait for a requested block before asking for it again
+pub const DEFAULT_BLOCK_REQUEST_TIMEOUT: u64 = 60;
+/// Default number of seconds to wait on the peer once a shutdown is requested
+pub const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 10;
+/// How often to check for a shutdown request while waiting on the peer
+pub const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Default number of seconds between sync progress log lines
+pub const DEFAULT_SYNC_PROGRESS_LOG_INTERVAL: u64 = 60;
+/// Default most stored blocks a reorg may take off the canonical chain before the sync halts for
+/// an operator to accept it
+pub const DEFAULT_MAX_REORG_DEPTH: u64 = 100;
+/// How far back `SyncStats::blocks_per_sec()` looks
+pub const SYNC_STATS_RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Number of invalid replies in a row after which we give up on a peer
+pub const MAX_INVALID_REPLIES: u64 = 3;
+/// Default number of seconds a read from or write to the peer's socket can block
+pub const DEFAULT_PEER_SOCKET_TIMEOUT: u64 = 300;
+/// Socket timeouts longer than this many seconds are allowed, but warned about
+pub const MAX_SANE_PEER_SOCKET_TIMEOUT: u64 = 60 * 60;
 
 // batch size for searching for a reorg
 // kept small since sometimes bitcoin will just send us one header at a time
@@ -81,6 +149,7 @@ pub fn network_id_to_bytes(network_id: BitcoinNetworkType) -> u32 {
         BitcoinNetworkType::Mainnet => BITCOIN_MAINNET,
         BitcoinNetworkType::Testnet => BITCOIN_TESTNET,
         BitcoinNetworkType::Regtest => BITCOIN_REGTEST,
+        BitcoinNetworkType::Signet => BITCOIN_SIGNET,
     }
 }
 
@@ -92,6 +161,7 @@ impl TryFrom<u32> for BitcoinNetworkType {
             BITCOIN_MAINNET => Ok(BitcoinNetworkType::Mainnet),
             BITCOIN_TESTNET => Ok(BitcoinNetworkType::Testnet),
             BITCOIN_REGTEST => Ok(BitcoinNetworkType::Regtest),
+            BITCOIN_SIGNET => Ok(BitcoinNetworkType::Signet),
             _ => Err("Invalid network type"),
         }
     }
@@ -103,7 +173,7 @@ impl TryFrom<u32> for BitcoinNetworkType {
 fn get_bitcoin_stacks_epochs(network_id: BitcoinNetworkType) -> Vec<StacksEpoch> {
     match network_id {
         BitcoinNetworkType::Mainnet => STACKS_EPOCHS_MAINNET.to_vec(),
-        BitcoinNetworkType::Testnet => STACKS_EPOCHS_TESTNET.to_vec(),
+        BitcoinNetworkType::Testnet | BitcoinNetworkType::Signet => STACKS_EPOCHS_TESTNET.to_vec(),
         BitcoinNetworkType::Regtest => STACKS_EPOCHS_REGTEST.to_vec(),
     }
 }
@@ -119,9 +189,615 @@ pub struct BitcoinIndexerConfig {
     pub password: Option<String>,
     pub timeout: u32,
     pub spv_headers_path: String,
+    /// Height of the first burn block, at which the burnchain DB starts.  Blocks below it are
+    /// never downloaded.  If it's above the chain tip, syncs only get headers until the chain
+    /// reaches it.
     pub first_block: u64,
+    /// Hash the header at `first_block` must have, if set.  Headers with a different hash at
+    /// that height are rejected with FirstBlockMismatch.  The node sets it to its network's
+    /// first block hash.
+    pub first_block_hash: Option<BurnchainHeaderHash>,
     pub magic_bytes: MagicBytes,
     pub epochs: Option<Vec<StacksEpoch>>,
+    /// Custom (height, block hash) header checkpoints.  If None, then the compiled-in checkpoints
+    /// for the network are used.  An empty list disables checkpoint validation.
+    pub checkpoints: Option<Vec<(u64, BurnchainHeaderHash)>>,
+    /// How long to wait between attempts to (re)connect to the peer
+    pub reconnect_policy: ReconnectPolicy,
+    /// How long to wait for a TCP connection to the peer to be established.  If None, the OS
+    /// decides.
+    pub connect_timeout: Option<Duration>,
+    /// Which of the peer's addresses to try connecting to first.  `peer_host` can be an IPv4
+    /// address, an IPv6 address (bracketed or not), or a host name, whose addresses are tried
+    /// in turn until one accepts a connection.
+    pub address_preference: AddressPreference,
+    /// If set, connect to the peer and to the JSON-RPC interface through this SOCKS5 proxy,
+    /// which resolves their host names for us (so they can be `.onion` addresses)
+    pub socks_proxy: Option<SocksProxy>,
+    /// How long a read from the peer's socket can block before failing
+    pub read_timeout: Duration,
+    /// How long a write to the peer's socket can block before failing
+    pub write_timeout: Duration,
+    /// Additional (host, port) peers to download headers from in parallel with
+    /// `peer_host:peer_port`.  If empty, headers are downloaded from `peer_host:peer_port` alone.
+    /// They're also the peers we fail over to, in order, when the current peer refuses
+    /// connections, times out, or keeps sending invalid replies (see
+    /// `BitcoinIndexer::fail_over_peer()`).
+    pub header_sync_peers: Vec<(String, u16)>,
+    /// How many of `peer_host:peer_port` and `header_sync_peers` to download headers from at
+    /// once.  The rest stand by to take over chunks that other peers fail to deliver.
+    pub header_sync_num_peers: usize,
+    /// Minimum number of headers per parallel-download chunk.  Chunks can only end at headers we
+    /// already trust (i.e. checkpoints), so checkpoints closer together than this are merged into
+    /// one chunk.
+    pub header_sync_chunk_size: u64,
+    /// If set, a batch of headers that takes longer than this to arrive while syncing headers
+    /// from `peer_host:peer_port` is given up on, and the sync carries on from whichever of
+    /// `header_sync_peers` has failed the fewest times (see
+    /// `BitcoinIndexer::switch_header_peer()`).  If None, we wait as long as the read timeout
+    /// allows, and then retry the same peer.
+    pub header_stall_timeout: Option<Duration>,
+    /// If set, watch the peer's mempool for burnchain transactions:  the peer is asked to relay
+    /// new transactions to us, and for its mempool after each handshake.  The burnchain
+    /// transactions found are kept apart from confirmed ones (see
+    /// `BitcoinIndexer::get_unconfirmed_txs()`) until they confirm or this long passes.
+    pub mempool_ttl: Option<Duration>,
+    /// If set, headers are pruned from the headers DB after each header sync (see
+    /// `SpvClient::prune_headers()`), except for the last `keep_headers` of them, the first
+    /// header of each difficulty interval, and the headers at checkpoint heights.  The pruned
+    /// headers are appended to the header archive if `archive` is set.  `max_height` must not
+    /// exceed the lowest height the burnchain needs headers for, such as `first_block`; leave it
+    /// unset only on nodes that just follow the chain tip.
+    pub header_pruning: Option<HeaderPruning>,
+    /// If nonzero, read headers through a memory map of up to this many bytes of the headers DB
+    /// (see `SpvClient::set_mmap_size()`).
+    pub spv_mmap_size: u64,
+    /// Output scripts that every burn operation we care about spends from or pays to (e.g. the
+    /// burn address).  If non-empty, and the peer serves BIP157 compact block filters, the
+    /// filters of new blocks are downloaded with their headers, for watch-only callers of
+    /// `block_may_contain_burn_ops()`.  Filters leave out OP_RETURN outputs, so they can't rule
+    /// out a burn operation for consensus:  the blocks `Burnchain::sync_with_indexer()`
+    /// processes are always downloaded in full.
+    pub filter_scripts: Vec<Vec<u8>>,
+    /// If set, talk to peers using this private network's magic bytes instead of those of
+    /// `runtime.network_id`
+    pub custom_network: Option<CustomNetworkParams>,
+    /// Height and hash of a header whose ancestors' proof-of-work is assumed valid, to speed up
+    /// the initial header sync (see `SpvClient::set_assume_valid()`).  If None, every header's
+    /// work is verified.  There is no default for any network.
+    pub assume_valid: Option<(u64, BurnchainHeaderHash)>,
+    /// If true, the proof-of-work of headers at or below the highest checkpoint is assumed valid
+    /// during the initial header sync (see `SpvClient::set_assume_valid_checkpoints()`).  Those
+    /// headers are still checked against the checkpoints.
+    pub assume_valid_checkpoints: bool,
+    /// If true, headers with versions that the soft forks buried by BIP90 (BIP34, BIP66, and
+    /// BIP65) rule out at their heights are rejected (see
+    /// `SpvClient::set_strict_header_versions()`).  Never enforced on regtest or signet.
+    pub strict_header_versions: bool,
+    /// If set, the headers DB of a private regtest network is seeded with this genesis block
+    /// instead of regtest's well-known one.  Not allowed on any other network.
+    pub custom_genesis: Option<CustomGenesisParams>,
+    /// If set, header sync starts from this trusted header (the "first burn block") instead of
+    /// the genesis block.  Headers (and so blocks) below its height are never available, so
+    /// `first_block` must not be below it.  Only used when the headers DB is created.
+    pub first_burn_block: Option<FirstBurnBlockParams>,
+    /// How many blocks to have requested from the peer at once while downloading blocks (see
+    /// `BitcoinBlockDownloader::run_window()`).  Blocks that arrive early are held until the
+    /// blocks before them arrive, so up to this many blocks can be in memory at once.
+    pub block_download_window: usize,
+    /// How many threads to parse downloaded blocks on.  Blocks are still handed to the burnchain
+    /// DB in height order, and at most `MAX_PENDING_BLOCKS_PER_PARSER` blocks per thread are
+    /// parsed ahead of the lowest block still being parsed (see `Burnchain::parse_blocks()`).
+    pub block_parse_threads: usize,
+    /// If nonzero, the signatures of the P2PKH inputs of each block's burnchain transactions are
+    /// checked on up to this many threads per block as it's parsed, and transactions whose
+    /// signatures don't verify are dropped (see `BitcoinBlockParser::set_sig_threads()`).  Only
+    /// for nodes that don't trust their peers to send valid blocks:  the spent outputs aren't
+    /// known, so an input that looks like a P2PKH spend but isn't one would be dropped too.
+    pub sig_verify_threads: usize,
+    /// How long to wait for a requested block before asking the peer for it (and only it) again
+    pub block_request_timeout: Duration,
+    /// Once a shutdown is requested (see `BitcoinIndexer::shutdown_handle()`), how much longer to
+    /// wait on the peer for the reply in progress before giving up on it
+    pub shutdown_timeout: Duration,
+    /// How often to log a one-line summary of the sync's progress (see `SyncStats`), if at all
+    pub sync_progress_log_interval: Option<Duration>,
+    /// If set, the most bytes per second to download blocks at (see `DownloadThrottle`).  Header
+    /// and other messages are never throttled.
+    pub block_download_rate_limit: Option<u64>,
+    /// How often to ping the peer while waiting for messages, and how long to wait for its pong
+    /// before giving up on the connection.  If None, the peer is never pinged.
+    pub keepalive: Option<KeepalivePolicy>,
+    /// If set, how fast to send requests to the peer, and how many to have awaiting replies at
+    /// once (see `RequestLimiter`)
+    pub request_limit: Option<RequestLimitPolicy>,
+    /// If set, and the peer supports it, ask the peer to announce new blocks by sending us their
+    /// headers, and take those announcements instead of asking for new headers on every header
+    /// sync.  We go back to asking whenever nothing has been announced for this long.  If None,
+    /// we always ask.
+    pub sendheaders_grace: Option<Duration>,
+    /// Where to get headers from when syncing them in `SyncMode::P2p`, where blocks are always
+    /// downloaded from the peer.  Ignored in `SyncMode::Rpc`.
+    pub header_source: HeaderSourceType,
+    /// Whether to sync from the bitcoin peer network, or only over the JSON-RPC interface
+    pub sync_mode: SyncMode,
+    /// Another bitcoind to get blocks from over JSON-RPC when the one at `peer_host:rpc_port`
+    /// has pruned them (see `BitcoinRpcClient::set_pruned_block_fallback()`)
+    pub pruned_block_fallback: Option<RpcEndpoint>,
+    /// Most connections to have open to the JSON-RPC interface at once (see
+    /// `BitcoinRpcClient::set_max_connections()`)
+    pub rpc_max_connections: usize,
+    /// How many times to retry a JSON-RPC call that failed in transit
+    pub rpc_retries: u32,
+    /// Make synced headers durable every this many headers, or every `header_fsync_period`,
+    /// whichever comes first (see `SpvClient::set_fsync_interval()`).  A crash can lose the
+    /// header batches since the last sync, but never part of a batch.
+    pub header_fsync_interval: u64,
+    pub header_fsync_period: Duration,
+    /// Outputs whose spends the bloom filter of a filtered block download also matches (see
+    /// `BitcoinBlockDownloader::new_filtered()`).  Compact filters can't match outpoints, so
+    /// these only add to what filtered blocks match.
+    pub filter_outpoints: Vec<OutPoint>,
+    /// False-positive rate to size the bloom filter for (see `bloom::Bip37Filter::new()`).  A
+    /// lower rate means fewer unwanted transactions but a bigger filter, and tells the peer
+    /// more about what we're watching.
+    pub bloom_filter_fp_rate: f64,
+    /// Largest payload to accept in a message from the peer, other than a block.  A message
+    /// claiming a bigger one is refused before we make room for it, and the peer is treated as
+    /// having sent an invalid reply (see `messages::read_message()`).
+    pub max_message_size: u32,
+    /// Largest payload to accept in a `block` message from the peer
+    pub max_block_size: u32,
+    /// If true, `Burnchain::sync_with_indexer()` only checks the blocks it downloads:  their
+    /// burn operations are extracted and validated as usual, but what was found goes to a
+    /// `DryRunReport` (see `take_dry_run_report()`) instead of the burnchain DB.  Headers are
+    /// still synced, since they don't affect consensus.
+    pub dry_run: bool,
+    /// If true, `Burnchain::sync_with_indexer()` drops the burnchain DB's blocks above a reorg's
+    /// fork, instead of keeping them alongside the new branch's (see
+    /// `Burnchain::invalidate_reorged_blocks()`).  Off by default.
+    pub drop_reorged_blocks: bool,
+    /// If set, a reorg that would take more than this many stored blocks off the canonical
+    /// chain halts `Burnchain::sync_with_indexer()`, leaving the burnchain DB as it was, until an
+    /// operator accepts it with `BitcoinIndexer::accept_deep_reorg()`.  If None, reorgs of any
+    /// depth are processed.
+    pub max_reorg_depth: Option<u64>,
+}
+
+/// Where the indexer gets headers from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderSourceType {
+    /// Ask the peer at `peer_host:peer_port` with `getheaders`
+    Peer,
+    /// Page through the headers with `getblockhash` and `getblockheader` over the JSON-RPC
+    /// interface at `peer_host:rpc_port`
+    Rpc,
+}
+
+/// How the indexer talks to bitcoin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncMode {
+    /// Download blocks from the peer at `peer_host:peer_port`, and headers from
+    /// `header_source`
+    P2p,
+    /// Never connect to the peer network.  Headers come from `getblockhash` and
+    /// `getblockheader`, and blocks from `getblock`, over the JSON-RPC interface at
+    /// `peer_host:rpc_port`, and are validated just like those from a peer.  There is no
+    /// mempool watch, compact filter sync or parallel header sync in this mode.
+    Rpc,
+}
+
+/// Which of a peer's addresses to try first, when its host name resolves to several
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressPreference {
+    /// In the order the resolver gives them
+    System,
+    /// IPv4 addresses before IPv6 addresses
+    Ipv4First,
+    /// IPv6 addresses before IPv4 addresses
+    Ipv6First,
+}
+
+/// Where to reach a bitcoind's JSON-RPC interface
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcEndpoint {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// A SOCKS5 proxy (e.g. Tor) to make outbound connections through
+#[derive(Debug, Clone, PartialEq)]
+pub struct SocksProxy {
+    pub host: String,
+    pub port: u16,
+    /// Credentials to authenticate to the proxy with, if it wants them
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Parameters of a private bitcoin network whose nodes use non-default magic bytes (e.g. a
+/// private regtest network).  Everything else -- genesis block, difficulty rules, checkpoints,
+/// epochs -- still comes from the indexer's `BitcoinNetworkType`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomNetworkParams {
+    /// Message start bytes, as a little-endian u32 (like `BITCOIN_REGTEST`)
+    pub magic: u32,
+    /// P2P port the network's nodes listen on by default
+    pub default_port: u16,
+    /// Version byte of the network's p2pkh addresses
+    pub p2pkh_version: u8,
+    /// Version byte of the network's p2sh addresses
+    pub p2sh_version: u8,
+}
+
+impl CustomNetworkParams {
+    /// Get the version byte of this network's legacy addresses of the given type
+    pub fn legacy_address_version(&self, addrtype: LegacyBitcoinAddressType) -> u8 {
+        match addrtype {
+            LegacyBitcoinAddressType::PublicKeyHash => self.p2pkh_version,
+            LegacyBitcoinAddressType::ScriptHash => self.p2sh_version,
+        }
+    }
+
+    /// Encode a legacy address the way this network's nodes expect
+    pub fn to_b58(&self, addr: &LegacyBitcoinAddress) -> String {
+        addr.to_b58_with_version(self.legacy_address_version(addr.addrtype))
+    }
+}
+
+/// Genesis block of a private regtest network that doesn't use regtest's well-known genesis block
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomGenesisParams {
+    /// The 80-byte genesis block header, hex-encoded
+    pub header_hex: String,
+    /// Hash of the genesis block, which the header must hash to
+    pub first_block_hash: BurnchainHeaderHash,
+}
+
+/// Trusted header to start syncing headers from instead of the genesis block (see
+/// `SpvClient::init_from_anchor()`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirstBurnBlockParams {
+    /// Height of the header.  Must be the first height of a difficulty adjustment interval.
+    pub height: u64,
+    /// The 80-byte header, hex-encoded
+    pub header_hex: String,
+    /// Hash of the header, which the header must hash to
+    pub block_hash: BurnchainHeaderHash,
+    /// Total work of the chain up to and including the header
+    pub chain_work: Uint256,
+}
+
+/// Exponential backoff policy for reconnecting to the bitcoin peer.
+/// The n-th consecutive failed attempt is followed by a delay drawn uniformly from
+/// [0, min(max_delay, base_delay * multiplier^n)] ("full jitter").  The backoff starts over
+/// once a new connection gets as far as exchanging a message after the handshake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// A connection that stays up at least this long resets the backoff to `base_delay`
+    pub reset_threshold: Duration,
+    /// Give up with TooManyReconnects once this many attempts in a row have failed.  0 means
+    /// never give up.
+    pub max_attempts: u32,
+}
+
+/// Reconnection backoff state, carried across connection attempts
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectBackoff {
+    /// Number of consecutive failed or dropped connections
+    pub attempts: u32,
+    /// Upper bound of the most recent delay
+    pub ceiling: Duration,
+    /// The most recent delay
+    pub last_delay: Duration,
+    /// When the current connection was established, if we're connected
+    pub connected_since: Option<Instant>,
+    /// Whether the connection dropped or a connection attempt failed since the last delay, so
+    /// the next connection attempt has to wait first
+    pub reconnect_pending: bool,
+}
+
+/// Keepalive policy for the connection to the bitcoin peer.
+/// While we wait for a message, the peer is sent a `ping` with a random nonce every `interval`.
+/// If the matching `pong` doesn't arrive within `timeout`, the connection is considered broken.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeepalivePolicy {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+/// Limits on the requests (`getdata` and `getheaders`) we send a peer, so that a fast sync
+/// doesn't flood it
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestLimitPolicy {
+    /// Most requests to send per second, on average
+    pub requests_per_sec: u64,
+    /// Most requests to send in a row without waiting, after not sending any for a while
+    pub burst: u64,
+    /// Most requests to have awaiting replies at once
+    pub max_in_flight: usize,
+}
+
+/// The last burnchain block that's been fully processed (see `BitcoinIndexer::get_burn_tip()`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BurnTip {
+    pub block_height: u64,
+    pub block_hash: BurnchainHeaderHash,
+    pub parent_block_hash: BurnchainHeaderHash,
+    /// The block's timestamp, in seconds since the epoch
+    pub timestamp: u64,
+}
+
+impl BurnTip {
+    /// Get the last fully processed block in `burnchain_db`
+    pub fn from_db(burnchain_db: &BurnchainDB) -> Result<BurnTip, burnchain_error> {
+        let (block_height, block_hash) = burnchain_db.get_processed_block()?;
+        let header = match block_hash {
+            Some(block_hash) => {
+                BurnchainDB::get_burnchain_block(burnchain_db.conn(), &block_hash)?.header
+            }
+            // only DBs written before the processed block's hash was recorded lack it
+            None => BurnchainDB::get_burnchain_header(burnchain_db.conn(), block_height)?
+                .ok_or(burnchain_error::MissingHeaders)?,
+        };
+        Ok(BurnTip {
+            block_height: header.block_height,
+            block_hash: header.block_hash,
+            parent_block_hash: header.parent_block_hash,
+            timestamp: header.timestamp,
+        })
+    }
+}
+
+/// Something that happened while the indexer synced, as reported to its `SyncObserver`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncEvent {
+    /// We connected and shook hands with a peer, whose chain tip is at `block_height`
+    PeerConnected {
+        host: String,
+        port: u16,
+        block_height: u64,
+    },
+    /// We dropped our connection to a peer
+    PeerDisconnected { host: String, port: u16 },
+    /// We're waiting `backoff.last_delay` before reconnecting to a peer, after
+    /// `backoff.attempts` failed or dropped connections in a row
+    ReconnectBackoff {
+        host: String,
+        port: u16,
+        backoff: ReconnectBackoff,
+    },
+    /// Our highest header is now `hash` at `height`.  `reorg_depth` is how many headers were
+    /// rolled back to switch to its branch, or 0 if it just extends our chain.
+    HeaderTipAdvanced {
+        height: u64,
+        hash: BurnchainHeaderHash,
+        reorg_depth: u64,
+    },
+    /// Our headers reorged, and the highest header in common with the old branch is at
+    /// `fork_height`
+    ReorgDetected { fork_height: u64 },
+    /// A reorg took the burnchain DB's blocks at heights `first_height` through `last_height`
+    /// off the canonical chain (and dropped them, if `drop_reorged_blocks` is set).  Burn
+    /// operations in them no longer count; the new branch's blocks at these heights get
+    /// processed in their place.
+    BurnBlocksInvalidated { first_height: u64, last_height: u64 },
+    /// The burnchain DB processed block `hash` at `height` (or a dry run checked it).
+    /// `block_time` is the time in its header.
+    BlockProcessed {
+        height: u64,
+        hash: BurnchainHeaderHash,
+        block_time: u32,
+    },
+    /// A dry run sync finished checking blocks, and found `report` in them
+    DryRunFinished { report: DryRunReport },
+}
+
+/// Receives `SyncEvent`s from the indexer as they happen, e.g. to drive a metrics exporter or a
+/// progress bar.  Called on the syncing thread, so it shouldn't block for long.
+pub trait SyncObserver: Send + Sync {
+    /// `timestamp` is when the event happened, in seconds since the epoch
+    fn on_sync_event(&self, event: &SyncEvent, timestamp: u64);
+}
+
+impl<F> SyncObserver for F
+where
+    F: Fn(&SyncEvent, u64) + Send + Sync,
+{
+    fn on_sync_event(&self, event: &SyncEvent, timestamp: u64) {
+        self(event, timestamp)
+    }
+}
+
+impl fmt::Debug for dyn SyncObserver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SyncObserver")
+    }
+}
+
+/// How far along the indexer's sync is, and how fast it's going.  Kept up to date by the
+/// indexer and its `dup()`s as they sync, and readable from any thread via
+/// `BitcoinIndexer::sync_stats_handle()`.
+#[derive(Debug, Clone)]
+pub struct SyncStats {
+    /// Height of our highest header
+    pub headers_height: u64,
+    /// Height of the highest block the burnchain DB has processed
+    pub blocks_height: u64,
+    /// Our best guess at the height of the peer's chain tip:  the height in its version
+    /// message, plus the blocks it has announced since
+    pub remote_tip_height: u64,
+    /// Total size of the messages we've received from peers, and of the blocks we've gotten
+    /// over JSON-RPC
+    pub bytes_downloaded: u64,
+    /// Host and port of the peer we're connected to, if any
+    pub peer: Option<(String, u16)>,
+    /// When we got each block within the last `SYNC_STATS_RATE_WINDOW`
+    recent_blocks: VecDeque<Instant>,
+    /// How often to log a progress summary, if at all
+    log_interval: Option<Duration>,
+    /// When we last logged a progress summary
+    last_logged: Instant,
+}
+
+impl SyncStats {
+    pub fn new(log_interval: Option<Duration>) -> SyncStats {
+        SyncStats {
+            headers_height: 0,
+            blocks_height: 0,
+            remote_tip_height: 0,
+            bytes_downloaded: 0,
+            peer: None,
+            recent_blocks: VecDeque::new(),
+            log_interval,
+            last_logged: Instant::now(),
+        }
+    }
+
+    /// Average number of blocks processed per second over the last `SYNC_STATS_RATE_WINDOW`
+    pub fn blocks_per_sec(&self) -> f64 {
+        let num_blocks = self
+            .recent_blocks
+            .iter()
+            .filter(|got_at| got_at.elapsed() < SYNC_STATS_RATE_WINDOW)
+            .count();
+        num_blocks as f64 / SYNC_STATS_RATE_WINDOW.as_secs_f64()
+    }
+
+    /// Log a progress summary every `interval`, or never if it's None
+    pub fn set_log_interval(&mut self, interval: Option<Duration>) {
+        self.log_interval = interval;
+    }
+
+    /// Note that our highest header is now at `height`
+    pub fn record_header_tip(&mut self, height: u64) {
+        self.headers_height = height;
+        self.remote_tip_height = cmp::max(self.remote_tip_height, height);
+        self.maybe_log();
+    }
+
+    /// Note that the peer announced `num_blocks` new blocks, each of which extends its chain
+    pub fn record_block_announcements(&mut self, num_blocks: u64) {
+        self.remote_tip_height = cmp::max(self.remote_tip_height, self.headers_height) + num_blocks;
+        self.maybe_log();
+    }
+
+    /// Note that we received a `num_bytes`-byte message from the peer
+    pub fn record_bytes_downloaded(&mut self, num_bytes: u64) {
+        self.bytes_downloaded += num_bytes;
+        self.maybe_log();
+    }
+
+    /// Update the stats with what `event` tells us
+    pub fn record_event(&mut self, event: &SyncEvent) {
+        match event {
+            SyncEvent::PeerConnected {
+                host,
+                port,
+                block_height,
+            } => {
+                self.peer = Some((host.clone(), *port));
+                self.remote_tip_height = cmp::max(self.remote_tip_height, *block_height);
+            }
+            SyncEvent::PeerDisconnected { .. } => {
+                self.peer = None;
+            }
+            SyncEvent::ReconnectBackoff { .. } => {}
+            SyncEvent::HeaderTipAdvanced { height, .. } => {
+                self.record_header_tip(*height);
+            }
+            SyncEvent::ReorgDetected { .. } => {}
+            SyncEvent::BlockProcessed { height, .. } => {
+                let now = Instant::now();
+                self.blocks_height = cmp::max(self.blocks_height, *height);
+                self.recent_blocks.push_back(now);
+                while let Some(got_at) = self.recent_blocks.front() {
+                    if now.duration_since(*got_at) < SYNC_STATS_RATE_WINDOW {
+                        break;
+                    }
+                    self.recent_blocks.pop_front();
+                }
+            }
+            SyncEvent::BurnBlocksInvalidated { first_height, .. } => {
+                self.blocks_height = cmp::min(self.blocks_height, first_height.saturating_sub(1));
+            }
+            SyncEvent::DryRunFinished { .. } => {}
+        }
+        self.maybe_log();
+    }
+
+    /// Log a progress summary if it's been `log_interval` since the last one
+    fn maybe_log(&mut self) {
+        match self.log_interval {
+            Some(interval) if self.last_logged.elapsed() >= interval => {
+                info!("Burnchain sync progress: {}", self);
+                self.last_logged = Instant::now();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for SyncStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "headers at {} of ~{}, blocks at {} ({:.2} blocks/s), {} bytes downloaded, peer ",
+            self.headers_height,
+            self.remote_tip_height,
+            self.blocks_height,
+            self.blocks_per_sec(),
+            self.bytes_downloaded
+        )?;
+        match self.peer {
+            Some((ref host, port)) => write!(f, "{}:{}", host, port),
+            None => write!(f, "none"),
+        }
+    }
+}
+
+/// Token bucket that holds downloads to `rate` bytes per second on average.  Up to a second's
+/// worth of bytes can be downloaded in a burst after a lull.  Shared by an indexer and its
+/// `dup()`s, so that the limit covers all of their downloads together.
+#[derive(Debug)]
+pub struct DownloadThrottle {
+    /// Bytes per second
+    rate: u64,
+    /// Bytes we can download right away.  Negative if we've downloaded more than we could, in
+    /// which case we're in debt until the bucket refills.
+    tokens: f64,
+    /// When `tokens` was last topped up
+    last_refill: Instant,
+}
+
+impl DownloadThrottle {
+    pub fn new(rate: u64) -> DownloadThrottle {
+        let rate = cmp::max(rate, 1);
+        DownloadThrottle {
+            rate,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take `num_bytes` out of the bucket, and return how long to wait before downloading any
+    /// more so as to stay under the rate limit
+    pub fn consume(&mut self, num_bytes: u64) -> Duration {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * self.rate as f64;
+        self.tokens = (self.tokens + refill).min(self.rate as f64) - num_bytes as f64;
+        self.last_refill = now;
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -135,6 +811,88 @@ pub struct BitcoinIndexerRuntime {
     pub last_getdata_send_time: u64,
     pub last_getheaders_send_time: u64,
     pub timeout: u64,
+    pub backoff: ReconnectBackoff,
+    /// Lowest fork height of any header reorg encountered while syncing headers, not yet
+    /// reported via `take_headers_reorg_height()`
+    pub headers_reorg_height: Option<u64>,
+    /// Receives header sync progress reports, and how many headers apart they are
+    pub header_sync_progress: Option<(Arc<dyn HeaderSyncProgress>, u64)>,
+    /// Receives sync events, if set (see `BitcoinIndexer::set_sync_observer()`)
+    pub sync_observer: Option<Arc<dyn SyncObserver>>,
+    /// Supplies the credentials to authenticate to bitcoind's JSON-RPC interface with, if set
+    /// (see `BitcoinIndexer::set_rpc_credentials()`)
+    pub rpc_credentials: Option<Arc<dyn RpcCredentials>>,
+    /// Receive the burnchain events of our syncs (see `BitcoinIndexer::add_event_observer()`)
+    pub event_observers: BurnchainEventObservers,
+    /// Services advertised by the peer in its version message
+    pub peer_services: u64,
+    /// Protocol version negotiated with the peer (the lower of its version and ours), or 0 if
+    /// we haven't shaken hands yet
+    pub peer_version: u32,
+    /// Nonce of the ping we're waiting on a pong for, and when we sent it
+    pub outstanding_ping: Option<(u64, Instant)>,
+    /// When we last sent a ping (or connected to the peer)
+    pub last_ping_time: Instant,
+    /// When we sent `sendheaders` on the current connection, if we did
+    pub sendheaders_sent_time: Option<Instant>,
+    /// When the peer last announced headers on the current connection, if it did
+    pub last_headers_announcement_time: Option<Instant>,
+    /// Set once the peer has ignored a `sendheaders` for longer than `sendheaders_grace`, so we
+    /// stop sending it
+    pub sendheaders_ignored: bool,
+    /// How many times each peer has stalled or dropped out while we synced headers from it
+    pub peer_failures: HashMap<(String, u16), u64>,
+    /// The address each peer last accepted a connection at, which is tried first when we
+    /// reconnect to it (or alone, if its host name doesn't resolve any more)
+    pub peer_addrs: HashMap<(String, u16), net::SocketAddr>,
+    /// Which peer we're connected to, or will connect to next:  0 for `peer_host:peer_port`, or
+    /// else 1 + its index in `header_sync_peers` (see `BitcoinIndexer::current_peer()`)
+    pub peer_index: usize,
+    /// Number of times we've failed over to another peer since the last successful handshake
+    pub failovers: u64,
+    /// Number of invalid replies in a row from the current peer
+    pub invalid_replies: u64,
+    /// Number of `peer_communicate()` calls so far, i.e. the current one's request ID
+    pub request_id: u64,
+    /// Context of the last error `peer_communicate()` failed with, taken by
+    /// `with_peer_context()`
+    pub error_context: Option<PeerContext>,
+    /// Number of peers in a row we've failed over from because they lacked a block we needed
+    pub pruned_failovers: u64,
+    /// The bloom filter we've loaded into the current peer, as updated since, if any.  Once
+    /// it's loaded, filtered block downloads ask the peer for filtered blocks (see
+    /// `BitcoinIndexer::load_bloom_filter()`).
+    pub bloom_filter: Option<Bip37Filter>,
+    /// The burnchain transactions in the peer's mempool, if `mempool_ttl` is set.  Created the
+    /// first time the peer sends us mempool traffic.
+    pub mempool: Option<MempoolWatcher>,
+    /// Holds our requests to the current peer to `request_limit`, if set
+    pub request_limiter: Option<RequestLimiter>,
+    /// Subscribers to the tip updates of the headers DB (see `BitcoinIndexer::subscribe_tip()`).
+    /// Locked only so that the indexer can be shared between threads.
+    tip_subscribers: Mutex<Vec<Sender<TipUpdate>>>,
+    /// Set to stop syncing at the next safe point (see `BitcoinIndexer::shutdown_handle()`).
+    /// Shared with this indexer's `dup()`s.
+    shutdown: Arc<AtomicBool>,
+    /// When this indexer gives up waiting on its peer, once it has seen that a shutdown was
+    /// requested (see `BitcoinIndexer::check_shutdown_deadline()`)
+    shutdown_deadline: Option<Instant>,
+    /// How far along the sync is (see `BitcoinIndexer::sync_stats_handle()`).  Shared with this
+    /// indexer's `dup()`s.
+    sync_stats: Arc<Mutex<SyncStats>>,
+    /// Holds block downloads to `block_download_rate_limit`, if set.  Shared with this indexer's
+    /// `dup()`s.
+    download_throttle: Option<Arc<Mutex<DownloadThrottle>>>,
+    /// How long this indexer has spent waiting on `download_throttle` in all
+    throttled_time: Duration,
+    /// What the last dry run sync found, if `dry_run` is set and it hasn't been taken yet
+    dry_run_report: Option<DryRunReport>,
+    /// Set if an operator accepted the next reorg deeper than `max_reorg_depth` (see
+    /// `BitcoinIndexer::accept_deep_reorg()`).  Shared with this indexer's `dup()`s.
+    deep_reorg_accepted: Arc<AtomicBool>,
+    /// The burnchain DB's canonical tip as of the current sync (see
+    /// `BurnchainIndexer::set_burnchain_tip()`), which reorgs are measured against
+    burnchain_tip: Option<BurnchainTip>,
 }
 
 pub struct BitcoinIndexer {
@@ -154,8 +912,56 @@ impl BitcoinIndexerConfig {
             timeout: 30,
             spv_headers_path: "./headers.sqlite".to_string(),
             first_block,
+            first_block_hash: None,
             magic_bytes: BLOCKSTACK_MAGIC_MAINNET.clone(),
             epochs: None,
+            checkpoints: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            connect_timeout: None,
+            address_preference: AddressPreference::System,
+            socks_proxy: None,
+            read_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+            write_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+            header_sync_peers: vec![],
+            header_sync_num_peers: DEFAULT_HEADER_SYNC_NUM_PEERS,
+            header_sync_chunk_size: DEFAULT_HEADER_SYNC_CHUNK_SIZE,
+            header_stall_timeout: Some(Duration::from_secs(DEFAULT_HEADER_STALL_TIMEOUT)),
+            mempool_ttl: None,
+            header_pruning: None,
+            spv_mmap_size: 0,
+            filter_scripts: vec![],
+            custom_network: None,
+            assume_valid: None,
+            assume_valid_checkpoints: false,
+            strict_header_versions: true,
+            custom_genesis: None,
+            first_burn_block: None,
+            block_download_window: DEFAULT_BLOCK_DOWNLOAD_WINDOW,
+            block_parse_threads: DEFAULT_BLOCK_PARSE_THREADS,
+            sig_verify_threads: 0,
+            block_request_timeout: Duration::from_secs(DEFAULT_BLOCK_REQUEST_TIMEOUT),
+            shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT),
+            sync_progress_log_interval: Some(Duration::from_secs(
+                DEFAULT_SYNC_PROGRESS_LOG_INTERVAL,
+            )),
+            block_download_rate_limit: None,
+            keepalive: Some(KeepalivePolicy::default()),
+            sendheaders_grace: Some(Duration::from_secs(DEFAULT_SENDHEADERS_GRACE)),
+            header_source: HeaderSourceType::Peer,
+            sync_mode: SyncMode::P2p,
+            pruned_block_fallback: None,
+            request_limit: None,
+            rpc_max_connections: DEFAULT_RPC_MAX_CONNECTIONS,
+            rpc_retries: DEFAULT_RPC_RETRIES,
+            header_fsync_interval: DEFAULT_HEADER_FSYNC_INTERVAL,
+            header_fsync_period: DEFAULT_HEADER_FSYNC_PERIOD,
+            filter_outpoints: vec![],
+            bloom_filter_fp_rate: DEFAULT_BLOOM_FP_RATE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+            dry_run: false,
+            drop_reorged_blocks: false,
+            max_reorg_depth: Some(DEFAULT_MAX_REORG_DEPTH),
         }
     }
 
@@ -170,11 +976,71 @@ impl BitcoinIndexerConfig {
             timeout: 30,
             spv_headers_path: spv_headers_path,
             first_block: 0,
+            first_block_hash: None,
             magic_bytes: BLOCKSTACK_MAGIC_MAINNET.clone(),
             epochs: None,
+            checkpoints: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            connect_timeout: None,
+            address_preference: AddressPreference::System,
+            socks_proxy: None,
+            read_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+            write_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+            header_sync_peers: vec![],
+            header_sync_num_peers: DEFAULT_HEADER_SYNC_NUM_PEERS,
+            header_sync_chunk_size: DEFAULT_HEADER_SYNC_CHUNK_SIZE,
+            header_stall_timeout: Some(Duration::from_secs(DEFAULT_HEADER_STALL_TIMEOUT)),
+            mempool_ttl: None,
+            header_pruning: None,
+            spv_mmap_size: 0,
+            filter_scripts: vec![],
+            custom_network: None,
+            assume_valid: None,
+            assume_valid_checkpoints: false,
+            strict_header_versions: false,
+            custom_genesis: None,
+            first_burn_block: None,
+            block_download_window: DEFAULT_BLOCK_DOWNLOAD_WINDOW,
+            block_parse_threads: DEFAULT_BLOCK_PARSE_THREADS,
+            sig_verify_threads: 0,
+            block_request_timeout: Duration::from_secs(DEFAULT_BLOCK_REQUEST_TIMEOUT),
+            shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT),
+            sync_progress_log_interval: Some(Duration::from_secs(
+                DEFAULT_SYNC_PROGRESS_LOG_INTERVAL,
+            )),
+            block_download_rate_limit: None,
+            keepalive: Some(KeepalivePolicy::default()),
+            sendheaders_grace: Some(Duration::from_secs(DEFAULT_SENDHEADERS_GRACE)),
+            header_source: HeaderSourceType::Peer,
+            sync_mode: SyncMode::P2p,
+            pruned_block_fallback: None,
+            request_limit: None,
+            rpc_max_connections: DEFAULT_RPC_MAX_CONNECTIONS,
+            rpc_retries: DEFAULT_RPC_RETRIES,
+            header_fsync_interval: DEFAULT_HEADER_FSYNC_INTERVAL,
+            header_fsync_period: DEFAULT_HEADER_FSYNC_PERIOD,
+            filter_outpoints: vec![],
+            bloom_filter_fp_rate: DEFAULT_BLOOM_FP_RATE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+            dry_run: false,
+            drop_reorged_blocks: false,
+            max_reorg_depth: Some(DEFAULT_MAX_REORG_DEPTH),
         }
     }
 
+    /// Config for a private regtest network with its own magic bytes, whose peer listens on
+    /// the network's default port on localhost
+    pub fn custom_regtest(
+        spv_headers_path: String,
+        params: CustomNetworkParams,
+    ) -> BitcoinIndexerConfig {
+        let mut config = BitcoinIndexerConfig::default_regtest(spv_headers_path);
+        config.peer_port = params.default_port;
+        config.custom_network = Some(params);
+        config
+    }
+
     #[cfg(test)]
     pub fn test_default(spv_headers_path: String) -> BitcoinIndexerConfig {
         BitcoinIndexerConfig {
@@ -187,9 +1053,87 @@ impl BitcoinIndexerConfig {
             timeout: 30,
             spv_headers_path,
             first_block: 0,
+            first_block_hash: None,
             magic_bytes: BLOCKSTACK_MAGIC_MAINNET.clone(),
             epochs: None,
+            checkpoints: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            connect_timeout: None,
+            address_preference: AddressPreference::System,
+            socks_proxy: None,
+            read_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+            write_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+            header_sync_peers: vec![],
+            header_sync_num_peers: DEFAULT_HEADER_SYNC_NUM_PEERS,
+            header_sync_chunk_size: DEFAULT_HEADER_SYNC_CHUNK_SIZE,
+            header_stall_timeout: None,
+            mempool_ttl: None,
+            header_pruning: None,
+            spv_mmap_size: 0,
+            filter_scripts: vec![],
+            custom_network: None,
+            assume_valid: None,
+            assume_valid_checkpoints: false,
+            strict_header_versions: false,
+            custom_genesis: None,
+            first_burn_block: None,
+            block_download_window: DEFAULT_BLOCK_DOWNLOAD_WINDOW,
+            block_parse_threads: DEFAULT_BLOCK_PARSE_THREADS,
+            sig_verify_threads: 0,
+            block_request_timeout: Duration::from_secs(DEFAULT_BLOCK_REQUEST_TIMEOUT),
+            shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT),
+            sync_progress_log_interval: Some(Duration::from_secs(
+                DEFAULT_SYNC_PROGRESS_LOG_INTERVAL,
+            )),
+            block_download_rate_limit: None,
+            keepalive: None,
+            sendheaders_grace: None,
+            header_source: HeaderSourceType::Peer,
+            sync_mode: SyncMode::P2p,
+            pruned_block_fallback: None,
+            request_limit: None,
+            rpc_max_connections: DEFAULT_RPC_MAX_CONNECTIONS,
+            rpc_retries: DEFAULT_RPC_RETRIES,
+            header_fsync_interval: DEFAULT_HEADER_FSYNC_INTERVAL,
+            header_fsync_period: DEFAULT_HEADER_FSYNC_PERIOD,
+            filter_outpoints: vec![],
+            bloom_filter_fp_rate: DEFAULT_BLOOM_FP_RATE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+            dry_run: false,
+            drop_reorged_blocks: false,
+            max_reorg_depth: Some(DEFAULT_MAX_REORG_DEPTH),
+        }
+    }
+
+    /// Check the peer socket timeouts.  A zero timeout is an error, since the OS would take it
+    /// to mean "block forever" (or reject it).  Timeouts longer than
+    /// `MAX_SANE_PEER_SOCKET_TIMEOUT` are allowed, but warned about.
+    pub fn check_socket_timeouts(&self) -> Result<(), btc_error> {
+        let timeouts = [
+            ("connect_timeout", self.connect_timeout),
+            ("read_timeout", Some(self.read_timeout)),
+            ("write_timeout", Some(self.write_timeout)),
+        ];
+        for (name, timeout) in timeouts.iter() {
+            let timeout = match timeout {
+                Some(timeout) => timeout,
+                None => continue,
+            };
+            if *timeout == Duration::from_secs(0) {
+                return Err(btc_error::ConfigError(format!(
+                    "Bitcoin peer {} must not be zero",
+                    name
+                )));
+            }
+            if *timeout > Duration::from_secs(MAX_SANE_PEER_SOCKET_TIMEOUT) {
+                warn!(
+                    "Bitcoin peer {} of {:?} is very long; a dead peer will go unnoticed for that long",
+                    name, timeout
+                );
+            }
         }
+        Ok(())
     }
 }
 
@@ -206,18 +1150,155 @@ impl BitcoinIndexerRuntime {
             last_getdata_send_time: 0,
             last_getheaders_send_time: 0,
             timeout: 300,
+            backoff: ReconnectBackoff::new(),
+            headers_reorg_height: None,
+            header_sync_progress: None,
+            sync_observer: None,
+            rpc_credentials: None,
+            event_observers: BurnchainEventObservers::default(),
+            peer_services: 0,
+            peer_version: 0,
+            outstanding_ping: None,
+            last_ping_time: Instant::now(),
+            sendheaders_sent_time: None,
+            last_headers_announcement_time: None,
+            sendheaders_ignored: false,
+            peer_failures: HashMap::new(),
+            peer_addrs: HashMap::new(),
+            peer_index: 0,
+            failovers: 0,
+            invalid_replies: 0,
+            request_id: 0,
+            error_context: None,
+            pruned_failovers: 0,
+            bloom_filter: None,
+            mempool: None,
+            request_limiter: None,
+            tip_subscribers: Mutex::new(vec![]),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            shutdown_deadline: None,
+            sync_stats: Arc::new(Mutex::new(SyncStats::new(None))),
+            download_throttle: None,
+            throttled_time: Duration::from_secs(0),
+            dry_run_report: None,
+            deep_reorg_accepted: Arc::new(AtomicBool::new(false)),
+            burnchain_tip: None,
+        }
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> ReconnectPolicy {
+        ReconnectPolicy {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            reset_threshold: Duration::from_secs(60),
+            max_attempts: 0,
+        }
+    }
+}
+
+impl Default for KeepalivePolicy {
+    fn default() -> KeepalivePolicy {
+        KeepalivePolicy {
+            interval: Duration::from_secs(120),
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ReconnectBackoff {
+    pub fn new() -> ReconnectBackoff {
+        ReconnectBackoff {
+            attempts: 0,
+            ceiling: Duration::from_secs(0),
+            last_delay: Duration::from_secs(0),
+            connected_since: None,
+            reconnect_pending: false,
+        }
+    }
+
+    /// Record that the connection to the peer dropped, or that a connection attempt failed, so
+    /// that the next connection attempt waits first (see `next_delay()`).  Recording it again
+    /// before then doesn't make the wait any longer.
+    pub fn reconnect_needed(&mut self) {
+        self.reconnect_pending = true;
+    }
+
+    /// Record that we're connected to the peer as of `now`
+    pub fn connected(&mut self, now: Instant) {
+        self.connected_since = Some(now);
+    }
+
+    /// Record that a message arrived from the peer.  Once that happens on a connection that got
+    /// through the handshake, the peer is back for real, and the backoff starts over from the
+    /// base delay.
+    pub fn message_received(&mut self) {
+        if self.connected_since.is_some() {
+            self.attempts = 0;
+        }
+    }
+
+    /// Have we failed to connect as many times in a row as the policy allows?
+    pub fn exhausted(&self, policy: &ReconnectPolicy) -> bool {
+        policy.max_attempts > 0 && self.attempts >= policy.max_attempts
+    }
+
+    /// Count a failed connection attempt or a dropped connection as of `now`, and get how long
+    /// to wait before trying again.  If the connection had stayed up for at least
+    /// the policy's reset threshold, the backoff starts over from the base delay.
+    pub fn next_delay<R: Rng>(
+        &mut self,
+        policy: &ReconnectPolicy,
+        now: Instant,
+        rng: &mut R,
+    ) -> Duration {
+        if let Some(connected_since) = self.connected_since.take() {
+            if now.saturating_duration_since(connected_since) >= policy.reset_threshold {
+                self.attempts = 0;
+            }
         }
+
+        let max_secs = policy.max_delay.as_secs_f64();
+        let ceiling_secs = (policy.base_delay.as_secs_f64()
+            * policy
+                .multiplier
+                .powi(cmp::min(self.attempts, i32::MAX as u32) as i32))
+        .min(max_secs);
+
+        self.attempts = self.attempts.saturating_add(1);
+        self.reconnect_pending = false;
+        self.ceiling = Duration::from_secs_f64(ceiling_secs);
+        self.last_delay = Duration::from_secs_f64(ceiling_secs * rng.gen::<f64>());
+        self.last_delay
     }
 }
 
 impl BitcoinIndexer {
-    pub fn new(config: BitcoinIndexerConfig, runtime: BitcoinIndexerRuntime) -> BitcoinIndexer {
+    pub fn new(config: BitcoinIndexerConfig, mut runtime: BitcoinIndexerRuntime) -> BitcoinIndexer {
+        runtime.download_throttle = config
+            .block_download_rate_limit
+            .map(|rate| Arc::new(Mutex::new(DownloadThrottle::new(rate))));
+        runtime
+            .sync_stats
+            .lock()
+            .expect("BUG: sync stats lock poisoned")
+            .set_log_interval(config.sync_progress_log_interval);
         BitcoinIndexer {
             config: config,
             runtime: runtime,
         }
     }
 
+    /// Get the magic bytes that frame every message to and from our peer
+    pub fn network_magic(&self) -> u32 {
+        match self.config.custom_network {
+            Some(ref params) => params.magic,
+            None => network_id_to_bytes(self.runtime.network_id),
+        }
+    }
+
     #[cfg(test)]
     pub fn new_unit_test(working_dir: &str) -> BitcoinIndexer {
         let mut working_dir_path = PathBuf::from(working_dir);
@@ -247,10 +1328,320 @@ impl BitcoinIndexer {
     }
 
     pub fn dup(&self) -> BitcoinIndexer {
+        let mut runtime = BitcoinIndexerRuntime::new(self.runtime.network_id);
+        runtime.header_sync_progress = self.runtime.header_sync_progress.clone();
+        runtime.sync_observer = self.runtime.sync_observer.clone();
+        runtime.rpc_credentials = self.runtime.rpc_credentials.clone();
+        runtime.event_observers = self.runtime.event_observers.clone();
+        runtime.shutdown = self.runtime.shutdown.clone();
+        runtime.sync_stats = self.runtime.sync_stats.clone();
+        runtime.download_throttle = self.runtime.download_throttle.clone();
+        runtime.deep_reorg_accepted = self.runtime.deep_reorg_accepted.clone();
+        runtime.peer_index = self.runtime.peer_index;
         BitcoinIndexer {
             config: self.config.clone(),
-            runtime: BitcoinIndexerRuntime::new(self.runtime.network_id),
+            runtime,
+        }
+    }
+
+    /// Report header sync progress to `handler` every `interval` headers.
+    pub fn set_header_sync_progress(
+        &mut self,
+        handler: Arc<dyn HeaderSyncProgress>,
+        interval: u64,
+    ) {
+        self.runtime.header_sync_progress = Some((handler, interval));
+    }
+
+    /// Report header tip changes, reorgs, downloaded blocks, and peer connections to `observer`
+    /// as they happen (see `SyncEvent`).
+    pub fn set_sync_observer(&mut self, observer: Arc<dyn SyncObserver>) {
+        self.runtime.sync_observer = Some(observer);
+    }
+
+    /// Authenticate to bitcoind's JSON-RPC interface (and to `pruned_block_fallback`) with the
+    /// credentials that `credentials` supplies.  Without any, RPC requests are unauthenticated.
+    pub fn set_rpc_credentials(&mut self, credentials: Arc<dyn RpcCredentials>) {
+        self.runtime.rpc_credentials = Some(credentials);
+    }
+
+    /// Report the burn blocks processed, reorgs handled, and syncs completed by
+    /// `Burnchain::sync_with_indexer()` to `observer`, after any observers added before it.
+    /// Only affects this indexer's syncs, and those of its `dup()`s made from now on.
+    pub fn add_event_observer(&mut self, observer: Box<dyn BurnchainEventObserver>) {
+        self.runtime.event_observers.add(observer);
+    }
+
+    /// Record the event built by `make_event` in the sync stats, and report it to the sync
+    /// observer, if there is one.
+    pub fn report_sync_event<F>(&self, make_event: F)
+    where
+        F: FnOnce() -> SyncEvent,
+    {
+        let event = make_event();
+        self.update_sync_stats(|stats| stats.record_event(&event));
+        if let Some(observer) = self.runtime.sync_observer.as_ref() {
+            observer.on_sync_event(&event, get_epoch_time_secs());
+        }
+    }
+
+    /// Get a handle on this indexer's `SyncStats`, which it (and its `dup()`s) keeps up to date
+    /// as it syncs, e.g. to show an operator how far along the sync is from another thread.
+    pub fn sync_stats_handle(&self) -> Arc<Mutex<SyncStats>> {
+        self.runtime.sync_stats.clone()
+    }
+
+    /// Get a snapshot of this indexer's `SyncStats`
+    pub fn sync_stats(&self) -> SyncStats {
+        self.runtime
+            .sync_stats
+            .lock()
+            .expect("BUG: sync stats lock poisoned")
+            .clone()
+    }
+
+    /// Apply `update` to this indexer's `SyncStats`
+    pub fn update_sync_stats<F>(&self, update: F)
+    where
+        F: FnOnce(&mut SyncStats),
+    {
+        let mut stats = self
+            .runtime
+            .sync_stats
+            .lock()
+            .expect("BUG: sync stats lock poisoned");
+        update(&mut *stats);
+    }
+
+    /// How long this indexer has spent holding block downloads to `block_download_rate_limit`.
+    /// Time spent throttled doesn't count towards any timeouts waiting on the peer.
+    pub fn throttled_time(&self) -> Duration {
+        self.runtime.throttled_time
+    }
+
+    /// Get a handle that stops this indexer's syncs once set.  Header syncs stop between header
+    /// batches, and `Burnchain::sync_with_indexer()` stops between block downloads, once the
+    /// blocks already downloaded are processed.  Either way, the sync returns the height it got
+    /// to, and picks up from there the next time.
+    pub fn shutdown_handle(&self) -> Arc<AtomicBool> {
+        self.runtime.shutdown.clone()
+    }
+
+    /// Take what the last dry run sync found, if `dry_run` is set (see
+    /// `BurnchainIndexer::dry_run()`)
+    pub fn take_dry_run_report(&mut self) -> Option<DryRunReport> {
+        self.runtime.dry_run_report.take()
+    }
+
+    /// Let the next sync process a reorg deeper than `max_reorg_depth`, which it would otherwise
+    /// refuse.  Only the next such reorg is accepted; a later one halts the sync again.
+    pub fn accept_deep_reorg(&self) {
+        self.runtime
+            .deep_reorg_accepted
+            .store(true, Ordering::SeqCst);
+    }
+
+    /// Lowest fork height a reorg may have before it takes more of the burnchain DB's blocks off
+    /// the canonical chain than `max_reorg_depth` allows.  None if there is no limit, or if an
+    /// operator accepted the next deep reorg (which the burnchain sync takes up once it has the
+    /// new headers).
+    fn reorg_floor(&self) -> Option<u64> {
+        if self.runtime.deep_reorg_accepted.load(Ordering::SeqCst) {
+            return None;
+        }
+        let max_depth = self.config.max_reorg_depth?;
+        let tip = self.runtime.burnchain_tip.as_ref()?;
+        Some(tip.0.saturating_sub(max_depth))
+    }
+
+    /// Convert a header sync's error for the burnchain sync:  a timeout is retried, and a reorg
+    /// that `reorg_floor()` kept out of the headers is refused the way
+    /// `Burnchain::sync_with_indexer()` refuses one.
+    fn to_sync_error(&self, error: btc_error) -> burnchain_error {
+        match (error, self.runtime.burnchain_tip.as_ref()) {
+            (btc_error::DeepReorg(fork_height), Some(tip)) => Burnchain::refuse_deep_reorg(
+                &self.runtime.event_observers,
+                tip,
+                fork_height,
+                self.config.max_reorg_depth.unwrap_or(0),
+            ),
+            (btc_error::TimedOut, _) => burnchain_error::TrySyncAgain,
+            (e, _) => burnchain_error::Bitcoin(e),
+        }
+    }
+
+    /// Stop syncing at the next safe point (see `shutdown_handle()`)
+    pub fn request_shutdown(&self) {
+        self.runtime.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown_requested(&self) -> bool {
+        self.runtime.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Once a shutdown has been requested, we only keep waiting on the peer (or reconnecting to
+    /// it) for `config.shutdown_timeout` more, so that a hung peer can't hold the shutdown up.
+    /// Fails with ShutdownRequested once that time is up.
+    pub fn check_shutdown_deadline(&mut self) -> Result<(), btc_error> {
+        if !self.is_shutdown_requested() {
+            return Ok(());
+        }
+        let deadline = *self
+            .runtime
+            .shutdown_deadline
+            .get_or_insert_with(|| Instant::now() + self.config.shutdown_timeout);
+        if Instant::now() >= deadline {
+            warn!(
+                "Shutdown requested; stop waiting on {}:{}",
+                self.peer_host(),
+                self.peer_port()
+            );
+            return Err(btc_error::ShutdownRequested);
+        }
+        Ok(())
+    }
+
+    /// Account for a `num_bytes`-byte block we just received, and wait as long as it takes to
+    /// get back under `block_download_rate_limit` (if set).  The wait isn't charged to any
+    /// timeout waiting on the peer:  the read timeout only starts once we go back to reading,
+    /// and the getdata timeout is pushed back by however long we waited.  Gives up with
+    /// ShutdownRequested if a shutdown was requested more than `shutdown_timeout` ago.
+    pub fn throttle_block_download(&mut self, num_bytes: u64) -> Result<(), btc_error> {
+        let wait = match self.runtime.download_throttle {
+            Some(ref throttle) => throttle
+                .lock()
+                .expect("BUG: download throttle lock poisoned")
+                .consume(num_bytes),
+            None => return Ok(()),
+        };
+        if wait == Duration::from_secs(0) {
+            return Ok(());
+        }
+
+        debug!("Throttle block download for {:?}", &wait);
+        let wait_start = Instant::now();
+        let res = loop {
+            if let Err(e) = self.check_shutdown_deadline() {
+                break Err(e);
+            }
+            let elapsed = wait_start.elapsed();
+            if elapsed >= wait {
+                break Ok(());
+            }
+            thread::sleep(cmp::min(wait - elapsed, SHUTDOWN_POLL_INTERVAL));
+        };
+
+        let waited = wait_start.elapsed();
+        self.runtime.throttled_time += waited;
+        if self.runtime.last_getdata_send_time > 0 {
+            self.runtime.last_getdata_send_time += waited.as_secs();
+        }
+        res
+    }
+
+    /// Get a `TipUpdate` every time header syncs accept a new best header (see
+    /// `SpvClient::subscribe_tip()`).  Dropping the receiver unsubscribes.
+    pub fn subscribe_tip(&mut self) -> Receiver<TipUpdate> {
+        let (sender, receiver) = channel();
+        self.runtime
+            .tip_subscribers
+            .get_mut()
+            .expect("BUG: tip subscriber lock poisoned")
+            .push(sender);
+        receiver
+    }
+
+    /// Get the header checkpoints to enforce while syncing.
+    /// Uses the custom checkpoints on the `BitcoinIndexerConfig`, if they exist, and the
+    /// compiled-in checkpoints for the network otherwise.
+    /// Fails if custom checkpoints are set on mainnet.
+    pub fn get_checkpoints(&self) -> Result<Vec<(u64, BurnchainHeaderHash)>, btc_error> {
+        match self.config.checkpoints {
+            Some(_) if self.runtime.network_id == BitcoinNetworkType::Mainnet => Err(
+                btc_error::ConfigError("Custom checkpoints can't be used on mainnet".to_string()),
+            ),
+            Some(ref checkpoints) => Ok(checkpoints.clone()),
+            None => Ok(SpvClient::default_checkpoints(self.runtime.network_id)),
+        }
+    }
+
+    /// Get the genesis header to seed the headers DB with, if the `BitcoinIndexerConfig` has a
+    /// custom one.  Returns None to use the network's well-known genesis header.
+    /// Fails if the custom genesis header is malformed, doesn't have the configured hash, or is
+    /// set on any network but regtest.
+    pub fn get_genesis_header(&self) -> Result<Option<BlockHeader>, btc_error> {
+        let params = match self.config.custom_genesis {
+            Some(ref params) => params,
+            None => return Ok(None),
+        };
+        if self.runtime.network_id != BitcoinNetworkType::Regtest {
+            return Err(btc_error::ConfigError(format!(
+                "A custom genesis header can only be used on regtest, not {:?}",
+                self.runtime.network_id
+            )));
+        }
+
+        let header_bytes = hex_bytes(&params.header_hex).map_err(|e| {
+            btc_error::ConfigError(format!("Invalid custom genesis header hex: {:?}", &e))
+        })?;
+        if header_bytes.len() != 80 {
+            return Err(btc_error::ConfigError(format!(
+                "Custom genesis header is {} bytes, not 80",
+                header_bytes.len()
+            )));
         }
+        let header: BlockHeader = deserialize(&header_bytes).map_err(|e| {
+            btc_error::ConfigError(format!("Invalid custom genesis header: {:?}", &e))
+        })?;
+
+        let header_hash = BurnchainHeaderHash::from_bitcoin_hash(&header.bitcoin_hash());
+        if header_hash != params.first_block_hash {
+            return Err(btc_error::ConfigError(format!(
+                "Custom genesis header hashes to {}, not {}",
+                &header_hash, &params.first_block_hash
+            )));
+        }
+        Ok(Some(header))
+    }
+
+    /// Get the trusted header to start the headers DB from, its height, and the total work of
+    /// the chain up to it, if the `BitcoinIndexerConfig` has a first burn block.  Returns None
+    /// to start from the genesis header.
+    /// Fails if the header is malformed, doesn't have the configured hash, or is above
+    /// `first_block`.
+    pub fn get_first_burn_block(&self) -> Result<Option<(u64, BlockHeader, Uint256)>, btc_error> {
+        let params = match self.config.first_burn_block {
+            Some(ref params) => params,
+            None => return Ok(None),
+        };
+        if params.height > self.config.first_block {
+            return Err(btc_error::ConfigError(format!(
+                "First burn block height {} is above the first block height {}",
+                params.height, self.config.first_block
+            )));
+        }
+
+        let header_bytes = hex_bytes(&params.header_hex).map_err(|e| {
+            btc_error::ConfigError(format!("Invalid first burn block header hex: {:?}", &e))
+        })?;
+        if header_bytes.len() != 80 {
+            return Err(btc_error::ConfigError(format!(
+                "First burn block header is {} bytes, not 80",
+                header_bytes.len()
+            )));
+        }
+        let header: BlockHeader = deserialize(&header_bytes).map_err(|e| {
+            btc_error::ConfigError(format!("Invalid first burn block header: {:?}", &e))
+        })?;
+
+        let header_hash = BurnchainHeaderHash::from_bitcoin_hash(&header.bitcoin_hash());
+        if header_hash != params.block_hash {
+            return Err(btc_error::ConfigError(format!(
+                "First burn block header hashes to {}, not {}",
+                &header_hash, &params.block_hash
+            )));
+        }
+        Ok(Some((params.height, header, params.chain_work)))
     }
 
     /// (re)connect to our configured network peer.
@@ -258,7 +1649,17 @@ impl BitcoinIndexer {
     /// Bitcoin peer.  If we fail to connect, this method sets the socket
     /// to None.
     fn reconnect_peer(&mut self) -> Result<(), btc_error> {
-        match net::TcpStream::connect((self.config.peer_host.as_str(), self.config.peer_port)) {
+        let connection_result = match self.config.socks_proxy {
+            Some(ref proxy) => socks::connect(
+                proxy,
+                BitcoinIndexer::unbracket_host(self.peer_host()),
+                self.peer_port(),
+                self.config.connect_timeout,
+                self.config.read_timeout,
+            ),
+            None => self.connect_peer_addrs().map_err(btc_error::Io),
+        };
+        match connection_result {
             Ok(s) => {
                 // Disable Nagle algorithm
                 s.set_nodelay(true).map_err(|_e| {
@@ -267,64 +1668,387 @@ impl BitcoinIndexer {
                 })?;
 
                 // set timeout
-                s.set_read_timeout(Some(Duration::from_secs(self.runtime.timeout)))
+                s.set_read_timeout(Some(self.config.read_timeout))
                     .map_err(|_e| {
                         test_debug!("Failed to set TCP read timeout: {:?}", &_e);
                         btc_error::ConnectionError
                     })?;
 
-                s.set_write_timeout(Some(Duration::from_secs(self.runtime.timeout)))
+                s.set_write_timeout(Some(self.config.write_timeout))
                     .map_err(|_e| {
                         test_debug!("Failed to set TCP write timeout: {:?}", &_e);
                         btc_error::ConnectionError
                     })?;
 
-                match self.runtime.sock.take() {
-                    Some(s) => {
-                        let _ = s.shutdown(Shutdown::Both);
-                    }
-                    None => {}
-                }
-
+                self.disconnect();
                 self.runtime.sock = Some(s);
+                self.runtime.outstanding_ping = None;
+                self.runtime.last_ping_time = Instant::now();
+                self.runtime.sendheaders_sent_time = None;
+                self.runtime.last_headers_announcement_time = None;
+                self.runtime.bloom_filter = None;
+                // the limits are per peer, and the old peer's requests are moot
+                self.runtime.request_limiter =
+                    self.config.request_limit.clone().map(RequestLimiter::new);
                 Ok(())
             }
+            Err(e @ btc_error::ProxyError(_)) => {
+                self.disconnect();
+                Err(e)
+            }
             Err(_e) => {
-                let s = self.runtime.sock.take();
-                match s {
-                    Some(s) => {
-                        let _ = s.shutdown(Shutdown::Both);
-                    }
-                    None => {}
-                }
+                self.disconnect();
                 Err(btc_error::ConnectionError)
             }
         }
     }
 
-    /// Run code with the socket
-    pub fn with_socket<F, R>(&mut self, closure: F) -> Result<R, btc_error>
-    where
-        F: FnOnce(&mut net::TcpStream) -> Result<R, btc_error>,
-    {
-        let mut sock = self.runtime.sock.take();
-        let res = match sock {
-            Some(ref mut s) => closure(s),
-            None => Err(btc_error::SocketNotConnectedToPeer),
-        };
-        self.runtime.sock = sock;
-        res
-    }
+    /// Connect to the current peer, trying the address it last accepted a connection at
+    /// first, and then the rest of its addresses in order of `address_preference`.  Remembers
+    /// the address that accepts the connection.
+    fn connect_peer_addrs(&mut self) -> io::Result<net::TcpStream> {
+        let peer = (self.peer_host().to_string(), self.peer_port());
+        let last_addr = self.runtime.peer_addrs.get(&peer).cloned();
+        let mut addrs =
+            match BitcoinIndexer::resolve_peer(&peer.0, peer.1, self.config.address_preference) {
+                Ok(addrs) => addrs,
+                Err(e) => match last_addr {
+                    Some(last_addr) => {
+                        warn!(
+                            "Failed to resolve {}:{} ({}); reconnecting to {}",
+                            &peer.0, peer.1, &e, &last_addr
+                        );
+                        vec![last_addr]
+                    }
+                    None => return Err(e),
+                },
+            };
+        if let Some(i) = last_addr.and_then(|last_addr| addrs.iter().position(|a| *a == last_addr))
+        {
+            let last_addr = addrs.remove(i);
+            addrs.insert(0, last_addr);
+        }
+
+        let (sock, addr) = BitcoinIndexer::connect_first(&addrs, self.config.connect_timeout)?;
+        debug!("Connected to {}:{} at {}", &peer.0, peer.1, &addr);
+        self.runtime.peer_addrs.insert(peer, addr);
+        Ok(sock)
+    }
+
+    /// Strip the brackets from an IPv6 address written as `[address]`
+    pub fn unbracket_host(host: &str) -> &str {
+        host.strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host)
+    }
+
+    /// Get the addresses of `host:port`, in the order to try connecting to them.  `host` can be
+    /// an IPv4 address, an IPv6 address (bracketed or not), or a host name to look up.
+    pub fn resolve_peer(
+        host: &str,
+        port: u16,
+        preference: AddressPreference,
+    ) -> io::Result<Vec<net::SocketAddr>> {
+        let mut addrs: Vec<_> =
+            net::ToSocketAddrs::to_socket_addrs(&(BitcoinIndexer::unbracket_host(host), port))?
+                .collect();
+        match preference {
+            AddressPreference::System => {}
+            // stable sorts, so the resolver's order holds within each family
+            AddressPreference::Ipv4First => addrs.sort_by_key(|addr| addr.is_ipv6()),
+            AddressPreference::Ipv6First => addrs.sort_by_key(|addr| addr.is_ipv4()),
+        }
+        Ok(addrs)
+    }
+
+    /// Connect to the first of `addrs` that accepts a connection (within `timeout`, if given).
+    /// Returns the socket, and the address it's connected to.
+    pub fn connect_first(
+        addrs: &[net::SocketAddr],
+        timeout: Option<Duration>,
+    ) -> io::Result<(net::TcpStream, net::SocketAddr)> {
+        let mut last_err = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Peer address resolved to no addresses",
+        );
+        for addr in addrs.iter() {
+            let res = match timeout {
+                Some(timeout) => net::TcpStream::connect_timeout(addr, timeout),
+                None => net::TcpStream::connect(addr),
+            };
+            match res {
+                Ok(s) => return Ok((s, *addr)),
+                Err(e) => {
+                    debug!("Failed to connect to {}: {}", addr, &e);
+                    last_err = e;
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Connect to the first of `peer_addr`'s addresses that accepts a connection within
+    /// `timeout`
+    pub fn connect_timeout<A: net::ToSocketAddrs>(
+        peer_addr: A,
+        timeout: Duration,
+    ) -> io::Result<net::TcpStream> {
+        let addrs: Vec<_> = peer_addr.to_socket_addrs()?.collect();
+        BitcoinIndexer::connect_first(&addrs, Some(timeout)).map(|(s, _)| s)
+    }
+
+    /// Run code with the socket.
+    /// If the code finds that the connection was lost, we disconnect and get ConnectionBroken:
+    /// a message may have been cut off partway, so nothing more is read from the socket, and
+    /// the conversation is picked up again over a new connection (see `peer_communicate()`).
+    pub fn with_socket<F, R>(&mut self, closure: F) -> Result<R, btc_error>
+    where
+        F: FnOnce(&mut net::TcpStream) -> Result<R, btc_error>,
+    {
+        let mut sock = self.runtime.sock.take();
+        let res = match sock {
+            Some(ref mut s) => closure(s),
+            None => Err(btc_error::SocketNotConnectedToPeer),
+        };
+        self.runtime.sock = sock;
+        match res {
+            Err(e) if self.is_connected() && e.is_connection_lost() => {
+                debug!(
+                    "Lost connection to {}:{}: {:?}",
+                    self.peer_host(),
+                    self.peer_port(),
+                    &e
+                );
+                self.disconnect();
+                Err(btc_error::ConnectionBroken)
+            }
+            res => res,
+        }
+    }
 
     /// Are we connected?
-    fn is_connected(&mut self) -> bool {
+    pub fn is_connected(&mut self) -> bool {
         self.runtime.sock.is_some()
     }
 
+    /// Shut down and drop our connection to the peer, if we have one
+    pub fn disconnect(&mut self) {
+        match self.runtime.sock.take() {
+            Some(s) => {
+                let _ = s.shutdown(Shutdown::Both);
+                self.report_sync_event(|| SyncEvent::PeerDisconnected {
+                    host: self.peer_host().to_string(),
+                    port: self.peer_port(),
+                });
+            }
+            None => {}
+        }
+    }
+
+    /// Number of peers we know of:  `peer_host:peer_port` and the `header_sync_peers`
+    pub fn num_peers(&self) -> usize {
+        1 + self.config.header_sync_peers.len()
+    }
+
+    /// The `i`th peer we know of:  `peer_host:peer_port` for 0, or else the `i - 1`th of the
+    /// `header_sync_peers`
+    fn peer_at(&self, i: usize) -> (&str, u16) {
+        if i == 0 {
+            (&self.config.peer_host, self.config.peer_port)
+        } else {
+            let (host, port) = &self.config.header_sync_peers[i - 1];
+            (host, *port)
+        }
+    }
+
+    /// The peer we're connected to, or will connect to next
+    pub fn current_peer(&self) -> (&str, u16) {
+        self.peer_at(self.runtime.peer_index)
+    }
+
+    /// Host of the peer we're connected to, or will connect to next
+    pub fn peer_host(&self) -> &str {
+        self.current_peer().0
+    }
+
+    /// Port of the peer we're connected to, or will connect to next
+    pub fn peer_port(&self) -> u16 {
+        self.current_peer().1
+    }
+
+    /// Give up on the current peer after it refused a connection, timed out, or kept sending
+    /// invalid replies:  drop the connection, and move on to the next peer we know of (see
+    /// `num_peers()`), wrapping around after the last one.  The next connection is made to the
+    /// new peer, and syncing carries on from wherever we got to.  Once every peer has been given
+    /// up on since the last successful handshake, the next connection attempt waits according
+    /// to the reconnect policy first.  With no other peers, this just schedules a reconnect to
+    /// the same peer.
+    pub fn fail_over_peer(&mut self) {
+        self.disconnect();
+        self.runtime.invalid_replies = 0;
+        let num_peers = self.num_peers();
+        if num_peers == 1 {
+            self.schedule_reconnect();
+            return;
+        }
+
+        let failed_index = self.runtime.peer_index;
+        self.runtime.peer_index = (failed_index + 1) % num_peers;
+        {
+            let (failed_host, failed_port) = self.peer_at(failed_index);
+            let (next_host, next_port) = self.current_peer();
+            warn!(
+                "Failing over from {}:{} to {}:{}",
+                failed_host, failed_port, next_host, next_port
+            );
+        }
+
+        self.runtime.failovers += 1;
+        if self.runtime.failovers % (num_peers as u64) == 0 {
+            warn!("Every peer has failed; backing off before trying them again");
+            self.schedule_reconnect();
+        }
+    }
+
+    /// Get the context of what we're doing right now:  which peer we're talking to, the height
+    /// of our highest header, and which `peer_communicate()` call we're in
+    pub fn log_context(&self) -> PeerContext {
+        let height = self
+            .runtime
+            .sync_stats
+            .lock()
+            .expect("BUG: sync stats lock poisoned")
+            .headers_height;
+        PeerContext {
+            peer_host: self.peer_host().to_string(),
+            peer_port: self.peer_port(),
+            height,
+            request_id: self.runtime.request_id,
+        }
+    }
+
+    /// Attach the context of `result`'s error, if any, so it can be traced back to the peer
+    /// that caused it.  The context is that of the last failed `peer_communicate()` call (which
+    /// may have failed over to another peer since), or else the current one.
+    pub fn with_peer_context<T>(&mut self, result: Result<T, btc_error>) -> Result<T, PeerError> {
+        match result {
+            Ok(x) => Ok(x),
+            Err(error) => {
+                let context = match self.runtime.error_context.take() {
+                    Some(context) => context,
+                    None => self.log_context(),
+                };
+                Err(PeerError { context, error })
+            }
+        }
+    }
+
+    /// Remember the current context as that of the error `peer_communicate()` is failing with
+    fn note_error_context(&mut self) -> PeerContext {
+        let context = self.log_context();
+        self.runtime.error_context = Some(context.clone());
+        context
+    }
+
+    /// Is this error the peer's fault, for sending something we can't use?
+    fn is_invalid_reply(e: &btc_error) -> bool {
+        match e {
+            btc_error::InvalidReply
+            | btc_error::InvalidMessage(..)
+            | btc_error::InvalidMagic
+            | btc_error::SerializationError(..)
+            | btc_error::OversizedMessage(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Count an invalid reply from `peer_host:peer_port`, and fail over to the next peer once it
+    /// has sent `MAX_INVALID_REPLIES` in a row
+    pub fn note_invalid_reply(&mut self) {
+        self.runtime.invalid_replies += 1;
+        if self.runtime.invalid_replies >= MAX_INVALID_REPLIES {
+            let context = self.log_context();
+            warn!("Peer sent {} invalid replies in a row", self.runtime.invalid_replies;
+                  "peer" => %context,
+                  "height" => context.height,
+                  "request_id" => context.request_id);
+            self.fail_over_peer();
+        }
+    }
+
+    /// How many times the `i`th peer we know of has stalled or dropped out
+    fn peer_failures_at(&self, i: usize) -> u64 {
+        let (host, port) = self.peer_at(i);
+        self.runtime
+            .peer_failures
+            .get(&(host.to_string(), port))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Give up on the peer we're syncing headers from:  drop the connection, count the failure
+    /// against the peer, and move on to whichever other peer we know of (see `num_peers()`) has
+    /// failed the fewest times (the first one after the current peer, on a tie).  We stay with
+    /// the current peer if every other one has failed more often than it, or if there are no
+    /// others.  Either way, the next connection is made to `current_peer()`.
+    pub fn switch_header_peer(&mut self) {
+        self.disconnect();
+
+        let cur_index = self.runtime.peer_index;
+        let cur_peer = (self.peer_host().to_string(), self.peer_port());
+        let cur_failures = {
+            let failures = self.runtime.peer_failures.entry(cur_peer).or_insert(0);
+            *failures += 1;
+            *failures
+        };
+        let num_peers = self.num_peers();
+        let best = (1..num_peers)
+            .map(|offset| {
+                let i = (cur_index + offset) % num_peers;
+                (self.peer_failures_at(i), offset, i)
+            })
+            .min();
+
+        match best {
+            Some((failures, _, i)) if failures <= cur_failures => {
+                self.runtime.peer_index = i;
+                let (prev_host, prev_port) = self.peer_at(cur_index);
+                let (next_host, next_port) = self.current_peer();
+                warn!(
+                    "Switching header sync from {}:{} ({} failures) to {}:{} ({} failures)",
+                    prev_host, prev_port, cur_failures, next_host, next_port, failures
+                );
+            }
+            _ => {
+                debug!(
+                    "No better peer than {}:{} ({} failures) to sync headers from",
+                    self.peer_host(),
+                    self.peer_port(),
+                    cur_failures
+                );
+            }
+        }
+    }
+
+    /// Get the current reconnection backoff state
+    pub fn get_reconnect_backoff(&self) -> &ReconnectBackoff {
+        &self.runtime.backoff
+    }
+
+    /// Note that the connection to the peer dropped, or that a connection attempt failed, so
+    /// that `connect_handshake_backoff()` waits according to our reconnect policy before its
+    /// next attempt.  That's the only place the backoff is waited out, so noting the same drop
+    /// more than once still only waits once.
+    pub fn schedule_reconnect(&mut self) {
+        self.runtime.backoff.reconnect_needed();
+    }
+
     /// Carry on a conversation with the bitcoin peer.
     /// Handle version, verack, ping, and pong messages automatically.
-    /// Reconnect to the peer automatically if the peer closes the connection.
-    /// Pass any other messages to a given message handler.
+    /// Reconnect to the peer automatically if the connection is lost (e.g. the peer closes it or
+    /// restarts), and start the conversation over with the message handler's `begin_session()`.
+    /// Pass any other messages to a given message handler, and let it know whenever none arrive
+    /// within its idle timeout.
     pub fn peer_communicate<T: BitcoinMessageHandler>(
         &mut self,
         message_handler: &mut T,
@@ -333,6 +2057,8 @@ impl BitcoinIndexer {
         let mut do_handshake = initial_handshake || !self.is_connected();
         let mut keep_going = true;
         let mut initiated = false;
+        self.runtime.request_id += 1;
+        self.runtime.error_context = None;
 
         while keep_going {
             if do_handshake {
@@ -345,10 +2071,19 @@ impl BitcoinIndexer {
                         // connection established!
                         do_handshake = false;
                     }
-                    Err(_) => {
+                    Err(e) if e.is_transient() => {
                         // need to try again
                         continue;
                     }
+                    Err(e) => {
+                        // trying again won't help
+                        let context = self.note_error_context();
+                        warn!("Failed to connect to peer: {:?}", &e;
+                              "peer" => %context,
+                              "height" => context.height,
+                              "request_id" => context.request_id);
+                        return Err(e);
+                    }
                 }
             }
 
@@ -362,23 +2097,45 @@ impl BitcoinIndexer {
                         }
                         initiated = true;
                     }
-                    Err(btc_error::ConnectionBroken) => {
+                    Err(e) if e.is_connection_lost() => {
                         debug!("Re-establish peer connection");
+                        self.schedule_reconnect();
                         do_handshake = true;
+                        continue;
                     }
                     Err(e) => {
-                        warn!("Unhandled error while initiating conversation: {:?}", e);
+                        let context = self.note_error_context();
+                        warn!("Unhandled error while initiating conversation: {:?}", &e;
+                              "peer" => %context,
+                              "height" => context.height,
+                              "request_id" => context.request_id);
                         return Err(e);
                     }
                 }
             }
 
-            match self.recv_message() {
+            let received = match message_handler.idle_timeout() {
+                Some(idle_timeout) => match self.poll_message(idle_timeout) {
+                    Ok(true) => self.recv_message(),
+                    Ok(false) => match message_handler.handle_idle(self) {
+                        Ok(do_continue) => {
+                            keep_going = do_continue;
+                            continue;
+                        }
+                        Err(e) => Err(e),
+                    },
+                    Err(e) => Err(e),
+                },
+                None => self.recv_message(),
+            };
+
+            match received {
                 Ok(msg) => {
                     // got a message; go consume it
                     let handled = self.handle_message(msg, Some(message_handler));
                     match handled {
                         Ok(do_continue) => {
+                            self.runtime.invalid_replies = 0;
                             keep_going = do_continue;
                             if !keep_going {
                                 debug!("Message handler indicates to stop");
@@ -395,26 +2152,51 @@ impl BitcoinIndexer {
                                 }
                             }
                         }
-                        Err(btc_error::ConnectionBroken) => {
+                        Err(e) if e.is_connection_lost() => {
                             debug!("Re-establish peer connection");
+                            self.schedule_reconnect();
                             do_handshake = true;
                         }
                         Err(e) => {
-                            warn!("Unhandled error {:?}", e);
+                            let context = self.note_error_context();
+                            warn!("Unhandled error {:?}", &e;
+                                  "peer" => %context,
+                                  "height" => context.height,
+                                  "request_id" => context.request_id);
+                            if BitcoinIndexer::is_invalid_reply(&e) {
+                                self.note_invalid_reply();
+                            }
                             return Err(e);
                         }
                     }
                 }
-                Err(btc_error::ConnectionBroken) => {
+                Err(e) if e.is_connection_lost() => {
+                    self.schedule_reconnect();
                     do_handshake = true;
                 }
+                Err(btc_error::ShutdownRequested) => {
+                    return Err(btc_error::ShutdownRequested);
+                }
                 Err(btc_error::SerializationError(
                     btc_serialization_err::UnrecognizedNetworkCommand(s),
                 )) => {
                     debug!("Received unrecognized network command while receiving a message: {}, ignoring", s);
                 }
                 Err(e) => {
-                    warn!("Unhandled error while receiving a message: {:?}", e);
+                    let context = self.log_context();
+                    warn!("Unhandled error while receiving a message: {:?}", &e;
+                          "peer" => %context,
+                          "height" => context.height,
+                          "request_id" => context.request_id);
+                    if BitcoinIndexer::is_invalid_reply(&e) {
+                        self.note_invalid_reply();
+                        if self.is_connected() {
+                            self.schedule_reconnect();
+                        }
+                    } else {
+                        // e.g. the read timed out
+                        self.fail_over_peer();
+                    }
                     do_handshake = true;
                 }
             }
@@ -431,14 +2213,52 @@ impl BitcoinIndexer {
         last_block: Option<u64>,
     ) -> Result<u64, btc_error> {
         debug!("Sync all headers starting at block {}", start_block);
-        let mut spv_client = SpvClient::new(
+        let mut spv_client = SpvClient::new_with_genesis(
             &self.config.spv_headers_path,
             start_block,
             last_block,
             self.runtime.network_id,
+            self.get_genesis_header()?,
             true,
             false,
         )?;
+        spv_client.set_checkpoints(self.get_checkpoints()?);
+        spv_client.set_reorg_floor(self.reorg_floor());
+        if let Some((height, header, chain_work)) = self.get_first_burn_block()? {
+            spv_client.init_from_anchor(height, header, chain_work)?;
+        }
+        spv_client.set_assume_valid(self.config.assume_valid.clone());
+        spv_client.set_assume_valid_checkpoints(self.config.assume_valid_checkpoints);
+        spv_client.set_first_block(
+            self.config
+                .first_block_hash
+                .clone()
+                .map(|hash| (self.config.first_block, hash)),
+        );
+        spv_client.check_first_block()?;
+        spv_client.set_strict_header_versions(self.config.strict_header_versions);
+        spv_client.set_fsync_interval(
+            self.config.header_fsync_interval,
+            self.config.header_fsync_period,
+        );
+        spv_client.set_shutdown_handle(self.runtime.shutdown.clone());
+        spv_client.set_sync_stats(self.sync_stats_handle());
+        let headers_height = spv_client.get_highest_header_height()?;
+        self.update_sync_stats(|stats| stats.record_header_tip(headers_height));
+        if let Some((handler, interval)) = self.runtime.header_sync_progress.as_ref() {
+            spv_client.set_progress_handler(handler.clone(), *interval);
+        }
+        for subscriber in self
+            .runtime
+            .tip_subscribers
+            .get_mut()
+            .expect("BUG: tip subscriber lock poisoned")
+            .iter()
+        {
+            spv_client.add_tip_subscriber(subscriber.clone());
+        }
+        // we only have blocks to download up to the headers we've actually accepted
+        let tip_updates = spv_client.subscribe_tip();
         if let Some(last_block) = last_block.as_ref() {
             // do we need to do anything?
             let cur_height = spv_client.get_headers_height()?;
@@ -447,504 +2267,570 @@ impl BitcoinIndexer {
                 return Ok(cur_height);
             }
         }
-        spv_client
-            .run(self)
-            .and_then(|_r| Ok(spv_client.end_block_height.unwrap()))
-    }
-
-    #[cfg(test)]
-    fn new_reorg_spv_client(
-        reorg_headers_path: &str,
-        start_block: u64,
-        end_block: Option<u64>,
-        network_id: BitcoinNetworkType,
-    ) -> Result<SpvClient, btc_error> {
-        SpvClient::new_without_migration(
-            &reorg_headers_path,
-            start_block,
-            end_block,
-            network_id,
-            true,
-            true,
-        )
-    }
-
-    #[cfg(not(test))]
-    fn new_reorg_spv_client(
-        reorg_headers_path: &str,
-        start_block: u64,
-        end_block: Option<u64>,
-        network_id: BitcoinNetworkType,
-    ) -> Result<SpvClient, btc_error> {
-        SpvClient::new(
-            &reorg_headers_path,
-            start_block,
-            end_block,
-            network_id,
-            true,
-            true,
-        )
-    }
 
-    /// Create a SPV client for starting reorg processing
-    fn setup_reorg_headers(
-        &mut self,
-        canonical_spv_client: &SpvClient,
-        reorg_headers_path: &str,
-        start_block: u64,
-        remove_old: bool,
-    ) -> Result<SpvClient, btc_error> {
-        if remove_old {
-            if PathBuf::from(&reorg_headers_path).exists() {
-                fs::remove_file(&reorg_headers_path).map_err(|e| {
-                    error!("Failed to remove {}", reorg_headers_path);
-                    btc_error::Io(e)
-                })?;
+        // if the peer is announcing new headers to us, we need not ask for them
+        let announced = match self.sync_announced_headers(&mut spv_client) {
+            Ok(live) => match last_block {
+                Some(last_block) => live && last_block <= spv_client.get_headers_height()?,
+                None => live,
+            },
+            Err(e) => {
+                debug!(
+                    "Failed to take header announcements from {}:{}: {:?}",
+                    self.peer_host(),
+                    self.peer_port(),
+                    &e
+                );
+                false
             }
+        };
+        if announced {
+            self.report_headers_reorg(&spv_client);
+            self.report_tip_updates(&tip_updates);
+            return spv_client.get_highest_header_height();
         }
 
-        // bootstrap reorg client
-        let mut reorg_spv_client = BitcoinIndexer::new_reorg_spv_client(
-            reorg_headers_path,
-            start_block,
-            Some(start_block + REORG_BATCH_SIZE),
-            self.runtime.network_id,
-        )?;
+        if self.header_source() == HeaderSourceType::Peer && self.config.header_sync_peers.len() > 0
+        {
+            // get as many headers as we can from our peers in parallel, and then get the rest
+            // serially
+            if let Err(e) = self.sync_header_chunks(&mut spv_client, last_block) {
+                warn!(
+                    "Failed to download headers in parallel, falling back to {}:{}: {:?}",
+                    self.peer_host(),
+                    self.peer_port(),
+                    &e
+                );
+            }
+            let tip_height = spv_client.get_highest_header_height()?;
+            if tip_height > start_block {
+                spv_client.set_scan_range(tip_height, last_block);
+            }
+        }
 
-        if start_block > 0 {
-            if start_block > BLOCK_DIFFICULTY_CHUNK_SIZE {
-                if remove_old {
-                    // set up a .reorg db
-                    // * needs the last difficulty interval of headers (note that the current
-                    // interval is `start_block / BLOCK_DIFFICULTY_CHUNK_SIZE - 1).
-                    // * needs the last interval's chain work calculation
-                    let interval_start_block =
-                        (start_block / BLOCK_DIFFICULTY_CHUNK_SIZE).saturating_sub(2);
-                    let base_block = interval_start_block * BLOCK_DIFFICULTY_CHUNK_SIZE;
+        let res = self.sync_spv_client(&mut spv_client);
+        self.report_headers_reorg(&spv_client);
+        res?;
 
-                    if base_block > 0 {
-                        let interval_headers =
-                            canonical_spv_client.read_block_headers(base_block, start_block + 1)?;
-                        assert!(
-                            interval_headers.len() >= (start_block - base_block) as usize,
-                            "BUG: missing headers for {}-{}",
-                            base_block,
-                            start_block
-                        );
+        if self.is_shutdown_requested() {
+            // we stopped between batches, so we only have headers up to the highest one
+            self.report_tip_updates(&tip_updates);
+            return spv_client.get_highest_header_height();
+        }
 
-                        debug!(
-                            "Copy headers {}-{}",
-                            base_block,
-                            base_block + interval_headers.len() as u64
-                        );
-                        reorg_spv_client
-                            .insert_block_headers_before(base_block - 1, interval_headers)?;
-                    } else {
-                        let interval_headers =
-                            canonical_spv_client.read_block_headers(1, start_block + 1)?;
-                        assert!(
-                            interval_headers.len() >= start_block as usize,
-                            "BUG: missing headers for 1-{}",
-                            start_block
-                        );
+        if let Some(pruning) = self.config.header_pruning.as_ref() {
+            spv_client.prune_headers(pruning)?;
+        }
+        if self.config.sync_mode == SyncMode::P2p && self.config.filter_scripts.len() > 0 {
+            // filters only save us from downloading blocks, so carry on without them
+            if let Err(e) = self.sync_compact_filters(&mut spv_client) {
+                warn!(
+                    "Failed to download compact block filters from {}:{}: {:?}",
+                    self.peer_host(),
+                    self.peer_port(),
+                    &e
+                );
+            }
+        }
+        match self.report_tip_updates(&tip_updates) {
+            Some(tip) => Ok(tip.height),
+            None => Ok(spv_client.end_block_height.unwrap()),
+        }
+    }
 
-                        debug!("Copy headers 1-{}", interval_headers.len() as u64);
-                        reorg_spv_client.insert_block_headers_before(0, interval_headers)?;
-                    }
+    /// Report each of the tip updates waiting in `tip_updates` to the sync observer, in order.
+    /// Returns the last one.
+    fn report_tip_updates(&self, tip_updates: &Receiver<TipUpdate>) -> Option<TipUpdate> {
+        let mut last_tip = None;
+        for tip in tip_updates.try_iter() {
+            self.report_sync_event(|| SyncEvent::HeaderTipAdvanced {
+                height: tip.height,
+                hash: tip.hash,
+                reorg_depth: tip.reorg_depth,
+            });
+            last_tip = Some(tip);
+        }
+        last_tip
+    }
 
-                    let last_interval = canonical_spv_client.find_highest_work_score_interval()?;
+    /// Where header syncs get headers from:  the configured `header_source`, unless we sync
+    /// over JSON-RPC alone
+    pub fn header_source(&self) -> HeaderSourceType {
+        match self.config.sync_mode {
+            SyncMode::P2p => self.config.header_source,
+            SyncMode::Rpc => HeaderSourceType::Rpc,
+        }
+    }
 
-                    // copy over the relevant difficulty intervals as well
-                    for interval in interval_start_block..(last_interval + 1) {
-                        test_debug!("Copy interval {} to {}", interval, &reorg_headers_path);
-                        let work_score = canonical_spv_client
-                            .find_interval_work(interval)?
-                            .expect(&format!("FATAL: no work score for interval {}", interval));
-                        reorg_spv_client.store_interval_work(interval, work_score)?;
-                    }
-                }
-            } else {
-                // no full difficulty intervals yet
-                let interval_headers =
-                    canonical_spv_client.read_block_headers(1, start_block + 1)?;
-                reorg_spv_client.insert_block_headers_before(0, interval_headers)?;
+    /// Get the headers after `spv_client`'s scan range start from the configured header source,
+    /// up to its chain tip
+    fn sync_spv_client(&mut self, spv_client: &mut SpvClient) -> Result<(), btc_error> {
+        match self.header_source() {
+            HeaderSourceType::Peer => spv_client.run(self),
+            HeaderSourceType::Rpc => {
+                let mut rpc_client = BitcoinRpcClient::from_config(
+                    &self.config,
+                    self.runtime.rpc_credentials.clone(),
+                )?;
+                self.check_rpc_node(&mut rpc_client)?;
+                spv_client.sync_from(&mut rpc_client)
             }
         }
+    }
 
-        Ok(reorg_spv_client)
+    /// Check that the bitcoind behind `rpc_client` is on our network before we get headers or
+    /// blocks from it.  A node on another chain is a ConfigError.  A node that's still in its
+    /// initial block download is only warned about:  its best chain is just behind, and a
+    /// regtest node claims to be in it until a recent block is mined.
+    pub fn check_rpc_node(
+        &self,
+        rpc_client: &mut BitcoinRpcClient,
+    ) -> Result<BlockchainInfo, btc_error> {
+        let info = rpc_client.get_blockchain_info()?;
+        let expected_chain = bitcoind_chain_name(self.runtime.network_id);
+        if info.chain != expected_chain {
+            return Err(btc_error::ConfigError(format!(
+                "bitcoind at {}:{} is on chain '{}', but we're configured for '{}'",
+                &self.config.peer_host, self.config.rpc_port, &info.chain, expected_chain
+            )));
+        }
+        if info.initial_block_download {
+            warn!(
+                "bitcoind at {}:{} is still in its initial block download ({} of {} blocks)",
+                &self.config.peer_host, self.config.rpc_port, info.blocks, info.headers
+            );
+        }
+        Ok(info)
     }
 
-    /// Search for a bitcoin reorg.  Return the offset into the canonical bitcoin headers where
-    /// the reorg starts.  Returns the hight of the highest common ancestor.
-    /// Note that under certain testnet settings, the bitcoin chain itself can shrink.
-    pub fn find_bitcoin_reorg<F>(
-        &mut self,
-        canonical_headers_path: &str,
-        reorg_headers_path: &str,
-        load_reorg_headers: F,
-    ) -> Result<u64, btc_error>
-    where
-        F: FnMut(&mut BitcoinIndexer, &mut SpvClient, u64, Option<u64>) -> Result<(), btc_error>,
-    {
-        // always check chain work, except in testing
-        self.inner_find_bitcoin_reorg(
-            canonical_headers_path,
-            reorg_headers_path,
-            load_reorg_headers,
-            true,
-        )
-    }
-
-    fn inner_find_bitcoin_reorg<F>(
-        &mut self,
-        canonical_headers_path: &str,
-        reorg_headers_path: &str,
-        mut load_reorg_headers: F,
-        check_chain_work: bool,
-    ) -> Result<u64, btc_error>
-    where
-        F: FnMut(&mut BitcoinIndexer, &mut SpvClient, u64, Option<u64>) -> Result<(), btc_error>,
-    {
-        let mut new_tip = 0;
-        let mut found_common_ancestor = false;
-
-        let mut orig_spv_client = SpvClient::new(
-            canonical_headers_path,
-            0,
-            None,
-            self.runtime.network_id,
-            true,
-            false,
-        )?;
-
-        // what's the last header we have from the canonical history?
-        let canonical_end_block = orig_spv_client.get_headers_height().map_err(|e| {
-            error!(
-                "Failed to get the last block from {}",
-                canonical_headers_path
+    /// Remember the lowest fork height of any reorg `spv_client` processed, until it's reported
+    /// via `take_headers_reorg_height()`
+    fn report_headers_reorg(&mut self, spv_client: &SpvClient) {
+        if let Some(reorg_height) = spv_client.get_reorg_height() {
+            warn!(
+                "Bitcoin headers reorged while syncing: highest common ancestor at height {}",
+                reorg_height
             );
-            e
-        })?;
-
-        // bootstrap reorg client
-        let mut start_block = canonical_end_block.saturating_sub(REORG_BATCH_SIZE);
-        let mut reorg_spv_client =
-            self.setup_reorg_headers(&orig_spv_client, reorg_headers_path, start_block, true)?;
-        let mut discontiguous_header_error_count = 0;
+            self.runtime.headers_reorg_height = Some(match self.runtime.headers_reorg_height {
+                Some(h) => cmp::min(h, reorg_height),
+                None => reorg_height,
+            });
+            self.report_sync_event(|| SyncEvent::ReorgDetected {
+                fork_height: reorg_height,
+            });
+        }
+    }
 
-        while !found_common_ancestor {
-            debug!(
-                "Search for reorg'ed Bitcoin headers from {} - {}",
-                start_block,
-                start_block + REORG_BATCH_SIZE
-            );
+    /// Store the headers our peer has announced since we last looked, without waiting for more.
+    /// Returns true if the peer is keeping us up to date with announcements: we asked it to
+    /// (see `send_sendheaders()`), none of its announcements left a gap in our headers, and it
+    /// has announced something (or we asked it to) within `sendheaders_grace`.  Otherwise, the
+    /// caller should ask the peer for new headers.  Never true if we don't get headers from the
+    /// peer.
+    fn sync_announced_headers(&mut self, spv_client: &mut SpvClient) -> Result<bool, btc_error> {
+        if self.header_source() != HeaderSourceType::Peer {
+            return Ok(false);
+        }
+        let grace = match self.config.sendheaders_grace {
+            Some(grace) => grace,
+            None => {
+                return Ok(false);
+            }
+        };
+        let sendheaders_sent_time = match self.runtime.sendheaders_sent_time {
+            Some(sent_time) if self.is_connected() => sent_time,
+            _ => {
+                return Ok(false);
+            }
+        };
 
-            // get new headers, starting off of start_block.  Feed them into the given
-            // reorg_spv_client.
-            match load_reorg_headers(
-                self,
-                &mut reorg_spv_client,
-                start_block,
-                Some(start_block + REORG_BATCH_SIZE),
-            ) {
-                Ok(_) => {}
-                Err(btc_error::NoncontiguousHeader) | Err(btc_error::InvalidPoW) => {
-                    warn!(
-                        "Received invalid headers from {} - {} -- possible reorg in progress",
-                        start_block,
-                        start_block + REORG_BATCH_SIZE
-                    );
-                    if start_block == 0 {
-                        // reorg all the way back to genesis
-                        new_tip = 0;
-                        break;
-                    }
+        // any getheaders from the last sync has been answered by now
+        self.runtime.last_getheaders_send_time = 0;
 
-                    // try again
-                    discontiguous_header_error_count += 1;
-                    start_block = start_block
-                        .saturating_sub(REORG_BATCH_SIZE * discontiguous_header_error_count);
-                    reorg_spv_client = self.setup_reorg_headers(
-                        &orig_spv_client,
-                        reorg_headers_path,
-                        start_block,
-                        false,
-                    )?;
-                    continue;
+        let mut announcements = HeaderAnnouncements::new(spv_client);
+        while self.poll_message(Duration::from_millis(1))? {
+            let msg = self.recv_message()?;
+            match self.handle_message(msg, Some(&mut announcements)) {
+                Ok(true) => {}
+                Ok(false) => {
+                    break;
+                }
+                Err(btc_error::UnhandledMessage(m)) => {
+                    debug!("Unhandled message {:?}", m);
                 }
                 Err(e) => {
-                    error!(
-                        "Failed to fetch Bitcoin headers from {} - {}: {:?}",
-                        start_block,
-                        start_block + REORG_BATCH_SIZE,
-                        &e
-                    );
                     return Err(e);
                 }
             }
+        }
+        if announcements.gap {
+            return Ok(false);
+        }
 
-            let reorg_headers = reorg_spv_client
-                .read_block_headers(start_block, start_block + REORG_BATCH_SIZE)
-                .map_err(|e| {
-                    error!(
-                        "Failed to read reorg Bitcoin headers from {} to {}",
-                        start_block,
-                        start_block + REORG_BATCH_SIZE
-                    );
-                    e
-                })?;
-
-            if reorg_headers.len() == 0 {
-                // chain shrank considerably
-                info!(
-                    "Missing Bitcoin headers in block range {}-{} -- did the Bitcoin chain shrink?",
-                    start_block,
-                    start_block + REORG_BATCH_SIZE
-                );
-                if start_block == 0 {
-                    // reorg chain is empty
-                    new_tip = 0;
-                    break;
+        match self.runtime.last_headers_announcement_time {
+            Some(announcement_time) => Ok(announcement_time.elapsed() < grace),
+            None => {
+                if sendheaders_sent_time.elapsed() < grace {
+                    return Ok(true);
                 }
-
-                start_block = start_block.saturating_sub(REORG_BATCH_SIZE);
-                reorg_spv_client.set_scan_range(start_block, Some(start_block + REORG_BATCH_SIZE));
-                continue;
+                warn!(
+                    "{}:{} has not announced any headers in {:?} since we sent sendheaders; asking for headers instead",
+                    self.peer_host(), self.peer_port(), &grace
+                );
+                self.runtime.sendheaders_ignored = true;
+                Ok(false)
             }
+        }
+    }
 
-            // got reorg headers.  Find the equivalent headers in our canonical history
-            let canonical_headers = orig_spv_client
-                .read_block_headers(start_block, start_block + REORG_BATCH_SIZE)
-                .map_err(|e| {
-                    error!(
-                        "Failed to read canonical headers from {} to {}",
-                        start_block,
-                        start_block + REORG_BATCH_SIZE
-                    );
-                    e
-                })?;
-
-            assert!(
-                canonical_headers.len() > 0,
-                "BUG: uninitialized canonical SPV headers DB"
-            );
+    /// Take the transactions our peer has announced since we last looked, fetch the ones we
+    /// haven't seen, and get the burnchain transactions among them that haven't confirmed (see
+    /// `get_unconfirmed_txs()`).  Waits up to the read timeout for fetched transactions to
+    /// arrive.  Returns nothing if we don't watch the peer's mempool, which we never do in
+    /// `SyncMode::Rpc`.
+    pub fn sync_mempool(&mut self) -> Result<Vec<UnconfirmedBitcoinTx>, btc_error> {
+        if self.config.mempool_ttl.is_none() || self.config.sync_mode == SyncMode::Rpc {
+            return Ok(vec![]);
+        }
+        if !self.is_connected() {
+            self.connect_handshake_backoff()?;
+        }
 
-            let max_headers_len = if canonical_headers.len() < reorg_headers.len() {
-                canonical_headers.len()
+        let deadline = Instant::now() + self.config.read_timeout;
+        loop {
+            let waiting = self
+                .runtime
+                .mempool
+                .as_ref()
+                .map(|watcher| watcher.has_requests())
+                .unwrap_or(false);
+            let timeout = if waiting {
+                deadline.saturating_duration_since(Instant::now())
             } else {
-                reorg_headers.len()
+                Duration::from_millis(1)
             };
-            let max_height = start_block + (max_headers_len as u64);
-
-            // scan for common ancestor, but excluding the block we wrote to bootstrap the
-            // reorg_spv_client.
-            for i in (start_block + 1..max_height).rev() {
-                if canonical_headers[(i - start_block) as usize].header
-                    == reorg_headers[(i - start_block) as usize].header
-                {
-                    // found common ancestor
-                    debug!(
-                        "Found common Bitcoin block ancestor at height {}: {:?}",
-                        i,
-                        &canonical_headers[(i - start_block) as usize].header
-                    );
-                    new_tip = i;
-                    found_common_ancestor = true;
-                    break;
-                } else {
-                    debug!(
-                        "Diverged headers at {}: {:?} != {:?}",
-                        i,
-                        &canonical_headers[(i - start_block) as usize].header,
-                        &reorg_headers[(i - start_block) as usize].header
+            if timeout == Duration::from_secs(0) || !self.poll_message(timeout)? {
+                if waiting {
+                    warn!(
+                        "Timed out waiting for mempool transactions from {}:{}",
+                        self.peer_host(),
+                        self.peer_port()
                     );
                 }
-            }
-            if found_common_ancestor {
                 break;
             }
 
-            debug!(
-                "No common ancestor found between Bitcoin headers {}-{}",
-                start_block, max_height
-            );
+            let msg = self.recv_message()?;
+            match self.handle_message::<SpvClient>(msg, None) {
+                Ok(_) => {}
+                Err(btc_error::UnhandledMessage(m)) => {
+                    debug!("Unhandled message {:?}", m);
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(self.get_unconfirmed_txs())
+    }
 
-            if start_block == 0 {
-                break;
+    /// Get the burnchain transactions we've seen in the peer's mempool that haven't confirmed,
+    /// oldest first.  Those first seen longer than `mempool_ttl` ago are dropped.
+    pub fn get_unconfirmed_txs(&mut self) -> Vec<UnconfirmedBitcoinTx> {
+        match self.runtime.mempool.as_mut() {
+            Some(watcher) => {
+                watcher.expire(get_epoch_time_secs());
+                watcher.get_unconfirmed()
             }
+            None => vec![],
+        }
+    }
 
-            // try again
-            start_block = start_block.saturating_sub(REORG_BATCH_SIZE);
-            reorg_spv_client =
-                self.setup_reorg_headers(&orig_spv_client, reorg_headers_path, start_block, false)?;
+    /// Drop the unconfirmed burnchain transactions that a downloaded and parsed block confirms.
+    /// Unconfirmed transactions are never dropped on their own until `mempool_ttl` passes, so
+    /// whoever processes the blocks should call this.
+    pub fn confirm_unconfirmed_txs(&mut self, block: &BitcoinBlock) {
+        if let Some(watcher) = self.runtime.mempool.as_mut() {
+            watcher.confirm_block(block);
+        }
+    }
+
+    /// Download the compact filters of the blocks from `first_block` (or our lowest header, if
+    /// it's higher) up to our chain tip, so blocks without burn operations need not be
+    /// downloaded.  Does nothing if the peer doesn't serve compact filters.
+    fn sync_compact_filters(&mut self, spv_client: &mut SpvClient) -> Result<(), btc_error> {
+        let start_height = cmp::max(self.config.first_block, spv_client.get_pruned_height()?);
+        let end_height = spv_client.get_highest_header_height()?;
+        if start_height > end_height {
+            return Ok(());
         }
 
-        if check_chain_work {
-            let reorg_total_work = reorg_spv_client.update_chain_work()?;
-            let orig_total_work = orig_spv_client.update_chain_work()?;
+        let mut filter_sync = FilterSync::new(spv_client, start_height, end_height)?;
+        self.peer_communicate(&mut filter_sync, false)?;
+        if filter_sync.unsupported {
+            debug!(
+                "Will download every block, since {}:{} does not serve compact filters",
+                self.peer_host(),
+                self.peer_port()
+            );
+        }
+        Ok(())
+    }
 
-            debug!("Bitcoin headers history is consistent up to {}", new_tip;
-                   "Orig chainwork" => %orig_total_work,
-                   "Reorg chainwork" => %reorg_total_work);
+    /// Get the timestamp and median-time-past of the header at `height`, if we have it (see
+    /// `SpvClient::read_block_times()`)
+    pub fn get_block_times(&self, height: u64) -> Result<Option<BlockTimes>, btc_error> {
+        self.open_headers_readonly()?.read_block_times(height)
+    }
 
-            if orig_total_work < reorg_total_work {
-                let reorg_tip = reorg_spv_client.get_headers_height()?;
-                let hdr_reorg = reorg_spv_client
-                    .read_block_header(reorg_tip - 1)?
-                    .expect("FATAL: no tip hash for existing chain tip");
-                info!(
-                    "New canonical Bitcoin chain found! New tip is {}",
-                    &hdr_reorg.header.bitcoin_hash()
-                );
+    /// Could the block at `height` contain burn operations, according to its compact filter and
+    /// our `filter_scripts`?  True unless we have its filter and it rules them out (see
+    /// `SpvClient::block_may_contain_burn_ops()`).  This is only a hint for watch-only uses,
+    /// e.g. deciding whether a block is worth scanning for a wallet's transactions; the block
+    /// downloader never skips a block because of it.
+    pub fn block_may_contain_burn_ops(&self, height: u64) -> Result<bool, btc_error> {
+        if self.config.filter_scripts.len() == 0 {
+            return Ok(true);
+        }
+        let mut spv_client = self.open_headers_readonly()?;
+        spv_client.set_filter_scripts(self.config.filter_scripts.clone());
+        spv_client.block_may_contain_burn_ops(height)
+    }
 
-                // merge the new headers and chain difficulty to the original headers
-                let mut orig_spv_client = SpvClient::new(
-                    canonical_headers_path,
-                    0,
-                    None,
-                    self.runtime.network_id,
-                    true,
-                    false,
-                )?;
+    /// Download headers up to the highest checkpoint (or `last_block`) from our peer and the
+    /// configured `header_sync_peers` in parallel, and store them with `spv_client`.
+    fn sync_header_chunks(
+        &mut self,
+        spv_client: &mut SpvClient,
+        last_block: Option<u64>,
+    ) -> Result<(), btc_error> {
+        let chunks =
+            spv_client.plan_header_chunks(last_block, self.config.header_sync_chunk_size)?;
+        if chunks.len() == 0 {
+            return Ok(());
+        }
 
-                // copy over new headers
-                if new_tip > 0 {
-                    let new_headers =
-                        reorg_spv_client.read_block_headers(new_tip, reorg_tip + 1)?;
-                    orig_spv_client.drop_headers(new_tip)?;
-                    orig_spv_client.insert_block_headers_after(new_tip - 1, new_headers)?;
+        let num_peers = self.num_peers();
+        let peers: Vec<_> = (0..num_peers)
+            .map(|i| {
+                let (host, port) = self.peer_at(i);
+                (host.to_string(), port)
+            })
+            .collect();
+
+        let max_in_flight = cmp::max(1, cmp::min(self.config.header_sync_num_peers, num_peers));
+        debug!(
+            "Download headers {}-{} in {} chunks from {} of {} peers at a time",
+            chunks[0].start_height,
+            chunks[chunks.len() - 1].end_height,
+            chunks.len(),
+            max_in_flight,
+            num_peers
+        );
+
+        let stall_timeout = Duration::from_secs(self.config.timeout as u64);
+        let config = self.config.clone();
+        let network_id = self.runtime.network_id;
+        BitcoinIndexer::fetch_header_chunks(
+            spv_client,
+            chunks,
+            num_peers,
+            max_in_flight,
+            stall_timeout,
+            move |peer, chunk| {
+                let mut peer_config = config.clone();
+                peer_config.peer_host = peers[peer].0.clone();
+                peer_config.peer_port = peers[peer].1;
+                BitcoinIndexer::fetch_header_chunk(peer_config, network_id, chunk)
+            },
+        )
+    }
+
+    /// Download a single chunk of headers from the given peer.
+    /// Does not retry -- any connection error fails the download.
+    fn fetch_header_chunk(
+        config: BitcoinIndexerConfig,
+        network_id: BitcoinNetworkType,
+        chunk: &HeaderChunk,
+    ) -> Result<Vec<LoneBlockHeader>, btc_error> {
+        let mut indexer = BitcoinIndexer::new(config, BitcoinIndexerRuntime::new(network_id));
+        indexer.reconnect_peer()?;
+        indexer.peer_handshake()?;
+
+        let mut fetcher = HeaderChunkFetcher::new(chunk.clone());
+        let mut keep_going = fetcher.begin_session(&mut indexer)?;
+        while keep_going {
+            let msg = indexer.recv_message()?;
+            keep_going = match indexer.handle_message(msg, Some(&mut fetcher)) {
+                Ok(do_continue) => do_continue,
+                Err(btc_error::UnhandledMessage(m)) => {
+                    debug!("Unhandled message {:?}", m);
+                    true
+                }
+                Err(e) => {
+                    return Err(e);
                 }
+            };
+        }
+        fetcher.into_headers()
+    }
 
-                // copy over new chain work
-                let orig_highest_interval = orig_spv_client.find_highest_work_score_interval()?;
-                let reorg_highest_interval = reorg_spv_client.find_highest_work_score_interval()?;
-                for interval in cmp::min(orig_highest_interval, reorg_highest_interval)
-                    ..(cmp::max(orig_highest_interval, reorg_highest_interval) + 1)
-                {
-                    if let Some(work_score) = reorg_spv_client.find_interval_work(interval)? {
-                        test_debug!(
-                            "Copy work score for interval {} ({}) to original SPV client DB",
-                            interval,
-                            &work_score
-                        );
-                        orig_spv_client
-                            .store_interval_work(interval, work_score)
-                            .expect("FATAL: failed to store better chain work");
-                    }
+    /// Download header chunks from `num_peers` peers in parallel, and store them with
+    /// `spv_client` in order.  `fetch` downloads a chunk from a given peer (numbered from 0).
+    /// Each peer downloads one chunk at a time, and at most `max_in_flight` peers download at
+    /// once.  A chunk that fails to download or validate, or that takes longer than
+    /// `stall_timeout` to arrive, is fetched again from a peer that hasn't tried it yet.  A
+    /// stalled peer gets no more work unless it eventually finishes.  If every peer fails on a
+    /// chunk, the last error is returned (and the chunks before it remain stored).
+    fn fetch_header_chunks<F>(
+        spv_client: &mut SpvClient,
+        chunks: Vec<HeaderChunk>,
+        num_peers: usize,
+        max_in_flight: usize,
+        stall_timeout: Duration,
+        fetch: F,
+    ) -> Result<(), btc_error>
+    where
+        F: Fn(usize, &HeaderChunk) -> Result<Vec<LoneBlockHeader>, btc_error>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let fetch = Arc::new(fetch);
+        let (result_send, result_recv) = channel();
+
+        let mut pending: VecDeque<usize> = (0..chunks.len()).collect();
+        let mut tried: Vec<HashSet<usize>> = vec![HashSet::new(); chunks.len()];
+        let mut downloaded: HashMap<usize, Vec<LoneBlockHeader>> = HashMap::new();
+        let mut idle: Vec<usize> = (0..num_peers).collect();
+        // peer --> (chunk index, when it was requested)
+        let mut in_flight: HashMap<usize, (usize, Instant)> = HashMap::new();
+        let mut stalled: HashSet<usize> = HashSet::new();
+        let mut next_chunk = 0;
+        let mut last_error = None;
+
+        while next_chunk < chunks.len() {
+            // hand out chunks to idle peers
+            let mut still_idle = vec![];
+            for peer in idle.drain(..) {
+                if in_flight.len() - stalled.len() >= max_in_flight {
+                    still_idle.push(peer);
+                    continue;
                 }
-            } else {
-                // ignore the reorg
-                test_debug!("Reorg chain does not overtake original Bitcoin chain");
-                new_tip = orig_spv_client.get_headers_height()?;
+                let pos = match pending.iter().position(|idx| !tried[*idx].contains(&peer)) {
+                    Some(pos) => pos,
+                    None => {
+                        still_idle.push(peer);
+                        continue;
+                    }
+                };
+                let chunk_idx = pending.remove(pos).expect("BUG: pending chunk disappeared");
+                tried[chunk_idx].insert(peer);
+
+                let chunk = chunks[chunk_idx].clone();
+                let fetch = fetch.clone();
+                let result_send = result_send.clone();
+                thread::spawn(move || {
+                    let res = fetch(peer, &chunk);
+                    let _ = result_send.send((peer, chunk_idx, res));
+                });
+                in_flight.insert(peer, (chunk_idx, Instant::now()));
             }
-        }
-
-        let hdr_reorg = reorg_spv_client.read_block_header(new_tip)?;
-        let hdr_canonical = orig_spv_client.read_block_header(new_tip)?;
-        assert_eq!(hdr_reorg, hdr_canonical);
-
-        Ok(new_tip)
-    }
-
-    #[cfg(test)]
-    pub fn raw_store_header(&mut self, header: BurnchainBlockHeader) -> Result<(), btc_error> {
-        let mut spv_client = SpvClient::new(
-            &self.config.spv_headers_path,
-            self.config.first_block,
-            None,
-            self.runtime.network_id,
-            true,
-            false,
-        )?;
-        spv_client.disable_check_txcount();
-
-        let hdr = LoneBlockHeader {
-            header: BitcoinIndexer::mock_bitcoin_header(
-                &header.parent_block_hash,
-                header.timestamp as u32,
-            ),
-            tx_count: VarInt(header.num_txs),
-        };
+            idle = still_idle;
 
-        assert!(header.block_height > 0);
-        let start_height = header.block_height - 1;
-        spv_client.insert_block_headers_after(start_height, vec![hdr])?;
-        Ok(())
-    }
+            if in_flight.len() == stalled.len() {
+                // every peer has failed to give us some chunk
+                return Err(last_error.unwrap_or(btc_error::InvalidReply));
+            }
 
-    #[cfg(test)]
-    pub fn mock_bitcoin_header(
-        parent_block_hash: &BurnchainHeaderHash,
-        timestamp: u32,
-    ) -> BlockHeader {
-        BlockHeader {
-            bits: 0,
-            merkle_root: Sha256dHash([0u8; 32]),
-            nonce: 0,
-            prev_blockhash: parent_block_hash.to_bitcoin_hash(),
-            time: timestamp,
-            version: 0x20000000,
-        }
-    }
+            // wait for the next result, or for the next in-flight download to stall
+            let now = Instant::now();
+            let wait = in_flight
+                .iter()
+                .filter(|(peer, _)| !stalled.contains(*peer))
+                .map(|(_, (_, start))| (*start + stall_timeout).saturating_duration_since(now))
+                .min()
+                .unwrap_or(stall_timeout);
+
+            let (peer, chunk_idx, res) = match result_recv.recv_timeout(wait) {
+                Ok(result) => result,
+                Err(RecvTimeoutError::Timeout) => {
+                    let now = Instant::now();
+                    for (peer, (chunk_idx, start)) in in_flight.iter() {
+                        if stalled.contains(peer) || *start + stall_timeout > now {
+                            continue;
+                        }
+                        warn!(
+                            "Peer {} stalled downloading headers {}-{}",
+                            peer, chunks[*chunk_idx].start_height, chunks[*chunk_idx].end_height
+                        );
+                        stalled.insert(*peer);
+                        let delivered =
+                            *chunk_idx < next_chunk || downloaded.contains_key(chunk_idx);
+                        if !delivered && !pending.contains(chunk_idx) {
+                            pending.push_back(*chunk_idx);
+                        }
+                        last_error = Some(btc_error::TimedOut);
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    panic!("FATAL: header download threads disconnected");
+                }
+            };
+            in_flight.remove(&peer);
+            stalled.remove(&peer);
+            idle.push(peer);
 
-    /// Verify that the last block header we have is within 2 hours of now.
-    /// Return burnchain_error::TrySyncAgain if not, and delete the offending header
-    pub fn check_chain_tip_timestamp(&mut self) -> Result<(), burnchain_error> {
-        // if there was no target block height, then verify that the highest header fetched is within
-        // 2 hours of now.  Remove headers that don't meet this criterion.
-        let highest_header_height = self.get_highest_header_height()?;
-        if highest_header_height == 0 {
-            return Err(burnchain_error::TrySyncAgain);
-        }
+            if chunk_idx < next_chunk || downloaded.contains_key(&chunk_idx) {
+                // someone else already delivered this chunk
+                continue;
+            }
 
-        let highest_header = self
-            .read_headers(highest_header_height, highest_header_height + 1)?
-            .pop()
-            .expect("FATAL: no header at highest known height");
-        let now = get_epoch_time_secs();
-        if now - 2 * 60 * 60 <= (highest_header.block_header.header.time as u64)
-            && (highest_header.block_header.header.time as u64) <= now + 2 * 60 * 60
-        {
-            // we're good
-            return Ok(());
-        }
-        warn!(
-            "Header at height {} is not wihtin 2 hours of now (is at {})",
-            highest_header_height, highest_header.block_header.header.time
-        );
-        self.drop_headers(highest_header_height.saturating_sub(1))?;
-        return Err(burnchain_error::TrySyncAgain);
-    }
-}
+            match res.and_then(|headers| {
+                chunks[chunk_idx].validate(&headers)?;
+                Ok(headers)
+            }) {
+                Ok(headers) => {
+                    pending.retain(|idx| *idx != chunk_idx);
+                    downloaded.insert(chunk_idx, headers);
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to download headers {}-{} from peer {}: {:?}",
+                        chunks[chunk_idx].start_height, chunks[chunk_idx].end_height, peer, &e
+                    );
+                    let retrying = pending.contains(&chunk_idx)
+                        || in_flight
+                            .iter()
+                            .any(|(other, (idx, _))| *idx == chunk_idx && !stalled.contains(other));
+                    if !retrying {
+                        pending.push_back(chunk_idx);
+                    }
+                    last_error = Some(e);
+                }
+            }
 
-impl Drop for BitcoinIndexer {
-    fn drop(&mut self) {
-        match self.runtime.sock {
-            Some(ref mut s) => {
-                let _ = s.shutdown(Shutdown::Both);
+            // store whatever we can, in order
+            while let Some(headers) = downloaded.remove(&next_chunk) {
+                if let Err(e) = spv_client.insert_header_chunk(&chunks[next_chunk], headers) {
+                    warn!(
+                        "Failed to store headers {}-{}: {:?}",
+                        chunks[next_chunk].start_height, chunks[next_chunk].end_height, &e
+                    );
+                    pending.push_front(next_chunk);
+                    last_error = Some(e);
+                    break;
+                }
+                next_chunk += 1;
+                spv_client.report_progress(chunks[chunks.len() - 1].end_height)?;
             }
-            None => {}
         }
-    }
-}
-
-impl BurnchainIndexer for BitcoinIndexer {
-    type P = BitcoinBlockParser;
-
-    /// Connect to the Bitcoin peer network.
-    /// Use the peer host and peer port given in the config file,
-    /// and loaded in on setup.
-    fn connect(&mut self) -> Result<(), burnchain_error> {
-        self.reconnect_peer().map_err(burnchain_error::Bitcoin)
-    }
-
-    /// Get the location on disk where we keep headers
-    fn get_headers_path(&self) -> String {
-        self.config.spv_headers_path.clone()
+        Ok(())
     }
 
-    /// Get the number of headers we have
-    fn get_headers_height(&self) -> Result<u64, burnchain_error> {
+    /// Open the headers DB read-only, to look up headers
+    pub fn open_headers_readonly(&self) -> Result<SpvClient, btc_error> {
         let spv_client = SpvClient::new(
             &self.config.spv_headers_path,
             0,
@@ -952,709 +2838,2337 @@ impl BurnchainIndexer for BitcoinIndexer {
             self.runtime.network_id,
             false,
             false,
-        )
-        .map_err(burnchain_error::Bitcoin)?;
-        spv_client
-            .get_headers_height()
-            .map_err(burnchain_error::Bitcoin)
+        )?;
+        if self.config.spv_mmap_size > 0 {
+            spv_client.set_mmap_size(self.config.spv_mmap_size)?;
+        }
+        Ok(spv_client)
     }
 
-    fn get_highest_header_height(&self) -> Result<u64, burnchain_error> {
-        let spv_client = SpvClient::new(
-            &self.config.spv_headers_path,
-            0,
-            None,
-            self.runtime.network_id,
-            false,
-            false,
+    #[cfg(test)]
+    fn new_reorg_spv_client(
+        reorg_headers_path: &str,
+        start_block: u64,
+        end_block: Option<u64>,
+        network_id: BitcoinNetworkType,
+        genesis_header: Option<BlockHeader>,
+    ) -> Result<SpvClient, btc_error> {
+        if genesis_header.is_some() {
+            return SpvClient::new_with_genesis(
+                &reorg_headers_path,
+                start_block,
+                end_block,
+                network_id,
+                genesis_header,
+                true,
+                true,
+            );
+        }
+        SpvClient::new_without_migration(
+            &reorg_headers_path,
+            start_block,
+            end_block,
+            network_id,
+            true,
+            true,
         )
-        .map_err(burnchain_error::Bitcoin)?;
-        spv_client
-            .get_highest_header_height()
-            .map_err(burnchain_error::Bitcoin)
     }
 
-    /// Get the first block height
-    fn get_first_block_height(&self) -> u64 {
-        self.config.first_block
+    #[cfg(not(test))]
+    fn new_reorg_spv_client(
+        reorg_headers_path: &str,
+        start_block: u64,
+        end_block: Option<u64>,
+        network_id: BitcoinNetworkType,
+        genesis_header: Option<BlockHeader>,
+    ) -> Result<SpvClient, btc_error> {
+        SpvClient::new_with_genesis(
+            &reorg_headers_path,
+            start_block,
+            end_block,
+            network_id,
+            genesis_header,
+            true,
+            true,
+        )
     }
 
-    /// Get the first block header hash
-    fn get_first_block_header_hash(&self) -> Result<BurnchainHeaderHash, burnchain_error> {
-        let spv_client = SpvClient::new(
-            &self.config.spv_headers_path,
-            0,
-            None,
-            self.runtime.network_id,
-            false,
-            false,
-        )?;
-        let first_block_height = self.get_first_block_height();
-        let first_header = spv_client
-            .read_block_header(first_block_height)?
-            .expect("BUG: no first block header hash");
-
-        let first_block_header_hash =
-            BurnchainHeaderHash::from_bitcoin_hash(&first_header.header.bitcoin_hash());
-        Ok(first_block_header_hash)
-    }
+    /// Create a SPV client for starting reorg processing
+    fn setup_reorg_headers(
+        &mut self,
+        canonical_spv_client: &SpvClient,
+        reorg_headers_path: &str,
+        start_block: u64,
+        remove_old: bool,
+    ) -> Result<SpvClient, btc_error> {
+        if remove_old {
+            if PathBuf::from(&reorg_headers_path).exists() {
+                fs::remove_file(&reorg_headers_path).map_err(|e| {
+                    error!("Failed to remove {}", reorg_headers_path);
+                    btc_error::Io(e)
+                })?;
+            }
+        }
 
-    /// Get the first block header timestamp
-    fn get_first_block_header_timestamp(&self) -> Result<u64, burnchain_error> {
-        let spv_client = SpvClient::new(
-            &self.config.spv_headers_path,
-            0,
-            None,
+        // bootstrap reorg client
+        let mut reorg_spv_client = BitcoinIndexer::new_reorg_spv_client(
+            reorg_headers_path,
+            start_block,
+            Some(start_block + REORG_BATCH_SIZE),
             self.runtime.network_id,
-            false,
-            false,
+            self.get_genesis_header()?,
         )?;
-        let first_block_height = self.get_first_block_height();
-        let first_header = spv_client
-            .read_block_header(first_block_height)?
-            .expect("BUG: no first block header timestamp");
-
-        let first_block_header_timestamp = first_header.header.time as u64;
-        Ok(first_block_header_timestamp)
-    }
+        reorg_spv_client.set_checkpoints(self.get_checkpoints()?);
+
+        // a headers DB that starts from a first burn block doesn't have the two difficulty
+        // intervals of headers below `start_block` to copy until it's synced that far past it
+        let pruned_height = canonical_spv_client.get_pruned_height()?;
+        if pruned_height > 0 && start_block < pruned_height + 2 * BLOCK_DIFFICULTY_CHUNK_SIZE {
+            if remove_old {
+                // start the .reorg db from the same header
+                let anchor = canonical_spv_client
+                    .read_block_header(pruned_height)?
+                    .ok_or(btc_error::MissingHeader)?;
+                let pruned_work = canonical_spv_client
+                    .find_interval_work(pruned_height / BLOCK_DIFFICULTY_CHUNK_SIZE - 1)?
+                    .ok_or(btc_error::InvalidChainWork)?;
+                reorg_spv_client.init_from_anchor(
+                    pruned_height,
+                    anchor.header,
+                    pruned_work + anchor.header.work(),
+                )?;
 
-    /// Get a vector of the stacks epochs. This notion of epochs is dependent on the burn block height.
-    /// Valid epochs include stacks 1.0, stacks 2.0, stacks 2.05, and so on.
-    ///
-    /// Choose according to:
-    /// 1) Use the custom epochs defined on the underlying `BitcoinIndexerConfig`, if they exist.
-    /// 2) Use hard-coded static values, otherwise.
-    ///
-    /// It is an error (panic) to set custom epochs if running on `Mainnet`.
-    fn get_stacks_epochs(&self) -> Vec<StacksEpoch> {
-        match self.config.epochs {
-            Some(ref epochs) => {
-                assert!(self.runtime.network_id != BitcoinNetworkType::Mainnet);
-                epochs.clone()
+                let headers =
+                    canonical_spv_client.read_block_headers(pruned_height + 1, start_block + 1)?;
+                debug!(
+                    "Copy headers {}-{}",
+                    pruned_height,
+                    pruned_height + headers.len() as u64
+                );
+                reorg_spv_client.insert_block_headers_after(pruned_height, headers)?;
             }
-            None => get_bitcoin_stacks_epochs(self.runtime.network_id),
-        }
-    }
+        } else if start_block > 0 {
+            if start_block > BLOCK_DIFFICULTY_CHUNK_SIZE {
+                if remove_old {
+                    // set up a .reorg db
+                    // * needs the last difficulty interval of headers (note that the current
+                    // interval is `start_block / BLOCK_DIFFICULTY_CHUNK_SIZE - 1).
+                    // * needs the last interval's chain work calculation
+                    let interval_start_block =
+                        (start_block / BLOCK_DIFFICULTY_CHUNK_SIZE).saturating_sub(2);
+                    let base_block = interval_start_block * BLOCK_DIFFICULTY_CHUNK_SIZE;
 
-    /// Read downloaded headers within a range
-    fn read_headers(
-        &self,
-        start_block: u64,
-        end_block: u64,
-    ) -> Result<Vec<BitcoinHeaderIPC>, burnchain_error> {
-        let spv_client = SpvClient::new(
-            &self.config.spv_headers_path,
-            0,
-            None,
-            self.runtime.network_id,
-            false,
-            false,
-        )?;
+                    if base_block > 0 {
+                        let interval_headers =
+                            canonical_spv_client.read_block_headers(base_block, start_block + 1)?;
+                        assert!(
+                            interval_headers.len() >= (start_block - base_block) as usize,
+                            "BUG: missing headers for {}-{}",
+                            base_block,
+                            start_block
+                        );
 
-        let headers = spv_client.read_block_headers(start_block, end_block)?;
-        let mut ret_headers: Vec<BitcoinHeaderIPC> = vec![];
-        for i in 0..headers.len() {
-            ret_headers.push({
-                BitcoinHeaderIPC {
-                    block_header: headers[i].clone(),
-                    block_height: (i as u64) + start_block,
+                        debug!(
+                            "Copy headers {}-{}",
+                            base_block,
+                            base_block + interval_headers.len() as u64
+                        );
+                        reorg_spv_client
+                            .insert_block_headers_before(base_block - 1, interval_headers)?;
+                    } else {
+                        let interval_headers =
+                            canonical_spv_client.read_block_headers(1, start_block + 1)?;
+                        assert!(
+                            interval_headers.len() >= start_block as usize,
+                            "BUG: missing headers for 1-{}",
+                            start_block
+                        );
+
+                        debug!("Copy headers 1-{}", interval_headers.len() as u64);
+                        reorg_spv_client.insert_block_headers_before(0, interval_headers)?;
+                    }
+
+                    let last_interval = canonical_spv_client.find_highest_work_score_interval()?;
+
+                    // copy over the relevant difficulty intervals as well
+                    for interval in interval_start_block..(last_interval + 1) {
+                        test_debug!("Copy interval {} to {}", interval, &reorg_headers_path);
+                        let work_score = canonical_spv_client
+                            .find_interval_work(interval)?
+                            .expect(&format!("FATAL: no work score for interval {}", interval));
+                        reorg_spv_client.store_interval_work(interval, work_score)?;
+                    }
                 }
-            });
+            } else {
+                // no full difficulty intervals yet
+                let interval_headers =
+                    canonical_spv_client.read_block_headers(1, start_block + 1)?;
+                reorg_spv_client.insert_block_headers_before(0, interval_headers)?;
+            }
         }
-        Ok(ret_headers)
+
+        Ok(reorg_spv_client)
     }
 
-    /// Identify underlying reorgs and return the block height of the highest block in common
-    /// between the remote node and our block headers.
-    fn find_chain_reorg(&mut self) -> Result<u64, burnchain_error> {
-        let headers_path = self.config.spv_headers_path.clone();
-        let reorg_path = format!("{}.reorg", &self.config.spv_headers_path);
-        self.find_bitcoin_reorg(
-            &headers_path,
-            &reorg_path,
-            |ref mut indexer, ref mut spv_client, start_block, end_block_opt| {
-                spv_client.set_scan_range(start_block, end_block_opt);
-                spv_client.run(indexer)
-            },
+    /// Search for a bitcoin reorg.  Return the offset into the canonical bitcoin headers where
+    /// the reorg starts.  Returns the hight of the highest common ancestor.
+    /// Note that under certain testnet settings, the bitcoin chain itself can shrink.
+    pub fn find_bitcoin_reorg<F>(
+        &mut self,
+        canonical_headers_path: &str,
+        reorg_headers_path: &str,
+        load_reorg_headers: F,
+    ) -> Result<u64, btc_error>
+    where
+        F: FnMut(&mut BitcoinIndexer, &mut SpvClient, u64, Option<u64>) -> Result<(), btc_error>,
+    {
+        // always check chain work, except in testing
+        self.inner_find_bitcoin_reorg(
+            canonical_headers_path,
+            reorg_headers_path,
+            load_reorg_headers,
+            true,
         )
-        .map_err(|e| match e {
-            btc_error::TimedOut => burnchain_error::TrySyncAgain,
-            x => burnchain_error::Bitcoin(x),
-        })
     }
 
-    /// Download and store all headers between two block heights
-    /// end_heights, if given, is inclusive.
-    /// Returns the height of the last header fetched
-    fn sync_headers(
+    fn inner_find_bitcoin_reorg<F>(
         &mut self,
-        start_height: u64,
-        end_height: Option<u64>,
-    ) -> Result<u64, burnchain_error> {
-        if end_height.is_some() && end_height <= Some(start_height) {
-            return Ok(end_height.unwrap());
-        }
-
-        let new_height = self
-            .sync_last_headers(start_height, end_height)
-            .map_err(|e| match e {
-                btc_error::TimedOut => burnchain_error::TrySyncAgain,
-                x => burnchain_error::Bitcoin(x),
-            })?;
-
-        // make sure the headers are up-to-date if we have no target height
-        if end_height.is_none() {
-            self.check_chain_tip_timestamp()?;
-        }
-        Ok(new_height)
-    }
+        canonical_headers_path: &str,
+        reorg_headers_path: &str,
+        mut load_reorg_headers: F,
+        check_chain_work: bool,
+    ) -> Result<u64, btc_error>
+    where
+        F: FnMut(&mut BitcoinIndexer, &mut SpvClient, u64, Option<u64>) -> Result<(), btc_error>,
+    {
+        let mut new_tip = 0;
+        let mut found_common_ancestor = false;
 
-    /// Drop headers after a given height -- i.e. to accomodate a reorg
-    fn drop_headers(&mut self, new_height: u64) -> Result<(), burnchain_error> {
-        let mut spv_client = SpvClient::new(
-            &self.config.spv_headers_path,
+        let mut orig_spv_client = SpvClient::new_with_genesis(
+            canonical_headers_path,
             0,
             None,
             self.runtime.network_id,
+            self.get_genesis_header()?,
             true,
             false,
-        )
-        .map_err(burnchain_error::Bitcoin)?;
-        spv_client
-            .drop_headers(new_height)
-            .map_err(burnchain_error::Bitcoin)
-    }
-
-    fn downloader(&self) -> BitcoinBlockDownloader {
-        BitcoinBlockDownloader::new(self.dup())
-    }
-
-    fn parser(&self) -> BitcoinBlockParser {
-        BitcoinBlockParser::new(self.runtime.network_id, self.config.magic_bytes)
-    }
+        )?;
 
-    fn reader(&self) -> BitcoinIndexer {
-        self.dup()
-    }
-}
+        // what's the last header we have from the canonical history?
+        let canonical_end_block = orig_spv_client.get_headers_height().map_err(|e| {
+            error!(
+                "Failed to get the last block from {}",
+                canonical_headers_path
+            );
+            e
+        })?;
 
-impl BurnchainHeaderReader for BitcoinIndexer {
-    fn read_burnchain_headers(
-        &self,
-        start_height: u64,
-        end_height: u64,
-    ) -> Result<Vec<BurnchainBlockHeader>, DBError> {
-        let hdrs = self
-            .read_headers(start_height, end_height)
-            .map_err(|e| DBError::Other(format!("Burnchain error: {:?}", &e)))?;
+        // headers below the lowest one we have (e.g. below the first burn block) can't reorg
+        let lowest_block = orig_spv_client.get_pruned_height()?;
 
-        Ok(hdrs
-            .into_iter()
-            .map(|hdr| BurnchainBlockHeader {
-                block_height: hdr.block_height,
-                block_hash: BurnchainHeaderHash::from_bitcoin_hash(&Sha256dHash(hdr.header_hash())),
-                parent_block_hash: BurnchainHeaderHash::from_bitcoin_hash(
-                    &hdr.block_header.header.prev_blockhash,
-                ),
-                num_txs: hdr.block_header.tx_count.0,
-                timestamp: hdr.block_header.header.time as u64,
-            })
-            .collect())
-    }
+        // bootstrap reorg client
+        let mut start_block = cmp::max(
+            canonical_end_block.saturating_sub(REORG_BATCH_SIZE),
+            lowest_block,
+        );
+        let mut reorg_spv_client =
+            self.setup_reorg_headers(&orig_spv_client, reorg_headers_path, start_block, true)?;
+        let mut discontiguous_header_error_count = 0;
 
-    fn get_burnchain_headers_height(&self) -> Result<u64, DBError> {
-        self.get_headers_height()
-            .map_err(|e| DBError::Other(format!("Burnchain error: {:?}", &e)))
-    }
+        while !found_common_ancestor {
+            debug!(
+                "Search for reorg'ed Bitcoin headers from {} - {}",
+                start_block,
+                start_block + REORG_BATCH_SIZE
+            );
 
-    fn find_burnchain_header_height(
-        &self,
-        burn_header_hash: &BurnchainHeaderHash,
-    ) -> Result<Option<u64>, DBError> {
-        let spv_client = SpvClient::new(
-            &self.config.spv_headers_path,
-            0,
-            None,
-            self.runtime.network_id,
-            false,
-            false,
-        )
-        .map_err(|e| DBError::Other(format!("Burnchain error: {:?}", &e)))?;
-        spv_client
-            .find_block_header_height(burn_header_hash)
-            .map_err(|e| DBError::Other(format!("Burnchain error: {:?}", &e)))
-    }
-}
+            // get new headers, starting off of start_block.  Feed them into the given
+            // reorg_spv_client.
+            match load_reorg_headers(
+                self,
+                &mut reorg_spv_client,
+                start_block,
+                Some(start_block + REORG_BATCH_SIZE),
+            ) {
+                Ok(_) => {}
+                Err(btc_error::NoncontiguousHeader)
+                | Err(btc_error::InvalidPoW)
+                | Err(btc_error::TimestampTooOld(_)) => {
+                    warn!(
+                        "Received invalid headers from {} - {} -- possible reorg in progress",
+                        start_block,
+                        start_block + REORG_BATCH_SIZE
+                    );
+                    if start_block <= lowest_block {
+                        // reorg all the way back to genesis (or the first burn block)
+                        new_tip = lowest_block;
+                        break;
+                    }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::burnchains::bitcoin::Error as btc_error;
-    use crate::burnchains::bitcoin::*;
-    use crate::burnchains::Error as burnchain_error;
-    use crate::burnchains::*;
+                    // try again
+                    discontiguous_header_error_count += 1;
+                    start_block = cmp::max(
+                        start_block
+                            .saturating_sub(REORG_BATCH_SIZE * discontiguous_header_error_count),
+                        lowest_block,
+                    );
+                    reorg_spv_client = self.setup_reorg_headers(
+                        &orig_spv_client,
+                        reorg_headers_path,
+                        start_block,
+                        false,
+                    )?;
+                    continue;
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to fetch Bitcoin headers from {} - {}: {:?}",
+                        start_block,
+                        start_block + REORG_BATCH_SIZE,
+                        &e
+                    );
+                    return Err(e);
+                }
+            }
 
-    use stacks_common::deps_common::bitcoin::blockdata::block::{BlockHeader, LoneBlockHeader};
-    use stacks_common::deps_common::bitcoin::network::encodable::VarInt;
-    use stacks_common::deps_common::bitcoin::network::serialize::{
-        deserialize, serialize, BitcoinHash,
-    };
-    use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
-    use stacks_common::util::get_epoch_time_secs;
-    use stacks_common::util::uint::Uint256;
+            let reorg_headers = reorg_spv_client
+                .get_headers_range(start_block, REORG_BATCH_SIZE)
+                .map_err(|e| {
+                    error!(
+                        "Failed to read reorg Bitcoin headers from {} to {}",
+                        start_block,
+                        start_block + REORG_BATCH_SIZE
+                    );
+                    e
+                })?;
 
-    use std::env;
+            if reorg_headers.len() == 0 {
+                // chain shrank considerably
+                info!(
+                    "Missing Bitcoin headers in block range {}-{} -- did the Bitcoin chain shrink?",
+                    start_block,
+                    start_block + REORG_BATCH_SIZE
+                );
+                if start_block <= lowest_block {
+                    // reorg chain is empty
+                    new_tip = lowest_block;
+                    break;
+                }
 
-    #[test]
-    fn test_indexer_find_bitcoin_reorg_genesis() {
-        let path_1 = "/tmp/test-indexer-find_bitcoin_reorg_genesis.dat";
-        let path_2 = "/tmp/test-indexer-find_bitcoin_reorg_genesis.dat.reorg.bak";
-        let path_reorg = "/tmp/test-indexer-find_bitcoin_reorg_genesis.dat.reorg";
+                start_block = cmp::max(start_block.saturating_sub(REORG_BATCH_SIZE), lowest_block);
+                reorg_spv_client.set_scan_range(start_block, Some(start_block + REORG_BATCH_SIZE));
+                continue;
+            }
 
-        if fs::metadata(path_1).is_ok() {
-            fs::remove_file(path_1).unwrap();
-        }
-        if fs::metadata(path_2).is_ok() {
-            fs::remove_file(path_2).unwrap();
-        }
-        if fs::metadata(path_reorg).is_ok() {
-            fs::remove_file(path_reorg).unwrap();
-        }
+            // got reorg headers.  Find the equivalent headers in our canonical history
+            let canonical_headers = orig_spv_client
+                .get_headers_range(start_block, REORG_BATCH_SIZE)
+                .map_err(|e| {
+                    error!(
+                        "Failed to read canonical headers from {} to {}",
+                        start_block,
+                        start_block + REORG_BATCH_SIZE
+                    );
+                    e
+                })?;
 
-        // two header sets -- both of which build off of the genesis block
-        let headers_1 = vec![
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "20bee96458517fc5082a9720ce6207b5742f2b18e4e0a7e7373342725d80f88c",
-                    )
-                    .unwrap(),
-                    nonce: 2,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
-                    )
-                    .unwrap(),
-                    time: 1587626881,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "39d1a6f1ee7a5903797f92ec89e4c58549013f38114186fc2eb6e5218cb2d0ac",
-                    )
-                    .unwrap(),
-                    nonce: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "606d31daaaa5919f3720d8440dd99d31f2a4e4189c65879f19ae43268425e74b",
-                    )
-                    .unwrap(),
-                    time: 1587626882,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "a7e04ed25f589938eb5627abb7b5913dd77b8955bcdf72d7f111d0a71e346e47",
-                    )
-                    .unwrap(),
-                    nonce: 4,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "2fa2f451ac27f0e5cd3760ba6cdf34ef46adb76a44d96bc0f3bf3e713dd955f0",
-                    )
-                    .unwrap(),
-                    time: 1587626882,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-        ];
+            assert!(
+                canonical_headers.len() > 0,
+                "BUG: uninitialized canonical SPV headers DB"
+            );
 
-        let headers_2 = vec![
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "677351ef5cd586c8d0ee7c242e0c5794d0bb4564107e567fd24e508aa66c8b79",
-                    )
-                    .unwrap(),
-                    nonce: 0,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
-                    )
-                    .unwrap(),
-                    time: 1587612061,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "a92e6612c0cde9b029081d90b1dcef97f95508b92dd982223c8fcbe9e953fc79",
-                    )
-                    .unwrap(),
-                    nonce: 0,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "0f4865e8169da0cb265ab0ea9eef440e6b7cc9bc1d5e74e4627c0f1e83e67e95",
-                    )
-                    .unwrap(),
-                    time: 1587612062,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "5bd6f6f0863582bb6910d772829b7cf36be262b74ac5775ef9d78180c90a9fef",
-                    )
-                    .unwrap(),
-                    nonce: 0,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "2dbbe38703af1918ef4091dfea226db8868843a55a4673e3d2d7259083b07063",
-                    )
-                    .unwrap(),
-                    time: 1587612062,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-        ];
+            let max_headers_len = if canonical_headers.len() < reorg_headers.len() {
+                canonical_headers.len()
+            } else {
+                reorg_headers.len()
+            };
+            let max_height = start_block + (max_headers_len as u64);
 
-        let mut spv_client =
-            SpvClient::new(path_1, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
-        let mut spv_client_reorg =
-            SpvClient::new(path_2, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+            // scan for common ancestor, but excluding the block we wrote to bootstrap the
+            // reorg_spv_client.
+            for i in (start_block + 1..max_height).rev() {
+                if canonical_headers[(i - start_block) as usize].header
+                    == reorg_headers[(i - start_block) as usize].header
+                {
+                    // found common ancestor
+                    debug!(
+                        "Found common Bitcoin block ancestor at height {}: {:?}",
+                        i,
+                        &canonical_headers[(i - start_block) as usize].header
+                    );
+                    new_tip = i;
+                    found_common_ancestor = true;
+                    break;
+                } else {
+                    debug!(
+                        "Diverged headers at {}: {:?} != {:?}",
+                        i,
+                        &canonical_headers[(i - start_block) as usize].header,
+                        &reorg_headers[(i - start_block) as usize].header
+                    );
+                }
+            }
+            if found_common_ancestor {
+                break;
+            }
 
-        spv_client
-            .insert_block_headers_after(0, headers_1.clone())
-            .unwrap();
-        spv_client_reorg
-            .insert_block_headers_after(0, headers_2.clone())
-            .unwrap();
+            debug!(
+                "No common ancestor found between Bitcoin headers {}-{}",
+                start_block, max_height
+            );
 
-        spv_client.update_chain_work().unwrap();
-        spv_client_reorg.update_chain_work().unwrap();
+            if start_block <= lowest_block {
+                new_tip = lowest_block;
+                break;
+            }
 
-        assert_eq!(spv_client.read_block_headers(0, 10).unwrap().len(), 4);
-        assert_eq!(spv_client_reorg.read_block_headers(0, 10).unwrap().len(), 4);
+            // try again
+            start_block = cmp::max(start_block.saturating_sub(REORG_BATCH_SIZE), lowest_block);
+            reorg_spv_client =
+                self.setup_reorg_headers(&orig_spv_client, reorg_headers_path, start_block, false)?;
+        }
 
-        assert_eq!(spv_client_reorg.read_block_headers(2, 10).unwrap().len(), 2);
+        if check_chain_work {
+            let reorg_total_work = reorg_spv_client.update_chain_work()?;
+            let orig_total_work = orig_spv_client.update_chain_work()?;
 
-        let mut indexer = BitcoinIndexer::new(
-            BitcoinIndexerConfig::test_default(path_1.to_string()),
-            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
-        );
-        let common_ancestor_height = indexer
-            .inner_find_bitcoin_reorg(
-                path_1,
-                path_reorg,
-                |ref mut indexer, ref mut spv_client, start_block, end_block_opt| {
-                    // mock the bitcoind by just copying over the relevant headers from our backup reorg db
-                    let end_block = end_block_opt.unwrap_or(10000000);
-                    let hdrs = spv_client_reorg
-                        .read_block_headers(start_block, end_block)
-                        .unwrap();
-
-                    if start_block > 0 {
-                        test_debug!("insert at {}: {:?}", start_block - 1, &hdrs);
-                        spv_client
-                            .insert_block_headers_before(start_block - 1, hdrs)
-                            .unwrap();
-                    } else if hdrs.len() > 0 {
-                        test_debug!("insert at {}: {:?}", 0, &hdrs);
-                        spv_client.test_write_block_headers(0, hdrs).unwrap();
-                    }
+            debug!("Bitcoin headers history is consistent up to {}", new_tip;
+                   "Orig chainwork" => %orig_total_work,
+                   "Reorg chainwork" => %reorg_total_work);
 
-                    Ok(())
-                },
-                false,
-            )
-            .unwrap();
+            if orig_total_work < reorg_total_work {
+                let reorg_tip = reorg_spv_client.get_headers_height()?;
+                let hdr_reorg = reorg_spv_client
+                    .get_header(reorg_tip - 1)?
+                    .expect("FATAL: no tip hash for existing chain tip");
+                info!(
+                    "New canonical Bitcoin chain found! New tip is {}",
+                    &hdr_reorg.header.bitcoin_hash()
+                );
 
-        // lowest common ancestor is the genesis block
-        assert_eq!(common_ancestor_height, 0);
-    }
+                // merge the new headers and chain difficulty to the original headers
+                let mut orig_spv_client = SpvClient::new_with_genesis(
+                    canonical_headers_path,
+                    0,
+                    None,
+                    self.runtime.network_id,
+                    self.get_genesis_header()?,
+                    true,
+                    false,
+                )?;
+                orig_spv_client.set_checkpoints(self.get_checkpoints()?);
+
+                // don't take a reorg the burnchain DB would refuse
+                if let Some(floor) = self.reorg_floor() {
+                    if new_tip < floor {
+                        warn!(
+                            "Refusing to reorg headers above {} in {}: reorg is below height {}",
+                            new_tip, canonical_headers_path, floor
+                        );
+                        return Err(btc_error::DeepReorg(new_tip));
+                    }
+                }
 
-    #[test]
-    fn test_indexer_find_bitcoin_reorg_midpoint() {
-        let path_1 = "/tmp/test-indexer-find_bitcoin_reorg_midpoint.dat";
-        let path_2 = "/tmp/test-indexer-find_bitcoin_reorg_midpoint.dat.reorg.bak";
-        let path_reorg = "/tmp/test-indexer-find_bitcoin_reorg_midpoint.dat.reorg";
+                // copy over new headers
+                if new_tip > 0 {
+                    let new_headers =
+                        reorg_spv_client.read_block_headers(new_tip, reorg_tip + 1)?;
+                    orig_spv_client.drop_headers(new_tip)?;
+                    orig_spv_client.insert_block_headers_after(new_tip - 1, new_headers)?;
+                }
 
-        if fs::metadata(path_1).is_ok() {
-            fs::remove_file(path_1).unwrap();
-        }
-        if fs::metadata(path_2).is_ok() {
-            fs::remove_file(path_2).unwrap();
+                // copy over new chain work
+                let orig_highest_interval = orig_spv_client.find_highest_work_score_interval()?;
+                let reorg_highest_interval = reorg_spv_client.find_highest_work_score_interval()?;
+                for interval in cmp::min(orig_highest_interval, reorg_highest_interval)
+                    ..(cmp::max(orig_highest_interval, reorg_highest_interval) + 1)
+                {
+                    if let Some(work_score) = reorg_spv_client.find_interval_work(interval)? {
+                        test_debug!(
+                            "Copy work score for interval {} ({}) to original SPV client DB",
+                            interval,
+                            &work_score
+                        );
+                        orig_spv_client
+                            .store_interval_work(interval, work_score)
+                            .expect("FATAL: failed to store better chain work");
+                    }
+                }
+            } else {
+                // ignore the reorg
+                test_debug!("Reorg chain does not overtake original Bitcoin chain");
+                new_tip = orig_spv_client.get_headers_height()?;
+            }
         }
 
-        // two header sets -- both of which build off of same first block
-        let headers_1 = vec![
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "677351ef5cd586c8d0ee7c242e0c5794d0bb4564107e567fd24e508aa66c8b79",
-                    )
-                    .unwrap(),
-                    nonce: 0,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
-                    )
-                    .unwrap(),
-                    time: 1587612061,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "39d1a6f1ee7a5903797f92ec89e4c58549013f38114186fc2eb6e5218cb2d0ac",
-                    )
-                    .unwrap(),
-                    nonce: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "0f4865e8169da0cb265ab0ea9eef440e6b7cc9bc1d5e74e4627c0f1e83e67e95",
-                    )
-                    .unwrap(),
-                    time: 1587626882,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "a7e04ed25f589938eb5627abb7b5913dd77b8955bcdf72d7f111d0a71e346e47",
-                    )
-                    .unwrap(),
-                    nonce: 4,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "7a06268e099dafa4549d9e2511c251497779cf16ab3b5363e5b25d3dd6f552e7",
-                    )
-                    .unwrap(),
-                    time: 1587626882,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-        ];
+        let hdr_reorg = reorg_spv_client.read_block_header(new_tip)?;
+        let hdr_canonical = orig_spv_client.read_block_header(new_tip)?;
+        assert_eq!(hdr_reorg, hdr_canonical);
 
-        let headers_2 = vec![
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "677351ef5cd586c8d0ee7c242e0c5794d0bb4564107e567fd24e508aa66c8b79",
-                    )
-                    .unwrap(),
-                    nonce: 0,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
-                    )
-                    .unwrap(),
-                    time: 1587612061,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "a92e6612c0cde9b029081d90b1dcef97f95508b92dd982223c8fcbe9e953fc79",
-                    )
-                    .unwrap(),
-                    nonce: 0,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "0f4865e8169da0cb265ab0ea9eef440e6b7cc9bc1d5e74e4627c0f1e83e67e95",
-                    )
-                    .unwrap(),
-                    time: 1587612062,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "5bd6f6f0863582bb6910d772829b7cf36be262b74ac5775ef9d78180c90a9fef",
-                    )
-                    .unwrap(),
-                    nonce: 0,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "2dbbe38703af1918ef4091dfea226db8868843a55a4673e3d2d7259083b07063",
-                    )
-                    .unwrap(),
-                    time: 1587612062,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-        ];
+        Ok(new_tip)
+    }
 
-        let mut spv_client =
-            SpvClient::new(path_1, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
-        let mut spv_client_reorg =
-            SpvClient::new(path_2, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+    #[cfg(test)]
+    pub fn raw_store_header(&mut self, header: BurnchainBlockHeader) -> Result<(), btc_error> {
+        let mut spv_client = SpvClient::new(
+            &self.config.spv_headers_path,
+            self.config.first_block,
+            None,
+            self.runtime.network_id,
+            true,
+            false,
+        )?;
+        spv_client.disable_check_txcount();
 
-        spv_client
-            .insert_block_headers_after(0, headers_1.clone())
-            .unwrap();
-        spv_client_reorg
-            .insert_block_headers_after(0, headers_2.clone())
-            .unwrap();
+        let hdr = LoneBlockHeader {
+            header: BitcoinIndexer::mock_bitcoin_header(
+                &header.parent_block_hash,
+                header.timestamp as u32,
+            ),
+            tx_count: VarInt(header.num_txs),
+        };
 
-        assert_eq!(spv_client.read_block_headers(0, 10).unwrap().len(), 4);
-        assert_eq!(spv_client_reorg.read_block_headers(0, 10).unwrap().len(), 4);
+        assert!(header.block_height > 0);
+        let start_height = header.block_height - 1;
+        spv_client.insert_block_headers_after(start_height, vec![hdr])?;
+        Ok(())
+    }
 
-        assert_eq!(spv_client_reorg.read_block_headers(2, 10).unwrap().len(), 2);
+    #[cfg(test)]
+    pub fn mock_bitcoin_header(
+        parent_block_hash: &BurnchainHeaderHash,
+        timestamp: u32,
+    ) -> BlockHeader {
+        BlockHeader {
+            bits: 0,
+            merkle_root: Sha256dHash([0u8; 32]),
+            nonce: 0,
+            prev_blockhash: parent_block_hash.to_bitcoin_hash(),
+            time: timestamp,
+            version: 0x20000000,
+        }
+    }
 
-        let mut indexer = BitcoinIndexer::new(
-            BitcoinIndexerConfig::test_default(path_1.to_string()),
-            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
-        );
-        let common_ancestor_height = indexer
-            .inner_find_bitcoin_reorg(
-                path_1,
-                path_reorg,
-                |ref mut indexer, ref mut spv_client, start_block, end_block_opt| {
-                    // mock the bitcoind by just copying over the relevant headers from our backup reorg db
-                    let end_block = end_block_opt.unwrap_or(10000000);
-                    let hdrs = spv_client_reorg
-                        .read_block_headers(start_block, end_block)
-                        .unwrap();
-                    if start_block > 0 {
-                        spv_client
-                            .insert_block_headers_before(start_block - 1, hdrs)
-                            .unwrap();
-                    } else if hdrs.len() > 0 {
-                        test_debug!("insert at {}: {:?}", 0, &hdrs);
-                        spv_client.test_write_block_headers(0, hdrs).unwrap();
-                    }
-                    Ok(())
-                },
-                false,
-            )
-            .unwrap();
+    /// Verify that the last block header we have is within 2 hours of now.
+    /// Return burnchain_error::TrySyncAgain if not, and delete the offending header
+    pub fn check_chain_tip_timestamp(&mut self) -> Result<(), burnchain_error> {
+        // if there was no target block height, then verify that the highest header fetched is within
+        // 2 hours of now.  Remove headers that don't meet this criterion.
+        let highest_header_height = self.get_highest_header_height()?;
+        if highest_header_height == 0 {
+            return Err(burnchain_error::TrySyncAgain);
+        }
 
-        // lowest common ancestor is the first block
-        assert_eq!(common_ancestor_height, 1);
+        let highest_header = self
+            .read_headers(highest_header_height, highest_header_height + 1)?
+            .pop()
+            .expect("FATAL: no header at highest known height");
+        let now = get_epoch_time_secs();
+        if now - 2 * 60 * 60 <= (highest_header.block_header.header.time as u64)
+            && (highest_header.block_header.header.time as u64) <= now + 2 * 60 * 60
+        {
+            // we're good
+            return Ok(());
+        }
+        warn!(
+            "Header at height {} is not wihtin 2 hours of now (is at {})",
+            highest_header_height, highest_header.block_header.header.time
+        );
+        self.drop_headers(highest_header_height.saturating_sub(1))?;
+        return Err(burnchain_error::TrySyncAgain);
     }
 
-    #[test]
-    fn test_indexer_sync_headers() {
-        if !env::var("BLOCKSTACK_SPV_BITCOIN_HOST").is_ok() {
-            eprintln!(
-                "Skipping test_indexer_sync_headers -- no BLOCKSTACK_SPV_BITCOIN_HOST envar set"
+    /// Get the last block in `burnchain`'s DB that's been fully processed -- i.e. whose operations
+    /// are committed and whose affirmation maps are up-to-date.  This trails our headers, and
+    /// can trail the DB's canonical tip while affirmation maps are being brought up to date.
+    pub fn get_burn_tip(&self, burnchain: &Burnchain) -> Result<BurnTip, burnchain_error> {
+        let burnchain_db = burnchain.open_burnchain_db(false)?;
+        BurnTip::from_db(&burnchain_db)
+    }
+
+    /// Re-process the burnchain blocks above `height` from scratch, e.g. after the rules for
+    /// parsing burn operations changed, or if the burnchain DB is suspect.  Our headers are
+    /// kept, but `burnchain`'s DB is rolled back to `height` (see
+    /// `BurnchainDB::drop_blocks_above()`), and the blocks above it are downloaded and processed
+    /// again by `Burnchain::sync_with_indexer()`.  The sortition DB is left alone.
+    /// Fails with `BlockchainHeight` if `height` is above the burnchain DB's tip or below its
+    /// first block.
+    /// Returns the header of the new burnchain tip.
+    pub fn reindex_from(
+        &mut self,
+        burnchain: &mut Burnchain,
+        coord_comm: CoordinatorChannels,
+        height: u64,
+    ) -> Result<BurnchainBlockHeader, burnchain_error> {
+        let mut burnchain_db = burnchain.open_burnchain_db(true)?;
+        let tip_height = burnchain_db.get_canonical_chain_tip()?.block_height;
+        if height > tip_height || height < burnchain.first_block_height {
+            warn!(
+                "Cannot reindex from height {}: burnchain DB has blocks {} through {}",
+                height, burnchain.first_block_height, tip_height
             );
-            return;
+            return Err(burnchain_error::Bitcoin(btc_error::BlockchainHeight));
         }
-        if !env::var("BLOCKSTACK_SPV_BITCOIN_PORT").is_ok() {
-            eprintln!(
-                "Skipping test_indexer_sync_headers -- no BLOCKSTACK_SPV_BITCOIN_PORT envar set"
-            );
-            return;
+
+        info!(
+            "Reindex burnchain blocks {} through {}",
+            height + 1,
+            tip_height
+        );
+        burnchain_db.drop_blocks_above(height)?;
+        drop(burnchain_db);
+
+        burnchain.sync_with_indexer(self, coord_comm, None, None, None)
+    }
+}
+
+impl Drop for BitcoinIndexer {
+    fn drop(&mut self) {
+        match self.runtime.sock {
+            Some(ref mut s) => {
+                let _ = s.shutdown(Shutdown::Both);
+            }
+            None => {}
         }
-        if !env::var("BLOCKSTACK_SPV_BITCOIN_MODE").is_ok() {
-            eprintln!(
-                "Skipping test_indexer_sync_headers -- no BLOCKSTACK_SPV_BITCOIN_MODE envar set"
-            );
-            return;
+    }
+}
+
+impl BurnchainIndexer for BitcoinIndexer {
+    type P = BitcoinBlockParser;
+
+    /// Connect to the Bitcoin peer network.
+    /// Use the peer host and peer port given in the config file,
+    /// and loaded in on setup.
+    /// Does nothing in `SyncMode::Rpc`, where each JSON-RPC call connects as needed.
+    fn connect(&mut self) -> Result<(), burnchain_error> {
+        if self.config.sync_mode == SyncMode::Rpc {
+            return Ok(());
         }
+        self.reconnect_peer().map_err(burnchain_error::Bitcoin)
+    }
 
-        let host = env::var("BLOCKSTACK_SPV_BITCOIN_HOST").unwrap();
-        let port = env::var("BLOCKSTACK_SPV_BITCOIN_PORT")
-            .unwrap()
-            .parse::<u16>()
-            .unwrap();
-        let mode = match env::var("BLOCKSTACK_SPV_BITCOIN_MODE").unwrap().as_str() {
-            "mainnet" => BitcoinNetworkType::Mainnet,
-            "testnet" => BitcoinNetworkType::Testnet,
-            "regtest" => BitcoinNetworkType::Regtest,
-            _ => {
-                panic!("Invalid bitcoin mode -- expected mainnet, testnet, or regtest");
+    /// Get the location on disk where we keep headers
+    fn get_headers_path(&self) -> String {
+        self.config.spv_headers_path.clone()
+    }
+
+    /// Get the number of headers we have
+    fn get_headers_height(&self) -> Result<u64, burnchain_error> {
+        let spv_client = self
+            .open_headers_readonly()
+            .map_err(burnchain_error::Bitcoin)?;
+        spv_client
+            .get_headers_height()
+            .map_err(burnchain_error::Bitcoin)
+    }
+
+    fn get_highest_header_height(&self) -> Result<u64, burnchain_error> {
+        let spv_client = self
+            .open_headers_readonly()
+            .map_err(burnchain_error::Bitcoin)?;
+        spv_client
+            .get_highest_header_height()
+            .map_err(burnchain_error::Bitcoin)
+    }
+
+    /// Get the first block height
+    fn get_first_block_height(&self) -> u64 {
+        self.config.first_block
+    }
+
+    /// Get the first block header hash
+    fn get_first_block_header_hash(&self) -> Result<BurnchainHeaderHash, burnchain_error> {
+        let spv_client = self.open_headers_readonly()?;
+        let first_block_height = self.get_first_block_height();
+        let first_header = spv_client
+            .read_block_header(first_block_height)?
+            .expect("BUG: no first block header hash");
+
+        let first_block_header_hash =
+            BurnchainHeaderHash::from_bitcoin_hash(&first_header.header.bitcoin_hash());
+        Ok(first_block_header_hash)
+    }
+
+    /// Get the first block header timestamp
+    fn get_first_block_header_timestamp(&self) -> Result<u64, burnchain_error> {
+        let spv_client = self.open_headers_readonly()?;
+        let first_block_height = self.get_first_block_height();
+        let first_header = spv_client
+            .read_block_header(first_block_height)?
+            .expect("BUG: no first block header timestamp");
+
+        let first_block_header_timestamp = first_header.header.time as u64;
+        Ok(first_block_header_timestamp)
+    }
+
+    /// Get a vector of the stacks epochs. This notion of epochs is dependent on the burn block height.
+    /// Valid epochs include stacks 1.0, stacks 2.0, stacks 2.05, and so on.
+    ///
+    /// Choose according to:
+    /// 1) Use the custom epochs defined on the underlying `BitcoinIndexerConfig`, if they exist.
+    /// 2) Use hard-coded static values, otherwise.
+    ///
+    /// It is an error (panic) to set custom epochs if running on `Mainnet`.
+    fn get_stacks_epochs(&self) -> Vec<StacksEpoch> {
+        match self.config.epochs {
+            Some(ref epochs) => {
+                assert!(self.runtime.network_id != BitcoinNetworkType::Mainnet);
+                epochs.clone()
             }
-        };
+            None => get_bitcoin_stacks_epochs(self.runtime.network_id),
+        }
+    }
 
-        let db_path = "/tmp/test_indexer_sync_headers.sqlite";
-        let indexer_conf = BitcoinIndexerConfig {
-            peer_host: host,
-            peer_port: port,
-            rpc_port: port + 1, // ignored
-            rpc_ssl: false,
-            username: Some("blockstack".to_string()),
-            password: Some("blockstacksystem".to_string()),
-            timeout: 30,
-            spv_headers_path: db_path.to_string(),
-            first_block: 0,
-            magic_bytes: MagicBytes([105, 100]),
-            epochs: None,
-        };
+    /// Read downloaded headers within a range
+    fn read_headers(
+        &self,
+        start_block: u64,
+        end_block: u64,
+    ) -> Result<Vec<BitcoinHeaderIPC>, burnchain_error> {
+        let spv_client = self.open_headers_readonly()?;
 
-        if fs::metadata(&indexer_conf.spv_headers_path).is_ok() {
-            fs::remove_file(&indexer_conf.spv_headers_path).unwrap();
+        let headers = spv_client.read_block_headers(start_block, end_block)?;
+        let mut ret_headers: Vec<BitcoinHeaderIPC> = vec![];
+        for i in 0..headers.len() {
+            ret_headers.push({
+                BitcoinHeaderIPC {
+                    block_header: headers[i].clone(),
+                    block_height: (i as u64) + start_block,
+                }
+            });
         }
+        Ok(ret_headers)
+    }
 
-        let mut indexer = BitcoinIndexer::new(indexer_conf, BitcoinIndexerRuntime::new(mode));
-        let last_block = indexer.sync_headers(0, None).unwrap();
-        eprintln!("sync'ed to block {}", last_block);
+    /// Identify underlying reorgs and return the block height of the highest block in common
+    /// between the remote node and our block headers.
+    fn find_chain_reorg(&mut self) -> Result<u64, burnchain_error> {
+        let headers_path = self.config.spv_headers_path.clone();
+        let reorg_path = format!("{}.reorg", &self.config.spv_headers_path);
+        self.find_bitcoin_reorg(
+            &headers_path,
+            &reorg_path,
+            |ref mut indexer, ref mut spv_client, start_block, end_block_opt| {
+                spv_client.set_scan_range(start_block, end_block_opt);
+                indexer.sync_spv_client(spv_client)
+            },
+        )
+        .map_err(|e| self.to_sync_error(e))
+    }
 
-        // compare against known-good chain work
-        let chain_work: Vec<(u64, &str)> = vec![
-            (
-                0,
-                "000000000000000000000000000000000000000000000000000007e007e007e0",
-            ),
-            (
-                1,
-                "00000000000000000000000000000000000000000000000000000fc00fc00fc0",
-            ),
-            (
-                2,
-                "000000000000000000000000000000000000000000000000000017a017a017a0",
-            ),
-            (
-                3,
-                "00000000000000000000000000000000000000000000000000001f801f801f80",
-            ),
-            (
-                4,
-                "0000000000000000000000000000000000000000000000000000276027602760",
-            ),
-            (
-                5,
-                "00000000000000000000000000000000000000000000000000002f402f402f40",
-            ),
-            (
-                6,
-                "0000000000000000000000000000000000000000000000000000372037203720",
-            ),
-            (
-                7,
-                "00000000000000000000000000000000000000000000000000003f003f003f00",
-            ),
-            (
-                8,
-                "000000000000000000000000000000000000000000000000000046e046e046e0",
-            ),
-            (
-                9,
-                "00000000000000000000000000000000000000000000000000004ec04ec04ec0",
+    /// Download and store all headers between two block heights
+    /// end_heights, if given, is inclusive.
+    /// Returns the height of the last header fetched
+    fn sync_headers(
+        &mut self,
+        start_height: u64,
+        end_height: Option<u64>,
+    ) -> Result<u64, burnchain_error> {
+        if end_height.is_some() && end_height <= Some(start_height) {
+            return Ok(end_height.unwrap());
+        }
+
+        let new_height = self
+            .sync_last_headers(start_height, end_height)
+            .map_err(|e| self.to_sync_error(e))?;
+
+        // make sure the headers are up-to-date if we have no target height
+        if end_height.is_none() {
+            self.check_chain_tip_timestamp()?;
+        }
+        Ok(new_height)
+    }
+
+    fn take_headers_reorg_height(&mut self) -> Option<u64> {
+        self.runtime.headers_reorg_height.take()
+    }
+
+    /// Drop headers after a given height -- i.e. to accomodate a reorg
+    fn drop_headers(&mut self, new_height: u64) -> Result<(), burnchain_error> {
+        let mut spv_client = SpvClient::new_with_genesis(
+            &self.config.spv_headers_path,
+            0,
+            None,
+            self.runtime.network_id,
+            self.get_genesis_header()
+                .map_err(burnchain_error::Bitcoin)?,
+            true,
+            false,
+        )
+        .map_err(burnchain_error::Bitcoin)?;
+        spv_client
+            .drop_headers(new_height)
+            .map_err(burnchain_error::Bitcoin)
+    }
+
+    fn report_burn_blocks_invalidated(&self, first_height: u64, last_height: u64) {
+        self.report_sync_event(|| SyncEvent::BurnBlocksInvalidated {
+            first_height,
+            last_height,
+        });
+    }
+
+    fn report_block_processed(&self, header: &BurnchainBlockHeader) {
+        self.report_sync_event(|| SyncEvent::BlockProcessed {
+            height: header.block_height,
+            hash: header.block_hash.clone(),
+            block_time: header.timestamp as u32,
+        });
+    }
+
+    fn downloader(&self) -> BitcoinBlockDownloader {
+        BitcoinBlockDownloader::new(self.dup())
+    }
+
+    fn parser(&self) -> BitcoinBlockParser {
+        let mut parser = BitcoinBlockParser::new(self.runtime.network_id, self.config.magic_bytes);
+        parser.set_sig_threads(self.config.sig_verify_threads);
+        parser
+    }
+
+    fn num_parser_threads(&self) -> usize {
+        self.config.block_parse_threads
+    }
+
+    fn dry_run(&self) -> bool {
+        self.config.dry_run
+    }
+
+    fn drop_reorged_blocks(&self) -> bool {
+        self.config.drop_reorged_blocks
+    }
+
+    fn report_dry_run(&mut self, report: DryRunReport) {
+        self.report_sync_event(|| SyncEvent::DryRunFinished {
+            report: report.clone(),
+        });
+        self.runtime.dry_run_report = Some(report);
+    }
+
+    fn event_observers(&self) -> BurnchainEventObservers {
+        self.runtime.event_observers.clone()
+    }
+
+    fn max_reorg_depth(&self) -> Option<u64> {
+        self.config.max_reorg_depth
+    }
+
+    fn take_deep_reorg_acceptance(&mut self) -> bool {
+        self.runtime
+            .deep_reorg_accepted
+            .swap(false, Ordering::SeqCst)
+    }
+
+    fn set_burnchain_tip(&mut self, tip: &BurnchainTip) {
+        self.runtime.burnchain_tip = Some(tip.clone());
+    }
+
+    fn reader(&self) -> BitcoinIndexer {
+        self.dup()
+    }
+
+    fn shutdown_handle(&self) -> Arc<AtomicBool> {
+        BitcoinIndexer::shutdown_handle(self)
+    }
+}
+
+impl BurnchainHeaderReader for BitcoinIndexer {
+    fn read_burnchain_headers(
+        &self,
+        start_height: u64,
+        end_height: u64,
+    ) -> Result<Vec<BurnchainBlockHeader>, DBError> {
+        let hdrs = self
+            .read_headers(start_height, end_height)
+            .map_err(|e| DBError::Other(format!("Burnchain error: {:?}", &e)))?;
+
+        Ok(hdrs
+            .into_iter()
+            .map(|hdr| BurnchainBlockHeader {
+                block_height: hdr.block_height,
+                block_hash: BurnchainHeaderHash::from_bitcoin_hash(&Sha256dHash(hdr.header_hash())),
+                parent_block_hash: BurnchainHeaderHash::from_bitcoin_hash(
+                    &hdr.block_header.header.prev_blockhash,
+                ),
+                num_txs: hdr.block_header.tx_count.0,
+                timestamp: hdr.block_header.header.time as u64,
+            })
+            .collect())
+    }
+
+    fn get_burnchain_headers_height(&self) -> Result<u64, DBError> {
+        self.get_headers_height()
+            .map_err(|e| DBError::Other(format!("Burnchain error: {:?}", &e)))
+    }
+
+    fn find_burnchain_header_height(
+        &self,
+        burn_header_hash: &BurnchainHeaderHash,
+    ) -> Result<Option<u64>, DBError> {
+        let spv_client = self
+            .open_headers_readonly()
+            .map_err(|e| DBError::Other(format!("Burnchain error: {:?}", &e)))?;
+        spv_client
+            .get_height_of(burn_header_hash)
+            .map_err(|e| DBError::Other(format!("Burnchain error: {:?}", &e)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::burnchains::bitcoin::blocks::{extract_proven_txs, BitcoinBlockIPC};
+    use crate::burnchains::bitcoin::bloom;
+    use crate::burnchains::bitcoin::filters::{BlockFilter, BASIC_FILTER_TYPE};
+    use crate::burnchains::bitcoin::task::{IndexerHandle, IndexerState, IndexerStats};
+    use crate::burnchains::bitcoin::Error as btc_error;
+    use crate::burnchains::bitcoin::*;
+    use crate::burnchains::tests::{make_regtest_block_chain, make_regtest_header_chain};
+    use crate::burnchains::Error as burnchain_error;
+    use crate::burnchains::*;
+    use crate::core::StacksEpochId;
+
+    use stacks_common::deps_common::bitcoin::blockdata::block::{
+        Block, BlockHeader, LoneBlockHeader,
+    };
+    use stacks_common::deps_common::bitcoin::blockdata::script::Script;
+    use stacks_common::deps_common::bitcoin::blockdata::transaction::{
+        OutPoint, Transaction, TxOut,
+    };
+    use stacks_common::deps_common::bitcoin::network::constants::{
+        NODE_BLOOM, NODE_NETWORK, PROTOCOL_VERSION,
+    };
+    use stacks_common::deps_common::bitcoin::network::encodable::{
+        ConsensusDecodable, ConsensusEncodable, VarInt,
+    };
+    use stacks_common::deps_common::bitcoin::network::message::RawNetworkMessage;
+    use stacks_common::deps_common::bitcoin::network::message_blockdata::{InvType, Inventory};
+    use stacks_common::deps_common::bitcoin::network::message_bloom::{FilterAdd, FilterLoad};
+    use stacks_common::deps_common::bitcoin::network::message_filter::{CFHeaders, CFilter};
+    use stacks_common::deps_common::bitcoin::network::serialize::{
+        deserialize, serialize, BitcoinHash, RawDecoder, RawEncoder,
+    };
+    use stacks_common::deps_common::bitcoin::util::hash::{bitcoin_merkle_root, Sha256dHash};
+    use stacks_common::util::get_epoch_time_secs;
+    use stacks_common::util::hash::{to_hex, Hash160};
+    use stacks_common::util::uint::Uint256;
+
+    use std::env;
+
+    #[test]
+    fn test_network_magic() {
+        let networks = [
+            (BitcoinNetworkType::Mainnet, [0xf9, 0xbe, 0xb4, 0xd9]),
+            (BitcoinNetworkType::Testnet, [0x0b, 0x11, 0x09, 0x07]),
+            (BitcoinNetworkType::Regtest, [0xfa, 0xbf, 0xb5, 0xda]),
+            (BitcoinNetworkType::Signet, [0x0a, 0x03, 0xcf, 0x40]),
+        ];
+        for (network_id, wire_bytes) in networks.iter() {
+            let magic = u32::from_le_bytes(*wire_bytes);
+            assert_eq!(network_id_to_bytes(*network_id), magic);
+            assert_eq!(BitcoinNetworkType::try_from(magic), Ok(*network_id));
+
+            // each magic is accepted only under its own network
+            for (other_network_id, _) in networks.iter() {
+                if other_network_id != network_id {
+                    assert!(network_id_to_bytes(*other_network_id) != magic);
+                }
+            }
+        }
+        assert!(BitcoinNetworkType::try_from(0x12345678).is_err());
+    }
+
+    /// Connect an indexer for `network_id` to a mock peer that completes the handshake with our
+    /// magic, and then frames a ping with `peer_magic`.  Returns what the indexer made of it.
+    fn recv_message_framed_with(
+        network_id: BitcoinNetworkType,
+        peer_magic: u32,
+        db_path: &str,
+    ) -> Result<PeerMessage, btc_error> {
+        let magic = network_id_to_bytes(network_id);
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_port = listener.local_addr().unwrap().port();
+
+        let peer = thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+            let mut encoder = RawEncoder::new(sock);
+
+            let version: RawNetworkMessage =
+                ConsensusDecodable::consensus_decode(&mut decoder).unwrap();
+            assert_eq!(version.magic, magic);
+            let mut version_body = match version.payload {
+                NetworkMessage::Version(body) => body,
+                x => panic!("Expected version, got {:?}", &x),
+            };
+            version_body.services = NODE_NETWORK;
+            for payload in vec![
+                NetworkMessage::Version(version_body),
+                NetworkMessage::Verack,
+            ] {
+                RawNetworkMessage { magic, payload }
+                    .consensus_encode(&mut encoder)
+                    .unwrap();
+            }
+
+            let verack: RawNetworkMessage =
+                ConsensusDecodable::consensus_decode(&mut decoder).unwrap();
+            assert_eq!(verack.payload, NetworkMessage::Verack);
+
+            RawNetworkMessage {
+                magic: peer_magic,
+                payload: NetworkMessage::Ping(1),
+            }
+            .consensus_encode(&mut encoder)
+            .unwrap();
+        });
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = peer_port;
+        let mut indexer = BitcoinIndexer::new(config, BitcoinIndexerRuntime::new(network_id));
+        indexer.connect_handshake_backoff().unwrap();
+        let res = indexer.recv_message();
+        peer.join().unwrap();
+        res
+    }
+
+    #[test]
+    fn test_recv_message_wrong_network_magic() {
+        // a testnet indexer refuses signet-framed messages, and vice versa
+        for (network_id, other_network_id) in [
+            (BitcoinNetworkType::Testnet, BitcoinNetworkType::Signet),
+            (BitcoinNetworkType::Signet, BitcoinNetworkType::Testnet),
+        ] {
+            match recv_message_framed_with(
+                network_id,
+                network_id_to_bytes(other_network_id),
+                "/tmp/test_recv_message_wrong_network_magic.dat",
+            ) {
+                Err(btc_error::InvalidMagic) => {}
+                x => panic!("Unexpected {:?} on {:?}", &x, network_id),
+            }
+        }
+
+        // the same message with the network's own magic gets through
+        assert_eq!(
+            recv_message_framed_with(
+                BitcoinNetworkType::Testnet,
+                BITCOIN_TESTNET,
+                "/tmp/test_recv_message_wrong_network_magic.dat",
+            )
+            .unwrap(),
+            NetworkMessage::Ping(1)
+        );
+    }
+
+    #[test]
+    fn test_custom_network_magic_handshake() {
+        let magic = 0xDEADBEEF;
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_port = listener.local_addr().unwrap().port();
+
+        // mock peer that only speaks the custom magic
+        let peer = thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+            let mut encoder = RawEncoder::new(sock);
+
+            let version: RawNetworkMessage =
+                ConsensusDecodable::consensus_decode(&mut decoder).unwrap();
+            assert_eq!(version.magic, magic);
+            let mut version_body = match version.payload {
+                NetworkMessage::Version(body) => body,
+                x => panic!("Expected version, got {:?}", &x),
+            };
+            version_body.start_height = 123;
+            version_body.services = NODE_NETWORK;
+            for payload in vec![
+                NetworkMessage::Version(version_body),
+                NetworkMessage::Verack,
+            ] {
+                RawNetworkMessage { magic, payload }
+                    .consensus_encode(&mut encoder)
+                    .unwrap();
+            }
+
+            let verack: RawNetworkMessage =
+                ConsensusDecodable::consensus_decode(&mut decoder).unwrap();
+            assert_eq!(verack.magic, magic);
+            assert_eq!(verack.payload, NetworkMessage::Verack);
+
+            // the default regtest magic won't do
+            RawNetworkMessage {
+                magic: BITCOIN_REGTEST,
+                payload: NetworkMessage::Ping(1),
+            }
+            .consensus_encode(&mut encoder)
+            .unwrap();
+        });
+
+        let params = CustomNetworkParams {
+            magic,
+            default_port: peer_port,
+            p2pkh_version: 0x6f,
+            p2sh_version: 0xc4,
+        };
+        let config = BitcoinIndexerConfig::custom_regtest(
+            "/tmp/test_custom_network_magic_handshake.dat".to_string(),
+            params,
+        );
+        assert_eq!(config.peer_port, peer_port);
+
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        assert_eq!(indexer.network_magic(), magic);
+        assert_eq!(indexer.connect_handshake_backoff().unwrap(), 123);
+        match indexer.recv_message() {
+            Err(btc_error::InvalidMagic) => {}
+            x => panic!("Unexpected {:?}", &x),
+        }
+        peer.join().unwrap();
+
+        // without an override, the network's own magic is used
+        let indexer = BitcoinIndexer::new(
+            BitcoinIndexerConfig::default_regtest("/tmp/unused.dat".to_string()),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        assert_eq!(indexer.network_magic(), BITCOIN_REGTEST);
+    }
+
+    #[test]
+    fn test_keepalive_ping_pong() {
+        let magic = BITCOIN_REGTEST;
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_port = listener.local_addr().unwrap().port();
+
+        // mock peer that pings us, answers our first ping, and ignores our second
+        let peer = thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+            let mut encoder = RawEncoder::new(sock);
+            let mut recv = move || -> Option<NetworkMessage> {
+                let msg: RawNetworkMessage =
+                    ConsensusDecodable::consensus_decode(&mut decoder).ok()?;
+                assert_eq!(msg.magic, magic);
+                Some(msg.payload)
+            };
+            let mut send = move |payload| {
+                RawNetworkMessage { magic, payload }
+                    .consensus_encode(&mut encoder)
+                    .unwrap();
+            };
+
+            let mut version_body = match recv() {
+                Some(NetworkMessage::Version(body)) => body,
+                x => panic!("Expected version, got {:?}", &x),
+            };
+            version_body.services = NODE_NETWORK;
+            send(NetworkMessage::Version(version_body));
+            send(NetworkMessage::Verack);
+            assert_eq!(recv(), Some(NetworkMessage::Verack));
+
+            // our ping is echoed
+            send(NetworkMessage::Ping(42));
+            assert_eq!(recv(), Some(NetworkMessage::Pong(42)));
+
+            // their first ping gets a pong
+            match recv() {
+                Some(NetworkMessage::Ping(n)) => send(NetworkMessage::Pong(n)),
+                x => panic!("Expected ping, got {:?}", &x),
+            }
+
+            // their second ping doesn't, so they hang up
+            match recv() {
+                Some(NetworkMessage::Ping(_)) => {}
+                x => panic!("Expected ping, got {:?}", &x),
+            }
+            assert_eq!(recv(), None);
+        });
+
+        let keepalive = KeepalivePolicy {
+            interval: Duration::from_millis(200),
+            timeout: Duration::from_millis(300),
+        };
+        let mut config =
+            BitcoinIndexerConfig::test_default("/tmp/test_keepalive_ping_pong.dat".to_string());
+        config.peer_port = peer_port;
+        config.keepalive = Some(keepalive.clone());
+
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        let connect_start = Instant::now();
+        indexer.connect_handshake_backoff().unwrap();
+
+        // inbound ping is answered with the same nonce
+        let ping = indexer.recv_message().unwrap();
+        assert_eq!(ping, NetworkMessage::Ping(42));
+        assert!(indexer.handle_message::<SpvClient>(ping, None).unwrap());
+        assert!(indexer.runtime.outstanding_ping.is_none());
+
+        // we ping the peer once the interval passes, and its pong clears the outstanding nonce
+        let pong = indexer.recv_message().unwrap();
+        assert!(connect_start.elapsed() >= keepalive.interval);
+        let (nonce, _) = indexer.runtime.outstanding_ping.clone().unwrap();
+        assert_eq!(pong, NetworkMessage::Pong(nonce));
+        assert!(indexer.handle_message::<SpvClient>(pong, None).unwrap());
+        assert!(indexer.runtime.outstanding_ping.is_none());
+
+        // a ping without a pong kills the connection
+        let wait_start = Instant::now();
+        match indexer.recv_message() {
+            Err(btc_error::ConnectionBroken) => {}
+            x => panic!("Unexpected {:?}", &x),
+        }
+        assert!(wait_start.elapsed() >= keepalive.timeout);
+        assert!(!indexer.is_connected());
+        assert!(indexer.runtime.outstanding_ping.is_none());
+
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn test_request_rate_limit() {
+        let magic = BITCOIN_REGTEST;
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_port = listener.local_addr().unwrap().port();
+        let block_hashes: Vec<_> = (0..10u8).map(|i| Sha256dHash::from_data(&[i])).collect();
+        let peer_block_hashes = block_hashes.clone();
+
+        // mock peer that answers every getdata with a notfound, noting when each arrived
+        let peer = thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let probe = sock.try_clone().unwrap();
+            let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+            let mut encoder = RawEncoder::new(sock);
+            let mut recv = move || -> Option<NetworkMessage> {
+                let msg: RawNetworkMessage =
+                    ConsensusDecodable::consensus_decode(&mut decoder).ok()?;
+                assert_eq!(msg.magic, magic);
+                Some(msg.payload)
+            };
+            let mut send = move |payload| {
+                RawNetworkMessage { magic, payload }
+                    .consensus_encode(&mut encoder)
+                    .unwrap();
+            };
+            let recv_getdata = |recv: &mut dyn FnMut() -> Option<NetworkMessage>| match recv() {
+                Some(NetworkMessage::GetData(invs)) => invs,
+                x => panic!("Expected getdata, got {:?}", &x),
+            };
+
+            let mut version_body = match recv() {
+                Some(NetworkMessage::Version(body)) => body,
+                x => panic!("Expected version, got {:?}", &x),
+            };
+            version_body.services = NODE_NETWORK;
+            send(NetworkMessage::Version(version_body));
+            send(NetworkMessage::Verack);
+            assert_eq!(recv(), Some(NetworkMessage::Verack));
+
+            // a burst of requests arrives in order, but no faster than the rate
+            let mut arrivals = vec![];
+            for hash in peer_block_hashes.iter() {
+                let invs = recv_getdata(&mut recv);
+                arrivals.push(Instant::now());
+                assert_eq!(invs.len(), 1);
+                assert_eq!(&invs[0].hash, hash);
+                send(NetworkMessage::NotFound(invs));
+            }
+            // 10 requests at 20 per second, with a burst of 1, take 9 intervals of 50ms
+            let elapsed = arrivals[9].duration_since(arrivals[0]);
+            assert!(
+                elapsed >= Duration::from_millis(440),
+                "Requests arrived too fast: {:?}",
+                &elapsed
+            );
+
+            // no more than two requests are ever unanswered
+            let mut unanswered = vec![recv_getdata(&mut recv), recv_getdata(&mut recv)];
+            for _ in 0..2 {
+                probe
+                    .set_read_timeout(Some(Duration::from_millis(300)))
+                    .unwrap();
+                let mut buf = [0u8; 1];
+                assert!(probe.peek(&mut buf).is_err());
+                send(NetworkMessage::NotFound(unanswered.remove(0)));
+                unanswered.push(recv_getdata(&mut recv));
+            }
+            for invs in unanswered.into_iter() {
+                send(NetworkMessage::NotFound(invs));
+            }
+
+            // nothing else until we hang up
+            probe.set_read_timeout(None).unwrap();
+            assert_eq!(recv(), None);
+        });
+
+        let mut config =
+            BitcoinIndexerConfig::test_default("/tmp/test_request_rate_limit.dat".to_string());
+        config.peer_port = peer_port;
+        config.request_limit = Some(RequestLimitPolicy {
+            requests_per_sec: 20,
+            burst: 1,
+            max_in_flight: 100,
+        });
+
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        indexer.connect_handshake_backoff().unwrap();
+
+        // only the first request of the burst goes out right away; the rest wait their turn
+        for hash in block_hashes.iter() {
+            indexer.send_getdata(&vec![hash.clone()]).unwrap();
+        }
+        {
+            let limiter = indexer.runtime.request_limiter.as_ref().unwrap();
+            assert_eq!(limiter.num_in_flight(), 1);
+            assert_eq!(limiter.num_queued(), 9);
+        }
+        for _ in 0..10 {
+            match indexer.recv_message().unwrap() {
+                NetworkMessage::NotFound(..) => {}
+                x => panic!("Expected notfound, got {:?}", &x),
+            }
+        }
+        {
+            let limiter = indexer.runtime.request_limiter.as_ref().unwrap();
+            assert_eq!(limiter.num_in_flight(), 0);
+            assert_eq!(limiter.num_queued(), 0);
+        }
+
+        // past the in-flight cap, requests wait for replies instead
+        indexer.runtime.request_limiter = Some(RequestLimiter::new(RequestLimitPolicy {
+            requests_per_sec: 1000,
+            burst: 10,
+            max_in_flight: 2,
+        }));
+        for hash in block_hashes[0..4].iter() {
+            indexer.send_getdata(&vec![hash.clone()]).unwrap();
+        }
+        {
+            let limiter = indexer.runtime.request_limiter.as_ref().unwrap();
+            assert_eq!(limiter.num_in_flight(), 2);
+            assert_eq!(limiter.num_queued(), 2);
+        }
+        for _ in 0..4 {
+            match indexer.recv_message().unwrap() {
+                NetworkMessage::NotFound(..) => {}
+                x => panic!("Expected notfound, got {:?}", &x),
+            }
+        }
+        {
+            let limiter = indexer.runtime.request_limiter.as_ref().unwrap();
+            assert_eq!(limiter.num_in_flight(), 0);
+            assert_eq!(limiter.num_queued(), 0);
+        }
+
+        indexer.disconnect();
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn test_handshake_rejects_incompatible_peers() {
+        // (peer version, peer services, negotiated version if the peer is usable)
+        let peers = vec![
+            (PROTOCOL_VERSION, NODE_NETWORK, Some(PROTOCOL_VERSION)),
+            (70015, NODE_NETWORK | (1 << 3), Some(PROTOCOL_VERSION)),
+            // too old
+            (60002, NODE_NETWORK, None),
+            // pruned: NODE_NETWORK_LIMITED only, which is fine for recent blocks
+            (70015, NODE_NETWORK_LIMITED, Some(PROTOCOL_VERSION)),
+            // serves no blocks at all
+            (70015, NODE_BLOOM, None),
+        ];
+
+        for (version, services, negotiated) in peers.into_iter() {
+            let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let peer_port = listener.local_addr().unwrap().port();
+
+            let peer = thread::spawn(move || {
+                let (sock, _) = listener.accept().unwrap();
+                let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+                let mut encoder = RawEncoder::new(sock);
+
+                let msg: RawNetworkMessage =
+                    ConsensusDecodable::consensus_decode(&mut decoder).unwrap();
+                let mut version_body = match msg.payload {
+                    NetworkMessage::Version(body) => body,
+                    x => panic!("Expected version, got {:?}", &x),
+                };
+                version_body.version = version;
+                version_body.services = services;
+                for payload in vec![
+                    NetworkMessage::Version(version_body),
+                    NetworkMessage::Verack,
+                ] {
+                    RawNetworkMessage {
+                        magic: BITCOIN_REGTEST,
+                        payload,
+                    }
+                    .consensus_encode(&mut encoder)
+                    .unwrap();
+                }
+
+                // a usable peer gets a verack; an unusable one gets nothing
+                let reply: Result<RawNetworkMessage, _> =
+                    ConsensusDecodable::consensus_decode(&mut decoder);
+                match reply {
+                    Ok(msg) => {
+                        assert!(negotiated.is_some());
+                        assert_eq!(msg.payload, NetworkMessage::Verack);
+                    }
+                    Err(_) => assert!(negotiated.is_none()),
+                }
+            });
+
+            let mut config = BitcoinIndexerConfig::test_default(
+                "/tmp/test_handshake_rejects_incompatible_peers.dat".to_string(),
+            );
+            config.peer_port = peer_port;
+            let mut indexer = BitcoinIndexer::new(
+                config,
+                BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+            );
+
+            match (indexer.connect_handshake_backoff(), negotiated) {
+                (Ok(_), Some(negotiated)) => {
+                    assert_eq!(indexer.runtime.peer_version, negotiated);
+                    assert_eq!(indexer.runtime.peer_services, services);
+                }
+                (Err(btc_error::IncompatiblePeer(_)), None) => {
+                    assert_eq!(indexer.runtime.peer_version, 0);
+                }
+                (x, _) => panic!(
+                    "Unexpected {:?} for version {} services {:#x}",
+                    &x, version, services
+                ),
+            }
+            indexer.disconnect();
+            peer.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_peer_communicate_gives_up_on_permanent_errors() {
+        let db_path = "/tmp/test_peer_communicate_gives_up_on_permanent_errors.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+
+        // a peer whose protocol version is too old to sync from
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_port = listener.local_addr().unwrap().port();
+        let peer = thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+            let mut encoder = RawEncoder::new(sock);
+
+            let msg: RawNetworkMessage =
+                ConsensusDecodable::consensus_decode(&mut decoder).unwrap();
+            let mut version_body = match msg.payload {
+                NetworkMessage::Version(body) => body,
+                x => panic!("Expected version, got {:?}", &x),
+            };
+            version_body.version = 60002;
+            for payload in vec![
+                NetworkMessage::Version(version_body),
+                NetworkMessage::Verack,
+            ] {
+                RawNetworkMessage {
+                    magic: BITCOIN_REGTEST,
+                    payload,
+                }
+                .consensus_encode(&mut encoder)
+                .unwrap();
+            }
+        });
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = peer_port;
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+
+        // retrying would just reconnect to the same peer, so this must not hang
+        match indexer.peer_communicate(&mut spv_client, true) {
+            Err(e) => {
+                assert_eq!(e.error_code(), "incompatible_peer");
+                assert!(!e.is_transient());
+            }
+            x => panic!("Expected IncompatiblePeer, got {:?}", &x),
+        }
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn test_peer_error_context() {
+        let db_path = "/tmp/test_peer_error_context.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+
+        // a peer that answers our version message with one we can't sync from
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_port = listener.local_addr().unwrap().port();
+        let peer = thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+            let mut encoder = RawEncoder::new(sock);
+
+            let msg: RawNetworkMessage =
+                ConsensusDecodable::consensus_decode(&mut decoder).unwrap();
+            let mut version_body = match msg.payload {
+                NetworkMessage::Version(body) => body,
+                x => panic!("Expected version, got {:?}", &x),
+            };
+            version_body.version = 60002;
+            for payload in vec![
+                NetworkMessage::Version(version_body),
+                NetworkMessage::Verack,
+            ] {
+                RawNetworkMessage {
+                    magic: BITCOIN_REGTEST,
+                    payload,
+                }
+                .consensus_encode(&mut encoder)
+                .unwrap();
+            }
+        });
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = peer_port;
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        indexer.update_sync_stats(|stats| stats.record_header_tip(7));
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+
+        // the error names the peer that caused it
+        let res = indexer.peer_communicate(&mut spv_client, true);
+        let expected_context = PeerContext {
+            peer_host: "127.0.0.1".to_string(),
+            peer_port,
+            height: 7,
+            request_id: 1,
+        };
+        match indexer.with_peer_context(res) {
+            Err(e) => {
+                assert_eq!(e.error.error_code(), "incompatible_peer");
+                assert_eq!(e.context, expected_context);
+                assert!(format!("{}", &e).contains(&format!(
+                    "peer 127.0.0.1:{}, height 7, request 1",
+                    peer_port
+                )));
+            }
+            x => panic!("Expected IncompatiblePeer, got {:?}", &x),
+        }
+        peer.join().unwrap();
+
+        // the error's context is only attached once; later errors get the current context
+        let context = match indexer.with_peer_context::<()>(Err(btc_error::TimedOut)) {
+            Err(e) => e.context,
+            x => panic!("Expected TimedOut, got {:?}", &x),
+        };
+        assert_eq!(context, indexer.log_context());
+        assert_eq!(indexer.with_peer_context(Ok(1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_sendheaders_announcement() {
+        let db_path = "/tmp/test_sendheaders_announcement.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let announced_header = BlockHeader {
+            version: 0x20000000,
+            prev_blockhash: genesis.bitcoin_hash(),
+            merkle_root: Sha256dHash::from_data(&[1]),
+            time: genesis.time + 600,
+            bits: genesis.bits,
+            nonce: 0,
+        };
+
+        let magic = BITCOIN_REGTEST;
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_port = listener.local_addr().unwrap().port();
+
+        // mock peer that announces a new block once we ask it to, and never gets a getheaders
+        let peer = thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+            let mut encoder = RawEncoder::new(sock);
+            let mut recv = move || -> Option<NetworkMessage> {
+                let msg: RawNetworkMessage =
+                    ConsensusDecodable::consensus_decode(&mut decoder).ok()?;
+                assert_eq!(msg.magic, magic);
+                Some(msg.payload)
+            };
+            let mut send = move |payload| {
+                RawNetworkMessage { magic, payload }
+                    .consensus_encode(&mut encoder)
+                    .unwrap();
+            };
+
+            let mut version_body = match recv() {
+                Some(NetworkMessage::Version(body)) => body,
+                x => panic!("Expected version, got {:?}", &x),
+            };
+            version_body.version = 70015;
+            version_body.services = NODE_NETWORK;
+            version_body.start_height = 0;
+            send(NetworkMessage::Version(version_body));
+            send(NetworkMessage::Verack);
+            assert_eq!(recv(), Some(NetworkMessage::Verack));
+            assert_eq!(recv(), Some(NetworkMessage::SendHeaders));
+
+            send(NetworkMessage::Headers(vec![LoneBlockHeader {
+                header: announced_header,
+                tx_count: VarInt(0),
+            }]));
+
+            // nothing else until we hang up
+            match recv() {
+                None => {}
+                x => panic!("Unexpected {:?}", &x),
+            }
+        });
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = peer_port;
+        config.sendheaders_grace = Some(Duration::from_secs(60));
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+
+        // the peer has no blocks yet, so there's nothing to ask for
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 0);
+        assert!(indexer.runtime.sendheaders_sent_time.is_some());
+
+        // the announced header is stored on the next sync, without asking for it
+        assert!(indexer.poll_message(Duration::from_secs(30)).unwrap());
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 1);
+        assert!(indexer.runtime.last_headers_announcement_time.is_some());
+        assert_eq!(indexer.runtime.block_height, 1);
+
+        let spv_client = indexer.open_headers_readonly().unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 1);
+        assert_eq!(
+            spv_client.read_block_header(1).unwrap().unwrap().header,
+            announced_header
+        );
+
+        indexer.disconnect();
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn test_mempool_watch() {
+        let db_path = "/tmp/test_mempool_watch.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+
+        // a burnchain transaction with the "id" magic bytes, and one that isn't
+        let burn_tx: Transaction = deserialize(&hex_bytes("010000000320a081bcd1a80d9c1945f863d29dc84278411ed74cb6dcba30541bf8d5770542020000008b483045022100be57031bf2c095945ba2876e97b3f86ee051643a29b908f22ed45ccf58620103022061e056e5f48c5a51c66604a1ca28e4bfaabab1478424c9bbb396cc6afe5c222e0141040fadbbcea0ff3b05f03195b41cd991d7a0af8bd38559943aec99cbdaf0b22cc806b9a4f07579934774cc0c155e781d45c989f94336765e88a66d91cfb9f060b0feffffff20a081bcd1a80d9c1945f863d29dc84278411ed74cb6dcba30541bf8d5770542010000008b483045022100fd9c04b330810694cb4bfef793b193f9cbfaa07325700f217b9cb03e5207005302202f07e7c9c6774c5619a043752444f6da6fd81b9d9d008ec965796d87271598de0141040fadbbcea0ff3b05f03195b41cd991d7a0af8bd38559943aec99cbdaf0b22cc806b9a4f07579934774cc0c155e781d45c989f94336765e88a66d91cfb9f060b0feffffff20a081bcd1a80d9c1945f863d29dc84278411ed74cb6dcba30541bf8d5770542040000008a47304402205e24943a40b8ef876cc218a7e8994f4be7afb7aa02403bb73510fac01b33ead3022033e5fb811c396b2fb50a825cd1d86e82eb83483901a1793d0eb15e3e9f1d1c5b814104c77f262dda02580d65c9069a8a34c56bd77325bba4110b693b90216f5a3edc0bebc8ce28d61aa86b414aa91ecb29823b11aeed06098fcd97fee4bc73d54b1e96feffffff030000000000000000296a2769642bfae543ff5672fb607fe15e16b1c3ef38737c631c7c5d911c6617993c21fba731363f1cfe6c6b0000000000001976a914395f3643cea07ec4eec73b4d9a973dcce56b9bf188acc5120100000000001976a9149f2660e75380675206b6f1e2b4f106ae33266be488ac00000000").unwrap()).unwrap();
+        let other_txid = Sha256dHash::from_data(&[2]);
+        let burn_txid = burn_tx.txid();
+
+        let magic = BITCOIN_REGTEST;
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_port = listener.local_addr().unwrap().port();
+
+        // mock peer that announces a burnchain transaction in its mempool
+        let peer_tx = burn_tx.clone();
+        let peer = thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+            let mut encoder = RawEncoder::new(sock);
+            let mut recv = move || -> Option<NetworkMessage> {
+                let msg: RawNetworkMessage =
+                    ConsensusDecodable::consensus_decode(&mut decoder).ok()?;
+                assert_eq!(msg.magic, magic);
+                Some(msg.payload)
+            };
+            let mut send = move |payload| {
+                RawNetworkMessage { magic, payload }
+                    .consensus_encode(&mut encoder)
+                    .unwrap();
+            };
+
+            let mut version_body = match recv() {
+                Some(NetworkMessage::Version(body)) => body,
+                x => panic!("Expected version, got {:?}", &x),
+            };
+            assert!(version_body.relay);
+            version_body.version = 70015;
+            version_body.services = NODE_NETWORK | NODE_BLOOM;
+            version_body.start_height = 0;
+            send(NetworkMessage::Version(version_body));
+            send(NetworkMessage::Verack);
+            assert_eq!(recv(), Some(NetworkMessage::Verack));
+            assert_eq!(recv(), Some(NetworkMessage::MemPool));
+
+            send(NetworkMessage::Inv(vec![
+                Inventory {
+                    inv_type: InvType::Transaction,
+                    hash: burn_txid,
+                },
+                Inventory {
+                    inv_type: InvType::Transaction,
+                    hash: other_txid,
+                },
+                Inventory {
+                    inv_type: InvType::Block,
+                    hash: Sha256dHash::from_data(&[3]),
+                },
+            ]));
+
+            // only the transactions are asked for
+            match recv() {
+                Some(NetworkMessage::GetData(invs)) => {
+                    assert_eq!(
+                        invs,
+                        vec![
+                            Inventory {
+                                inv_type: InvType::Transaction,
+                                hash: burn_txid,
+                            },
+                            Inventory {
+                                inv_type: InvType::Transaction,
+                                hash: other_txid,
+                            },
+                        ]
+                    );
+                }
+                x => panic!("Expected getdata, got {:?}", &x),
+            }
+            send(NetworkMessage::Tx(peer_tx));
+            send(NetworkMessage::NotFound(vec![Inventory {
+                inv_type: InvType::Transaction,
+                hash: other_txid,
+            }]));
+
+            // nothing else until we hang up
+            match recv() {
+                None => {}
+                x => panic!("Unexpected {:?}", &x),
+            }
+        });
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = peer_port;
+        config.mempool_ttl = Some(Duration::from_secs(600));
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+
+        indexer.connect_handshake_backoff().unwrap();
+        assert!(indexer.poll_message(Duration::from_secs(30)).unwrap());
+
+        let unconfirmed = indexer.sync_mempool().unwrap();
+        assert_eq!(unconfirmed.len(), 1);
+        let expected_tx =
+            BitcoinBlockParser::new(BitcoinNetworkType::Regtest, MagicBytes([105, 100]))
+                .parse_tx(&burn_tx, 0, StacksEpochId::Epoch20)
+                .unwrap();
+        assert_eq!(unconfirmed[0].tx, expected_tx);
+        assert!(!indexer.runtime.mempool.as_ref().unwrap().has_requests());
+
+        // once it confirms, it's no longer unconfirmed
+        let block = BitcoinBlock::new(
+            1,
+            &BurnchainHeaderHash([1; 32]),
+            &BurnchainHeaderHash([0; 32]),
+            vec![expected_tx],
+            get_epoch_time_secs(),
+        );
+        indexer.confirm_unconfirmed_txs(&block);
+        assert!(indexer.get_unconfirmed_txs().is_empty());
+
+        indexer.disconnect();
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn test_mempool_watch_expires() {
+        let tx = deserialize(&hex_bytes("0100000001d8b97932f097b9fbf0c7584f29515862911ac830826fdfd72d06402c21543e38000000006a47304402202801bc5d11eefddc586b1171bf607cc2be1c661d22e215153f2630316f973a200220628cc08858bba3f0cda661dbef2f007e48f8cb531edc0b54edb573226816f253012103d6967618e0159c9bfcd03ea33d368c8b2a98af5a054364c6b5e7215d7d809169ffffffff030000000000000000356a336469240efa29f955c6ae3bb5037039d89dba5e00000000000000000000000000535441434b5300000000000003e854455354217c150000000000001976a914cfd25e09f2d33e1aec73bfcc5b608ec513bbe6c088ac34460200000000001976a9144cb912533a6935880df7647fd5232e40aca07b8088ac00000000").unwrap()).unwrap();
+
+        // "di" magic bytes
+        let mut watcher = MempoolWatcher::new(
+            BitcoinNetworkType::Regtest,
+            MagicBytes([100, 105]),
+            Duration::from_secs(60),
+        );
+        let unconfirmed = watcher
+            .handle_tx(&tx, StacksEpochId::Epoch21, 1000)
+            .unwrap();
+        assert_eq!(unconfirmed.first_seen, 1000);
+        assert_eq!(watcher.get_unconfirmed(), vec![unconfirmed]);
+
+        // seen transactions aren't asked for or taken twice
+        let inv = Inventory {
+            inv_type: InvType::Transaction,
+            hash: tx.txid(),
+        };
+        assert!(watcher.handle_inv(&[inv.clone()]).is_empty());
+        assert!(watcher
+            .handle_tx(&tx, StacksEpochId::Epoch21, 1010)
+            .is_none());
+
+        watcher.expire(1059);
+        assert_eq!(watcher.get_unconfirmed().len(), 1);
+        watcher.expire(1060);
+        assert!(watcher.get_unconfirmed().is_empty());
+
+        // once expired, it's forgotten, so it can be asked for again
+        assert_eq!(watcher.handle_inv(&[inv]), vec![tx.txid()]);
+        assert!(watcher.has_requests());
+        assert!(watcher
+            .handle_tx(&tx, StacksEpochId::Epoch21, 1070)
+            .is_some());
+    }
+
+    #[test]
+    fn test_custom_network_addresses() {
+        let addr = LegacyBitcoinAddress {
+            addrtype: LegacyBitcoinAddressType::PublicKeyHash,
+            network_id: BitcoinNetworkType::Regtest,
+            bytes: Hash160([0x11; 20]),
+        };
+        let mut params = CustomNetworkParams {
+            magic: 0xDEADBEEF,
+            default_port: 18555,
+            p2pkh_version: 0x6f,
+            p2sh_version: 0xc4,
+        };
+        assert_eq!(params.to_b58(&addr), addr.to_b58());
+
+        params.p2pkh_version = 0x00;
+        let mainnet_addr = LegacyBitcoinAddress {
+            network_id: BitcoinNetworkType::Mainnet,
+            ..addr.clone()
+        };
+        assert_eq!(params.to_b58(&addr), mainnet_addr.to_b58());
+        assert_eq!(
+            params.legacy_address_version(LegacyBitcoinAddressType::ScriptHash),
+            0xc4
+        );
+    }
+
+    #[test]
+    fn test_indexer_find_bitcoin_reorg_genesis() {
+        let path_1 = "/tmp/test-indexer-find_bitcoin_reorg_genesis.dat";
+        let path_2 = "/tmp/test-indexer-find_bitcoin_reorg_genesis.dat.reorg.bak";
+        let path_reorg = "/tmp/test-indexer-find_bitcoin_reorg_genesis.dat.reorg";
+
+        if fs::metadata(path_1).is_ok() {
+            fs::remove_file(path_1).unwrap();
+        }
+        if fs::metadata(path_2).is_ok() {
+            fs::remove_file(path_2).unwrap();
+        }
+        if fs::metadata(path_reorg).is_ok() {
+            fs::remove_file(path_reorg).unwrap();
+        }
+
+        // two header sets -- both of which build off of the genesis block
+        let headers_1 = vec![
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "20bee96458517fc5082a9720ce6207b5742f2b18e4e0a7e7373342725d80f88c",
+                    )
+                    .unwrap(),
+                    nonce: 2,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+                    )
+                    .unwrap(),
+                    time: 1587626881,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "39d1a6f1ee7a5903797f92ec89e4c58549013f38114186fc2eb6e5218cb2d0ac",
+                    )
+                    .unwrap(),
+                    nonce: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "606d31daaaa5919f3720d8440dd99d31f2a4e4189c65879f19ae43268425e74b",
+                    )
+                    .unwrap(),
+                    time: 1587626882,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "a7e04ed25f589938eb5627abb7b5913dd77b8955bcdf72d7f111d0a71e346e47",
+                    )
+                    .unwrap(),
+                    nonce: 4,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "2fa2f451ac27f0e5cd3760ba6cdf34ef46adb76a44d96bc0f3bf3e713dd955f0",
+                    )
+                    .unwrap(),
+                    time: 1587626882,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+        ];
+
+        let headers_2 = vec![
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "677351ef5cd586c8d0ee7c242e0c5794d0bb4564107e567fd24e508aa66c8b79",
+                    )
+                    .unwrap(),
+                    nonce: 0,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+                    )
+                    .unwrap(),
+                    time: 1587612061,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "a92e6612c0cde9b029081d90b1dcef97f95508b92dd982223c8fcbe9e953fc79",
+                    )
+                    .unwrap(),
+                    nonce: 0,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "0f4865e8169da0cb265ab0ea9eef440e6b7cc9bc1d5e74e4627c0f1e83e67e95",
+                    )
+                    .unwrap(),
+                    time: 1587612062,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "5bd6f6f0863582bb6910d772829b7cf36be262b74ac5775ef9d78180c90a9fef",
+                    )
+                    .unwrap(),
+                    nonce: 0,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "2dbbe38703af1918ef4091dfea226db8868843a55a4673e3d2d7259083b07063",
+                    )
+                    .unwrap(),
+                    time: 1587612062,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+        ];
+
+        let mut spv_client =
+            SpvClient::new(path_1, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        let mut spv_client_reorg =
+            SpvClient::new(path_2, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+
+        spv_client
+            .insert_block_headers_after(0, headers_1.clone())
+            .unwrap();
+        spv_client_reorg
+            .insert_block_headers_after(0, headers_2.clone())
+            .unwrap();
+
+        spv_client.update_chain_work().unwrap();
+        spv_client_reorg.update_chain_work().unwrap();
+
+        assert_eq!(spv_client.read_block_headers(0, 10).unwrap().len(), 4);
+        assert_eq!(spv_client_reorg.read_block_headers(0, 10).unwrap().len(), 4);
+
+        assert_eq!(spv_client_reorg.read_block_headers(2, 10).unwrap().len(), 2);
+
+        let mut indexer = BitcoinIndexer::new(
+            BitcoinIndexerConfig::test_default(path_1.to_string()),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        let common_ancestor_height = indexer
+            .inner_find_bitcoin_reorg(
+                path_1,
+                path_reorg,
+                |ref mut indexer, ref mut spv_client, start_block, end_block_opt| {
+                    // mock the bitcoind by just copying over the relevant headers from our backup reorg db
+                    let end_block = end_block_opt.unwrap_or(10000000);
+                    let hdrs = spv_client_reorg
+                        .read_block_headers(start_block, end_block)
+                        .unwrap();
+
+                    if start_block > 0 {
+                        test_debug!("insert at {}: {:?}", start_block - 1, &hdrs);
+                        spv_client
+                            .insert_block_headers_before(start_block - 1, hdrs)
+                            .unwrap();
+                    } else if hdrs.len() > 0 {
+                        test_debug!("insert at {}: {:?}", 0, &hdrs);
+                        spv_client.test_write_block_headers(0, hdrs).unwrap();
+                    }
+
+                    Ok(())
+                },
+                false,
+            )
+            .unwrap();
+
+        // lowest common ancestor is the genesis block
+        assert_eq!(common_ancestor_height, 0);
+    }
+
+    #[test]
+    fn test_indexer_find_bitcoin_reorg_midpoint() {
+        let path_1 = "/tmp/test-indexer-find_bitcoin_reorg_midpoint.dat";
+        let path_2 = "/tmp/test-indexer-find_bitcoin_reorg_midpoint.dat.reorg.bak";
+        let path_reorg = "/tmp/test-indexer-find_bitcoin_reorg_midpoint.dat.reorg";
+
+        if fs::metadata(path_1).is_ok() {
+            fs::remove_file(path_1).unwrap();
+        }
+        if fs::metadata(path_2).is_ok() {
+            fs::remove_file(path_2).unwrap();
+        }
+
+        // two header sets -- both of which build off of same first block
+        let headers_1 = vec![
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "677351ef5cd586c8d0ee7c242e0c5794d0bb4564107e567fd24e508aa66c8b79",
+                    )
+                    .unwrap(),
+                    nonce: 0,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+                    )
+                    .unwrap(),
+                    time: 1587612061,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "39d1a6f1ee7a5903797f92ec89e4c58549013f38114186fc2eb6e5218cb2d0ac",
+                    )
+                    .unwrap(),
+                    nonce: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "0f4865e8169da0cb265ab0ea9eef440e6b7cc9bc1d5e74e4627c0f1e83e67e95",
+                    )
+                    .unwrap(),
+                    time: 1587626882,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "a7e04ed25f589938eb5627abb7b5913dd77b8955bcdf72d7f111d0a71e346e47",
+                    )
+                    .unwrap(),
+                    nonce: 4,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "7a06268e099dafa4549d9e2511c251497779cf16ab3b5363e5b25d3dd6f552e7",
+                    )
+                    .unwrap(),
+                    time: 1587626882,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+        ];
+
+        let headers_2 = vec![
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "677351ef5cd586c8d0ee7c242e0c5794d0bb4564107e567fd24e508aa66c8b79",
+                    )
+                    .unwrap(),
+                    nonce: 0,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+                    )
+                    .unwrap(),
+                    time: 1587612061,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "a92e6612c0cde9b029081d90b1dcef97f95508b92dd982223c8fcbe9e953fc79",
+                    )
+                    .unwrap(),
+                    nonce: 0,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "0f4865e8169da0cb265ab0ea9eef440e6b7cc9bc1d5e74e4627c0f1e83e67e95",
+                    )
+                    .unwrap(),
+                    time: 1587612062,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "5bd6f6f0863582bb6910d772829b7cf36be262b74ac5775ef9d78180c90a9fef",
+                    )
+                    .unwrap(),
+                    nonce: 0,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "2dbbe38703af1918ef4091dfea226db8868843a55a4673e3d2d7259083b07063",
+                    )
+                    .unwrap(),
+                    time: 1587612062,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+        ];
+
+        let mut spv_client =
+            SpvClient::new(path_1, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        let mut spv_client_reorg =
+            SpvClient::new(path_2, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+
+        spv_client
+            .insert_block_headers_after(0, headers_1.clone())
+            .unwrap();
+        spv_client_reorg
+            .insert_block_headers_after(0, headers_2.clone())
+            .unwrap();
+
+        assert_eq!(spv_client.read_block_headers(0, 10).unwrap().len(), 4);
+        assert_eq!(spv_client_reorg.read_block_headers(0, 10).unwrap().len(), 4);
+
+        assert_eq!(spv_client_reorg.read_block_headers(2, 10).unwrap().len(), 2);
+
+        let mut indexer = BitcoinIndexer::new(
+            BitcoinIndexerConfig::test_default(path_1.to_string()),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        let common_ancestor_height = indexer
+            .inner_find_bitcoin_reorg(
+                path_1,
+                path_reorg,
+                |ref mut indexer, ref mut spv_client, start_block, end_block_opt| {
+                    // mock the bitcoind by just copying over the relevant headers from our backup reorg db
+                    let end_block = end_block_opt.unwrap_or(10000000);
+                    let hdrs = spv_client_reorg
+                        .read_block_headers(start_block, end_block)
+                        .unwrap();
+                    if start_block > 0 {
+                        spv_client
+                            .insert_block_headers_before(start_block - 1, hdrs)
+                            .unwrap();
+                    } else if hdrs.len() > 0 {
+                        test_debug!("insert at {}: {:?}", 0, &hdrs);
+                        spv_client.test_write_block_headers(0, hdrs).unwrap();
+                    }
+                    Ok(())
+                },
+                false,
+            )
+            .unwrap();
+
+        // lowest common ancestor is the first block
+        assert_eq!(common_ancestor_height, 1);
+    }
+
+    #[test]
+    fn test_indexer_sync_headers() {
+        if !env::var("BLOCKSTACK_SPV_BITCOIN_HOST").is_ok() {
+            eprintln!(
+                "Skipping test_indexer_sync_headers -- no BLOCKSTACK_SPV_BITCOIN_HOST envar set"
+            );
+            return;
+        }
+        if !env::var("BLOCKSTACK_SPV_BITCOIN_PORT").is_ok() {
+            eprintln!(
+                "Skipping test_indexer_sync_headers -- no BLOCKSTACK_SPV_BITCOIN_PORT envar set"
+            );
+            return;
+        }
+        if !env::var("BLOCKSTACK_SPV_BITCOIN_MODE").is_ok() {
+            eprintln!(
+                "Skipping test_indexer_sync_headers -- no BLOCKSTACK_SPV_BITCOIN_MODE envar set"
+            );
+            return;
+        }
+
+        let host = env::var("BLOCKSTACK_SPV_BITCOIN_HOST").unwrap();
+        let port = env::var("BLOCKSTACK_SPV_BITCOIN_PORT")
+            .unwrap()
+            .parse::<u16>()
+            .unwrap();
+        let mode = match env::var("BLOCKSTACK_SPV_BITCOIN_MODE").unwrap().as_str() {
+            "mainnet" => BitcoinNetworkType::Mainnet,
+            "testnet" => BitcoinNetworkType::Testnet,
+            "regtest" => BitcoinNetworkType::Regtest,
+            "signet" => BitcoinNetworkType::Signet,
+            _ => {
+                panic!("Invalid bitcoin mode -- expected mainnet, testnet, regtest, or signet");
+            }
+        };
+
+        let db_path = "/tmp/test_indexer_sync_headers.sqlite";
+        let indexer_conf = BitcoinIndexerConfig {
+            peer_host: host,
+            peer_port: port,
+            rpc_port: port + 1, // ignored
+            rpc_ssl: false,
+            username: Some("blockstack".to_string()),
+            password: Some("blockstacksystem".to_string()),
+            timeout: 30,
+            spv_headers_path: db_path.to_string(),
+            first_block: 0,
+            first_block_hash: None,
+            magic_bytes: MagicBytes([105, 100]),
+            epochs: None,
+            checkpoints: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            connect_timeout: None,
+            address_preference: AddressPreference::System,
+            socks_proxy: None,
+            read_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+            write_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+            header_sync_peers: vec![],
+            header_sync_num_peers: DEFAULT_HEADER_SYNC_NUM_PEERS,
+            header_sync_chunk_size: DEFAULT_HEADER_SYNC_CHUNK_SIZE,
+            header_stall_timeout: None,
+            mempool_ttl: None,
+            header_pruning: None,
+            spv_mmap_size: 0,
+            filter_scripts: vec![],
+            custom_network: None,
+            assume_valid: None,
+            assume_valid_checkpoints: false,
+            strict_header_versions: false,
+            custom_genesis: None,
+            first_burn_block: None,
+            block_download_window: DEFAULT_BLOCK_DOWNLOAD_WINDOW,
+            block_parse_threads: DEFAULT_BLOCK_PARSE_THREADS,
+            sig_verify_threads: 0,
+            block_request_timeout: Duration::from_secs(DEFAULT_BLOCK_REQUEST_TIMEOUT),
+            shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT),
+            sync_progress_log_interval: Some(Duration::from_secs(
+                DEFAULT_SYNC_PROGRESS_LOG_INTERVAL,
+            )),
+            block_download_rate_limit: None,
+            keepalive: None,
+            sendheaders_grace: None,
+            header_source: HeaderSourceType::Peer,
+            sync_mode: SyncMode::P2p,
+            pruned_block_fallback: None,
+            request_limit: None,
+            rpc_max_connections: DEFAULT_RPC_MAX_CONNECTIONS,
+            rpc_retries: DEFAULT_RPC_RETRIES,
+            header_fsync_interval: DEFAULT_HEADER_FSYNC_INTERVAL,
+            header_fsync_period: DEFAULT_HEADER_FSYNC_PERIOD,
+            filter_outpoints: vec![],
+            bloom_filter_fp_rate: DEFAULT_BLOOM_FP_RATE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+            dry_run: false,
+            drop_reorged_blocks: false,
+            max_reorg_depth: Some(DEFAULT_MAX_REORG_DEPTH),
+        };
+
+        if fs::metadata(&indexer_conf.spv_headers_path).is_ok() {
+            fs::remove_file(&indexer_conf.spv_headers_path).unwrap();
+        }
+
+        let mut indexer = BitcoinIndexer::new(indexer_conf, BitcoinIndexerRuntime::new(mode));
+        let last_block = indexer.sync_headers(0, None).unwrap();
+        eprintln!("sync'ed to block {}", last_block);
+
+        // compare against known-good chain work
+        let chain_work: Vec<(u64, &str)> = vec![
+            (
+                0,
+                "000000000000000000000000000000000000000000000000000007e007e007e0",
+            ),
+            (
+                1,
+                "00000000000000000000000000000000000000000000000000000fc00fc00fc0",
+            ),
+            (
+                2,
+                "000000000000000000000000000000000000000000000000000017a017a017a0",
+            ),
+            (
+                3,
+                "00000000000000000000000000000000000000000000000000001f801f801f80",
+            ),
+            (
+                4,
+                "0000000000000000000000000000000000000000000000000000276027602760",
+            ),
+            (
+                5,
+                "00000000000000000000000000000000000000000000000000002f402f402f40",
+            ),
+            (
+                6,
+                "0000000000000000000000000000000000000000000000000000372037203720",
+            ),
+            (
+                7,
+                "00000000000000000000000000000000000000000000000000003f003f003f00",
+            ),
+            (
+                8,
+                "000000000000000000000000000000000000000000000000000046e046e046e0",
+            ),
+            (
+                9,
+                "00000000000000000000000000000000000000000000000000004ec04ec04ec0",
             ),
             (
                 10,
@@ -3072,425 +6586,3380 @@ mod test {
                 363,
                 "00000000000000000000000000000000000000002cc925a3aa907374b5f0b6c0",
             ),
-            (
-                364,
-                "00000000000000000000000000000000000000002d9e8bc0134624c9d3ce88c0",
+            (
+                364,
+                "00000000000000000000000000000000000000002d9e8bc0134624c9d3ce88c0",
+            ),
+            (
+                365,
+                "00000000000000000000000000000000000000002e7e60cf6c8d3d2643d9ed00",
+            ),
+        ];
+
+        let spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Mainnet, false, false).unwrap();
+        for (interval, work_str) in chain_work.iter() {
+            let calculated_work = spv_client.find_interval_work(*interval).unwrap().unwrap();
+            let expected_work = Uint256::from_hex_be(work_str).unwrap();
+            assert_eq!(calculated_work, expected_work);
+        }
+    }
+
+    #[test]
+    fn test_spv_check_work_reorg_ignored() {
+        if !env::var("BLOCKSTACK_SPV_HEADERS_DB").is_ok() {
+            eprintln!("Skipping test_spv_check_work_reorg_ignored -- no BLOCKSTACK_SPV_HEADERS_DB envar set");
+            return;
+        }
+        let db_path_source = env::var("BLOCKSTACK_SPV_HEADERS_DB").unwrap();
+        let db_path = "/tmp/test_spv_check_work_reorg_ignored.dat".to_string();
+        let reorg_db_path = "/tmp/test_spv_check_work_ignored.dat.reorg".to_string();
+
+        if fs::metadata(&db_path).is_ok() {
+            fs::remove_file(&db_path).unwrap();
+        }
+
+        if fs::metadata(&reorg_db_path).is_ok() {
+            fs::remove_file(&reorg_db_path).unwrap();
+        }
+
+        fs::copy(&db_path_source, &db_path).unwrap();
+
+        {
+            // set up SPV client so we don't have chain work at first
+            let mut spv_client = SpvClient::new_without_migration(
+                &db_path,
+                0,
+                None,
+                BitcoinNetworkType::Mainnet,
+                true,
+                false,
+            )
+            .unwrap();
+
+            assert!(
+                spv_client.get_headers_height().unwrap() >= 40322,
+                "This test needs headers up to 40320"
+            );
+            spv_client.drop_headers(40320).unwrap();
+        }
+
+        let mut spv_client =
+            SpvClient::new(&db_path, 0, None, BitcoinNetworkType::Mainnet, true, false).unwrap();
+
+        assert_eq!(spv_client.get_headers_height().unwrap(), 40321);
+        let total_work_before = spv_client.update_chain_work().unwrap();
+        assert_eq!(total_work_before, spv_client.get_chain_work().unwrap());
+
+        let total_work_before_idempotent = spv_client.update_chain_work().unwrap();
+        assert_eq!(total_work_before, total_work_before_idempotent);
+
+        // fake block headers for mainnet 40319-40320, which is on a difficulty adjustment boundary
+        let bad_headers = vec![
+            LoneBlockHeader {
+                header: BlockHeader {
+                    version: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "000000000683a474ef810000fd22f0edde4cf33ae76ae506b220e57aeeafeaa4",
+                    )
+                    .unwrap(),
+                    merkle_root: Sha256dHash::from_hex(
+                        "b4d736ca74838036ebd19b085c3eeb9ffec2307f6452347cdd8ddaa249686f39",
+                    )
+                    .unwrap(),
+                    time: 1716199659,
+                    bits: 486575299,
+                    nonce: 201337507,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    version: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "000000006f403731d720174cd6875e331ac079b438cf53aa685f9cd068fd4ca8",
+                    )
+                    .unwrap(),
+                    merkle_root: Sha256dHash::from_hex(
+                        "a86b3c149f204d4cb47c67bf9bfeea2719df101dd6e6fc3f0e60d86efeba22a8",
+                    )
+                    .unwrap(),
+                    time: 1716161259,
+                    bits: 486604799,
+                    nonce: 144574511,
+                },
+                tx_count: VarInt(0),
+            },
+        ];
+
+        let mut indexer = BitcoinIndexer::new(
+            BitcoinIndexerConfig::test_default(db_path.to_string()),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Mainnet),
+        );
+
+        let mut inserted_bad_header = false;
+
+        let new_tip = indexer
+            .find_bitcoin_reorg(
+                &db_path,
+                &reorg_db_path,
+                |ref mut indexer, ref mut reorg_spv_client, start_block, end_block_opt| {
+                    let end_block =
+                        end_block_opt.unwrap_or(start_block + BLOCK_DIFFICULTY_CHUNK_SIZE);
+
+                    let mut ret = vec![];
+                    for block_height in start_block..end_block {
+                        if block_height > 40320 {
+                            break;
+                        }
+                        if block_height >= 40319 && block_height <= 40320 {
+                            test_debug!("insert bad header {}", block_height);
+                            ret.push(bad_headers[(block_height - 40319) as usize].clone());
+                            inserted_bad_header = true;
+                        } else {
+                            let orig_spv_client = SpvClient::new_without_migration(
+                                &db_path,
+                                0,
+                                None,
+                                BitcoinNetworkType::Mainnet,
+                                true,
+                                false,
+                            )
+                            .unwrap();
+                            let hdr = orig_spv_client.read_block_header(block_height)?.unwrap();
+                            ret.push(hdr);
+                        }
+                    }
+
+                    test_debug!(
+                        "add headers after {} (bad header: {})",
+                        start_block,
+                        inserted_bad_header
+                    );
+                    reorg_spv_client
+                        .insert_block_headers_after(start_block - 1, ret)
+                        .unwrap();
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        assert!(inserted_bad_header);
+
+        // reorg is ignored
+        assert_eq!(new_tip, 40321);
+        let total_work_after = spv_client.update_chain_work().unwrap();
+        assert_eq!(total_work_after, total_work_before);
+    }
+
+    #[test]
+    fn test_spv_check_work_reorg_accepted() {
+        if !env::var("BLOCKSTACK_SPV_HEADERS_DB").is_ok() {
+            eprintln!("Skipping test_spv_check_work_reorg_accepted -- no BLOCKSTACK_SPV_HEADERS_DB envar set");
+            return;
+        }
+        let db_path_source = env::var("BLOCKSTACK_SPV_HEADERS_DB").unwrap();
+        let db_path = "/tmp/test_spv_check_work_reorg_accepted.dat".to_string();
+        let reorg_db_path = "/tmp/test_spv_check_work_reorg_accepted.dat.reorg".to_string();
+
+        if fs::metadata(&db_path).is_ok() {
+            fs::remove_file(&db_path).unwrap();
+        }
+
+        if fs::metadata(&reorg_db_path).is_ok() {
+            fs::remove_file(&reorg_db_path).unwrap();
+        }
+
+        fs::copy(&db_path_source, &db_path).unwrap();
+
+        // set up SPV client so we don't have chain work at first
+        let mut spv_client = SpvClient::new_without_migration(
+            &db_path,
+            0,
+            None,
+            BitcoinNetworkType::Mainnet,
+            true,
+            false,
+        )
+        .unwrap();
+
+        assert!(
+            spv_client.get_headers_height().unwrap() >= 40322,
+            "This test needs headers up to 40320"
+        );
+        spv_client.drop_headers(40320).unwrap();
+
+        assert_eq!(spv_client.get_headers_height().unwrap(), 40321);
+
+        // fake block headers for mainnet 40319-40320, which is on a difficulty adjustment boundary
+        let bad_headers = vec![
+            LoneBlockHeader {
+                header: BlockHeader {
+                    version: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "000000000683a474ef810000fd22f0edde4cf33ae76ae506b220e57aeeafeaa4",
+                    )
+                    .unwrap(),
+                    merkle_root: Sha256dHash::from_hex(
+                        "b4d736ca74838036ebd19b085c3eeb9ffec2307f6452347cdd8ddaa249686f39",
+                    )
+                    .unwrap(),
+                    time: 1716199659,
+                    bits: 486575299,
+                    nonce: 201337507,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    version: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "000000006f403731d720174cd6875e331ac079b438cf53aa685f9cd068fd4ca8",
+                    )
+                    .unwrap(),
+                    merkle_root: Sha256dHash::from_hex(
+                        "a86b3c149f204d4cb47c67bf9bfeea2719df101dd6e6fc3f0e60d86efeba22a8",
+                    )
+                    .unwrap(),
+                    time: 1716161259,
+                    bits: 486604799,
+                    nonce: 144574511,
+                },
+                tx_count: VarInt(0),
+            },
+        ];
+
+        // get the canonical chain's headers for this range
+        let good_headers = spv_client.read_block_headers(40319, 40321).unwrap();
+        assert_eq!(good_headers.len(), 2);
+        assert_eq!(
+            good_headers[0].header.prev_blockhash,
+            bad_headers[0].header.prev_blockhash
+        );
+        assert!(good_headers[0].header != bad_headers[0].header);
+        assert!(good_headers[1].header != bad_headers[1].header);
+
+        // put these bad headers into the "main" chain
+        spv_client
+            .insert_block_headers_after(40318, bad_headers.clone())
+            .unwrap();
+
+        // *now* calculate main chain work
+        SpvClient::test_db_migrate(spv_client.conn_mut()).unwrap();
+        let total_work_before = spv_client.update_chain_work().unwrap();
+        assert_eq!(total_work_before, spv_client.get_chain_work().unwrap());
+
+        let total_work_before_idempotent = spv_client.update_chain_work().unwrap();
+        assert_eq!(total_work_before, total_work_before_idempotent);
+
+        let mut indexer = BitcoinIndexer::new(
+            BitcoinIndexerConfig::test_default(db_path.to_string()),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Mainnet),
+        );
+
+        let mut inserted_good_header = false;
+
+        let new_tip = indexer
+            .find_bitcoin_reorg(
+                &db_path,
+                &reorg_db_path,
+                |ref mut indexer, ref mut reorg_spv_client, start_block, end_block_opt| {
+                    let end_block =
+                        end_block_opt.unwrap_or(start_block + BLOCK_DIFFICULTY_CHUNK_SIZE);
+
+                    let mut ret = vec![];
+                    for block_height in start_block..end_block {
+                        if block_height > 40320 {
+                            break;
+                        }
+                        if block_height >= 40319 && block_height <= 40320 {
+                            test_debug!("insert good header {}", block_height);
+                            ret.push(good_headers[(block_height - 40319) as usize].clone());
+                            inserted_good_header = true;
+                        } else {
+                            let orig_spv_client = SpvClient::new_without_migration(
+                                &db_path,
+                                0,
+                                None,
+                                BitcoinNetworkType::Mainnet,
+                                true,
+                                false,
+                            )
+                            .unwrap();
+                            let hdr = orig_spv_client.read_block_header(block_height)?.unwrap();
+                            ret.push(hdr);
+                        }
+                    }
+
+                    test_debug!(
+                        "add headers after {} (good header: {})",
+                        start_block,
+                        inserted_good_header
+                    );
+                    reorg_spv_client
+                        .insert_block_headers_after(start_block - 1, ret)
+                        .unwrap();
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        assert!(inserted_good_header);
+
+        // chain reorg detected!
+        assert_eq!(new_tip, 40318);
+
+        // total work increased
+        let total_work_after = spv_client.update_chain_work().unwrap();
+        assert!(total_work_after > total_work_before);
+    }
+
+    #[test]
+    fn test_check_header_timestamp() {
+        let db_path = "/tmp/test-indexer-check-header-timestamp.dat";
+
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+
+        let headers = vec![
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "20bee96458517fc5082a9720ce6207b5742f2b18e4e0a7e7373342725d80f88c",
+                    )
+                    .unwrap(),
+                    nonce: 2,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+                    )
+                    .unwrap(),
+                    time: (get_epoch_time_secs() - 1) as u32,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "39d1a6f1ee7a5903797f92ec89e4c58549013f38114186fc2eb6e5218cb2d0ac",
+                    )
+                    .unwrap(),
+                    nonce: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "606d31daaaa5919f3720d8440dd99d31f2a4e4189c65879f19ae43268425e74b",
+                    )
+                    .unwrap(),
+                    time: (get_epoch_time_secs() - 1) as u32,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "a7e04ed25f589938eb5627abb7b5913dd77b8955bcdf72d7f111d0a71e346e47",
+                    )
+                    .unwrap(),
+                    nonce: 4,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "2fa2f451ac27f0e5cd3760ba6cdf34ef46adb76a44d96bc0f3bf3e713dd955f0",
+                    )
+                    .unwrap(),
+                    time: 1587626882,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+        ];
+
+        // set up SPV client so we don't have chain work at first
+        let mut spv_client = SpvClient::new_without_migration(
+            &db_path,
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            true,
+            false,
+        )
+        .unwrap();
+
+        spv_client
+            .test_write_block_headers(0, headers.clone())
+            .unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 2);
+
+        let mut indexer = BitcoinIndexer::new(
+            BitcoinIndexerConfig::test_default(db_path.to_string()),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+
+        if let Err(burnchain_error::TrySyncAgain) = indexer.check_chain_tip_timestamp() {
+        } else {
+            panic!("stale tip not detected");
+        }
+
+        // peeled
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 1);
+        assert!(indexer.check_chain_tip_timestamp().is_ok());
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let policy = ReconnectPolicy {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+            multiplier: 2.0,
+            reset_threshold: Duration::from_secs(60),
+            max_attempts: 0,
+        };
+        let mut backoff = ReconnectBackoff::new();
+        let mut rng = thread_rng();
+        let now = Instant::now();
+
+        // twenty failed attempts in a row
+        let mut ceilings = vec![];
+        for _ in 0..20 {
+            backoff.reconnect_needed();
+            let delay = backoff.next_delay(&policy, now, &mut rng);
+            assert!(!backoff.reconnect_pending);
+            assert!(delay <= backoff.ceiling);
+            assert_eq!(delay, backoff.last_delay);
+            ceilings.push(backoff.ceiling);
+        }
+        assert_eq!(backoff.attempts, 20);
+
+        // delays grow from the base delay by the multiplier, until they hit the cap
+        for (i, ceiling) in ceilings.iter().enumerate() {
+            let expected = cmp::min(Duration::from_secs(1 << i), policy.max_delay);
+            assert_eq!(*ceiling, expected);
+        }
+        assert_eq!(*ceilings.last().unwrap(), policy.max_delay);
+
+        // still capped after a very long time
+        backoff.attempts = u32::MAX;
+        backoff.next_delay(&policy, now, &mut rng);
+        assert_eq!(backoff.ceiling, policy.max_delay);
+        assert_eq!(backoff.attempts, u32::MAX);
+
+        // a connection that drops quickly does not reset the backoff
+        backoff.connected(now);
+        backoff.next_delay(&policy, now + Duration::from_secs(59), &mut rng);
+        assert_eq!(backoff.ceiling, policy.max_delay);
+        assert!(backoff.connected_since.is_none());
+
+        // a connection that stays up long enough does
+        backoff.connected(now);
+        backoff.next_delay(&policy, now + Duration::from_secs(60), &mut rng);
+        assert_eq!(backoff.ceiling, policy.base_delay);
+        assert_eq!(backoff.attempts, 1);
+        backoff.next_delay(&policy, now + Duration::from_secs(60), &mut rng);
+        assert_eq!(backoff.ceiling, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_reconnect_backoff_after_drops() {
+        let db_path = "/tmp/test_reconnect_backoff_after_drops.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let chain = Arc::new(Mutex::new(make_regtest_block_chain(3)));
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // a peer that shakes hands, and then hangs up as soon as we ask it for anything
+        spawn_mock_dropping_chain_peer(listener, chain, Some(1), None);
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = port;
+        config.reconnect_policy = ReconnectPolicy {
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(4),
+            multiplier: 2.0,
+            reset_threshold: Duration::from_secs(60),
+            max_attempts: 5,
+        };
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        let events = Arc::new(Mutex::new(vec![]));
+        let events_inner = events.clone();
+        indexer.set_sync_observer(Arc::new(
+            move |event: &SyncEvent, _timestamp: u64| match event {
+                SyncEvent::PeerConnected { .. } | SyncEvent::ReconnectBackoff { .. } => {
+                    events_inner.lock().unwrap().push(event.clone())
+                }
+                _ => {}
+            },
+        ));
+
+        match indexer.sync_last_headers(0, None) {
+            Err(btc_error::TooManyReconnects(5)) => {}
+            x => panic!("Expected TooManyReconnects, got {:?}", &x),
+        }
+
+        // each drop is waited out exactly once before the next connection, with delays that
+        // grow until they hit the cap.  The fifth drop uses up the attempts, so there's no
+        // wait after it.
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 9);
+        let mut ceilings = vec![];
+        for (i, event) in events.iter().enumerate() {
+            match (i % 2, event) {
+                (0, SyncEvent::PeerConnected { .. }) => {}
+                (1, SyncEvent::ReconnectBackoff { backoff, .. }) => {
+                    assert_eq!(backoff.attempts as usize, ceilings.len() + 1);
+                    assert!(backoff.last_delay <= backoff.ceiling);
+                    ceilings.push(backoff.ceiling.as_millis());
+                }
+                x => panic!("Unexpected event {:?} at {}", &x, i),
+            }
+        }
+        assert_eq!(ceilings, vec![1, 2, 4, 4]);
+        assert_eq!(indexer.get_reconnect_backoff().attempts, 5);
+    }
+
+    #[test]
+    fn test_reconnect_backoff_schedule() {
+        let policy = ReconnectPolicy {
+            max_attempts: 10,
+            ..ReconnectPolicy::default()
+        };
+        let mut backoff = ReconnectBackoff::new();
+        let mut rng = thread_rng();
+
+        // a fake clock, which only moves when we "sleep"
+        let mut now = Instant::now();
+
+        // bitcoind is down: 1s, doubling up to the 60s cap, with jitter under each
+        let mut ceilings = vec![];
+        while !backoff.exhausted(&policy) {
+            let delay = backoff.next_delay(&policy, now, &mut rng);
+            assert!(delay <= backoff.ceiling);
+            ceilings.push(backoff.ceiling.as_secs());
+            now += delay;
+        }
+        assert_eq!(ceilings, vec![1, 2, 4, 8, 16, 32, 60, 60, 60, 60]);
+        assert_eq!(backoff.attempts, 10);
+
+        // a handshake alone isn't enough to reset the backoff...
+        backoff.attempts = 3;
+        backoff.connected(now);
+        now += Duration::from_secs(1);
+        backoff.next_delay(&policy, now, &mut rng);
+        assert_eq!(backoff.ceiling, Duration::from_secs(8));
+
+        // ...and neither is a message on a connection that never got through it...
+        backoff.message_received();
+        backoff.next_delay(&policy, now, &mut rng);
+        assert_eq!(backoff.ceiling, Duration::from_secs(16));
+
+        // ...but a message after a handshake is
+        backoff.connected(now);
+        backoff.message_received();
+        assert_eq!(backoff.attempts, 0);
+        now += Duration::from_secs(1);
+        backoff.next_delay(&policy, now, &mut rng);
+        assert_eq!(backoff.ceiling, policy.base_delay);
+
+        // a peer that never comes back is given up on
+        let refusing_port = net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut config = BitcoinIndexerConfig::test_default(
+            "/tmp/test_reconnect_backoff_schedule.dat".to_string(),
+        );
+        config.peer_port = refusing_port;
+        config.reconnect_policy = ReconnectPolicy {
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            max_attempts: 3,
+            ..ReconnectPolicy::default()
+        };
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        match indexer.connect_handshake_backoff() {
+            Err(btc_error::TooManyReconnects(3)) => {}
+            x => panic!("Expected TooManyReconnects, got {:?}", &x),
+        }
+        assert_eq!(indexer.runtime.backoff.attempts, 3);
+    }
+
+    fn setup_header_chunks_client(
+        db_path: &str,
+        headers: &[LoneBlockHeader],
+        checkpoint_heights: &[u64],
+    ) -> SpvClient {
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        spv_client.set_checkpoints(
+            checkpoint_heights
+                .iter()
+                .map(|height| {
+                    (
+                        *height,
+                        BurnchainHeaderHash::from_bitcoin_hash(
+                            &headers[(*height - 1) as usize].header.bitcoin_hash(),
+                        ),
+                    )
+                })
+                .collect(),
+        );
+        spv_client
+    }
+
+    #[test]
+    fn test_fetch_header_chunks() {
+        let headers = make_regtest_header_chain(9);
+        let mut spv_client =
+            setup_header_chunks_client("/tmp/test_fetch_header_chunks.dat", &headers, &[3, 6, 9]);
+        let chunks = spv_client.plan_header_chunks(None, 1).unwrap();
+        assert_eq!(chunks.len(), 3);
+
+        // peer 0 always serves a truncated chunk 1; peer 1 is honest
+        let served = Arc::new(std::sync::Mutex::new(vec![]));
+        let served_inner = served.clone();
+        let all_headers = headers.clone();
+        BitcoinIndexer::fetch_header_chunks(
+            &mut spv_client,
+            chunks,
+            2,
+            2,
+            Duration::from_secs(30),
+            move |peer, chunk| {
+                served_inner.lock().unwrap().push((peer, chunk.end_height));
+                let mut chunk_headers = all_headers
+                    [(chunk.start_height as usize)..(chunk.end_height as usize)]
+                    .to_vec();
+                if peer == 0 && chunk.end_height == 6 {
+                    chunk_headers.pop();
+                }
+                Ok(chunk_headers)
+            },
+        )
+        .unwrap();
+
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 9);
+        for (i, hdr) in headers.iter().enumerate() {
+            assert_eq!(
+                spv_client
+                    .read_block_header((i as u64) + 1)
+                    .unwrap()
+                    .unwrap()
+                    .header,
+                hdr.header
+            );
+        }
+
+        // chunk 1 came from peer 1, and was only tried once per peer
+        let served = served.lock().unwrap();
+        assert!(served.contains(&(1, 6)));
+        assert!(served.iter().filter(|(_, end)| *end == 6).count() <= 2);
+    }
+
+    #[test]
+    fn test_fetch_header_chunks_all_peers_fail() {
+        let headers = make_regtest_header_chain(9);
+        let mut spv_client = setup_header_chunks_client(
+            "/tmp/test_fetch_header_chunks_all_peers_fail.dat",
+            &headers,
+            &[3, 6, 9],
+        );
+        let chunks = spv_client.plan_header_chunks(None, 1).unwrap();
+
+        // nobody can serve chunk 2
+        let all_headers = headers.clone();
+        let res = BitcoinIndexer::fetch_header_chunks(
+            &mut spv_client,
+            chunks,
+            3,
+            3,
+            Duration::from_secs(30),
+            move |_peer, chunk| {
+                if chunk.end_height == 9 {
+                    return Err(btc_error::ConnectionBroken);
+                }
+                Ok(
+                    all_headers[(chunk.start_height as usize)..(chunk.end_height as usize)]
+                        .to_vec(),
+                )
+            },
+        );
+        match res {
+            Err(btc_error::ConnectionBroken) => {}
+            res => panic!("Expected ConnectionBroken, got {:?}", res),
+        }
+
+        // the chunks before it were stored
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 6);
+    }
+
+    #[test]
+    fn test_fetch_header_chunks_stalled_peer() {
+        let headers = make_regtest_header_chain(12);
+        let mut spv_client = setup_header_chunks_client(
+            "/tmp/test_fetch_header_chunks_stalled_peer.dat",
+            &headers,
+            &[3, 6, 9, 12],
+        );
+        let chunks = spv_client.plan_header_chunks(None, 1).unwrap();
+        assert_eq!(chunks.len(), 4);
+
+        // peer 0 drops off the network after taking a chunk, and peer 1 sends garbage for
+        // chunk 1.  Peers 1 and 2 serve everything else.
+        let served = Arc::new(std::sync::Mutex::new(vec![]));
+        let served_inner = served.clone();
+        let all_headers = headers.clone();
+        BitcoinIndexer::fetch_header_chunks(
+            &mut spv_client,
+            chunks,
+            3,
+            2,
+            Duration::from_millis(200),
+            move |peer, chunk| {
+                served_inner.lock().unwrap().push((peer, chunk.end_height));
+                let mut chunk_headers = all_headers
+                    [(chunk.start_height as usize)..(chunk.end_height as usize)]
+                    .to_vec();
+                if peer == 0 {
+                    thread::sleep(Duration::from_secs(5));
+                    return Err(btc_error::ConnectionBroken);
+                }
+                if peer == 1 && chunk.end_height == 6 {
+                    chunk_headers.reverse();
+                }
+                Ok(chunk_headers)
+            },
+        )
+        .unwrap();
+
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 12);
+        for (i, hdr) in headers.iter().enumerate() {
+            assert_eq!(
+                spv_client
+                    .read_block_header((i as u64) + 1)
+                    .unwrap()
+                    .unwrap()
+                    .header,
+                hdr.header
+            );
+        }
+
+        // peer 0 only ever got one chunk, and someone else served it
+        let served = served.lock().unwrap();
+        let peer_0_chunks: Vec<_> = served.iter().filter(|(peer, _)| *peer == 0).collect();
+        assert_eq!(peer_0_chunks.len(), 1);
+        let stalled_chunk = peer_0_chunks[0].1;
+        assert!(served
+            .iter()
+            .any(|(peer, end)| *peer != 0 && *end == stalled_chunk));
+        assert!(served.contains(&(2, 6)));
+    }
+
+    #[test]
+    fn test_fetch_header_chunks_max_in_flight() {
+        let headers = make_regtest_header_chain(12);
+        let mut spv_client = setup_header_chunks_client(
+            "/tmp/test_fetch_header_chunks_max_in_flight.dat",
+            &headers,
+            &[2, 4, 6, 8, 10, 12],
+        );
+        let chunks = spv_client.plan_header_chunks(None, 1).unwrap();
+        assert_eq!(chunks.len(), 6);
+
+        let reports = Arc::new(std::sync::Mutex::new(vec![]));
+        let reports_inner = reports.clone();
+        spv_client.set_progress_handler(
+            Arc::new(
+                move |height: u64, tip: u64, _rate: f64| -> Result<(), String> {
+                    reports_inner.lock().unwrap().push((height, tip));
+                    Ok(())
+                },
+            ),
+            1,
+        );
+
+        let active = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let max_active = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let active_inner = active.clone();
+        let max_active_inner = max_active.clone();
+        let all_headers = headers.clone();
+        BitcoinIndexer::fetch_header_chunks(
+            &mut spv_client,
+            chunks,
+            4,
+            2,
+            Duration::from_secs(30),
+            move |_peer, chunk| {
+                let now_active = active_inner.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                max_active_inner.fetch_max(now_active, std::sync::atomic::Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(50));
+                active_inner.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                Ok(
+                    all_headers[(chunk.start_height as usize)..(chunk.end_height as usize)]
+                        .to_vec(),
+                )
+            },
+        )
+        .unwrap();
+
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 12);
+        assert!(max_active.load(std::sync::atomic::Ordering::SeqCst) <= 2);
+
+        // progress is reported as each chunk is stored
+        assert_eq!(
+            *reports.lock().unwrap(),
+            vec![(2, 12), (4, 12), (6, 12), (8, 12), (10, 12), (12, 12)]
+        );
+    }
+
+    /// Mock peer that serves `headers` in batches of `batch_size` in answer to getheaders, and
+    /// ignores every getheaders after the first `max_batches`.  Runs until we hang up, and
+    /// returns how many batches it served.
+    fn spawn_mock_headers_peer(
+        listener: net::TcpListener,
+        headers: Vec<LoneBlockHeader>,
+        batch_size: usize,
+        max_batches: usize,
+    ) -> thread::JoinHandle<usize> {
+        let magic = BITCOIN_REGTEST;
+        thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+            let mut encoder = RawEncoder::new(sock);
+            let mut recv = move || -> Option<NetworkMessage> {
+                let msg: RawNetworkMessage =
+                    ConsensusDecodable::consensus_decode(&mut decoder).ok()?;
+                assert_eq!(msg.magic, magic);
+                Some(msg.payload)
+            };
+            let mut send = move |payload| {
+                RawNetworkMessage { magic, payload }
+                    .consensus_encode(&mut encoder)
+                    .unwrap();
+            };
+
+            let mut version_body = match recv() {
+                Some(NetworkMessage::Version(body)) => body,
+                x => panic!("Expected version, got {:?}", &x),
+            };
+            version_body.services = NODE_NETWORK;
+            version_body.start_height = headers.len() as i32;
+            send(NetworkMessage::Version(version_body));
+            send(NetworkMessage::Verack);
+            assert_eq!(recv(), Some(NetworkMessage::Verack));
+
+            let genesis_hash =
+                SpvClient::get_genesis_header(BitcoinNetworkType::Regtest).bitcoin_hash();
+            let mut batches = 0;
+            while let Some(msg) = recv() {
+                let getheaders = match msg {
+                    NetworkMessage::GetHeaders(getheaders) => getheaders,
+                    x => panic!("Expected getheaders, got {:?}", &x),
+                };
+                if batches >= max_batches {
+                    continue;
+                }
+                let start = if getheaders.locator_hashes[0] == genesis_hash {
+                    0
+                } else {
+                    headers
+                        .iter()
+                        .position(|hdr| hdr.header.bitcoin_hash() == getheaders.locator_hashes[0])
+                        .unwrap()
+                        + 1
+                };
+                let end = cmp::min(start + batch_size, headers.len());
+                send(NetworkMessage::Headers(headers[start..end].to_vec()));
+                batches += 1;
+            }
+            batches
+        })
+    }
+
+    #[test]
+    fn test_header_sync_switches_stalled_peer() {
+        let db_path = "/tmp/test_header_sync_switches_stalled_peer.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let headers = make_regtest_header_chain(6);
+
+        // the first peer stops answering after two batches; the second one has them all
+        let stalling_listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stalling_port = stalling_listener.local_addr().unwrap().port();
+        let stalling_peer = spawn_mock_headers_peer(stalling_listener, headers.clone(), 2, 2);
+
+        let backup_listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let backup_port = backup_listener.local_addr().unwrap().port();
+        let backup_peer = spawn_mock_headers_peer(backup_listener, headers.clone(), 2, usize::MAX);
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = stalling_port;
+        config.header_sync_peers = vec![("127.0.0.1".to_string(), backup_port)];
+        config.header_stall_timeout = Some(Duration::from_millis(500));
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 6);
+        let spv_client = indexer.open_headers_readonly().unwrap();
+        for (i, hdr) in headers.iter().enumerate() {
+            assert_eq!(
+                spv_client
+                    .read_block_header((i as u64) + 1)
+                    .unwrap()
+                    .unwrap()
+                    .header,
+                hdr.header
+            );
+        }
+
+        // the stalling peer was switched away from, and holds the failure; the config is
+        // left alone
+        assert_eq!(indexer.current_peer(), ("127.0.0.1", backup_port));
+        assert_eq!(indexer.config.peer_port, stalling_port);
+        assert_eq!(
+            indexer.config.header_sync_peers,
+            vec![("127.0.0.1".to_string(), backup_port)]
+        );
+        assert_eq!(
+            indexer
+                .runtime
+                .peer_failures
+                .get(&("127.0.0.1".to_string(), stalling_port)),
+            Some(&1)
+        );
+
+        indexer.disconnect();
+        assert_eq!(stalling_peer.join().unwrap(), 2);
+        assert_eq!(backup_peer.join().unwrap(), 1);
+
+        // with no better peer to go to, we stay put
+        indexer
+            .runtime
+            .peer_failures
+            .insert(("127.0.0.1".to_string(), stalling_port), 5);
+        indexer.switch_header_peer();
+        assert_eq!(indexer.peer_port(), backup_port);
+
+        // a peer that has failed as often as the current one gets a turn
+        indexer
+            .runtime
+            .peer_failures
+            .insert(("127.0.0.1".to_string(), backup_port), 4);
+        indexer.switch_header_peer();
+        assert_eq!(indexer.peer_port(), stalling_port);
+    }
+
+    #[test]
+    fn test_header_sync_from_first_burn_block() {
+        let db_path = "/tmp/test_header_sync_from_first_burn_block.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let headers = make_regtest_header_chain(BLOCK_DIFFICULTY_CHUNK_SIZE + 4);
+        let anchor_height = BLOCK_DIFFICULTY_CHUNK_SIZE;
+        let anchor = headers[(anchor_height - 1) as usize].header;
+
+        // every regtest header has the genesis header's work
+        let header_work = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest).work();
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.first_block = anchor_height;
+        config.first_burn_block = Some(FirstBurnBlockParams {
+            height: anchor_height,
+            header_hex: to_hex(&serialize(&anchor).unwrap()),
+            block_hash: BurnchainHeaderHash::from_bitcoin_hash(&anchor.bitcoin_hash()),
+            chain_work: header_work * Uint256::from_u64(anchor_height + 1),
+        });
+
+        // the anchor must be the first header of a difficulty interval...
+        let mut bad_config = config.clone();
+        bad_config.first_burn_block.as_mut().unwrap().height = anchor_height + 1;
+        bad_config.first_block = anchor_height + 1;
+        let mut indexer = BitcoinIndexer::new(
+            bad_config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        match indexer.sync_last_headers(0, None) {
+            Err(btc_error::ConfigError(_)) => {}
+            x => panic!("Expected ConfigError, got {:?}", x),
+        }
+
+        // ...and must match any checkpoint at its height
+        let mut bad_config = config.clone();
+        bad_config.checkpoints = Some(vec![(
+            anchor_height,
+            BurnchainHeaderHash::from_bitcoin_hash(&headers[0].header.bitcoin_hash()),
+        )]);
+        let mut indexer = BitcoinIndexer::new(
+            bad_config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        match indexer.sync_last_headers(0, None) {
+            Err(btc_error::CheckpointMismatch(height)) => assert_eq!(height, anchor_height),
+            x => panic!("Expected CheckpointMismatch, got {:?}", x),
+        }
+        fs::remove_file(db_path).unwrap();
+
+        // sync the headers after the anchor
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        config.peer_port = listener.local_addr().unwrap().port();
+        config.checkpoints = Some(vec![(
+            anchor_height,
+            BurnchainHeaderHash::from_bitcoin_hash(&anchor.bitcoin_hash()),
+        )]);
+        let peer = spawn_mock_headers_peer(listener, headers.clone(), 2000, usize::MAX);
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        assert_eq!(
+            indexer.sync_last_headers(0, None).unwrap(),
+            anchor_height + 4
+        );
+        indexer.disconnect();
+        assert_eq!(peer.join().unwrap(), 1);
+
+        let spv_client = indexer.open_headers_readonly().unwrap();
+        assert_eq!(spv_client.get_pruned_height().unwrap(), anchor_height);
+        for height in anchor_height..(anchor_height + 5) {
+            assert_eq!(
+                spv_client
+                    .read_block_header(height)
+                    .unwrap()
+                    .unwrap()
+                    .header,
+                headers[(height - 1) as usize].header
+            );
+        }
+        assert_eq!(
+            spv_client.get_tip_chain_work().unwrap(),
+            Some(header_work * Uint256::from_u64(anchor_height + 5))
+        );
+
+        // headers below the anchor are never available
+        match spv_client.read_block_header(anchor_height - 1) {
+            Err(btc_error::MissingHeader) => {}
+            x => panic!("Expected MissingHeader, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_header_sync_first_block_hash() {
+        let db_path = "/tmp/test_header_sync_first_block_hash.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let headers = make_regtest_header_chain(110);
+        let first_block_hash =
+            BurnchainHeaderHash::from_bitcoin_hash(&headers[104].header.bitcoin_hash());
+        let wrong_hash =
+            BurnchainHeaderHash::from_bitcoin_hash(&headers[103].header.bitcoin_hash());
+
+        // a header chain without the configured first block is rejected...
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = listener.local_addr().unwrap().port();
+        config.first_block = 105;
+        config.first_block_hash = Some(wrong_hash.clone());
+        let peer = spawn_mock_headers_peer(listener, headers.clone(), 2000, usize::MAX);
+        let mut indexer = BitcoinIndexer::new(
+            config.clone(),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        match indexer.sync_last_headers(0, None) {
+            Err(btc_error::FirstBlockMismatch(height)) => assert_eq!(height, 105),
+            x => panic!("Expected FirstBlockMismatch, got {:?}", x),
+        }
+        indexer.disconnect();
+        peer.join().unwrap();
+        assert_eq!(indexer.get_highest_header_height().unwrap(), 0);
+
+        // ...and one with it is accepted
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        config.peer_port = listener.local_addr().unwrap().port();
+        config.first_block_hash = Some(first_block_hash.clone());
+        let peer = spawn_mock_headers_peer(listener, headers.clone(), 2000, usize::MAX);
+        let mut indexer = BitcoinIndexer::new(
+            config.clone(),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 110);
+        indexer.disconnect();
+        assert_eq!(peer.join().unwrap(), 1);
+        assert_eq!(indexer.get_first_block_height(), 105);
+
+        // already-stored headers are checked too, before asking for more
+        let mut bad_config = config.clone();
+        bad_config.first_block_hash = Some(wrong_hash);
+        let mut indexer = BitcoinIndexer::new(
+            bad_config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        match indexer.sync_last_headers(110, None) {
+            Err(btc_error::FirstBlockMismatch(height)) => assert_eq!(height, 105),
+            x => panic!("Expected FirstBlockMismatch, got {:?}", x),
+        }
+        fs::remove_file(db_path).unwrap();
+
+        // a first block above the chain tip isn't an error:  the headers are synced, and there
+        // just aren't any blocks to process yet
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        config.peer_port = listener.local_addr().unwrap().port();
+        config.first_block = 200;
+        config.first_block_hash = Some(first_block_hash);
+        let peer = spawn_mock_headers_peer(listener, headers.clone(), 2000, usize::MAX);
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 110);
+        indexer.disconnect();
+        assert_eq!(peer.join().unwrap(), 1);
+        assert_eq!(indexer.get_highest_header_height().unwrap(), 110);
+    }
+
+    #[test]
+    fn test_header_sync_shutdown() {
+        let db_path = "/tmp/test_header_sync_shutdown.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let headers = make_regtest_header_chain(6);
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let peer = spawn_mock_headers_peer(listener, headers.clone(), 2, usize::MAX);
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = port;
+        let mut indexer = BitcoinIndexer::new(
+            config.clone(),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+
+        // ask to shut down as soon as the first batch is stored
+        let shutdown = indexer.shutdown_handle();
+        indexer.set_header_sync_progress(
+            Arc::new(
+                move |_height: u64, _tip: u64, _rate: f64| -> Result<(), String> {
+                    shutdown.store(true, Ordering::SeqCst);
+                    Ok(())
+                },
             ),
-            (
-                365,
-                "00000000000000000000000000000000000000002e7e60cf6c8d3d2643d9ed00",
+            1,
+        );
+        // the sync finishes the batch it's on, and stops
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 2);
+        assert!(indexer.dup().is_shutdown_requested());
+        indexer.disconnect();
+        assert_eq!(peer.join().unwrap(), 1);
+
+        // on restart, the headers DB holds exactly the headers synced before the shutdown
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        config.peer_port = listener.local_addr().unwrap().port();
+        let peer = spawn_mock_headers_peer(listener, headers.clone(), 2, usize::MAX);
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        let spv_client = indexer.open_headers_readonly().unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 2);
+        for (i, hdr) in headers[0..2].iter().enumerate() {
+            assert_eq!(
+                spv_client
+                    .read_block_header((i as u64) + 1)
+                    .unwrap()
+                    .unwrap()
+                    .header,
+                hdr.header
+            );
+        }
+
+        // ...and the sync picks up where it left off
+        assert_eq!(indexer.sync_last_headers(2, None).unwrap(), 6);
+        indexer.disconnect();
+        assert_eq!(peer.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_header_sync_shutdown_hung_peer() {
+        let db_path = "/tmp/test_header_sync_shutdown_hung_peer.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let headers = make_regtest_header_chain(6);
+
+        // the peer never answers the second request
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let peer = spawn_mock_headers_peer(listener, headers.clone(), 2, 1);
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = port;
+        config.read_timeout = Duration::from_secs(60);
+        config.shutdown_timeout = Duration::from_millis(500);
+        let mut indexer = BitcoinIndexer::new(
+            config.clone(),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+
+        // ask to shut down while we're waiting on the second batch
+        let shutdown = indexer.shutdown_handle();
+        indexer.set_header_sync_progress(
+            Arc::new(
+                move |_height: u64, _tip: u64, _rate: f64| -> Result<(), String> {
+                    let shutdown = shutdown.clone();
+                    thread::spawn(move || {
+                        thread::sleep(Duration::from_millis(200));
+                        shutdown.store(true, Ordering::SeqCst);
+                    });
+                    Ok(())
+                },
             ),
-        ];
+            1,
+        );
 
-        let spv_client =
-            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Mainnet, false, false).unwrap();
-        for (interval, work_str) in chain_work.iter() {
-            let calculated_work = spv_client.find_interval_work(*interval).unwrap().unwrap();
-            let expected_work = Uint256::from_hex_be(work_str).unwrap();
-            assert_eq!(calculated_work, expected_work);
+        // we give up on the peer shortly after the shutdown, not after the read timeout
+        let sync_start = Instant::now();
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 2);
+        let elapsed = sync_start.elapsed();
+        assert!(elapsed >= Duration::from_millis(700), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(10), "{:?}", elapsed);
+        indexer.disconnect();
+        assert_eq!(peer.join().unwrap(), 1);
+
+        // the headers DB holds exactly the first batch...
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        config.peer_port = listener.local_addr().unwrap().port();
+        let peer = spawn_mock_headers_peer(listener, headers.clone(), 2, usize::MAX);
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        let spv_client = indexer.open_headers_readonly().unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 2);
+        for (i, hdr) in headers[0..2].iter().enumerate() {
+            assert_eq!(
+                spv_client
+                    .read_block_header((i as u64) + 1)
+                    .unwrap()
+                    .unwrap()
+                    .header,
+                hdr.header
+            );
         }
+
+        // ...and the sync picks up where it left off
+        assert_eq!(indexer.sync_last_headers(2, None).unwrap(), 6);
+        indexer.disconnect();
+        assert_eq!(peer.join().unwrap(), 2);
     }
 
     #[test]
-    fn test_spv_check_work_reorg_ignored() {
-        if !env::var("BLOCKSTACK_SPV_HEADERS_DB").is_ok() {
-            eprintln!("Skipping test_spv_check_work_reorg_ignored -- no BLOCKSTACK_SPV_HEADERS_DB envar set");
-            return;
+    fn test_fail_over_refused_peer() {
+        let db_path = "/tmp/test_fail_over_refused_peer.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
         }
-        let db_path_source = env::var("BLOCKSTACK_SPV_HEADERS_DB").unwrap();
-        let db_path = "/tmp/test_spv_check_work_reorg_ignored.dat".to_string();
-        let reorg_db_path = "/tmp/test_spv_check_work_ignored.dat.reorg".to_string();
+        let headers = make_regtest_header_chain(6);
 
-        if fs::metadata(&db_path).is_ok() {
-            fs::remove_file(&db_path).unwrap();
+        // nothing listens on the first peer's port
+        let refusing_port = net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let live_port = listener.local_addr().unwrap().port();
+        let live_peer = spawn_mock_headers_peer(listener, headers.clone(), 2, usize::MAX);
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = refusing_port;
+        config.header_sync_peers = vec![("127.0.0.1".to_string(), live_port)];
+        config.reconnect_policy = ReconnectPolicy {
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            multiplier: 2.0,
+            reset_threshold: Duration::from_secs(60),
+            max_attempts: 0,
+        };
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        assert_eq!(indexer.current_peer(), ("127.0.0.1", refusing_port));
+
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 6);
+        let spv_client = indexer.open_headers_readonly().unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 6);
+        assert_eq!(
+            spv_client.read_block_header(6).unwrap().unwrap().header,
+            headers[5].header
+        );
+
+        // we moved on from the refusing peer without backing off, and left the config alone
+        assert_eq!(indexer.current_peer(), ("127.0.0.1", live_port));
+        assert_eq!(indexer.config.peer_port, refusing_port);
+        assert_eq!(
+            indexer.config.header_sync_peers,
+            vec![("127.0.0.1".to_string(), live_port)]
+        );
+        assert_eq!(indexer.runtime.failovers, 0);
+        assert_eq!(indexer.runtime.backoff.attempts, 0);
+
+        indexer.disconnect();
+        assert_eq!(live_peer.join().unwrap(), 3);
+
+        // once every peer has failed, we back off before going around again
+        indexer.fail_over_peer();
+        assert_eq!(indexer.current_peer(), ("127.0.0.1", refusing_port));
+        assert_eq!(indexer.runtime.backoff.attempts, 0);
+        indexer.fail_over_peer();
+        assert_eq!(indexer.current_peer(), ("127.0.0.1", live_port));
+        assert_eq!(indexer.runtime.backoff.attempts, 1);
+
+        // a peer that keeps sending invalid replies is failed over too
+        for _ in 0..(MAX_INVALID_REPLIES - 1) {
+            indexer.note_invalid_reply();
         }
+        assert_eq!(indexer.current_peer(), ("127.0.0.1", live_port));
+        indexer.note_invalid_reply();
+        assert_eq!(indexer.current_peer(), ("127.0.0.1", refusing_port));
+        assert_eq!(indexer.runtime.invalid_replies, 0);
+    }
 
-        if fs::metadata(&reorg_db_path).is_ok() {
-            fs::remove_file(&reorg_db_path).unwrap();
+    #[test]
+    fn test_sync_observer() {
+        let db_path = "/tmp/test_sync_observer.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
         }
+        let headers = make_regtest_header_chain(6);
 
-        fs::copy(&db_path_source, &db_path).unwrap();
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_port = listener.local_addr().unwrap().port();
+        let peer = spawn_mock_headers_peer(listener, headers.clone(), 2, usize::MAX);
 
-        {
-            // set up SPV client so we don't have chain work at first
-            let mut spv_client = SpvClient::new_without_migration(
-                &db_path,
-                0,
-                None,
-                BitcoinNetworkType::Mainnet,
-                true,
-                false,
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = peer_port;
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let events_inner = events.clone();
+        indexer.set_sync_observer(Arc::new(move |event: &SyncEvent, timestamp: u64| {
+            events_inner
+                .lock()
+                .unwrap()
+                .push((event.clone(), timestamp));
+        }));
+
+        let start_time = get_epoch_time_secs();
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 6);
+        indexer.disconnect();
+        assert_eq!(peer.join().unwrap(), 3);
+
+        let events = events.lock().unwrap();
+        assert!(events.len() >= 3);
+        assert!(events.iter().all(|(_, timestamp)| *timestamp >= start_time));
+        assert!(events.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+
+        // connected, then the tip advanced in order up to the peer's tip, then disconnected
+        assert_eq!(
+            events[0].0,
+            SyncEvent::PeerConnected {
+                host: "127.0.0.1".to_string(),
+                port: peer_port,
+                block_height: 6,
+            }
+        );
+        let tip_heights: Vec<_> = events[1..events.len() - 1]
+            .iter()
+            .map(|(event, _)| match event {
+                SyncEvent::HeaderTipAdvanced {
+                    height,
+                    reorg_depth: 0,
+                    ..
+                } => *height,
+                x => panic!("Expected a header tip update, got {:?}", x),
+            })
+            .collect();
+        assert!(tip_heights.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(
+            events[events.len() - 2].0,
+            SyncEvent::HeaderTipAdvanced {
+                height: 6,
+                hash: BurnchainHeaderHash::from_bitcoin_hash(&headers[5].header.bitcoin_hash()),
+                reorg_depth: 0,
+            }
+        );
+        assert_eq!(
+            events[events.len() - 1].0,
+            SyncEvent::PeerDisconnected {
+                host: "127.0.0.1".to_string(),
+                port: peer_port,
+            }
+        );
+    }
+
+    /// Records the name of each callback it gets, and fails or panics on every callback if
+    /// told to
+    struct RecordingObserver {
+        calls: Arc<Mutex<Vec<String>>>,
+        fail: bool,
+        panic: bool,
+    }
+
+    impl RecordingObserver {
+        fn record(&self, call: String) -> Result<(), String> {
+            self.calls.lock().unwrap().push(call);
+            if self.panic {
+                panic!("observer panicked");
+            }
+            if self.fail {
+                return Err("observer failed".to_string());
+            }
+            Ok(())
+        }
+    }
+
+    impl BurnchainEventObserver for RecordingObserver {
+        fn burn_block_processed(
+            &self,
+            height: u64,
+            _hash: &BurnchainHeaderHash,
+            num_ops: usize,
+        ) -> Result<(), String> {
+            self.record(format!("processed {} {}", height, num_ops))
+        }
+
+        fn reorg_detected(
+            &self,
+            old_tip: &BurnchainTip,
+            new_tip: &BurnchainTip,
+            depth: u64,
+        ) -> Result<(), String> {
+            self.record(format!("reorg {} {} {}", old_tip.0, new_tip.0, depth))
+        }
+
+        fn sync_completed(&self, tip: &BurnchainTip) -> Result<(), String> {
+            self.record(format!("completed {}", tip.0))
+        }
+    }
+
+    #[test]
+    fn test_event_observers() {
+        let mut indexer = BitcoinIndexer::new(
+            BitcoinIndexerConfig::test_default("/tmp/test_event_observers.dat".to_string()),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        assert!(indexer.event_observers().is_empty());
+
+        // observers that panic or fail don't keep the ones after them from being called
+        let panicking_calls = Arc::new(Mutex::new(vec![]));
+        let failing_calls = Arc::new(Mutex::new(vec![]));
+        let calls = Arc::new(Mutex::new(vec![]));
+        let (event_send, event_recv) = channel();
+        indexer.add_event_observer(Box::new(LoggingEventObserver));
+        indexer.add_event_observer(Box::new(RecordingObserver {
+            calls: panicking_calls.clone(),
+            fail: false,
+            panic: true,
+        }));
+        indexer.add_event_observer(Box::new(RecordingObserver {
+            calls: failing_calls.clone(),
+            fail: true,
+            panic: false,
+        }));
+        indexer.add_event_observer(Box::new(RecordingObserver {
+            calls: calls.clone(),
+            fail: false,
+            panic: false,
+        }));
+        indexer.add_event_observer(Box::new(ChannelEventObserver::new(event_send)));
+
+        // the sync's threads get the observers via dup()s
+        let observers = indexer.dup().event_observers();
+        let blocks = make_regtest_block_chain(4);
+        let tip = |height: u64| -> BurnchainTip {
+            (
+                height,
+                BurnchainHeaderHash::from_bitcoin_hash(
+                    &blocks[(height - 1) as usize].bitcoin_hash(),
+                ),
             )
+        };
+        let fork_tip =
+            |height: u64| -> BurnchainTip { (height, BurnchainHeaderHash([height as u8; 32])) };
+
+        // blocks 1 through 3 get processed, and then a reorg replaces blocks 2 and 3 with a
+        // longer branch
+        for height in 1..4 {
+            observers.burn_block_processed(height, &tip(height).1, height as usize);
+        }
+        observers.sync_completed(&tip(3));
+        observers.reorg_detected(&tip(3), &fork_tip(4), 2);
+        for height in 2..5 {
+            observers.burn_block_processed(height, &fork_tip(height).1, 0);
+        }
+        observers.sync_completed(&fork_tip(4));
+
+        let expected_calls: Vec<String> = vec![
+            "processed 1 1",
+            "processed 2 2",
+            "processed 3 3",
+            "completed 3",
+            "reorg 3 4 2",
+            "processed 2 0",
+            "processed 3 0",
+            "processed 4 0",
+            "completed 4",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(*panicking_calls.lock().unwrap(), expected_calls);
+        assert_eq!(*failing_calls.lock().unwrap(), expected_calls);
+        assert_eq!(*calls.lock().unwrap(), expected_calls);
+
+        let mut expected_events = vec![];
+        for height in 1..4 {
+            expected_events.push(BurnchainEvent::BurnBlockProcessed {
+                height,
+                hash: tip(height).1,
+                num_ops: height as usize,
+            });
+        }
+        expected_events.push(BurnchainEvent::SyncCompleted { tip: tip(3) });
+        expected_events.push(BurnchainEvent::ReorgDetected {
+            old_tip: tip(3),
+            new_tip: fork_tip(4),
+            depth: 2,
+        });
+        for height in 2..5 {
+            expected_events.push(BurnchainEvent::BurnBlockProcessed {
+                height,
+                hash: fork_tip(height).1,
+                num_ops: 0,
+            });
+        }
+        expected_events.push(BurnchainEvent::SyncCompleted { tip: fork_tip(4) });
+        let events: Vec<_> = event_recv.try_iter().collect();
+        assert_eq!(events, expected_events);
+
+        // once the channel's receiver is gone, forwarding fails without affecting the others
+        drop(event_recv);
+        observers.sync_completed(&fork_tip(4));
+        assert_eq!(calls.lock().unwrap().len(), expected_calls.len() + 1);
+    }
+
+    #[test]
+    fn test_max_reorg_depth() {
+        let mut indexer = BitcoinIndexer::new(
+            BitcoinIndexerConfig::test_default("/tmp/test_max_reorg_depth.dat".to_string()),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        assert_eq!(indexer.max_reorg_depth(), Some(DEFAULT_MAX_REORG_DEPTH));
+        indexer.config.max_reorg_depth = Some(10);
+
+        let (event_send, event_recv) = channel();
+        indexer.add_event_observer(Box::new(ChannelEventObserver::new(event_send)));
+        let observers = indexer.event_observers();
+        let old_tip: BurnchainTip = (110, BurnchainHeaderHash([0x11; 32]));
+
+        // a reorg exactly as deep as the maximum is processed
+        Burnchain::check_reorg_depth(&mut indexer, &observers, &old_tip, 100).unwrap();
+        assert!(event_recv.try_recv().is_err());
+
+        // one block deeper is refused, every time the sync is retried
+        for _ in 0..2 {
+            match Burnchain::check_reorg_depth(&mut indexer, &observers, &old_tip, 99) {
+                Err(burnchain_error::DeepReorg(11, 10)) => {}
+                res => panic!("expected DeepReorg, got {:?}", res),
+            }
+            assert_eq!(
+                event_recv.try_recv().unwrap(),
+                BurnchainEvent::DeepReorgRefused {
+                    old_tip: old_tip.clone(),
+                    fork_height: 99,
+                    depth: 11,
+                    max_depth: 10,
+                }
+            );
+        }
+
+        // ...until it's accepted (here through a dup(), as another thread would), which lets
+        // only that one through
+        indexer.dup().accept_deep_reorg();
+        Burnchain::check_reorg_depth(&mut indexer, &observers, &old_tip, 99).unwrap();
+        assert!(event_recv.try_recv().is_err());
+        match Burnchain::check_reorg_depth(&mut indexer, &observers, &old_tip, 50) {
+            Err(burnchain_error::DeepReorg(60, 10)) => {}
+            res => panic!("expected DeepReorg, got {:?}", res),
+        }
+
+        // without a maximum, reorgs of any depth are processed
+        indexer.config.max_reorg_depth = None;
+        Burnchain::check_reorg_depth(&mut indexer, &observers, &old_tip, 0).unwrap();
+    }
+
+    #[test]
+    fn test_peer_address_fallthrough() {
+        // IP literals, with or without brackets around IPv6 addresses
+        let v4: net::SocketAddr = "127.0.0.1:8333".parse().unwrap();
+        let v6: net::SocketAddr = "[::1]:8333".parse().unwrap();
+        for (host, addr) in [("127.0.0.1", v4), ("::1", v6), ("[::1]", v6)].iter() {
+            assert_eq!(
+                BitcoinIndexer::resolve_peer(host, 8333, AddressPreference::System).unwrap(),
+                vec![*addr]
+            );
+        }
+        // whichever family comes first, `localhost`'s addresses are ordered as preferred
+        let addrs =
+            BitcoinIndexer::resolve_peer("localhost", 8333, AddressPreference::Ipv4First).unwrap();
+        assert!(addrs
+            .windows(2)
+            .all(|pair| pair[0].is_ipv4() || pair[1].is_ipv6()));
+        let addrs =
+            BitcoinIndexer::resolve_peer("localhost", 8333, AddressPreference::Ipv6First).unwrap();
+        assert!(addrs
+            .windows(2)
+            .all(|pair| pair[0].is_ipv6() || pair[1].is_ipv4()));
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let live_addr = listener.local_addr().unwrap();
+        let dead_addr = net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        // each address is tried in turn, until one connects
+        let (_sock, addr) =
+            BitcoinIndexer::connect_first(&[dead_addr, live_addr], Some(Duration::from_secs(5)))
+                .unwrap();
+        assert_eq!(addr, live_addr);
+        assert!(BitcoinIndexer::connect_first(&[dead_addr], None).is_err());
+
+        // `localhost` may resolve to `::1` as well, where nothing listens; either way, we get
+        // through, and remember where
+        let mut config = BitcoinIndexerConfig::test_default("/dev/null".to_string());
+        config.peer_host = "localhost".to_string();
+        config.peer_port = live_addr.port();
+        config.connect_timeout = Some(Duration::from_secs(5));
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        indexer.reconnect_peer().unwrap();
+        assert_eq!(
+            indexer
+                .runtime
+                .peer_addrs
+                .get(&("localhost".to_string(), live_addr.port())),
+            Some(&live_addr)
+        );
+
+        // a host name that no longer resolves is reconnected to where it last accepted
+        indexer.config.peer_host = "bitcoin.invalid".to_string();
+        indexer
+            .runtime
+            .peer_addrs
+            .insert(("bitcoin.invalid".to_string(), live_addr.port()), live_addr);
+        indexer.reconnect_peer().unwrap();
+
+        // once every address fails, it's a connection error
+        drop(listener);
+        match indexer.reconnect_peer() {
+            Err(btc_error::ConnectionError) => {}
+            x => panic!("Expected ConnectionError, got {:?}", &x),
+        }
+        indexer.config.peer_host = "localhost".to_string();
+        match indexer.reconnect_peer() {
+            Err(btc_error::ConnectionError) => {}
+            x => panic!("Expected ConnectionError, got {:?}", &x),
+        }
+    }
+
+    #[test]
+    fn test_socket_timeouts() {
+        // mock peer that accepts our connection, and then never says anything
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_port = listener.local_addr().unwrap().port();
+        let peer = thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut buf = vec![];
+            let _ = std::io::Read::read_to_end(&mut sock, &mut buf);
+        });
+
+        let mut config = BitcoinIndexerConfig::test_default("/dev/null".to_string());
+        config.peer_port = peer_port;
+        config.connect_timeout = Some(Duration::from_secs(5));
+        config.read_timeout = Duration::from_millis(500);
+        assert!(config.check_socket_timeouts().is_ok());
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+
+        indexer.connect().unwrap();
+        let recv_start = Instant::now();
+        match indexer.recv_message() {
+            Err(btc_error::Io(ref e))
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+            }
+            x => panic!("Expected a read timeout, got {:?}", x),
+        }
+        let elapsed = recv_start.elapsed();
+        assert!(elapsed >= Duration::from_millis(500));
+        assert!(elapsed < Duration::from_secs(5));
+
+        indexer.disconnect();
+        peer.join().unwrap();
+
+        // zero timeouts are rejected before we try to connect
+        for field in 0..3 {
+            let mut config = BitcoinIndexerConfig::test_default("/dev/null".to_string());
+            match field {
+                0 => config.connect_timeout = Some(Duration::from_secs(0)),
+                1 => config.read_timeout = Duration::from_secs(0),
+                _ => config.write_timeout = Duration::from_secs(0),
+            }
+            let mut indexer = BitcoinIndexer::new(
+                config,
+                BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+            );
+            match indexer.connect_handshake_backoff() {
+                Err(btc_error::ConfigError(_)) => {}
+                x => panic!("Expected ConfigError, got {:?}", x),
+            }
+        }
+
+        // long timeouts are allowed
+        let mut config = BitcoinIndexerConfig::test_default("/dev/null".to_string());
+        config.read_timeout = Duration::from_secs(MAX_SANE_PEER_SOCKET_TIMEOUT + 1);
+        assert!(config.check_socket_timeouts().is_ok());
+    }
+
+    #[test]
+    fn test_reindex_from_checks_height() {
+        let working_dir = "/tmp/test_reindex_from_checks_height";
+        if fs::metadata(working_dir).is_ok() {
+            fs::remove_dir_all(working_dir).unwrap();
+        }
+        let mut indexer = BitcoinIndexer::new_unit_test(working_dir);
+        let mut burnchain = Burnchain::regtest(working_dir);
+        let burnchain_db = crate::burnchains::db::BurnchainDB::connect(
+            &burnchain.get_burnchaindb_path(),
+            &burnchain,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            burnchain_db.get_canonical_chain_tip().unwrap().block_height,
+            0
+        );
+        drop(burnchain_db);
+
+        // can't reindex from above the burnchain DB's tip
+        let (_coord_recv, coord_comm) = CoordinatorChannels::instantiate();
+        match indexer.reindex_from(&mut burnchain, coord_comm, 1) {
+            Err(burnchain_error::Bitcoin(btc_error::BlockchainHeight)) => {}
+            x => panic!("Expected BlockchainHeight, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_header_sync_progress() {
+        let db_path = "/tmp/test_header_sync_progress.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let headers = make_regtest_header_chain(50);
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+
+        // mock peer that advertises 50 blocks and ignores our getheaders requests -- we feed
+        // its headers to the client directly
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut buf = vec![];
+            let _ = std::io::Read::read_to_end(&mut sock, &mut buf);
+        });
+        let mut indexer = BitcoinIndexer::new(
+            BitcoinIndexerConfig::test_default(db_path.to_string()),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        indexer.runtime.sock = Some(net::TcpStream::connect(peer_addr).unwrap());
+        indexer.runtime.block_height = 50;
+
+        // a failing handler doesn't stop the sync
+        let reports = Arc::new(std::sync::Mutex::new(vec![]));
+        let reports_inner = reports.clone();
+        indexer.set_header_sync_progress(
+            Arc::new(
+                move |height: u64, tip: u64, rate: f64| -> Result<(), String> {
+                    let mut reports = reports_inner.lock().unwrap();
+                    reports.push((height, tip, rate));
+                    if reports.len() == 1 {
+                        return Err("handler failed".to_string());
+                    }
+                    Ok(())
+                },
+            ),
+            10,
+        );
+        let (handler, interval) = indexer.runtime.header_sync_progress.clone().unwrap();
+        spv_client.set_progress_handler(handler, interval);
+
+        assert!(spv_client.begin_session(&mut indexer).unwrap());
+        for batch in headers.chunks(7) {
+            spv_client
+                .handle_message(&mut indexer, NetworkMessage::Headers(batch.to_vec()))
+                .unwrap();
+        }
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 50);
+
+        // a report at least every 10 headers, with increasing heights
+        let reports = reports.lock().unwrap();
+        assert_eq!(
+            reports
+                .iter()
+                .map(|(height, tip, _)| (*height, *tip))
+                .collect::<Vec<_>>(),
+            vec![(14, 50), (28, 50), (42, 50)]
+        );
+        for (_, _, rate) in reports.iter() {
+            assert!(*rate >= 0.0);
+        }
+    }
+
+    #[test]
+    fn test_indexer_custom_genesis() {
+        let path = "/tmp/test_indexer_custom_genesis.sqlite";
+        if fs::metadata(path).is_ok() {
+            fs::remove_file(path).unwrap();
+        }
+
+        let mut genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        genesis.time = 1600000000;
+        genesis.nonce = 1;
+        let genesis_hash = BurnchainHeaderHash::from_bitcoin_hash(&genesis.bitcoin_hash());
+        let header_hex = to_hex(&serialize(&genesis).unwrap());
+
+        let make_indexer = |network_id: BitcoinNetworkType, header_hex: &str| {
+            let mut config = BitcoinIndexerConfig::test_default(path.to_string());
+            config.custom_genesis = Some(CustomGenesisParams {
+                header_hex: header_hex.to_string(),
+                first_block_hash: genesis_hash.clone(),
+            });
+            BitcoinIndexer::new(config, BitcoinIndexerRuntime::new(network_id))
+        };
+
+        let indexer = make_indexer(BitcoinNetworkType::Regtest, &header_hex);
+        assert_eq!(indexer.get_genesis_header().unwrap(), Some(genesis));
+
+        // the headers DB gets seeded with it, so it's the first block
+        SpvClient::new_with_genesis(
+            path,
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            indexer.get_genesis_header().unwrap(),
+            true,
+            false,
+        )
+        .unwrap();
+        assert_eq!(indexer.get_first_block_header_hash().unwrap(), genesis_hash);
+
+        // not allowed on other networks
+        for network_id in [BitcoinNetworkType::Mainnet, BitcoinNetworkType::Testnet] {
+            match make_indexer(network_id, &header_hex).get_genesis_header() {
+                Err(btc_error::ConfigError(_)) => {}
+                x => panic!("Expected ConfigError, got {:?}", x),
+            }
+        }
+
+        // must be a well-formed header with the configured hash
+        let mut other_genesis = genesis;
+        other_genesis.nonce = 2;
+        for bad_hex in [
+            "not hex".to_string(),
+            header_hex[2..].to_string(),
+            format!("{}00", &header_hex),
+            to_hex(&serialize(&other_genesis).unwrap()),
+        ] {
+            match make_indexer(BitcoinNetworkType::Regtest, &bad_hex).get_genesis_header() {
+                Err(btc_error::ConfigError(_)) => {}
+                x => panic!("Expected ConfigError for {}, got {:?}", &bad_hex, x),
+            }
+        }
+    }
+
+    #[test]
+    fn test_indexer_custom_checkpoints() {
+        let path = "/tmp/test_indexer_custom_checkpoints.sqlite";
+        if fs::metadata(path).is_ok() {
+            fs::remove_file(path).unwrap();
+        }
+
+        let checkpoints = vec![(1, BurnchainHeaderHash([0x01; 32]))];
+        let make_indexer = |network_id: BitcoinNetworkType| {
+            let mut config = BitcoinIndexerConfig::test_default(path.to_string());
+            config.checkpoints = Some(checkpoints.clone());
+            BitcoinIndexer::new(config, BitcoinIndexerRuntime::new(network_id))
+        };
+
+        assert_eq!(
+            make_indexer(BitcoinNetworkType::Testnet)
+                .get_checkpoints()
+                .unwrap(),
+            checkpoints
+        );
+
+        // not allowed on mainnet, which a header sync reports instead of panicking
+        let mut indexer = make_indexer(BitcoinNetworkType::Mainnet);
+        match indexer.get_checkpoints() {
+            Err(btc_error::ConfigError(_)) => {}
+            x => panic!("Expected ConfigError, got {:?}", x),
+        }
+        match indexer.sync_last_headers(0, None) {
+            Err(btc_error::ConfigError(_)) => {}
+            x => panic!("Expected ConfigError, got {:?}", x),
+        }
+
+        // without custom checkpoints, mainnet gets its compiled-in ones
+        let indexer = BitcoinIndexer::new(
+            BitcoinIndexerConfig::test_default(path.to_string()),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Mainnet),
+        );
+        assert_eq!(
+            indexer.get_checkpoints().unwrap(),
+            SpvClient::default_checkpoints(BitcoinNetworkType::Mainnet)
+        );
+    }
+
+    /// Serve `blocks` to one connection.  Each `getdata` is answered `latency` after it arrives,
+    /// with its blocks in reverse order.  The block at `drop_once` (if given) is left out of the
+    /// first reply that asks for it.  Returns the block hashes of each `getdata` received.
+    fn spawn_mock_blocks_peer(
+        listener: net::TcpListener,
+        blocks: Vec<Block>,
+        latency: Duration,
+        drop_once: Option<usize>,
+    ) -> thread::JoinHandle<Vec<Vec<Sha256dHash>>> {
+        let magic = BITCOIN_REGTEST;
+        thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+            let mut encoder = RawEncoder::new(sock);
+            let mut recv = move || -> Option<NetworkMessage> {
+                let msg: RawNetworkMessage =
+                    ConsensusDecodable::consensus_decode(&mut decoder).ok()?;
+                assert_eq!(msg.magic, magic);
+                Some(msg.payload)
+            };
+            let mut send = move |payload| {
+                RawNetworkMessage { magic, payload }
+                    .consensus_encode(&mut encoder)
+                    .unwrap();
+            };
+
+            let mut version_body = match recv() {
+                Some(NetworkMessage::Version(body)) => body,
+                x => panic!("Expected version, got {:?}", &x),
+            };
+            version_body.services = NODE_NETWORK;
+            version_body.start_height = blocks.len() as i32;
+            send(NetworkMessage::Version(version_body));
+            send(NetworkMessage::Verack);
+            assert_eq!(recv(), Some(NetworkMessage::Verack));
+
+            let mut drop_once = drop_once.map(|i| blocks[i].bitcoin_hash());
+            let mut requests = vec![];
+            while let Some(msg) = recv() {
+                let invs = match msg {
+                    NetworkMessage::GetData(invs) => invs,
+                    _ => continue,
+                };
+                let block_hashes: Vec<_> = invs.iter().map(|inv| inv.hash).collect();
+                requests.push(block_hashes.clone());
+
+                thread::sleep(latency);
+                for block_hash in block_hashes.iter().rev() {
+                    if drop_once == Some(*block_hash) {
+                        drop_once = None;
+                        continue;
+                    }
+                    let block = blocks
+                        .iter()
+                        .find(|block| block.bitcoin_hash() == *block_hash)
+                        .unwrap();
+                    send(NetworkMessage::Block(block.clone()));
+                }
+            }
+            requests
+        })
+    }
+
+    /// Download `blocks` from a mock peer with the given window, and check that they're delivered
+    /// in height order.  Returns how long it took and the peer's `getdata` requests.
+    fn download_mock_blocks(
+        blocks: &[Block],
+        window: usize,
+        latency: Duration,
+        drop_once: Option<usize>,
+    ) -> (Duration, Vec<Vec<Sha256dHash>>) {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let peer = spawn_mock_blocks_peer(listener, blocks.to_vec(), latency, drop_once);
+
+        let mut config =
+            BitcoinIndexerConfig::test_default("/tmp/test_block_download_window.dat".to_string());
+        config.peer_port = port;
+        config.block_download_window = window;
+        config.block_request_timeout = latency * 3 / 2;
+        let indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+
+        let mut headers = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| BitcoinHeaderIPC {
+                block_header: LoneBlockHeader {
+                    header: block.header.clone(),
+                    tx_count: VarInt(block.txdata.len() as u64),
+                },
+                block_height: (i as u64) + 1,
+            })
+            .collect::<Vec<_>>()
+            .into_iter();
+
+        let mut downloader = BitcoinBlockDownloader::new(indexer);
+        let mut delivered = vec![];
+        let start = Instant::now();
+        downloader
+            .run_window(&mut headers, &mut |ipc_block| {
+                delivered.push(ipc_block);
+                Ok(())
+            })
             .unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(delivered.len(), blocks.len());
+        for (i, ipc_block) in delivered.iter().enumerate() {
+            assert_eq!(ipc_block.header_data.block_height, (i as u64) + 1);
+            assert_eq!(
+                ipc_block.block_message,
+                NetworkMessage::Block(blocks[i].clone())
+            );
+        }
+
+        // hang up, so the peer returns
+        drop(downloader);
+        (elapsed, peer.join().unwrap())
+    }
+
+    #[test]
+    fn test_block_download_window() {
+        let blocks = make_regtest_block_chain(8);
+        let block_hashes: Vec<_> = blocks.iter().map(|block| block.bitcoin_hash()).collect();
+        let latency = Duration::from_millis(100);
+
+        // one block at a time: one round trip per block
+        let (sequential_time, requests) = download_mock_blocks(&blocks, 1, latency, None);
+        assert_eq!(
+            requests,
+            block_hashes
+                .iter()
+                .map(|block_hash| vec![*block_hash])
+                .collect::<Vec<_>>()
+        );
+        assert!(sequential_time >= latency * 8);
 
-            assert!(
-                spv_client.get_headers_height().unwrap() >= 40322,
-                "This test needs headers up to 40320"
-            );
-            spv_client.drop_headers(40320).unwrap();
-        }
+        // the whole window at once: one round trip, with the blocks arriving in reverse order
+        let (window_time, requests) = download_mock_blocks(&blocks, 8, latency, None);
+        assert_eq!(requests, vec![block_hashes.clone()]);
+        assert!(
+            window_time * 2 < sequential_time,
+            "Windowed download took {:?}, sequential took {:?}",
+            window_time,
+            sequential_time
+        );
 
-        let mut spv_client =
-            SpvClient::new(&db_path, 0, None, BitcoinNetworkType::Mainnet, true, false).unwrap();
+        // a window smaller than the chain slides along it
+        let (_, requests) = download_mock_blocks(&blocks, 3, latency, None);
+        assert_eq!(requests[0], block_hashes[0..3].to_vec());
+        assert_eq!(requests.concat(), block_hashes);
 
-        assert_eq!(spv_client.get_headers_height().unwrap(), 40321);
-        let total_work_before = spv_client.update_chain_work().unwrap();
-        assert_eq!(total_work_before, spv_client.get_chain_work().unwrap());
+        // a block that doesn't arrive is asked for again on its own
+        let (_, requests) = download_mock_blocks(&blocks, 8, latency, Some(0));
+        assert_eq!(requests, vec![block_hashes.clone(), vec![block_hashes[0]]]);
+    }
 
-        let total_work_before_idempotent = spv_client.update_chain_work().unwrap();
-        assert_eq!(total_work_before, total_work_before_idempotent);
+    /// Shake hands as a peer with the given services and chain height, and answer every
+    /// `getdata` with a `notfound`.  Returns the block hashes of each `getdata` received.
+    fn spawn_mock_notfound_peer(
+        listener: net::TcpListener,
+        services: u64,
+        start_height: i32,
+    ) -> thread::JoinHandle<Vec<Vec<Sha256dHash>>> {
+        let magic = BITCOIN_REGTEST;
+        thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+            let mut encoder = RawEncoder::new(sock);
+            let mut recv = move || -> Option<NetworkMessage> {
+                let msg: RawNetworkMessage =
+                    ConsensusDecodable::consensus_decode(&mut decoder).ok()?;
+                assert_eq!(msg.magic, magic);
+                Some(msg.payload)
+            };
+            let mut send = move |payload| {
+                RawNetworkMessage { magic, payload }
+                    .consensus_encode(&mut encoder)
+                    .unwrap();
+            };
 
-        // fake block headers for mainnet 40319-40320, which is on a difficulty adjustment boundary
-        let bad_headers = vec![
-            LoneBlockHeader {
-                header: BlockHeader {
-                    version: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "000000000683a474ef810000fd22f0edde4cf33ae76ae506b220e57aeeafeaa4",
-                    )
-                    .unwrap(),
-                    merkle_root: Sha256dHash::from_hex(
-                        "b4d736ca74838036ebd19b085c3eeb9ffec2307f6452347cdd8ddaa249686f39",
-                    )
-                    .unwrap(),
-                    time: 1716199659,
-                    bits: 486575299,
-                    nonce: 201337507,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    version: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "000000006f403731d720174cd6875e331ac079b438cf53aa685f9cd068fd4ca8",
-                    )
-                    .unwrap(),
-                    merkle_root: Sha256dHash::from_hex(
-                        "a86b3c149f204d4cb47c67bf9bfeea2719df101dd6e6fc3f0e60d86efeba22a8",
-                    )
-                    .unwrap(),
-                    time: 1716161259,
-                    bits: 486604799,
-                    nonce: 144574511,
-                },
-                tx_count: VarInt(0),
-            },
-        ];
+            let mut version_body = match recv() {
+                Some(NetworkMessage::Version(body)) => body,
+                x => panic!("Expected version, got {:?}", &x),
+            };
+            version_body.services = services;
+            version_body.start_height = start_height;
+            send(NetworkMessage::Version(version_body));
+            send(NetworkMessage::Verack);
+            assert_eq!(recv(), Some(NetworkMessage::Verack));
+
+            let mut requests = vec![];
+            while let Some(msg) = recv() {
+                if let NetworkMessage::GetData(invs) = msg {
+                    requests.push(invs.iter().map(|inv| inv.hash).collect());
+                    send(NetworkMessage::NotFound(invs));
+                }
+            }
+            requests
+        })
+    }
 
-        let mut indexer = BitcoinIndexer::new(
-            BitcoinIndexerConfig::test_default(db_path.to_string()),
-            BitcoinIndexerRuntime::new(BitcoinNetworkType::Mainnet),
+    /// Download the blocks of `blocks`, as heights 1 and up, with the given config
+    fn download_blocks_with(
+        config: BitcoinIndexerConfig,
+        blocks: &[Block],
+    ) -> Result<Vec<BitcoinBlockIPC>, burnchain_error> {
+        let indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
         );
+        download_blocks_from(indexer, blocks)
+    }
 
-        let mut inserted_bad_header = false;
+    /// Like `download_blocks_with()`, but with an indexer that may already be talking to the peer
+    fn download_blocks_from(
+        indexer: BitcoinIndexer,
+        blocks: &[Block],
+    ) -> Result<Vec<BitcoinBlockIPC>, burnchain_error> {
+        download_blocks_by(BitcoinBlockDownloader::new(indexer), blocks)
+    }
 
-        let new_tip = indexer
-            .find_bitcoin_reorg(
-                &db_path,
-                &reorg_db_path,
-                |ref mut indexer, ref mut reorg_spv_client, start_block, end_block_opt| {
-                    let end_block =
-                        end_block_opt.unwrap_or(start_block + BLOCK_DIFFICULTY_CHUNK_SIZE);
+    /// Like `download_blocks_from()`, but with the given downloader
+    fn download_blocks_by(
+        mut downloader: BitcoinBlockDownloader,
+        blocks: &[Block],
+    ) -> Result<Vec<BitcoinBlockIPC>, burnchain_error> {
+        let mut headers = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| BitcoinHeaderIPC {
+                block_header: LoneBlockHeader {
+                    header: block.header.clone(),
+                    tx_count: VarInt(block.txdata.len() as u64),
+                },
+                block_height: (i as u64) + 1,
+            })
+            .collect::<Vec<_>>()
+            .into_iter();
 
-                    let mut ret = vec![];
-                    for block_height in start_block..end_block {
-                        if block_height > 40320 {
-                            break;
-                        }
-                        if block_height >= 40319 && block_height <= 40320 {
-                            test_debug!("insert bad header {}", block_height);
-                            ret.push(bad_headers[(block_height - 40319) as usize].clone());
-                            inserted_bad_header = true;
-                        } else {
-                            let orig_spv_client = SpvClient::new_without_migration(
-                                &db_path,
-                                0,
-                                None,
-                                BitcoinNetworkType::Mainnet,
-                                true,
-                                false,
-                            )
-                            .unwrap();
-                            let hdr = orig_spv_client.read_block_header(block_height)?.unwrap();
-                            ret.push(hdr);
-                        }
-                    }
+        let mut delivered = vec![];
+        downloader.run_window(&mut headers, &mut |ipc_block| {
+            delivered.push(ipc_block);
+            Ok(())
+        })?;
+        Ok(delivered)
+    }
 
-                    test_debug!(
-                        "add headers after {} (bad header: {})",
-                        start_block,
-                        inserted_bad_header
-                    );
-                    reorg_spv_client
-                        .insert_block_headers_after(start_block - 1, ret)
-                        .unwrap();
-                    Ok(())
+    #[test]
+    fn test_filtered_out_blocks_downloaded() {
+        let db_path = "/tmp/test_filtered_out_blocks_downloaded.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let burn_script = hex_bytes("76a914000000000000000000000000000000000000000088ac").unwrap();
+        let blocks = make_regtest_block_chain(2);
+        let headers: Vec<_> = blocks
+            .iter()
+            .map(|block| LoneBlockHeader {
+                header: block.header.clone(),
+                tx_count: VarInt(0),
+            })
+            .collect();
+
+        // block 1's filter rules out the burn address
+        let filter = BlockFilter::from_block(&blocks[0], &[]);
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        spv_client.insert_block_headers_after(0, headers).unwrap();
+        spv_client
+            .insert_filter_headers(
+                0,
+                &CFHeaders {
+                    filter_type: BASIC_FILTER_TYPE,
+                    stop_hash: blocks[0].bitcoin_hash(),
+                    previous_filter_header: Sha256dHash::default(),
+                    filter_hashes: vec![Sha256dHash::from_data(&[0]), filter.filter_hash()],
                 },
+                &[],
             )
             .unwrap();
+        spv_client
+            .insert_filter(
+                1,
+                &CFilter {
+                    filter_type: BASIC_FILTER_TYPE,
+                    block_hash: blocks[0].bitcoin_hash(),
+                    filter: filter.content.clone(),
+                },
+            )
+            .unwrap();
+        drop(spv_client);
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let peer = spawn_mock_blocks_peer(listener, blocks.clone(), Duration::from_millis(0), None);
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = port;
+        config.filter_scripts = vec![burn_script];
+        let indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        assert!(!indexer.block_may_contain_burn_ops(1).unwrap());
+        assert!(indexer.block_may_contain_burn_ops(2).unwrap());
 
-        assert!(inserted_bad_header);
-
-        // reorg is ignored
-        assert_eq!(new_tip, 40321);
-        let total_work_after = spv_client.update_chain_work().unwrap();
-        assert_eq!(total_work_after, total_work_before);
+        // filters leave out OP_RETURN outputs, so every block is still downloaded in full
+        let delivered = download_blocks_from(indexer, &blocks).unwrap();
+        let requested: Vec<_> = peer.join().unwrap().into_iter().flatten().collect();
+        assert_eq!(
+            requested,
+            blocks
+                .iter()
+                .map(|block| block.bitcoin_hash())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(delivered.len(), blocks.len());
+        for (ipc_block, block) in delivered.iter().zip(blocks.iter()) {
+            assert_eq!(
+                ipc_block.block_message,
+                NetworkMessage::Block(block.clone())
+            );
+        }
     }
 
     #[test]
-    fn test_spv_check_work_reorg_accepted() {
-        if !env::var("BLOCKSTACK_SPV_HEADERS_DB").is_ok() {
-            eprintln!("Skipping test_spv_check_work_reorg_accepted -- no BLOCKSTACK_SPV_HEADERS_DB envar set");
-            return;
+    fn test_pruned_peer() {
+        let blocks = make_regtest_block_chain(3);
+        let block_hashes: Vec<_> = blocks.iter().map(|block| block.bitcoin_hash()).collect();
+
+        // a full node serves every block; a pruned one only the last 288
+        let mut indexer = BitcoinIndexer::new(
+            BitcoinIndexerConfig::test_default("/tmp/test_pruned_peer.dat".to_string()),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        indexer.runtime.block_height = 600287;
+        indexer.runtime.peer_services = NODE_NETWORK | NODE_NETWORK_LIMITED;
+        assert_eq!(indexer.peer_oldest_block(), 0);
+        indexer.check_peer_serves_block(1).unwrap();
+        indexer.runtime.peer_services = NODE_NETWORK_LIMITED;
+        assert_eq!(indexer.peer_oldest_block(), 600000);
+        indexer.check_peer_serves_block(600000).unwrap();
+        match indexer.check_peer_serves_block(599999) {
+            Err(btc_error::PrunedPeer(msg)) => assert!(
+                msg.ends_with(
+                    "only serves the last 288 blocks (from height 600000), need height 599999"
+                ),
+                "{}",
+                &msg
+            ),
+            x => panic!("Expected PrunedPeer, got {:?}", &x),
         }
-        let db_path_source = env::var("BLOCKSTACK_SPV_HEADERS_DB").unwrap();
-        let db_path = "/tmp/test_spv_check_work_reorg_accepted.dat".to_string();
-        let reorg_db_path = "/tmp/test_spv_check_work_reorg_accepted.dat.reorg".to_string();
 
-        if fs::metadata(&db_path).is_ok() {
-            fs::remove_file(&db_path).unwrap();
+        // a pruned peer isn't asked for blocks it doesn't have
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let pruned_port = listener.local_addr().unwrap().port();
+        let peer = spawn_mock_notfound_peer(listener, NODE_NETWORK_LIMITED, 300);
+        let mut config =
+            BitcoinIndexerConfig::test_default("/tmp/test_pruned_peer.dat".to_string());
+        config.peer_port = pruned_port;
+        match download_blocks_with(config, &blocks) {
+            Err(burnchain_error::DownloadError(btc_error::PrunedPeer(msg))) => {
+                assert!(msg.ends_with("need height 1"), "{}", &msg)
+            }
+            x => panic!("Expected PrunedPeer, got {:?}", &x),
+        }
+        assert!(peer.join().unwrap().is_empty());
+
+        // a peer that answers with notfound isn't asked again
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let peer = spawn_mock_notfound_peer(listener, NODE_NETWORK, 3);
+        let mut config =
+            BitcoinIndexerConfig::test_default("/tmp/test_pruned_peer.dat".to_string());
+        config.peer_port = port;
+        let start = Instant::now();
+        match download_blocks_with(config.clone(), &blocks) {
+            Err(burnchain_error::DownloadError(btc_error::PrunedPeer(msg))) => {
+                assert!(msg.contains("sent notfound for block 1"), "{}", &msg)
+            }
+            x => panic!("Expected PrunedPeer, got {:?}", &x),
         }
+        assert!(start.elapsed() < config.block_request_timeout);
+        assert_eq!(peer.join().unwrap(), vec![block_hashes.clone()]);
+
+        // with another peer, we fail over to it instead
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let pruned_port = listener.local_addr().unwrap().port();
+        let pruned_peer = spawn_mock_notfound_peer(listener, NODE_NETWORK_LIMITED, 300);
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let full_port = listener.local_addr().unwrap().port();
+        let full_peer =
+            spawn_mock_blocks_peer(listener, blocks.clone(), Duration::from_millis(0), None);
+        let mut config =
+            BitcoinIndexerConfig::test_default("/tmp/test_pruned_peer.dat".to_string());
+        config.peer_port = pruned_port;
+        config.header_sync_peers = vec![("127.0.0.1".to_string(), full_port)];
+        config.reconnect_policy.base_delay = Duration::from_millis(10);
+        let delivered = download_blocks_with(config, &blocks).unwrap();
+        assert_eq!(delivered.len(), blocks.len());
+        assert!(pruned_peer.join().unwrap().is_empty());
+        assert_eq!(full_peer.join().unwrap(), vec![block_hashes]);
+    }
 
-        if fs::metadata(&reorg_db_path).is_ok() {
-            fs::remove_file(&reorg_db_path).unwrap();
+    #[test]
+    fn test_bad_block_peer() {
+        let blocks = make_regtest_block_chain(3);
+        let block_hashes: Vec<_> = blocks.iter().map(|block| block.bitcoin_hash()).collect();
+
+        // a peer that sends the second block with a tampered transaction (but the right header),
+        // as the last block of its reply (so that it's done sending when we hang up)
+        let mut bad_blocks = blocks.clone();
+        bad_blocks[1].txdata[0].lock_time += 1;
+        assert_eq!(bad_blocks[1].bitcoin_hash(), block_hashes[1]);
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let bad_port = listener.local_addr().unwrap().port();
+        let bad_peer =
+            spawn_mock_blocks_peer(listener, bad_blocks, Duration::from_millis(0), Some(0));
+
+        // is dropped for the other peer, which is asked for the blocks still missing
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let good_port = listener.local_addr().unwrap().port();
+        let good_peer =
+            spawn_mock_blocks_peer(listener, blocks.clone(), Duration::from_millis(0), None);
+        let mut config =
+            BitcoinIndexerConfig::test_default("/tmp/test_bad_block_peer.dat".to_string());
+        config.peer_port = bad_port;
+        config.header_sync_peers = vec![("127.0.0.1".to_string(), good_port)];
+        config.reconnect_policy.base_delay = Duration::from_millis(10);
+        let delivered = download_blocks_with(config, &blocks).unwrap();
+        assert_eq!(delivered.len(), blocks.len());
+        for (ipc_block, block) in delivered.iter().zip(blocks.iter()) {
+            assert_eq!(
+                ipc_block.block_message,
+                NetworkMessage::Block(block.clone())
+            );
         }
+        assert_eq!(bad_peer.join().unwrap(), vec![block_hashes.clone()]);
+        assert_eq!(good_peer.join().unwrap(), vec![block_hashes[0..2].to_vec()]);
+    }
 
-        fs::copy(&db_path_source, &db_path).unwrap();
+    /// Serve `blocks` to one connection, as a peer with the given `services`.  A `getdata` for
+    /// filtered blocks is answered with a `merkleblock` for each block, matching the transactions
+    /// with an output to `match_script` (or, if None, the ones that match the loaded filter, as
+    /// a real peer would), followed by those transactions.  Returns the filters loaded into it,
+    /// the elements added to them, and the inventories of each `getdata` received.
+    fn spawn_mock_bloom_peer(
+        listener: net::TcpListener,
+        blocks: Vec<Block>,
+        services: u64,
+        match_script: Option<Script>,
+    ) -> thread::JoinHandle<(Vec<FilterLoad>, Vec<FilterAdd>, Vec<Vec<Inventory>>)> {
+        let magic = BITCOIN_REGTEST;
+        thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+            let mut encoder = RawEncoder::new(sock);
+            let mut recv = move || -> Option<NetworkMessage> {
+                let msg: RawNetworkMessage =
+                    ConsensusDecodable::consensus_decode(&mut decoder).ok()?;
+                assert_eq!(msg.magic, magic);
+                Some(msg.payload)
+            };
+            let mut send = move |payload| {
+                RawNetworkMessage { magic, payload }
+                    .consensus_encode(&mut encoder)
+                    .unwrap();
+            };
 
-        // set up SPV client so we don't have chain work at first
-        let mut spv_client = SpvClient::new_without_migration(
-            &db_path,
-            0,
-            None,
-            BitcoinNetworkType::Mainnet,
-            true,
-            false,
-        )
-        .unwrap();
+            let mut version_body = match recv() {
+                Some(NetworkMessage::Version(body)) => body,
+                x => panic!("Expected version, got {:?}", &x),
+            };
+            version_body.services = services;
+            version_body.start_height = blocks.len() as i32;
+            send(NetworkMessage::Version(version_body));
+            send(NetworkMessage::Verack);
+            assert_eq!(recv(), Some(NetworkMessage::Verack));
+
+            let mut filterloads = vec![];
+            let mut filteradds = vec![];
+            let mut filter = None;
+            let mut requests = vec![];
+            while let Some(msg) = recv() {
+                let invs = match msg {
+                    NetworkMessage::FilterLoad(filterload) => {
+                        filter = Some(bloom::Bip37Filter::from_filterload(&filterload));
+                        filterloads.push(filterload);
+                        continue;
+                    }
+                    NetworkMessage::FilterAdd(filteradd) => {
+                        filter.as_mut().unwrap().insert(&filteradd.data);
+                        filteradds.push(filteradd);
+                        continue;
+                    }
+                    NetworkMessage::GetData(invs) => invs,
+                    _ => continue,
+                };
+                requests.push(invs.clone());
+
+                for inv in invs.iter() {
+                    let block = blocks
+                        .iter()
+                        .find(|block| block.bitcoin_hash() == inv.hash)
+                        .unwrap();
+                    if inv.inv_type != InvType::FilteredBlock {
+                        send(NetworkMessage::Block(block.clone()));
+                        continue;
+                    }
+                    let matches: Vec<bool> = block
+                        .txdata
+                        .iter()
+                        .map(|tx| match match_script {
+                            Some(ref match_script) => tx
+                                .output
+                                .iter()
+                                .any(|output| output.script_pubkey == *match_script),
+                            None => filter.as_mut().unwrap().matches_tx(tx),
+                        })
+                        .collect();
+                    send(NetworkMessage::MerkleBlock(bloom::build_merkle_block(
+                        block, &matches,
+                    )));
+                    for (tx, matched) in block.txdata.iter().zip(matches.iter()) {
+                        if *matched {
+                            send(NetworkMessage::Tx(tx.clone()));
+                        }
+                    }
+                }
+            }
+            (filterloads, filteradds, requests)
+        })
+    }
 
-        assert!(
-            spv_client.get_headers_height().unwrap() >= 40322,
-            "This test needs headers up to 40320"
-        );
-        spv_client.drop_headers(40320).unwrap();
+    #[test]
+    fn test_filtered_blocks() {
+        let burn_script =
+            Script::from(hex_bytes("76a914000000000000000000000000000000000000000088ac").unwrap());
+        let mut blocks = make_regtest_block_chain(3);
+        for (i, block) in blocks.iter_mut().enumerate() {
+            // every other transaction burns
+            for j in 0..(2 * i + 1) {
+                let mut tx = block.txdata[0].clone();
+                tx.input[0].sequence = j as u32;
+                if j % 2 == 1 {
+                    tx.output.push(TxOut {
+                        value: 1000,
+                        script_pubkey: burn_script.clone(),
+                    });
+                }
+                block.txdata.push(tx);
+            }
+            block.header.merkle_root =
+                bitcoin_merkle_root(block.txdata.iter().map(|tx| tx.txid()).collect());
+        }
+        let block_hashes: Vec<_> = blocks.iter().map(|block| block.bitcoin_hash()).collect();
+
+        for (services, filtered_download, filtered) in [
+            (NODE_NETWORK | NODE_BLOOM, true, true),
+            // a peer that doesn't serve bloom filters is asked for whole blocks
+            (NODE_NETWORK, true, false),
+            // the burnchain sync always asks for whole blocks
+            (NODE_NETWORK | NODE_BLOOM, false, false),
+        ]
+        .iter()
+        {
+            let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let peer = spawn_mock_bloom_peer(
+                listener,
+                blocks.clone(),
+                *services,
+                Some(burn_script.clone()),
+            );
+            let mut config =
+                BitcoinIndexerConfig::test_default("/tmp/test_filtered_blocks.dat".to_string());
+            config.peer_port = port;
+            config.filter_scripts = vec![burn_script.to_bytes()];
+            let indexer = BitcoinIndexer::new(
+                config,
+                BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+            );
+            let downloader = if *filtered_download {
+                BitcoinBlockDownloader::new_filtered(indexer)
+            } else {
+                BitcoinBlockDownloader::new(indexer)
+            };
+            let delivered = download_blocks_by(downloader, &blocks).unwrap();
+            assert_eq!(delivered.len(), blocks.len());
+
+            let (filterloads, filteradds, requests) = peer.join().unwrap();
+            assert!(filteradds.is_empty());
+            let inv_type = if *filtered {
+                assert_eq!(filterloads.len(), 1);
+                assert_eq!(filterloads[0].flags, bloom::BLOOM_UPDATE_ALL);
+                InvType::FilteredBlock
+            } else {
+                assert_eq!(filterloads.len(), 0);
+                InvType::Block
+            };
+            let requested: Vec<_> = requests
+                .iter()
+                .flatten()
+                .map(|inv| {
+                    assert_eq!(inv.inv_type, inv_type);
+                    inv.hash
+                })
+                .collect();
+            assert_eq!(requested, block_hashes);
+
+            for (ipc_block, block) in delivered.iter().zip(blocks.iter()) {
+                if !*filtered {
+                    assert_eq!(
+                        ipc_block.block_message,
+                        NetworkMessage::Block(block.clone())
+                    );
+                    assert!(ipc_block.matched_txs.is_empty());
+                    continue;
+                }
+                let burn_txs: Vec<_> = block
+                    .txdata
+                    .iter()
+                    .filter(|tx| !tx.output.is_empty())
+                    .cloned()
+                    .collect();
+                match ipc_block.block_message {
+                    NetworkMessage::MerkleBlock(ref merkle_block) => {
+                        assert_eq!(merkle_block.header, block.header);
+                        assert_eq!(merkle_block.total_transactions as usize, block.txdata.len());
+                    }
+                    ref x => panic!("Expected a merkleblock, got {:?}", x),
+                }
+                assert_eq!(ipc_block.matched_txs, burn_txs);
+            }
+        }
+    }
 
-        assert_eq!(spv_client.get_headers_height().unwrap(), 40321);
+    #[test]
+    fn test_filtered_blocks_watch_targets() {
+        let burn_script =
+            Script::from(hex_bytes("76a914000000000000000000000000000000000000000088ac").unwrap());
+        let other_script =
+            Script::from(hex_bytes("76a914333333333333333333333333333333333333333388ac").unwrap());
+        let watched = OutPoint {
+            txid: Sha256dHash::from_data(&[1]),
+            vout: 0,
+        };
+        let unwatched = OutPoint {
+            txid: Sha256dHash::from_data(&[2]),
+            vout: 0,
+        };
 
-        // fake block headers for mainnet 40319-40320, which is on a difficulty adjustment boundary
-        let bad_headers = vec![
-            LoneBlockHeader {
-                header: BlockHeader {
-                    version: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "000000000683a474ef810000fd22f0edde4cf33ae76ae506b220e57aeeafeaa4",
-                    )
-                    .unwrap(),
-                    merkle_root: Sha256dHash::from_hex(
-                        "b4d736ca74838036ebd19b085c3eeb9ffec2307f6452347cdd8ddaa249686f39",
-                    )
-                    .unwrap(),
-                    time: 1716199659,
-                    bits: 486575299,
-                    nonce: 201337507,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    version: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "000000006f403731d720174cd6875e331ac079b438cf53aa685f9cd068fd4ca8",
-                    )
-                    .unwrap(),
-                    merkle_root: Sha256dHash::from_hex(
-                        "a86b3c149f204d4cb47c67bf9bfeea2719df101dd6e6fc3f0e60d86efeba22a8",
-                    )
-                    .unwrap(),
-                    time: 1716161259,
-                    bits: 486604799,
-                    nonce: 144574511,
-                },
-                tx_count: VarInt(0),
-            },
-        ];
+        // the first block pays to the burn script, spends the watched outpoint, and spends
+        // another outpoint; the second one has only its coinbase
+        let mut blocks = make_regtest_block_chain(2);
+        let base_tx = blocks[0].txdata[0].clone();
+        let mut burn_tx = base_tx.clone();
+        burn_tx.input[0].sequence = 1;
+        burn_tx.output.push(TxOut {
+            value: 1000,
+            script_pubkey: burn_script.clone(),
+        });
+        let mut watched_spend = base_tx.clone();
+        watched_spend.input[0].previous_output = watched;
+        watched_spend.output.push(TxOut {
+            value: 1000,
+            script_pubkey: other_script.clone(),
+        });
+        let mut unwatched_spend = base_tx;
+        unwatched_spend.input[0].previous_output = unwatched;
+        unwatched_spend.output.push(TxOut {
+            value: 1000,
+            script_pubkey: other_script,
+        });
+        blocks[0].txdata.push(burn_tx.clone());
+        blocks[0].txdata.push(watched_spend.clone());
+        blocks[0].txdata.push(unwatched_spend);
+        blocks[0].header.merkle_root =
+            bitcoin_merkle_root(blocks[0].txdata.iter().map(|tx| tx.txid()).collect());
+        blocks[1].header.prev_blockhash = blocks[0].bitcoin_hash();
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let peer = spawn_mock_bloom_peer(listener, blocks.clone(), NODE_NETWORK | NODE_BLOOM, None);
+        let mut config = BitcoinIndexerConfig::test_default(
+            "/tmp/test_filtered_blocks_watch_targets.dat".to_string(),
+        );
+        config.peer_port = port;
+        config.filter_scripts = vec![burn_script.to_bytes()];
+        // low enough that the coinbases and the unwatched spend won't match by chance
+        config.bloom_filter_fp_rate = 0.000000001;
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
 
-        // get the canonical chain's headers for this range
-        let good_headers = spv_client.read_block_headers(40319, 40321).unwrap();
-        assert_eq!(good_headers.len(), 2);
+        // start watching the outpoint once the filter is loaded
+        indexer.connect_handshake_backoff().unwrap();
+        indexer.load_bloom_filter().unwrap();
+        indexer.add_filter_outpoint(watched).unwrap();
+        indexer.add_filter_outpoint(watched).unwrap();
+        assert_eq!(indexer.config.filter_outpoints, vec![watched]);
+        assert!(!indexer
+            .runtime
+            .bloom_filter
+            .as_ref()
+            .unwrap()
+            .is_saturated());
+
+        let delivered =
+            download_blocks_by(BitcoinBlockDownloader::new_filtered(indexer), &blocks).unwrap();
+        let (filterloads, filteradds, _) = peer.join().unwrap();
+        assert_eq!(filterloads.len(), 1);
         assert_eq!(
-            good_headers[0].header.prev_blockhash,
-            bad_headers[0].header.prev_blockhash
+            filteradds,
+            vec![FilterAdd {
+                data: bloom::Bip37Filter::outpoint_element(&watched)
+            }]
         );
-        assert!(good_headers[0].header != bad_headers[0].header);
-        assert!(good_headers[1].header != bad_headers[1].header);
 
-        // put these bad headers into the "main" chain
-        spv_client
-            .insert_block_headers_after(40318, bad_headers.clone())
-            .unwrap();
+        assert_eq!(delivered.len(), 2);
+        let expected = [vec![(1, burn_tx), (2, watched_spend)], vec![]];
+        for ((ipc_block, block), expected) in delivered.iter().zip(blocks.iter()).zip(expected) {
+            let merkle_block = match ipc_block.block_message {
+                NetworkMessage::MerkleBlock(ref merkle_block) => merkle_block,
+                ref x => panic!("Expected a merkleblock, got {:?}", x),
+            };
+            let proven_txs = extract_proven_txs(merkle_block, &ipc_block.matched_txs).unwrap();
+            let positions_and_txs: Vec<_> = proven_txs
+                .iter()
+                .map(|proven_tx| (proven_tx.position, proven_tx.tx.clone()))
+                .collect();
+            assert_eq!(positions_and_txs, expected);
+            for proven_tx in proven_txs.iter() {
+                assert!(proven_tx.verify(&block.header).unwrap());
+            }
 
-        // *now* calculate main chain work
-        SpvClient::test_db_migrate(spv_client.conn_mut()).unwrap();
-        let total_work_before = spv_client.update_chain_work().unwrap();
-        assert_eq!(total_work_before, spv_client.get_chain_work().unwrap());
+            // the transactions have to be the ones the partial merkle tree matched
+            if !ipc_block.matched_txs.is_empty() {
+                let mut txs = ipc_block.matched_txs.clone();
+                txs.reverse();
+                match extract_proven_txs(merkle_block, &txs) {
+                    Err(btc_error::InvalidMerkleBlock(_)) => {}
+                    x => panic!("Expected InvalidMerkleBlock, got {:?}", x),
+                }
+            }
+        }
+    }
 
-        let total_work_before_idempotent = spv_client.update_chain_work().unwrap();
-        assert_eq!(total_work_before, total_work_before_idempotent);
+    /// Serve the blocks of `chain` (as heights 1 and up) to every connection, as it is when
+    /// each `getheaders` or `getdata` arrives.  Runs until the test ends.
+    fn spawn_mock_chain_peer(listener: net::TcpListener, chain: Arc<Mutex<Vec<Block>>>) {
+        spawn_mock_dropping_chain_peer(listener, chain, None, None)
+    }
+
+    /// Like `spawn_mock_chain_peer()`, but if `drop_every` is given, each connection is hung up
+    /// on when the `drop_every`th message after the handshake arrives, as if the peer restarted.
+    /// If that message is a `getdata`, the first half of the first block it asks for is sent
+    /// before hanging up.  If `served` is given, the hash of each block sent in full is sent to
+    /// it once the block is.
+    fn spawn_mock_dropping_chain_peer(
+        listener: net::TcpListener,
+        chain: Arc<Mutex<Vec<Block>>>,
+        drop_every: Option<usize>,
+        served: Option<Sender<Sha256dHash>>,
+    ) {
+        let magic = BITCOIN_REGTEST;
+        thread::spawn(move || loop {
+            let (sock, _) = listener.accept().unwrap();
+            let chain = chain.clone();
+            let served = served.clone();
+            thread::spawn(move || {
+                let mut raw_sock = sock.try_clone().unwrap();
+                let mut decoder = RawDecoder::new(sock.try_clone().unwrap());
+                let mut encoder = RawEncoder::new(sock);
+                let mut recv = move || -> Option<NetworkMessage> {
+                    let msg: RawNetworkMessage =
+                        ConsensusDecodable::consensus_decode(&mut decoder).ok()?;
+                    assert_eq!(msg.magic, magic);
+                    Some(msg.payload)
+                };
+                let mut send = move |payload| {
+                    let _ = RawNetworkMessage { magic, payload }.consensus_encode(&mut encoder);
+                };
+
+                let mut version_body = match recv() {
+                    Some(NetworkMessage::Version(body)) => body,
+                    x => panic!("Expected version, got {:?}", &x),
+                };
+                version_body.services = NODE_NETWORK;
+                version_body.start_height = chain.lock().unwrap().len() as i32;
+                send(NetworkMessage::Version(version_body));
+                send(NetworkMessage::Verack);
+                assert_eq!(recv(), Some(NetworkMessage::Verack));
+
+                let mut num_received = 0;
+                while let Some(msg) = recv() {
+                    let blocks = chain.lock().unwrap().clone();
+                    num_received += 1;
+                    if drop_every == Some(num_received) {
+                        if let NetworkMessage::GetData(invs) = msg {
+                            let block = blocks
+                                .iter()
+                                .find(|block| block.bitcoin_hash() == invs[0].hash)
+                                .unwrap();
+                            let block_msg = serialize(&RawNetworkMessage {
+                                magic,
+                                payload: NetworkMessage::Block(block.clone()),
+                            })
+                            .unwrap();
+                            let _ = io::Write::write_all(
+                                &mut raw_sock,
+                                &block_msg[..block_msg.len() / 2],
+                            );
+                        }
+                        let _ = raw_sock.shutdown(Shutdown::Both);
+                        return;
+                    }
+                    match msg {
+                        NetworkMessage::GetHeaders(getheaders) => {
+                            // the headers after the first locator hash we have
+                            let start = getheaders
+                                .locator_hashes
+                                .iter()
+                                .find_map(|hash| {
+                                    blocks
+                                        .iter()
+                                        .position(|block| block.bitcoin_hash() == *hash)
+                                })
+                                .map(|i| i + 1)
+                                .unwrap_or(0);
+                            send(NetworkMessage::Headers(
+                                blocks[start..]
+                                    .iter()
+                                    .map(|block| LoneBlockHeader {
+                                        header: block.header.clone(),
+                                        tx_count: VarInt(0),
+                                    })
+                                    .collect(),
+                            ));
+                        }
+                        NetworkMessage::GetData(invs) => {
+                            for inv in invs.iter() {
+                                let block = blocks
+                                    .iter()
+                                    .find(|block| block.bitcoin_hash() == inv.hash)
+                                    .unwrap();
+                                send(NetworkMessage::Block(block.clone()));
+                                if let Some(served) = served.as_ref() {
+                                    let _ = served.send(inv.hash);
+                                }
+                            }
+                        }
+                        NetworkMessage::Ping(nonce) => send(NetworkMessage::Pong(nonce)),
+                        _ => {}
+                    }
+                }
+            });
+        });
+    }
 
+    #[test]
+    fn test_sync_survives_dropped_connections() {
+        let db_path = "/tmp/test_sync_survives_dropped_connections.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let blocks = make_regtest_block_chain(10);
+        let chain = Arc::new(Mutex::new(blocks.clone()));
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // the peer answers one request per connection, and hangs up on the next (partway
+        // through a block, if it's asked for blocks)
+        spawn_mock_dropping_chain_peer(listener, chain, Some(2), None);
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = port;
+        config.block_download_window = 3;
+        config.reconnect_policy = ReconnectPolicy {
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            multiplier: 2.0,
+            reset_threshold: Duration::from_secs(60),
+            max_attempts: 0,
+        };
         let mut indexer = BitcoinIndexer::new(
-            BitcoinIndexerConfig::test_default(db_path.to_string()),
-            BitcoinIndexerRuntime::new(BitcoinNetworkType::Mainnet),
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
         );
 
-        let mut inserted_good_header = false;
+        // every header gets synced...
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 10);
+        assert_eq!(indexer.get_highest_header_height().unwrap(), 10);
+
+        // ...and every block gets downloaded whole, in order, despite the hang-ups
+        let delivered = download_blocks_from(indexer, &blocks).unwrap();
+        assert_eq!(delivered.len(), blocks.len());
+        for (i, (ipc_block, block)) in delivered.iter().zip(blocks.iter()).enumerate() {
+            assert_eq!(ipc_block.height(), (i as u64) + 1);
+            assert_eq!(
+                ipc_block.block_message,
+                NetworkMessage::Block(block.clone())
+            );
+        }
+    }
 
-        let new_tip = indexer
-            .find_bitcoin_reorg(
-                &db_path,
-                &reorg_db_path,
-                |ref mut indexer, ref mut reorg_spv_client, start_block, end_block_opt| {
-                    let end_block =
-                        end_block_opt.unwrap_or(start_block + BLOCK_DIFFICULTY_CHUNK_SIZE);
+    /// Wait up to 10 seconds for the spawned indexer's stats to satisfy `done`
+    fn wait_for_indexer<F>(handle: &IndexerHandle, done: F) -> IndexerStats
+    where
+        F: Fn(&IndexerStats) -> bool,
+    {
+        let start = Instant::now();
+        loop {
+            let stats = handle.stats();
+            if done(&stats) {
+                return stats;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "Timed out waiting on the indexer: {:?}",
+                &stats
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
 
-                    let mut ret = vec![];
-                    for block_height in start_block..end_block {
-                        if block_height > 40320 {
-                            break;
-                        }
-                        if block_height >= 40319 && block_height <= 40320 {
-                            test_debug!("insert good header {}", block_height);
-                            ret.push(good_headers[(block_height - 40319) as usize].clone());
-                            inserted_good_header = true;
-                        } else {
-                            let orig_spv_client = SpvClient::new_without_migration(
-                                &db_path,
-                                0,
-                                None,
-                                BitcoinNetworkType::Mainnet,
-                                true,
-                                false,
-                            )
-                            .unwrap();
-                            let hdr = orig_spv_client.read_block_header(block_height)?.unwrap();
-                            ret.push(hdr);
-                        }
-                    }
+    #[test]
+    fn test_spawned_indexer() {
+        let db_path = "/tmp/test_spawned_indexer.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let blocks = make_regtest_block_chain(6);
+        let chain = Arc::new(Mutex::new(blocks[0..3].to_vec()));
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        spawn_mock_chain_peer(listener, chain.clone());
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = port;
+        let handle = BitcoinIndexer::spawn(config, BitcoinNetworkType::Regtest).unwrap();
+        let recv_block = |height: u64| {
+            let block = handle
+                .blocks()
+                .recv_timeout(Duration::from_secs(10))
+                .unwrap();
+            assert_eq!(block.block_height, height);
+            assert_eq!(
+                block.block_hash,
+                BurnchainHeaderHash::from_bitcoin_hash(
+                    &blocks[(height - 1) as usize].bitcoin_hash()
+                )
+            );
+        };
 
-                    test_debug!(
-                        "add headers after {} (good header: {})",
-                        start_block,
-                        inserted_good_header
-                    );
-                    reorg_spv_client
-                        .insert_block_headers_after(start_block - 1, ret)
-                        .unwrap();
-                    Ok(())
-                },
+        // it syncs up to the peer's chain tip on its own
+        for height in 1..4 {
+            recv_block(height);
+        }
+
+        // while it's paused, a new block arrives, which it doesn't sync until it's resumed
+        handle.pause();
+        let stats = wait_for_indexer(&handle, |stats| stats.state == IndexerState::Paused);
+        assert_eq!(stats.block_height, 3);
+        chain.lock().unwrap().push(blocks[3].clone());
+        thread::sleep(Duration::from_millis(200));
+        assert!(handle.blocks().try_recv().is_err());
+        assert_eq!(handle.stats().headers_height, 3);
+
+        handle.resume();
+        recv_block(4);
+        let stats = wait_for_indexer(&handle, |stats| stats.state == IndexerState::Idle);
+        assert_eq!(stats.headers_height, 4);
+        assert_eq!(stats.block_height, 4);
+
+        // with a target height, it stops there even if the peer has more
+        handle.pause();
+        wait_for_indexer(&handle, |stats| stats.state == IndexerState::Paused);
+        chain.lock().unwrap().extend_from_slice(&blocks[4..6]);
+        handle.sync_to(5);
+        handle.resume();
+        recv_block(5);
+        let stats = wait_for_indexer(&handle, |stats| stats.state == IndexerState::Idle);
+        assert_eq!(stats.headers_height, 6);
+        assert_eq!(stats.block_height, 5);
+        assert_eq!(stats.target_height, Some(5));
+        assert!(handle.blocks().try_recv().is_err());
+
+        handle.sync_to(6);
+        recv_block(6);
+
+        // shutting down joins the thread; the headers it synced are stored
+        handle.shutdown().unwrap();
+        let indexer = BitcoinIndexer::new(
+            BitcoinIndexerConfig::test_default(db_path.to_string()),
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        assert_eq!(indexer.get_highest_header_height().unwrap(), 6);
+    }
+
+    #[test]
+    fn test_spawned_indexer_backpressure() {
+        let blocks = make_regtest_block_chain(6);
+        let chain = Arc::new(Mutex::new(blocks.clone()));
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (served_sender, served) = channel();
+        spawn_mock_dropping_chain_peer(listener, chain.clone(), None, Some(served_sender));
+
+        let spawn = |db_path: &str, block_download_window: usize| {
+            if fs::metadata(db_path).is_ok() {
+                fs::remove_file(db_path).unwrap();
+            }
+            let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+            config.peer_port = port;
+            config.block_download_window = block_download_window;
+            BitcoinIndexer::spawn(config, BitcoinNetworkType::Regtest).unwrap()
+        };
+
+        // no more than the download window's worth of blocks wait to be received:  the third
+        // block is downloaded, but waits for room
+        let handle = spawn("/tmp/test_spawned_indexer_backpressure.dat", 2);
+        for block in blocks[0..3].iter() {
+            assert_eq!(
+                served.recv_timeout(Duration::from_secs(10)).unwrap(),
+                block.bitcoin_hash()
+            );
+        }
+        assert_eq!(handle.stats().block_height, 2);
+
+        // an indexer waiting for room can still be paused, and drops the waiting block
+        handle.pause();
+        let stats = wait_for_indexer(&handle, |stats| stats.state == IndexerState::Paused);
+        assert_eq!(stats.block_height, 2);
+        for height in 1..3 {
+            assert_eq!(handle.blocks().try_recv().unwrap().block_height, height);
+        }
+        assert!(handle.blocks().try_recv().is_err());
+
+        // ...and the rest follow as they're received once it's resumed
+        handle.resume();
+        for height in 3..7 {
+            let block = handle
+                .blocks()
+                .recv_timeout(Duration::from_secs(10))
+                .unwrap();
+            assert_eq!(block.block_height, height);
+        }
+        for block in blocks[2..6].iter() {
+            assert_eq!(
+                served.recv_timeout(Duration::from_secs(10)).unwrap(),
+                block.bitcoin_hash()
+            );
+        }
+        let stats = wait_for_indexer(&handle, |stats| stats.state == IndexerState::Idle);
+        assert_eq!(stats.block_height, 6);
+        handle.shutdown().unwrap();
+
+        // an indexer waiting for room can still be shut down
+        let handle = spawn("/tmp/test_spawned_indexer_backpressure_shutdown.dat", 1);
+        for block in blocks[0..2].iter() {
+            assert_eq!(
+                served.recv_timeout(Duration::from_secs(10)).unwrap(),
+                block.bitcoin_hash()
+            );
+        }
+        let start = Instant::now();
+        handle.shutdown().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    /// Sync `indexer`'s headers from its peer, and download and process the blocks above
+    /// `burnchain`'s burn tip.  Returns the new burn tip.
+    fn sync_and_process_blocks(indexer: &mut BitcoinIndexer, burnchain: &Burnchain) -> BurnTip {
+        let start_height = indexer.get_highest_header_height().unwrap();
+        let headers_height = indexer.sync_last_headers(start_height, None).unwrap();
+        indexer.disconnect();
+
+        let mut burnchain_db =
+            BurnchainDB::connect(&burnchain.get_burnchaindb_path(), burnchain, true).unwrap();
+        let burn_height = BurnTip::from_db(&burnchain_db).unwrap().block_height;
+        let headers = indexer
+            .read_headers(burn_height + 1, headers_height + 1)
+            .unwrap();
+        let mut downloader = indexer.downloader();
+        let mut parser = indexer.parser();
+        for header in headers.iter() {
+            let ipc_block = downloader.download(header).unwrap();
+            let block = parser.parse(&ipc_block, StacksEpochId::Epoch21).unwrap();
+            Burnchain::process_block(
+                burnchain,
+                &mut burnchain_db,
+                &*indexer,
+                &block,
+                StacksEpochId::Epoch21,
             )
             .unwrap();
+        }
+        BurnTip::from_db(&burnchain_db).unwrap()
+    }
 
-        assert!(inserted_good_header);
+    #[test]
+    fn test_snapshot_restore() {
+        let snapshot_path = "/tmp/test_snapshot_restore.snapshot";
+        let corrupt_path = "/tmp/test_snapshot_restore_corrupt.snapshot";
+        let blocks = make_regtest_block_chain(6);
+        let chain = Arc::new(Mutex::new(blocks[0..4].to_vec()));
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        spawn_mock_chain_peer(listener, chain.clone());
+
+        let make_node = |working_dir: &str| {
+            if fs::metadata(working_dir).is_ok() {
+                fs::remove_dir_all(working_dir).unwrap();
+            }
+            let mut config =
+                BitcoinIndexerConfig::test_default(format!("{}/headers.sqlite", working_dir));
+            config.peer_port = port;
+            let indexer = BitcoinIndexer::new(
+                config,
+                BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+            );
+            let mut burnchain = Burnchain::regtest(working_dir);
+            burnchain.pox_constants = PoxConstants::test_default();
+            (indexer, burnchain)
+        };
+        let block_tip = |height: u64| {
+            let block = &blocks[(height - 1) as usize];
+            BurnTip {
+                block_height: height,
+                block_hash: BurnchainHeaderHash::from_bitcoin_hash(&block.bitcoin_hash()),
+                parent_block_hash: BurnchainHeaderHash::from_bitcoin_hash(
+                    &block.header.prev_blockhash,
+                ),
+                timestamp: block.header.time as u64,
+            }
+        };
 
-        // chain reorg detected!
-        assert_eq!(new_tip, 40318);
+        // a node that's synced the first 4 blocks takes a snapshot
+        let (mut indexer, burnchain) = make_node("/tmp/test_snapshot_restore_a");
+        fs::create_dir_all(&burnchain.working_dir).unwrap();
+        let burn_tip = sync_and_process_blocks(&mut indexer, &burnchain);
+        assert_eq!(burn_tip, block_tip(4));
+
+        let manifest = indexer.create_snapshot(&burnchain, snapshot_path).unwrap();
+        assert_eq!(manifest.network, BitcoinNetworkType::Regtest);
+        assert_eq!(manifest.headers_height, 4);
+        assert_eq!(manifest.burn_tip, burn_tip);
+        assert_eq!(manifest.files.len(), 2);
+
+        // it can't be restored over a node's existing state
+        match indexer.restore_snapshot(&burnchain, snapshot_path) {
+            Err(burnchain_error::Bitcoin(btc_error::ConfigError(_))) => {}
+            x => panic!("Expected ConfigError, got {:?}", x),
+        }
 
-        // total work increased
-        let total_work_after = spv_client.update_chain_work().unwrap();
-        assert!(total_work_after > total_work_before);
+        // a corrupt snapshot is refused, and leaves nothing behind
+        let mut corrupt = fs::read(snapshot_path).unwrap();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xff;
+        fs::write(corrupt_path, &corrupt).unwrap();
+        let (corrupt_indexer, corrupt_burnchain) = make_node("/tmp/test_snapshot_restore_c");
+        match corrupt_indexer.restore_snapshot(&corrupt_burnchain, corrupt_path) {
+            Err(burnchain_error::Bitcoin(btc_error::InvalidSnapshot(_))) => {}
+            x => panic!("Expected InvalidSnapshot, got {:?}", x),
+        }
+        assert!(fs::metadata(&corrupt_indexer.config.spv_headers_path).is_err());
+        assert!(fs::metadata(&corrupt_burnchain.get_burnchaindb_path()).is_err());
+
+        // a new node restored from the snapshot starts where the first one was...
+        let (mut restored_indexer, restored_burnchain) = make_node("/tmp/test_snapshot_restore_b");
+        assert_eq!(
+            restored_indexer
+                .restore_snapshot(&restored_burnchain, snapshot_path)
+                .unwrap(),
+            manifest
+        );
+        assert_eq!(restored_indexer.get_highest_header_height().unwrap(), 4);
+        assert_eq!(
+            restored_indexer.get_burn_tip(&restored_burnchain).unwrap(),
+            block_tip(4)
+        );
+
+        // ...and carries on syncing from there
+        chain.lock().unwrap().extend_from_slice(&blocks[4..6]);
+        let burn_tip = sync_and_process_blocks(&mut restored_indexer, &restored_burnchain);
+        assert_eq!(burn_tip, block_tip(6));
+        assert_eq!(restored_indexer.get_highest_header_height().unwrap(), 6);
     }
 
     #[test]
-    fn test_check_header_timestamp() {
-        let db_path = "/tmp/test-indexer-check-header-timestamp.dat";
+    fn test_block_download_rate_limit() {
+        let blocks = make_regtest_block_chain(12);
+        let block_hashes: Vec<_> = blocks.iter().map(|block| block.bitcoin_hash()).collect();
+        let block_msg_size = serialize(&RawNetworkMessage {
+            magic: BITCOIN_REGTEST,
+            payload: NetworkMessage::Block(blocks[0].clone()),
+        })
+        .unwrap()
+        .len() as u64;
+        // the blocks only differ in their coinbase's lock time, so they're all the same size
+        let total_bytes = block_msg_size * (blocks.len() as u64);
+
+        // four blocks' worth of bytes a second, so each block past the initial burst holds the
+        // download up for about a quarter of a second
+        let rate = 4 * block_msg_size;
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let peer =
+            spawn_mock_blocks_peer(listener, blocks.clone(), Duration::from_millis(10), None);
+
+        let mut config = BitcoinIndexerConfig::test_default(
+            "/tmp/test_block_download_rate_limit.dat".to_string(),
+        );
+        config.peer_port = port;
+        config.block_download_window = 4;
+        config.block_download_rate_limit = Some(rate);
+        // both shorter than the wait after each block, which mustn't count towards either
+        config.block_request_timeout = Duration::from_millis(100);
+        config.read_timeout = Duration::from_millis(200);
+        let indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+
+        let mut headers = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| BitcoinHeaderIPC {
+                block_header: LoneBlockHeader {
+                    header: block.header.clone(),
+                    tx_count: VarInt(block.txdata.len() as u64),
+                },
+                block_height: (i as u64) + 1,
+            })
+            .collect::<Vec<_>>()
+            .into_iter();
+
+        let mut downloader = BitcoinBlockDownloader::new(indexer);
+        let mut delivered = vec![];
+        let start = Instant::now();
+        downloader
+            .run_window(&mut headers, &mut |ipc_block| {
+                delivered.push(ipc_block.header_data.block_height);
+                Ok(())
+            })
+            .unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(delivered, (1..13).collect::<Vec<u64>>());
+
+        // we can't have downloaded more than the burst plus `rate` bytes a second, but we
+        // shouldn't have been held up much longer than that either
+        let min_time = Duration::from_secs_f64((total_bytes - rate) as f64 / rate as f64);
+        assert!(
+            elapsed >= min_time,
+            "Downloaded {} bytes in {:?}, limit is {} bytes/s",
+            total_bytes,
+            &elapsed,
+            rate
+        );
+        assert!(
+            elapsed < min_time + Duration::from_secs(1),
+            "Downloaded {} bytes in {:?}, expected about {:?}",
+            total_bytes,
+            &elapsed,
+            &min_time
+        );
+
+        // no block was asked for twice, or the connection dropped, while we were throttled
+        drop(downloader);
+        let requests = peer.join().unwrap();
+        assert_eq!(requests.concat(), block_hashes);
+    }
 
+    #[test]
+    fn test_sync_stats() {
+        let db_path = "/tmp/test_sync_stats.dat";
         if fs::metadata(db_path).is_ok() {
             fs::remove_file(db_path).unwrap();
         }
-
-        let headers = vec![
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "20bee96458517fc5082a9720ce6207b5742f2b18e4e0a7e7373342725d80f88c",
-                    )
-                    .unwrap(),
-                    nonce: 2,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
-                    )
-                    .unwrap(),
-                    time: (get_epoch_time_secs() - 1) as u32,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "39d1a6f1ee7a5903797f92ec89e4c58549013f38114186fc2eb6e5218cb2d0ac",
-                    )
-                    .unwrap(),
-                    nonce: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "606d31daaaa5919f3720d8440dd99d31f2a4e4189c65879f19ae43268425e74b",
-                    )
-                    .unwrap(),
-                    time: (get_epoch_time_secs() - 1) as u32,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "a7e04ed25f589938eb5627abb7b5913dd77b8955bcdf72d7f111d0a71e346e47",
-                    )
-                    .unwrap(),
-                    nonce: 4,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "2fa2f451ac27f0e5cd3760ba6cdf34ef46adb76a44d96bc0f3bf3e713dd955f0",
-                    )
-                    .unwrap(),
-                    time: 1587626882,
-                    version: 0x20000000,
-                },
+        let blocks = make_regtest_block_chain(6);
+        let headers: Vec<_> = blocks
+            .iter()
+            .map(|block| LoneBlockHeader {
+                header: block.header.clone(),
                 tx_count: VarInt(0),
-            },
-        ];
-
-        // set up SPV client so we don't have chain work at first
-        let mut spv_client = SpvClient::new_without_migration(
-            &db_path,
-            0,
-            None,
-            BitcoinNetworkType::Regtest,
-            true,
-            false,
-        )
-        .unwrap();
-
-        spv_client
-            .test_write_block_headers(0, headers.clone())
-            .unwrap();
-        assert_eq!(spv_client.get_highest_header_height().unwrap(), 2);
+            })
+            .collect();
 
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.sync_progress_log_interval = Some(Duration::from_secs(0));
         let mut indexer = BitcoinIndexer::new(
-            BitcoinIndexerConfig::test_default(db_path.to_string()),
+            config,
             BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
         );
 
-        if let Err(burnchain_error::TrySyncAgain) = indexer.check_chain_tip_timestamp() {
-        } else {
-            panic!("stale tip not detected");
+        // watch the stats from another thread while we sync
+        let stats_handle = indexer.sync_stats_handle();
+        let done = Arc::new(AtomicBool::new(false));
+        let sampler_done = done.clone();
+        let sampler = thread::spawn(move || {
+            let mut samples = vec![];
+            while !sampler_done.load(Ordering::SeqCst) {
+                samples.push(stats_handle.lock().unwrap().clone());
+                thread::sleep(Duration::from_millis(1));
+            }
+            samples.push(stats_handle.lock().unwrap().clone());
+            samples
+        });
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let headers_port = listener.local_addr().unwrap().port();
+        let headers_peer = spawn_mock_headers_peer(listener, headers, 2, usize::MAX);
+        indexer.config.peer_port = headers_port;
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 6);
+
+        let stats = indexer.sync_stats();
+        assert_eq!(stats.headers_height, 6);
+        assert_eq!(stats.remote_tip_height, 6);
+        assert_eq!(stats.blocks_height, 0);
+        assert_eq!(stats.peer, Some(("127.0.0.1".to_string(), headers_port)));
+        assert!(stats.bytes_downloaded > 6 * BLOCK_HEADER_SIZE);
+
+        indexer.disconnect();
+        assert_eq!(headers_peer.join().unwrap(), 3);
+        assert_eq!(indexer.sync_stats().peer, None);
+
+        // the blocks are downloaded by a dup() of the indexer, which shares its stats
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let blocks_port = listener.local_addr().unwrap().port();
+        let blocks_peer =
+            spawn_mock_blocks_peer(listener, blocks.clone(), Duration::from_millis(10), None);
+        indexer.config.peer_port = blocks_port;
+
+        let mut ipc_headers = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| BitcoinHeaderIPC {
+                block_header: LoneBlockHeader {
+                    header: block.header.clone(),
+                    tx_count: VarInt(block.txdata.len() as u64),
+                },
+                block_height: (i as u64) + 1,
+            })
+            .collect::<Vec<_>>()
+            .into_iter();
+        let bytes_before_blocks = indexer.sync_stats().bytes_downloaded;
+        let mut downloader = BitcoinBlockDownloader::new(indexer.dup());
+        downloader
+            .run_window(&mut ipc_headers, &mut |_ipc_block| Ok(()))
+            .unwrap();
+        drop(downloader);
+        blocks_peer.join().unwrap();
+
+        // blocks only count once they're processed
+        let stats = indexer.sync_stats();
+        assert_eq!(stats.blocks_height, 0);
+        assert!(stats.bytes_downloaded > bytes_before_blocks);
+        for (i, block) in blocks.iter().enumerate() {
+            indexer.report_block_processed(&BurnchainBlockHeader {
+                block_height: (i as u64) + 1,
+                block_hash: BurnchainHeaderHash::from_bitcoin_hash(&block.bitcoin_hash()),
+                parent_block_hash: BurnchainHeaderHash::from_bitcoin_hash(
+                    &block.header.prev_blockhash,
+                ),
+                num_txs: block.txdata.len() as u64,
+                timestamp: block.header.time as u64,
+            });
         }
 
-        // peeled
-        assert_eq!(spv_client.get_highest_header_height().unwrap(), 1);
-        assert!(indexer.check_chain_tip_timestamp().is_ok());
-        assert_eq!(spv_client.get_highest_header_height().unwrap(), 1);
+        let stats = indexer.sync_stats();
+        assert_eq!(stats.blocks_height, 6);
+        assert!(stats.blocks_per_sec() > 0.0);
+        assert!(stats.bytes_downloaded > bytes_before_blocks);
+        assert_eq!(stats.peer, Some(("127.0.0.1".to_string(), blocks_port)));
+
+        // a block announcement means the peer's tip has moved past ours
+        let announcement = NetworkMessage::Inv(vec![Inventory {
+            inv_type: InvType::Block,
+            hash: Sha256dHash::from_data(b"block 7"),
+        }]);
+        assert!(indexer
+            .handle_message::<SpvClient>(announcement, None)
+            .is_err());
+        assert_eq!(indexer.sync_stats().remote_tip_height, 7);
+
+        done.store(true, Ordering::SeqCst);
+        let samples = sampler.join().unwrap();
+        assert!(samples.len() > 1);
+        for pair in samples.windows(2) {
+            assert!(pair[0].headers_height <= pair[1].headers_height);
+            assert!(pair[0].blocks_height <= pair[1].blocks_height);
+            assert!(pair[0].remote_tip_height <= pair[1].remote_tip_height);
+            assert!(pair[0].bytes_downloaded <= pair[1].bytes_downloaded);
+        }
+        let last_sample = samples.last().unwrap();
+        assert_eq!(last_sample.headers_height, 6);
+        assert_eq!(last_sample.blocks_height, 6);
+        assert_eq!(last_sample.remote_tip_height, 7);
     }
 }
@@ -14,6 +14,361 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cmp;
+use std::thread;
+
+use crate::burnchains::bitcoin::Error as btc_error;
+use ::secp256k1::Error as LibSecp256k1Error;
 use stacks_common::util::secp256k1;
 
 pub type BitcoinPublicKey = secp256k1::Secp256k1PublicKey;
+
+/// Length of a compressed public key: 0x02 or 0x03, then the x coordinate
+pub const PUBLIC_KEY_COMPRESSED_LEN: usize = 33;
+/// Length of an uncompressed public key: 0x04, then the x and y coordinates
+pub const PUBLIC_KEY_UNCOMPRESSED_LEN: usize = 65;
+/// Length of a BIP340 x-only public key, i.e. a taproot output key
+pub const PUBLIC_KEY_XONLY_LEN: usize = 32;
+/// Not worth spawning a thread to verify fewer signatures than this
+const MIN_SIG_CHECKS_PER_THREAD: usize = 16;
+
+/// Parse a public key, accepting only the two standard encodings: compressed (33 bytes,
+/// starting with 0x02 or 0x03) or uncompressed (65 bytes, starting with 0x04).  Unlike
+/// BitcoinPublicKey::from_slice(), this rejects libsecp256k1's "hybrid" 0x06/0x07 encoding.
+/// The point must be on the curve either way.
+pub fn parse_public_key(data: &[u8]) -> Result<BitcoinPublicKey, btc_error> {
+    let well_formed = match data.first() {
+        Some(0x02) | Some(0x03) => data.len() == PUBLIC_KEY_COMPRESSED_LEN,
+        Some(0x04) => data.len() == PUBLIC_KEY_UNCOMPRESSED_LEN,
+        _ => false,
+    };
+    if !well_formed {
+        test_debug!(
+            "Invalid public key encoding: {} bytes, prefix {:?}",
+            data.len(),
+            data.first()
+        );
+        return Err(btc_error::InvalidByteSequence);
+    }
+    BitcoinPublicKey::from_slice(data).map_err(|e| {
+        test_debug!("Invalid public key: {}", e);
+        btc_error::InvalidByteSequence
+    })
+}
+
+/// Get the compressed form of a public key.  The compressed and uncompressed encodings of a key
+/// are different byte strings (and hash to different addresses), so compare normalized keys to
+/// tell whether two encodings are the same key.
+pub fn normalize_public_key(pubkey: &BitcoinPublicKey) -> BitcoinPublicKey {
+    let mut normalized = *pubkey;
+    normalized.set_compressed(true);
+    normalized
+}
+
+/// Verify a batch of DER-encoded signatures on up to `num_threads` threads.  Each check is the
+/// signer's public key, the 32-byte hash it signed, and the signature (without a sighash type
+/// byte).  Returns the indices of the checks that failed, in order, so an empty vec means they
+/// all passed.  A signature that can't be decoded fails.
+pub fn verify_batch(
+    checks: &[(BitcoinPublicKey, Vec<u8>, Vec<u8>)],
+    num_threads: usize,
+) -> Vec<usize> {
+    let num_threads = cmp::min(num_threads, checks.len() / MIN_SIG_CHECKS_PER_THREAD);
+    if num_threads <= 1 {
+        return verify_batch_serial(checks, 0);
+    }
+
+    let chunk_size = (checks.len() + num_threads - 1) / num_threads;
+    let workers: Vec<_> = checks
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let chunk = chunk.to_vec();
+            thread::spawn(move || verify_batch_serial(&chunk, i * chunk_size))
+        })
+        .collect();
+
+    // chunks are in order, so their failures are too
+    let mut failed = vec![];
+    for worker in workers.into_iter() {
+        failed.append(
+            &mut worker
+                .join()
+                .expect("FATAL: signature verification thread panicked"),
+        );
+    }
+    failed
+}
+
+fn verify_batch_serial(
+    checks: &[(BitcoinPublicKey, Vec<u8>, Vec<u8>)],
+    offset: usize,
+) -> Vec<usize> {
+    checks
+        .iter()
+        .enumerate()
+        .filter(|(_, (pubkey, hash, sig))| !pubkey.verify_der(hash, sig).unwrap_or(false))
+        .map(|(i, _)| offset + i)
+        .collect()
+}
+
+/// Verify a BIP340 Schnorr signature, such as the one in a taproot key-path spend.
+/// `pubkey_xonly` is the 32-byte x-only public key, and `msg` is the 32-byte hash that was signed.
+/// Returns Ok(false) if the signature doesn't verify, and btc_error::InvalidByteSequence if the
+/// public key isn't 32 bytes or isn't the x coordinate of a point on the curve.
+pub fn verify_schnorr(
+    pubkey_xonly: &[u8],
+    msg: &[u8; 32],
+    sig: &[u8; 64],
+) -> Result<bool, btc_error> {
+    if pubkey_xonly.len() != PUBLIC_KEY_XONLY_LEN {
+        test_debug!("Invalid x-only public key: {} bytes", pubkey_xonly.len());
+        return Err(btc_error::InvalidByteSequence);
+    }
+    match secp256k1::secp256k1_verify_schnorr(msg, sig, pubkey_xonly) {
+        Ok(()) => Ok(true),
+        Err(LibSecp256k1Error::InvalidPublicKey) => {
+            test_debug!("Invalid x-only public key: not on the curve");
+            Err(btc_error::InvalidByteSequence)
+        }
+        Err(e) => {
+            test_debug!("Schnorr signature does not verify: {:?}", e);
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::burnchains::{PrivateKey, PublicKey};
+    use stacks_common::util::hash::{hex_bytes, Sha256Sum};
+    use stacks_common::util::secp256k1::Secp256k1PrivateKey;
+
+    #[test]
+    fn test_parse_public_key() {
+        let compressed_bytes =
+            hex_bytes("025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357")
+                .unwrap();
+        let uncompressed_bytes = hex_bytes("045476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357fd57dee6b46a6b010a3e4a70961ecf44a40e18b279ec9e9fba9c1dbc64896198").unwrap();
+
+        let compressed = parse_public_key(&compressed_bytes).unwrap();
+        let uncompressed = parse_public_key(&uncompressed_bytes).unwrap();
+        assert!(compressed.compressed());
+        assert!(!uncompressed.compressed());
+        assert_eq!(compressed.to_bytes(), compressed_bytes);
+        assert_eq!(uncompressed.to_bytes(), uncompressed_bytes);
+
+        // both forms of the same key
+        assert!(compressed != uncompressed);
+        assert_eq!(normalize_public_key(&compressed), compressed);
+        assert_eq!(normalize_public_key(&uncompressed), compressed);
+        for key in [compressed, uncompressed].iter() {
+            assert_eq!(key.to_bytes_compressed(), compressed_bytes);
+            assert_eq!(key.to_bytes_uncompressed(), uncompressed_bytes);
+            assert_eq!(
+                parse_public_key(&key.to_bytes_compressed()).unwrap(),
+                compressed
+            );
+            assert_eq!(
+                parse_public_key(&key.to_bytes_uncompressed()).unwrap(),
+                uncompressed
+            );
+        }
+
+        // wrong prefix for the length
+        let mut bad_prefix = compressed_bytes.clone();
+        bad_prefix[0] = 0x04;
+        assert!(parse_public_key(&bad_prefix).is_err());
+
+        let mut bad_prefix = uncompressed_bytes.clone();
+        bad_prefix[0] = 0x02;
+        assert!(parse_public_key(&bad_prefix).is_err());
+
+        // hybrid encoding: libsecp256k1 takes it, we don't
+        let mut hybrid = uncompressed_bytes.clone();
+        hybrid[0] = 0x07;
+        assert!(BitcoinPublicKey::from_slice(&hybrid).is_ok());
+        assert!(parse_public_key(&hybrid).is_err());
+
+        // truncated
+        assert!(parse_public_key(&compressed_bytes[0..32]).is_err());
+        assert!(parse_public_key(&uncompressed_bytes[0..64]).is_err());
+        assert!(parse_public_key(&[]).is_err());
+
+        // not on the curve
+        let mut off_curve = vec![0x02];
+        off_curve.extend_from_slice(&[0u8; 31]);
+        off_curve.push(0x05);
+        assert!(parse_public_key(&off_curve).is_err());
+    }
+
+    #[test]
+    fn test_verify_batch() {
+        let mut checks = vec![];
+        for i in 0..256u32 {
+            let privkey = Secp256k1PrivateKey::new();
+            let hash = Sha256Sum::from_data(&i.to_be_bytes());
+            let sig = privkey
+                .sign(hash.as_bytes())
+                .unwrap()
+                .to_secp256k1_recoverable()
+                .unwrap()
+                .to_standard()
+                .serialize_der()
+                .to_vec();
+            checks.push((
+                BitcoinPublicKey::from_private(&privkey),
+                hash.as_bytes().to_vec(),
+                sig,
+            ));
+        }
+
+        assert_eq!(verify_batch(&checks, 1), Vec::<usize>::new());
+        assert_eq!(verify_batch(&checks, 4), Vec::<usize>::new());
+
+        // signature over the wrong hash
+        checks[100].1 = checks[101].1.clone();
+        assert_eq!(verify_batch(&checks, 1), vec![100]);
+        assert_eq!(verify_batch(&checks, 4), vec![100]);
+
+        // undecodable signature
+        checks[200].2 = vec![0x30, 0x00];
+        assert_eq!(verify_batch(&checks, 1), vec![100, 200]);
+        assert_eq!(verify_batch(&checks, 4), vec![100, 200]);
+
+        assert_eq!(verify_batch(&[], 4), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_verify_schnorr() {
+        // BIP340 test vectors: public key, message, signature, and whether or not the signature
+        // verifies (None if the public key is invalid)
+        let vectors: Vec<(&str, &str, &str, Option<bool>)> = vec![
+            (
+                "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "e907831f80848d1069a5371b402410364bdf1c5f8307b0084c55f1ce2dca821525f66a4a85ea8b71e482a74f382d2ce5ebeee8fdb2172f477df4900d310536c0",
+                Some(true),
+            ),
+            (
+                "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de33418906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a",
+                Some(true),
+            ),
+            (
+                "d69c3509bb99e412e68b0fe8544e72837dfa30746d8be2aa65975f29d22dc7b9",
+                "4df3c3f68fcc83b27e9d42c90431a72499f17875c81a599b566c9889b9696703",
+                "00000000000000000000003b78ce563f89a0ed9414f5aa28ad0d96d6795f9c6376afb1548af603b3eb45c9f8207dee1060cb71c04e80f593060b07d28308d7f4",
+                Some(true),
+            ),
+            // public key not on the curve
+            (
+                "eefdea4cdb677750a420fee807eacf21eb9898ae79b9768766e4faa04a2d4a34",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "6cff5c3ba86c69ea4b7376f31a9bcb4f74c1976089b2d9963da2e5543e17776969e89b4c5564d00349106b8497785dd7d1d713a8ae82b32fa79d5f7fc407d39b",
+                None,
+            ),
+            // R has an odd y coordinate
+            (
+                "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a14602975563cc27944640ac607cd107ae10923d9ef7a73c643e166be5ebeafa34b1ac553e2",
+                Some(false),
+            ),
+            // negated message
+            (
+                "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "1fa62e331edbc21c394792d2ab1100a7b432b013df3f6ff4f99fcb33e0e1515f28890b3edb6e7189b630448b515ce4f8622a954cfe545735aaea5134fccdb2bd",
+                Some(false),
+            ),
+            // negated s
+            (
+                "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "6cff5c3ba86c69ea4b7376f31a9bcb4f74c1976089b2d9963da2e5543e177769961764b3aa9b2ffcb6ef947b6887a226e8d7c93e00c5ed0c1834ff0d0c2e6da6",
+                Some(false),
+            ),
+            // sG - eP is infinite (x(inf) = 0)
+            (
+                "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "0000000000000000000000000000000000000000000000000000000000000000123dda8328af9c23a94c1feecfd123ba4fb73476f0d594dcb65c6425bd186051",
+                Some(false),
+            ),
+            // sG - eP is infinite (x(inf) = 1)
+            (
+                "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "00000000000000000000000000000000000000000000000000000000000000017615fbaf5ae28864013c099742deadb4dba87f11ac6754f93780d5a1837cf197",
+                Some(false),
+            ),
+            // r is not the x coordinate of a point on the curve
+            (
+                "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "4a298dacae57395a15d0795ddbfd1dcb564da82b0f269bc70a74f8220429ba1d69e89b4c5564d00349106b8497785dd7d1d713a8ae82b32fa79d5f7fc407d39b",
+                Some(false),
+            ),
+            // r is the field size
+            (
+                "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f69e89b4c5564d00349106b8497785dd7d1d713a8ae82b32fa79d5f7fc407d39b",
+                Some(false),
+            ),
+            // s is the curve order
+            (
+                "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "6cff5c3ba86c69ea4b7376f31a9bcb4f74c1976089b2d9963da2e5543e177769fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+                Some(false),
+            ),
+            // public key exceeds the field size
+            (
+                "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc30",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "6cff5c3ba86c69ea4b7376f31a9bcb4f74c1976089b2d9963da2e5543e17776969e89b4c5564d00349106b8497785dd7d1d713a8ae82b32fa79d5f7fc407d39b",
+                None,
+            ),
+        ];
+
+        for (pubkey_hex, msg_hex, sig_hex, expected) in vectors.into_iter() {
+            let pubkey = hex_bytes(pubkey_hex).unwrap();
+            let mut msg = [0u8; 32];
+            msg.copy_from_slice(&hex_bytes(msg_hex).unwrap());
+            let mut sig = [0u8; 64];
+            sig.copy_from_slice(&hex_bytes(sig_hex).unwrap());
+            match (verify_schnorr(&pubkey, &msg, &sig), expected) {
+                (Ok(valid), Some(expected_valid)) if valid == expected_valid => {}
+                (Err(btc_error::InvalidByteSequence), None) => {}
+                (res, _) => panic!(
+                    "BIP340 vector with public key {}, signature {}: expected {:?}, got {:?}",
+                    pubkey_hex, sig_hex, expected, res
+                ),
+            }
+        }
+
+        // x-only public keys must be exactly 32 bytes
+        let pubkey =
+            hex_bytes("dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659").unwrap();
+        let mut msg = [0u8; 32];
+        msg.copy_from_slice(
+            &hex_bytes("243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89").unwrap(),
+        );
+        let mut sig = [0u8; 64];
+        sig.copy_from_slice(&hex_bytes("6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de33418906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a").unwrap());
+        assert!(verify_schnorr(&pubkey, &msg, &sig).unwrap());
+
+        let mut compressed = vec![0x02];
+        compressed.extend_from_slice(&pubkey);
+        for bad_pubkey in [&pubkey[..31], &compressed[..], &[][..]].iter() {
+            match verify_schnorr(bad_pubkey, &msg, &sig) {
+                Err(btc_error::InvalidByteSequence) => {}
+                res => panic!("Expected InvalidByteSequence, got {:?}", res),
+            }
+        }
+    }
+}
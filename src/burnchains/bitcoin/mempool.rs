@@ -0,0 +1,174 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Burnchain transactions in the peer's mempool.
+//!
+//! When the indexer watches the mempool, it asks the peer for its mempool after each handshake
+//! (and has the peer relay new transactions to it), fetches every transaction the peer
+//! announces, and picks out the burnchain transactions with the same logic used on blocks.
+//! These are kept apart from confirmed burnchain transactions as `UnconfirmedBitcoinTx`s, until
+//! they confirm or get too old.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use stacks_common::deps_common::bitcoin::blockdata::transaction::Transaction;
+use stacks_common::deps_common::bitcoin::network::message_blockdata::{InvType, Inventory};
+use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+
+use crate::burnchains::bitcoin::blocks::BitcoinBlockParser;
+use crate::burnchains::bitcoin::{BitcoinBlock, BitcoinNetworkType, BitcoinTransaction};
+use crate::burnchains::{MagicBytes, Txid};
+use crate::core::StacksEpochId;
+
+/// A burnchain transaction seen in the peer's mempool, which is not in a block yet.  It may
+/// never be.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnconfirmedBitcoinTx {
+    /// The transaction, parsed as if it were in the next block.  Its `vtxindex` is meaningless
+    /// (always 0).
+    pub tx: BitcoinTransaction,
+    /// When we first saw it, in seconds since the epoch
+    pub first_seen: u64,
+}
+
+/// Tracks the transactions in the peer's mempool, and the burnchain transactions among them
+#[derive(Debug)]
+pub struct MempoolWatcher {
+    network_id: BitcoinNetworkType,
+    magic_bytes: MagicBytes,
+    /// How long to keep a transaction that hasn't confirmed
+    ttl: Duration,
+    /// Transactions we've asked the peer for, and haven't gotten yet
+    requested: HashSet<Sha256dHash>,
+    /// Every transaction we've gotten (burnchain transaction or not), and when, so we don't ask
+    /// for it again
+    seen: HashMap<Sha256dHash, u64>,
+    /// The burnchain transactions we've gotten that haven't confirmed yet
+    unconfirmed: HashMap<Txid, UnconfirmedBitcoinTx>,
+}
+
+impl MempoolWatcher {
+    pub fn new(
+        network_id: BitcoinNetworkType,
+        magic_bytes: MagicBytes,
+        ttl: Duration,
+    ) -> MempoolWatcher {
+        MempoolWatcher {
+            network_id,
+            magic_bytes,
+            ttl,
+            requested: HashSet::new(),
+            seen: HashMap::new(),
+            unconfirmed: HashMap::new(),
+        }
+    }
+
+    /// Take an `inv` from the peer, and get the IDs of the transactions in it that we should ask
+    /// for.  Those are considered requested until they arrive or the peer says it doesn't have
+    /// them.  Other inventory types are ignored.
+    pub fn handle_inv(&mut self, invs: &[Inventory]) -> Vec<Sha256dHash> {
+        let mut wanted = vec![];
+        for inv in invs.iter() {
+            match inv.inv_type {
+                InvType::Transaction | InvType::WitnessTransaction => {}
+                _ => continue,
+            }
+            if self.seen.contains_key(&inv.hash) || self.requested.contains(&inv.hash) {
+                continue;
+            }
+            self.requested.insert(inv.hash);
+            wanted.push(inv.hash);
+        }
+        wanted
+    }
+
+    /// Take a `notfound` from the peer:  it no longer has these transactions, so stop waiting
+    /// for them
+    pub fn handle_notfound(&mut self, invs: &[Inventory]) {
+        for inv in invs.iter() {
+            self.requested.remove(&inv.hash);
+        }
+    }
+
+    /// Take a transaction from the peer, and parse it as a burnchain transaction of the given
+    /// epoch.  Returns the unconfirmed burnchain transaction if it is one we haven't seen yet.
+    pub fn handle_tx(
+        &mut self,
+        tx: &Transaction,
+        epoch_id: StacksEpochId,
+        now: u64,
+    ) -> Option<UnconfirmedBitcoinTx> {
+        let txid = tx.txid();
+        self.requested.remove(&txid);
+        if self.seen.contains_key(&txid) {
+            return None;
+        }
+        self.seen.insert(txid, now);
+
+        let parser = BitcoinBlockParser::new(self.network_id, self.magic_bytes);
+        let bitcoin_tx = parser.parse_tx(tx, 0, epoch_id)?;
+        debug!(
+            "Unconfirmed burnchain transaction {} (opcode {})",
+            &bitcoin_tx.txid, bitcoin_tx.opcode
+        );
+        let unconfirmed_tx = UnconfirmedBitcoinTx {
+            tx: bitcoin_tx,
+            first_seen: now,
+        };
+        self.unconfirmed
+            .insert(unconfirmed_tx.tx.txid, unconfirmed_tx.clone());
+        Some(unconfirmed_tx)
+    }
+
+    /// Have we asked the peer for transactions that haven't arrived yet?
+    pub fn has_requests(&self) -> bool {
+        !self.requested.is_empty()
+    }
+
+    /// Drop the unconfirmed transactions that a block confirms.  They're still remembered as
+    /// seen, so if the peer announces them again, they aren't fetched.
+    pub fn confirm_block(&mut self, block: &BitcoinBlock) {
+        for tx in block.txs.iter() {
+            if self.unconfirmed.remove(&tx.txid).is_some() {
+                debug!(
+                    "Burnchain transaction {} confirmed in block {}",
+                    &tx.txid, block.block_height
+                );
+            }
+        }
+    }
+
+    /// Forget the transactions first seen more than the TTL before `now`
+    pub fn expire(&mut self, now: u64) {
+        let ttl = self.ttl.as_secs();
+        self.unconfirmed
+            .retain(|_, tx| now.saturating_sub(tx.first_seen) < ttl);
+        self.seen
+            .retain(|_, first_seen| now.saturating_sub(*first_seen) < ttl);
+    }
+
+    /// Get the unconfirmed burnchain transactions, oldest first
+    pub fn get_unconfirmed(&self) -> Vec<UnconfirmedBitcoinTx> {
+        let mut txs: Vec<_> = self.unconfirmed.values().cloned().collect();
+        txs.sort_by(|a, b| {
+            a.first_seen
+                .cmp(&b.first_seen)
+                .then_with(|| a.tx.txid.cmp(&b.tx.txid))
+        });
+        txs
+    }
+}
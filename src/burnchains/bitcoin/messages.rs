@@ -14,10 +14,29 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::io;
+use std::io::{Cursor, Read};
+use std::time::Duration;
+
+use stacks_common::deps_common::bitcoin::network::encodable::ConsensusDecodable;
+use stacks_common::deps_common::bitcoin::network::message::{CommandString, RawNetworkMessage};
+use stacks_common::deps_common::bitcoin::network::serialize::{
+    deserialize, Error as btc_serialize_error, RawDecoder,
+};
+
 use crate::burnchains::bitcoin::indexer::BitcoinIndexer;
 use crate::burnchains::bitcoin::Error as btc_error;
 use crate::burnchains::bitcoin::PeerMessage;
 
+/// Size of a message's header:  magic, command, payload length, and payload checksum
+pub const MESSAGE_HEADER_SIZE: usize = 24;
+/// Largest payload we accept for any message but a block.  This is Bitcoin Core's own limit on
+/// every message, and well over the largest non-block message it sends (2000 headers).
+pub const DEFAULT_MAX_MESSAGE_SIZE: u32 = 4_000_000;
+/// Largest `block` payload we accept:  twice the most a block can take up under the 4,000,000
+/// weight unit consensus limit
+pub const DEFAULT_MAX_BLOCK_SIZE: u32 = 8_000_000;
+
 pub trait BitcoinMessageHandler {
     fn begin_session(&mut self, indexer: &mut BitcoinIndexer) -> Result<bool, btc_error>;
     fn handle_message(
@@ -25,4 +44,140 @@ pub trait BitcoinMessageHandler {
         indexer: &mut BitcoinIndexer,
         msg: PeerMessage,
     ) -> Result<bool, btc_error>;
+
+    /// How long to wait for a message before calling `handle_idle()`.  If None, we wait as long
+    /// as the socket's read timeout allows.
+    fn idle_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Called when no message has arrived within `idle_timeout()`.  Returns whether or not to
+    /// keep going; errors are handled like errors receiving a message.
+    fn handle_idle(&mut self, _indexer: &mut BitcoinIndexer) -> Result<bool, btc_error> {
+        Ok(true)
+    }
+}
+
+/// Read the next message off of `reader`.  Its header is read and checked first, so a message
+/// that claims a payload over `max_block_size` (for a `block`) or `max_message_size` (for
+/// anything else) is refused with OversizedMessage before any room is made for the payload.
+/// The payload is left unread, so the connection can't be used any more.
+pub fn read_message<R: Read>(
+    reader: &mut R,
+    max_message_size: u32,
+    max_block_size: u32,
+) -> Result<RawNetworkMessage, btc_error> {
+    let mut header = [0u8; MESSAGE_HEADER_SIZE];
+    reader
+        .read_exact(&mut header)
+        .map_err(|e| decode_error(btc_serialize_error::Io(e)))?;
+
+    let CommandString(command) = deserialize(&header[4..16])?;
+    let length = u32::from_le_bytes([header[16], header[17], header[18], header[19]]);
+    let max_size = if command == "block" {
+        max_block_size
+    } else {
+        max_message_size
+    };
+    if length > max_size {
+        return Err(btc_error::OversizedMessage(command, length));
+    }
+
+    let mut decoder = RawDecoder::new(Cursor::new(&header[..]).chain(reader));
+    ConsensusDecodable::consensus_decode(&mut decoder).map_err(decode_error)
+}
+
+/// Translate an error decoding a message into ours.  If we can't finish a read, then the
+/// connection is broken.
+fn decode_error(e: btc_serialize_error) -> btc_error {
+    match e {
+        btc_serialize_error::Io(ref io_error) => {
+            if io_error.kind() == io::ErrorKind::UnexpectedEof {
+                btc_error::ConnectionBroken
+            } else {
+                btc_error::Io(io::Error::new(
+                    io_error.kind(),
+                    "I/O error when processing message",
+                ))
+            }
+        }
+        _ => btc_error::SerializationError(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use stacks_common::deps_common::bitcoin::blockdata::constants::genesis_block;
+    use stacks_common::deps_common::bitcoin::network::constants::Network;
+    use stacks_common::deps_common::bitcoin::network::message::NetworkMessage;
+    use stacks_common::deps_common::bitcoin::network::serialize::serialize;
+
+    fn message_bytes(payload: NetworkMessage) -> Vec<u8> {
+        serialize(&RawNetworkMessage {
+            magic: Network::Regtest.magic(),
+            payload,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_read_message_size_limits() {
+        // messages within the limits are read as usual
+        let ping = message_bytes(NetworkMessage::Ping(1234));
+        let msg = read_message(&mut Cursor::new(ping.clone()), 8, 8).unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Ping(1234));
+
+        // ...and ones over them are not
+        match read_message(&mut Cursor::new(ping), 7, 8).map(|msg| msg.payload) {
+            Err(btc_error::OversizedMessage(command, length)) => {
+                assert_eq!(command, "ping");
+                assert_eq!(length, 8);
+            }
+            x => panic!("Expected OversizedMessage, got {:?}", x),
+        }
+
+        // a block has a limit of its own
+        let block = message_bytes(NetworkMessage::Block(genesis_block(Network::Regtest)));
+        let block_size = (block.len() - MESSAGE_HEADER_SIZE) as u32;
+        assert!(read_message(&mut Cursor::new(block.clone()), 8, block_size).is_ok());
+        match read_message(&mut Cursor::new(block), block_size, block_size - 1)
+            .map(|msg| msg.payload)
+        {
+            Err(btc_error::OversizedMessage(command, length)) => {
+                assert_eq!(command, "block");
+                assert_eq!(length, block_size);
+            }
+            x => panic!("Expected OversizedMessage, got {:?}", x),
+        }
+
+        // a header that claims an absurd payload is refused before the payload is read, or
+        // room is made for it
+        let mut bytes = message_bytes(NetworkMessage::Verack);
+        bytes[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[0u8; 1000]);
+        let mut reader = Cursor::new(bytes);
+        match read_message(
+            &mut reader,
+            DEFAULT_MAX_MESSAGE_SIZE,
+            DEFAULT_MAX_BLOCK_SIZE,
+        )
+        .map(|msg| msg.payload)
+        {
+            Err(btc_error::OversizedMessage(command, length)) => {
+                assert_eq!(command, "verack");
+                assert_eq!(length, u32::MAX);
+            }
+            x => panic!("Expected OversizedMessage, got {:?}", x),
+        }
+        assert_eq!(reader.position() as usize, MESSAGE_HEADER_SIZE);
+
+        // a message cut short means the connection broke
+        let ping = message_bytes(NetworkMessage::Ping(1234));
+        match read_message(&mut Cursor::new(ping[..28].to_vec()), 8, 8).map(|msg| msg.payload) {
+            Err(btc_error::ConnectionBroken) => {}
+            x => panic!("Expected ConnectionBroken, got {:?}", x),
+        }
+    }
 }
@@ -36,11 +36,18 @@ use crate::types::chainstate::BurnchainHeaderHash;
 pub mod address;
 pub mod bits;
 pub mod blocks;
+pub mod bloom;
+pub mod filters;
 pub mod indexer;
 pub mod keys;
+pub mod mempool;
 pub mod messages;
 pub mod network;
+pub mod rpc;
+pub mod snapshot;
+pub mod socks;
 pub mod spv;
+pub mod task;
 
 pub type PeerMessage = stacks_common::deps_common::bitcoin::network::message::NetworkMessage;
 
@@ -83,12 +90,67 @@ pub enum Error {
     InvalidChainWork,
     /// Wrong number of bytes for constructing an address
     InvalidByteSequence,
+    /// Base58check-encoded data (e.g. an address) does not match its checksum
+    InvalidChecksum,
     /// Configuration error
     ConfigError(String),
     /// Tried to synchronize to a point above the chain tip
     BlockchainHeight,
     /// Request timed out
     TimedOut,
+    /// Data (e.g. an address) belongs to a different Bitcoin network than the one configured
+    WrongNetwork,
+    /// Header does not match the checkpointed block hash at its height
+    CheckpointMismatch(u64),
+    /// Header snapshot file is truncated or corrupt
+    InvalidHeaderSnapshot(String),
+    /// Compact block filter could not be decoded
+    InvalidFilter(String),
+    /// Compact block filter (header) does not match what was committed to at a given height
+    FilterHeaderMismatch(u64),
+    /// Header at the assume-valid height does not have the expected hash
+    AssumeValidMismatch(u64),
+    /// Peer's protocol version or services don't let us sync from it
+    IncompatiblePeer(String),
+    /// Stored headers are corrupt below the tip, at the given height, and must be resynced
+    CorruptHeaders(u64),
+    /// Header at the given height is not timestamped after the median of the 11 headers before it
+    TimestampTooOld(u64),
+    /// Header at the given height is timestamped too far ahead of our clock
+    TimestampTooNew(u64),
+    /// bitcoind's JSON-RPC interface returned an error or a malformed reply
+    RpcError(String),
+    /// A segwit input's public key or redeem script does not hash to the program it spends
+    WitnessProgramMismatch,
+    /// Header at the given height has a version that its network's buried soft forks obsolete
+    ObsoleteHeaderVersion(u64, u32),
+    /// Transaction has a nonstandard or malformed OP_RETURN output
+    MalformedOpReturn(String),
+    /// Gave up waiting on the peer because a shutdown was requested
+    ShutdownRequested,
+    /// bitcoind's JSON-RPC interface does not have a block because it pruned it
+    BlockPruned(String),
+    /// Peer does not have a block we need, e.g. because it's pruned, and no other peer has it
+    PrunedPeer(String),
+    /// Gave up reconnecting to the peer after this many failed attempts in a row
+    TooManyReconnects(u32),
+    /// The SOCKS5 proxy could not be reached, or would not connect us to anything
+    ProxyError(String),
+    /// Fast-bootstrap snapshot is truncated, corrupt, or doesn't match its manifest
+    InvalidSnapshot(String),
+    /// Filtered block (`merkleblock`) has a malformed partial merkle tree, or one that doesn't
+    /// match its header
+    InvalidMerkleBlock(String),
+    /// Merkle proof of a transaction's inclusion in a block is malformed
+    InvalidMerkleProof(String),
+    /// Header at the first burn block height does not have the configured first block hash
+    FirstBlockMismatch(u64),
+    /// Peer sent a message whose header claims a payload over our limit:  (command, claimed
+    /// payload length)
+    OversizedMessage(String, u32),
+    /// Refused to replace the headers above the given fork height, because the reorg would take
+    /// more stored burnchain blocks off the canonical chain than `max_reorg_depth` allows
+    DeepReorg(u64),
 }
 
 impl fmt::Display for Error {
@@ -111,9 +173,77 @@ impl fmt::Display for Error {
             Error::InvalidPoW => write!(f, "Invalid proof of work"),
             Error::InvalidChainWork => write!(f, "Chain difficulty cannot decrease"),
             Error::InvalidByteSequence => write!(f, "Invalid sequence of bytes"),
+            Error::InvalidChecksum => write!(f, "Invalid base58check checksum"),
             Error::ConfigError(ref e_str) => fmt::Display::fmt(e_str, f),
             Error::BlockchainHeight => write!(f, "Value is beyond the end of the blockchain"),
             Error::TimedOut => write!(f, "Request timed out"),
+            Error::WrongNetwork => write!(f, "Data is for a different Bitcoin network"),
+            Error::CheckpointMismatch(ref height) => {
+                write!(f, "Header does not match checkpoint at height {}", height)
+            }
+            Error::InvalidHeaderSnapshot(ref e_str) => {
+                write!(f, "Invalid header snapshot: {}", e_str)
+            }
+            Error::InvalidFilter(ref e_str) => write!(f, "Invalid compact filter: {}", e_str),
+            Error::FilterHeaderMismatch(ref height) => {
+                write!(f, "Filter header mismatch at height {}", height)
+            }
+            Error::AssumeValidMismatch(ref height) => write!(
+                f,
+                "Header at assume-valid height {} does not have the expected hash",
+                height
+            ),
+            Error::IncompatiblePeer(ref e_str) => write!(f, "Incompatible peer: {}", e_str),
+            Error::CorruptHeaders(ref height) => write!(
+                f,
+                "Stored headers are corrupt at height {}; remove them and resync",
+                height
+            ),
+            Error::TimestampTooOld(ref height) => write!(
+                f,
+                "Header {} is not timestamped after the median time of the headers before it",
+                height
+            ),
+            Error::TimestampTooNew(ref height) => {
+                write!(f, "Header {} is timestamped too far in the future", height)
+            }
+            Error::RpcError(ref e_str) => write!(f, "Bitcoin RPC error: {}", e_str),
+            Error::WitnessProgramMismatch => {
+                write!(f, "Witness does not match the spent witness program")
+            }
+            Error::ObsoleteHeaderVersion(ref height, ref version) => write!(
+                f,
+                "Header at height {} has obsolete version {}",
+                height, version
+            ),
+            Error::MalformedOpReturn(ref e_str) => write!(f, "Malformed OP_RETURN: {}", e_str),
+            Error::ShutdownRequested => write!(f, "Shutdown requested"),
+            Error::BlockPruned(ref e_str) => {
+                write!(f, "Block pruned by the bitcoin node: {}", e_str)
+            }
+            Error::PrunedPeer(ref e_str) => write!(f, "Peer lacks a block we need: {}", e_str),
+            Error::TooManyReconnects(attempts) => {
+                write!(f, "Gave up reconnecting after {} attempts", attempts)
+            }
+            Error::ProxyError(ref e_str) => write!(f, "SOCKS5 proxy error: {}", e_str),
+            Error::InvalidSnapshot(ref e_str) => write!(f, "Invalid snapshot: {}", e_str),
+            Error::InvalidMerkleBlock(ref e_str) => write!(f, "Invalid merkleblock: {}", e_str),
+            Error::InvalidMerkleProof(ref e_str) => write!(f, "Invalid merkle proof: {}", e_str),
+            Error::FirstBlockMismatch(ref height) => write!(
+                f,
+                "Header at first block height {} does not have the configured hash",
+                height
+            ),
+            Error::OversizedMessage(ref command, ref length) => write!(
+                f,
+                "Peer sent a {} message of {} bytes, over our limit",
+                command, length
+            ),
+            Error::DeepReorg(ref height) => write!(
+                f,
+                "Refused to reorg headers above height {}: the reorg is too deep",
+                height
+            ),
         }
     }
 }
@@ -138,24 +268,246 @@ impl error::Error for Error {
             Error::InvalidPoW => None,
             Error::InvalidChainWork => None,
             Error::InvalidByteSequence => None,
+            Error::InvalidChecksum => None,
             Error::ConfigError(ref _e_str) => None,
             Error::BlockchainHeight => None,
             Error::TimedOut => None,
+            Error::WrongNetwork => None,
+            Error::CheckpointMismatch(..) => None,
+            Error::InvalidHeaderSnapshot(..) => None,
+            Error::InvalidFilter(..) => None,
+            Error::FilterHeaderMismatch(..) => None,
+            Error::AssumeValidMismatch(..) => None,
+            Error::IncompatiblePeer(..) => None,
+            Error::CorruptHeaders(..) => None,
+            Error::TimestampTooOld(..) => None,
+            Error::TimestampTooNew(..) => None,
+            Error::RpcError(..) => None,
+            Error::WitnessProgramMismatch => None,
+            Error::ObsoleteHeaderVersion(..) => None,
+            Error::MalformedOpReturn(..) => None,
+            Error::ShutdownRequested => None,
+            Error::BlockPruned(..) => None,
+            Error::PrunedPeer(..) => None,
+            Error::TooManyReconnects(..) => None,
+            Error::ProxyError(..) => None,
+            Error::InvalidSnapshot(..) => None,
+            Error::InvalidMerkleBlock(..) => None,
+            Error::InvalidMerkleProof(..) => None,
+            Error::FirstBlockMismatch(..) => None,
+            Error::OversizedMessage(..) => None,
+            Error::DeepReorg(..) => None,
         }
     }
 }
 
+impl Error {
+    /// Get a stable, machine-readable identifier for the kind of error this is (e.g. for
+    /// monitoring).  Identifiers never change once assigned.
+    pub fn error_code(&self) -> &'static str {
+        match *self {
+            Error::Io(..) => "io",
+            Error::SocketNotConnectedToPeer => "socket_not_connected_to_peer",
+            Error::SerializationError(..) => "serialization_error",
+            Error::InvalidMessage(..) => "invalid_message",
+            Error::InvalidReply => "invalid_reply",
+            Error::InvalidMagic => "invalid_magic",
+            Error::UnhandledMessage(..) => "unhandled_message",
+            Error::ConnectionBroken => "connection_broken",
+            Error::ConnectionError => "connection_error",
+            Error::FilesystemError(..) => "filesystem_error",
+            Error::DBError(..) => "db_error",
+            Error::HashError(..) => "hash_error",
+            Error::NoncontiguousHeader => "noncontiguous_header",
+            Error::MissingHeader => "missing_header",
+            Error::InvalidPoW => "invalid_pow",
+            Error::InvalidChainWork => "invalid_chain_work",
+            Error::InvalidByteSequence => "invalid_byte_sequence",
+            Error::InvalidChecksum => "invalid_checksum",
+            Error::ConfigError(..) => "config_error",
+            Error::BlockchainHeight => "blockchain_height",
+            Error::TimedOut => "timed_out",
+            Error::WrongNetwork => "wrong_network",
+            Error::CheckpointMismatch(..) => "checkpoint_mismatch",
+            Error::InvalidHeaderSnapshot(..) => "invalid_header_snapshot",
+            Error::InvalidFilter(..) => "invalid_filter",
+            Error::FilterHeaderMismatch(..) => "filter_header_mismatch",
+            Error::AssumeValidMismatch(..) => "assume_valid_mismatch",
+            Error::IncompatiblePeer(..) => "incompatible_peer",
+            Error::CorruptHeaders(..) => "corrupt_headers",
+            Error::TimestampTooOld(..) => "timestamp_too_old",
+            Error::TimestampTooNew(..) => "timestamp_too_new",
+            Error::RpcError(..) => "rpc_error",
+            Error::WitnessProgramMismatch => "witness_program_mismatch",
+            Error::ObsoleteHeaderVersion(..) => "obsolete_header_version",
+            Error::MalformedOpReturn(..) => "malformed_op_return",
+            Error::ShutdownRequested => "shutdown_requested",
+            Error::BlockPruned(..) => "block_pruned",
+            Error::PrunedPeer(..) => "pruned_peer",
+            Error::TooManyReconnects(..) => "too_many_reconnects",
+            Error::ProxyError(..) => "proxy_error",
+            Error::InvalidSnapshot(..) => "invalid_snapshot",
+            Error::InvalidMerkleBlock(..) => "invalid_merkle_block",
+            Error::InvalidMerkleProof(..) => "invalid_merkle_proof",
+            Error::FirstBlockMismatch(..) => "first_block_mismatch",
+            Error::OversizedMessage(..) => "oversized_message",
+            Error::DeepReorg(..) => "deep_reorg",
+        }
+    }
+
+    /// Is this error worth retrying?  Transient errors come from the connection to the bitcoin
+    /// node, and may go away on their own.  Everything else -- bad data from the node, our own
+    /// misconfiguration, a broken headers DB -- will happen again if we retry.
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            Error::Io(..)
+            | Error::SocketNotConnectedToPeer
+            | Error::ConnectionBroken
+            | Error::ConnectionError
+            | Error::TimedOut
+            | Error::ProxyError(..) => true,
+            Error::SerializationError(..)
+            | Error::InvalidMessage(..)
+            | Error::InvalidReply
+            | Error::InvalidMagic
+            | Error::UnhandledMessage(..)
+            | Error::FilesystemError(..)
+            | Error::DBError(..)
+            | Error::HashError(..)
+            | Error::NoncontiguousHeader
+            | Error::MissingHeader
+            | Error::InvalidPoW
+            | Error::InvalidChainWork
+            | Error::InvalidByteSequence
+            | Error::InvalidChecksum
+            | Error::ConfigError(..)
+            | Error::BlockchainHeight
+            | Error::WrongNetwork
+            | Error::CheckpointMismatch(..)
+            | Error::InvalidHeaderSnapshot(..)
+            | Error::InvalidFilter(..)
+            | Error::FilterHeaderMismatch(..)
+            | Error::AssumeValidMismatch(..)
+            | Error::IncompatiblePeer(..)
+            | Error::CorruptHeaders(..)
+            | Error::TimestampTooOld(..)
+            | Error::TimestampTooNew(..)
+            | Error::RpcError(..)
+            | Error::WitnessProgramMismatch
+            | Error::ObsoleteHeaderVersion(..)
+            | Error::MalformedOpReturn(..)
+            | Error::ShutdownRequested
+            | Error::BlockPruned(..)
+            | Error::PrunedPeer(..)
+            | Error::TooManyReconnects(..)
+            | Error::InvalidSnapshot(..)
+            | Error::InvalidMerkleBlock(..)
+            | Error::InvalidMerkleProof(..)
+            | Error::FirstBlockMismatch(..)
+            | Error::OversizedMessage(..)
+            | Error::DeepReorg(..) => false,
+        }
+    }
+
+    /// Did we lose our connection to the peer, e.g. because it restarted?  Nothing's wrong with
+    /// the peer or with us, so we can just reconnect and carry on.
+    pub fn is_connection_lost(&self) -> bool {
+        let io_error = match self {
+            Error::ConnectionBroken | Error::SocketNotConnectedToPeer => return true,
+            Error::Io(e) => e,
+            Error::SerializationError(btc_serialize_error::Io(e)) => e,
+            _ => return false,
+        };
+        match io_error.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotConnected => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<btc_serialize_error> for Error {
+    fn from(e: btc_serialize_error) -> Error {
+        Error::SerializationError(e)
+    }
+}
+
+impl From<btc_hex_error> for Error {
+    fn from(e: btc_hex_error) -> Error {
+        Error::HashError(e)
+    }
+}
+
 impl From<db_error> for Error {
     fn from(e: db_error) -> Error {
         Error::DBError(e)
     }
 }
 
+/// Which peer connection an indexer operation was on, how far our headers had synced, and
+/// which of our requests to the peer it was part of (see `BitcoinIndexer::log_context()`).
+/// Attached to log lines and errors, so that those of different peers can be told apart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerContext {
+    pub peer_host: String,
+    pub peer_port: u16,
+    /// Height of our highest header
+    pub height: u64,
+    /// Which `BitcoinIndexer::peer_communicate()` call this was, counting from 1 (or 0 if
+    /// there hasn't been one yet)
+    pub request_id: u64,
+}
+
+impl fmt::Display for PeerContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", &self.peer_host, self.peer_port)
+    }
+}
+
+/// An `Error` from talking to a peer, and the context it happened in
+#[derive(Debug)]
+pub struct PeerError {
+    pub context: PeerContext,
+    pub error: Error,
+}
+
+impl fmt::Display for PeerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (peer {}, height {}, request {})",
+            &self.error, &self.context, self.context.height, self.context.request_id
+        )
+    }
+}
+
+impl error::Error for PeerError {
+    fn cause(&self) -> Option<&dyn error::Error> {
+        Some(&self.error)
+    }
+}
+
+impl From<PeerError> for Error {
+    fn from(e: PeerError) -> Error {
+        e.error
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BitcoinNetworkType {
     Mainnet,
     Testnet,
     Regtest,
+    Signet,
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Serialize, Deserialize)]
@@ -171,6 +523,14 @@ pub enum BitcoinInputType {
     SegwitP2SH,
 }
 
+/// An m-of-n multisig script: OP_m <pubkey1> ... <pubkey_n> OP_n OP_CHECKMULTISIG.
+/// This is either a bare multisig scriptPubKey, or the redeem script of a p2sh or p2wsh input.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct BitcoinMultisigScript {
+    pub num_required: usize,
+    pub keys: Vec<BitcoinPublicKey>,
+}
+
 /// Bitcoin tx input we can parse in 2.05 and earlier.
 /// In 2.05 and earlier, we cared about being able to parse a scriptSig and witness.
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -246,3 +606,155 @@ impl BitcoinBlock {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use stacks_common::deps_common::bitcoin::network::message::NetworkMessage;
+
+    #[test]
+    fn test_error_codes() {
+        // every variant, with its code and whether or not it's transient
+        let errors = vec![
+            (
+                Error::Io(io::Error::new(io::ErrorKind::Other, "test")),
+                "io",
+                true,
+            ),
+            (
+                Error::SocketNotConnectedToPeer,
+                "socket_not_connected_to_peer",
+                true,
+            ),
+            (
+                Error::SerializationError(btc_serialize_error::ParseFailed("test")),
+                "serialization_error",
+                false,
+            ),
+            (
+                Error::InvalidMessage(NetworkMessage::Verack),
+                "invalid_message",
+                false,
+            ),
+            (Error::InvalidReply, "invalid_reply", false),
+            (Error::InvalidMagic, "invalid_magic", false),
+            (
+                Error::UnhandledMessage(NetworkMessage::Verack),
+                "unhandled_message",
+                false,
+            ),
+            (Error::ConnectionBroken, "connection_broken", true),
+            (Error::ConnectionError, "connection_error", true),
+            (
+                Error::FilesystemError(io::Error::new(io::ErrorKind::Other, "test")),
+                "filesystem_error",
+                false,
+            ),
+            (Error::DBError(db_error::NotFoundError), "db_error", false),
+            (
+                Error::HashError(btc_hex_error::BadLength(1)),
+                "hash_error",
+                false,
+            ),
+            (Error::NoncontiguousHeader, "noncontiguous_header", false),
+            (Error::MissingHeader, "missing_header", false),
+            (Error::InvalidPoW, "invalid_pow", false),
+            (Error::InvalidChainWork, "invalid_chain_work", false),
+            (Error::InvalidByteSequence, "invalid_byte_sequence", false),
+            (Error::InvalidChecksum, "invalid_checksum", false),
+            (
+                Error::ConfigError("test".to_string()),
+                "config_error",
+                false,
+            ),
+            (Error::BlockchainHeight, "blockchain_height", false),
+            (Error::TimedOut, "timed_out", true),
+            (Error::WrongNetwork, "wrong_network", false),
+            (Error::CheckpointMismatch(1), "checkpoint_mismatch", false),
+            (
+                Error::InvalidHeaderSnapshot("test".to_string()),
+                "invalid_header_snapshot",
+                false,
+            ),
+            (
+                Error::InvalidFilter("test".to_string()),
+                "invalid_filter",
+                false,
+            ),
+            (
+                Error::FilterHeaderMismatch(1),
+                "filter_header_mismatch",
+                false,
+            ),
+            (
+                Error::AssumeValidMismatch(1),
+                "assume_valid_mismatch",
+                false,
+            ),
+            (
+                Error::IncompatiblePeer("test".to_string()),
+                "incompatible_peer",
+                false,
+            ),
+            (Error::CorruptHeaders(1), "corrupt_headers", false),
+            (Error::TimestampTooOld(1), "timestamp_too_old", false),
+            (Error::TimestampTooNew(1), "timestamp_too_new", false),
+            (Error::RpcError("test".to_string()), "rpc_error", false),
+            (
+                Error::WitnessProgramMismatch,
+                "witness_program_mismatch",
+                false,
+            ),
+            (
+                Error::ObsoleteHeaderVersion(1, 1),
+                "obsolete_header_version",
+                false,
+            ),
+            (
+                Error::MalformedOpReturn("test".to_string()),
+                "malformed_op_return",
+                false,
+            ),
+            (Error::ShutdownRequested, "shutdown_requested", false),
+            (
+                Error::BlockPruned("test".to_string()),
+                "block_pruned",
+                false,
+            ),
+            (Error::PrunedPeer("test".to_string()), "pruned_peer", false),
+            (Error::TooManyReconnects(3), "too_many_reconnects", false),
+            (Error::ProxyError("test".to_string()), "proxy_error", true),
+            (
+                Error::InvalidSnapshot("test".to_string()),
+                "invalid_snapshot",
+                false,
+            ),
+            (
+                Error::InvalidMerkleBlock("test".to_string()),
+                "invalid_merkle_block",
+                false,
+            ),
+            (
+                Error::InvalidMerkleProof("test".to_string()),
+                "invalid_merkle_proof",
+                false,
+            ),
+            (Error::FirstBlockMismatch(1), "first_block_mismatch", false),
+            (
+                Error::OversizedMessage("block".to_string(), u32::MAX),
+                "oversized_message",
+                false,
+            ),
+            (Error::DeepReorg(1), "deep_reorg", false),
+        ];
+
+        let mut codes = HashSet::new();
+        for (error, code, transient) in errors.iter() {
+            assert_eq!(error.error_code(), *code);
+            assert_eq!(error.is_transient(), *transient, "{:?}", error);
+            assert!(codes.insert(*code), "duplicate error code {}", code);
+        }
+    }
+}
@@ -14,82 +14,292 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cmp;
+use std::collections::VecDeque;
 use std::io;
 use std::io::Write;
 use std::net::SocketAddr;
 use std::ops::Deref;
 use std::thread;
-use std::time;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use rand::{thread_rng, Rng};
 
+use stacks_common::deps_common::bitcoin::blockdata::transaction::OutPoint;
 use stacks_common::deps_common::bitcoin::network::address as btc_network_address;
 use stacks_common::deps_common::bitcoin::network::constants as btc_constants;
-use stacks_common::deps_common::bitcoin::network::encodable::{
-    ConsensusDecodable, ConsensusEncodable,
-};
+use stacks_common::deps_common::bitcoin::network::encodable::ConsensusEncodable;
 use stacks_common::deps_common::bitcoin::network::message as btc_message;
 use stacks_common::deps_common::bitcoin::network::message_blockdata as btc_message_blockdata;
+use stacks_common::deps_common::bitcoin::network::message_bloom::FilterAdd;
+use stacks_common::deps_common::bitcoin::network::message_filter as btc_message_filter;
 use stacks_common::deps_common::bitcoin::network::message_network as btc_message_network;
-use stacks_common::deps_common::bitcoin::network::serialize as btc_serialize;
-use stacks_common::deps_common::bitcoin::network::serialize::{RawDecoder, RawEncoder};
+use stacks_common::deps_common::bitcoin::network::serialize::RawEncoder;
 
 use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
 
-use crate::burnchains::bitcoin::indexer::{network_id_to_bytes, BitcoinIndexer};
+use crate::burnchains::bitcoin::bloom::Bip37Filter;
+use crate::burnchains::bitcoin::filters::BASIC_FILTER_TYPE;
+use crate::burnchains::bitcoin::indexer::{
+    BitcoinIndexer, KeepalivePolicy, RequestLimitPolicy, SyncEvent, MIN_PEER_PROTOCOL_VERSION,
+    NODE_NETWORK_LIMITED, NODE_NETWORK_LIMITED_MIN_BLOCKS, SHUTDOWN_POLL_INTERVAL,
+};
+use crate::burnchains::bitcoin::mempool::MempoolWatcher;
+use crate::burnchains::bitcoin::messages;
 use crate::burnchains::bitcoin::messages::BitcoinMessageHandler;
 use crate::burnchains::bitcoin::Error as btc_error;
 use crate::burnchains::bitcoin::PeerMessage;
 use crate::burnchains::indexer::BurnchainIndexer;
+use crate::burnchains::Error as burnchain_error;
+use crate::core::{StacksEpoch, StacksEpochId};
 
 use stacks_common::util::get_epoch_time_secs;
 use stacks_common::util::log;
 
+/// Reads from `inner`, counting the bytes read
+struct CountingReader<'a, R: io::Read> {
+    inner: &'a mut R,
+    num_bytes: u64,
+}
+
+impl<'a, R: io::Read> io::Read for CountingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_read = self.inner.read(buf)?;
+        self.num_bytes += num_read as u64;
+        Ok(num_read)
+    }
+}
+
+/// Holds the requests (`getdata` and `getheaders`) we send a peer to a `RequestLimitPolicy`, with
+/// a token bucket for the rate and a count of the requests awaiting replies.  Requests past
+/// either limit wait their turn in a queue rather than being dropped (see
+/// `BitcoinIndexer::flush_requests()`).
+#[derive(Debug)]
+pub struct RequestLimiter {
+    policy: RequestLimitPolicy,
+    /// Requests we may send right away, as far as the rate goes
+    tokens: f64,
+    last_refill: Instant,
+    /// Requests sent but not yet (fully) answered, oldest first
+    in_flight: VecDeque<InFlightRequest>,
+    /// Requests waiting to be sent, oldest first
+    queue: VecDeque<btc_message::NetworkMessage>,
+}
+
+#[derive(Debug)]
+struct InFlightRequest {
+    sent_at: Instant,
+    /// Replies still to come:  one per item of a `getdata`, or one `headers` for a `getheaders`
+    replies_left: usize,
+    is_getheaders: bool,
+}
+
+impl RequestLimiter {
+    pub fn new(policy: RequestLimitPolicy) -> RequestLimiter {
+        let mut policy = policy;
+        // zeroes would hold every request forever
+        policy.requests_per_sec = cmp::max(policy.requests_per_sec, 1);
+        policy.burst = cmp::max(policy.burst, 1);
+        policy.max_in_flight = cmp::max(policy.max_in_flight, 1);
+        RequestLimiter {
+            tokens: policy.burst as f64,
+            last_refill: Instant::now(),
+            in_flight: VecDeque::new(),
+            queue: VecDeque::new(),
+            policy,
+        }
+    }
+
+    /// Number of requests waiting to be sent
+    pub fn num_queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Number of requests sent that the peer has yet to answer
+    pub fn num_in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// The tokens in the bucket at `now`
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        (self.tokens + elapsed * self.policy.requests_per_sec as f64).min(self.policy.burst as f64)
+    }
+
+    /// Queue `request`, leaving out the items of a `getdata` that are already queued.  Returns
+    /// false if there was nothing left to queue.
+    fn enqueue(&mut self, request: btc_message::NetworkMessage) -> bool {
+        let request = match request {
+            btc_message::NetworkMessage::GetData(invs) => {
+                let invs: Vec<_> = invs
+                    .into_iter()
+                    .filter(|inv| {
+                        !self.queue.iter().any(|queued| match queued {
+                            btc_message::NetworkMessage::GetData(queued_invs) => {
+                                queued_invs.contains(inv)
+                            }
+                            _ => false,
+                        })
+                    })
+                    .collect();
+                if invs.is_empty() {
+                    return false;
+                }
+                btc_message::NetworkMessage::GetData(invs)
+            }
+            request => request,
+        };
+        self.queue.push_back(request);
+        true
+    }
+
+    /// Take the request at the front of the queue if the limits let it out at `now`, and count
+    /// it as sent
+    fn pop_ready(&mut self, now: Instant) -> Option<btc_message::NetworkMessage> {
+        if self.queue.is_empty() || self.in_flight.len() >= self.policy.max_in_flight {
+            return None;
+        }
+        self.tokens = self.tokens_at(now);
+        self.last_refill = now;
+        if self.tokens < 1.0 {
+            return None;
+        }
+        let request = self.queue.pop_front()?;
+        self.tokens -= 1.0;
+        let (replies_left, is_getheaders) = match request {
+            btc_message::NetworkMessage::GetData(ref invs) => (invs.len(), false),
+            _ => (1, true),
+        };
+        self.in_flight.push_back(InFlightRequest {
+            sent_at: now,
+            replies_left,
+            is_getheaders,
+        });
+        Some(request)
+    }
+
+    /// How long after `now` until the request at the front of the queue may be sent, as far as
+    /// the rate goes.  None if nothing is queued, or if it's waiting on a reply instead.
+    fn next_send_delay(&self, now: Instant) -> Option<Duration> {
+        if self.queue.is_empty() || self.in_flight.len() >= self.policy.max_in_flight {
+            return None;
+        }
+        let missing = 1.0 - self.tokens_at(now);
+        if missing <= 0.0 {
+            return Some(Duration::from_secs(0));
+        }
+        Some(Duration::from_secs_f64(
+            missing / self.policy.requests_per_sec as f64,
+        ))
+    }
+
+    /// Count `msg` from the peer against the requests it answers.  The peer answers requests in
+    /// the order we sent them.
+    fn note_reply(&mut self, msg: &btc_message::NetworkMessage) {
+        let (mut num_replies, is_getheaders) = match msg {
+            btc_message::NetworkMessage::Headers(..) => (1, true),
+            btc_message::NetworkMessage::Block(..)
+            | btc_message::NetworkMessage::MerkleBlock(..)
+            | btc_message::NetworkMessage::Tx(..) => (1, false),
+            btc_message::NetworkMessage::NotFound(ref invs) => (invs.len(), false),
+            _ => return,
+        };
+        for request in self.in_flight.iter_mut() {
+            if num_replies == 0 {
+                break;
+            }
+            if request.is_getheaders == is_getheaders {
+                let answered = cmp::min(num_replies, request.replies_left);
+                request.replies_left -= answered;
+                num_replies -= answered;
+            }
+        }
+        self.in_flight.retain(|request| request.replies_left > 0);
+    }
+
+    /// Stop waiting on replies to requests sent more than `max_age` before `now` -- the peer
+    /// isn't going to answer them
+    fn expire(&mut self, now: Instant, max_age: Duration) {
+        self.in_flight
+            .retain(|request| now.saturating_duration_since(request.sent_at) < max_age);
+    }
+}
+
 // Based on Andrew Poelstra's rust-bitcoin library.
 impl BitcoinIndexer {
     /// Send a Bitcoin protocol message on the wire
     pub fn send_message(&mut self, payload: btc_message::NetworkMessage) -> Result<(), btc_error> {
         let message = btc_message::RawNetworkMessage {
-            magic: network_id_to_bytes(self.runtime.network_id),
+            magic: self.network_magic(),
             payload: payload,
         };
 
         self.with_socket(|ref mut sock| {
-            message
-                .consensus_encode(&mut RawEncoder::new(&mut *sock))
-                .map_err(btc_error::SerializationError)?;
-
-            sock.flush().map_err(btc_error::Io)
+            message.consensus_encode(&mut RawEncoder::new(&mut *sock))?;
+            sock.flush()?;
+            Ok(())
         })
     }
 
+    /// Send a request (`getdata` or `getheaders`), or queue it if `config.request_limit` doesn't
+    /// let it out yet.  Queued requests go out as soon as the limits allow, whenever we wait
+    /// on the peer (see `flush_requests()`).
+    fn send_request(&mut self, request: btc_message::NetworkMessage) -> Result<(), btc_error> {
+        match self.runtime.request_limiter.as_mut() {
+            Some(limiter) => {
+                if !limiter.enqueue(request) {
+                    debug!("Request is already queued");
+                    return Ok(());
+                }
+            }
+            None => {
+                return self.send_message(request);
+            }
+        }
+        self.flush_requests()
+    }
+
+    /// Send the queued requests that `config.request_limit` lets out now
+    pub fn flush_requests(&mut self) -> Result<(), btc_error> {
+        let max_age = self.config.read_timeout;
+        loop {
+            let request = match self.runtime.request_limiter.as_mut() {
+                Some(limiter) => {
+                    let now = Instant::now();
+                    limiter.expire(now, max_age);
+                    limiter.pop_ready(now)
+                }
+                None => None,
+            };
+            match request {
+                Some(request) => self.send_message(request)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
     /// Receive a Bitcoin protocol message on the wire
     /// If this method returns Err(ConnectionBroken), then the caller should attempt to re-connect.
     pub fn recv_message(&mut self) -> Result<PeerMessage, btc_error> {
-        let magic = network_id_to_bytes(self.runtime.network_id);
+        let magic = self.network_magic();
 
-        self.with_socket(|ref mut sock| {
+        let policy = self.config.keepalive.clone();
+        self.wait_for_message(policy.as_ref())?;
+
+        let max_message_size = self.config.max_message_size;
+        let max_block_size = self.config.max_block_size;
+        let mut num_bytes = 0;
+        let result = self.with_socket(|ref mut sock| {
             // read the message off the wire
-            let mut decoder = RawDecoder::new(sock);
-
-            let decoded: btc_message::RawNetworkMessage =
-                ConsensusDecodable::consensus_decode(&mut decoder).map_err(|e| {
-                    // if we can't finish a recv(), then report that the connection is broken
-                    match e {
-                        btc_serialize::Error::Io(ref io_error) => {
-                            if io_error.kind() == io::ErrorKind::UnexpectedEof {
-                                btc_error::ConnectionBroken
-                            } else {
-                                btc_error::Io(io::Error::new(
-                                    io_error.kind(),
-                                    "I/O error when processing message",
-                                ))
-                            }
-                        }
-                        _ => btc_error::SerializationError(e),
-                    }
-                })?;
+            let mut reader = CountingReader {
+                inner: sock,
+                num_bytes: 0,
+            };
+            let decoded = messages::read_message(&mut reader, max_message_size, max_block_size);
+            num_bytes = reader.num_bytes;
+            let decoded = decoded?;
 
             // sanity check -- must match our network
             if decoded.magic != magic {
@@ -97,17 +307,146 @@ impl BitcoinIndexer {
             }
 
             Ok(decoded.payload)
+        });
+
+        self.update_sync_stats(|stats| stats.record_bytes_downloaded(num_bytes));
+        let payload = result?;
+        self.runtime.backoff.message_received();
+
+        // a reply may make room for a queued request
+        if let Some(limiter) = self.runtime.request_limiter.as_mut() {
+            limiter.note_reply(&payload);
+        }
+        self.flush_requests()?;
+
+        // only blocks count towards the download rate limit, so headers sync at full speed.
+        // Throttle between messages rather than in the middle of one, so a throttled read
+        // never looks like a stalled (or broken) connection.
+        match payload {
+            btc_message::NetworkMessage::Block(_) | btc_message::NetworkMessage::MerkleBlock(_) => {
+                self.throttle_block_download(num_bytes)?;
+            }
+            _ => {}
+        }
+        Ok(payload)
+    }
+
+    /// Wait until the peer has sent us something to read, pinging it as `policy` (if given)
+    /// dictates in the meantime.  Gives up with an I/O error once nothing has arrived for the
+    /// configured `read_timeout`, like a plain read would, and with ShutdownRequested if a
+    /// shutdown was requested more than `shutdown_timeout` ago.
+    fn wait_for_message(&mut self, policy: Option<&KeepalivePolicy>) -> Result<(), btc_error> {
+        let read_timeout = self.config.read_timeout;
+        let mut poll_timeout = cmp::min(SHUTDOWN_POLL_INTERVAL, read_timeout);
+        if let Some(policy) = policy {
+            poll_timeout = cmp::min(poll_timeout, cmp::min(policy.interval, policy.timeout));
+        }
+        let poll_timeout = cmp::max(poll_timeout, Duration::from_millis(1));
+        let wait_start = Instant::now();
+
+        loop {
+            self.check_shutdown_deadline()?;
+            if let Some(policy) = policy {
+                self.keepalive(policy)?;
+            }
+
+            if self.poll_message(poll_timeout)? {
+                return Ok(());
+            }
+
+            if wait_start.elapsed() >= read_timeout {
+                return Err(btc_error::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Timed out waiting for a message",
+                )));
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for the peer to send us something to read, sending queued requests
+    /// as `config.request_limit` lets them out in the meantime.
+    /// Returns true if there's something to read, and Err(ConnectionBroken) if the peer closed
+    /// the connection.
+    pub fn poll_message(&mut self, timeout: Duration) -> Result<bool, btc_error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.flush_requests()?;
+            let now = Instant::now();
+            let mut wait = deadline.saturating_duration_since(now);
+            if let Some(delay) = self
+                .runtime
+                .request_limiter
+                .as_ref()
+                .and_then(|limiter| limiter.next_send_delay(now))
+            {
+                wait = cmp::min(wait, delay);
+            }
+            if self.peek_message(cmp::max(wait, Duration::from_millis(1)))? {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for the peer to send us something to read (see `poll_message()`)
+    fn peek_message(&mut self, timeout: Duration) -> Result<bool, btc_error> {
+        let read_timeout = self.config.read_timeout;
+        self.with_socket(|ref mut sock| {
+            sock.set_read_timeout(Some(timeout))?;
+            let mut buf = [0u8; 1];
+            let peek_result = sock.peek(&mut buf);
+            sock.set_read_timeout(Some(read_timeout))?;
+
+            match peek_result {
+                Ok(0) => Err(btc_error::ConnectionBroken),
+                Ok(_) => Ok(true),
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    Ok(false)
+                }
+                Err(e) => Err(btc_error::Io(e)),
+            }
         })
     }
 
+    /// Ping the peer if `policy.interval` has passed since the last ping, and drop the
+    /// connection if the peer hasn't answered the outstanding ping within `policy.timeout`.
+    /// Returns Err(ConnectionBroken) if the connection was dropped.
+    pub fn keepalive(&mut self, policy: &KeepalivePolicy) -> Result<(), btc_error> {
+        if let Some((nonce, sent_at)) = self.runtime.outstanding_ping {
+            if sent_at.elapsed() >= policy.timeout {
+                warn!(
+                    "No pong {} from {}:{} within {:?}.  Killing connection.",
+                    nonce,
+                    self.peer_host(),
+                    self.peer_port(),
+                    &policy.timeout
+                );
+                self.runtime.outstanding_ping = None;
+                self.disconnect();
+                return Err(btc_error::ConnectionBroken);
+            }
+            return Ok(());
+        }
+
+        if self.runtime.last_ping_time.elapsed() >= policy.interval {
+            self.send_ping()?;
+        }
+        Ok(())
+    }
+
     /// Get sender address from our socket
     pub fn get_local_sockaddr(&mut self) -> Result<SocketAddr, btc_error> {
-        self.with_socket(|ref mut sock| sock.local_addr().map_err(btc_error::Io))
+        self.with_socket(|ref mut sock| Ok(sock.local_addr()?))
     }
 
     /// Get receiver address from our socket
     pub fn get_remote_sockaddr(&mut self) -> Result<SocketAddr, btc_error> {
-        self.with_socket(|ref mut sock| sock.peer_addr().map_err(btc_error::Io))
+        self.with_socket(|ref mut sock| Ok(sock.peer_addr()?))
     }
 
     /// Handle and consume message we received, if we can.
@@ -131,6 +470,10 @@ impl BitcoinIndexer {
             return Err(btc_error::TimedOut);
         }
 
+        if let btc_message::NetworkMessage::Inv(ref invs) = message {
+            self.note_block_announcements(invs);
+        }
+
         // classify the message here, so we can pass it along to the handler explicitly
         match message {
             btc_message::NetworkMessage::Version(..) => {
@@ -145,6 +488,27 @@ impl BitcoinIndexer {
             btc_message::NetworkMessage::Pong(..) => {
                 return self.handle_pong(message).and_then(|_r| Ok(true));
             }
+            btc_message::NetworkMessage::Inv(..) if self.config.mempool_ttl.is_some() => {
+                return self.handle_mempool_message(message).and_then(|_r| Ok(true));
+            }
+            // a tx may belong to a filtered block the handler asked for
+            btc_message::NetworkMessage::Tx(..) if self.config.mempool_ttl.is_some() => {
+                let message = match handler {
+                    Some(custom_handler) => match custom_handler.handle_message(self, message) {
+                        Err(btc_error::UnhandledMessage(message)) => message,
+                        res => return res,
+                    },
+                    None => message,
+                };
+                return self.handle_mempool_message(message).and_then(|_r| Ok(true));
+            }
+            // a notfound for blocks goes to the handler that asked for them
+            btc_message::NetworkMessage::NotFound(..)
+                if self.config.mempool_ttl.is_some()
+                    && BitcoinIndexer::is_tx_notfound(&message) =>
+            {
+                return self.handle_mempool_message(message).and_then(|_r| Ok(true));
+            }
             _ => match handler {
                 Some(custom_handler) => custom_handler.handle_message(self, message),
                 None => Err(btc_error::UnhandledMessage(message)),
@@ -152,12 +516,38 @@ impl BitcoinIndexer {
         }
     }
 
+    /// Is `message` a notfound for transactions only?
+    fn is_tx_notfound(message: &PeerMessage) -> bool {
+        match message {
+            btc_message::NetworkMessage::NotFound(ref invs) => invs.iter().all(|inv| {
+                inv.inv_type == btc_message_blockdata::InvType::Transaction
+                    || inv.inv_type == btc_message_blockdata::InvType::WitnessTransaction
+            }),
+            _ => false,
+        }
+    }
+
+    /// Count the blocks announced in an Inv message towards our estimate of the peer's chain tip
+    fn note_block_announcements(&self, invs: &[btc_message_blockdata::Inventory]) {
+        let num_blocks = invs
+            .iter()
+            .filter(|inv| {
+                inv.inv_type == btc_message_blockdata::InvType::Block
+                    || inv.inv_type == btc_message_blockdata::InvType::WitnessBlock
+            })
+            .count() as u64;
+        if num_blocks > 0 {
+            self.update_sync_stats(|stats| stats.record_block_announcements(num_blocks));
+        }
+    }
+
     /// Do the initial handshake to the remote peer.
     /// Returns the remote peer's block height
     pub fn peer_handshake(&mut self) -> Result<u64, btc_error> {
         debug!(
             "Begin peer handshake to {}:{}",
-            self.config.peer_host, self.config.peer_port
+            self.peer_host(),
+            self.peer_port()
         );
         self.send_version()?;
         let version_reply = self.recv_message()?;
@@ -166,22 +556,96 @@ impl BitcoinIndexer {
         let verack_reply = self.recv_message()?;
         self.handle_verack(verack_reply)?;
 
+        if self.config.sendheaders_grace.is_some()
+            && !self.runtime.sendheaders_ignored
+            && self.runtime.peer_version >= btc_constants::SENDHEADERS_VERSION
+        {
+            self.send_sendheaders()?;
+        }
+
+        if self.config.mempool_ttl.is_some() && self.peer_serves_mempool() {
+            self.send_mempool()?;
+        }
+
         debug!(
             "Established connection to {}:{}, who has {} blocks",
-            self.config.peer_host, self.config.peer_port, self.runtime.block_height
+            self.peer_host(),
+            self.peer_port(),
+            self.runtime.block_height
         );
         Ok(self.runtime.block_height)
     }
 
+    /// Wait according to our reconnect policy, before a connection attempt that follows a
+    /// dropped connection or a failed attempt (see `schedule_reconnect()`).  Doesn't wait if the
+    /// policy's `max_attempts` are used up, since `connect_handshake_backoff()` gives up
+    /// instead.
+    fn wait_before_reconnect(&mut self) {
+        let last_ceiling = self.runtime.backoff.ceiling;
+        let delay = self.runtime.backoff.next_delay(
+            &self.config.reconnect_policy,
+            Instant::now(),
+            &mut thread_rng(),
+        );
+        if self
+            .runtime
+            .backoff
+            .exhausted(&self.config.reconnect_policy)
+        {
+            return;
+        }
+        // warn once per backoff step, rather than on every attempt once the delay is capped
+        if self.runtime.backoff.ceiling != last_ceiling {
+            warn!(
+                "Connection to {}:{} broken; retrying in {:?} (backoff: {:?})",
+                self.peer_host(),
+                self.peer_port(),
+                &delay,
+                &self.runtime.backoff
+            );
+        } else {
+            debug!(
+                "Connection to {}:{} broken; retrying in {:?} (backoff: {:?})",
+                self.peer_host(),
+                self.peer_port(),
+                &delay,
+                &self.runtime.backoff
+            );
+        }
+        self.report_sync_event(|| SyncEvent::ReconnectBackoff {
+            host: self.peer_host().to_string(),
+            port: self.peer_port(),
+            backoff: self.runtime.backoff.clone(),
+        });
+        thread::sleep(delay);
+    }
+
     /// Connect to a remote peer, do a handshake with the remote peer, and use exponential backoff until we
-    /// succeed in establishing a connection.
+    /// succeed in establishing a connection.  The delays between attempts are governed by the
+    /// indexer's `ReconnectPolicy`.  Each failed attempt fails over to the next peer we know of
+    /// (see `fail_over_peer()`), if there are any others.
     /// This method masks ConnectionBroken errors, but does not mask other network errors.
     /// Returns the remote peer's block height on success
     pub fn connect_handshake_backoff(&mut self) -> Result<u64, btc_error> {
-        let mut backoff: f64 = 1.0;
-        let mut rng = thread_rng();
-
+        self.config.check_socket_timeouts()?;
         loop {
+            self.check_shutdown_deadline()?;
+            if self.runtime.backoff.reconnect_pending {
+                self.wait_before_reconnect();
+            }
+            if self
+                .runtime
+                .backoff
+                .exhausted(&self.config.reconnect_policy)
+            {
+                error!(
+                    "Giving up on reconnecting to {}:{} after {} attempts",
+                    self.peer_host(),
+                    self.peer_port(),
+                    self.runtime.backoff.attempts
+                );
+                return Err(btc_error::TooManyReconnects(self.runtime.backoff.attempts));
+            }
             let connection_result = self.connect();
             match connection_result {
                 Ok(()) => {
@@ -190,42 +654,58 @@ impl BitcoinIndexer {
                     match handshake_result {
                         Ok(block_height) => {
                             // connected!
+                            self.runtime.backoff.connected(Instant::now());
+                            self.runtime.failovers = 0;
+                            self.report_sync_event(|| SyncEvent::PeerConnected {
+                                host: self.peer_host().to_string(),
+                                port: self.peer_port(),
+                                block_height,
+                            });
                             return Ok(block_height);
                         }
-                        Err(btc_error::ConnectionBroken) => {
+                        Err(e) if e.is_transient() => {
                             // need to try again
-                            backoff = 2.0 * backoff + (backoff * rng.gen_range(0.0, 1.0));
+                            debug!(
+                                "Failed to handshake with {}:{}, will retry: {:?}",
+                                self.peer_host(),
+                                self.peer_port(),
+                                &e
+                            );
                         }
                         Err(e) => {
-                            // propagate other network error
+                            // propagate errors that retrying won't fix
                             warn!(
                                 "Failed to handshake with {}:{}: {:?}",
-                                &self.config.peer_host, self.config.peer_port, &e
+                                self.peer_host(),
+                                self.peer_port(),
+                                &e
                             );
                             return Err(e);
                         }
                     }
                 }
+                Err(burnchain_error::Bitcoin(btc_error::ProxyError(msg))) => {
+                    // not the peer's fault, so don't give up on it
+                    error!(
+                        "Failed to connect to peer {}:{} through the proxy: {}",
+                        self.peer_host(),
+                        self.peer_port(),
+                        msg
+                    );
+                    self.schedule_reconnect();
+                    continue;
+                }
                 Err(err_msg) => {
                     error!(
                         "Failed to connect to peer {}:{}: {}",
-                        &self.config.peer_host, self.config.peer_port, err_msg
+                        self.peer_host(),
+                        self.peer_port(),
+                        err_msg
                     );
-                    backoff = 2.0 * backoff + (backoff * rng.gen_range(0.0, 1.0));
                 }
             }
 
-            // don't sleep more than 60 seconds
-            if backoff > 60.0 {
-                backoff = 60.0;
-            }
-
-            if backoff > 10.0 {
-                warn!("Connection broken; retrying in {} sec...", backoff);
-            }
-
-            let duration = time::Duration::from_millis((backoff * 1_000.0) as u64);
-            thread::sleep(duration);
+            self.fail_over_peer();
         }
     }
 
@@ -252,25 +732,72 @@ impl BitcoinIndexer {
             nonce: self.runtime.version_nonce,
             user_agent: self.runtime.user_agent.to_owned(),
             start_height: 0,
-            relay: false,
+            // have the peer announce new transactions if we're watching its mempool
+            relay: self.config.mempool_ttl.is_some(),
         };
 
         debug!(
             "Send version (nonce={}) to {}:{}",
-            self.runtime.version_nonce, self.config.peer_host, self.config.peer_port
+            self.runtime.version_nonce,
+            self.peer_host(),
+            self.peer_port()
         );
         self.send_message(btc_message::NetworkMessage::Version(payload))
     }
 
-    /// Receive a Version message and reply with a Verack
+    /// Receive a Version message and reply with a Verack.
+    /// Fails with IncompatiblePeer, without replying, if the peer's protocol version is older than
+    /// `MIN_PEER_PROTOCOL_VERSION` or if it serves no blocks at all.  A pruned peer
+    /// (`NODE_NETWORK_LIMITED`) is fine, as long as we only need recent blocks from it (see
+    /// `check_peer_serves_block()`).
     pub fn handle_version(&mut self, version_message: PeerMessage) -> Result<(), btc_error> {
         match version_message {
             btc_message::NetworkMessage::Version(msg_body) => {
                 debug!(
-                    "Handle version -- remote peer blockchain height is {}",
-                    msg_body.start_height
+                    "Handle version -- remote peer blockchain height is {} (version {}, services {:#x})",
+                    msg_body.start_height, msg_body.version, msg_body.services
                 );
+                if msg_body.version < MIN_PEER_PROTOCOL_VERSION {
+                    warn!(
+                        "Peer {}:{} ({}) speaks protocol version {}, but we need at least {}",
+                        self.peer_host(),
+                        self.peer_port(),
+                        &msg_body.user_agent,
+                        msg_body.version,
+                        MIN_PEER_PROTOCOL_VERSION
+                    );
+                    return Err(btc_error::IncompatiblePeer(format!(
+                        "protocol version {} is older than {}",
+                        msg_body.version, MIN_PEER_PROTOCOL_VERSION
+                    )));
+                }
+                if msg_body.services & (btc_constants::NODE_NETWORK | NODE_NETWORK_LIMITED) == 0 {
+                    warn!(
+                        "Peer {}:{} ({}) does not serve blocks (services {:#x})",
+                        self.peer_host(),
+                        self.peer_port(),
+                        &msg_body.user_agent,
+                        msg_body.services
+                    );
+                    return Err(btc_error::IncompatiblePeer(format!(
+                        "services {:#x} include neither NODE_NETWORK nor NODE_NETWORK_LIMITED",
+                        msg_body.services
+                    )));
+                }
+                if msg_body.services & btc_constants::NODE_NETWORK == 0 {
+                    info!(
+                        "Peer {}:{} ({}) is pruned, and only serves the last {} blocks",
+                        self.peer_host(),
+                        self.peer_port(),
+                        &msg_body.user_agent,
+                        NODE_NETWORK_LIMITED_MIN_BLOCKS
+                    );
+                }
+
                 self.runtime.block_height = msg_body.start_height as u64;
+                self.runtime.peer_services = msg_body.services;
+                self.runtime.peer_version =
+                    cmp::min(msg_body.version, btc_constants::PROTOCOL_VERSION);
                 return self.send_verack();
             }
             _ => {
@@ -320,12 +847,39 @@ impl BitcoinIndexer {
         Err(btc_error::InvalidMessage(ping_message))
     }
 
+    /// Send a Ping message with a random nonce, and remember it so we can match the pong
+    pub fn send_ping(&mut self) -> Result<(), btc_error> {
+        let nonce: u64 = thread_rng().gen();
+        let payload = btc_message::NetworkMessage::Ping(nonce);
+
+        debug!(
+            "Send ping {} to {}:{}",
+            nonce,
+            self.peer_host(),
+            self.peer_port()
+        );
+        self.send_message(payload)?;
+
+        let now = Instant::now();
+        self.runtime.outstanding_ping = Some((nonce, now));
+        self.runtime.last_ping_time = now;
+        Ok(())
+    }
+
     /// Respond to a Pong message.
-    /// Does nothing.
+    /// Clears the outstanding ping if the nonce matches it.
     pub fn handle_pong(&mut self, pong_message: PeerMessage) -> Result<(), btc_error> {
         match pong_message {
             btc_message::NetworkMessage::Pong(n) => {
                 debug!("Handle pong {}", n);
+                match self.runtime.outstanding_ping {
+                    Some((nonce, _)) if nonce == n => {
+                        self.runtime.outstanding_ping = None;
+                    }
+                    _ => {
+                        debug!("Pong {} does not match any outstanding ping", n);
+                    }
+                }
                 return Ok(());
             }
             _ => {
@@ -335,32 +889,91 @@ impl BitcoinIndexer {
         Err(btc_error::InvalidReply)
     }
 
-    /// Send a GetHeaders message
-    /// Note that this isn't a generic GetHeaders message -- you should use this only to ask
-    /// for a batch of 2,000 block hashes after this given hash.
-    pub fn send_getheaders(&mut self, prev_block_hash: Sha256dHash) -> Result<(), btc_error> {
+    /// Send a GetHeaders message for the (up to 2,000) headers after the highest header in
+    /// `locator` that the peer has in its best chain.  See SpvClient::make_block_locator().
+    pub fn send_getheaders(&mut self, locator: Vec<Sha256dHash>) -> Result<(), btc_error> {
+        assert!(locator.len() > 0);
+        debug!(
+            "Send GetHeaders {} (locator of {} hashes) for 2000 headers to {}:{}",
+            locator[0].be_hex_string(),
+            locator.len(),
+            self.peer_host(),
+            self.peer_port()
+        );
+
         let getheaders =
-            btc_message_blockdata::GetHeadersMessage::new(vec![prev_block_hash], prev_block_hash);
+            btc_message_blockdata::GetHeadersMessage::new(locator, Sha256dHash::default());
+        let payload = btc_message::NetworkMessage::GetHeaders(getheaders);
+
+        self.runtime.last_getheaders_send_time = get_epoch_time_secs();
+        self.send_request(payload)
+    }
+
+    /// Send a GetHeaders message for the headers after `prev_block_hash`, up to and including
+    /// `stop_hash` (or up to 2000 headers, whichever comes first).
+    pub fn send_getheaders_until(
+        &mut self,
+        prev_block_hash: Sha256dHash,
+        stop_hash: Sha256dHash,
+    ) -> Result<(), btc_error> {
+        let getheaders =
+            btc_message_blockdata::GetHeadersMessage::new(vec![prev_block_hash], stop_hash);
         let payload = btc_message::NetworkMessage::GetHeaders(getheaders);
 
         debug!(
-            "Send GetHeaders {} for 2000 headers to {}:{}",
+            "Send GetHeaders {} for headers up to {} to {}:{}",
             prev_block_hash.be_hex_string(),
-            self.config.peer_host,
-            self.config.peer_port
+            stop_hash.be_hex_string(),
+            self.peer_host(),
+            self.peer_port()
         );
 
         self.runtime.last_getheaders_send_time = get_epoch_time_secs();
-        self.send_message(payload)
+        self.send_request(payload)
+    }
+
+    /// Send a SendHeaders message, asking the peer to announce new blocks with their headers
+    /// instead of with `inv`s
+    pub fn send_sendheaders(&mut self) -> Result<(), btc_error> {
+        debug!(
+            "Send sendheaders to {}:{}",
+            self.peer_host(),
+            self.peer_port()
+        );
+        self.send_message(btc_message::NetworkMessage::SendHeaders)?;
+        self.runtime.sendheaders_sent_time = Some(Instant::now());
+        Ok(())
     }
 
-    /// Send a GetData message
+    /// Send a GetData message for whole blocks
     pub fn send_getdata(&mut self, block_hashes: &Vec<Sha256dHash>) -> Result<(), btc_error> {
+        self.send_block_getdata(block_hashes, btc_message_blockdata::InvType::Block)
+    }
+
+    /// Send a GetData message for blocks as filtered blocks (BIP37), if we've loaded a bloom
+    /// filter into the peer (see `load_bloom_filter()`), or else whole
+    pub fn send_filtered_getdata(
+        &mut self,
+        block_hashes: &Vec<Sha256dHash>,
+    ) -> Result<(), btc_error> {
+        let inv_type = if self.runtime.bloom_filter.is_some() {
+            btc_message_blockdata::InvType::FilteredBlock
+        } else {
+            btc_message_blockdata::InvType::Block
+        };
+        self.send_block_getdata(block_hashes, inv_type)
+    }
+
+    fn send_block_getdata(
+        &mut self,
+        block_hashes: &Vec<Sha256dHash>,
+        inv_type: btc_message_blockdata::InvType,
+    ) -> Result<(), btc_error> {
         assert!(block_hashes.len() > 0);
         let getdata_invs = block_hashes
             .iter()
             .map(|h| btc_message_blockdata::Inventory {
-                inv_type: btc_message_blockdata::InvType::Block,
+                inv_type: inv_type.clone(),
                 hash: h.clone(),
             })
             .collect();
@@ -372,9 +985,327 @@ impl BitcoinIndexer {
             "Send GetData {}-{} to {}:{}",
             block_hashes[0].be_hex_string(),
             block_hashes[block_hashes.len() - 1].be_hex_string(),
-            self.config.peer_host,
-            self.config.peer_port
+            self.peer_host(),
+            self.peer_port()
+        );
+        self.send_request(getdata)
+    }
+
+    /// Lowest block height the peer serves:  every block for a full node, but only the last
+    /// `NODE_NETWORK_LIMITED_MIN_BLOCKS` up to its chain tip (as of the handshake) for a pruned
+    /// one
+    pub fn peer_oldest_block(&self) -> u64 {
+        if self.runtime.peer_services & btc_constants::NODE_NETWORK != 0 {
+            return 0;
+        }
+        (self.runtime.block_height + 1).saturating_sub(NODE_NETWORK_LIMITED_MIN_BLOCKS)
+    }
+
+    /// Check that the peer serves the block at `height` before asking it for the block, since a
+    /// pruned peer never answers for blocks it doesn't have.  If it doesn't, we give up on it
+    /// (see `peer_lacks_block()`).
+    pub fn check_peer_serves_block(&mut self, height: u64) -> Result<(), btc_error> {
+        let oldest_block = self.peer_oldest_block();
+        if height >= oldest_block {
+            return Ok(());
+        }
+        Err(self.peer_lacks_block(format!(
+            "{}:{} only serves the last {} blocks (from height {}), need height {}",
+            self.peer_host(),
+            self.peer_port(),
+            NODE_NETWORK_LIMITED_MIN_BLOCKS,
+            oldest_block,
+            height
+        )))
+    }
+
+    /// Give up on getting a block from the peer, which doesn't have it for the given `reason`.
+    /// If there's another peer we haven't tried for it yet, fail over to it and get
+    /// ConnectionBroken, so that the conversation carries on with that peer.  Otherwise, get
+    /// PrunedPeer -- no peer we know of has the block.
+    pub fn peer_lacks_block(&mut self, reason: String) -> btc_error {
+        if self.runtime.pruned_failovers < self.num_peers() as u64 - 1 {
+            warn!("Peer lacks a block we need, so trying another: {}", &reason);
+            self.runtime.pruned_failovers += 1;
+            self.fail_over_peer();
+            return btc_error::ConnectionBroken;
+        }
+        warn!("Peer lacks a block we need: {}", &reason);
+        self.runtime.pruned_failovers = 0;
+        btc_error::PrunedPeer(reason)
+    }
+
+    /// Give up on the peer for sending a block whose transactions don't match its header's
+    /// merkle root, for the given `reason`.  That's no accident, so the failure is counted
+    /// against the peer (see `switch_header_peer()`), and we fail over to the next peer (or
+    /// reconnect, if there are no others), which is asked for the block again.  Returns
+    /// ConnectionBroken, so that the conversation carries on over the new connection.
+    pub fn peer_sent_bad_block(&mut self, reason: String) -> btc_error {
+        warn!("Dropping peer for sending a bad block: {}", &reason);
+        *self
+            .runtime
+            .peer_failures
+            .entry((self.peer_host().to_string(), self.peer_port()))
+            .or_insert(0) += 1;
+        self.fail_over_peer();
+        btc_error::ConnectionBroken
+    }
+
+    /// Does the peer answer `mempool` messages?  Peers that don't serve bloom filters hang up on
+    /// us if we send one (unless they've whitelisted us), so we only rely on them relaying new
+    /// transactions.
+    pub fn peer_serves_mempool(&self) -> bool {
+        self.runtime.peer_services & btc_constants::NODE_BLOOM != 0
+    }
+
+    /// Does the peer serve BIP37 bloom filters (and so filtered blocks)?
+    pub fn peer_serves_bloom_filters(&self) -> bool {
+        self.runtime.peer_services & btc_constants::NODE_BLOOM != 0
+    }
+
+    /// Load a bloom filter of our `filter_scripts` and `filter_outpoints` into the peer, if it
+    /// serves bloom filters, so that we can ask it for filtered blocks (see
+    /// `BitcoinBlockDownloader::new_filtered()`).  The filter gets a fresh random tweak each
+    /// time, and stays loaded for the rest of the connection.  Otherwise, blocks are asked for
+    /// whole.
+    pub fn load_bloom_filter(&mut self) -> Result<(), btc_error> {
+        if self.runtime.bloom_filter.is_some()
+            || (self.config.filter_scripts.is_empty() && self.config.filter_outpoints.is_empty())
+        {
+            return Ok(());
+        }
+        if !self.peer_serves_bloom_filters() {
+            debug!(
+                "Peer {}:{} does not serve bloom filters, so downloading whole blocks",
+                self.peer_host(),
+                self.peer_port()
+            );
+            return Ok(());
+        }
+
+        let filter = Bip37Filter::for_watch_targets(
+            &self.config.filter_scripts,
+            &self.config.filter_outpoints,
+            self.config.bloom_filter_fp_rate,
+            thread_rng().gen(),
+        );
+        let filterload = filter.to_filterload();
+        debug!(
+            "Send filterload ({} bytes, {} hash functions) to {}:{}",
+            filterload.filter.len(),
+            filterload.hash_funcs,
+            self.peer_host(),
+            self.peer_port()
         );
-        self.send_message(getdata)
+        self.send_message(btc_message::NetworkMessage::FilterLoad(filterload))?;
+        self.runtime.bloom_filter = Some(filter);
+        Ok(())
+    }
+
+    /// Add `elements` to the bloom filter loaded into the peer, if there is one, with a
+    /// `filteradd` each.  If that would push the filter past the number of elements it was sized
+    /// for, a bigger filter of all our watch targets is loaded in its place instead, so the
+    /// false-positive rate stays at `bloom_filter_fp_rate`.  (The new filter doesn't have the
+    /// outpoints the peer added to the old one as transactions matched it.)
+    fn add_bloom_filter_elements(&mut self, elements: &[Vec<u8>]) -> Result<(), btc_error> {
+        let saturated = match self.runtime.bloom_filter.as_mut() {
+            Some(filter) => {
+                for element in elements.iter() {
+                    filter.insert(element);
+                }
+                filter.is_saturated()
+            }
+            None => {
+                return Ok(());
+            }
+        };
+        if saturated {
+            debug!(
+                "Bloom filter of {}:{} is saturated; loading a new one",
+                self.peer_host(),
+                self.peer_port()
+            );
+            self.runtime.bloom_filter = None;
+            return self.load_bloom_filter();
+        }
+        for element in elements.iter() {
+            self.send_message(btc_message::NetworkMessage::FilterAdd(FilterAdd {
+                data: element.clone(),
+            }))?;
+        }
+        Ok(())
+    }
+
+    /// Start watching for transactions that pay to or spend from `script`:  add it to
+    /// `filter_scripts`, and to the bloom filter loaded into the peer, if any.  Like the rest of
+    /// `filter_scripts`, it's also matched against the compact filters of blocks we haven't
+    /// downloaded yet.
+    pub fn add_filter_script(&mut self, script: Vec<u8>) -> Result<(), btc_error> {
+        if self.config.filter_scripts.contains(&script) {
+            return Ok(());
+        }
+        let elements = Bip37Filter::script_elements(&script);
+        self.config.filter_scripts.push(script);
+        self.add_bloom_filter_elements(&elements)
+    }
+
+    /// Start watching for transactions that spend `outpoint`:  add it to `filter_outpoints`,
+    /// and to the bloom filter loaded into the peer, if any
+    pub fn add_filter_outpoint(&mut self, outpoint: OutPoint) -> Result<(), btc_error> {
+        if self.config.filter_outpoints.contains(&outpoint) {
+            return Ok(());
+        }
+        let element = Bip37Filter::outpoint_element(&outpoint);
+        self.config.filter_outpoints.push(outpoint);
+        self.add_bloom_filter_elements(&[element])
+    }
+
+    /// Send a MemPool message, asking the peer to announce the transactions in its mempool
+    pub fn send_mempool(&mut self) -> Result<(), btc_error> {
+        debug!("Send mempool to {}:{}", self.peer_host(), self.peer_port());
+        self.send_message(btc_message::NetworkMessage::MemPool)
+    }
+
+    /// Send a GetData message for transactions announced by the peer
+    pub fn send_getdata_txs(&mut self, txids: &[Sha256dHash]) -> Result<(), btc_error> {
+        assert!(!txids.is_empty());
+        let getdata_invs = txids
+            .iter()
+            .map(|h| btc_message_blockdata::Inventory {
+                inv_type: btc_message_blockdata::InvType::Transaction,
+                hash: *h,
+            })
+            .collect();
+
+        debug!(
+            "Send GetData for {} transaction(s) to {}:{}",
+            txids.len(),
+            self.peer_host(),
+            self.peer_port()
+        );
+        self.send_request(btc_message::NetworkMessage::GetData(getdata_invs))
+    }
+
+    /// Handle an Inv, Tx, or NotFound message while watching the peer's mempool:  ask for the
+    /// announced transactions we haven't seen, and pick out the burnchain transactions from the
+    /// ones we get.  Announced blocks are ignored.
+    pub fn handle_mempool_message(&mut self, message: PeerMessage) -> Result<(), btc_error> {
+        let ttl = match self.config.mempool_ttl {
+            Some(ttl) => ttl,
+            None => {
+                return Err(btc_error::UnhandledMessage(message));
+            }
+        };
+        let epoch_id = self.get_mempool_epoch_id();
+        let network_id = self.runtime.network_id;
+        let magic_bytes = self.config.magic_bytes;
+        let watcher = self
+            .runtime
+            .mempool
+            .get_or_insert_with(|| MempoolWatcher::new(network_id, magic_bytes, ttl));
+
+        match message {
+            btc_message::NetworkMessage::Inv(invs) => {
+                let wanted = watcher.handle_inv(&invs);
+                if !wanted.is_empty() {
+                    self.send_getdata_txs(&wanted)?;
+                }
+            }
+            btc_message::NetworkMessage::Tx(tx) => {
+                watcher.handle_tx(&tx, epoch_id, get_epoch_time_secs());
+            }
+            btc_message::NetworkMessage::NotFound(invs) => {
+                watcher.handle_notfound(&invs);
+            }
+            _ => {
+                return Err(btc_error::UnhandledMessage(message));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the epoch of the block after the peer's chain tip, which is the earliest that a
+    /// mempool transaction can confirm in
+    fn get_mempool_epoch_id(&self) -> StacksEpochId {
+        let epochs = self.get_stacks_epochs();
+        let height = self.runtime.block_height + 1;
+        match StacksEpoch::find_epoch(&epochs, height) {
+            Some(index) => epochs[index].epoch_id,
+            None => epochs[epochs.len() - 1].epoch_id,
+        }
+    }
+
+    /// Does the peer serve BIP157 compact block filters?
+    pub fn peer_serves_compact_filters(&self) -> bool {
+        self.runtime.peer_services & btc_constants::NODE_COMPACT_FILTERS != 0
+    }
+
+    /// Send a GetCFCheckpt message for the basic filter headers at every 1,000th block up to
+    /// `stop_hash`
+    pub fn send_getcfcheckpt(&mut self, stop_hash: Sha256dHash) -> Result<(), btc_error> {
+        let payload = btc_message::NetworkMessage::GetCFCheckpt(btc_message_filter::GetCFCheckpt {
+            filter_type: BASIC_FILTER_TYPE,
+            stop_hash,
+        });
+
+        debug!(
+            "Send GetCFCheckpt up to {} to {}:{}",
+            stop_hash.be_hex_string(),
+            self.peer_host(),
+            self.peer_port()
+        );
+
+        self.runtime.last_getheaders_send_time = get_epoch_time_secs();
+        self.send_message(payload)
+    }
+
+    /// Send a GetCFHeaders message for the basic filter hashes of the blocks from `start_height`
+    /// up to `stop_hash` (at most 2,000 blocks)
+    pub fn send_getcfheaders(
+        &mut self,
+        start_height: u64,
+        stop_hash: Sha256dHash,
+    ) -> Result<(), btc_error> {
+        let payload = btc_message::NetworkMessage::GetCFHeaders(btc_message_filter::GetCFHeaders {
+            filter_type: BASIC_FILTER_TYPE,
+            start_height: start_height as u32,
+            stop_hash,
+        });
+
+        debug!(
+            "Send GetCFHeaders {}-{} to {}:{}",
+            start_height,
+            stop_hash.be_hex_string(),
+            self.peer_host(),
+            self.peer_port()
+        );
+
+        self.runtime.last_getheaders_send_time = get_epoch_time_secs();
+        self.send_message(payload)
+    }
+
+    /// Send a GetCFilters message for the basic filters of the blocks from `start_height` up to
+    /// `stop_hash` (at most 1,000 blocks)
+    pub fn send_getcfilters(
+        &mut self,
+        start_height: u64,
+        stop_hash: Sha256dHash,
+    ) -> Result<(), btc_error> {
+        let payload = btc_message::NetworkMessage::GetCFilters(btc_message_filter::GetCFilters {
+            filter_type: BASIC_FILTER_TYPE,
+            start_height: start_height as u32,
+            stop_hash,
+        });
+
+        debug!(
+            "Send GetCFilters {}-{} to {}:{}",
+            start_height,
+            stop_hash.be_hex_string(),
+            self.peer_host(),
+            self.peer_port()
+        );
+
+        self.runtime.last_getdata_send_time = get_epoch_time_secs();
+        self.send_message(payload)
     }
 }
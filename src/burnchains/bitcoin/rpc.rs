@@ -0,0 +1,2433 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A minimal client for bitcoind's JSON-RPC interface, just enough to fetch headers and blocks
+//! with.
+//!
+//! Each call is one HTTP/1.1 POST.  bitcoind keeps the connection open for the next call, and
+//! replies with a Content-Length, so no HTTP library is needed.  Connections are kept in a pool
+//! (see `RpcConnectionPool`) shared by a client and its clones.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use stacks_common::deps_common::bitcoin::blockdata::block::{Block, BlockHeader, LoneBlockHeader};
+use stacks_common::deps_common::bitcoin::network::encodable::VarInt;
+use stacks_common::deps_common::bitcoin::network::message_bloom::MerkleBlock;
+use stacks_common::deps_common::bitcoin::network::serialize::{deserialize, BitcoinHash};
+use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+use stacks_common::util::hash::hex_bytes;
+use stacks_common::util::sleep_ms;
+
+use crate::burnchains::bitcoin::bloom;
+use crate::burnchains::bitcoin::indexer::{BitcoinIndexerConfig, RpcEndpoint, SocksProxy};
+use crate::burnchains::bitcoin::socks;
+use crate::burnchains::bitcoin::spv::HeaderSource;
+use crate::burnchains::bitcoin::BitcoinNetworkType;
+use crate::burnchains::bitcoin::Error as btc_error;
+
+/// Most headers to get in one `get_headers()` call (the same as a `headers` message holds)
+const MAX_HEADERS_PER_REQUEST: u64 = 2000;
+
+/// bitcoind's error code for errors without a more specific one
+const RPC_MISC_ERROR: i64 = -1;
+
+/// Default number of connections a client and its clones have open to bitcoind at most (the
+/// number of calls bitcoind serves at once by default; see its `-rpcthreads`)
+pub const DEFAULT_RPC_MAX_CONNECTIONS: usize = 4;
+
+/// Default number of times to retry a call that failed in transit
+pub const DEFAULT_RPC_RETRIES: u32 = 3;
+
+/// Delay before the first retry of a call that failed in transit.  It doubles on each retry.
+const RPC_RETRY_BASE_DELAY_MS: u64 = 250;
+
+/// Run the idempotent RPC operation `op`, retrying it with exponential backoff up to `retries`
+/// times while it fails with an error that `is_retryable` accepts -- one in transit.  Errors
+/// from bitcoind itself (e.g. a block that doesn't exist), and replies that can't be made sense
+/// of, shouldn't be retried.  Both this module's client and the node's use it.
+pub fn retry_rpc_call<T, E, F, P>(retries: u32, is_retryable: P, mut op: F) -> Result<T, E>
+where
+    E: fmt::Debug,
+    F: FnMut() -> Result<T, E>,
+    P: Fn(&E) -> bool,
+{
+    let mut retried = 0;
+    loop {
+        match op() {
+            Err(e) if is_retryable(&e) && retried < retries => {
+                let delay_ms = RPC_RETRY_BASE_DELAY_MS << cmp::min(retried, 6);
+                warn!(
+                    "Bitcoin RPC call failed: {:?}; retrying in {} ms ({} of {})",
+                    &e,
+                    delay_ms,
+                    retried + 1,
+                    retries
+                );
+                sleep_ms(delay_ms);
+                retried += 1;
+            }
+            res => {
+                return res;
+            }
+        }
+    }
+}
+
+/// What bitcoind says about its chain, from `getblockchaininfo`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockchainInfo {
+    /// Which chain bitcoind is on (see `bitcoind_chain_name()`)
+    pub chain: String,
+    /// Height of its highest header
+    pub headers: u64,
+    /// Height of its highest fully validated block
+    pub blocks: u64,
+    /// Whether it's still in its initial block download
+    pub initial_block_download: bool,
+}
+
+/// Get the name `getblockchaininfo` gives a network's chain
+pub fn bitcoind_chain_name(network_id: BitcoinNetworkType) -> &'static str {
+    match network_id {
+        BitcoinNetworkType::Mainnet => "main",
+        BitcoinNetworkType::Testnet => "test",
+        BitcoinNetworkType::Regtest => "regtest",
+        BitcoinNetworkType::Signet => "signet",
+    }
+}
+
+/// Supplies the credentials a `BitcoinRpcClient` authenticates to bitcoind with, e.g. from a
+/// fixed username and password or from bitcoind's RPC cookie file.  It's asked for every
+/// request, and again when bitcoind rejects a request as unauthorized, so it can pick up a
+/// cookie that bitcoind rotated when it restarted.
+pub trait RpcCredentials: Send + Sync {
+    /// Get the value of the `Authorization` header to send to `endpoint`, or None to send
+    /// none.  Credentials that can't be had (e.g. a missing cookie file) are a `ConfigError`.
+    fn authorization(&self, endpoint: &RpcEndpoint) -> Result<Option<String>, btc_error>;
+}
+
+impl fmt::Debug for dyn RpcCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RpcCredentials")
+    }
+}
+
+/// Get `host` without the brackets around it, if it's an IPv6 address in brackets (as in a URL)
+fn unbracket_host(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// What a JSON-RPC batch reply has for one of the batch's requests
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatchReply {
+    /// The request's `result`
+    Result(Value),
+    /// The request's `error`, from bitcoind
+    Error(Value),
+    /// bitcoind didn't reply to the request
+    Missing,
+}
+
+/// Split the reply to a JSON-RPC batch request into the replies to each request, in the order
+/// of the requests, whose IDs are `ids`.  bitcoind can reply in any order, so replies are
+/// matched up by ID; replies to IDs not in `ids` are dropped.  The IDs must be unique.  Only
+/// fails if the batch as a whole failed -- one request's error doesn't fail the others.  Both
+/// this module's client and the node's use it.
+pub fn split_batch_replies(ids: &[Value], replies: Value) -> Result<Vec<BatchReply>, btc_error> {
+    let replies = match replies {
+        Value::Array(replies) => replies,
+        reply => {
+            return Err(btc_error::RpcError(format!(
+                "Expected a batch reply, got {}",
+                reply
+            )));
+        }
+    };
+
+    let indexes: HashMap<String, usize> = ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (id.to_string(), i))
+        .collect();
+    let mut ordered: Vec<BatchReply> = vec![BatchReply::Missing; ids.len()];
+    for mut reply in replies.into_iter() {
+        let index = match reply.get("id").and_then(|id| indexes.get(&id.to_string())) {
+            Some(index) => *index,
+            None => {
+                warn!("Unexpected reply in JSON-RPC batch: {}", &reply);
+                continue;
+            }
+        };
+        ordered[index] = match reply.get_mut("error") {
+            Some(error) if !error.is_null() => BatchReply::Error(error.take()),
+            _ => BatchReply::Result(
+                reply
+                    .get_mut("result")
+                    .map(|result| result.take())
+                    .unwrap_or(Value::Null),
+            ),
+        };
+    }
+    Ok(ordered)
+}
+
+/// Connections held by a `RpcConnectionPool`
+#[derive(Debug)]
+struct PoolState {
+    /// Open connections that aren't in use, waiting to be reused
+    idle: Vec<TcpStream>,
+    /// Number of connections open, whether in use or idle
+    num_open: usize,
+    /// Number of connections opened over the pool's lifetime
+    num_opened: u64,
+}
+
+/// Keep-alive connections to bitcoind's JSON-RPC interface.  Shared by a `BitcoinRpcClient` and
+/// its clones, which can be used from different threads at once.  No more than
+/// `max_connections` are open at a time; a call that needs one more waits for one to free up.
+#[derive(Debug)]
+pub struct RpcConnectionPool {
+    max_connections: usize,
+    state: Mutex<PoolState>,
+    /// Notified whenever a connection is put back into the pool or closed
+    freed: Condvar,
+}
+
+impl RpcConnectionPool {
+    pub fn new(max_connections: usize) -> RpcConnectionPool {
+        RpcConnectionPool {
+            max_connections: cmp::max(max_connections, 1),
+            state: Mutex::new(PoolState {
+                idle: vec![],
+                num_open: 0,
+                num_opened: 0,
+            }),
+            freed: Condvar::new(),
+        }
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// Number of connections open right now, whether in use or idle
+    pub fn num_open(&self) -> usize {
+        self.lock_state().num_open
+    }
+
+    /// Number of connections opened over the pool's lifetime
+    pub fn num_opened(&self) -> u64 {
+        self.lock_state().num_opened
+    }
+
+    fn lock_state(&self) -> MutexGuard<PoolState> {
+        self.state
+            .lock()
+            .expect("BUG: RPC connection pool lock poisoned")
+    }
+
+    /// Take an idle connection, or open a new one with `connect` if fewer than
+    /// `max_connections` are open.  Otherwise, wait up to `timeout` for one to be put back or
+    /// closed, and give up with TimedOut if none is.
+    fn get<F>(
+        pool: &Arc<RpcConnectionPool>,
+        timeout: Duration,
+        connect: F,
+    ) -> Result<PooledConnection, btc_error>
+    where
+        F: FnOnce() -> Result<TcpStream, btc_error>,
+    {
+        let deadline = Instant::now() + timeout;
+        let mut state = pool.lock_state();
+        loop {
+            if let Some(sock) = state.idle.pop() {
+                return Ok(PooledConnection::new(pool.clone(), sock, true));
+            }
+            if state.num_open < pool.max_connections {
+                state.num_open += 1;
+                break;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                warn!(
+                    "Timed out waiting for one of {} RPC connections to free up",
+                    pool.max_connections
+                );
+                return Err(btc_error::TimedOut);
+            }
+            state = pool
+                .freed
+                .wait_timeout(state, deadline - now)
+                .expect("BUG: RPC connection pool lock poisoned")
+                .0;
+        }
+        drop(state);
+
+        match connect() {
+            Ok(sock) => {
+                pool.lock_state().num_opened += 1;
+                Ok(PooledConnection::new(pool.clone(), sock, false))
+            }
+            Err(e) => {
+                pool.close();
+                Err(e)
+            }
+        }
+    }
+
+    /// Make `sock` available to the next caller
+    fn put_back(&self, sock: TcpStream) {
+        self.lock_state().idle.push(sock);
+        self.freed.notify_one();
+    }
+
+    /// Forget a connection that was closed, making room for a new one
+    fn close(&self) {
+        let mut state = self.lock_state();
+        state.num_open = state.num_open.saturating_sub(1);
+        drop(state);
+        self.freed.notify_one();
+    }
+}
+
+/// A connection taken from a `RpcConnectionPool`.  Dropping it closes it, so that a connection
+/// left in an unknown state (e.g. by a transport error) is never reused; `put_back()` returns it
+/// to the pool instead, once its reply has been read in full.
+struct PooledConnection {
+    pool: Arc<RpcConnectionPool>,
+    reader: Option<BufReader<TcpStream>>,
+    /// Whether the connection has been used before, and so may have been closed by bitcoind
+    /// while it sat idle
+    reused: bool,
+}
+
+impl PooledConnection {
+    fn new(pool: Arc<RpcConnectionPool>, sock: TcpStream, reused: bool) -> PooledConnection {
+        PooledConnection {
+            pool,
+            reader: Some(BufReader::new(sock)),
+            reused,
+        }
+    }
+
+    fn reader(&mut self) -> &mut BufReader<TcpStream> {
+        self.reader
+            .as_mut()
+            .expect("BUG: connection already put back")
+    }
+
+    /// Return the connection to the pool for reuse, unless bitcoind sent us more than the reply
+    /// we just read, in which case we can't tell where its next reply would begin
+    fn put_back(mut self) {
+        if let Some(reader) = self.reader.take() {
+            if reader.buffer().is_empty() {
+                self.pool.put_back(reader.into_inner());
+            } else {
+                debug!("Unexpected data after RPC reply; closing the connection");
+                self.pool.close();
+            }
+        }
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if self.reader.take().is_some() {
+            self.pool.close();
+        }
+    }
+}
+
+/// Client for bitcoind's JSON-RPC interface
+#[derive(Debug, Clone)]
+pub struct BitcoinRpcClient {
+    endpoint: RpcEndpoint,
+    /// Where to get the credentials to authenticate with, if we authenticate
+    credentials: Option<Arc<dyn RpcCredentials>>,
+    timeout: Duration,
+    /// How many times to retry a call that failed in transit
+    retries: u32,
+    next_id: u64,
+    /// Connections to bitcoind, shared with this client's clones
+    pool: Arc<RpcConnectionPool>,
+    /// Where to get blocks that bitcoind has pruned, if anywhere
+    pruned_fallback: Option<Box<BitcoinRpcClient>>,
+    /// SOCKS5 proxy to connect to bitcoind through, if any
+    proxy: Option<SocksProxy>,
+}
+
+impl BitcoinRpcClient {
+    /// Make a client for bitcoind at `host:port`.  `username` and `password` are the endpoint's
+    /// own, and go to the client's credentials (see `set_credentials()`).
+    pub fn new(
+        host: &str,
+        port: u16,
+        username: Option<&str>,
+        password: Option<&str>,
+        timeout: Duration,
+    ) -> BitcoinRpcClient {
+        BitcoinRpcClient {
+            endpoint: RpcEndpoint {
+                host: host.to_string(),
+                port,
+                username: username.map(|username| username.to_string()),
+                password: password.map(|password| password.to_string()),
+            },
+            credentials: None,
+            timeout,
+            retries: DEFAULT_RPC_RETRIES,
+            next_id: 0,
+            pool: Arc::new(RpcConnectionPool::new(DEFAULT_RPC_MAX_CONNECTIONS)),
+            pruned_fallback: None,
+            proxy: None,
+        }
+    }
+
+    /// Have at most `max_connections` connections open to bitcoind at once, across this client
+    /// and the clones made from it from now on.  Calls wait up to the client's timeout for a
+    /// connection to free up.
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.pool = Arc::new(RpcConnectionPool::new(max_connections));
+    }
+
+    /// Get the pool of this client's connections
+    pub fn connection_pool(&self) -> Arc<RpcConnectionPool> {
+        self.pool.clone()
+    }
+
+    /// Get blocks from `fallback` when bitcoind has pruned them.  Every other call, and every
+    /// block bitcoind still has, still goes to bitcoind.
+    pub fn set_pruned_block_fallback(&mut self, fallback: Option<BitcoinRpcClient>) {
+        self.pruned_fallback = fallback.map(Box::new);
+    }
+
+    /// Authenticate with the credentials that `credentials` supplies for this client's
+    /// endpoint (see `RpcCredentials`).  Without any, requests carry no `Authorization` header.
+    pub fn set_credentials(&mut self, credentials: Option<Arc<dyn RpcCredentials>>) {
+        self.credentials = credentials;
+    }
+
+    /// Retry a call that fails in transit up to `retries` times, with exponential backoff
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    /// Connect to bitcoind through the SOCKS5 proxy `proxy`, which resolves `host` for us (so it
+    /// can be a `.onion` address), instead of directly
+    pub fn set_socks_proxy(&mut self, proxy: Option<SocksProxy>) {
+        self.proxy = proxy;
+    }
+
+    /// Make a client for the JSON-RPC interface at the indexer's `peer_host:rpc_port`, which
+    /// authenticates with `credentials` (see `BitcoinIndexer::set_rpc_credentials()`)
+    pub fn from_config(
+        config: &BitcoinIndexerConfig,
+        credentials: Option<Arc<dyn RpcCredentials>>,
+    ) -> Result<BitcoinRpcClient, btc_error> {
+        if config.rpc_ssl {
+            return Err(btc_error::ConfigError(
+                "Cannot get headers over RPC with SSL enabled".to_string(),
+            ));
+        }
+        let timeout = Duration::from_secs(config.timeout as u64);
+        let mut client = BitcoinRpcClient::new(
+            &config.peer_host,
+            config.rpc_port,
+            config.username.as_deref(),
+            config.password.as_deref(),
+            timeout,
+        );
+        client.set_max_connections(config.rpc_max_connections);
+        client.set_socks_proxy(config.socks_proxy.clone());
+        client.set_credentials(credentials.clone());
+        client.set_retries(config.rpc_retries);
+        if let Some(ref endpoint) = config.pruned_block_fallback {
+            let mut fallback = BitcoinRpcClient::new(
+                &endpoint.host,
+                endpoint.port,
+                endpoint.username.as_deref(),
+                endpoint.password.as_deref(),
+                timeout,
+            );
+            fallback.set_max_connections(config.rpc_max_connections);
+            fallback.set_socks_proxy(config.socks_proxy.clone());
+            fallback.set_credentials(credentials);
+            fallback.set_retries(config.rpc_retries);
+            client.set_pruned_block_fallback(Some(fallback));
+        }
+        Ok(client)
+    }
+
+    /// Call a method, and get its result.  The call is sent again if it fails in transit, so
+    /// the method must be idempotent.
+    pub fn call(&mut self, method: &str, params: Value) -> Result<Value, btc_error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = json!({
+            "jsonrpc": "1.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        let reply = retry_rpc_call(self.retries, btc_error::is_transient, || {
+            self.post(&request)
+        })?;
+        BitcoinRpcClient::take_result(reply)
+    }
+
+    /// Call a method once with each of the given params in a single batch request, and get the
+    /// results in the same order.  Fails if any of the calls fails.  Like `call()`, the batch
+    /// is sent again if it fails in transit.
+    pub fn batch_call(
+        &mut self,
+        method: &str,
+        params: Vec<Value>,
+    ) -> Result<Vec<Value>, btc_error> {
+        if params.is_empty() {
+            return Ok(vec![]);
+        }
+        let first_id = self.next_id;
+        let num_calls = params.len();
+        self.next_id += num_calls as u64;
+
+        let ids: Vec<Value> = (0..num_calls)
+            .map(|i| json!(first_id + (i as u64)))
+            .collect();
+        let requests: Vec<Value> = params
+            .into_iter()
+            .zip(ids.iter())
+            .map(|(params, id)| {
+                json!({
+                    "jsonrpc": "1.0",
+                    "id": id,
+                    "method": method,
+                    "params": params,
+                })
+            })
+            .collect();
+        let request = Value::Array(requests);
+
+        let replies = retry_rpc_call(self.retries, btc_error::is_transient, || {
+            self.post(&request)
+        })?;
+        split_batch_replies(&ids, replies)?
+            .into_iter()
+            .map(|reply| match reply {
+                BatchReply::Result(result) => Ok(result),
+                BatchReply::Error(e) => Err(BitcoinRpcClient::reply_error(&e)),
+                BatchReply::Missing => Err(btc_error::RpcError(format!(
+                    "Missing reply in batch of {}",
+                    method
+                ))),
+            })
+            .collect()
+    }
+
+    /// Get the result out of a reply, or its error
+    fn take_result(mut reply: Value) -> Result<Value, btc_error> {
+        match reply.get("error") {
+            None | Some(Value::Null) => {}
+            Some(e) => {
+                return Err(BitcoinRpcClient::reply_error(e));
+            }
+        }
+        match reply.get_mut("result") {
+            Some(result) => Ok(result.take()),
+            None => Err(btc_error::RpcError(format!(
+                "Reply has no result: {}",
+                reply
+            ))),
+        }
+    }
+
+    /// Get our error for the `error` in one of bitcoind's replies
+    fn reply_error(error: &Value) -> btc_error {
+        if BitcoinRpcClient::is_pruned_error(error) {
+            btc_error::BlockPruned(error.to_string())
+        } else {
+            btc_error::RpcError(error.to_string())
+        }
+    }
+
+    /// Is this the error bitcoind gives for a block it has pruned?  It's reported with the
+    /// catch-all RPC_MISC_ERROR code, so the message tells it apart.
+    fn is_pruned_error(error: &Value) -> bool {
+        error.get("code").and_then(|code| code.as_i64()) == Some(RPC_MISC_ERROR)
+            && error
+                .get("message")
+                .and_then(|message| message.as_str())
+                .map(|message| message.contains("pruned data"))
+                .unwrap_or(false)
+    }
+
+    /// Connect to bitcoind, trying each address its host resolves to in turn
+    fn connect(&self) -> Result<TcpStream, btc_error> {
+        let host = unbracket_host(&self.endpoint.host);
+        if let Some(ref proxy) = self.proxy {
+            return socks::connect(
+                proxy,
+                host,
+                self.endpoint.port,
+                Some(self.timeout),
+                self.timeout,
+            );
+        }
+        let mut last_error = None;
+        for addr in (host, self.endpoint.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(sock) => {
+                    sock.set_read_timeout(Some(self.timeout))?;
+                    sock.set_write_timeout(Some(self.timeout))?;
+                    return Ok(sock);
+                }
+                Err(e) => {
+                    debug!("Bitcoin RPC: failed to connect to {}: {:?}", &addr, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) => Err(e.into()),
+            None => Err(btc_error::ConfigError(format!(
+                "Failed to resolve {}",
+                &self.endpoint.host
+            ))),
+        }
+    }
+
+    /// Get bitcoind's chain and how far along its own sync is, via `getblockchaininfo`
+    pub fn get_blockchain_info(&mut self) -> Result<BlockchainInfo, btc_error> {
+        let info = self.call("getblockchaininfo", json!([]))?;
+        let invalid = || btc_error::RpcError(format!("Invalid getblockchaininfo result: {}", info));
+        Ok(BlockchainInfo {
+            chain: info
+                .get("chain")
+                .and_then(|chain| chain.as_str())
+                .ok_or_else(invalid)?
+                .to_string(),
+            headers: info
+                .get("headers")
+                .and_then(|headers| headers.as_u64())
+                .ok_or_else(invalid)?,
+            blocks: info
+                .get("blocks")
+                .and_then(|blocks| blocks.as_u64())
+                .ok_or_else(invalid)?,
+            initial_block_download: info
+                .get("initialblockdownload")
+                .and_then(|ibd| ibd.as_bool())
+                .ok_or_else(invalid)?,
+        })
+    }
+
+    /// Get a block, via `getblock` with verbosity 0.  The whole block is held in memory (twice,
+    /// while it's decoded from hex); see `get_block_stream()` for large blocks.
+    pub fn get_block(&mut self, block_hash: &Sha256dHash) -> Result<Block, btc_error> {
+        let block_bytes = self.get_raw_block(block_hash)?;
+        Ok(deserialize(&block_bytes)?)
+    }
+
+    /// Get a block's serialized bytes, via `getblock` with verbosity 0.  If bitcoind has pruned
+    /// it, the block comes from the pruned block fallback instead (if there is one).
+    pub fn get_raw_block(&mut self, block_hash: &Sha256dHash) -> Result<Vec<u8>, btc_error> {
+        match (
+            self.inner_get_raw_block(block_hash),
+            self.pruned_fallback.as_mut(),
+        ) {
+            (Err(btc_error::BlockPruned(e)), Some(fallback)) => {
+                debug!(
+                    "Get pruned block {} from fallback: {}",
+                    block_hash.be_hex_string(),
+                    &e
+                );
+                fallback.get_raw_block(block_hash)
+            }
+            (res, _) => res,
+        }
+    }
+
+    fn inner_get_raw_block(&mut self, block_hash: &Sha256dHash) -> Result<Vec<u8>, btc_error> {
+        let block_hex = self.call("getblock", json!([block_hash.be_hex_string(), 0]))?;
+        block_hex
+            .as_str()
+            .and_then(|block_hex| hex_bytes(block_hex).ok())
+            .ok_or_else(|| btc_error::RpcError(format!("Invalid getblock result: {}", block_hex)))
+    }
+
+    /// Get bitcoind's proof that the transaction `txid` is in the block `block_hash`, via
+    /// `gettxoutproof`, as the transaction's position in the block and its merkle branch (see
+    /// `bloom::merkle_block_branch()`).  The proof is only checked against the header bitcoind
+    /// sends with it, so check it against our own header with `blocks::verify_merkle_proof()`.
+    pub fn get_tx_merkle_proof(
+        &mut self,
+        txid: &Sha256dHash,
+        block_hash: &Sha256dHash,
+    ) -> Result<(u32, Vec<Sha256dHash>), btc_error> {
+        let proof_hex = self.call(
+            "gettxoutproof",
+            json!([[txid.be_hex_string()], block_hash.be_hex_string()]),
+        )?;
+        let proof_bytes = proof_hex
+            .as_str()
+            .and_then(|proof_hex| hex_bytes(proof_hex).ok())
+            .ok_or_else(|| {
+                btc_error::RpcError(format!("Invalid gettxoutproof result: {}", proof_hex))
+            })?;
+        let merkle_block: MerkleBlock = deserialize(&proof_bytes)?;
+        if merkle_block.header.bitcoin_hash() != *block_hash {
+            return Err(btc_error::InvalidMerkleProof(format!(
+                "gettxoutproof gave a proof for block {}, not {}",
+                merkle_block.header.bitcoin_hash().be_hex_string(),
+                block_hash.be_hex_string()
+            )));
+        }
+        bloom::merkle_block_branch(&merkle_block, txid)?.ok_or_else(|| {
+            btc_error::InvalidMerkleProof(format!(
+                "gettxoutproof gave a proof that doesn't include {}",
+                txid.be_hex_string()
+            ))
+        })
+    }
+
+    /// Get a block via `getblock` with verbosity 0, and pass `consume` a reader of its bytes,
+    /// which are decoded from hex as they arrive from bitcoind rather than buffered.  `consume`
+    /// must read the whole block.  If bitcoind has pruned the block, it comes from the pruned
+    /// block fallback instead (if there is one).
+    pub fn get_block_stream<F, T>(
+        &mut self,
+        block_hash: &Sha256dHash,
+        consume: F,
+    ) -> Result<T, btc_error>
+    where
+        F: FnOnce(&mut dyn Read) -> Result<T, btc_error>,
+    {
+        // bitcoind says it pruned the block before we'd hand it to `consume`
+        let mut consume = Some(consume);
+        match (
+            self.inner_get_block_stream(block_hash, &mut consume),
+            consume,
+            self.pruned_fallback.as_mut(),
+        ) {
+            (Err(btc_error::BlockPruned(e)), Some(consume), Some(fallback)) => {
+                debug!(
+                    "Get pruned block {} from fallback: {}",
+                    block_hash.be_hex_string(),
+                    &e
+                );
+                fallback.get_block_stream(block_hash, consume)
+            }
+            (res, _, _) => res,
+        }
+    }
+
+    fn inner_get_block_stream<F, T>(
+        &mut self,
+        block_hash: &Sha256dHash,
+        consume: &mut Option<F>,
+    ) -> Result<T, btc_error>
+    where
+        F: FnOnce(&mut dyn Read) -> Result<T, btc_error>,
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        let (mut conn, head) = self.send(&json!({
+            "jsonrpc": "1.0",
+            "id": id,
+            "method": "getblock",
+            "params": [block_hash.be_hex_string(), 0],
+        }))?;
+        let mut reader = conn.reader().take(head.content_length.unwrap_or(u64::MAX));
+
+        // the block is streamed out of the reply's result, if that's a (hex) string.  The
+        // reply's other members are kept, to tell why if it isn't.
+        let mut envelope = EnvelopeReader::new(&mut reader);
+        let mut members = serde_json::Map::new();
+        let mut res = None;
+        while let Some(name) = envelope.next_member()? {
+            if name == "result" && res.is_none() && envelope.peek()? == Some(b'"') {
+                envelope.inner.consume(1);
+                let consume = consume.take().expect("BUG: block already consumed");
+                let mut hex_reader = HexReader::new(&mut envelope.inner);
+                res = Some(consume(&mut hex_reader)?);
+                if !hex_reader.at_end()? {
+                    return Err(btc_error::RpcError(
+                        "getblock result has data past the end of the block".to_string(),
+                    ));
+                }
+                hex_reader.finish()?;
+            } else {
+                let value = envelope.read_value()?;
+                members.insert(name, value);
+            }
+        }
+        let res = match res {
+            Some(res) => res,
+            None => {
+                let result = BitcoinRpcClient::take_result(Value::Object(members))?;
+                return Err(btc_error::RpcError(format!(
+                    "Invalid getblock result {}",
+                    result
+                )));
+            }
+        };
+        if let Some(e) = members.get("error") {
+            if !e.is_null() {
+                return Err(BitcoinRpcClient::reply_error(e));
+            }
+        }
+
+        // read the rest of the reply, so the connection can be reused
+        io::copy(&mut reader, &mut io::sink()).map_err(map_io_error)?;
+        if head.reusable() {
+            conn.put_back();
+        }
+        Ok(res)
+    }
+
+    /// POST a JSON-RPC request, and get the JSON reply
+    fn post(&self, request: &Value) -> Result<Value, btc_error> {
+        let (mut conn, head) = self.send(request)?;
+        let reply = read_http_body(conn.reader(), &head)?;
+        if head.reusable() {
+            conn.put_back();
+        }
+        Ok(reply)
+    }
+
+    /// Get the `Authorization` header value to send, if any.  The credentials are asked for
+    /// anew every time.
+    fn authorization(&self) -> Result<Option<String>, btc_error> {
+        match self.credentials {
+            Some(ref credentials) => credentials.authorization(&self.endpoint),
+            None => Ok(None),
+        }
+    }
+
+    /// POST a JSON-RPC request on a pooled connection, and get the connection to read the body
+    /// of the reply from, along with the head of the reply.  If the connection was reused and
+    /// bitcoind has closed it since, the request is sent again on another one.  A request that
+    /// bitcoind rejects as unauthorized is sent once more, with the credentials asked for again.
+    fn send(&self, request: &Value) -> Result<(PooledConnection, HttpReplyHead), btc_error> {
+        let body = request.to_string();
+        let mut can_retry_auth = self.credentials.is_some();
+        loop {
+            let (conn, head) = self.send_body(&body)?;
+            if head.is_unauthorized() && can_retry_auth {
+                warn!("Bitcoin RPC: authentication failed; asking for credentials again");
+                can_retry_auth = false;
+                continue;
+            }
+            return Ok((conn, head));
+        }
+    }
+
+    /// POST a JSON-RPC request body on a pooled connection (see `send()`)
+    fn send_body(&self, body: &str) -> Result<(PooledConnection, HttpReplyHead), btc_error> {
+        let mut http_request = format!(
+            "POST / HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: keep-alive\r\n",
+            &self.endpoint.host,
+            self.endpoint.port,
+            body.len()
+        );
+        if let Some(auth) = self.authorization()? {
+            http_request.push_str(&format!("Authorization: {}\r\n", auth));
+        }
+        http_request.push_str("\r\n");
+        http_request.push_str(body);
+
+        loop {
+            let mut conn = RpcConnectionPool::get(&self.pool, self.timeout, || self.connect())?;
+            let sent = {
+                let sock = conn.reader().get_mut();
+                sock.write_all(http_request.as_bytes())
+                    .and_then(|_| sock.flush())
+                    .map_err(map_io_error)
+            };
+            let res = sent.and_then(|_| read_http_head(conn.reader()));
+            match res {
+                Ok(head) => return Ok((conn, head)),
+                Err(btc_error::TimedOut) => return Err(btc_error::TimedOut),
+                Err(e) if conn.reused => {
+                    debug!("Reused RPC connection failed ({}); trying another", &e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// The error for a reply that isn't a well-formed JSON-RPC reply, because of `what`
+fn malformed_reply(what: &str) -> btc_error {
+    btc_error::RpcError(format!("Malformed JSON-RPC reply: {}", what))
+}
+
+/// Reads the JSON object that a JSON-RPC reply consists of one member at a time, so that a
+/// member's value can be streamed (e.g. through a `HexReader`) instead of parsed whole
+struct EnvelopeReader<R: BufRead> {
+    inner: R,
+    /// Whether we've read the opening brace
+    started: bool,
+    /// Whether we've read the closing brace
+    done: bool,
+}
+
+impl<R: BufRead> EnvelopeReader<R> {
+    fn new(inner: R) -> EnvelopeReader<R> {
+        EnvelopeReader {
+            inner,
+            started: false,
+            done: false,
+        }
+    }
+
+    /// Skip any whitespace, and get the next byte without reading it
+    fn peek(&mut self) -> Result<Option<u8>, btc_error> {
+        loop {
+            match self.inner.fill_buf().map_err(map_io_error)?.first() {
+                Some(c) if c.is_ascii_whitespace() => self.inner.consume(1),
+                next => {
+                    return Ok(next.cloned());
+                }
+            }
+        }
+    }
+
+    /// Skip any whitespace, and read `byte`
+    fn expect(&mut self, byte: u8) -> Result<(), btc_error> {
+        match self.peek()? {
+            Some(next) if next == byte => {
+                self.inner.consume(1);
+                Ok(())
+            }
+            Some(next) => Err(malformed_reply(&format!(
+                "expected {:?}, got {:?}",
+                byte as char, next as char
+            ))),
+            None => Err(malformed_reply("truncated")),
+        }
+    }
+
+    /// Read up to the value of the next member, and get the member's name.  None if there are
+    /// no more members.  The value must be read before the next member.
+    fn next_member(&mut self) -> Result<Option<String>, btc_error> {
+        if self.done {
+            return Ok(None);
+        }
+        if !self.started {
+            self.expect(b'{')?;
+            self.started = true;
+            if self.peek()? == Some(b'}') {
+                self.inner.consume(1);
+                self.done = true;
+                return Ok(None);
+            }
+        } else {
+            match self.peek()? {
+                Some(b',') => self.inner.consume(1),
+                Some(b'}') => {
+                    self.inner.consume(1);
+                    self.done = true;
+                    return Ok(None);
+                }
+                _ => {
+                    return Err(malformed_reply("expected ',' or '}'"));
+                }
+            }
+        }
+        let name = match self.read_value()? {
+            Value::String(name) => name,
+            name => {
+                return Err(malformed_reply(&format!(
+                    "member name {} is not a string",
+                    name
+                )));
+            }
+        };
+        self.expect(b':')?;
+        Ok(Some(name))
+    }
+
+    /// Read the next JSON value whole
+    fn read_value(&mut self) -> Result<Value, btc_error> {
+        self.peek()?;
+        let mut bytes = vec![];
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        loop {
+            let c = match self.inner.fill_buf().map_err(map_io_error)?.first() {
+                Some(c) => *c,
+                None => break,
+            };
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if c == b'\\' {
+                    escaped = true;
+                } else if c == b'"' {
+                    in_string = false;
+                }
+            } else if depth == 0 && (c == b',' || c == b'}' || c == b']' || c.is_ascii_whitespace())
+            {
+                // the end of a number or literal
+                break;
+            } else if c == b'"' {
+                in_string = true;
+            } else if c == b'{' || c == b'[' {
+                depth += 1;
+            } else if c == b'}' || c == b']' {
+                depth -= 1;
+            }
+            bytes.push(c);
+            self.inner.consume(1);
+            if depth == 0 && !in_string && (c == b'"' || c == b'}' || c == b']') {
+                break;
+            }
+        }
+        serde_json::from_slice(&bytes).map_err(|_| {
+            malformed_reply(&format!(
+                "invalid value {}",
+                String::from_utf8_lossy(&bytes)
+            ))
+        })
+    }
+}
+
+/// Reads the bytes encoded by the hex digits of a JSON string, up to its closing quote
+struct HexReader<R: BufRead> {
+    inner: R,
+    /// High nibble of a byte whose low nibble we haven't read yet
+    high_nibble: Option<u8>,
+    /// Whether we've read the closing quote
+    done: bool,
+}
+
+impl<R: BufRead> HexReader<R> {
+    fn new(inner: R) -> HexReader<R> {
+        HexReader {
+            inner,
+            high_nibble: None,
+            done: false,
+        }
+    }
+
+    /// Read the closing quote, which must be next (see `at_end()`)
+    fn finish(&mut self) -> Result<(), btc_error> {
+        if !self.at_end()? {
+            return Err(btc_error::RpcError(
+                "Hex string continues past its end".to_string(),
+            ));
+        }
+        if !self.done {
+            self.inner.consume(1);
+            self.done = true;
+        }
+        Ok(())
+    }
+
+    /// Is the next thing to read the closing quote (or has it been read)?
+    fn at_end(&mut self) -> Result<bool, btc_error> {
+        if self.done {
+            return Ok(true);
+        }
+        let next = self
+            .inner
+            .fill_buf()
+            .map_err(map_io_error)?
+            .first()
+            .cloned();
+        Ok(self.high_nibble.is_none() && next == Some(b'"'))
+    }
+}
+
+impl<R: BufRead> Read for HexReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut nread = 0;
+        while nread < buf.len() && !self.done {
+            let available = self.inner.fill_buf()?;
+            if available.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Truncated hex string",
+                ));
+            }
+            let mut used = 0;
+            for c in available.iter() {
+                used += 1;
+                if *c == b'"' {
+                    if self.high_nibble.is_some() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Odd number of hex digits",
+                        ));
+                    }
+                    self.done = true;
+                    break;
+                }
+                let nibble = match *c {
+                    b'0'..=b'9' => c - b'0',
+                    b'a'..=b'f' => c - b'a' + 10,
+                    b'A'..=b'F' => c - b'A' + 10,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Invalid hex digit {:?}", *c as char),
+                        ));
+                    }
+                };
+                match self.high_nibble.take() {
+                    None => {
+                        self.high_nibble = Some(nibble);
+                    }
+                    Some(high_nibble) => {
+                        buf[nread] = (high_nibble << 4) | nibble;
+                        nread += 1;
+                        if nread == buf.len() {
+                            break;
+                        }
+                    }
+                }
+            }
+            self.inner.consume(used);
+        }
+        Ok(nread)
+    }
+}
+
+/// Report a timed-out read or write as such
+fn map_io_error(e: io::Error) -> btc_error {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => btc_error::TimedOut,
+        _ => btc_error::Io(e),
+    }
+}
+
+/// The parts of the head of an HTTP reply that we use
+struct HttpReplyHead {
+    status: String,
+    content_length: Option<u64>,
+    /// Whether bitcoind keeps the connection open after the reply
+    keep_alive: bool,
+}
+
+impl HttpReplyHead {
+    /// Can the connection be used for another call once the body has been read?  Without a
+    /// Content-Length, the body runs until bitcoind closes the connection.
+    fn reusable(&self) -> bool {
+        self.keep_alive && self.content_length.is_some()
+    }
+
+    /// Did bitcoind refuse our credentials?
+    fn is_unauthorized(&self) -> bool {
+        self.status.split_whitespace().nth(1) == Some("401")
+    }
+}
+
+/// Read the status line and headers of an HTTP reply, up to its body
+fn read_http_head<R: BufRead>(reader: &mut R) -> Result<HttpReplyHead, btc_error> {
+    let mut status = String::new();
+    let mut content_length = None;
+    let mut connection = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(map_io_error)? == 0 {
+            if status.is_empty() {
+                // bitcoind hung up without replying
+                return Err(btc_error::ConnectionBroken);
+            }
+            return Err(btc_error::RpcError("Truncated HTTP reply".to_string()));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if status.is_empty() {
+            status = line.to_string();
+            continue;
+        }
+        let mut parts = line.splitn(2, ':');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name.trim(), value.trim()),
+            _ => continue,
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length =
+                Some(value.parse::<u64>().map_err(|_| {
+                    btc_error::RpcError(format!("Invalid Content-Length: {}", value))
+                })?);
+        } else if name.eq_ignore_ascii_case("connection") {
+            connection = Some(value.to_ascii_lowercase());
+        } else if name.eq_ignore_ascii_case("transfer-encoding")
+            && !value.eq_ignore_ascii_case("identity")
+        {
+            return Err(btc_error::RpcError(format!(
+                "Unsupported Transfer-Encoding: {}",
+                value
+            )));
+        }
+    }
+
+    // HTTP/1.1 connections stay open unless either side says otherwise; HTTP/1.0 ones don't
+    let keep_alive = match connection.as_deref() {
+        Some("close") => false,
+        Some("keep-alive") => true,
+        _ => !status.starts_with("HTTP/1.0"),
+    };
+    Ok(HttpReplyHead {
+        status,
+        content_length,
+        keep_alive,
+    })
+}
+
+/// Read the JSON body of an HTTP reply whose head is `head`.  bitcoind replies to failed calls
+/// with an error status and a JSON body carrying the error, so the status only matters if
+/// there's no JSON body.
+fn read_http_body<R: BufRead>(reader: &mut R, head: &HttpReplyHead) -> Result<Value, btc_error> {
+    let mut body = vec![];
+    match head.content_length {
+        Some(len) => {
+            reader
+                .by_ref()
+                .take(len)
+                .read_to_end(&mut body)
+                .map_err(map_io_error)?;
+            if (body.len() as u64) < len {
+                return Err(btc_error::RpcError("Truncated HTTP reply".to_string()));
+            }
+        }
+        None => {
+            reader.read_to_end(&mut body).map_err(map_io_error)?;
+        }
+    }
+    serde_json::from_slice(&body)
+        .map_err(|_| btc_error::RpcError(format!("Non-JSON reply with status {}", &head.status)))
+}
+
+/// Headers from bitcoind's JSON-RPC interface, via `getblockhash` and `getblockheader`
+impl HeaderSource for BitcoinRpcClient {
+    fn get_tip_height(&mut self) -> Result<u64, btc_error> {
+        let count = self.call("getblockcount", json!([]))?;
+        count
+            .as_u64()
+            .ok_or_else(|| btc_error::RpcError(format!("Invalid getblockcount result: {}", count)))
+    }
+
+    fn get_headers(
+        &mut self,
+        locator: Vec<Sha256dHash>,
+    ) -> Result<Vec<LoneBlockHeader>, btc_error> {
+        // find the highest locator header on bitcoind's best chain.  Like getheaders, start
+        // from genesis if there isn't one.
+        let mut start_height = 0;
+        for hash in locator.iter() {
+            let info = match self.call("getblockheader", json!([hash.be_hex_string(), true])) {
+                Ok(info) => info,
+                Err(btc_error::RpcError(_)) => {
+                    // bitcoind doesn't have it
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            };
+            // headers off the best chain have -1 confirmations
+            if info
+                .get("confirmations")
+                .and_then(|c| c.as_i64())
+                .unwrap_or(-1)
+                < 0
+            {
+                continue;
+            }
+            start_height = info.get("height").and_then(|h| h.as_u64()).ok_or_else(|| {
+                btc_error::RpcError(format!("Invalid getblockheader result: {}", info))
+            })?;
+            break;
+        }
+
+        let tip_height = self.get_tip_height()?;
+        let end_height = std::cmp::min(tip_height, start_height + MAX_HEADERS_PER_REQUEST);
+        if end_height <= start_height {
+            return Ok(vec![]);
+        }
+
+        let hashes = self.batch_call(
+            "getblockhash",
+            ((start_height + 1)..(end_height + 1))
+                .map(|height| json!([height]))
+                .collect(),
+        )?;
+        let headers_hex = self.batch_call(
+            "getblockheader",
+            hashes
+                .into_iter()
+                .map(|hash| json!([hash, false]))
+                .collect(),
+        )?;
+
+        // these are validated like any other headers when they're stored
+        let mut headers = Vec::with_capacity(headers_hex.len());
+        for header_hex in headers_hex.into_iter() {
+            let header_bytes = header_hex.as_str().map(hex_bytes).ok_or_else(|| {
+                btc_error::RpcError(format!("Invalid getblockheader result: {}", header_hex))
+            })??;
+            let header: BlockHeader = deserialize(&header_bytes)?;
+            headers.push(LoneBlockHeader {
+                header,
+                tx_count: VarInt(0),
+            });
+        }
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::burnchains::bitcoin::blocks::{
+        verify_merkle_proof, BitcoinBlockParser, BitcoinBlockReader,
+    };
+    use crate::burnchains::bitcoin::indexer::{
+        BitcoinIndexer, BitcoinIndexerRuntime, HeaderSourceType, RpcEndpoint, SyncMode,
+    };
+    use crate::burnchains::bitcoin::spv::SpvClient;
+    use crate::burnchains::bitcoin::BitcoinNetworkType;
+    use crate::burnchains::indexer::{
+        BurnBlockIPC, BurnchainBlockDownloader, BurnchainBlockParser, BurnchainIndexer,
+    };
+    use crate::burnchains::tests::{make_regtest_block_chain, make_regtest_header_chain};
+    use crate::burnchains::Error as burnchain_error;
+    use crate::burnchains::MagicBytes;
+    use crate::core::StacksEpochId;
+    use stacks_common::deps_common::bitcoin::blockdata::script::Script;
+    use stacks_common::deps_common::bitcoin::blockdata::transaction::{
+        OutPoint, Transaction, TxIn, TxOut,
+    };
+    use stacks_common::deps_common::bitcoin::network::message::NetworkMessage;
+    use stacks_common::deps_common::bitcoin::network::serialize::{serialize, BitcoinHash};
+    use stacks_common::deps_common::bitcoin::util::hash::bitcoin_merkle_root;
+    use stacks_common::util::hash::to_hex;
+    use std::fs;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    /// Get the JSON body of a complete HTTP message
+    fn parse_http_reply(http_reply: &[u8]) -> Result<Value, btc_error> {
+        let mut reader = http_reply;
+        let head = read_http_head(&mut reader)?;
+        read_http_body(&mut reader, &head)
+    }
+
+    /// Like `mock_rpc_reply()`, with `blocks` as bitcoind's best chain (after genesis), and
+    /// answering `getblock` too
+    fn mock_rpc_block_reply(blocks: &[Block], request: &Value) -> Value {
+        if request["method"] != "getblock" {
+            let mut headers = vec![SpvClient::get_genesis_header(BitcoinNetworkType::Regtest)];
+            headers.extend(blocks.iter().map(|block| block.header));
+            return mock_rpc_reply(&headers, request);
+        }
+        let id = request["id"].clone();
+        assert_eq!(request["params"][1], 0);
+        match blocks
+            .iter()
+            .find(|block| request["params"][0] == block.bitcoin_hash().be_hex_string())
+        {
+            Some(block) => {
+                json!({"result": to_hex(&serialize(block).unwrap()), "error": null, "id": id})
+            }
+            None => {
+                json!({"result": null, "error": {"code": -5, "message": "Block not found"}, "id": id})
+            }
+        }
+    }
+
+    /// Answer one JSON-RPC call the way bitcoind would, with `headers` as its best chain
+    /// (genesis first)
+    fn mock_rpc_reply(headers: &[BlockHeader], request: &Value) -> Value {
+        let id = request["id"].clone();
+        let params = &request["params"];
+        let find_height = |hash: &str| {
+            headers
+                .iter()
+                .position(|h| h.bitcoin_hash().be_hex_string() == hash)
+        };
+        let result = match request["method"].as_str().unwrap() {
+            "getblockchaininfo" => json!({
+                "chain": "regtest",
+                "headers": headers.len() - 1,
+                "blocks": headers.len() - 1,
+                "initialblockdownload": false,
+            }),
+            "getblockcount" => json!(headers.len() - 1),
+            "getblockhash" => match headers.get(params[0].as_u64().unwrap() as usize) {
+                Some(header) => json!(header.bitcoin_hash().be_hex_string()),
+                None => Value::Null,
+            },
+            "getblockheader" => match find_height(params[0].as_str().unwrap()) {
+                Some(height) if params[1].as_bool().unwrap() => json!({
+                    "height": height,
+                    "confirmations": headers.len() - height,
+                }),
+                Some(height) => json!(to_hex(&serialize(&headers[height]).unwrap())),
+                None => Value::Null,
+            },
+            method => panic!("Unexpected method {}", method),
+        };
+        if result.is_null() {
+            json!({"result": null, "error": {"code": -5, "message": "Block not found"}, "id": id})
+        } else {
+            json!({"result": result, "error": null, "id": id})
+        }
+    }
+
+    /// Serve JSON-RPC requests on a local port forever, answering each call with `reply`.
+    /// Batches are answered in reverse order.  Connections are kept alive and served
+    /// concurrently, like bitcoind does.  If `reply` returns null, the connection is closed
+    /// without a reply instead.
+    fn spawn_mock_rpc_server<F>(reply: F) -> u16
+    where
+        F: Fn(&Value) -> Value + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let reply = Arc::new(reply);
+        thread::spawn(move || loop {
+            let (sock, _) = listener.accept().unwrap();
+            let reply = reply.clone();
+            thread::spawn(move || serve_mock_rpc_connection(sock, &*reply));
+        });
+        port
+    }
+
+    fn serve_mock_rpc_connection<F>(mut sock: TcpStream, reply: &F)
+    where
+        F: Fn(&Value) -> Value,
+    {
+        let mut buf = [0u8; 4096];
+        loop {
+            let mut http_request = vec![];
+            let request = loop {
+                let nread = match sock.read(&mut buf) {
+                    Ok(nread) => nread,
+                    Err(_) => return,
+                };
+                if nread == 0 {
+                    // the client hung up
+                    assert!(
+                        http_request.is_empty(),
+                        "connection closed before end of request"
+                    );
+                    return;
+                }
+                http_request.extend_from_slice(&buf[..nread]);
+                // requests and replies look alike past the first line, and our requests always
+                // have a Content-Length
+                if let Ok(request) = parse_http_reply(&http_request) {
+                    break request;
+                }
+            };
+            let reply_body = match request {
+                Value::Array(requests) => {
+                    Value::Array(requests.iter().rev().map(|r| reply(r)).collect())
+                }
+                request => reply(&request),
+            };
+            if reply_body.is_null() {
+                return;
+            }
+            let reply_body = reply_body.to_string();
+            let http_reply = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                reply_body.len(),
+                reply_body
+            );
+            // the client may hang up before reading the whole reply
+            if sock.write_all(http_reply.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Serve one JSON-RPC call on each of the next `num_conns` connections to a local port.
+    /// `reply` gets the connection's number and the call's Authorization header, and returns
+    /// the HTTP status and body to reply with, or None to hang up without replying.
+    fn spawn_one_shot_rpc_server<F>(num_conns: usize, reply: F) -> (u16, thread::JoinHandle<()>)
+    where
+        F: Fn(usize, Option<String>) -> Option<(&'static str, String)> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            for i in 0..num_conns {
+                let (sock, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(sock);
+                let mut auth = None;
+                let mut content_length = 0;
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("Authorization: ") {
+                        auth = Some(value.to_string());
+                    } else if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        content_length = value.parse().unwrap();
+                    }
+                }
+                let mut body = vec![0u8; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                if let Some((status, reply_body)) = reply(i, auth) {
+                    let http_reply = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        reply_body.len(),
+                        reply_body
+                    );
+                    reader.get_mut().write_all(http_reply.as_bytes()).unwrap();
+                }
+            }
+        });
+        (port, server)
+    }
+
+    /// Credentials that move on to the next of `passwords` every time they're asked for, until
+    /// the last, like a cookie that bitcoind rotated
+    struct RotatingCredentials {
+        passwords: Mutex<Vec<&'static str>>,
+        endpoints: Mutex<Vec<RpcEndpoint>>,
+    }
+
+    impl RpcCredentials for RotatingCredentials {
+        fn authorization(&self, endpoint: &RpcEndpoint) -> Result<Option<String>, btc_error> {
+            self.endpoints.lock().unwrap().push(endpoint.clone());
+            let mut passwords = self.passwords.lock().unwrap();
+            let password = match passwords.first() {
+                Some(password) => *password,
+                None => return Err(btc_error::ConfigError("No cookie".to_string())),
+            };
+            if passwords.len() > 1 {
+                passwords.remove(0);
+            }
+            Ok(Some(format!("Basic __cookie__:{}", password)))
+        }
+    }
+
+    #[test]
+    fn test_rpc_credentials() {
+        // bitcoind restarted with a new cookie just before our first call
+        let (port, server) = spawn_one_shot_rpc_server(5, move |_, auth| {
+            if auth.as_deref() == Some("Basic __cookie__:newpassword") {
+                let reply = json!({"result": 123, "error": null, "id": 0});
+                return Some(("200 OK", reply.to_string()));
+            }
+            Some(("401 Unauthorized", "".to_string()))
+        });
+
+        let credentials = Arc::new(RotatingCredentials {
+            passwords: Mutex::new(vec!["oldpassword", "newpassword"]),
+            endpoints: Mutex::new(vec![]),
+        });
+        let mut client = BitcoinRpcClient::new(
+            "127.0.0.1",
+            port,
+            Some("alice"),
+            Some("hunter2"),
+            Duration::from_secs(5),
+        );
+        client.set_credentials(Some(credentials.clone()));
+
+        // the credentials are asked for again when the call is refused...
+        assert_eq!(client.call("getblockcount", json!([])).unwrap(), json!(123));
+        assert_eq!(client.call("getblockcount", json!([])).unwrap(), json!(123));
+
+        // ...but only once
+        *credentials.passwords.lock().unwrap() = vec!["wrongpassword"];
+        match client.call("getblockcount", json!([])) {
+            Err(btc_error::RpcError(e)) => assert!(e.contains("401")),
+            x => panic!("Expected RpcError, got {:?}", x),
+        }
+        server.join().unwrap();
+
+        // the credentials are for the client's endpoint
+        assert!(credentials
+            .endpoints
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|endpoint| *endpoint
+                == RpcEndpoint {
+                    host: "127.0.0.1".to_string(),
+                    port,
+                    username: Some("alice".to_string()),
+                    password: Some("hunter2".to_string()),
+                }));
+
+        // a client can't call without its credentials, and doesn't retry for them
+        *credentials.passwords.lock().unwrap() = vec![];
+        match client.call("getblockcount", json!([])) {
+            Err(btc_error::ConfigError(_)) => {}
+            x => panic!("Missing credentials accepted: {:?}", x),
+        }
+
+        // without credentials, no Authorization header is sent
+        let (port, server) = spawn_one_shot_rpc_server(1, |_, auth| {
+            assert_eq!(auth, None);
+            let reply = json!({"result": 123, "error": null, "id": 0});
+            Some(("200 OK", reply.to_string()))
+        });
+        let mut client = BitcoinRpcClient::new(
+            "127.0.0.1",
+            port,
+            Some("alice"),
+            Some("hunter2"),
+            Duration::from_secs(5),
+        );
+        assert_eq!(client.call("getblockcount", json!([])).unwrap(), json!(123));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_rpc_call_retries() {
+        let reply = json!({"result": 123, "error": null, "id": 0}).to_string();
+
+        // the first two connections drop before replying
+        let (port, server) = spawn_one_shot_rpc_server(3, move |i, _| {
+            if i >= 2 {
+                Some(("200 OK", reply.clone()))
+            } else {
+                None
+            }
+        });
+        let mut client =
+            BitcoinRpcClient::new("127.0.0.1", port, None, None, Duration::from_secs(5));
+        assert_eq!(client.call("getblockcount", json!([])).unwrap(), json!(123));
+        server.join().unwrap();
+
+        // without retries, the first failure is final
+        let (port, server) = spawn_one_shot_rpc_server(1, |_, _| None);
+        let mut client =
+            BitcoinRpcClient::new("127.0.0.1", port, None, None, Duration::from_secs(5));
+        client.set_retries(0);
+        match client.call("getblockcount", json!([])) {
+            Err(btc_error::ConnectionBroken) => {}
+            x => panic!("Expected ConnectionBroken, got {:?}", x),
+        }
+        server.join().unwrap();
+
+        // errors from bitcoind aren't retried (a retry would find the server gone)
+        let (port, server) = spawn_one_shot_rpc_server(1, |_, _| {
+            let reply = json!({
+                "result": null,
+                "error": {"code": -5, "message": "Block not found"},
+                "id": 0
+            });
+            Some(("500 Internal Server Error", reply.to_string()))
+        });
+        let mut client =
+            BitcoinRpcClient::new("127.0.0.1", port, None, None, Duration::from_secs(5));
+        match client.call("getblockheader", json!(["00"])) {
+            Err(btc_error::RpcError(e)) => assert!(e.contains("Block not found")),
+            x => panic!("Expected RpcError, got {:?}", x),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_split_batch_replies() {
+        let ids = vec![json!(1), json!("two"), json!(3), json!(4)];
+        let replies = json!([
+            {"result": 3, "error": null, "id": 3},
+            {"result": 5, "error": null, "id": 5},
+            {"result": null, "error": {"code": -5, "message": "Block not found"}, "id": 4},
+            {"result": 1, "error": null, "id": 1},
+        ]);
+        assert_eq!(
+            split_batch_replies(&ids, replies).unwrap(),
+            vec![
+                BatchReply::Result(json!(1)),
+                BatchReply::Missing,
+                BatchReply::Result(json!(3)),
+                BatchReply::Error(json!({"code": -5, "message": "Block not found"})),
+            ]
+        );
+
+        // bitcoind rejects the whole batch
+        let reply = json!({
+            "result": null,
+            "error": {"code": -32700, "message": "Parse error"},
+            "id": null
+        });
+        match split_batch_replies(&ids, reply) {
+            Err(btc_error::RpcError(_)) => {}
+            x => panic!("Expected RpcError, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_rpc_connect() {
+        assert_eq!(unbracket_host("[::1]"), "::1");
+        assert_eq!(unbracket_host("::1"), "::1");
+        assert_eq!(unbracket_host("127.0.0.1"), "127.0.0.1");
+        assert_eq!(unbracket_host("[bitcoind"), "[bitcoind");
+
+        // localhost may resolve to ::1 before 127.0.0.1, and nothing listens on ::1
+        let reply = json!({"result": 123, "error": null, "id": 0}).to_string();
+        let (port, server) =
+            spawn_one_shot_rpc_server(1, move |_, _| Some(("200 OK", reply.clone())));
+        let mut client =
+            BitcoinRpcClient::new("localhost", port, None, None, Duration::from_secs(5));
+        client.set_retries(0);
+        assert_eq!(client.call("getblockcount", json!([])).unwrap(), json!(123));
+        server.join().unwrap();
+
+        // every address is refused
+        let refused_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut client = BitcoinRpcClient::new(
+            "localhost",
+            refused_port,
+            None,
+            None,
+            Duration::from_secs(5),
+        );
+        client.set_retries(0);
+        match client.call("getblockcount", json!([])) {
+            Err(btc_error::Io(ref e)) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+            x => panic!("Expected a refused connection, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_parse_http_reply() {
+        assert_eq!(
+            parse_http_reply(b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n{\"a\": true}trailing")
+                .unwrap(),
+            json!({"a": true})
+        );
+        assert_eq!(
+            parse_http_reply(b"HTTP/1.1 500 Internal Server Error\r\n\r\n[1, 2]").unwrap(),
+            json!([1, 2])
+        );
+        for bad_reply in [
+            &b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n{\"a\": "[..],
+            &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n[]\r\n0\r\n\r\n"[..],
+            &b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n"[..],
+            &b"HTTP/1.1 200 OK\r\n"[..],
+        ] {
+            match parse_http_reply(bad_reply) {
+                Err(btc_error::RpcError(_)) => {}
+                x => panic!("Expected RpcError, got {:?}", x),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sync_headers_over_rpc() {
+        let db_path = "/tmp/test_sync_headers_over_rpc.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+
+        let num_headers = MAX_HEADERS_PER_REQUEST + 500;
+        let mut chain = vec![SpvClient::get_genesis_header(BitcoinNetworkType::Regtest)];
+        let headers = make_regtest_header_chain(num_headers);
+        chain.extend(headers.iter().map(|h| h.header));
+        let served = Arc::new(Mutex::new(chain[..101].to_vec()));
+        let served_inner = served.clone();
+        let port = spawn_mock_rpc_server(move |request| {
+            mock_rpc_reply(&served_inner.lock().unwrap(), request)
+        });
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.rpc_port = port;
+        config.header_source = HeaderSourceType::Rpc;
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+
+        let open_headers =
+            || SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, false, false).unwrap();
+
+        let tip_updates = indexer.subscribe_tip();
+
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 100);
+        assert_eq!(tip_updates.try_iter().last().unwrap().height, 100);
+        assert_eq!(
+            open_headers().read_block_headers(1, 101).unwrap(),
+            headers[..100].to_vec()
+        );
+
+        // picks up where it left off, and pages through the rest
+        *served.lock().unwrap() = chain.clone();
+        assert_eq!(indexer.sync_last_headers(100, None).unwrap(), num_headers);
+        assert_eq!(
+            open_headers()
+                .read_block_headers(1, num_headers + 1)
+                .unwrap(),
+            headers
+        );
+
+        // a header that doesn't build on ours is rejected, even though bitcoind sent it
+        let mut bad_header = headers[0].header;
+        bad_header.prev_blockhash = Sha256dHash::from_data(b"not a block");
+        chain.push(bad_header);
+        *served.lock().unwrap() = chain;
+        match indexer.sync_last_headers(num_headers, None) {
+            Err(btc_error::NoncontiguousHeader) => {}
+            x => panic!("Expected NoncontiguousHeader, got {:?}", x),
+        }
+        assert_eq!(
+            open_headers().get_highest_header_height().unwrap(),
+            num_headers
+        );
+    }
+
+    #[test]
+    fn test_rpc_chain_check() {
+        let db_path = "/tmp/test_rpc_chain_check.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+
+        let mut chain = vec![SpvClient::get_genesis_header(BitcoinNetworkType::Regtest)];
+        chain.extend(make_regtest_header_chain(5).iter().map(|h| h.header));
+        let chain_info = Arc::new(Mutex::new(json!({
+            "chain": "main",
+            "headers": 5,
+            "blocks": 5,
+            "initialblockdownload": false,
+        })));
+        let chain_info_inner = chain_info.clone();
+        let port = spawn_mock_rpc_server(move |request| {
+            if request["method"] == "getblockchaininfo" {
+                let result = chain_info_inner.lock().unwrap().clone();
+                json!({"result": result, "error": null, "id": request["id"].clone()})
+            } else {
+                mock_rpc_reply(&chain, request)
+            }
+        });
+
+        let mut client =
+            BitcoinRpcClient::new("127.0.0.1", port, None, None, Duration::from_secs(30));
+        assert_eq!(
+            client.get_blockchain_info().unwrap(),
+            BlockchainInfo {
+                chain: "main".to_string(),
+                headers: 5,
+                blocks: 5,
+                initial_block_download: false,
+            }
+        );
+
+        // a regtest indexer refuses a mainnet node before taking any headers from it
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.rpc_port = port;
+        config.header_source = HeaderSourceType::Rpc;
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        match indexer.sync_last_headers(0, None) {
+            Err(btc_error::ConfigError(e)) => assert!(e.contains("'main'")),
+            x => panic!("Expected ConfigError, got {:?}", x),
+        }
+        assert_eq!(
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, false, false)
+                .unwrap()
+                .get_highest_header_height()
+                .unwrap(),
+            0
+        );
+
+        // a node that's still in its initial block download is only warned about
+        *chain_info.lock().unwrap() = json!({
+            "chain": "regtest",
+            "headers": 5,
+            "blocks": 3,
+            "initialblockdownload": true,
+        });
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), 5);
+
+        // a reply missing fields is an RpcError
+        *chain_info.lock().unwrap() = json!({"chain": "regtest"});
+        match client.get_blockchain_info() {
+            Err(btc_error::RpcError(_)) => {}
+            x => panic!("Expected RpcError, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_get_tx_merkle_proof() {
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let txdata: Vec<Transaction> = (0..5)
+            .map(|i| Transaction {
+                version: 1,
+                lock_time: i,
+                input: vec![],
+                output: vec![],
+            })
+            .collect();
+        let txids: Vec<Sha256dHash> = txdata.iter().map(|tx| tx.txid()).collect();
+        let header = BlockHeader {
+            version: 0x20000000,
+            prev_blockhash: genesis.bitcoin_hash(),
+            merkle_root: bitcoin_merkle_root(txids.clone()),
+            time: genesis.time + 600,
+            bits: genesis.bits,
+            nonce: 0,
+        };
+        let block = Block { header, txdata };
+        let block_hash = block.bitcoin_hash();
+        let proof_hex = to_hex(
+            &serialize(&bloom::build_merkle_block(
+                &block,
+                &[false, false, false, true, false],
+            ))
+            .unwrap(),
+        );
+
+        let port = spawn_mock_rpc_server(move |request| {
+            assert_eq!(request["method"], "gettxoutproof");
+            json!({"result": proof_hex, "error": null, "id": request["id"].clone()})
+        });
+        let mut client =
+            BitcoinRpcClient::new("127.0.0.1", port, None, None, Duration::from_secs(30));
+
+        let (position, branch) = client.get_tx_merkle_proof(&txids[3], &block_hash).unwrap();
+        assert_eq!(position, 3);
+        assert!(verify_merkle_proof(&txids[3], &branch, position, &header).unwrap());
+
+        // a proof that doesn't cover the transaction
+        match client.get_tx_merkle_proof(&txids[1], &block_hash) {
+            Err(btc_error::InvalidMerkleProof(_)) => {}
+            x => panic!("Expected InvalidMerkleProof, got {:?}", x),
+        }
+
+        // a proof for some other block
+        match client.get_tx_merkle_proof(&txids[3], &genesis.bitcoin_hash()) {
+            Err(btc_error::InvalidMerkleProof(_)) => {}
+            x => panic!("Expected InvalidMerkleProof, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_get_block_stream() {
+        // a block of a few megabytes
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let txdata: Vec<Transaction> = (0..2000)
+            .map(|i| Transaction {
+                version: 1,
+                lock_time: i,
+                input: vec![TxIn {
+                    previous_output: OutPoint::null(),
+                    script_sig: Script::from(vec![0x51]),
+                    sequence: 0xffffffff,
+                    witness: vec![],
+                }],
+                output: vec![TxOut {
+                    value: 0,
+                    script_pubkey: Script::from(vec![0xab; 2000]),
+                }],
+            })
+            .collect();
+        let header = BlockHeader {
+            version: 0x20000000,
+            prev_blockhash: genesis.bitcoin_hash(),
+            merkle_root: bitcoin_merkle_root(txdata.iter().map(|tx| tx.txid()).collect()),
+            time: genesis.time + 600,
+            bits: genesis.bits,
+            nonce: 0,
+        };
+        let block = Block { header, txdata };
+        let block_hash = block.bitcoin_hash();
+        let block_hex = to_hex(&serialize(&block).unwrap());
+        assert!(block_hex.len() > 8_000_000);
+
+        let port = spawn_mock_rpc_server(move |request| {
+            let id = request["id"].clone();
+            assert_eq!(request["method"], "getblock");
+            assert_eq!(request["params"][1], 0);
+            if request["params"][0] == block_hash.be_hex_string() {
+                json!({"result": block_hex, "error": null, "id": id})
+            } else {
+                json!({"result": null, "error": {"code": -5, "message": "Block not found"}, "id": id})
+            }
+        });
+        let mut client =
+            BitcoinRpcClient::new("127.0.0.1", port, None, None, Duration::from_secs(30));
+
+        // all at once
+        assert_eq!(client.get_block(&block_hash).unwrap(), block);
+
+        // one transaction at a time
+        let txs = client
+            .get_block_stream(&block_hash, |reader| {
+                let mut block_reader = BitcoinBlockReader::new(reader)?;
+                assert_eq!(block_reader.header(), &block.header);
+                let mut txs = vec![];
+                while let Some(tx) = block_reader.next_tx()? {
+                    txs.push(tx);
+                }
+                assert_eq!(block_reader.merkle_root(), block.header.merkle_root);
+                Ok(txs)
+            })
+            .unwrap();
+        assert_eq!(txs, block.txdata);
+
+        let parser = BitcoinBlockParser::new(BitcoinNetworkType::Regtest, MagicBytes([105, 100]));
+        let lone_header = LoneBlockHeader {
+            header: block.header,
+            tx_count: VarInt(0),
+        };
+        let burn_block = client
+            .get_block_stream(&block_hash, |reader| {
+                parser.process_block_stream(reader, &lone_header, 1, StacksEpochId::Epoch21)
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(burn_block.block_hash.to_bitcoin_hash(), block_hash);
+        assert_eq!(burn_block.txs.len(), 0);
+
+        // the block must be read to the end
+        match client.get_block_stream(&block_hash, |reader| {
+            BitcoinBlockReader::new(reader).map(|_| ())
+        }) {
+            Err(btc_error::RpcError(_)) => {}
+            x => panic!("Expected RpcError, got {:?}", x),
+        }
+
+        // bitcoind's errors come through
+        match client.get_block_stream(&Sha256dHash::from_data(b"not a block"), |_| Ok(())) {
+            Err(btc_error::RpcError(e)) => assert!(e.contains("Block not found")),
+            x => panic!("Expected RpcError, got {:?}", x),
+        }
+
+        // the reply's members can come in any order, with any spacing
+        let block_hex = to_hex(&serialize(&block).unwrap());
+        let (port, server) = spawn_one_shot_rpc_server(2, move |i, _| {
+            let reply = match i {
+                0 => format!(
+                    "{{\n  \"result\" : \"{}\",\n  \"error\" : null,\n  \"id\" : 0\n}}\n",
+                    &block_hex
+                ),
+                _ => "{\"id\": 1, \"result\": 1234, \"error\": null}".to_string(),
+            };
+            Some(("200 OK", reply))
+        });
+        let mut client =
+            BitcoinRpcClient::new("127.0.0.1", port, None, None, Duration::from_secs(30));
+        let read_block = |reader: &mut dyn Read| -> Result<Vec<u8>, btc_error> {
+            let mut bytes = vec![];
+            reader.read_to_end(&mut bytes)?;
+            Ok(bytes)
+        };
+        assert_eq!(
+            client.get_block_stream(&block_hash, read_block).unwrap(),
+            serialize(&block).unwrap()
+        );
+        match client.get_block_stream(&block_hash, read_block) {
+            Err(btc_error::RpcError(e)) => assert!(e.contains("Invalid getblock result 1234")),
+            x => panic!("Expected RpcError, got {:?}", x),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_envelope_reader() {
+        let reply = br#" { "id" : "a\"}" , "error":null,"result" : "00ff" ,
+            "extra":[1,{"b":"]"}], "n": -1.5e3}trailing"#;
+        let mut envelope = EnvelopeReader::new(&reply[..]);
+        assert_eq!(envelope.next_member().unwrap(), Some("id".to_string()));
+        assert_eq!(envelope.read_value().unwrap(), json!("a\"}"));
+        assert_eq!(envelope.next_member().unwrap(), Some("error".to_string()));
+        assert_eq!(envelope.read_value().unwrap(), Value::Null);
+        assert_eq!(envelope.next_member().unwrap(), Some("result".to_string()));
+
+        // a value can be streamed instead
+        assert_eq!(envelope.peek().unwrap(), Some(b'"'));
+        envelope.inner.consume(1);
+        let mut hex_reader = HexReader::new(&mut envelope.inner);
+        let mut bytes = vec![];
+        hex_reader.read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![0x00, 0xff]);
+        hex_reader.finish().unwrap();
+
+        assert_eq!(envelope.next_member().unwrap(), Some("extra".to_string()));
+        assert_eq!(envelope.read_value().unwrap(), json!([1, {"b": "]"}]));
+        assert_eq!(envelope.next_member().unwrap(), Some("n".to_string()));
+        assert_eq!(envelope.read_value().unwrap(), json!(-1500.0));
+        assert_eq!(envelope.next_member().unwrap(), None);
+        assert_eq!(envelope.next_member().unwrap(), None);
+
+        for bad_reply in [
+            &b"<html>"[..],
+            &b"{\"result\" \"00\"}"[..],
+            &b"{1: 2}"[..],
+            &b"{\"result\": 1"[..],
+            &b"{\"result\": [1}"[..],
+            &b"{\"result\": nul}"[..],
+        ] {
+            let mut envelope = EnvelopeReader::new(bad_reply);
+            let res = loop {
+                match envelope.next_member() {
+                    Ok(Some(_)) => {
+                        if let Err(e) = envelope.read_value() {
+                            break Err(e);
+                        }
+                    }
+                    res => break res,
+                }
+            };
+            match res {
+                Err(btc_error::RpcError(_)) => {}
+                x => panic!("Malformed reply {:?} accepted: {:?}", bad_reply, x),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rpc_connection_pool() {
+        // echoes its param back, slowly enough for calls to pile up, and keeps track of how many
+        // calls it's serving at once
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let server_in_flight = in_flight.clone();
+        let server_max_in_flight = max_in_flight.clone();
+        let port = spawn_mock_rpc_server(move |request| {
+            let id = request["id"].clone();
+            if request["method"] == "hangup" {
+                return Value::Null;
+            }
+            let num_in_flight = server_in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            server_max_in_flight.fetch_max(num_in_flight, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            server_in_flight.fetch_sub(1, Ordering::SeqCst);
+            json!({"result": request["params"][0], "error": null, "id": id})
+        });
+
+        let mut client =
+            BitcoinRpcClient::new("127.0.0.1", port, None, None, Duration::from_secs(30));
+        client.set_max_connections(2);
+        let pool = client.connection_pool();
+
+        // more callers than connections:  they take turns, on the same two connections
+        let callers: Vec<_> = (0..8)
+            .map(|i| {
+                let mut client = client.clone();
+                thread::spawn(move || {
+                    for j in 0..5 {
+                        let param = i * 100 + j;
+                        assert_eq!(client.call("echo", json!([param])).unwrap(), json!(param));
+                    }
+                    assert_eq!(
+                        client
+                            .batch_call("echo", vec![json!([i]), json!([i + 1])])
+                            .unwrap(),
+                        vec![json!(i), json!(i + 1)]
+                    );
+                })
+            })
+            .collect();
+        for caller in callers {
+            caller.join().unwrap();
+        }
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.num_opened(), 2);
+        assert_eq!(pool.num_open(), 2);
+
+        // with every connection taken, a call waits, and gives up after the client's timeout
+        let mut impatient = client.clone();
+        impatient.timeout = Duration::from_millis(200);
+        impatient.set_retries(0);
+        let taken: Vec<_> = (0..2)
+            .map(|_| RpcConnectionPool::get(&pool, impatient.timeout, || panic!()).unwrap())
+            .collect();
+        match impatient.call("echo", json!([1])) {
+            Err(btc_error::TimedOut) => {}
+            x => panic!("Expected TimedOut, got {:?}", x),
+        }
+
+        // ...but gets a connection as soon as one is put back
+        let waiter = thread::spawn(move || impatient.call("echo", json!([1])).unwrap());
+        thread::sleep(Duration::from_millis(50));
+        for conn in taken {
+            conn.put_back();
+        }
+        assert_eq!(waiter.join().unwrap(), json!(1));
+        assert_eq!(pool.num_opened(), 2);
+
+        // connections that fail are closed, not put back:  the call fails on both reused
+        // connections and then on a new one, leaving none open
+        let mut no_retries = client.clone();
+        no_retries.set_retries(0);
+        match no_retries.call("hangup", json!([])) {
+            Err(btc_error::ConnectionBroken) => {}
+            x => panic!("Expected ConnectionBroken, got {:?}", x),
+        }
+        assert_eq!(pool.num_open(), 0);
+        assert_eq!(pool.num_opened(), 3);
+
+        assert_eq!(client.call("echo", json!([2])).unwrap(), json!(2));
+        assert_eq!(pool.num_open(), 1);
+        assert_eq!(pool.num_opened(), 4);
+    }
+
+    #[test]
+    fn test_sync_over_rpc_only() {
+        let db_path = "/tmp/test_sync_over_rpc_only.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+
+        let num_blocks = 20;
+        let blocks = make_regtest_block_chain(num_blocks);
+        let served = Arc::new(Mutex::new(blocks.clone()));
+        let served_inner = served.clone();
+        let num_getblocks = Arc::new(AtomicUsize::new(0));
+        let num_getblocks_inner = num_getblocks.clone();
+        let port = spawn_mock_rpc_server(move |request| {
+            if request["method"] == "getblock" {
+                num_getblocks_inner.fetch_add(1, Ordering::SeqCst);
+            }
+            mock_rpc_block_reply(&served_inner.lock().unwrap(), request)
+        });
+
+        // nobody answers on the p2p port, so we'd notice any attempt to use the peer network
+        let p2p_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        p2p_listener.set_nonblocking(true).unwrap();
+
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.peer_port = p2p_listener.local_addr().unwrap().port();
+        config.rpc_port = port;
+        config.sync_mode = SyncMode::Rpc;
+        config.mempool_ttl = Some(Duration::from_secs(60));
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        indexer.connect().unwrap();
+
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), num_blocks);
+        assert!(indexer.sync_mempool().unwrap().is_empty());
+
+        let headers = indexer.read_headers(1, num_blocks + 1).unwrap();
+        assert_eq!(
+            headers
+                .iter()
+                .map(|h| h.block_header.header)
+                .collect::<Vec<_>>(),
+            blocks.iter().map(|b| b.header).collect::<Vec<_>>()
+        );
+
+        let mut downloader = indexer.downloader();
+        let mut downloaded = vec![];
+        downloader
+            .download_all(&mut headers.clone().into_iter(), &mut |ipc_block| {
+                downloaded.push(ipc_block);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(downloaded.len(), blocks.len());
+        assert_eq!(num_getblocks.load(Ordering::SeqCst), blocks.len());
+
+        let mut parser = indexer.parser();
+        for (ipc_block, block) in downloaded.iter().zip(blocks.iter()) {
+            match ipc_block.block() {
+                NetworkMessage::Block(ref b) => assert_eq!(b, block),
+                x => panic!("Expected a block, got {:?}", x),
+            }
+            let burn_block = parser.parse(ipc_block, StacksEpochId::Epoch21).unwrap();
+            assert_eq!(burn_block.block_height(), ipc_block.height());
+            assert_eq!(
+                burn_block.block_hash().to_bitcoin_hash(),
+                block.bitcoin_hash()
+            );
+        }
+        assert!(indexer.sync_stats().bytes_downloaded > 0);
+        assert_eq!(indexer.sync_stats().blocks_height, num_blocks);
+
+        // a block that doesn't match its header is refused, even though bitcoind sent it
+        served.lock().unwrap()[4].txdata[0].lock_time += 1000;
+        match downloader.download(&headers[4]) {
+            Err(burnchain_error::DownloadError(btc_error::RpcError(_))) => {}
+            x => panic!("Expected RpcError, got {:?}", x),
+        }
+
+        // bitcoind's errors come through
+        served.lock().unwrap().truncate(10);
+        match downloader.download(&headers[15]) {
+            Err(burnchain_error::DownloadError(btc_error::RpcError(e))) => {
+                assert!(e.contains("Block not found"))
+            }
+            x => panic!("Expected RpcError, got {:?}", x),
+        }
+
+        match p2p_listener.accept() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            x => panic!("Expected no p2p connection, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_pruned_block_fallback() {
+        let db_path = "/tmp/test_pruned_block_fallback.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+
+        // the primary has every header, but has pruned the first 5 blocks
+        let num_blocks = 10;
+        let num_pruned = 5;
+        let blocks = make_regtest_block_chain(num_blocks);
+        let primary_blocks = blocks.clone();
+        let primary_port = spawn_mock_rpc_server(move |request| {
+            let pruned = request["method"] == "getblock"
+                && primary_blocks[..num_pruned]
+                    .iter()
+                    .any(|block| request["params"][0] == block.bitcoin_hash().be_hex_string());
+            if pruned {
+                json!({
+                    "result": null,
+                    "error": {"code": -1, "message": "Block not available (pruned data)"},
+                    "id": request["id"].clone(),
+                })
+            } else {
+                mock_rpc_block_reply(&primary_blocks, request)
+            }
+        });
+
+        // the fallback has every block but the first
+        let fallback_blocks = blocks[1..].to_vec();
+        let num_fallback_calls = Arc::new(AtomicUsize::new(0));
+        let num_fallback_calls_inner = num_fallback_calls.clone();
+        let fallback_port = spawn_mock_rpc_server(move |request| {
+            num_fallback_calls_inner.fetch_add(1, Ordering::SeqCst);
+            mock_rpc_block_reply(&fallback_blocks, request)
+        });
+
+        let mut client = BitcoinRpcClient::new(
+            "127.0.0.1",
+            primary_port,
+            None,
+            None,
+            Duration::from_secs(30),
+        );
+
+        // the pruned error is told apart from the rest
+        match client.get_block(&blocks[2].bitcoin_hash()) {
+            Err(btc_error::BlockPruned(e)) => assert!(e.contains("pruned data")),
+            x => panic!("Expected BlockPruned, got {:?}", x),
+        }
+        match client.get_block(&Sha256dHash::from_data(b"not a block")) {
+            Err(btc_error::RpcError(e)) => assert!(e.contains("Block not found")),
+            x => panic!("Expected RpcError, got {:?}", x),
+        }
+
+        client.set_pruned_block_fallback(Some(BitcoinRpcClient::new(
+            "127.0.0.1",
+            fallback_port,
+            None,
+            None,
+            Duration::from_secs(30),
+        )));
+        assert_eq!(
+            client.get_block(&blocks[2].bitcoin_hash()).unwrap(),
+            blocks[2]
+        );
+        assert_eq!(num_fallback_calls.load(Ordering::SeqCst), 1);
+        let block_bytes = client
+            .get_block_stream(&blocks[3].bitcoin_hash(), |reader| {
+                let mut block_bytes = vec![];
+                reader.read_to_end(&mut block_bytes)?;
+                Ok(block_bytes)
+            })
+            .unwrap();
+        assert_eq!(block_bytes, serialize(&blocks[3]).unwrap());
+        assert_eq!(num_fallback_calls.load(Ordering::SeqCst), 2);
+
+        // blocks the primary still has come from the primary
+        assert_eq!(
+            client.get_block(&blocks[7].bitcoin_hash()).unwrap(),
+            blocks[7]
+        );
+        assert_eq!(num_fallback_calls.load(Ordering::SeqCst), 2);
+
+        // the fallback's errors come through
+        match client.get_block(&blocks[0].bitcoin_hash()) {
+            Err(btc_error::RpcError(e)) => assert!(e.contains("Block not found")),
+            x => panic!("Expected RpcError, got {:?}", x),
+        }
+        assert_eq!(num_fallback_calls.load(Ordering::SeqCst), 3);
+
+        // and the indexer gets pruned blocks from its configured fallback
+        let mut config = BitcoinIndexerConfig::test_default(db_path.to_string());
+        config.rpc_port = primary_port;
+        config.sync_mode = SyncMode::Rpc;
+        config.pruned_block_fallback = Some(RpcEndpoint {
+            host: "127.0.0.1".to_string(),
+            port: fallback_port,
+            username: None,
+            password: None,
+        });
+        let mut indexer = BitcoinIndexer::new(
+            config,
+            BitcoinIndexerRuntime::new(BitcoinNetworkType::Regtest),
+        );
+        assert_eq!(indexer.sync_last_headers(0, None).unwrap(), num_blocks);
+        let headers = indexer.read_headers(2, num_blocks + 1).unwrap();
+        let mut downloaded = vec![];
+        indexer
+            .downloader()
+            .download_all(&mut headers.into_iter(), &mut |ipc_block| {
+                downloaded.push(ipc_block.block());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            downloaded,
+            blocks[1..]
+                .iter()
+                .map(|block| NetworkMessage::Block(block.clone()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            num_fallback_calls.load(Ordering::SeqCst),
+            3 + num_pruned - 1
+        );
+    }
+}
@@ -0,0 +1,473 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Fast-bootstrap snapshots of the indexer's state.
+//!
+//! `BitcoinIndexer::create_snapshot()` packs the SPV headers DB and the burnchain DB (which
+//! records the last fully processed burnchain block) into one file, which a new node can start
+//! from with `BitcoinIndexer::restore_snapshot()` instead of syncing from scratch.  A snapshot
+//! holds:
+//!
+//! * the 8-byte magic `SNAPSHOT_MAGIC`,
+//! * the length of the manifest (4 bytes, little-endian),
+//! * the manifest, as JSON (see `SnapshotManifest`),
+//! * the contents of each file listed in the manifest, back to back, in the manifest's order.
+//!
+//! The manifest's hashes only catch corruption.  On restore, the last
+//! `SNAPSHOT_VALIDATE_HEADERS` headers are validated again and checked against our
+//! checkpoints, but the rest of the snapshot is taken on trust.
+
+use std::fs;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::burnchains::bitcoin::indexer::{BitcoinIndexer, BurnTip};
+use crate::burnchains::bitcoin::spv::SpvClient;
+use crate::burnchains::bitcoin::BitcoinNetworkType;
+use crate::burnchains::bitcoin::Error as btc_error;
+use crate::burnchains::db::BurnchainDB;
+use crate::burnchains::Burnchain;
+use crate::burnchains::Error as burnchain_error;
+use crate::types::chainstate::BurnchainHeaderHash;
+use crate::util_lib::db::DBConn;
+use crate::util_lib::db::Error as db_error;
+
+use stacks_common::deps_common::bitcoin::network::serialize::BitcoinHash;
+use stacks_common::util::get_epoch_time_secs;
+use stacks_common::util::hash::to_hex;
+
+/// First bytes of every snapshot file
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"STXBURN1";
+/// Version of the snapshot format we write, and the only one we read
+pub const SNAPSHOT_VERSION: u32 = 1;
+/// Largest manifest we'll read, in bytes
+pub const MAX_SNAPSHOT_MANIFEST_SIZE: u32 = 1024 * 1024;
+/// How many headers, counting down from the tip, are validated again on restore
+pub const SNAPSHOT_VALIDATE_HEADERS: u64 = 2016;
+
+/// Name of the SPV headers DB in a snapshot
+pub const SNAPSHOT_HEADERS_FILE: &str = "headers.sqlite";
+/// Name of the burnchain DB in a snapshot
+pub const SNAPSHOT_BURNCHAIN_FILE: &str = "burnchain.sqlite";
+
+/// A file in a snapshot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    pub name: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Hex SHA256 of the contents
+    pub sha256: String,
+}
+
+/// What a snapshot holds, and what it was taken from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub version: u32,
+    pub network: BitcoinNetworkType,
+    /// First block of the burnchain the burnchain DB belongs to
+    pub first_block_height: u64,
+    pub first_block_hash: BurnchainHeaderHash,
+    /// Height and hash of the highest header in the headers DB
+    pub headers_height: u64,
+    pub headers_tip: BurnchainHeaderHash,
+    /// Last fully processed block in the burnchain DB
+    pub burn_tip: BurnTip,
+    /// When the snapshot was taken, in seconds since the epoch
+    pub created: u64,
+    pub files: Vec<SnapshotFile>,
+}
+
+/// Copy `reader` to `writer`.  Returns the number of bytes copied, and their hex SHA256.
+fn copy_hashed<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 65536];
+    let mut total = 0u64;
+    loop {
+        let num_read = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(num_read) => num_read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..num_read]);
+        writer.write_all(&buf[..num_read])?;
+        total += num_read as u64;
+    }
+    Ok((total, to_hex(&hasher.finalize())))
+}
+
+/// Write a consistent copy of the DB behind `conn` to `path`, even if it's being written to
+fn copy_db(conn: &DBConn, path: &str) -> Result<(), btc_error> {
+    if fs::metadata(path).is_ok() {
+        fs::remove_file(path).map_err(btc_error::FilesystemError)?;
+    }
+    conn.execute("VACUUM INTO ?1", &[&path])
+        .map_err(|e| btc_error::DBError(db_error::SqliteError(e)))?;
+    Ok(())
+}
+
+/// Fill `buf` from a snapshot, which is invalid if it ends first
+fn read_snapshot_bytes<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), btc_error> {
+    reader.read_exact(buf).map_err(|e| {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            btc_error::InvalidSnapshot("file is truncated".to_string())
+        } else {
+            btc_error::FilesystemError(e)
+        }
+    })
+}
+
+/// Read and decode a snapshot's magic and manifest, leaving `reader` at the first file
+fn read_manifest<R: Read>(reader: &mut R) -> Result<SnapshotManifest, btc_error> {
+    let mut magic = [0u8; 8];
+    read_snapshot_bytes(reader, &mut magic)?;
+    if &magic != SNAPSHOT_MAGIC {
+        return Err(btc_error::InvalidSnapshot("not a snapshot".to_string()));
+    }
+
+    let mut len_bytes = [0u8; 4];
+    read_snapshot_bytes(reader, &mut len_bytes)?;
+    let len = u32::from_le_bytes(len_bytes);
+    if len > MAX_SNAPSHOT_MANIFEST_SIZE {
+        return Err(btc_error::InvalidSnapshot(format!(
+            "manifest is too big ({} bytes)",
+            len
+        )));
+    }
+    let mut manifest_bytes = vec![0u8; len as usize];
+    read_snapshot_bytes(reader, &mut manifest_bytes)?;
+    let manifest: SnapshotManifest = serde_json::from_slice(&manifest_bytes)
+        .map_err(|e| btc_error::InvalidSnapshot(format!("bad manifest: {}", e)))?;
+    if manifest.version != SNAPSHOT_VERSION {
+        return Err(btc_error::InvalidSnapshot(format!(
+            "unsupported version {}",
+            manifest.version
+        )));
+    }
+    Ok(manifest)
+}
+
+/// Get the height and hash of the highest header in `spv_client`
+fn read_headers_tip(spv_client: &SpvClient) -> Result<(u64, BurnchainHeaderHash), btc_error> {
+    let headers_height = spv_client.get_highest_header_height()?;
+    let tip = spv_client
+        .read_block_header(headers_height)?
+        .ok_or(btc_error::MissingHeader)?;
+    Ok((
+        headers_height,
+        BurnchainHeaderHash::from_bitcoin_hash(&tip.header.bitcoin_hash()),
+    ))
+}
+
+impl BitcoinIndexer {
+    /// Write a snapshot of our headers and of `burnchain`'s burnchain DB to `path`, to bootstrap
+    /// another node with `restore_snapshot()`.  Both DBs may be in use while this runs.
+    /// Returns the snapshot's manifest.
+    pub fn create_snapshot(
+        &self,
+        burnchain: &Burnchain,
+        path: &str,
+    ) -> Result<SnapshotManifest, burnchain_error> {
+        let db_copies = vec![
+            (
+                SNAPSHOT_HEADERS_FILE,
+                format!("{}.{}.tmp", path, SNAPSHOT_HEADERS_FILE),
+            ),
+            (
+                SNAPSHOT_BURNCHAIN_FILE,
+                format!("{}.{}.tmp", path, SNAPSHOT_BURNCHAIN_FILE),
+            ),
+        ];
+        let bundle_path = format!("{}.tmp", path);
+        let res = self.write_snapshot(burnchain, &db_copies, &bundle_path);
+        for (_, copy_path) in db_copies.iter() {
+            if fs::metadata(copy_path).is_ok() {
+                if let Err(e) = fs::remove_file(copy_path) {
+                    warn!("Failed to remove {}: {:?}", copy_path, &e);
+                }
+            }
+        }
+        let manifest = match res {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                if fs::metadata(&bundle_path).is_ok() {
+                    let _ = fs::remove_file(&bundle_path);
+                }
+                return Err(e);
+            }
+        };
+
+        fs::rename(&bundle_path, path).map_err(btc_error::FilesystemError)?;
+        info!(
+            "Wrote snapshot of headers up to {} and burnchain blocks up to {} to {}",
+            manifest.headers_height, manifest.burn_tip.block_height, path
+        );
+        Ok(manifest)
+    }
+
+    /// Copy both DBs to the temporary paths in `db_copies`, and pack them into a snapshot at
+    /// `bundle_path`
+    fn write_snapshot(
+        &self,
+        burnchain: &Burnchain,
+        db_copies: &[(&str, String)],
+        bundle_path: &str,
+    ) -> Result<SnapshotManifest, burnchain_error> {
+        let headers_copy = &db_copies[0].1;
+        let burnchain_copy = &db_copies[1].1;
+
+        // the burnchain DB is copied first, so the copied headers reach at least as high as its
+        // tip.  The manifest describes the copies, not the DBs, which may have moved on.
+        copy_db(burnchain.open_burnchain_db(false)?.conn(), burnchain_copy)?;
+        copy_db(self.open_headers_readonly()?.conn(), headers_copy)?;
+
+        let burn_tip = BurnTip::from_db(&BurnchainDB::open(burnchain_copy, false)?)?;
+        let (headers_height, headers_tip) = read_headers_tip(&SpvClient::new(
+            headers_copy,
+            0,
+            None,
+            self.runtime.network_id,
+            false,
+            false,
+        )?)?;
+
+        let mut files = vec![];
+        for (name, copy_path) in db_copies.iter() {
+            let mut file = fs::File::open(copy_path).map_err(btc_error::FilesystemError)?;
+            let (size, sha256) =
+                copy_hashed(&mut file, &mut io::sink()).map_err(btc_error::FilesystemError)?;
+            files.push(SnapshotFile {
+                name: name.to_string(),
+                size,
+                sha256,
+            });
+        }
+
+        let manifest = SnapshotManifest {
+            version: SNAPSHOT_VERSION,
+            network: self.runtime.network_id,
+            first_block_height: burnchain.first_block_height,
+            first_block_hash: burnchain.first_block_hash.clone(),
+            headers_height,
+            headers_tip,
+            burn_tip,
+            created: get_epoch_time_secs(),
+            files,
+        };
+        let manifest_bytes =
+            serde_json::to_vec(&manifest).expect("FATAL: failed to serialize snapshot manifest");
+
+        let bundle = fs::File::create(bundle_path).map_err(btc_error::FilesystemError)?;
+        let mut writer = BufWriter::new(bundle);
+        let mut write_bundle = || -> io::Result<()> {
+            writer.write_all(SNAPSHOT_MAGIC)?;
+            writer.write_all(&(manifest_bytes.len() as u32).to_le_bytes())?;
+            writer.write_all(&manifest_bytes)?;
+            for (_, copy_path) in db_copies.iter() {
+                io::copy(&mut fs::File::open(copy_path)?, &mut writer)?;
+            }
+            writer.flush()?;
+            writer.get_ref().sync_all()
+        };
+        write_bundle().map_err(btc_error::FilesystemError)?;
+        Ok(manifest)
+    }
+
+    /// Set up our headers DB and `burnchain`'s burnchain DB from a snapshot written by
+    /// `create_snapshot()`.  `burnchain`'s working directory must be empty (or not exist yet),
+    /// and our headers DB must not exist.  Besides checking the files against the manifest,
+    /// the last `SNAPSHOT_VALIDATE_HEADERS` headers are validated again, and the burnchain DB
+    /// must end where the manifest says it does.  If anything is wrong, the files restored so
+    /// far are removed again.
+    /// Returns the snapshot's manifest.
+    pub fn restore_snapshot(
+        &self,
+        burnchain: &Burnchain,
+        path: &str,
+    ) -> Result<SnapshotManifest, burnchain_error> {
+        if let Ok(mut entries) = fs::read_dir(&burnchain.working_dir) {
+            if entries.next().is_some() {
+                return Err(btc_error::ConfigError(format!(
+                    "Refusing to restore a snapshot into non-empty directory {}",
+                    &burnchain.working_dir
+                ))
+                .into());
+            }
+        }
+        if fs::metadata(&self.config.spv_headers_path).is_ok() {
+            return Err(btc_error::ConfigError(format!(
+                "Refusing to restore a snapshot over existing headers DB {}",
+                &self.config.spv_headers_path
+            ))
+            .into());
+        }
+
+        let snapshot = fs::File::open(path).map_err(btc_error::FilesystemError)?;
+        let mut reader = BufReader::new(snapshot);
+        let manifest = read_manifest(&mut reader)?;
+        if manifest.network != self.runtime.network_id {
+            warn!(
+                "Snapshot {} is for {:?}, but we're on {:?}",
+                path, manifest.network, self.runtime.network_id
+            );
+            return Err(btc_error::WrongNetwork.into());
+        }
+        if manifest.first_block_height != burnchain.first_block_height
+            || manifest.first_block_hash != burnchain.first_block_hash
+        {
+            return Err(btc_error::InvalidSnapshot(format!(
+                "snapshot starts at burnchain block {} ({}), but ours starts at {} ({})",
+                manifest.first_block_height,
+                &manifest.first_block_hash,
+                burnchain.first_block_height,
+                &burnchain.first_block_hash
+            ))
+            .into());
+        }
+
+        let mut restored = vec![];
+        let res = self
+            .restore_files(burnchain, &manifest, &mut reader, &mut restored)
+            .and_then(|_| self.check_restored(burnchain, &manifest));
+        if let Err(e) = res {
+            warn!("Failed to restore snapshot {}: {:?}", path, &e);
+            for restored_path in restored.iter() {
+                if let Err(e) = fs::remove_file(restored_path) {
+                    warn!("Failed to remove {}: {:?}", restored_path, &e);
+                }
+            }
+            return Err(e);
+        }
+
+        info!(
+            "Restored headers up to {} and burnchain blocks up to {} from {}",
+            manifest.headers_height, manifest.burn_tip.block_height, path
+        );
+        Ok(manifest)
+    }
+
+    /// Write out the files of a snapshot as `reader` gets to them, checking each against the
+    /// manifest.  Each path written to is added to `restored`.
+    fn restore_files<R: Read>(
+        &self,
+        burnchain: &Burnchain,
+        manifest: &SnapshotManifest,
+        reader: &mut R,
+        restored: &mut Vec<String>,
+    ) -> Result<(), burnchain_error> {
+        let mut names: Vec<_> = manifest
+            .files
+            .iter()
+            .map(|file| file.name.as_str())
+            .collect();
+        names.sort();
+        if names != [SNAPSHOT_BURNCHAIN_FILE, SNAPSHOT_HEADERS_FILE] {
+            return Err(
+                btc_error::InvalidSnapshot(format!("unexpected files {:?}", &names)).into(),
+            );
+        }
+
+        for file in manifest.files.iter() {
+            let dest_path = if file.name == SNAPSHOT_HEADERS_FILE {
+                self.config.spv_headers_path.clone()
+            } else {
+                burnchain.get_burnchaindb_path()
+            };
+            if let Some(parent) = Path::new(&dest_path).parent() {
+                fs::create_dir_all(parent).map_err(btc_error::FilesystemError)?;
+            }
+
+            let mut dest = fs::File::create(&dest_path).map_err(btc_error::FilesystemError)?;
+            restored.push(dest_path.clone());
+            let (size, sha256) = copy_hashed(&mut reader.by_ref().take(file.size), &mut dest)
+                .map_err(btc_error::FilesystemError)?;
+            if size != file.size {
+                return Err(btc_error::InvalidSnapshot(format!(
+                    "file is truncated in {}",
+                    &file.name
+                ))
+                .into());
+            }
+            if sha256 != file.sha256 {
+                return Err(btc_error::InvalidSnapshot(format!(
+                    "hash mismatch for {}: expected {}, got {}",
+                    &file.name, &file.sha256, &sha256
+                ))
+                .into());
+            }
+            dest.sync_all().map_err(btc_error::FilesystemError)?;
+        }
+
+        let mut trailing = [0u8; 1];
+        if reader
+            .read(&mut trailing)
+            .map_err(btc_error::FilesystemError)?
+            > 0
+        {
+            return Err(btc_error::InvalidSnapshot("trailing data".to_string()).into());
+        }
+        Ok(())
+    }
+
+    /// Check the restored DBs against the manifest, and validate the last headers again
+    fn check_restored(
+        &self,
+        burnchain: &Burnchain,
+        manifest: &SnapshotManifest,
+    ) -> Result<(), burnchain_error> {
+        let mut spv_client = self.open_headers_readonly()?;
+        spv_client.set_checkpoints(self.get_checkpoints()?);
+        let headers_tip = read_headers_tip(&spv_client)?;
+        if headers_tip != (manifest.headers_height, manifest.headers_tip.clone()) {
+            return Err(btc_error::InvalidSnapshot(format!(
+                "headers end at {} ({}), but the manifest says {} ({})",
+                headers_tip.0, &headers_tip.1, manifest.headers_height, &manifest.headers_tip
+            ))
+            .into());
+        }
+        spv_client.validate_last_headers(SNAPSHOT_VALIDATE_HEADERS)?;
+
+        let burn_tip = BurnTip::from_db(&burnchain.open_burnchain_db(false)?)?;
+        if burn_tip != manifest.burn_tip {
+            return Err(btc_error::InvalidSnapshot(format!(
+                "burnchain DB ends at {} ({}), but the manifest says {} ({})",
+                burn_tip.block_height,
+                &burn_tip.block_hash,
+                manifest.burn_tip.block_height,
+                &manifest.burn_tip.block_hash
+            ))
+            .into());
+        }
+
+        // the burnchain DB's tip must be one of our headers
+        let burn_tip_header = spv_client.read_block_header(burn_tip.block_height)?;
+        if burn_tip_header
+            .map(|header| BurnchainHeaderHash::from_bitcoin_hash(&header.header.bitcoin_hash()))
+            != Some(burn_tip.block_hash.clone())
+        {
+            return Err(btc_error::InvalidSnapshot(format!(
+                "burnchain DB tip {} ({}) is not in the headers DB",
+                burn_tip.block_height, &burn_tip.block_hash
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,394 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::time::Duration;
+
+use crate::burnchains::bitcoin::indexer::{BitcoinIndexer, SocksProxy};
+use crate::burnchains::bitcoin::Error as btc_error;
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_NONE: u8 = 0x00;
+const AUTH_USERNAME_PASSWORD: u8 = 0x02;
+const AUTH_NO_ACCEPTABLE_METHOD: u8 = 0xff;
+/// Version of the username/password subnegotiation (RFC 1929)
+const USERNAME_PASSWORD_VERSION: u8 = 0x01;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN_NAME: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_NETWORK_UNREACHABLE: u8 = 0x03;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_CONNECTION_REFUSED: u8 = 0x05;
+const REPLY_TTL_EXPIRED: u8 = 0x06;
+
+/// Connect to `host:port` through the SOCKS5 `proxy` (RFC 1928), and get the socket, ready to
+/// use as if we had connected to `host:port` directly.  Unless `host` is an IP address, the
+/// proxy resolves it, so that it can be a `.onion` address (and so that we don't leak DNS
+/// lookups).  `connect_timeout` bounds connecting to the proxy, as it would connecting to the
+/// host; `timeout` bounds each read and write while we negotiate with the proxy.
+/// Fails with ProxyError if the proxy can't be reached or won't serve us, and with
+/// ConnectionError if the proxy can't reach `host:port` -- i.e. if a direct connection would
+/// have failed too.
+pub fn connect(
+    proxy: &SocksProxy,
+    host: &str,
+    port: u16,
+    connect_timeout: Option<Duration>,
+    timeout: Duration,
+) -> Result<TcpStream, btc_error> {
+    let proxy_error =
+        |e: io::Error| btc_error::ProxyError(format!("{}:{}: {}", &proxy.host, proxy.port, e));
+    let proxy_addr = (proxy.host.as_str(), proxy.port);
+    let mut sock = match connect_timeout {
+        Some(connect_timeout) => BitcoinIndexer::connect_timeout(proxy_addr, connect_timeout),
+        None => TcpStream::connect(proxy_addr),
+    }
+    .map_err(proxy_error)?;
+    sock.set_read_timeout(Some(timeout)).map_err(proxy_error)?;
+    sock.set_write_timeout(Some(timeout)).map_err(proxy_error)?;
+
+    negotiate(&mut sock, proxy, host, port).map_err(|e| match e {
+        btc_error::Io(e) => proxy_error(e),
+        e => e,
+    })?;
+    debug!(
+        "Connected to {}:{} through SOCKS5 proxy {}:{}",
+        host, port, &proxy.host, proxy.port
+    );
+    Ok(sock)
+}
+
+/// Authenticate to the proxy on `sock`, and have it connect us to `host:port`
+fn negotiate<S: Read + Write>(
+    sock: &mut S,
+    proxy: &SocksProxy,
+    host: &str,
+    port: u16,
+) -> Result<(), btc_error> {
+    let credentials = match (proxy.username.as_ref(), proxy.password.as_ref()) {
+        (Some(username), Some(password)) => Some((username, password)),
+        (_, _) => None,
+    };
+
+    // offer to authenticate if we can, but let the proxy decide
+    if credentials.is_some() {
+        sock.write_all(&[SOCKS_VERSION, 2, AUTH_NONE, AUTH_USERNAME_PASSWORD])?;
+    } else {
+        sock.write_all(&[SOCKS_VERSION, 1, AUTH_NONE])?;
+    }
+    let mut method_reply = [0u8; 2];
+    sock.read_exact(&mut method_reply)?;
+    if method_reply[0] != SOCKS_VERSION {
+        return Err(btc_error::ProxyError(format!(
+            "not a SOCKS5 proxy (version {})",
+            method_reply[0]
+        )));
+    }
+    match (method_reply[1], credentials) {
+        (AUTH_NONE, _) => {}
+        (AUTH_USERNAME_PASSWORD, Some((username, password))) => {
+            authenticate(sock, username, password)?;
+        }
+        (AUTH_NO_ACCEPTABLE_METHOD, _) => {
+            return Err(btc_error::ProxyError(
+                "proxy accepts none of our authentication methods".to_string(),
+            ));
+        }
+        (method, _) => {
+            return Err(btc_error::ProxyError(format!(
+                "proxy chose authentication method {}, which we did not offer",
+                method
+            )));
+        }
+    }
+
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(addr)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.octets());
+        }
+        Ok(IpAddr::V6(addr)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.octets());
+        }
+        Err(_) => {
+            if host.len() > (u8::MAX as usize) {
+                return Err(btc_error::ConfigError(format!(
+                    "Host name is too long for a SOCKS5 proxy: {}",
+                    host
+                )));
+            }
+            request.push(ATYP_DOMAIN_NAME);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    sock.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    sock.read_exact(&mut reply)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(btc_error::ProxyError(format!(
+            "not a SOCKS5 proxy (version {})",
+            reply[0]
+        )));
+    }
+    match reply[1] {
+        REPLY_SUCCEEDED => {}
+        // the proxy works, but couldn't reach the host -- as if we'd failed to connect directly
+        REPLY_NETWORK_UNREACHABLE
+        | REPLY_HOST_UNREACHABLE
+        | REPLY_CONNECTION_REFUSED
+        | REPLY_TTL_EXPIRED => {
+            debug!(
+                "SOCKS5 proxy failed to connect to {}:{} (reply {})",
+                host, port, reply[1]
+            );
+            return Err(btc_error::ConnectionError);
+        }
+        code => {
+            return Err(btc_error::ProxyError(format!(
+                "proxy refused to connect to {}:{} (reply {})",
+                host, port, code
+            )));
+        }
+    }
+
+    // skip the address the proxy bound, which we have no use for
+    let bound_addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN_NAME => {
+            let mut len = [0u8; 1];
+            sock.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        atyp => {
+            return Err(btc_error::ProxyError(format!(
+                "unknown bound address type {}",
+                atyp
+            )));
+        }
+    };
+    let mut bound_addr = vec![0u8; bound_addr_len + 2];
+    sock.read_exact(&mut bound_addr)?;
+    Ok(())
+}
+
+/// Authenticate to the proxy with a username and password (RFC 1929)
+fn authenticate<S: Read + Write>(
+    sock: &mut S,
+    username: &str,
+    password: &str,
+) -> Result<(), btc_error> {
+    if username.len() > (u8::MAX as usize) || password.len() > (u8::MAX as usize) {
+        return Err(btc_error::ConfigError(
+            "SOCKS5 proxy username and password must be at most 255 bytes each".to_string(),
+        ));
+    }
+    let mut request = vec![USERNAME_PASSWORD_VERSION, username.len() as u8];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password.len() as u8);
+    request.extend_from_slice(password.as_bytes());
+    sock.write_all(&request)?;
+
+    let mut reply = [0u8; 2];
+    sock.read_exact(&mut reply)?;
+    if reply[1] != 0x00 {
+        return Err(btc_error::ProxyError(format!(
+            "proxy rejected our credentials (status {})",
+            reply[1]
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net;
+    use std::thread;
+
+    /// Run a one-connection SOCKS5 proxy, which handles the connection with `serve`
+    fn spawn_mock_proxy<F>(serve: F) -> (u16, thread::JoinHandle<()>)
+    where
+        F: FnOnce(TcpStream) + Send + 'static,
+    {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            serve(sock);
+        });
+        (port, handle)
+    }
+
+    fn read_bytes(sock: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        sock.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    fn proxy_at(port: u16) -> SocksProxy {
+        SocksProxy {
+            host: "127.0.0.1".to_string(),
+            port,
+            username: None,
+            password: None,
+        }
+    }
+
+    fn connect_through(proxy_port: u16) -> Result<TcpStream, btc_error> {
+        connect(
+            &proxy_at(proxy_port),
+            "10.0.0.1",
+            8333,
+            None,
+            Duration::from_secs(5),
+        )
+    }
+
+    #[test]
+    fn test_socks5_connect() {
+        let onion = "bitcoinxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx.onion";
+
+        // no authentication; the proxy resolves the host name
+        let (port, proxy) = spawn_mock_proxy(move |mut sock| {
+            assert_eq!(read_bytes(&mut sock, 3), vec![5, 1, 0]);
+            sock.write_all(&[5, 0]).unwrap();
+
+            let mut expected = vec![5, 1, 0, 3, onion.len() as u8];
+            expected.extend_from_slice(onion.as_bytes());
+            expected.extend_from_slice(&[0x20, 0x8d]);
+            assert_eq!(read_bytes(&mut sock, expected.len()), expected);
+            sock.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0x12, 0x34])
+                .unwrap();
+
+            // from here on, we're the peer
+            assert_eq!(read_bytes(&mut sock, 4), b"ping".to_vec());
+            sock.write_all(b"pong").unwrap();
+        });
+        let mut sock = connect(
+            &proxy_at(port),
+            onion,
+            8333,
+            Some(Duration::from_secs(5)),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        sock.write_all(b"ping").unwrap();
+        assert_eq!(read_bytes(&mut sock, 4), b"pong".to_vec());
+        proxy.join().unwrap();
+
+        // with authentication, to an IP address, with a domain name for the bound address
+        let (port, proxy) = spawn_mock_proxy(|mut sock| {
+            assert_eq!(read_bytes(&mut sock, 4), vec![5, 2, 0, 2]);
+            sock.write_all(&[5, 2]).unwrap();
+            assert_eq!(read_bytes(&mut sock, 12), b"\x01\x05alice\x04pass".to_vec());
+            sock.write_all(&[1, 0]).unwrap();
+
+            assert_eq!(
+                read_bytes(&mut sock, 10),
+                vec![5, 1, 0, 1, 10, 0, 0, 1, 0x47, 0x9d]
+            );
+            sock.write_all(&[5, 0, 0, 3, 5]).unwrap();
+            sock.write_all(b"proxy\x12\x34").unwrap();
+
+            assert_eq!(read_bytes(&mut sock, 4), b"ping".to_vec());
+            sock.write_all(b"pong").unwrap();
+        });
+        let mut proxy_config = proxy_at(port);
+        proxy_config.username = Some("alice".to_string());
+        proxy_config.password = Some("pass".to_string());
+        let mut sock = connect(
+            &proxy_config,
+            "10.0.0.1",
+            18333,
+            None,
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        sock.write_all(b"ping").unwrap();
+        assert_eq!(read_bytes(&mut sock, 4), b"pong".to_vec());
+        proxy.join().unwrap();
+    }
+
+    #[test]
+    fn test_socks5_connect_failures() {
+        // the proxy can't reach the host:  a connection failure, like without a proxy
+        let (port, proxy) = spawn_mock_proxy(|mut sock| {
+            read_bytes(&mut sock, 3);
+            sock.write_all(&[5, 0]).unwrap();
+            read_bytes(&mut sock, 10);
+            sock.write_all(&[5, REPLY_CONNECTION_REFUSED, 0, 1, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+        });
+        match connect_through(port) {
+            Err(btc_error::ConnectionError) => {}
+            x => panic!("Expected ConnectionError, got {:?}", &x),
+        }
+        proxy.join().unwrap();
+
+        // the proxy itself fails
+        let (port, proxy) = spawn_mock_proxy(|mut sock| {
+            read_bytes(&mut sock, 3);
+            sock.write_all(&[5, 0]).unwrap();
+            read_bytes(&mut sock, 10);
+            sock.write_all(&[5, 0x01, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+        match connect_through(port) {
+            Err(btc_error::ProxyError(_)) => {}
+            x => panic!("Expected ProxyError, got {:?}", &x),
+        }
+        proxy.join().unwrap();
+
+        // the proxy wants credentials we don't have
+        let (port, proxy) = spawn_mock_proxy(|mut sock| {
+            read_bytes(&mut sock, 3);
+            sock.write_all(&[5, AUTH_NO_ACCEPTABLE_METHOD]).unwrap();
+        });
+        match connect_through(port) {
+            Err(btc_error::ProxyError(_)) => {}
+            x => panic!("Expected ProxyError, got {:?}", &x),
+        }
+        proxy.join().unwrap();
+
+        // the proxy hangs up on us
+        let (port, proxy) = spawn_mock_proxy(|_sock| {});
+        match connect_through(port) {
+            Err(btc_error::ProxyError(_)) => {}
+            x => panic!("Expected ProxyError, got {:?}", &x),
+        }
+        proxy.join().unwrap();
+
+        // the proxy isn't there at all
+        let refusing_port = net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        match connect_through(refusing_port) {
+            Err(btc_error::ProxyError(_)) => {}
+            x => panic!("Expected ProxyError, got {:?}", &x),
+        }
+    }
+}
@@ -16,15 +16,24 @@
 
 use std::cmp;
 use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem;
 use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use stacks_common::deps_common::bitcoin::blockdata::block::{BlockHeader, LoneBlockHeader};
 use stacks_common::deps_common::bitcoin::blockdata::constants::genesis_block;
 use stacks_common::deps_common::bitcoin::network::constants::Network;
 use stacks_common::deps_common::bitcoin::network::encodable::VarInt;
 use stacks_common::deps_common::bitcoin::network::message as btc_message;
+use stacks_common::deps_common::bitcoin::network::message_filter::{CFHeaders, CFilter};
 use stacks_common::deps_common::bitcoin::network::serialize::{
     deserialize, serialize, BitcoinHash,
 };
@@ -32,7 +41,14 @@ use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
 
 use stacks_common::util::uint::Uint256;
 
-use crate::burnchains::bitcoin::indexer::BitcoinIndexer;
+use crate::burnchains::bitcoin::blocks::verify_merkle_proof;
+use crate::burnchains::bitcoin::filters::{
+    filter_header, BlockFilter, BASIC_FILTER_TYPE, FILTER_CHECKPOINT_INTERVAL,
+    MAX_CFHEADERS_PER_REQUEST, MAX_CFILTERS_PER_REQUEST,
+};
+use crate::burnchains::bitcoin::indexer::{
+    network_id_to_bytes, BitcoinIndexer, SyncStats, SHUTDOWN_POLL_INTERVAL,
+};
 use crate::burnchains::bitcoin::messages::BitcoinMessageHandler;
 use crate::burnchains::bitcoin::BitcoinNetworkType;
 use crate::burnchains::bitcoin::Error as btc_error;
@@ -47,15 +63,25 @@ use rusqlite::Transaction;
 use rusqlite::{Connection, OpenFlags, NO_PARAMS};
 
 use crate::util_lib::db::{
-    query_int, query_row, query_rows, sqlite_open, tx_begin_immediate, tx_busy_handler, u64_to_sql,
-    DBConn, DBTx, Error as db_error, FromColumn, FromRow,
+    query_int, query_row, query_rows, sql_pragma, sqlite_open, tx_begin_immediate, tx_busy_handler,
+    u64_to_sql, DBConn, DBTx, Error as db_error, FromColumn, FromRow,
 };
 use stacks_common::util::get_epoch_time_secs;
-use stacks_common::util::hash::{hex_bytes, to_hex};
+use stacks_common::util::hash::{hex_bytes, to_hex, Sha256Sum};
 use stacks_common::util::log;
 
 const BLOCK_HEADER_SIZE: u64 = 81;
 
+/// Size of a header in a header snapshot (no tx count)
+const SNAPSHOT_HEADER_SIZE: usize = 80;
+/// Size of the network magic and header count at the start of a header snapshot
+const SNAPSHOT_PREAMBLE_SIZE: usize = 4 + 8;
+/// Size of the SHA256 checksum at the end of a header snapshot
+const SNAPSHOT_CHECKSUM_SIZE: usize = 32;
+
+/// Every SQLite database file begins with these bytes
+const SQLITE_FILE_MAGIC: &'static [u8] = b"SQLite format 3\0";
+
 pub const BITCOIN_GENESIS_BLOCK_HASH_MAINNET: &'static str =
     "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
 pub const BITCOIN_GENESIS_BLOCK_MERKLE_ROOT_MAINNET: &'static str =
@@ -67,10 +93,107 @@ pub const BITCOIN_GENESIS_BLOCK_HASH_TESTNET: &'static str =
 pub const BITCOIN_GENESIS_BLOCK_HASH_REGTEST: &'static str =
     "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
 
+pub const BITCOIN_GENESIS_BLOCK_HASH_SIGNET: &'static str =
+    "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6";
+
+/// Known-good (height, block hash) pairs on mainnet.  Headers at these heights must have these
+/// hashes, so a peer cannot feed us a divergent chain below the highest checkpoint.
+pub const BITCOIN_MAINNET_CHECKPOINTS: &[(u64, &'static str)] = &[
+    (
+        11111,
+        "0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d",
+    ),
+    (
+        33333,
+        "000000002dd5588a74784eaa7ab0507a18ad16a236e7b1ce69f00d7ddfb5d0a6",
+    ),
+    (
+        74000,
+        "0000000000573993a3c9e41ce34471c079dcf5f52a0e824a81e7f953b8661a20",
+    ),
+    (
+        105000,
+        "00000000000291ce28027faea320c8d2b054b2e0fe44a773f3eefb151d6bdc97",
+    ),
+    (
+        134444,
+        "00000000000005b12ffd4cd315cd34ffd4a594f430ac814c91184a0d42d2b0fe",
+    ),
+    (
+        168000,
+        "000000000000099e61ea72015e79632f216fe6cb33d7899acb35b75c8303b763",
+    ),
+    (
+        193000,
+        "000000000000059f452a5f7340de6682a977387c17010ff6e6c3bd83ca8b1317",
+    ),
+    (
+        210000,
+        "000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e",
+    ),
+    (
+        216116,
+        "00000000000001b4f4b433e81ee46494af945cf96014816a4e2370f11b23df4e",
+    ),
+    (
+        225430,
+        "00000000000001c108384350f74090433e7fcf79a606b8e797f065b130575932",
+    ),
+    (
+        250000,
+        "000000000000003887df1f29024b06fc2200b55f8af8f35453d7be294df2d214",
+    ),
+    (
+        279000,
+        "0000000000000001ae8c72a0b0c301f67e3afca10e819efa9041e458e9bd7e40",
+    ),
+    (
+        295000,
+        "00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983",
+    ),
+];
+
+/// Known-good (height, block hash) pairs on testnet3
+pub const BITCOIN_TESTNET_CHECKPOINTS: &[(u64, &'static str)] = &[(
+    546,
+    "000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70",
+)];
+
 pub const BLOCK_DIFFICULTY_CHUNK_SIZE: u64 = 2016;
-const BLOCK_DIFFICULTY_INTERVAL: u32 = 14 * 24 * 60 * 60; // two weeks, in seconds
 
-pub const SPV_DB_VERSION: &'static str = "3";
+/// Heights from which headers must have at least the given version, since BIP34, BIP66, and
+/// BIP65 (in that order) activated there.  BIP90 buried these deployments at these heights.
+pub const BITCOIN_MAINNET_MIN_HEADER_VERSIONS: &[(u64, u32)] =
+    &[(227931, 2), (363725, 3), (388381, 4)];
+pub const BITCOIN_TESTNET_MIN_HEADER_VERSIONS: &[(u64, u32)] =
+    &[(21111, 2), (330776, 3), (581885, 4)];
+/// Number of headers below the tip that are re-checked when the headers are opened read/write.
+/// These are the ones that could have been mid-write if we were killed.
+const SPV_TAIL_CHECK_DEPTH: u64 = 16;
+/// Minimum number of header hashes to check per thread
+const MIN_POW_CHECKS_PER_THREAD: usize = 64;
+const BLOCK_DIFFICULTY_INTERVAL: u32 = 14 * 24 * 60 * 60; // two weeks, in seconds
+/// Headers may not be timestamped more than this many seconds ahead of our clock
+const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+/// Only headers this close to our chain tip are checked against our clock
+const FUTURE_BLOCK_TIME_CHECK_DEPTH: u64 = 144;
+/// A header's median-time-past is the median of its own timestamp and those of the headers
+/// before it, up to this many in all
+pub const MEDIAN_TIME_SPAN: u64 = 11;
+/// By default, make written headers durable once this many have been written since the last
+/// sync...
+pub const DEFAULT_HEADER_FSYNC_INTERVAL: u64 = 20_000;
+/// ...or once this long has passed since the last sync, whichever comes first
+pub const DEFAULT_HEADER_FSYNC_PERIOD: Duration = Duration::from_millis(5_000);
+/// Fewest headers below the tip (or `HeaderPruning::max_height`) that
+/// `SpvClient::prune_headers()` keeps:  the two difficulty intervals that the next retarget and a
+/// reorg search could need.  This is far deeper
+/// than any plausible reorg.
+pub const MIN_HEADER_RETENTION: u64 = 2 * BLOCK_DIFFICULTY_CHUNK_SIZE;
+/// Size of a header in the header archive
+const ARCHIVED_HEADER_SIZE: u64 = 80;
+
+pub const SPV_DB_VERSION: &'static str = "8";
 
 const SPV_INITIAL_SCHEMA: &[&'static str] = &[
     r#"
@@ -123,6 +246,65 @@ const SPV_SCHEMA_3: &[&'static str] = &[
     "#,
 ];
 
+// record the height below which headers have been pruned.  The header at this height is kept as
+// the anchor of the remaining chain.
+const SPV_SCHEMA_4: &[&'static str] = &[r#"
+    CREATE TABLE pruned_headers(
+        height INTEGER NOT NULL
+    );
+    "#];
+
+// BIP158 basic block filters, and the BIP157 filter header chain that authenticates them.
+// Filter headers are only stored once they have been checked against the peer's filter header
+// checkpoints, and filters are only stored if they hash to the stored filter hash.
+const SPV_SCHEMA_5: &[&'static str] = &[
+    r#"
+    CREATE TABLE filter_headers(
+        height INTEGER PRIMARY KEY NOT NULL,
+        filter_hash TEXT NOT NULL,
+        header TEXT NOT NULL
+    );
+    "#,
+    r#"
+    CREATE TABLE block_filters(
+        height INTEGER PRIMARY KEY NOT NULL,
+        filter BLOB NOT NULL
+    );
+    "#,
+];
+
+// running total work of the header chain up to and including each header.  Unlike `chain_work`,
+// rows are dropped along with (or when replacing) their headers, so they always describe the
+// headers we currently have.  Rows are filled in by `update_chain_work()`.
+const SPV_SCHEMA_6: &[&'static str] = &[r#"
+    CREATE TABLE header_chain_work(
+        height INTEGER PRIMARY KEY NOT NULL,
+        work TEXT NOT NULL  -- 32-byte (256-bit) integer
+    );
+    "#];
+
+// the network the headers DB holds headers for, as its network magic (see `network_id_to_bytes()`).
+// This is NULL in headers DBs created before it was recorded, until `check_network()` works it out
+// from their genesis header.
+const SPV_SCHEMA_7: &[&'static str] = &[r#"
+    ALTER TABLE db_config ADD COLUMN network INTEGER;
+    "#];
+
+// headers below the pruned height that are kept when the rest are pruned:  the first header of
+// each difficulty interval, and the headers at checkpoint heights.
+const SPV_SCHEMA_8: &[&'static str] = &[r#"
+    CREATE TABLE retained_headers(
+        version INTEGER NOT NULL,
+        prev_blockhash TEXT NOT NULL,
+        merkle_root TEXT NOT NULL,
+        time INTEGER NOT NULL,
+        bits INTEGER NOT NULL,
+        nonce INTEGER NOT NULL,
+        height INTEGER PRIMARY KEY NOT NULL,
+        hash TEXT NOT NULL
+    );
+    "#];
+
 pub struct SpvClient {
     pub headers_path: String,
     pub start_block_height: u64,
@@ -133,6 +315,174 @@ pub struct SpvClient {
     reverse_order: bool,
     headers_db: DBConn,
     check_txcount: bool,
+    checkpoints: Vec<(u64, BurnchainHeaderHash)>,
+    /// Lowest fork height of any reorg this client has processed
+    reorg_height: Option<u64>,
+    /// Lowest fork height of a reorg this client may process (see `set_reorg_floor()`)
+    reorg_floor: Option<u64>,
+    /// Receives a progress report every `progress_interval` headers while syncing
+    progress_handler: Option<Arc<dyn HeaderSyncProgress>>,
+    progress_interval: u64,
+    /// Height and time of the last progress report
+    last_progress: (u64, Instant),
+    /// Output scripts checked against compact block filters
+    filter_scripts: Vec<Vec<u8>>,
+    /// Height and hash of a header whose ancestors' work is not verified
+    assume_valid: Option<(u64, BurnchainHeaderHash)>,
+    /// Whether or not the work of headers at or below the highest checkpoint is verified
+    assume_valid_checkpoints: bool,
+    /// Height and expected hash of the first burn block, if it's checked
+    first_block: Option<(u64, BurnchainHeaderHash)>,
+    /// Whether or not headers with versions obsoleted by buried soft forks are rejected
+    strict_header_versions: bool,
+    /// Number of threads to check header hashes against their targets with
+    pow_threads: usize,
+    /// Header the headers DB is seeded with when it is created
+    genesis_header: BlockHeader,
+    /// Get a `TipUpdate` whenever we accept a new best header
+    tip_subscribers: Vec<Sender<TipUpdate>>,
+    /// Make written headers durable every this many headers (see `set_fsync_interval()`)
+    fsync_interval: u64,
+    /// ...or every this long, whichever comes first
+    fsync_period: Duration,
+    /// Number of headers written since headers were last made durable
+    unsynced_headers: u64,
+    /// When headers were last made durable
+    last_fsync: Instant,
+    /// Number of times written headers have been made durable
+    num_fsyncs: u64,
+    /// Once set, `sync_from()` stops between header batches (see `set_shutdown_handle()`)
+    shutdown: Option<Arc<AtomicBool>>,
+    /// Kept up to date with our highest header's height, if set (see `set_sync_stats()`)
+    sync_stats: Option<Arc<Mutex<SyncStats>>>,
+}
+
+/// A new best header, as sent to `SpvClient::subscribe_tip()` subscribers
+#[derive(Debug, Clone, PartialEq)]
+pub struct TipUpdate {
+    pub height: u64,
+    pub hash: BurnchainHeaderHash,
+    /// How many of our headers were rolled back to switch to the branch this header is on, or 0
+    /// if it just extends our chain
+    pub reorg_depth: u64,
+}
+
+/// Which headers to prune from the headers DB (see `SpvClient::prune_headers()`), for
+/// `BitcoinIndexerConfig::header_pruning`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderPruning {
+    /// Prune no headers at or above this height, e.g. because the burnchain still needs them.
+    /// If None, headers are pruned up to the tip.
+    pub max_height: Option<u64>,
+    /// Keep at least this many headers below `max_height` (or the tip, if it's lower).  Raised
+    /// to MIN_HEADER_RETENTION if it's less.
+    pub keep_headers: u64,
+    /// Append the pruned headers to the header archive instead of discarding them
+    pub archive: bool,
+}
+
+/// Whether or not we can still read the header at a height (see
+/// `SpvClient::get_header_availability()`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderAvailability {
+    /// The header is in the headers DB
+    Available,
+    /// The header was pruned, but kept as the first header of its difficulty interval or as a
+    /// checkpoint
+    Retained,
+    /// The header was pruned, and is in the header archive
+    Archived,
+    /// The header was pruned and discarded
+    NotRetained,
+    /// We don't have a header at this height yet
+    NotSynced,
+}
+
+/// The times of a block header.  Header timestamps can go backwards from one header to the next,
+/// but a header's median-time-past never does, so it's what rules about block time should use.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockTimes {
+    /// The timestamp in the header itself
+    pub timestamp: u32,
+    /// The median of the timestamps of the header and the (up to) MEDIAN_TIME_SPAN - 1 headers
+    /// before it
+    pub median_time_past: u32,
+}
+
+/// Receives progress reports from an `SpvClient` while it syncs headers, e.g. to log them.
+pub trait HeaderSyncProgress: Send + Sync {
+    /// `current_height` is the height of our highest header, `estimated_tip` is our best guess
+    /// of the height of the peer's chain tip, and `headers_per_sec` is the download rate since
+    /// the last report.  Errors are logged, but do not stop the sync.
+    fn on_progress(
+        &self,
+        current_height: u64,
+        estimated_tip: u64,
+        headers_per_sec: f64,
+    ) -> Result<(), String>;
+
+    /// `chain_work` is the total work of our headers so far, and `estimated_work_remaining` is
+    /// how much more the headers up to the estimated tip will add if they are as hard as our
+    /// highest header.  Reported right after `on_progress`.  Does nothing by default.
+    fn on_work_progress(
+        &self,
+        _chain_work: Uint256,
+        _estimated_work_remaining: Uint256,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl<F> HeaderSyncProgress for F
+where
+    F: Fn(u64, u64, f64) -> Result<(), String> + Send + Sync,
+{
+    fn on_progress(
+        &self,
+        current_height: u64,
+        estimated_tip: u64,
+        headers_per_sec: f64,
+    ) -> Result<(), String> {
+        self(current_height, estimated_tip, headers_per_sec)
+    }
+}
+
+impl fmt::Debug for dyn HeaderSyncProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HeaderSyncProgress")
+    }
+}
+
+/// Where an `SpvClient` gets headers from when it syncs (see `SpvClient::sync_from()`).  A source
+/// only fetches headers.  They are validated and stored the same way no matter which source they
+/// came from, so a misbehaving source can't get bad headers stored.
+pub trait HeaderSource {
+    /// Get the height of the source's chain tip
+    fn get_tip_height(&mut self) -> Result<u64, btc_error>;
+
+    /// Get up to 2000 consecutive headers of the source's best chain, like `getheaders` does:
+    /// they start after the first header in `locator` (ordered from highest to lowest) that is on
+    /// the source's best chain.  Returns no headers if there are none after it.
+    fn get_headers(&mut self, locator: Vec<Sha256dHash>)
+        -> Result<Vec<LoneBlockHeader>, btc_error>;
+}
+
+/// A run of headers between two headers we already trust (our chain tip or a checkpoint),
+/// which can be downloaded and checked independently of the headers around it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderChunk {
+    /// Height and hash of the trusted header the chunk builds on
+    pub start_height: u64,
+    pub start_hash: Sha256dHash,
+    /// Height and hash of the trusted header the chunk ends with
+    pub end_height: u64,
+    pub end_hash: Sha256dHash,
+}
+
+/// Message handler that downloads a single `HeaderChunk` from a peer
+pub struct HeaderChunkFetcher {
+    pub chunk: HeaderChunk,
+    pub headers: Vec<LoneBlockHeader>,
 }
 
 impl FromColumn<Sha256dHash> for Sha256dHash {
@@ -170,6 +520,46 @@ impl SpvClient {
         readwrite: bool,
         reverse_order: bool,
     ) -> Result<SpvClient, btc_error> {
+        SpvClient::new_with_genesis(
+            headers_path,
+            start_block,
+            end_block,
+            network_id,
+            None,
+            readwrite,
+            reverse_order,
+        )
+    }
+
+    /// Like `new()`, but if `genesis_header` is given, a new headers DB is seeded with it instead
+    /// of the network's well-known genesis header, and an existing headers DB must start with
+    /// it.  This is only allowed on regtest, for private networks with their own genesis block.
+    pub fn new_with_genesis(
+        headers_path: &str,
+        start_block: u64,
+        end_block: Option<u64>,
+        network_id: BitcoinNetworkType,
+        genesis_header: Option<BlockHeader>,
+        readwrite: bool,
+        reverse_order: bool,
+    ) -> Result<SpvClient, btc_error> {
+        if genesis_header.is_some() && network_id != BitcoinNetworkType::Regtest {
+            return Err(btc_error::ConfigError(format!(
+                "A custom genesis header can only be used on regtest, not {:?}",
+                network_id
+            )));
+        }
+
+        if readwrite {
+            SpvClient::stage_legacy_headers_file(headers_path, network_id)?;
+        } else if SpvClient::is_legacy_headers_file(headers_path)? {
+            error!(
+                "Headers file {} must be opened read/write once to migrate it to a headers DB",
+                headers_path
+            );
+            return Err(btc_error::DBError(db_error::ReadOnly));
+        }
+
         let exists = fs::metadata(headers_path).is_ok();
         let conn = SpvClient::db_open(headers_path, readwrite, true)?;
         let mut client = SpvClient {
@@ -182,11 +572,40 @@ impl SpvClient {
             reverse_order: reverse_order,
             headers_db: conn,
             check_txcount: true,
+            checkpoints: SpvClient::default_checkpoints(network_id),
+            reorg_height: None,
+            reorg_floor: None,
+            progress_handler: None,
+            progress_interval: 0,
+            last_progress: (start_block, Instant::now()),
+            filter_scripts: vec![],
+            assume_valid: None,
+            assume_valid_checkpoints: false,
+            first_block: None,
+            strict_header_versions: network_id == BitcoinNetworkType::Mainnet,
+            pow_threads: SpvClient::default_pow_threads(),
+            genesis_header: genesis_header
+                .unwrap_or_else(|| SpvClient::get_genesis_header(network_id)),
+            tip_subscribers: vec![],
+            fsync_interval: DEFAULT_HEADER_FSYNC_INTERVAL,
+            fsync_period: DEFAULT_HEADER_FSYNC_PERIOD,
+            unsynced_headers: 0,
+            last_fsync: Instant::now(),
+            num_fsyncs: 0,
+            shutdown: None,
+            sync_stats: None,
         };
 
         let empty = client.is_empty()?;
         if readwrite && (!exists || empty) {
             client.init_block_headers(true)?;
+        } else if genesis_header.is_some() {
+            client.check_genesis_header()?;
+        }
+        client.check_network()?;
+        if readwrite {
+            client.finish_legacy_headers_migration()?;
+            client.repair_headers_tail()?;
         }
 
         Ok(client)
@@ -212,6 +631,27 @@ impl SpvClient {
             reverse_order: reverse_order,
             headers_db: conn,
             check_txcount: true,
+            checkpoints: SpvClient::default_checkpoints(network_id),
+            reorg_height: None,
+            reorg_floor: None,
+            progress_handler: None,
+            progress_interval: 0,
+            last_progress: (start_block, Instant::now()),
+            filter_scripts: vec![],
+            assume_valid: None,
+            assume_valid_checkpoints: false,
+            first_block: None,
+            strict_header_versions: network_id == BitcoinNetworkType::Mainnet,
+            pow_threads: SpvClient::default_pow_threads(),
+            genesis_header: SpvClient::get_genesis_header(network_id),
+            tip_subscribers: vec![],
+            fsync_interval: DEFAULT_HEADER_FSYNC_INTERVAL,
+            fsync_period: DEFAULT_HEADER_FSYNC_PERIOD,
+            unsynced_headers: 0,
+            last_fsync: Instant::now(),
+            num_fsyncs: 0,
+            shutdown: None,
+            sync_stats: None,
         };
 
         if readwrite {
@@ -226,6 +666,350 @@ impl SpvClient {
         self.check_txcount = false;
     }
 
+    /// Get the compiled-in header checkpoints for a network.
+    /// Regtest has none.
+    pub fn default_checkpoints(network_id: BitcoinNetworkType) -> Vec<(u64, BurnchainHeaderHash)> {
+        let checkpoints = match network_id {
+            BitcoinNetworkType::Mainnet => BITCOIN_MAINNET_CHECKPOINTS,
+            BitcoinNetworkType::Testnet => BITCOIN_TESTNET_CHECKPOINTS,
+            BitcoinNetworkType::Regtest | BitcoinNetworkType::Signet => &[],
+        };
+        checkpoints
+            .iter()
+            .map(|(height, hash_hex)| {
+                let hash =
+                    Sha256dHash::from_hex(hash_hex).expect("FATAL: invalid checkpoint block hash");
+                (*height, BurnchainHeaderHash::from_bitcoin_hash(&hash))
+            })
+            .collect()
+    }
+
+    /// Replace the header checkpoints this client enforces.
+    /// Pass an empty list to disable checkpoint validation.
+    pub fn set_checkpoints(&mut self, checkpoints: Vec<(u64, BurnchainHeaderHash)>) {
+        self.checkpoints = checkpoints;
+    }
+
+    /// Skip the target and timestamp checks of headers at or below the given height, which must
+    /// have the given hash.  Those headers are still checked for linkage, and a header at that
+    /// height with a different hash is rejected with AssumeValidMismatch.  Pass None to verify
+    /// every header (the default).
+    pub fn set_assume_valid(&mut self, assume_valid: Option<(u64, BurnchainHeaderHash)>) {
+        self.assume_valid = assume_valid;
+    }
+
+    /// Reject headers at the given height that don't have the given hash, with
+    /// FirstBlockMismatch:  the first burn block's hash, if it's configured.  Unlike a
+    /// checkpoint, it doesn't exempt the headers below it from any checks.  Pass None to accept
+    /// any first burn block (the default).
+    pub fn set_first_block(&mut self, first_block: Option<(u64, BurnchainHeaderHash)>) {
+        self.first_block = first_block;
+    }
+
+    /// Check that the stored header at the first burn block height, if we have it yet, has the
+    /// hash given to `set_first_block()`.  Fails with FirstBlockMismatch if it doesn't.
+    pub fn check_first_block(&self) -> Result<(), btc_error> {
+        let (height, hash) = match self.first_block {
+            Some((height, ref hash)) => (height, hash),
+            None => return Ok(()),
+        };
+        if height < self.get_pruned_height()? {
+            // nothing to check against
+            return Ok(());
+        }
+        match self.read_block_header(height)? {
+            Some(header) => {
+                if BurnchainHeaderHash::from_bitcoin_hash(&header.header.bitcoin_hash()) != *hash {
+                    error!(
+                        "Stored header at first block height {} has hash {}, but expected {}",
+                        height,
+                        header.header.bitcoin_hash(),
+                        hash.to_bitcoin_hash()
+                    );
+                    return Err(btc_error::FirstBlockMismatch(height));
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Skip the target and timestamp checks of headers at or below the highest checkpoint, the
+    /// same way as for headers below the assume-valid header.  Since headers must still match the
+    /// checkpoints, a chain that isn't the checkpointed one is rejected once it reaches the
+    /// highest checkpoint.  Off by default.
+    pub fn set_assume_valid_checkpoints(&mut self, assume_valid_checkpoints: bool) {
+        self.assume_valid_checkpoints = assume_valid_checkpoints;
+    }
+
+    /// Make written headers durable (i.e. fsync them) once `interval` headers have been written
+    /// since the last sync, or once `period` has passed since it, whichever comes first.  Each
+    /// batch is committed atomically to the headers DB's write-ahead log, so a crash or power
+    /// loss leaves us at the end of some batch, never in the middle of one.  But until the log
+    /// is synced, the batches written since the last sync can be lost, and have to be downloaded
+    /// again.  Both limits are checked as batches are written, and `sync_from()` syncs whatever
+    /// is left when it returns (as does dropping the client).  A 0 interval or period disables
+    /// that limit; with both disabled, headers are only synced then (and by SQLite's automatic
+    /// checkpoints).
+    pub fn set_fsync_interval(&mut self, interval: u64, period: Duration) {
+        self.fsync_interval = interval;
+        self.fsync_period = period;
+    }
+
+    /// Number of times written headers have been made durable so far
+    pub fn get_num_fsyncs(&self) -> u64 {
+        self.num_fsyncs
+    }
+
+    /// Stop `sync_from()` between header batches once `shutdown` is set.  The batches stored so
+    /// far are made durable before it returns.
+    pub fn set_shutdown_handle(&mut self, shutdown: Arc<AtomicBool>) {
+        self.shutdown = Some(shutdown);
+    }
+
+    /// Record the height of each new best header in `sync_stats`, as it's accepted
+    pub fn set_sync_stats(&mut self, sync_stats: Arc<Mutex<SyncStats>>) {
+        self.sync_stats = Some(sync_stats);
+    }
+
+    fn is_shutdown_requested(&self) -> bool {
+        self.shutdown
+            .as_ref()
+            .map(|shutdown| shutdown.load(Ordering::SeqCst))
+            .unwrap_or(false)
+    }
+
+    /// Note that a batch of `num_headers` headers was committed, and make it and the batches
+    /// before it durable if it's time to.
+    fn headers_committed(&mut self, num_headers: u64) -> Result<(), btc_error> {
+        self.unsynced_headers += num_headers;
+        let interval_reached =
+            self.fsync_interval > 0 && self.unsynced_headers >= self.fsync_interval;
+        let period_reached = self.fsync_period > Duration::from_millis(0)
+            && self.last_fsync.elapsed() >= self.fsync_period;
+        if !interval_reached && !period_reached {
+            return Ok(());
+        }
+        self.flush_unsynced_headers()
+    }
+
+    /// Make the header batches written since the last sync durable
+    fn flush_headers(&mut self) -> Result<(), btc_error> {
+        // with synchronous=NORMAL, the log is fsynced before it is checkpointed into the DB, and
+        // the DB is fsynced after
+        self.headers_db
+            .query_row("PRAGMA wal_checkpoint(PASSIVE)", NO_PARAMS, |_row| Ok(()))
+            .map_err(db_error::SqliteError)?;
+        self.unsynced_headers = 0;
+        self.last_fsync = Instant::now();
+        self.num_fsyncs += 1;
+        Ok(())
+    }
+
+    /// Make the headers written since the last sync durable, if there are any
+    fn flush_unsynced_headers(&mut self) -> Result<(), btc_error> {
+        if self.unsynced_headers == 0 {
+            return Ok(());
+        }
+        self.flush_headers()
+    }
+
+    /// Open another, read-only handle on this client's headers DB, e.g. to hand to a thread that
+    /// serves header queries while this one syncs.  The headers DB is in WAL mode, so readers
+    /// don't block the writer (or each other), and every query sees only committed header
+    /// batches.
+    pub fn reader(&self) -> Result<SpvClient, btc_error> {
+        let mut reader = SpvClient::new(
+            &self.headers_path,
+            self.start_block_height,
+            self.end_block_height,
+            self.network_id,
+            false,
+            self.reverse_order,
+        )?;
+        reader.checkpoints = self.checkpoints.clone();
+        reader.genesis_header = self.genesis_header.clone();
+        Ok(reader)
+    }
+
+    /// Reject headers whose versions are obsoleted by the soft forks that BIP90 buries (see
+    /// `validate_header_versions()`).  On by default on mainnet.  Never enforced on regtest or
+    /// signet, whatever this is set to.
+    pub fn set_strict_header_versions(&mut self, strict_header_versions: bool) {
+        self.strict_header_versions = strict_header_versions;
+    }
+
+    /// Check header hashes against their targets on up to this many threads (at least 1).
+    pub fn set_pow_threads(&mut self, num_threads: usize) {
+        self.pow_threads = cmp::max(num_threads, 1);
+    }
+
+    /// One proof-of-work checking thread per core
+    fn default_pow_threads() -> usize {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    }
+
+    /// Is the header at `block_height` buried under the assume-valid header, or under the highest
+    /// checkpoint if checkpoints are assumed valid?
+    fn is_assumed_valid(&self, block_height: u64) -> bool {
+        if self.assume_valid_checkpoints
+            && self
+                .checkpoints
+                .iter()
+                .any(|(height, _)| block_height <= *height)
+        {
+            return true;
+        }
+        match self.assume_valid {
+            Some((height, _)) => block_height <= height,
+            None => false,
+        }
+    }
+
+    /// Set the output scripts that `block_may_contain_burn_ops()` looks for in compact block
+    /// filters.
+    pub fn set_filter_scripts(&mut self, scripts: Vec<Vec<u8>>) {
+        self.filter_scripts = scripts;
+    }
+
+    /// Get the lowest fork height of any reorg this client has processed, if any.
+    /// The fork height is the height of the highest header both branches had in common -- all
+    /// headers (and anything derived from them) above it have been replaced.
+    pub fn get_reorg_height(&self) -> Option<u64> {
+        self.reorg_height
+    }
+
+    /// Refuse, with `Error::DeepReorg`, to replace the headers of any fork below `floor` -- i.e.
+    /// a reorg that would take too many stored burnchain blocks off the canonical chain.  The
+    /// headers are left as they were.  If None, forks at any height are processed.
+    pub fn set_reorg_floor(&mut self, floor: Option<u64>) {
+        self.reorg_floor = floor;
+    }
+
+    /// Get a `TipUpdate` every time we accept a new best header, including when a reorg
+    /// switches us to another branch.  Dropping the receiver unsubscribes.
+    pub fn subscribe_tip(&mut self) -> Receiver<TipUpdate> {
+        let (sender, receiver) = channel();
+        self.tip_subscribers.push(sender);
+        receiver
+    }
+
+    /// Send a `TipUpdate` to `subscriber` every time we accept a new best header (see
+    /// `subscribe_tip()`).
+    pub fn add_tip_subscriber(&mut self, subscriber: Sender<TipUpdate>) {
+        self.tip_subscribers.push(subscriber);
+    }
+
+    /// Get the hash of our highest header, if we have any headers
+    fn read_tip_hash(&self) -> Result<Option<Sha256dHash>, btc_error> {
+        Ok(self.get_tip()?.map(|(_, hdr)| hdr.header.bitcoin_hash()))
+    }
+
+    /// Tell tip subscribers (and the sync stats, if set) about our highest header, unless it's
+    /// still `prev_tip`.  `reorg_depth` is how many headers were rolled back to switch to its
+    /// branch.
+    fn publish_tip(
+        &mut self,
+        prev_tip: Option<Sha256dHash>,
+        reorg_depth: u64,
+    ) -> Result<(), btc_error> {
+        if self.tip_subscribers.is_empty() && self.sync_stats.is_none() {
+            return Ok(());
+        }
+        let tip_hash = match self.read_tip_hash()? {
+            Some(tip_hash) if Some(tip_hash) != prev_tip => tip_hash,
+            _ => {
+                return Ok(());
+            }
+        };
+        let update = TipUpdate {
+            height: self.get_highest_header_height()?,
+            hash: BurnchainHeaderHash::from_bitcoin_hash(&tip_hash),
+            reorg_depth,
+        };
+        if let Some(sync_stats) = self.sync_stats.as_ref() {
+            sync_stats
+                .lock()
+                .expect("BUG: sync stats lock poisoned")
+                .record_header_tip(update.height);
+        }
+        // sends never block, and subscribers that have gone away are dropped
+        self.tip_subscribers
+            .retain(|subscriber| subscriber.send(update.clone()).is_ok());
+        Ok(())
+    }
+
+    /// Report sync progress to `handler` every `interval` headers.
+    pub fn set_progress_handler(&mut self, handler: Arc<dyn HeaderSyncProgress>, interval: u64) {
+        self.progress_handler = Some(handler);
+        self.progress_interval = interval;
+        self.last_progress = (self.cur_block_height, Instant::now());
+    }
+
+    /// Tell the progress handler how far along we are, if we've gotten at least
+    /// `progress_interval` headers since the last report.  `estimated_tip` is the height we
+    /// expect the chain tip to be at.
+    pub fn report_progress(&mut self, estimated_tip: u64) -> Result<(), btc_error> {
+        let handler = match self.progress_handler {
+            Some(ref handler) => handler.clone(),
+            None => return Ok(()),
+        };
+        let height = self.get_highest_header_height()?;
+        let (last_height, last_time) = self.last_progress;
+        if height < last_height + cmp::max(self.progress_interval, 1) {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(last_time).as_secs_f64();
+        let headers_per_sec = if elapsed > 0.0 {
+            ((height - last_height) as f64) / elapsed
+        } else {
+            0.0
+        };
+        self.last_progress = (height, now);
+
+        if let Err(e) =
+            handler.on_progress(height, cmp::max(height, estimated_tip), headers_per_sec)
+        {
+            warn!("Header sync progress handler failed: {}", &e);
+        }
+
+        if let (Some(chain_work), Some(tip_header)) = (
+            self.get_chain_work_at(height)?,
+            self.read_block_header(height)?,
+        ) {
+            let remaining = Uint256::from_u64(estimated_tip.saturating_sub(height));
+            if let Err(e) =
+                handler.on_work_progress(chain_work, tip_header.header.work() * remaining)
+            {
+                warn!("Header sync progress handler failed: {}", &e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Read headers through a memory map of (up to) the first `mmap_size` bytes of the headers
+    /// DB, instead of with a read() call per page.  0 turns this off.
+    /// SQLite keeps the mapping consistent with writes and with the file's growth, and reads
+    /// the rest of the file (or all of it, if mapping fails or isn't supported on this
+    /// platform) the usual way, so this only ever affects speed.
+    /// Returns the mapping size SQLite actually uses, which may be smaller than `mmap_size`.
+    pub fn set_mmap_size(&self, mmap_size: u64) -> Result<u64, btc_error> {
+        let mmap_size = i64::try_from(mmap_size).unwrap_or(i64::MAX);
+        sql_pragma(&self.headers_db, "mmap_size", &mmap_size)?;
+        let actual_size = query_int(&self.headers_db, "PRAGMA mmap_size", NO_PARAMS)?;
+        if actual_size < mmap_size {
+            debug!(
+                "Memory-mapping at most {} bytes of {} (requested {})",
+                actual_size, &self.headers_path, mmap_size
+            );
+        }
+        Ok(actual_size as u64)
+    }
+
     pub fn conn(&self) -> &DBConn {
         &self.headers_db
     }
@@ -257,6 +1041,21 @@ impl SpvClient {
         for row_text in SPV_SCHEMA_3 {
             tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
         }
+        for row_text in SPV_SCHEMA_4 {
+            tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
+        }
+        for row_text in SPV_SCHEMA_5 {
+            tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
+        }
+        for row_text in SPV_SCHEMA_6 {
+            tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
+        }
+        for row_text in SPV_SCHEMA_7 {
+            tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
+        }
+        for row_text in SPV_SCHEMA_8 {
+            tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
+        }
 
         tx.execute(
             "INSERT INTO db_config (version) VALUES (?1)",
@@ -287,6 +1086,31 @@ impl SpvClient {
             .and_then(|_| Ok(()))
     }
 
+    /// Get the network magic recorded in the headers DB, if there is one.  A headers DB that is
+    /// opened read-only is not migrated, so it may predate the column it is recorded in.
+    fn db_get_network(conn: &DBConn) -> Result<Option<u32>, btc_error> {
+        let version = SpvClient::db_get_version(conn)?;
+        if version.parse::<u64>().unwrap_or(0) < 7 {
+            return Ok(None);
+        }
+        let magic = conn
+            .query_row("SELECT network FROM db_config LIMIT 1", NO_PARAMS, |row| {
+                let magic: Option<i64> = row.get_unwrap(0);
+                Ok(magic)
+            })
+            .optional()
+            .map_err(db_error::SqliteError)?
+            .flatten();
+        Ok(magic.map(|magic| magic as u32))
+    }
+
+    fn db_set_network(tx: &Transaction, network_id: BitcoinNetworkType) -> Result<(), btc_error> {
+        let magic = network_id_to_bytes(network_id) as i64;
+        tx.execute("UPDATE db_config SET network = ?1", &[&magic])
+            .map_err(db_error::SqliteError)?;
+        Ok(())
+    }
+
     #[cfg(test)]
     pub fn test_db_migrate(conn: &mut DBConn) -> Result<(), btc_error> {
         SpvClient::db_migrate(conn)
@@ -317,6 +1141,56 @@ impl SpvClient {
                     SpvClient::db_set_version(&tx, "3")?;
                     tx.commit().map_err(db_error::SqliteError)?;
                 }
+                "3" => {
+                    debug!("Migrate SPV DB from schema 3 to 4");
+                    let tx = tx_begin_immediate(conn)?;
+                    for row_text in SPV_SCHEMA_4 {
+                        tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
+                    }
+
+                    SpvClient::db_set_version(&tx, "4")?;
+                    tx.commit().map_err(db_error::SqliteError)?;
+                }
+                "4" => {
+                    debug!("Migrate SPV DB from schema 4 to 5");
+                    let tx = tx_begin_immediate(conn)?;
+                    for row_text in SPV_SCHEMA_5 {
+                        tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
+                    }
+
+                    SpvClient::db_set_version(&tx, "5")?;
+                    tx.commit().map_err(db_error::SqliteError)?;
+                }
+                "5" => {
+                    debug!("Migrate SPV DB from schema 5 to 6");
+                    let tx = tx_begin_immediate(conn)?;
+                    for row_text in SPV_SCHEMA_6 {
+                        tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
+                    }
+
+                    SpvClient::db_set_version(&tx, "6")?;
+                    tx.commit().map_err(db_error::SqliteError)?;
+                }
+                "6" => {
+                    debug!("Migrate SPV DB from schema 6 to 7");
+                    let tx = tx_begin_immediate(conn)?;
+                    for row_text in SPV_SCHEMA_7 {
+                        tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
+                    }
+
+                    SpvClient::db_set_version(&tx, "7")?;
+                    tx.commit().map_err(db_error::SqliteError)?;
+                }
+                "7" => {
+                    debug!("Migrate SPV DB from schema 7 to 8");
+                    let tx = tx_begin_immediate(conn)?;
+                    for row_text in SPV_SCHEMA_8 {
+                        tx.execute_batch(row_text).map_err(db_error::SqliteError)?;
+                    }
+
+                    SpvClient::db_set_version(&tx, "8")?;
+                    tx.commit().map_err(db_error::SqliteError)?;
+                }
                 SPV_DB_VERSION => {
                     break;
                 }
@@ -347,8 +1221,8 @@ impl SpvClient {
             }
         };
 
-        let mut conn = sqlite_open(headers_path, open_flags, false)
-            .map_err(|e| btc_error::DBError(db_error::SqliteError(e)))?;
+        let mut conn =
+            sqlite_open(headers_path, open_flags, false).map_err(db_error::SqliteError)?;
 
         if create_flag {
             SpvClient::db_instantiate(&mut conn)?;
@@ -360,24 +1234,382 @@ impl SpvClient {
         Ok(conn)
     }
 
-    // are headers ready and available?
-    pub fn is_initialized(&self) -> Result<(), btc_error> {
-        fs::metadata(&self.headers_path)
-            .map_err(btc_error::FilesystemError)
-            .and_then(|_m| Ok(()))
+    /// Is the file at the given path a legacy (pre-SQLite) flat headers file?
+    /// Such a file is a sequence of BLOCK_HEADER_SIZE-byte serialized LoneBlockHeaders, starting
+    /// with the genesis header.  Returns false if the file does not exist or is empty.
+    fn is_legacy_headers_file(headers_path: &str) -> Result<bool, btc_error> {
+        let mut f = match fs::File::open(headers_path) {
+            Ok(f) => f,
+            Err(e) => {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    return Ok(false);
+                }
+                return Err(btc_error::FilesystemError(e));
+            }
+        };
+
+        let mut prefix = vec![];
+        f.by_ref()
+            .take(SQLITE_FILE_MAGIC.len() as u64)
+            .read_to_end(&mut prefix)
+            .map_err(btc_error::FilesystemError)?;
+
+        Ok(prefix.len() > 0 && prefix.as_slice() != SQLITE_FILE_MAGIC)
     }
 
-    /// Get the block range to scan
-    pub fn set_scan_range(&mut self, start_block: u64, end_block: Option<u64>) -> () {
-        self.start_block_height = start_block;
+    /// Read all headers from a legacy flat headers file.
+    /// A trailing partial record (i.e. from a crash in the middle of an append) is ignored, as is
+    /// everything from the first record that can't be decoded onwards.
+    fn read_legacy_headers_file(headers_path: &str) -> Result<Vec<LoneBlockHeader>, btc_error> {
+        let mut f = fs::File::open(headers_path).map_err(btc_error::FilesystemError)?;
+        let mut bytes = vec![];
+        f.read_to_end(&mut bytes)
+            .map_err(btc_error::FilesystemError)?;
+
+        let num_headers = (bytes.len() as u64) / BLOCK_HEADER_SIZE;
+        if (bytes.len() as u64) % BLOCK_HEADER_SIZE != 0 {
+            warn!(
+                "Legacy headers file {} has a partial header at offset {}; ignoring it",
+                headers_path,
+                num_headers * BLOCK_HEADER_SIZE
+            );
+        }
+
+        let mut headers = Vec::with_capacity(num_headers as usize);
+        for chunk in bytes
+            .chunks_exact(BLOCK_HEADER_SIZE as usize)
+            .take(num_headers as usize)
+        {
+            match deserialize::<LoneBlockHeader>(chunk) {
+                Ok(header) => headers.push(header),
+                Err(e) => {
+                    warn!(
+                        "Legacy headers file {} has an undecodable header at height {} ({:?}); ignoring it and the {} headers after it",
+                        headers_path,
+                        headers.len(),
+                        &e,
+                        num_headers - (headers.len() as u64) - 1
+                    );
+                    break;
+                }
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Truncate a legacy headers file to its first `num_headers` headers, dropping anything
+    /// after them that we couldn't use.  The file is never extended, so this is a no-op if it
+    /// doesn't hold more than `num_headers` headers' worth of bytes.
+    fn truncate_legacy_headers_file(headers_path: &str, num_headers: u64) -> Result<(), btc_error> {
+        let f = fs::OpenOptions::new()
+            .write(true)
+            .open(headers_path)
+            .map_err(btc_error::FilesystemError)?;
+        let len = f.metadata().map_err(btc_error::FilesystemError)?.len();
+        let new_len = num_headers * BLOCK_HEADER_SIZE;
+        if new_len >= len {
+            return Ok(());
+        }
+
+        warn!(
+            "Truncate legacy headers file {} from {} to {} bytes ({} headers)",
+            headers_path, len, new_len, num_headers
+        );
+        f.set_len(new_len).map_err(btc_error::FilesystemError)?;
+        f.sync_all().map_err(btc_error::FilesystemError)?;
+        Ok(())
+    }
+
+    /// Read the headers of a legacy headers file that can be imported: those that decode, and
+    /// that build on the genesis header of `network_id` without a break.  The file is truncated
+    /// to them, so the headers after them are gone for good.
+    /// If not even the genesis header can be read, the file is removed and an empty list is
+    /// returned; the headers are then synced from scratch (or from the configured first burn
+    /// block).
+    fn recover_legacy_headers_file(
+        headers_path: &str,
+        network_id: BitcoinNetworkType,
+    ) -> Result<Vec<LoneBlockHeader>, btc_error> {
+        let mut headers = SpvClient::read_legacy_headers_file(headers_path)?;
+        if headers.is_empty() {
+            warn!(
+                "Legacy headers file {} has no valid headers; removing it and syncing headers from scratch",
+                headers_path
+            );
+            fs::remove_file(headers_path).map_err(btc_error::FilesystemError)?;
+            return Ok(headers);
+        }
+
+        SpvClient::check_legacy_genesis_header(headers_path, &headers, network_id)?;
+        SpvClient::check_legacy_headers_linkage(headers_path, &mut headers);
+        SpvClient::truncate_legacy_headers_file(headers_path, headers.len() as u64)?;
+        Ok(headers)
+    }
+
+    /// First step of the one-shot migration of a legacy flat headers file at `headers_path` into
+    /// a headers DB at the same path.  The legacy file's first header must be the genesis header
+    /// for `network_id`; if it is not, btc_error::WrongNetwork is returned and the file is left
+    /// untouched.  Otherwise, any headers after a torn, corrupt, or non-contiguous record are
+    /// discarded (see `recover_legacy_headers_file()`), and the file is moved aside to
+    /// `{headers_path}.legacy` so the headers DB can be created in its place.  The headers are
+    /// imported once the DB is open (see `finish_legacy_headers_migration()`).
+    ///
+    /// This is a no-op if there is no legacy headers file to migrate.
+    fn stage_legacy_headers_file(
+        headers_path: &str,
+        network_id: BitcoinNetworkType,
+    ) -> Result<(), btc_error> {
+        if !SpvClient::is_legacy_headers_file(headers_path)? {
+            return Ok(());
+        }
+        let headers = SpvClient::recover_legacy_headers_file(headers_path, network_id)?;
+        if headers.is_empty() {
+            return Ok(());
+        }
+        let legacy_path = format!("{}.legacy", headers_path);
+        fs::rename(headers_path, &legacy_path).map_err(btc_error::FilesystemError)?;
+        Ok(())
+    }
+
+    /// Second step of the one-shot migration of a legacy flat headers file (see
+    /// `stage_legacy_headers_file()`):  import the headers of `{headers_path}.legacy` into our
+    /// headers DB.  They are validated like any other imported headers -- linkage, checkpoints,
+    /// versions, and proof-of-work -- and stored in a single transaction (see
+    /// `import_headers()`).  The legacy file is only removed once that transaction commits, so
+    /// if we crash partway through, the migration is simply re-run (idempotently) the next time
+    /// the headers are opened.  If the legacy file's headers are invalid, the error is returned
+    /// and the legacy file is kept.
+    ///
+    /// This is a no-op if there is no legacy headers file to migrate.
+    fn finish_legacy_headers_migration(&mut self) -> Result<(), btc_error> {
+        let legacy_path = format!("{}.legacy", &self.headers_path);
+        if fs::metadata(&legacy_path).is_err() {
+            // nothing to do
+            return Ok(());
+        }
+
+        let headers = SpvClient::recover_legacy_headers_file(&legacy_path, self.network_id)?;
+        if headers.is_empty() {
+            return Ok(());
+        }
+
+        info!(
+            "Migrate {} headers from legacy headers file {} to {}",
+            headers.len(),
+            &legacy_path,
+            &self.headers_path
+        );
+        self.import_header_chain(&headers)?;
+
+        fs::remove_file(&legacy_path).map_err(btc_error::FilesystemError)?;
+        Ok(())
+    }
+
+    /// Import the headers of a legacy flat headers file at `path` (see
+    /// `is_legacy_headers_file()`) on top of the headers we already have.  The file's first
+    /// header must be our network's genesis header, and only its headers before the first torn,
+    /// corrupt, or non-contiguous record are read.  They are then validated like any other
+    /// imported headers, including their proof-of-work (see `import_headers()`).  The file
+    /// itself is left as it is.
+    /// Returns the height of our chain tip afterwards.
+    pub fn import_legacy_headers_file(&mut self, path: &str) -> Result<u64, btc_error> {
+        assert!(self.readwrite, "SPV header DB is open read-only");
+
+        let mut headers = SpvClient::read_legacy_headers_file(path)?;
+        SpvClient::check_legacy_genesis_header(path, &headers, self.network_id)?;
+        SpvClient::check_legacy_headers_linkage(path, &mut headers);
+        let tip_height = self.import_header_chain(&headers)?;
+        debug!("Read legacy headers file {} up to {}", path, tip_height);
+        Ok(tip_height)
+    }
+
+    /// Verify that a legacy headers file's headers begin with the genesis header of the given
+    /// network.
+    fn check_legacy_genesis_header(
+        headers_path: &str,
+        headers: &[LoneBlockHeader],
+        network_id: BitcoinNetworkType,
+    ) -> Result<(), btc_error> {
+        let genesis_header = SpvClient::get_genesis_header(network_id);
+        match headers.first() {
+            Some(first_header) if first_header.header == genesis_header => Ok(()),
+            Some(first_header) => {
+                error!(
+                    "Legacy headers file {} starts with block {}, which is not the {:?} genesis block {}",
+                    headers_path,
+                    first_header.header.bitcoin_hash(),
+                    network_id,
+                    genesis_header.bitcoin_hash()
+                );
+                Err(btc_error::WrongNetwork)
+            }
+            None => {
+                error!("Legacy headers file {} has no headers", headers_path);
+                Err(btc_error::MissingHeader)
+            }
+        }
+    }
+
+    /// Verify that each of a legacy headers file's headers builds on the one before it.
+    /// The headers from the first one that doesn't onwards are discarded, leaving the longest
+    /// contiguous run of headers from genesis.
+    fn check_legacy_headers_linkage(headers_path: &str, headers: &mut Vec<LoneBlockHeader>) {
+        let break_height = match (1..headers.len())
+            .find(|i| headers[*i].header.prev_blockhash != headers[*i - 1].header.bitcoin_hash())
+        {
+            Some(i) => i as u64,
+            None => return,
+        };
+
+        let tip_height = (headers.len() as u64) - 1;
+        warn!(
+            "Legacy headers file {} breaks off at header {}; discarding {} headers ({}-{})",
+            headers_path,
+            break_height,
+            tip_height - break_height + 1,
+            break_height,
+            tip_height
+        );
+        headers.truncate(break_height as usize);
+    }
+
+    // are headers ready and available?
+    pub fn is_initialized(&self) -> Result<(), btc_error> {
+        fs::metadata(&self.headers_path)
+            .map_err(btc_error::FilesystemError)
+            .and_then(|_m| Ok(()))
+    }
+
+    /// Get the block range to scan
+    pub fn set_scan_range(&mut self, start_block: u64, end_block: Option<u64>) -> () {
+        self.start_block_height = start_block;
         self.end_block_height = end_block;
         self.cur_block_height = start_block;
     }
 
-    /// go get all the headers.
+    /// go get all the headers from our p2p peer.
     /// keep trying forever.
     pub fn run(&mut self, indexer: &mut BitcoinIndexer) -> Result<(), btc_error> {
-        indexer.peer_communicate(self, true)
+        self.sync_from(indexer)
+    }
+
+    /// Get all the headers from the current block height up to the source's chain tip, and
+    /// validate and store them.
+    pub fn sync_from<S: HeaderSource>(&mut self, source: &mut S) -> Result<(), btc_error> {
+        let start_height = self.cur_block_height;
+        let end_block_height = match source.get_tip_height() {
+            Ok(height) => height,
+            Err(btc_error::ShutdownRequested) => {
+                return self.stop_for_shutdown(start_height);
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        };
+        self.end_block_height = Some(end_block_height);
+
+        if end_block_height <= start_height {
+            debug!("Have all headers up to {}", start_height);
+            return Ok(());
+        }
+
+        debug!(
+            "Get headers {}-{} to {}",
+            start_height, end_block_height, self.headers_path
+        );
+
+        let mut block_height = start_height;
+        while self.cur_block_height < end_block_height {
+            if self.is_shutdown_requested() {
+                return self.stop_for_shutdown(block_height);
+            }
+            let locator = self.make_next_block_locator(block_height)?;
+            let block_headers = match source.get_headers(locator) {
+                Ok(block_headers) => block_headers,
+                Err(btc_error::ShutdownRequested) => {
+                    // the source didn't answer before the shutdown deadline
+                    return self.stop_for_shutdown(block_height);
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            };
+            if block_headers.len() == 0 {
+                debug!(
+                    "Header source has no headers after {}; expected headers up to {}",
+                    block_height, end_block_height
+                );
+                break;
+            }
+            self.absorb_headers(block_headers)?;
+            block_height = self.get_highest_header_height()?;
+        }
+        self.flush_unsynced_headers()
+    }
+
+    /// Stop syncing headers because a shutdown was requested, making the header batches stored
+    /// so far durable
+    fn stop_for_shutdown(&mut self, block_height: u64) -> Result<(), btc_error> {
+        info!(
+            "Shutdown requested; stop syncing headers at {}",
+            block_height
+        );
+        self.flush_unsynced_headers()
+    }
+
+    /// Validate and store a batch of headers that a header source sent after we asked it for
+    /// the headers after our highest header.  Headers past `end_block_height` are dropped.
+    fn absorb_headers(&mut self, mut block_headers: Vec<LoneBlockHeader>) -> Result<(), btc_error> {
+        let end_block_height = self
+            .end_block_height
+            .expect("BUG: absorbing headers without an end block height");
+
+        // only handle headers we asked for
+        if end_block_height - self.cur_block_height < block_headers.len() as u64 {
+            debug!(
+                "Truncate received headers from block range {}-{} to range {}-{}",
+                self.cur_block_height,
+                end_block_height,
+                self.cur_block_height,
+                self.cur_block_height + (block_headers.len() as u64) - end_block_height
+            );
+            block_headers.truncate((end_block_height - self.cur_block_height) as usize);
+        }
+
+        let insert_height = self.cur_block_height;
+        let num_headers = block_headers.len();
+
+        if self.handle_headers(insert_height, block_headers)?.is_some() {
+            // the headers replaced some of ours, so they didn't start at insert_height
+            self.cur_block_height = self.get_highest_header_height()?;
+        } else {
+            self.cur_block_height += num_headers as u64;
+        }
+        self.report_progress(end_block_height)?;
+
+        let block_height = self.get_highest_header_height()?;
+        assert!(block_height > 0, "BUG: uninitialized SPV headers DB");
+
+        // if syncing requires to request more than one batch of 2000 headers,
+        // we'll provide some progress in the logs
+        let total = end_block_height - self.start_block_height;
+        let batch_size = 2000;
+        if total > batch_size {
+            let progress = (block_height - self.start_block_height) as f32 / total as f32 * 100.;
+            info!(
+                "Syncing Bitcoin headers: {:.1}% ({} out of {})",
+                progress, block_height, total
+            );
+        } else {
+            debug!(
+                "Request headers for blocks {} - {} in range {} - {}",
+                block_height,
+                block_height + batch_size,
+                self.start_block_height,
+                end_block_height
+            );
+        }
+        Ok(())
     }
 
     /// Calculate the total work over a given interval of headers.
@@ -449,7 +1681,12 @@ impl SpvClient {
     /// This method is idempotent.
     /// Returns the total work.
     pub fn update_chain_work(&mut self) -> Result<Uint256, btc_error> {
-        let highest_interval = self.find_highest_work_score_interval()?;
+        // the headers of the intervals below the lowest header we have are gone, so their work
+        // scores can't be recalculated (and must already be stored)
+        let highest_interval = cmp::max(
+            self.find_highest_work_score_interval()?,
+            self.get_pruned_height()? / BLOCK_DIFFICULTY_CHUNK_SIZE,
+        );
         let mut work_so_far = if highest_interval > 0 {
             self.find_interval_work(highest_interval - 1)?
                 .expect("FATAL: no work score for highest known interval")
@@ -495,9 +1732,105 @@ impl SpvClient {
             }
         }
 
+        if self.readwrite {
+            self.update_header_chain_work()?;
+        }
         Ok(work_so_far)
     }
 
+    /// Fill in the running total work of each header that doesn't have one yet, starting from
+    /// the lowest header (the genesis header, or the anchor of a pruned chain) and stopping at
+    /// the first missing header.
+    fn update_header_chain_work(&mut self) -> Result<(), btc_error> {
+        let highest_height = query_row::<u64, _>(
+            &self.headers_db,
+            "SELECT height FROM header_chain_work ORDER BY height DESC LIMIT 1",
+            NO_PARAMS,
+        )?;
+        let (mut next_height, mut work_so_far) = match highest_height {
+            Some(height) => (
+                height + 1,
+                self.get_chain_work_at(height)?
+                    .expect("FATAL: no work for highest header with work"),
+            ),
+            None => {
+                let pruned_height = self.get_pruned_height()?;
+                if pruned_height == 0 {
+                    (0, Uint256::from_u64(0))
+                } else {
+                    // pruning only happens on interval boundaries, so the work of the pruned
+                    // headers is the running total of the interval before the anchor
+                    match self
+                        .find_interval_work(pruned_height / BLOCK_DIFFICULTY_CHUNK_SIZE - 1)?
+                    {
+                        Some(work) => (pruned_height, work),
+                        None => {
+                            warn!(
+                                "No work score for headers pruned below {} in {}",
+                                pruned_height, &self.headers_path
+                            );
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        };
+
+        let tip_height = self.get_highest_header_height()?;
+        while next_height <= tip_height {
+            let end_height = cmp::min(next_height + BLOCK_DIFFICULTY_CHUNK_SIZE, tip_height + 1);
+            let headers = self.read_block_headers(next_height, end_height)?;
+            if headers.len() == 0 {
+                break;
+            }
+
+            let tx = self.tx_begin()?;
+            for (i, hdr) in headers.iter().enumerate() {
+                work_so_far = work_so_far + hdr.header.work();
+                let args: &[&dyn ToSql] = &[
+                    &u64_to_sql(next_height + (i as u64))?,
+                    &work_so_far.to_hex_be(),
+                ];
+                tx.execute(
+                    "INSERT OR REPLACE INTO header_chain_work (height,work) VALUES (?1,?2)",
+                    args,
+                )
+                .map_err(db_error::SqliteError)?;
+            }
+            tx.commit().map_err(db_error::SqliteError)?;
+
+            if (headers.len() as u64) < end_height - next_height {
+                // gap in the headers
+                break;
+            }
+            next_height = end_height;
+        }
+        Ok(())
+    }
+
+    /// Get the total work of the header chain up to and including the header at `height`.
+    /// Returns None if we don't have the header, or if its work hasn't been calculated yet --
+    /// you will have needed to call update_chain_work() prior to this after inserting new
+    /// headers.
+    pub fn get_chain_work_at(&self, height: u64) -> Result<Option<Uint256>, btc_error> {
+        let work_hex: Option<String> = self
+            .conn()
+            .query_row(
+                "SELECT work FROM header_chain_work WHERE height = ?1",
+                &[&u64_to_sql(height)?],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error::SqliteError)?;
+        Ok(work_hex.map(|x| Uint256::from_hex_be(&x).expect("FATAL: work is not a uint256")))
+    }
+
+    /// Get the total work of the header chain up to and including our highest header.
+    /// Returns None under the same conditions as get_chain_work_at().
+    pub fn get_tip_chain_work(&self) -> Result<Option<Uint256>, btc_error> {
+        self.get_chain_work_at(self.get_highest_header_height()?)
+    }
+
     /// Get the total chain work.
     /// You will have needed to call update_chain_work() prior to this after inserting new headers.
     pub fn get_chain_work(&self) -> Result<Uint256, btc_error> {
@@ -573,8 +1906,121 @@ impl SpvClient {
         return Ok(());
     }
 
+    /// Verify that none of the given headers contradict a checkpoint, the assume-valid header, or
+    /// the first burn block.
+    /// `first_height` is the height of the first header in `headers`.
+    fn validate_header_checkpoints(
+        &self,
+        first_height: u64,
+        headers: &[LoneBlockHeader],
+    ) -> Result<(), btc_error> {
+        let end_height = first_height + (headers.len() as u64);
+        for (height, hash) in self.checkpoints.iter() {
+            if *height < first_height || *height >= end_height {
+                continue;
+            }
+            let header = &headers[(*height - first_height) as usize];
+            let header_hash = BurnchainHeaderHash::from_bitcoin_hash(&header.header.bitcoin_hash());
+            if header_hash != *hash {
+                warn!(
+                    "Header at checkpoint height {} has hash {}, but expected {}",
+                    height,
+                    header.header.bitcoin_hash(),
+                    hash.to_bitcoin_hash()
+                );
+                return Err(btc_error::CheckpointMismatch(*height));
+            }
+        }
+        if let Some((height, ref hash)) = self.assume_valid {
+            if height >= first_height && height < end_height {
+                let header = &headers[(height - first_height) as usize];
+                let header_hash =
+                    BurnchainHeaderHash::from_bitcoin_hash(&header.header.bitcoin_hash());
+                if header_hash != *hash {
+                    error!(
+                        "Header at assume-valid height {} has hash {}, but expected {}",
+                        height,
+                        header.header.bitcoin_hash(),
+                        hash.to_bitcoin_hash()
+                    );
+                    return Err(btc_error::AssumeValidMismatch(height));
+                }
+            }
+        }
+        if let Some((height, ref hash)) = self.first_block {
+            if height >= first_height && height < end_height {
+                let header = &headers[(height - first_height) as usize];
+                let header_hash =
+                    BurnchainHeaderHash::from_bitcoin_hash(&header.header.bitcoin_hash());
+                if header_hash != *hash {
+                    error!(
+                        "Header at first block height {} has hash {}, but expected {}",
+                        height,
+                        header.header.bitcoin_hash(),
+                        hash.to_bitcoin_hash()
+                    );
+                    return Err(btc_error::FirstBlockMismatch(height));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the heights from which headers on a network must have a minimum version.
+    /// Regtest and signet have none.
+    pub fn min_header_versions(network_id: BitcoinNetworkType) -> &'static [(u64, u32)] {
+        match network_id {
+            BitcoinNetworkType::Mainnet => BITCOIN_MAINNET_MIN_HEADER_VERSIONS,
+            BitcoinNetworkType::Testnet => BITCOIN_TESTNET_MIN_HEADER_VERSIONS,
+            BitcoinNetworkType::Regtest | BitcoinNetworkType::Signet => &[],
+        }
+    }
+
+    /// If strict header versions are on, verify that none of the given headers, starting at
+    /// `first_height`, has a version below the minimum for its height.  Like Bitcoin Core, the
+    /// version is compared as a signed integer, so versions with the high bit set are too low.
+    fn validate_header_versions(
+        &self,
+        first_height: u64,
+        headers: &[LoneBlockHeader],
+    ) -> Result<(), btc_error> {
+        if !self.strict_header_versions {
+            return Ok(());
+        }
+        let min_versions = SpvClient::min_header_versions(self.network_id);
+        for (i, header) in headers.iter().enumerate() {
+            let height = first_height + (i as u64);
+            let min_version = min_versions
+                .iter()
+                .filter(|(activation_height, _)| height >= *activation_height)
+                .map(|(_, version)| *version)
+                .max();
+            if let Some(min_version) = min_version {
+                if (header.header.version as i32) < (min_version as i32) {
+                    warn!(
+                        "Header {} at height {} has version {}, but must have at least version {}",
+                        header.header.bitcoin_hash(),
+                        height,
+                        header.header.version,
+                        min_version
+                    );
+                    return Err(btc_error::ObsoleteHeaderVersion(
+                        height,
+                        header.header.version,
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Verify that the given headers have the correct amount of work to be appended to our
     /// local header chain.  Checks the difficulty between [interval, interval+1]
+    /// On regtest, only the timestamps are checked.  Headers at or below the assume-valid height
+    /// (if any) are not checked.
+    /// The rules that depend on earlier headers (timestamps and retargets) are checked one
+    /// header at a time, and then the hashes of the interval's headers are checked against their
+    /// targets in parallel.
     fn validate_header_work(
         &self,
         interval_start: u64,
@@ -586,68 +2032,156 @@ impl SpvClient {
             interval_end * BLOCK_DIFFICULTY_CHUNK_SIZE
         );
         assert!(interval_start <= interval_end);
-        if interval_start == 0 {
-            return Ok(());
-        }
 
         for i in interval_start..interval_end {
-            let mut headers = VecDeque::new();
-            for block_height in
-                (i * BLOCK_DIFFICULTY_CHUNK_SIZE)..((i + 1) * BLOCK_DIFFICULTY_CHUNK_SIZE)
+            let mut pow_batch = Vec::with_capacity(BLOCK_DIFFICULTY_CHUNK_SIZE as usize);
+            let res = self.validate_interval_rules(i, &mut pow_batch);
+
+            // check the hashes we got to even if a later header broke a rule, so the error is
+            // always about the first invalid header
+            if let Some(block_height) =
+                SpvClient::find_insufficient_work(pow_batch, self.pow_threads)
             {
-                let header_i = match self.read_block_header(block_height)? {
-                    None => return Ok(()),
-                    Some(res) => res.header,
-                };
+                error!(
+                    "block {} has less work than its target in {}",
+                    block_height, self.headers_path
+                );
+                return Err(btc_error::InvalidPoW);
+            }
+            if !res? {
+                // out of headers
+                return Ok(());
+            }
+        }
+        return Ok(());
+    }
 
-                // each header's timestamp must exceed the median of the past 11 blocks
-                if block_height > 11 {
-                    let past_11_headers =
-                        self.read_block_headers(block_height - 11, block_height)?;
-                    let mut past_timestamps: Vec<u32> =
-                        past_11_headers.iter().map(|hdr| hdr.header.time).collect();
-                    past_timestamps.sort();
+    /// Check the timestamps and difficulty bits of the headers in difficulty interval
+    /// `interval`, and add each header whose hash must meet a target to `pow_batch`, along with
+    /// its height and target.
+    /// Returns Ok(false) if we ran out of headers.
+    fn validate_interval_rules(
+        &self,
+        interval: u64,
+        pow_batch: &mut Vec<(u64, BlockHeader, Uint256)>,
+    ) -> Result<bool, btc_error> {
+        let tip_height = self.get_highest_header_height()?;
+        let pruned_height = self.get_pruned_height()?;
+        let max_time = get_epoch_time_secs() + MAX_FUTURE_BLOCK_TIME;
+        let mut headers = VecDeque::new();
+        for block_height in
+            (interval * BLOCK_DIFFICULTY_CHUNK_SIZE)..((interval + 1) * BLOCK_DIFFICULTY_CHUNK_SIZE)
+        {
+            let header_i = match self.read_block_header(block_height)? {
+                None => return Ok(false),
+                Some(res) => res.header,
+            };
+
+            if self.is_assumed_valid(block_height)
+                || (pruned_height > 0 && block_height == pruned_height)
+            {
+                // buried under the assume-valid header, so only its linkage (already
+                // checked when it was inserted) matters.  The lowest header of a pruned chain
+                // is trusted outright.
+                headers.push_front(header_i);
+                continue;
+            }
 
-                    if header_i.time <= past_timestamps[5] {
+            // each header's timestamp must exceed its parent's median-time-past
+            if block_height > 0 {
+                if let Some(median) = self.median_time_past(block_height - 1)? {
+                    if header_i.time <= median {
                         error!(
-                            "Block {} timestamp {} <= {} (median of {:?})",
-                            block_height, header_i.time, past_timestamps[5], &past_timestamps
+                            "Block {} timestamp {} <= {} (median time past of block {})",
+                            block_height,
+                            header_i.time,
+                            median,
+                            block_height - 1
                         );
-                        return Err(btc_error::InvalidPoW);
+                        return Err(btc_error::TimestampTooOld(block_height));
                     }
                 }
+            }
 
-                // header difficulty must not change in a difficulty interval
-                let (bits, difficulty) =
-                    match self.get_target(block_height, &header_i, &headers, i)? {
-                        Some(x) => x,
-                        None => {
-                            // out of headers
-                            return Ok(());
-                        }
-                    };
+            // headers near the tip can't be from too far in the future.  Older headers aren't
+            // checked, so a bad local clock can't stop us from syncing history.
+            if block_height + FUTURE_BLOCK_TIME_CHECK_DEPTH > tip_height
+                && (header_i.time as u64) > max_time
+            {
+                error!(
+                    "Block {} timestamp {} is more than {} seconds ahead of our clock",
+                    block_height, header_i.time, MAX_FUTURE_BLOCK_TIME
+                );
+                return Err(btc_error::TimestampTooNew(block_height));
+            }
 
-                if header_i.bits != bits {
-                    error!("bits mismatch at block {} of {} (offset {} interval {} of {}-{}): {:08x} != {:08x}",
-                            block_height, self.headers_path, block_height % BLOCK_DIFFICULTY_CHUNK_SIZE, i, interval_start, interval_end, header_i.bits, bits);
-                    return Err(btc_error::InvalidPoW);
-                }
-                let header_hash = header_i.bitcoin_hash().into_le();
-                if difficulty < header_hash {
-                    error!(
-                        "block {} hash {} has less work than difficulty {} in {}",
-                        block_height,
-                        header_i.bitcoin_hash(),
-                        difficulty,
-                        self.headers_path
-                    );
-                    return Err(btc_error::InvalidPoW);
+            if self.network_id == BitcoinNetworkType::Regtest {
+                // regtest blocks can be mined at any difficulty, so there's no target to
+                // check.  Their linkage was already checked when they were inserted.
+                headers.push_front(header_i);
+                continue;
+            }
+
+            // header difficulty must match the retarget, and must not change in a
+            // difficulty interval
+            let difficulty = match self.verify_retarget(block_height, &header_i, &headers)? {
+                Some(x) => x,
+                None => {
+                    // out of headers
+                    return Ok(false);
                 }
+            };
 
-                headers.push_front(header_i);
+            pow_batch.push((block_height, header_i, difficulty));
+            headers.push_front(header_i);
+        }
+        Ok(true)
+    }
+
+    /// Find the first header whose hash does not meet its target.  `headers` are (height,
+    /// header, target) tuples in ascending height order.  The hashes are checked on up to
+    /// `num_threads` threads, each taking a contiguous run of headers.
+    /// Returns the height of the first such header, if there is one.
+    pub fn find_insufficient_work(
+        headers: Vec<(u64, BlockHeader, Uint256)>,
+        num_threads: usize,
+    ) -> Option<u64> {
+        // not worth spawning threads for a handful of hashes
+        let num_threads = cmp::min(num_threads, headers.len() / MIN_POW_CHECKS_PER_THREAD);
+        if num_threads <= 1 {
+            return SpvClient::find_insufficient_work_serial(&headers);
+        }
+
+        let chunk_size = (headers.len() + num_threads - 1) / num_threads;
+        let mut rest = headers;
+        let mut workers = Vec::with_capacity(num_threads);
+        while rest.len() > 0 {
+            let tail = rest.split_off(cmp::min(chunk_size, rest.len()));
+            let chunk = mem::replace(&mut rest, tail);
+            workers.push(thread::spawn(move || {
+                SpvClient::find_insufficient_work_serial(&chunk)
+            }));
+        }
+
+        // the chunks are in height order, so the first chunk with a bad header has the first bad
+        // header
+        let mut first_bad_height = None;
+        for worker in workers.into_iter() {
+            let bad_height = worker
+                .join()
+                .expect("FATAL: proof-of-work check thread panicked");
+            if first_bad_height.is_none() {
+                first_bad_height = bad_height;
             }
         }
-        return Ok(());
+        first_bad_height
+    }
+
+    fn find_insufficient_work_serial(headers: &[(u64, BlockHeader, Uint256)]) -> Option<u64> {
+        headers
+            .iter()
+            .find(|(_, header, target)| *target < header.bitcoin_hash().into_le())
+            .map(|(block_height, _, _)| *block_height)
     }
 
     /// Report how many block headers (+ 1) we have downloaded to the given path.
@@ -673,12 +2207,40 @@ impl SpvClient {
         Ok(self.get_highest_header_height()? == 0)
     }
 
-    /// Read the block header at a particular height
-    /// Returns None if the requested block height is beyond the end of the headers file
-    pub fn read_block_header(
+    /// Get the height below which headers have been pruned (0 if none have been).
+    /// The header at this height is the lowest one we have.
+    pub fn get_pruned_height(&self) -> Result<u64, btc_error> {
+        match query_row::<u64, _>(
+            &self.headers_db,
+            "SELECT IFNULL(MAX(height),0) FROM pruned_headers",
+            NO_PARAMS,
+        )? {
+            Some(height) => Ok(height),
+            None => Ok(0),
+        }
+    }
+
+    /// Fail with MissingHeader if the header at `block_height` has been pruned
+    fn check_not_pruned(&self, block_height: u64) -> Result<(), btc_error> {
+        let pruned_height = self.get_pruned_height()?;
+        if block_height < pruned_height {
+            debug!(
+                "Header {} was pruned from {} (lowest header is {})",
+                block_height, &self.headers_path, pruned_height
+            );
+            return Err(btc_error::MissingHeader);
+        }
+        Ok(())
+    }
+
+    /// Read the block header at a particular height
+    /// Returns None if the requested block height is beyond the end of the headers file
+    /// Returns MissingHeader if the header was pruned
+    pub fn read_block_header(
         &self,
         block_height: u64,
     ) -> Result<Option<LoneBlockHeader>, btc_error> {
+        self.check_not_pruned(block_height)?;
         let header_opt: Option<BlockHeader> = query_row(
             &self.headers_db,
             "SELECT * FROM headers WHERE height = ?1",
@@ -690,6 +2252,116 @@ impl SpvClient {
         }))
     }
 
+    /// Check a merkle proof that the transaction `txid` is at `position` in our block at
+    /// `block_height`, against the merkle root of our header there (see
+    /// `blocks::verify_merkle_proof()`).  Returns MissingHeader if we don't have the header.
+    pub fn verify_tx_inclusion(
+        &self,
+        txid: &Sha256dHash,
+        branch: &[Sha256dHash],
+        position: u32,
+        block_height: u64,
+    ) -> Result<bool, btc_error> {
+        let header = self
+            .read_block_header(block_height)?
+            .ok_or(btc_error::MissingHeader)?;
+        verify_merkle_proof(txid, branch, position, &header.header)
+    }
+
+    /// Get the timestamp and median-time-past of the block header at `block_height`.  Near
+    /// genesis (or the lowest header of a pruned chain), the median-time-past is the median of
+    /// however many headers there are, up to MEDIAN_TIME_SPAN.
+    /// Returns None if we don't have the header, and MissingHeader if it was pruned.
+    pub fn read_block_times(&self, block_height: u64) -> Result<Option<BlockTimes>, btc_error> {
+        self.check_not_pruned(block_height)?;
+        let lowest_height = cmp::max(
+            block_height.saturating_sub(MEDIAN_TIME_SPAN - 1),
+            self.get_pruned_height()?,
+        );
+        let headers = self.read_block_headers(lowest_height, block_height + 1)?;
+        let timestamp = match headers.last() {
+            Some(hdr) if (headers.len() as u64) == block_height + 1 - lowest_height => {
+                hdr.header.time
+            }
+            _ => {
+                return Ok(None);
+            }
+        };
+
+        let mut timestamps: Vec<u32> = headers.iter().map(|hdr| hdr.header.time).collect();
+        timestamps.sort();
+        Ok(Some(BlockTimes {
+            timestamp,
+            median_time_past: timestamps[timestamps.len() / 2],
+        }))
+    }
+
+    /// Get the median-time-past of the block header at `block_height` (see `read_block_times()`)
+    pub fn median_time_past(&self, block_height: u64) -> Result<Option<u32>, btc_error> {
+        Ok(self
+            .read_block_times(block_height)?
+            .map(|times| times.median_time_past))
+    }
+
+    /// Read the block header with the given burnchain header hash, along with its height.
+    /// Returns None if we don't have it.
+    pub fn get_header_by_hash(
+        &self,
+        burn_header_hash: &BurnchainHeaderHash,
+    ) -> Result<Option<(u64, LoneBlockHeader)>, btc_error> {
+        // one query, so a concurrent reorg can't change the header between finding its height
+        // and reading it
+        Ok(self
+            .query_headers_with_heights(
+                "SELECT * FROM headers WHERE hash = ?1",
+                &[burn_header_hash],
+            )?
+            .pop())
+    }
+
+    /// Get our highest block header and its height, or None if we have no headers.
+    /// Unlike get_highest_header_height() followed by read_block_header(), this is a single
+    /// query, so it's safe to use while another thread is writing headers.
+    pub fn get_tip(&self) -> Result<Option<(u64, LoneBlockHeader)>, btc_error> {
+        Ok(self
+            .query_headers_with_heights(
+                "SELECT * FROM headers ORDER BY height DESC LIMIT 1",
+                NO_PARAMS,
+            )?
+            .pop())
+    }
+
+    /// Run a query over the headers table, and get each matching header with its height
+    fn query_headers_with_heights<P>(
+        &self,
+        sql_query: &str,
+        sql_args: P,
+    ) -> Result<Vec<(u64, LoneBlockHeader)>, btc_error>
+    where
+        P: IntoIterator,
+        P::Item: ToSql,
+    {
+        let mut stmt = self
+            .headers_db
+            .prepare(sql_query)
+            .map_err(db_error::SqliteError)?;
+        let mut rows = stmt.query(sql_args).map_err(db_error::SqliteError)?;
+
+        let mut headers = vec![];
+        while let Some(row) = rows.next().map_err(db_error::SqliteError)? {
+            let height: u64 = u64::from_column(&row, "height")?;
+            let header = BlockHeader::from_row(&row)?;
+            headers.push((
+                height,
+                LoneBlockHeader {
+                    header,
+                    tx_count: VarInt(0),
+                },
+            ));
+        }
+        Ok(headers)
+    }
+
     /// Find a block header height with a given burnchain header hash, if it is present
     pub fn find_block_header_height(
         &self,
@@ -703,18 +2375,58 @@ impl SpvClient {
         .map_err(|e| e.into())
     }
 
+    /// Get the block header at the given height.
+    /// Unlike read_block_header(), this returns None (instead of MissingHeader) if the header was
+    /// pruned.
+    pub fn get_header(&self, height: u64) -> Result<Option<LoneBlockHeader>, btc_error> {
+        match self.read_block_header(height) {
+            Err(btc_error::MissingHeader) => Ok(None),
+            res => res,
+        }
+    }
+
+    /// Get up to `count` consecutive block headers, starting at `start_height`.
+    /// The list is truncated at the first header we don't have.
+    pub fn get_headers_range(
+        &self,
+        start_height: u64,
+        count: u64,
+    ) -> Result<Vec<LoneBlockHeader>, btc_error> {
+        // heights are stored as i64
+        let end_height = cmp::min(start_height.saturating_add(count), i64::MAX as u64);
+        self.read_block_headers(start_height, end_height)
+    }
+
+    /// Get the height of the block header with the given hash, if we have it.
+    /// This is a lookup in index_headers_by_hash, not a scan.
+    pub fn get_height_of(
+        &self,
+        burn_header_hash: &BurnchainHeaderHash,
+    ) -> Result<Option<u64>, btc_error> {
+        self.find_block_header_height(burn_header_hash)
+    }
+
+    /// Do we have the block header with the given hash?
+    pub fn contains(&self, burn_header_hash: &BurnchainHeaderHash) -> Result<bool, btc_error> {
+        Ok(self.get_height_of(burn_header_hash)?.is_some())
+    }
+
     /// Get a range of block headers from a file.
     /// If the range falls of the end of the headers file, then the returned array will be
     /// truncated to not include them (note that this method can return an empty list of the
     /// start_block is off the end of the file).
     /// If the range does _not_ include start_block, then this method returns an empty array (even
     /// if there are headers in the range).
+    /// Returns MissingHeader if start_block was pruned.
     pub fn read_block_headers(
         &self,
         start_block: u64,
         end_block: u64,
     ) -> Result<Vec<LoneBlockHeader>, btc_error> {
         let mut headers = vec![];
+        if start_block < end_block {
+            self.check_not_pruned(start_block)?;
+        }
 
         let sql_query = "SELECT * FROM headers WHERE height >= ?1 AND height < ?2 ORDER BY height";
         let sql_args: &[&dyn ToSql] = &[&u64_to_sql(start_block)?, &u64_to_sql(end_block)?];
@@ -722,18 +2434,13 @@ impl SpvClient {
         let mut stmt = self
             .headers_db
             .prepare(sql_query)
-            .map_err(|e| btc_error::DBError(db_error::SqliteError(e)))?;
+            .map_err(db_error::SqliteError)?;
 
-        let mut rows = stmt
-            .query(sql_args)
-            .map_err(|e| btc_error::DBError(db_error::SqliteError(e)))?;
+        let mut rows = stmt.query(sql_args).map_err(db_error::SqliteError)?;
 
         // gather, but make sure we get _all_ headers
         let mut next_height = start_block;
-        while let Some(row) = rows
-            .next()
-            .map_err(|e| btc_error::DBError(db_error::SqliteError(e)))?
-        {
+        while let Some(row) = rows.next().map_err(db_error::SqliteError)? {
             let height: u64 = u64::from_column(&row, "height")?;
             if height != next_height {
                 break;
@@ -770,16 +2477,21 @@ impl SpvClient {
             &BurnchainHeaderHash::from_bitcoin_hash(&header.bitcoin_hash()),
         ];
 
-        tx.execute(sql, args)
-            .map_err(|e| btc_error::DBError(db_error::SqliteError(e)))
-            .and_then(|_x| Ok(()))
+        tx.execute(sql, args).map_err(db_error::SqliteError)?;
+
+        // the running work of this header and its descendants must be recalculated
+        tx.execute(
+            "DELETE FROM header_chain_work WHERE height >= ?1",
+            &[&u64_to_sql(height)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        Ok(())
     }
 
-    /// Initialize the block headers file with the genesis block hash.
-    /// Optionally sip migration for testing.
-    fn init_block_headers(&mut self, migrate: bool) -> Result<(), btc_error> {
-        assert!(self.readwrite, "SPV header DB is open read-only");
-        let (genesis_block, genesis_block_hash_str) = match self.network_id {
+    /// Get the genesis block header for the given network.
+    /// Panics if the header does not hash to the well-known genesis block hash.
+    pub fn get_genesis_header(network_id: BitcoinNetworkType) -> BlockHeader {
+        let (genesis_block, genesis_block_hash_str) = match network_id {
             BitcoinNetworkType::Mainnet => (
                 genesis_block(Network::Bitcoin),
                 BITCOIN_GENESIS_BLOCK_HASH_MAINNET,
@@ -792,11 +2504,15 @@ impl SpvClient {
                 genesis_block(Network::Regtest),
                 BITCOIN_GENESIS_BLOCK_HASH_REGTEST,
             ),
+            BitcoinNetworkType::Signet => (
+                genesis_block(Network::Signet),
+                BITCOIN_GENESIS_BLOCK_HASH_SIGNET,
+            ),
         };
 
         // sanity check
-        let genesis_block_hash =
-            Sha256dHash::from_hex(genesis_block_hash_str).map_err(btc_error::HashError)?;
+        let genesis_block_hash = Sha256dHash::from_hex(genesis_block_hash_str)
+            .expect("FATAL: invalid genesis block hash");
         if genesis_block.header.bitcoin_hash() != genesis_block_hash {
             error!(
                 "Failed passing genesis block sanity check ({} != {})",
@@ -806,8 +2522,17 @@ impl SpvClient {
             panic!();
         }
 
+        genesis_block.header
+    }
+
+    /// Initialize the block headers file with the genesis block hash.
+    /// Optionally sip migration for testing.
+    fn init_block_headers(&mut self, migrate: bool) -> Result<(), btc_error> {
+        assert!(self.readwrite, "SPV header DB is open read-only");
+        let genesis_header = self.genesis_header;
+
         let mut tx = self.tx_begin()?;
-        SpvClient::insert_block_header(&mut tx, genesis_block.header, 0)?;
+        SpvClient::insert_block_header(&mut tx, genesis_header, 0)?;
         tx.commit().map_err(db_error::SqliteError)?;
 
         debug!("Initialized block headers at {}", self.headers_path);
@@ -818,50 +2543,192 @@ impl SpvClient {
         return Ok(());
     }
 
+    /// Verify that the headers DB starts with our genesis header, unless it has been pruned.
+    fn check_genesis_header(&self) -> Result<(), btc_error> {
+        if self.get_pruned_height()? > 0 {
+            return Ok(());
+        }
+        match self.read_block_header(0)? {
+            Some(hdr) if hdr.header != self.genesis_header => {
+                error!(
+                    "Headers DB {} starts with block {}, not the genesis block {}",
+                    &self.headers_path,
+                    hdr.header.bitcoin_hash(),
+                    self.genesis_header.bitcoin_hash()
+                );
+                Err(btc_error::WrongNetwork)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Verify that the headers DB holds headers for our network.  The network is recorded in the
+    /// headers DB when it is created; a headers DB from before then has its network worked out
+    /// from its genesis header, and recorded if we can write to it.
+    /// Returns btc_error::ConfigError if the headers DB is for another network.
+    fn check_network(&mut self) -> Result<(), btc_error> {
+        let db_network_id = match SpvClient::db_get_network(&self.headers_db)? {
+            Some(magic) => BitcoinNetworkType::try_from(magic).map_err(|_| {
+                btc_error::ConfigError(format!(
+                    "Headers DB {} is for an unknown network (magic {:#010x}), not {:?}",
+                    &self.headers_path, magic, self.network_id
+                ))
+            })?,
+            None => {
+                let db_network_id = self.identify_network()?;
+                if self.readwrite {
+                    let tx = self.tx_begin()?;
+                    SpvClient::db_set_network(&tx, db_network_id)?;
+                    tx.commit().map_err(db_error::SqliteError)?;
+                    debug!(
+                        "Recorded network {:?} in headers DB {}",
+                        db_network_id, &self.headers_path
+                    );
+                }
+                db_network_id
+            }
+        };
+
+        if db_network_id != self.network_id {
+            error!(
+                "Headers DB {} holds {:?} headers, but we're on {:?}",
+                &self.headers_path, db_network_id, self.network_id
+            );
+            return Err(btc_error::ConfigError(format!(
+                "Headers DB {} is for {:?}, but the indexer is configured for {:?}",
+                &self.headers_path, db_network_id, self.network_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Work out which network the headers DB's headers are for from its genesis header.
+    /// A headers DB that has been pruned (or has no headers) is assumed to be for our network,
+    /// as is one that starts with an unknown genesis header on regtest (i.e. a private network).
+    fn identify_network(&self) -> Result<BitcoinNetworkType, btc_error> {
+        let first_header = if self.get_pruned_height()? > 0 {
+            None
+        } else {
+            self.read_block_header(0)?
+        };
+        let first_header = match first_header {
+            Some(hdr) => hdr.header,
+            None => {
+                warn!(
+                    "Headers DB {} has no genesis header to identify its network by; assuming {:?}",
+                    &self.headers_path, self.network_id
+                );
+                return Ok(self.network_id);
+            }
+        };
+
+        if first_header == self.genesis_header {
+            return Ok(self.network_id);
+        }
+        for network_id in [
+            BitcoinNetworkType::Mainnet,
+            BitcoinNetworkType::Testnet,
+            BitcoinNetworkType::Regtest,
+            BitcoinNetworkType::Signet,
+        ]
+        .iter()
+        {
+            if first_header == SpvClient::get_genesis_header(*network_id) {
+                return Ok(*network_id);
+            }
+        }
+        if self.network_id == BitcoinNetworkType::Regtest {
+            warn!(
+                "Headers DB {} starts with unknown genesis block {}; assuming a private regtest network",
+                &self.headers_path,
+                first_header.bitcoin_hash()
+            );
+            return Ok(self.network_id);
+        }
+        Err(btc_error::ConfigError(format!(
+            "Headers DB {} starts with block {}, which is not the genesis block of any known network",
+            &self.headers_path,
+            first_header.bitcoin_hash()
+        )))
+    }
+
     /// Handle a Headers message
     /// -- validate them
     /// -- store them
-    /// Can error if there has been a reorg, or if the headers don't correspond to headers we asked
-    /// for, or if the new chain has less total work than the old chain.
+    /// Can error if the headers fork off of our chain but have less work than it, or if the
+    /// headers don't correspond to headers we asked for, or if the new chain has less total work
+    /// than the old chain.
+    /// Returns the fork height if the headers caused a reorg.
     fn handle_headers(
         &mut self,
-        insert_height: u64,
-        block_headers: Vec<LoneBlockHeader>,
-    ) -> Result<(), btc_error> {
+        mut insert_height: u64,
+        mut block_headers: Vec<LoneBlockHeader>,
+    ) -> Result<Option<u64>, btc_error> {
         assert!(self.readwrite, "SPV header DB is open read-only");
 
         let num_headers = block_headers.len();
         if num_headers == 0 {
             // nothing to do
-            return Ok(());
+            return Ok(None);
         }
 
         let first_header_hash = block_headers[0].header.bitcoin_hash();
         let last_header_hash = block_headers[block_headers.len() - 1].header.bitcoin_hash();
         let total_work_before = self.update_chain_work()?;
+        let tip_height_before = self.get_highest_header_height()?;
+        let tip_before = self.read_tip_hash()?;
 
+        let mut reorg_height = None;
         if !self.reverse_order {
-            // fetching headers in ascending order, so verify that the first item in
-            // `block_headers` connects to a parent in the DB (if it has one)
-            self.insert_block_headers_after(insert_height, block_headers)
+            // a restarted sync (or a peer re-sending headers) may give us headers we already
+            // have.  Whatever is left over either extends our tip or forks off below it, so
+            // handle it relative to our tip.
+            if self.skip_known_headers(&mut block_headers)?.is_some() {
+                if block_headers.len() == 0 {
+                    debug!(
+                        "Already have all {} headers {}-{}",
+                        num_headers, first_header_hash, last_header_hash
+                    );
+                    return Ok(None);
+                }
+                insert_height = tip_height_before;
+            }
+
+            // the peer may have reorged below our chain tip, in which case these headers fork off
+            // of an earlier header
+            reorg_height = self.handle_fork(insert_height, &block_headers)?;
+            if let Some(fork_height) = reorg_height {
+                warn!(
+                    "Bitcoin reorg: replaced headers above {} in {} with a higher-work branch",
+                    fork_height, &self.headers_path
+                );
+                self.reorg_height = Some(match self.reorg_height {
+                    Some(h) => cmp::min(h, fork_height),
+                    None => fork_height,
+                });
+            } else {
+                // fetching headers in ascending order, so verify that the first item in
+                // `block_headers` connects to a parent in the DB (if it has one)
+                self.insert_block_headers_after(insert_height, block_headers)
+                    .map_err(|e| {
+                        error!("Failed to insert block headers: {:?}", &e);
+                        e
+                    })?;
+
+                // check work
+                let chain_tip = self.get_headers_height()?;
+                self.validate_header_work(
+                    (insert_height.saturating_sub(1)) / BLOCK_DIFFICULTY_CHUNK_SIZE,
+                    chain_tip / BLOCK_DIFFICULTY_CHUNK_SIZE + 1,
+                )
                 .map_err(|e| {
-                    error!("Failed to insert block headers: {:?}", &e);
+                    error!(
+                        "Received headers with bad target, difficulty, or continuity: {:?}",
+                        &e
+                    );
                     e
                 })?;
-
-            // check work
-            let chain_tip = self.get_headers_height()?;
-            self.validate_header_work(
-                (insert_height.saturating_sub(1)) / BLOCK_DIFFICULTY_CHUNK_SIZE,
-                chain_tip / BLOCK_DIFFICULTY_CHUNK_SIZE + 1,
-            )
-            .map_err(|e| {
-                error!(
-                    "Received headers with bad target, difficulty, or continuity: {:?}",
-                    &e
-                );
-                e
-            })?;
+            }
         } else {
             // fetching headers in descending order, so verify that the last item in
             // `block_headers` connects to a child in the DB (if it has one)
@@ -909,7 +2776,188 @@ impl SpvClient {
             debug!("Handled empty header reply");
         }
 
-        return Ok(());
+        let reorg_depth = reorg_height
+            .map(|fork_height| tip_height_before.saturating_sub(fork_height))
+            .unwrap_or(0);
+        self.publish_tip(tip_before, reorg_depth)?;
+
+        return Ok(reorg_height);
+    }
+
+    /// Remove the headers at the start of `block_headers` that we already have at the same
+    /// heights.  Returns the height of the last one removed (i.e. the height of the parent of
+    /// what's left), or None if the first header is not one we have.
+    fn skip_known_headers(
+        &self,
+        block_headers: &mut Vec<LoneBlockHeader>,
+    ) -> Result<Option<u64>, btc_error> {
+        let parent_hash = match block_headers.first() {
+            Some(hdr) => BurnchainHeaderHash::from_bitcoin_hash(&hdr.header.prev_blockhash),
+            None => {
+                return Ok(None);
+            }
+        };
+        let parent_height = match self.find_block_header_height(&parent_hash)? {
+            Some(height) => height,
+            None => {
+                return Ok(None);
+            }
+        };
+        let known_height = match self.find_common_ancestor(block_headers)? {
+            Some(height) if height > parent_height => height,
+            _ => {
+                return Ok(None);
+            }
+        };
+
+        let num_known = (known_height - parent_height) as usize;
+        debug!(
+            "Skip {} headers in {} that we already have at {}-{}",
+            num_known,
+            &self.headers_path,
+            parent_height + 1,
+            known_height
+        );
+        block_headers.drain(..num_known);
+        Ok(Some(known_height))
+    }
+
+    /// Handle headers that fork off of our header chain below `insert_height`.  This happens when
+    /// the peer's chain reorged below our chain tip:  the peer doesn't know about our tip, so it
+    /// sends us headers starting from an earlier ancestor (possibly including some headers we
+    /// already have).
+    /// If the new branch has more work than the headers it would replace, then our headers above
+    /// the fork point are atomically replaced with it -- either all of the new branch is stored
+    /// and passes validation, or nothing changes.
+    ///
+    /// Returns Ok(Some(fork height)) if the new branch was stored, where the fork height is the
+    /// height of the highest header both branches have in common.
+    /// Returns Ok(None) if the headers don't fork off of our chain below `insert_height`, in
+    /// which case they should be inserted as usual.
+    /// Returns Err(btc_error::NoncontiguousHeader) if they fork off of our chain but don't have
+    /// more work than our branch.
+    fn handle_fork(
+        &mut self,
+        insert_height: u64,
+        block_headers: &[LoneBlockHeader],
+    ) -> Result<Option<u64>, btc_error> {
+        assert!(self.readwrite, "SPV header DB is open read-only");
+        if block_headers.len() == 0 {
+            return Ok(None);
+        }
+
+        match self.read_block_header(insert_height)? {
+            Some(hdr) => {
+                if hdr.header.bitcoin_hash() == block_headers[0].header.prev_blockhash {
+                    // not a fork
+                    return Ok(None);
+                }
+            }
+            None => {
+                return Ok(None);
+            }
+        }
+
+        let parent_hash =
+            BurnchainHeaderHash::from_bitcoin_hash(&block_headers[0].header.prev_blockhash);
+        let parent_height = match self.find_block_header_height(&parent_hash)? {
+            Some(height) if height < insert_height => height,
+            _ => {
+                // doesn't connect to any ancestor
+                return Ok(None);
+            }
+        };
+        let fork_height = match self.find_common_ancestor(block_headers)? {
+            Some(height) => height,
+            None => {
+                return Ok(None);
+            }
+        };
+
+        // skip headers we already have
+        let num_known = (fork_height - parent_height) as usize;
+        let new_branch = &block_headers[num_known..];
+        if new_branch.len() == 0 {
+            // nothing new
+            return Ok(None);
+        }
+
+        SpvClient::validate_header_integrity(fork_height, &new_branch.to_vec(), self.check_txcount)
+            .map_err(|e| {
+                error!("Received invalid headers: {:?}", &e);
+                e
+            })?;
+        self.validate_header_checkpoints(fork_height + 1, new_branch)?;
+        self.validate_header_versions(fork_height + 1, new_branch)?;
+
+        let highest_height = self.get_highest_header_height()?;
+        let old_branch = self.read_block_headers(fork_height + 1, highest_height + 1)?;
+        let old_work = SpvClient::get_interval_work(&old_branch);
+        let new_work = SpvClient::get_interval_work(new_branch);
+        if new_work <= old_work {
+            warn!(
+                "Received {} headers that fork off of block {}, but have less work than our {} headers ({} <= {})",
+                new_branch.len(),
+                fork_height,
+                old_branch.len(),
+                new_work,
+                old_work
+            );
+            return Err(btc_error::NoncontiguousHeader);
+        }
+
+        if let Some(floor) = self.reorg_floor {
+            if fork_height < floor {
+                warn!(
+                    "Refusing to replace {} headers above {} in {}: reorg is below height {}",
+                    old_branch.len(),
+                    fork_height,
+                    &self.headers_path,
+                    floor
+                );
+                return Err(btc_error::DeepReorg(fork_height));
+            }
+        }
+
+        debug!(
+            "Replace {} headers above {} in {} with {} headers ({} > {} work)",
+            old_branch.len(),
+            fork_height,
+            &self.headers_path,
+            new_branch.len(),
+            new_work,
+            old_work
+        );
+
+        // Replace the old branch and validate the new branch's work in a single transaction, so
+        // we never store (or commit to) a partially-replaced header chain.  The validation reads
+        // see the transaction's writes since they share the connection.
+        let mut tx = self
+            .headers_db
+            .unchecked_transaction()
+            .map_err(db_error::SqliteError)?;
+        SpvClient::drop_headers_above_tx(&tx, fork_height)?;
+
+        for (i, header) in new_branch.iter().enumerate() {
+            SpvClient::insert_block_header(&mut tx, header.header, fork_height + 1 + (i as u64))?;
+        }
+
+        let new_tip = fork_height + (new_branch.len() as u64);
+        self.validate_header_work(
+            (fork_height.saturating_sub(1)) / BLOCK_DIFFICULTY_CHUNK_SIZE,
+            new_tip / BLOCK_DIFFICULTY_CHUNK_SIZE + 1,
+        )
+        .map_err(|e| {
+            error!(
+                "Received headers with bad target, difficulty, or continuity: {:?}",
+                &e
+            );
+            e
+        })?;
+
+        tx.commit().map_err(db_error::SqliteError)?;
+        self.headers_committed(new_branch.len() as u64)?;
+        Ok(Some(fork_height))
     }
 
     /// Write a run of continuous headers to a particular location.
@@ -920,19 +2968,22 @@ impl SpvClient {
         headers: Vec<LoneBlockHeader>,
     ) -> Result<(), btc_error> {
         assert!(self.readwrite, "SPV header DB is open read-only");
+        if headers.len() > 0 {
+            self.check_not_pruned(height)?;
+        }
         debug!(
             "Write {} headers at {} at {}",
             headers.len(),
             &self.headers_path,
             height
         );
+        let num_headers = headers.len() as u64;
         let mut tx = self.tx_begin()?;
         for (i, header) in headers.into_iter().enumerate() {
             SpvClient::insert_block_header(&mut tx, header.header, height + (i as u64))?;
         }
-        tx.commit()
-            .map_err(|e| btc_error::DBError(db_error::SqliteError(e)))?;
-        Ok(())
+        tx.commit().map_err(db_error::SqliteError)?;
+        self.headers_committed(num_headers)
     }
 
     #[cfg(test)]
@@ -973,6 +3024,9 @@ impl SpvClient {
                 e
             })?;
 
+        self.validate_header_checkpoints(start_height + 1, &block_headers)?;
+        self.validate_header_versions(start_height + 1, &block_headers)?;
+
         let parent_header = match self.read_block_header(start_height)? {
             Some(header) => header,
             None => {
@@ -1027,6 +3081,9 @@ impl SpvClient {
                 e
             })?;
 
+        self.validate_header_checkpoints(start_height + 1, &block_headers)?;
+        self.validate_header_versions(start_height + 1, &block_headers)?;
+
         match self.read_block_header(end_height)? {
             Some(child_header) => {
                 // contiguous?
@@ -1051,805 +3108,6513 @@ impl SpvClient {
         self.write_block_headers(start_height + 1, block_headers)
     }
 
-    /// Drop headers after a block height (i.e. due to a reorg).
-    /// The headers at new_max_height are kept.
-    pub fn drop_headers(&mut self, new_max_height: u64) -> Result<(), btc_error> {
+    /// Decode a header and its stored hash from a `headers` row, without panicking if a column
+    /// is garbled.
+    fn decode_header_row(row: &Row) -> Option<(BlockHeader, BurnchainHeaderHash)> {
+        let header = BlockHeader {
+            version: row.get("version").ok()?,
+            prev_blockhash: row.get("prev_blockhash").ok()?,
+            merkle_root: row.get("merkle_root").ok()?,
+            time: row.get("time").ok()?,
+            bits: row.get("bits").ok()?,
+            nonce: row.get("nonce").ok()?,
+        };
+        let hash = row.get("hash").ok()?;
+        Some((header, hash))
+    }
+
+    /// Re-check the last SPV_TAIL_CHECK_DEPTH headers, in case we were killed while writing them.
+    /// Headers at the tip that can't be decoded, or that don't build on the header below them,
+    /// are rolled back.  A header that can't be decoded or doesn't hash to its stored hash, but
+    /// that has good headers above it, can't be fixed by a rollback, so btc_error::CorruptHeaders
+    /// is returned for it instead.
+    /// Returns the height we rolled back to, if we had to.
+    fn repair_headers_tail(&mut self) -> Result<Option<u64>, btc_error> {
         assert!(self.readwrite, "SPV header DB is open read-only");
 
-        debug!(
-            "Drop all headers after block {} in {}",
-            new_max_height, self.headers_path
+        let tip_height = self.get_highest_header_height()?;
+        let lowest_height = cmp::max(
+            self.get_pruned_height()?,
+            tip_height.saturating_sub(SPV_TAIL_CHECK_DEPTH),
         );
 
-        let tx = self.tx_begin()?;
-        tx.execute(
-            "DELETE FROM headers WHERE height > ?1",
-            &[&u64_to_sql(new_max_height)?],
-        )
-        .map_err(db_error::SqliteError)?;
-        tx.commit()
-            .map_err(|e| btc_error::DBError(db_error::SqliteError(e)))?;
-        Ok(())
-    }
+        let mut rows = vec![];
+        {
+            let mut stmt = self
+                .headers_db
+                .prepare("SELECT * FROM headers WHERE height >= ?1 ORDER BY height")
+                .map_err(db_error::SqliteError)?;
+            let mut cursor = stmt
+                .query(&[&u64_to_sql(lowest_height)?])
+                .map_err(db_error::SqliteError)?;
+            while let Some(row) = cursor.next().map_err(db_error::SqliteError)? {
+                let height = u64::from_column(row, "height")?;
+                rows.push((height, SpvClient::decode_header_row(row)));
+            }
+        }
 
-    /// Determine the (bits, target) between two headers
-    pub fn get_target_between_headers(
-        first_header: &LoneBlockHeader,
-        last_header: &LoneBlockHeader,
-    ) -> (u32, Uint256) {
-        let max_target = Uint256([
-            0x0000000000000000,
-            0x0000000000000000,
-            0x0000000000000000,
-            0x00000000ffff0000,
-        ]);
+        // anything above the highest decodable header is a torn write
+        let last_decodable = rows.iter().rposition(|(_, row)| row.is_some());
 
-        // find actual timespan as being clamped between +/- 4x of the target timespan
-        let mut actual_timespan = (last_header.header.time - first_header.header.time) as u64;
-        let target_timespan = BLOCK_DIFFICULTY_INTERVAL as u64;
-        if actual_timespan < (target_timespan / 4) {
-            actual_timespan = target_timespan / 4;
+        let mut verified: Option<(u64, Sha256dHash)> = None;
+        for (i, (height, row)) in rows.iter().enumerate() {
+            let (header, stored_hash) = match (row, verified) {
+                (Some(decoded), _) => decoded,
+                (None, Some(_)) if last_decodable.map(|j| i > j).unwrap_or(true) => break,
+                (None, _) => {
+                    error!(
+                        "Header {} in {} cannot be decoded",
+                        height, &self.headers_path
+                    );
+                    return Err(btc_error::CorruptHeaders(*height));
+                }
+            };
+
+            let hash = header.bitcoin_hash();
+            if BurnchainHeaderHash::from_bitcoin_hash(&hash) != *stored_hash {
+                error!(
+                    "Header {} in {} hashes to {}, but its stored hash is {}",
+                    height,
+                    &self.headers_path,
+                    BurnchainHeaderHash::from_bitcoin_hash(&hash),
+                    stored_hash
+                );
+                return Err(btc_error::CorruptHeaders(*height));
+            }
+
+            match verified {
+                Some((prev_height, prev_hash))
+                    if *height != prev_height + 1 || header.prev_blockhash != prev_hash =>
+                {
+                    break;
+                }
+                None if *height != lowest_height => {
+                    error!(
+                        "Header {} is missing from {}",
+                        lowest_height, &self.headers_path
+                    );
+                    return Err(btc_error::CorruptHeaders(lowest_height));
+                }
+                _ => {}
+            }
+            verified = Some((*height, hash));
         }
-        if actual_timespan > (target_timespan * 4) {
-            actual_timespan = target_timespan * 4;
+
+        let verified_height = match verified {
+            Some((height, _)) => height,
+            None => return Ok(None),
+        };
+        if verified_height == tip_height {
+            return Ok(None);
         }
 
-        let last_target = last_header.header.target();
-        let new_target =
-            (last_target * Uint256::from_u64(actual_timespan)) / Uint256::from_u64(target_timespan);
-        let target = cmp::min(new_target, max_target);
+        warn!(
+            "Headers {}-{} in {} are incomplete or do not build on header {}; discarding them",
+            verified_height + 1,
+            tip_height,
+            &self.headers_path,
+            verified_height
+        );
+        self.drop_headers_above(verified_height)?;
+        Ok(Some(verified_height))
+    }
 
-        let bits = BlockHeader::compact_target_from_u256(&target);
-        let target = BlockHeader::compact_target_to_u256(bits);
+    /// Drop headers after a block height (i.e. due to a reorg).
+    /// The headers at new_max_height are kept.
+    pub fn drop_headers(&mut self, new_max_height: u64) -> Result<(), btc_error> {
+        assert!(self.readwrite, "SPV header DB is open read-only");
+        self.check_not_pruned(new_max_height)?;
 
-        (bits, target)
+        debug!(
+            "Drop all headers after block {} in {}",
+            new_max_height, self.headers_path
+        );
+
+        let tx = self.tx_begin()?;
+        tx.execute(
+            "DELETE FROM headers WHERE height > ?1",
+            &[&u64_to_sql(new_max_height)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        tx.execute(
+            "DELETE FROM header_chain_work WHERE height > ?1",
+            &[&u64_to_sql(new_max_height)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        SpvClient::drop_filters_above_tx(&tx, new_max_height)?;
+        tx.commit().map_err(db_error::SqliteError)?;
+        Ok(())
     }
 
-    /// Determine the target difficult over a given difficulty adjustment interval
-    /// the `interval` parameter is the difficulty interval -- a 2016-block interval.
-    /// * On mainnet, `headers_in_range` can be empty. If it's not empty, then the 0th element is
-    /// treated as the parent of `current_header`.  On testnet, `headers_in_range` must be a range
-    /// of headers in the given `interval`.
-    /// Returns (new bits, new target)
-    pub fn get_target(
+    /// Find the height of the highest header in our chain that `new_headers` builds on.  The
+    /// first header's parent must be in our chain.  Leading headers that we already have are
+    /// skipped, so if `new_headers` diverges from our chain, this is the fork point.
+    /// Returns None if `new_headers` is empty or doesn't connect to our chain.
+    pub fn find_common_ancestor(
         &self,
-        current_header_height: u64,
-        current_header: &BlockHeader,
-        headers_in_range: &VecDeque<BlockHeader>,
-        interval: u64,
-    ) -> Result<Option<(u32, Uint256)>, btc_error> {
-        if interval == 0 {
-            panic!(
-                "Invalid argument: interval must be positive (got {})",
-                interval
-            );
-        }
-
-        // In Regtest mode there's no difficulty adjustment active.
-        // it uses the highest possible difficulty -- represents nBits = 0x207fffff
-        if self.network_id == BitcoinNetworkType::Regtest {
-            return Ok(Some((
-                0x207fffff,
-                Uint256([
-                    0x0000000000000000,
-                    0x0000000000000000,
-                    0x0000000000000000,
-                    0x7fffffff00000000,
-                ]),
-            )));
+        new_headers: &[LoneBlockHeader],
+    ) -> Result<Option<u64>, btc_error> {
+        if new_headers.len() == 0 {
+            return Ok(None);
         }
 
-        let max_target = Uint256([
-            0x0000000000000000,
-            0x0000000000000000,
-            0x0000000000000000,
-            0x00000000ffff0000,
-        ]);
-        let max_target_bits = BlockHeader::compact_target_from_u256(&max_target);
-
-        let parent_header = if headers_in_range.len() > 0 {
-            headers_in_range[0]
-        } else {
-            match self.read_block_header(current_header_height - 1)? {
-                Some(res) => res.header,
-                None => return Ok(None),
+        let parent_hash =
+            BurnchainHeaderHash::from_bitcoin_hash(&new_headers[0].header.prev_blockhash);
+        let mut ancestor_height = match self.find_block_header_height(&parent_hash)? {
+            Some(height) => height,
+            None => {
+                return Ok(None);
             }
         };
 
-        if current_header_height % BLOCK_DIFFICULTY_CHUNK_SIZE != 0
-            && self.network_id == BitcoinNetworkType::Testnet
-        {
-            // In Testnet mode, if the new block's timestamp is more than 2 * 60 * 10 minutes
-            // then allow mining of a min-difficulty block.
-            if current_header.time > parent_header.time + 10 * 60 * 2 {
-                return Ok(Some((max_target_bits, max_target)));
-            }
-
-            // Otherwise return the last non-special-min-difficulty-rules-block
-            for ancestor in headers_in_range {
-                if ancestor.bits != max_target_bits {
-                    let target = ancestor.target();
-                    return Ok(Some((ancestor.bits, target)));
+        for hdr in new_headers.iter() {
+            match self.read_block_header(ancestor_height + 1)? {
+                Some(ours) if ours.header.bitcoin_hash() == hdr.header.bitcoin_hash() => {
+                    ancestor_height += 1;
+                }
+                _ => {
+                    break;
                 }
             }
         }
+        Ok(Some(ancestor_height))
+    }
 
-        let first_header =
-            match self.read_block_header((interval - 1) * BLOCK_DIFFICULTY_CHUNK_SIZE)? {
-                Some(res) => res,
-                None => return Ok(None),
-            };
+    /// Drop all headers above `height`, as well as the running chain work totals they
+    /// contributed to, in one transaction.  Use this to roll back to a fork point.
+    pub fn drop_headers_above(&mut self, height: u64) -> Result<(), btc_error> {
+        assert!(self.readwrite, "SPV header DB is open read-only");
+        // the running chain work totals are recalculated from the start of the interval before
+        // the first dropped header, and that can't be done from pruned headers
+        self.check_not_pruned(
+            ((height + 1) / BLOCK_DIFFICULTY_CHUNK_SIZE).saturating_sub(1)
+                * BLOCK_DIFFICULTY_CHUNK_SIZE,
+        )?;
 
-        let last_header =
-            match self.read_block_header(interval * BLOCK_DIFFICULTY_CHUNK_SIZE - 1)? {
-                Some(res) => res,
-                None => return Ok(None),
-            };
+        debug!(
+            "Roll back all headers above block {} in {}",
+            height, self.headers_path
+        );
 
-        Ok(Some(SpvClient::get_target_between_headers(
-            &first_header,
-            &last_header,
-        )))
+        let tx = self.tx_begin()?;
+        SpvClient::drop_headers_above_tx(&tx, height)?;
+        tx.commit().map_err(db_error::SqliteError)?;
+        Ok(())
     }
 
-    /// Ask for the next batch of headers (note that this will return the maximal size of headers)
-    pub fn send_next_getheaders(
-        &mut self,
-        indexer: &mut BitcoinIndexer,
-        block_height: u64,
-    ) -> Result<(), btc_error> {
-        // ask for the next batch
-        let block_header = match self.read_block_header(block_height) {
-            Ok(Some(header)) => header,
-            Ok(None) => {
-                debug!(
-                    "No header found for block {} in {}",
-                    block_height, &self.headers_path
-                );
-                return Err(btc_error::MissingHeader);
-            }
-            Err(e) => {
-                warn!(
-                    "Failed to read block header at height {} in {}: {:?}",
-                    block_height, &self.headers_path, &e
-                );
-                return Err(e);
-            }
-        };
-        indexer.send_getheaders(block_header.header.bitcoin_hash())
-    }
-}
+    fn drop_headers_above_tx<'a>(tx: &DBTx<'a>, height: u64) -> Result<(), btc_error> {
+        tx.execute(
+            "DELETE FROM headers WHERE height > ?1",
+            &[&u64_to_sql(height)?],
+        )
+        .map_err(db_error::SqliteError)?;
 
-impl BitcoinMessageHandler for SpvClient {
-    /// Trait message handler
-    /// initiate the conversation with the bitcoin peer
-    fn begin_session(&mut self, indexer: &mut BitcoinIndexer) -> Result<bool, btc_error> {
-        let start_height = self.cur_block_height;
-        self.end_block_height = Some(indexer.runtime.block_height);
+        // running chain work totals from the interval of the first dropped header onwards are
+        // no longer valid
+        tx.execute(
+            "DELETE FROM chain_work WHERE interval >= ?1",
+            &[&u64_to_sql((height + 1) / BLOCK_DIFFICULTY_CHUNK_SIZE)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        tx.execute(
+            "DELETE FROM header_chain_work WHERE height > ?1",
+            &[&u64_to_sql(height)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        SpvClient::drop_filters_above_tx(tx, height)?;
+        Ok(())
+    }
 
-        if indexer.runtime.block_height <= start_height {
-            debug!("Have all headers up to {}", start_height);
-            return Ok(false);
-        }
+    /// Filters and filter headers are only valid for the headers they were checked against, so
+    /// they go away along with them.
+    fn drop_filters_above_tx<'a>(tx: &DBTx<'a>, height: u64) -> Result<(), btc_error> {
+        tx.execute(
+            "DELETE FROM filter_headers WHERE height > ?1",
+            &[&u64_to_sql(height)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        tx.execute(
+            "DELETE FROM block_filters WHERE height > ?1",
+            &[&u64_to_sql(height)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        Ok(())
+    }
 
-        debug!(
-            "Get headers {}-{} to {}",
-            self.cur_block_height,
-            self.end_block_height.unwrap(),
-            self.headers_path
-        );
+    /// Delete the headers that `pruning` says we no longer need, and compact the DB.
+    /// At least `pruning.keep_headers` headers below `pruning.max_height` (or our chain tip, if
+    /// it's lower) are kept, and never fewer than MIN_HEADER_RETENTION, since validating new
+    /// headers and searching for reorgs needs them.  Headers are pruned a whole difficulty
+    /// interval at a time, so a few more may be kept.  The lowest remaining header anchors the
+    /// chain:  headers are still read and written at their absolute heights, and reading a
+    /// pruned header fails with MissingHeader.  The first header of each pruned interval and the
+    /// headers at checkpoint heights are kept apart, though (see `read_retained_header()`), and
+    /// if `pruning.archive` is set, the pruned headers are appended to the header archive (see
+    /// `read_archived_header()`).
+    /// Returns the height of the lowest remaining header.
+    pub fn prune_headers(&mut self, pruning: &HeaderPruning) -> Result<u64, btc_error> {
+        assert!(self.readwrite, "SPV header DB is open read-only");
 
-        indexer.runtime.last_getheaders_send_time = get_epoch_time_secs();
-        self.send_next_getheaders(indexer, start_height)
-            .and_then(|_r| Ok(true))
+        if pruning.keep_headers < MIN_HEADER_RETENTION {
+            debug!(
+                "Keep {} headers instead of {} in {}",
+                MIN_HEADER_RETENTION, pruning.keep_headers, &self.headers_path
+            );
+        }
+        let keep_headers = cmp::max(pruning.keep_headers, MIN_HEADER_RETENTION);
+        let tip_height = self.get_highest_header_height()?;
+        let max_height = match pruning.max_height {
+            Some(max_height) => cmp::min(max_height, tip_height),
+            None => tip_height,
+        };
+        let prune_height = max_height.saturating_sub(keep_headers) / BLOCK_DIFFICULTY_CHUNK_SIZE
+            * BLOCK_DIFFICULTY_CHUNK_SIZE;
+        self.prune_headers_below(prune_height, pruning.archive)
     }
 
-    /// Trait message handler
-    /// Take headers, validate them, and ask for more
-    fn handle_message(
-        &mut self,
-        indexer: &mut BitcoinIndexer,
-        msg: PeerMessage,
-    ) -> Result<bool, btc_error> {
-        assert!(self.end_block_height.is_some());
+    /// Delete the headers below `prune_height`, which must be the start of a difficulty
+    /// interval, except for the first header of each interval and the headers at checkpoint
+    /// heights.  If `archive` is set, they are appended to the header archive first.
+    /// Returns the height of the lowest remaining header.
+    fn prune_headers_below(&mut self, prune_height: u64, archive: bool) -> Result<u64, btc_error> {
+        let pruned_height = self.get_pruned_height()?;
+        if prune_height <= pruned_height {
+            return Ok(pruned_height);
+        }
 
-        let end_block_height = self.end_block_height.unwrap();
+        // the work scores of the pruned intervals must be stored before their headers go away
+        self.update_chain_work()?;
 
-        match msg {
-            btc_message::NetworkMessage::Headers(mut block_headers) => {
-                if self.cur_block_height >= end_block_height {
-                    // done
-                    return Ok(false);
-                }
+        if archive {
+            self.archive_headers(pruned_height, prune_height)?;
+        }
 
-                // only handle headers we asked for
-                if end_block_height - self.cur_block_height < block_headers.len() as u64 {
-                    debug!(
-                        "Truncate received headers from block range {}-{} to range {}-{}",
-                        self.cur_block_height,
-                        end_block_height,
-                        self.cur_block_height,
-                        self.cur_block_height + (block_headers.len() as u64) - end_block_height
-                    );
-                    block_headers.truncate((end_block_height - self.cur_block_height) as usize);
-                }
+        debug!(
+            "Prune headers {}-{} from {}",
+            pruned_height,
+            prune_height - 1,
+            &self.headers_path
+        );
 
-                let insert_height = self.cur_block_height;
-                let num_headers = block_headers.len();
+        let checkpoint_heights: Vec<u64> = self
+            .checkpoints
+            .iter()
+            .map(|(height, _)| *height)
+            .filter(|height| *height < prune_height)
+            .collect();
 
-                self.handle_headers(insert_height, block_headers)?;
-                self.cur_block_height += num_headers as u64;
+        let tx = self.tx_begin()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO retained_headers
+            (version, prev_blockhash, merkle_root, time, bits, nonce, height, hash)
+            SELECT version, prev_blockhash, merkle_root, time, bits, nonce, height, hash
+            FROM headers WHERE height < ?1 AND height % ?2 = 0",
+            &[
+                &u64_to_sql(prune_height)?,
+                &u64_to_sql(BLOCK_DIFFICULTY_CHUNK_SIZE)?,
+            ],
+        )
+        .map_err(db_error::SqliteError)?;
+        for height in checkpoint_heights.into_iter() {
+            tx.execute(
+                "INSERT OR REPLACE INTO retained_headers
+                (version, prev_blockhash, merkle_root, time, bits, nonce, height, hash)
+                SELECT version, prev_blockhash, merkle_root, time, bits, nonce, height, hash
+                FROM headers WHERE height = ?1",
+                &[&u64_to_sql(height)?],
+            )
+            .map_err(db_error::SqliteError)?;
+        }
+        tx.execute(
+            "DELETE FROM headers WHERE height < ?1",
+            &[&u64_to_sql(prune_height)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        tx.execute(
+            "DELETE FROM header_chain_work WHERE height < ?1",
+            &[&u64_to_sql(prune_height)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        tx.execute(
+            "DELETE FROM filter_headers WHERE height < ?1",
+            &[&u64_to_sql(prune_height)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        tx.execute(
+            "DELETE FROM block_filters WHERE height < ?1",
+            &[&u64_to_sql(prune_height)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        tx.execute("DELETE FROM pruned_headers", NO_PARAMS)
+            .map_err(db_error::SqliteError)?;
+        tx.execute(
+            "INSERT INTO pruned_headers (height) VALUES (?1)",
+            &[&u64_to_sql(prune_height)?],
+        )
+        .map_err(db_error::SqliteError)?;
+        tx.commit().map_err(db_error::SqliteError)?;
 
-                // ask for the next batch
-                let block_height = self.get_highest_header_height()?;
-                assert!(block_height > 0, "BUG: uninitialized SPV headers DB");
+        self.headers_db
+            .execute_batch("VACUUM")
+            .map_err(db_error::SqliteError)?;
+        Ok(prune_height)
+    }
 
-                // clear timeout
-                indexer.runtime.last_getheaders_send_time = 0;
+    /// Path of the header archive that pruned headers are appended to, if they're archived (see
+    /// `HeaderPruning::archive`).  The archive starts with the height of its first header
+    /// as an 8-byte big-endian integer, followed by the headers, in order, as 80-byte serialized
+    /// `BlockHeader`s.
+    pub fn get_header_archive_path(&self) -> String {
+        format!("{}.archive", &self.headers_path)
+    }
 
-                // if syncing requires to request more than one batch of 2000 headers,
-                // we'll provide some progress in the logs
-                let total = end_block_height - self.start_block_height;
-                let batch_size = 2000;
-                if total > batch_size {
-                    let progress =
-                        (block_height - self.start_block_height) as f32 / total as f32 * 100.;
-                    info!(
-                        "Syncing Bitcoin headers: {:.1}% ({} out of {})",
-                        progress, block_height, total
-                    );
-                } else {
-                    debug!(
-                        "Request headers for blocks {} - {} in range {} - {}",
-                        block_height,
-                        block_height + batch_size,
-                        self.start_block_height,
-                        end_block_height
-                    );
-                }
-                self.send_next_getheaders(indexer, block_height)
-                    .and_then(|_| Ok(true))
+    /// Append the headers from `start_height` up to (but not including) `end_height` to the
+    /// header archive, which must end at `start_height`.  Anything after that was left by a
+    /// prune that didn't finish, and is overwritten.  If the archive doesn't reach
+    /// `start_height`, because headers were pruned without being archived, a new archive is
+    /// started.
+    fn archive_headers(&self, start_height: u64, end_height: u64) -> Result<(), btc_error> {
+        let archive_path = self.get_header_archive_path();
+        let mut f = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&archive_path)
+            .map_err(btc_error::FilesystemError)?;
+        let len = f.metadata().map_err(btc_error::FilesystemError)?.len();
+
+        let mut first_height = start_height;
+        if len >= 8 {
+            let mut height_bytes = [0u8; 8];
+            f.read_exact(&mut height_bytes)
+                .map_err(btc_error::FilesystemError)?;
+            first_height = u64::from_be_bytes(height_bytes);
+        }
+        let archive_end_height =
+            first_height.saturating_add((len.saturating_sub(8)) / ARCHIVED_HEADER_SIZE);
+        if len < 8 || first_height > start_height || archive_end_height < start_height {
+            if len > 0 {
+                warn!(
+                    "Header archive {} ends at {}, not {}; starting a new one",
+                    &archive_path, archive_end_height, start_height
+                );
             }
-            x => Err(btc_error::UnhandledMessage(x)),
+            f.set_len(0).map_err(btc_error::FilesystemError)?;
+            f.seek(SeekFrom::Start(0))
+                .map_err(btc_error::FilesystemError)?;
+            f.write_all(&start_height.to_be_bytes())
+                .map_err(btc_error::FilesystemError)?;
+            first_height = start_height;
         }
-    }
-}
 
-#[cfg(test)]
-mod test {
+        let offset = 8 + (start_height - first_height) * ARCHIVED_HEADER_SIZE;
+        f.set_len(offset).map_err(btc_error::FilesystemError)?;
+        f.seek(SeekFrom::Start(offset))
+            .map_err(btc_error::FilesystemError)?;
+
+        debug!(
+            "Archive headers {}-{} to {}",
+            start_height,
+            end_height - 1,
+            &archive_path
+        );
+        let mut height = start_height;
+        while height < end_height {
+            let batch_end = cmp::min(height + BLOCK_DIFFICULTY_CHUNK_SIZE, end_height);
+            let headers = self.read_block_headers(height, batch_end)?;
+            if (headers.len() as u64) != batch_end - height {
+                return Err(btc_error::MissingHeader);
+            }
+            let mut bytes = Vec::with_capacity(headers.len() * (ARCHIVED_HEADER_SIZE as usize));
+            for hdr in headers.iter() {
+                bytes.extend_from_slice(&serialize(&hdr.header)?);
+            }
+            f.write_all(&bytes).map_err(btc_error::FilesystemError)?;
+            height = batch_end;
+        }
+        f.sync_all().map_err(btc_error::FilesystemError)?;
+        Ok(())
+    }
+
+    /// Read a pruned header from the header archive.
+    /// Returns None if the archive doesn't have it.
+    pub fn read_archived_header(
+        &self,
+        block_height: u64,
+    ) -> Result<Option<BlockHeader>, btc_error> {
+        let mut f = match fs::File::open(self.get_header_archive_path()) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(btc_error::FilesystemError(e));
+            }
+        };
+        let len = f.metadata().map_err(btc_error::FilesystemError)?.len();
+        if len < 8 {
+            return Ok(None);
+        }
+        let mut height_bytes = [0u8; 8];
+        f.read_exact(&mut height_bytes)
+            .map_err(btc_error::FilesystemError)?;
+        let first_height = u64::from_be_bytes(height_bytes);
+        if block_height < first_height {
+            return Ok(None);
+        }
+        let offset = 8 + (block_height - first_height) * ARCHIVED_HEADER_SIZE;
+        if offset + ARCHIVED_HEADER_SIZE > len {
+            return Ok(None);
+        }
+
+        let mut header_bytes = [0u8; ARCHIVED_HEADER_SIZE as usize];
+        f.seek(SeekFrom::Start(offset))
+            .map_err(btc_error::FilesystemError)?;
+        f.read_exact(&mut header_bytes)
+            .map_err(btc_error::FilesystemError)?;
+        let header: BlockHeader = deserialize(&header_bytes)?;
+        Ok(Some(header))
+    }
+
+    /// Read a pruned header that was kept as the first header of its difficulty interval, or as
+    /// a checkpoint.
+    /// Returns None if it wasn't kept.
+    pub fn read_retained_header(
+        &self,
+        block_height: u64,
+    ) -> Result<Option<BlockHeader>, btc_error> {
+        let header_opt = query_row(
+            &self.headers_db,
+            "SELECT * FROM retained_headers WHERE height = ?1",
+            &[&u64_to_sql(block_height)?],
+        )?;
+        Ok(header_opt)
+    }
+
+    /// Find out whether or not we can still read the header at `block_height`:  from the headers
+    /// DB, as a retained header (see `read_retained_header()`), or from the header archive (see
+    /// `read_archived_header()`).
+    pub fn get_header_availability(
+        &self,
+        block_height: u64,
+    ) -> Result<HeaderAvailability, btc_error> {
+        if block_height > self.get_highest_header_height()? {
+            return Ok(HeaderAvailability::NotSynced);
+        }
+        if block_height >= self.get_pruned_height()? {
+            return Ok(HeaderAvailability::Available);
+        }
+        if self.read_retained_header(block_height)?.is_some() {
+            return Ok(HeaderAvailability::Retained);
+        }
+        if self.read_archived_header(block_height)?.is_some() {
+            return Ok(HeaderAvailability::Archived);
+        }
+        Ok(HeaderAvailability::NotRetained)
+    }
+
+    /// Start the headers DB from a trusted header at `height` instead of the genesis header,
+    /// as if every header below it had been pruned (see `prune_headers()`).  `chain_work` is the
+    /// total work of the chain up to and including the anchor header.  Headers below `height`
+    /// will never be available.
+    /// The anchor must be the first header of a difficulty adjustment interval, so that the
+    /// headers after it can be validated, and must match any checkpoint at its height.
+    /// This is a no-op if the headers DB already has the anchor header; it fails with
+    /// ConfigError if it has a different header at `height`, or has headers below `height` but
+    /// not up to it.
+    pub fn init_from_anchor(
+        &mut self,
+        height: u64,
+        header: BlockHeader,
+        chain_work: Uint256,
+    ) -> Result<(), btc_error> {
+        assert!(self.readwrite, "SPV header DB is open read-only");
+
+        if height == 0 || height % BLOCK_DIFFICULTY_CHUNK_SIZE != 0 {
+            return Err(btc_error::ConfigError(format!(
+                "Anchor header height {} is not the start of a difficulty adjustment interval",
+                height
+            )));
+        }
+        if chain_work < header.work() {
+            return Err(btc_error::ConfigError(format!(
+                "Anchor header chain work {} is less than the header's own work",
+                chain_work
+            )));
+        }
+        self.validate_header_checkpoints(
+            height,
+            &[LoneBlockHeader {
+                header,
+                tx_count: VarInt(0),
+            }],
+        )?;
+
+        let tip_height = self.get_highest_header_height()?;
+        if tip_height >= height {
+            return match self.read_block_header(height) {
+                Ok(Some(hdr)) if hdr.header == header => {
+                    self.cur_block_height = cmp::max(self.cur_block_height, height);
+                    Ok(())
+                }
+                _ => Err(btc_error::ConfigError(format!(
+                    "Headers DB {} does not have anchor header {} at height {}",
+                    &self.headers_path,
+                    header.bitcoin_hash(),
+                    height
+                ))),
+            };
+        }
+        if tip_height > 0 {
+            return Err(btc_error::ConfigError(format!(
+                "Headers DB {} has headers up to {}, below anchor height {}; remove it to start from the anchor",
+                &self.headers_path, tip_height, height
+            )));
+        }
+
+        debug!(
+            "Start headers DB {} from {} at height {}",
+            &self.headers_path,
+            header.bitcoin_hash(),
+            height
+        );
+
+        let mut tx = self.tx_begin()?;
+        for table in [
+            "headers",
+            "header_chain_work",
+            "chain_work",
+            "filter_headers",
+            "block_filters",
+            "pruned_headers",
+            "retained_headers",
+        ] {
+            tx.execute(&format!("DELETE FROM {}", table), NO_PARAMS)
+                .map_err(db_error::SqliteError)?;
+        }
+        SpvClient::insert_block_header(&mut tx, header, height)?;
+        tx.execute(
+            "INSERT INTO pruned_headers (height) VALUES (?1)",
+            &[&u64_to_sql(height)?],
+        )
+        .map_err(db_error::SqliteError)?;
+
+        // the running total of the interval before the anchor is the work of the headers we
+        // don't have
+        let args: &[&dyn ToSql] = &[
+            &u64_to_sql(height / BLOCK_DIFFICULTY_CHUNK_SIZE - 1)?,
+            &(chain_work - header.work()).to_hex_be(),
+        ];
+        tx.execute(
+            "INSERT INTO chain_work (interval,work) VALUES (?1,?2)",
+            args,
+        )
+        .map_err(db_error::SqliteError)?;
+        tx.commit().map_err(db_error::SqliteError)?;
+
+        self.update_chain_work()?;
+        self.start_block_height = cmp::max(self.start_block_height, height);
+        self.cur_block_height = cmp::max(self.cur_block_height, height);
+        Ok(())
+    }
+
+    /// Get the height of the highest filter header we have, if any
+    pub fn get_highest_filter_header_height(&self) -> Result<Option<u64>, btc_error> {
+        let height = query_row::<u64, _>(
+            &self.headers_db,
+            "SELECT height FROM filter_headers ORDER BY height DESC LIMIT 1",
+            NO_PARAMS,
+        )?;
+        Ok(height)
+    }
+
+    /// Get the height of the highest block filter we have, if any
+    pub fn get_highest_filter_height(&self) -> Result<Option<u64>, btc_error> {
+        let height = query_row::<u64, _>(
+            &self.headers_db,
+            "SELECT height FROM block_filters ORDER BY height DESC LIMIT 1",
+            NO_PARAMS,
+        )?;
+        Ok(height)
+    }
+
+    /// Read the filter hash and filter header of the block at `height`, if we have them
+    pub fn read_filter_header(
+        &self,
+        height: u64,
+    ) -> Result<Option<(Sha256dHash, Sha256dHash)>, btc_error> {
+        let row = self
+            .headers_db
+            .query_row(
+                "SELECT filter_hash, header FROM filter_headers WHERE height = ?1",
+                &[&u64_to_sql(height)?],
+                |row| {
+                    let filter_hash: Sha256dHash = row.get_unwrap(0);
+                    let header: Sha256dHash = row.get_unwrap(1);
+                    Ok((filter_hash, header))
+                },
+            )
+            .optional()
+            .map_err(db_error::SqliteError)?;
+        Ok(row)
+    }
+
+    /// Read the basic filter of the block at `height`, if we have it
+    pub fn read_filter(&self, height: u64) -> Result<Option<BlockFilter>, btc_error> {
+        let content = self
+            .headers_db
+            .query_row(
+                "SELECT filter FROM block_filters WHERE height = ?1",
+                &[&u64_to_sql(height)?],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(db_error::SqliteError)?;
+        Ok(content.map(BlockFilter::new))
+    }
+
+    /// Get the filter header advertised for `height`, if it's a checkpoint height.
+    /// `checkpoints[i]` is the filter header at height `(i + 1) * FILTER_CHECKPOINT_INTERVAL`,
+    /// as in a `cfcheckpt` reply.
+    fn filter_checkpoint_at(checkpoints: &[Sha256dHash], height: u64) -> Option<Sha256dHash> {
+        if height == 0 || height % FILTER_CHECKPOINT_INTERVAL != 0 {
+            return None;
+        }
+        checkpoints
+            .get((height / FILTER_CHECKPOINT_INTERVAL - 1) as usize)
+            .cloned()
+    }
+
+    /// Store the filter headers in a `cfheaders` reply, which cover the blocks from
+    /// `start_height` up to the one with hash `cfheaders.stop_hash`.
+    /// The header chain must build on the filter header we already have at `start_height - 1`
+    /// (or on the checkpoint there, or on the zero hash if `start_height` is 0), and must pass
+    /// through every filter header in `checkpoints` that it reaches.  Since each filter header
+    /// commits to all the filter hashes before it, this authenticates every filter hash up to
+    /// the last checkpoint; those above it are only as good as the peer that sent them.
+    /// Returns the height of the last stored filter header.
+    pub fn insert_filter_headers(
+        &mut self,
+        start_height: u64,
+        cfheaders: &CFHeaders,
+        checkpoints: &[Sha256dHash],
+    ) -> Result<u64, btc_error> {
+        assert!(self.readwrite, "SPV header DB is open read-only");
+        if cfheaders.filter_type != BASIC_FILTER_TYPE || cfheaders.filter_hashes.len() == 0 {
+            return Err(btc_error::InvalidReply);
+        }
+
+        let end_height = start_height + (cfheaders.filter_hashes.len() as u64) - 1;
+        match self.read_block_header(end_height)? {
+            Some(hdr) if hdr.header.bitcoin_hash() == cfheaders.stop_hash => {}
+            _ => {
+                warn!(
+                    "Filter headers {}-{} do not end at block {}",
+                    start_height, end_height, &cfheaders.stop_hash
+                );
+                return Err(btc_error::InvalidReply);
+            }
+        }
+
+        let trusted_prev_header = if start_height == 0 {
+            Some(Sha256dHash::default())
+        } else {
+            match self.read_filter_header(start_height - 1)? {
+                Some((_, header)) => Some(header),
+                None => SpvClient::filter_checkpoint_at(checkpoints, start_height - 1),
+            }
+        };
+        match trusted_prev_header {
+            Some(header) if header == cfheaders.previous_filter_header => {}
+            Some(_) => {
+                warn!(
+                    "Filter header {} does not match the previous filter header in the reply",
+                    start_height.saturating_sub(1)
+                );
+                return Err(btc_error::FilterHeaderMismatch(
+                    start_height.saturating_sub(1),
+                ));
+            }
+            None => {
+                return Err(btc_error::MissingHeader);
+            }
+        }
+
+        let mut prev_header = cfheaders.previous_filter_header.clone();
+        let mut filter_headers = Vec::with_capacity(cfheaders.filter_hashes.len());
+        for (i, filter_hash) in cfheaders.filter_hashes.iter().enumerate() {
+            let height = start_height + (i as u64);
+            let header = filter_header(filter_hash, &prev_header);
+            if let Some(checkpoint) = SpvClient::filter_checkpoint_at(checkpoints, height) {
+                if checkpoint != header {
+                    warn!(
+                        "Filter header {} is {}, but the checkpoint is {}",
+                        height, &header, &checkpoint
+                    );
+                    return Err(btc_error::FilterHeaderMismatch(height));
+                }
+            }
+            filter_headers.push((height, filter_hash.clone(), header.clone()));
+            prev_header = header;
+        }
+
+        let tx = self.tx_begin()?;
+        for (height, filter_hash, header) in filter_headers.iter() {
+            let args: &[&dyn ToSql] = &[&u64_to_sql(*height)?, filter_hash, header];
+            tx.execute(
+                "INSERT OR REPLACE INTO filter_headers (height, filter_hash, header) VALUES (?1, ?2, ?3)",
+                args,
+            )
+            .map_err(db_error::SqliteError)?;
+        }
+        tx.commit().map_err(db_error::SqliteError)?;
+
+        debug!(
+            "Stored filter headers {}-{} in {}",
+            start_height, end_height, &self.headers_path
+        );
+        Ok(end_height)
+    }
+
+    /// Store the filter in a `cfilter` reply for the block at `height`.  The filter must hash to
+    /// the filter hash in our filter header for that block.
+    pub fn insert_filter(&mut self, height: u64, cfilter: &CFilter) -> Result<(), btc_error> {
+        assert!(self.readwrite, "SPV header DB is open read-only");
+        if cfilter.filter_type != BASIC_FILTER_TYPE {
+            return Err(btc_error::InvalidReply);
+        }
+        match self.read_block_header(height)? {
+            Some(hdr) if hdr.header.bitcoin_hash() == cfilter.block_hash => {}
+            _ => {
+                warn!(
+                    "Got filter for block {}, but expected block {}",
+                    &cfilter.block_hash, height
+                );
+                return Err(btc_error::InvalidReply);
+            }
+        }
+
+        let filter = BlockFilter::new(cfilter.filter.clone());
+        match self.read_filter_header(height)? {
+            Some((filter_hash, _)) if filter_hash == filter.filter_hash() => {}
+            Some(_) => {
+                warn!(
+                    "Filter for block {} does not match its filter header",
+                    height
+                );
+                return Err(btc_error::FilterHeaderMismatch(height));
+            }
+            None => {
+                return Err(btc_error::MissingHeader);
+            }
+        }
+
+        let tx = self.tx_begin()?;
+        let args: &[&dyn ToSql] = &[&u64_to_sql(height)?, &filter.content];
+        tx.execute(
+            "INSERT OR REPLACE INTO block_filters (height, filter) VALUES (?1, ?2)",
+            args,
+        )
+        .map_err(db_error::SqliteError)?;
+        tx.commit().map_err(db_error::SqliteError)?;
+        Ok(())
+    }
+
+    /// Could the block at `height` contain burn operations?  This is only false if we have the
+    /// block's filter and it matches none of the scripts given to `set_filter_scripts()`.
+    /// Basic filters leave out OP_RETURN outputs, so a burn operation can only be found by the
+    /// scripts its transaction spends from or pays to (e.g. the burn address), and this is only
+    /// as complete as the list of scripts.  With no scripts, no filter, or a filter that can't
+    /// be decoded, every block may contain burn operations.
+    pub fn block_may_contain_burn_ops(&self, height: u64) -> Result<bool, btc_error> {
+        if self.filter_scripts.len() == 0 {
+            return Ok(true);
+        }
+        let filter = match self.read_filter(height)? {
+            Some(filter) => filter,
+            None => {
+                return Ok(true);
+            }
+        };
+        let block_hash = match self.read_block_header(height)? {
+            Some(hdr) => hdr.header.bitcoin_hash(),
+            None => {
+                return Ok(true);
+            }
+        };
+        match filter.match_any(&block_hash, &self.filter_scripts) {
+            Ok(matched) => Ok(matched),
+            Err(e) => {
+                warn!("Failed to match filter for block {}: {:?}", height, &e);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Split the headers we don't have yet into chunks that can be downloaded in parallel.
+    /// Each chunk runs between two headers we already trust:  our chain tip, and the checkpoints
+    /// above it.  Headers above the highest checkpoint (or above `end_height`, if given) are not
+    /// covered, since there's nothing to anchor them to.
+    /// A chunk ends at the first checkpoint at least `chunk_size` headers past its start (or at
+    /// the highest usable checkpoint), so chunks are never shorter than `chunk_size` unless
+    /// there aren't enough headers left.
+    pub fn plan_header_chunks(
+        &self,
+        end_height: Option<u64>,
+        chunk_size: u64,
+    ) -> Result<Vec<HeaderChunk>, btc_error> {
+        let tip_height = self.get_highest_header_height()?;
+        let tip_header = self
+            .read_block_header(tip_height)?
+            .ok_or(btc_error::MissingHeader)?;
+
+        let mut checkpoints: Vec<_> = self
+            .checkpoints
+            .iter()
+            .filter(|(height, _)| {
+                *height > tip_height && end_height.map(|h| *height <= h).unwrap_or(true)
+            })
+            .cloned()
+            .collect();
+        checkpoints.sort_by_key(|(height, _)| *height);
+
+        let mut chunks = vec![];
+        let mut start_height = tip_height;
+        let mut start_hash = tip_header.header.bitcoin_hash();
+        let num_checkpoints = checkpoints.len();
+        for (i, (height, hash)) in checkpoints.into_iter().enumerate() {
+            if height - start_height < chunk_size && i + 1 < num_checkpoints {
+                continue;
+            }
+            let end_hash = hash.to_bitcoin_hash();
+            chunks.push(HeaderChunk {
+                start_height,
+                start_hash,
+                end_height: height,
+                end_hash,
+            });
+            start_height = height;
+            start_hash = end_hash;
+        }
+        Ok(chunks)
+    }
+
+    /// Store a downloaded chunk on top of our chain tip, and validate its work.  The chunk must
+    /// start at our chain tip.  If the headers fail validation, they are dropped again so the
+    /// chunk can be fetched from someone else.
+    pub fn insert_header_chunk(
+        &mut self,
+        chunk: &HeaderChunk,
+        headers: Vec<LoneBlockHeader>,
+    ) -> Result<(), btc_error> {
+        chunk.validate(&headers)?;
+        let tip_height = self.get_highest_header_height()?;
+        if tip_height != chunk.start_height {
+            warn!(
+                "Header chunk {}-{} does not start at our chain tip {}",
+                chunk.start_height, chunk.end_height, tip_height
+            );
+            return Err(btc_error::NoncontiguousHeader);
+        }
+
+        let tip_before = self.read_tip_hash()?;
+        self.insert_block_headers_after(chunk.start_height, headers)?;
+        if let Err(e) = self.validate_header_work(
+            (chunk.start_height.saturating_sub(1)) / BLOCK_DIFFICULTY_CHUNK_SIZE,
+            chunk.end_height / BLOCK_DIFFICULTY_CHUNK_SIZE + 1,
+        ) {
+            error!(
+                "Header chunk {}-{} has bad target, difficulty, or continuity: {:?}",
+                chunk.start_height, chunk.end_height, &e
+            );
+            self.drop_headers_above(chunk.start_height)?;
+            return Err(e);
+        }
+        self.update_chain_work()?;
+        self.publish_tip(tip_before, 0)
+    }
+
+    /// Write headers 0 through `max_height` (or our chain tip, if it's lower) to `writer` as a
+    /// portable snapshot, which another node can load with `import_headers()`.
+    /// The snapshot holds the network magic (4 bytes, little-endian), the number of headers (8
+    /// bytes, little-endian), the 80-byte headers in height order, and the SHA256 of all of the
+    /// above.  Headers below our pruned height are gone, so they can't be exported.
+    /// Returns the height of the last header written.
+    pub fn export_headers<W: Write>(
+        &self,
+        writer: &mut W,
+        max_height: u64,
+    ) -> Result<u64, btc_error> {
+        self.check_not_pruned(0)?;
+        let end_height = cmp::min(max_height, self.get_highest_header_height()?);
+        let headers = self.read_block_headers(0, end_height + 1)?;
+        if headers.len() as u64 != end_height + 1 {
+            warn!(
+                "Missing headers in {}: expected {}, got {}",
+                &self.headers_path,
+                end_height + 1,
+                headers.len()
+            );
+            return Err(btc_error::MissingHeader);
+        }
+
+        let mut snapshot = Vec::with_capacity(
+            SNAPSHOT_PREAMBLE_SIZE + headers.len() * SNAPSHOT_HEADER_SIZE + SNAPSHOT_CHECKSUM_SIZE,
+        );
+        snapshot.extend_from_slice(&network_id_to_bytes(self.network_id).to_le_bytes());
+        snapshot.extend_from_slice(&(headers.len() as u64).to_le_bytes());
+        for header in headers.iter() {
+            let header_bytes = serialize(&header.header)?;
+            snapshot.extend_from_slice(&header_bytes);
+        }
+        let checksum = Sha256Sum::from_data(&snapshot);
+        snapshot.extend_from_slice(checksum.as_bytes());
+
+        writer.write_all(&snapshot)?;
+        writer.flush()?;
+        debug!(
+            "Exported headers 0-{} from {}",
+            end_height, &self.headers_path
+        );
+        Ok(end_height)
+    }
+
+    /// Write headers 0 through `max_height` to a snapshot file at `path` (see
+    /// `export_headers()`).
+    /// Returns the height of the last header written.
+    pub fn export_headers_file(&self, path: &str, max_height: u64) -> Result<u64, btc_error> {
+        let mut file = fs::File::create(path).map_err(btc_error::FilesystemError)?;
+        let end_height = self.export_headers(&mut file, max_height)?;
+        file.sync_all().map_err(btc_error::FilesystemError)?;
+        debug!("Wrote header snapshot 0-{} to {}", end_height, path);
+        Ok(end_height)
+    }
+
+    /// Decode a header snapshot written by `export_headers()`, and check its checksum and
+    /// network.  The headers themselves are not validated.
+    fn parse_header_snapshot(
+        snapshot: &[u8],
+        network_id: BitcoinNetworkType,
+    ) -> Result<Vec<LoneBlockHeader>, btc_error> {
+        if snapshot.len() < SNAPSHOT_PREAMBLE_SIZE + SNAPSHOT_CHECKSUM_SIZE {
+            return Err(btc_error::InvalidHeaderSnapshot(format!(
+                "file is too short ({} bytes)",
+                snapshot.len()
+            )));
+        }
+        let (payload, checksum) = snapshot.split_at(snapshot.len() - SNAPSHOT_CHECKSUM_SIZE);
+        if Sha256Sum::from_data(payload).as_bytes() != checksum {
+            return Err(btc_error::InvalidHeaderSnapshot(
+                "checksum mismatch".to_string(),
+            ));
+        }
+
+        let mut magic_bytes = [0u8; 4];
+        magic_bytes.copy_from_slice(&payload[0..4]);
+        let snapshot_network_id = BitcoinNetworkType::try_from(u32::from_le_bytes(magic_bytes))
+            .map_err(|_| btc_error::InvalidMagic)?;
+        if snapshot_network_id != network_id {
+            warn!(
+                "Header snapshot is for {:?}, but we're on {:?}",
+                snapshot_network_id, network_id
+            );
+            return Err(btc_error::WrongNetwork);
+        }
+
+        let mut count_bytes = [0u8; 8];
+        count_bytes.copy_from_slice(&payload[4..SNAPSHOT_PREAMBLE_SIZE]);
+        let count = u64::from_le_bytes(count_bytes);
+        let header_bytes = &payload[SNAPSHOT_PREAMBLE_SIZE..];
+        if count.checked_mul(SNAPSHOT_HEADER_SIZE as u64) != Some(header_bytes.len() as u64) {
+            return Err(btc_error::InvalidHeaderSnapshot(format!(
+                "expected {} headers, but got {} bytes",
+                count,
+                header_bytes.len()
+            )));
+        }
+
+        let mut headers = Vec::with_capacity(header_bytes.len() / SNAPSHOT_HEADER_SIZE);
+        for bytes in header_bytes.chunks(SNAPSHOT_HEADER_SIZE) {
+            let header: BlockHeader = deserialize(bytes)?;
+            headers.push(LoneBlockHeader {
+                header,
+                tx_count: VarInt(0),
+            });
+        }
+        Ok(headers)
+    }
+
+    /// Load headers from a snapshot written by `export_headers()`, read from `reader`, on top of
+    /// the headers we already have.  Nothing in the snapshot is trusted besides its checksum:
+    /// it must have the headers we already have from our lowest one (the anchor, if we were
+    /// started from one -- see `init_from_anchor()`) up, and the rest are validated (linkage,
+    /// checkpoints, versions, and proof-of-work) as if a peer had sent them.  The new headers
+    /// are stored in one transaction, so if any of them are invalid (or the import fails for
+    /// any other reason), none are.
+    /// Returns the height of our chain tip afterwards.
+    pub fn import_headers<R: Read>(&mut self, reader: &mut R) -> Result<u64, btc_error> {
+        assert!(self.readwrite, "SPV header DB is open read-only");
+
+        let mut snapshot = vec![];
+        reader.read_to_end(&mut snapshot)?;
+        let headers = SpvClient::parse_header_snapshot(&snapshot, self.network_id)?;
+        self.import_header_chain(&headers)
+    }
+
+    /// Store `headers`, a chain of headers from the genesis header up, on top of the headers we
+    /// already have (see `import_headers()`).  The ones we already have must match ours, and the
+    /// rest are validated (linkage, checkpoints, versions, and proof-of-work) and stored in a
+    /// single transaction.
+    /// Returns the height of our chain tip afterwards.
+    fn import_header_chain(&mut self, headers: &[LoneBlockHeader]) -> Result<u64, btc_error> {
+        // the headers below our lowest one are gone, so the imported chain must connect to it
+        let pruned_height = self.get_pruned_height()?;
+        if headers.len() as u64 <= pruned_height {
+            warn!(
+                "Imported headers end at height {}, below our lowest header at height {}",
+                headers.len() as i64 - 1,
+                pruned_height
+            );
+            return Err(btc_error::NoncontiguousHeader);
+        }
+
+        let tip_height = self.get_highest_header_height()?;
+        let num_known = cmp::min(headers.len() as u64, tip_height + 1);
+        let known_headers = self.read_block_headers(pruned_height, num_known)?;
+        for (i, (ours, theirs)) in known_headers
+            .iter()
+            .zip(headers[(pruned_height as usize)..].iter())
+            .enumerate()
+        {
+            if ours.header.bitcoin_hash() != theirs.header.bitcoin_hash() {
+                warn!(
+                    "Imported headers have header {} at height {}, but we have {}",
+                    theirs.header.bitcoin_hash(),
+                    pruned_height + (i as u64),
+                    ours.header.bitcoin_hash()
+                );
+                return Err(btc_error::NoncontiguousHeader);
+            }
+        }
+
+        let new_headers = &headers[(num_known as usize)..];
+        if new_headers.is_empty() {
+            return Ok(tip_height);
+        }
+
+        // the imported header at our tip height matches ours, so the new headers connect to
+        // our chain if they're contiguous with it
+        let mut linked_headers = vec![headers[tip_height as usize].clone()];
+        linked_headers.extend_from_slice(new_headers);
+        SpvClient::validate_header_integrity(tip_height, &linked_headers, self.check_txcount)?;
+        self.validate_header_checkpoints(tip_height + 1, new_headers)?;
+        self.validate_header_versions(tip_height + 1, new_headers)?;
+
+        // store the new headers and validate their work in a single transaction, so a chain
+        // with an invalid header leaves our headers as they were.  The validation reads see the
+        // transaction's writes since they share the connection.
+        let tip_before = self.read_tip_hash()?;
+        let new_tip = tip_height + (new_headers.len() as u64);
+        let mut tx = self
+            .headers_db
+            .unchecked_transaction()
+            .map_err(db_error::SqliteError)?;
+        for (i, header) in new_headers.iter().enumerate() {
+            SpvClient::insert_block_header(&mut tx, header.header, tip_height + 1 + (i as u64))?;
+        }
+        self.validate_header_work(
+            cmp::max(
+                (tip_height.saturating_sub(1)) / BLOCK_DIFFICULTY_CHUNK_SIZE,
+                pruned_height / BLOCK_DIFFICULTY_CHUNK_SIZE,
+            ),
+            new_tip / BLOCK_DIFFICULTY_CHUNK_SIZE + 1,
+        )
+        .map_err(|e| {
+            warn!(
+                "Invalid imported headers after height {}: {:?}",
+                tip_height, &e
+            );
+            e
+        })?;
+        tx.commit().map_err(db_error::SqliteError)?;
+        self.headers_committed(new_headers.len() as u64)?;
+
+        self.update_chain_work()?;
+        self.publish_tip(tip_before, 0)?;
+
+        debug!(
+            "Imported headers {}-{} to {}",
+            tip_height + 1,
+            new_tip,
+            &self.headers_path
+        );
+        Ok(new_tip)
+    }
+
+    /// Load headers from a snapshot file at `path` (see `import_headers()`).
+    /// Returns the height of our chain tip afterwards.
+    pub fn import_headers_file(&mut self, path: &str) -> Result<u64, btc_error> {
+        let mut file = fs::File::open(path).map_err(btc_error::FilesystemError)?;
+        let tip_height = self.import_headers(&mut file)?;
+        debug!("Read header snapshot {} up to {}", path, tip_height);
+        Ok(tip_height)
+    }
+
+    /// Re-validate the last `count` headers below and including our chain tip, e.g. in a header
+    /// DB we got from somewhere else:  they must be contiguous, match our checkpoints, and have
+    /// enough work (the difficulty intervals they fall in are checked in full).  Headers below
+    /// the pruned height are not checked.
+    /// Returns the height of the lowest header checked.
+    pub fn validate_last_headers(&self, count: u64) -> Result<u64, btc_error> {
+        let tip_height = self.get_highest_header_height()?;
+        let start_height = cmp::max(
+            (tip_height + 1).saturating_sub(count),
+            self.get_pruned_height()?,
+        );
+        let headers = self.read_block_headers(start_height, tip_height + 1)?;
+        if headers.len() as u64 != tip_height + 1 - start_height {
+            warn!(
+                "Missing headers in {}: expected {}, got {}",
+                &self.headers_path,
+                tip_height + 1 - start_height,
+                headers.len()
+            );
+            return Err(btc_error::MissingHeader);
+        }
+
+        SpvClient::validate_header_integrity(start_height, &headers, false)?;
+        self.validate_header_checkpoints(start_height, &headers)?;
+        self.validate_header_work(
+            start_height / BLOCK_DIFFICULTY_CHUNK_SIZE,
+            tip_height / BLOCK_DIFFICULTY_CHUNK_SIZE + 1,
+        )?;
+        Ok(start_height)
+    }
+
+    /// Get the easiest target a block on the given network may have (its proof-of-work limit).
+    /// Signet's limit is lower than mainnet's and testnet's, since its blocks are authorized by
+    /// a signature challenge instead of by work.  Regtest has no difficulty adjustment, and uses
+    /// nBits = 0x207fffff throughout.
+    pub fn max_target(network_id: BitcoinNetworkType) -> Uint256 {
+        match network_id {
+            BitcoinNetworkType::Mainnet | BitcoinNetworkType::Testnet => Uint256([
+                0x0000000000000000,
+                0x0000000000000000,
+                0x0000000000000000,
+                0x00000000ffff0000,
+            ]),
+            BitcoinNetworkType::Signet => Uint256([
+                0x0000000000000000,
+                0x0000000000000000,
+                0x0000000000000000,
+                0x00000377ae000000,
+            ]),
+            BitcoinNetworkType::Regtest => Uint256([
+                0x0000000000000000,
+                0x0000000000000000,
+                0x0000000000000000,
+                0x7fffffff00000000,
+            ]),
+        }
+    }
+
+    /// Determine the (bits, target) between two headers, using mainnet's proof-of-work limit
+    pub fn get_target_between_headers(
+        first_header: &LoneBlockHeader,
+        last_header: &LoneBlockHeader,
+    ) -> (u32, Uint256) {
+        SpvClient::get_network_target_between_headers(
+            BitcoinNetworkType::Mainnet,
+            first_header,
+            last_header,
+        )
+    }
+
+    /// Determine the (bits, target) between two headers on the given network
+    pub fn get_network_target_between_headers(
+        network_id: BitcoinNetworkType,
+        first_header: &LoneBlockHeader,
+        last_header: &LoneBlockHeader,
+    ) -> (u32, Uint256) {
+        let max_target = SpvClient::max_target(network_id);
+
+        // find actual timespan as being clamped between +/- 4x of the target timespan
+        let mut actual_timespan = (last_header.header.time - first_header.header.time) as u64;
+        let target_timespan = BLOCK_DIFFICULTY_INTERVAL as u64;
+        if actual_timespan < (target_timespan / 4) {
+            actual_timespan = target_timespan / 4;
+        }
+        if actual_timespan > (target_timespan * 4) {
+            actual_timespan = target_timespan * 4;
+        }
+
+        let last_target = last_header.header.target();
+        let new_target =
+            (last_target * Uint256::from_u64(actual_timespan)) / Uint256::from_u64(target_timespan);
+        let target = cmp::min(new_target, max_target);
+
+        let bits = BlockHeader::compact_target_from_u256(&target);
+        let target = BlockHeader::compact_target_to_u256(bits);
+
+        (bits, target)
+    }
+
+    /// Testnet only: walk back from the parent of the block at `current_header_height` to find
+    /// the last block that was not mined under the 20-minute min-difficulty rule.  The walk stops
+    /// at the first block of the current difficulty interval, since its bits were set by the
+    /// retarget (even if the retarget itself produced the min difficulty).  This mirrors
+    /// Bitcoin Core's `GetNextWorkRequired()` for testnet.
+    /// `headers_in_range` is consulted first (the 0th element is the parent); any remaining
+    /// ancestors are read from the DB.
+    /// Returns None if we're missing an ancestor.
+    fn find_last_non_min_difficulty_header(
+        &self,
+        current_header_height: u64,
+        headers_in_range: &VecDeque<BlockHeader>,
+        max_target_bits: u32,
+    ) -> Result<Option<BlockHeader>, btc_error> {
+        let interval_start_height =
+            (current_header_height / BLOCK_DIFFICULTY_CHUNK_SIZE) * BLOCK_DIFFICULTY_CHUNK_SIZE;
+        let mut height = current_header_height;
+        while height > interval_start_height {
+            height -= 1;
+            let offset = (current_header_height - 1 - height) as usize;
+            let ancestor = match headers_in_range.get(offset) {
+                Some(hdr) => hdr.clone(),
+                None => match self.read_block_header(height)? {
+                    Some(res) => res.header,
+                    None => return Ok(None),
+                },
+            };
+            if ancestor.bits != max_target_bits || height == interval_start_height {
+                return Ok(Some(ancestor));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Verify that `current_header`'s difficulty target is the one the chain requires at
+    /// `current_header_height`.
+    /// * At a retarget boundary, the expected target is recomputed from the timespan of the
+    /// previous 2016 blocks (clamped to 4x/0.25x of two weeks).
+    /// * Between boundaries, the target must stay the same as the boundary's, except under the
+    /// testnet 20-minute rule.
+    /// * In the first interval, the target must be the genesis block's.
+    /// `headers_in_range` has the same meaning as in `get_target()`.
+    /// Returns the target the header's hash must meet, or None if we're missing headers needed
+    /// to compute it.
+    /// Returns Err(btc_error::InvalidPoW) if the header's bits don't match.
+    pub fn verify_retarget(
+        &self,
+        current_header_height: u64,
+        current_header: &BlockHeader,
+        headers_in_range: &VecDeque<BlockHeader>,
+    ) -> Result<Option<Uint256>, btc_error> {
+        let interval = current_header_height / BLOCK_DIFFICULTY_CHUNK_SIZE;
+        let (bits, target) = if interval == 0 {
+            let genesis_header = SpvClient::get_genesis_header(self.network_id);
+            (genesis_header.bits, genesis_header.target())
+        } else {
+            match self.get_target(
+                current_header_height,
+                current_header,
+                headers_in_range,
+                interval,
+            )? {
+                Some(x) => x,
+                None => return Ok(None),
+            }
+        };
+
+        if current_header.bits != bits {
+            error!(
+                "bits mismatch at block {} of {} (offset {} interval {}): {:08x} != {:08x}",
+                current_header_height,
+                self.headers_path,
+                current_header_height % BLOCK_DIFFICULTY_CHUNK_SIZE,
+                interval,
+                current_header.bits,
+                bits
+            );
+            return Err(btc_error::InvalidPoW);
+        }
+        Ok(Some(target))
+    }
+
+    /// Determine the target difficult over a given difficulty adjustment interval
+    /// the `interval` parameter is the difficulty interval -- a 2016-block interval.
+    /// * On mainnet, `headers_in_range` can be empty. If it's not empty, then the 0th element is
+    /// treated as the parent of `current_header`.  On testnet, `headers_in_range` should be the
+    /// headers in the given `interval` that precede `current_header`, newest first; any that
+    /// are missing are read from the DB.
+    /// * On testnet, a block more than 20 minutes after its parent may be mined at the minimum
+    /// difficulty.  Otherwise, a non-retarget block must use the bits of the last block in the
+    /// interval that was not mined at the minimum difficulty.  Retargets are computed from the
+    /// last block of the previous interval, as on mainnet -- even if it was a min-difficulty
+    /// block (this is how testnet3 behaves).
+    /// * Signet retargets like mainnet, but with a lower proof-of-work limit.  Its blocks also
+    /// carry a signature for the network's challenge script, but that lives in the coinbase, so
+    /// it is not checked here.
+    /// Returns (new bits, new target)
+    pub fn get_target(
+        &self,
+        current_header_height: u64,
+        current_header: &BlockHeader,
+        headers_in_range: &VecDeque<BlockHeader>,
+        interval: u64,
+    ) -> Result<Option<(u32, Uint256)>, btc_error> {
+        if interval == 0 {
+            panic!(
+                "Invalid argument: interval must be positive (got {})",
+                interval
+            );
+        }
+
+        // In Regtest mode there's no difficulty adjustment active.
+        // it uses the highest possible difficulty -- represents nBits = 0x207fffff
+        if self.network_id == BitcoinNetworkType::Regtest {
+            return Ok(Some((
+                0x207fffff,
+                SpvClient::max_target(BitcoinNetworkType::Regtest),
+            )));
+        }
+
+        let max_target = SpvClient::max_target(self.network_id);
+        let max_target_bits = BlockHeader::compact_target_from_u256(&max_target);
+
+        let parent_header = if headers_in_range.len() > 0 {
+            headers_in_range[0]
+        } else {
+            match self.read_block_header(current_header_height - 1)? {
+                Some(res) => res.header,
+                None => return Ok(None),
+            }
+        };
+
+        if current_header_height % BLOCK_DIFFICULTY_CHUNK_SIZE != 0
+            && self.network_id == BitcoinNetworkType::Testnet
+        {
+            // In Testnet mode, if the new block's timestamp is more than 2 * 60 * 10 minutes
+            // then allow mining of a min-difficulty block.
+            if current_header.time > parent_header.time + 10 * 60 * 2 {
+                return Ok(Some((max_target_bits, max_target)));
+            }
+
+            // Otherwise return the last non-special-min-difficulty-rules-block
+            let ancestor = match self.find_last_non_min_difficulty_header(
+                current_header_height,
+                headers_in_range,
+                max_target_bits,
+            )? {
+                Some(hdr) => hdr,
+                None => return Ok(None),
+            };
+            return Ok(Some((ancestor.bits, ancestor.target())));
+        }
+
+        let pruned_height = self.get_pruned_height()?;
+        if pruned_height > 0 && interval * BLOCK_DIFFICULTY_CHUNK_SIZE == pruned_height {
+            // the headers this interval's target was computed from were pruned (or are below
+            // the first burn block), so take the target of the interval's first header
+            return match self.read_block_header(pruned_height)? {
+                Some(res) => Ok(Some((res.header.bits, res.header.target()))),
+                None => Ok(None),
+            };
+        }
+
+        let first_header =
+            match self.read_block_header((interval - 1) * BLOCK_DIFFICULTY_CHUNK_SIZE)? {
+                Some(res) => res,
+                None => return Ok(None),
+            };
+
+        let last_header =
+            match self.read_block_header(interval * BLOCK_DIFFICULTY_CHUNK_SIZE - 1)? {
+                Some(res) => res,
+                None => return Ok(None),
+            };
+
+        Ok(Some(SpvClient::get_network_target_between_headers(
+            self.network_id,
+            &first_header,
+            &last_header,
+        )))
+    }
+
+    /// Ask for the next batch of headers (note that this will return the maximal size of headers)
+    pub fn send_next_getheaders(
+        &mut self,
+        indexer: &mut BitcoinIndexer,
+        block_height: u64,
+    ) -> Result<(), btc_error> {
+        // ask for the next batch
+        let locator = self.make_next_block_locator(block_height)?;
+        indexer.send_getheaders(locator)
+    }
+
+    /// Make the block locator to ask for the headers after the one at `block_height`, which we
+    /// must have.
+    fn make_next_block_locator(&self, block_height: u64) -> Result<Vec<Sha256dHash>, btc_error> {
+        match self.read_block_header(block_height) {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                debug!(
+                    "No header found for block {} in {}",
+                    block_height, &self.headers_path
+                );
+                return Err(btc_error::MissingHeader);
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to read block header at height {} in {}: {:?}",
+                    block_height, &self.headers_path, &e
+                );
+                return Err(e);
+            }
+        };
+        self.make_block_locator_from(block_height)
+    }
+
+    /// Get the heights of the headers in a block locator for the header at `height`:  the 10
+    /// headers from `height` down, and then headers exponentially farther apart down to
+    /// `lowest_height`, which is always last.
+    fn block_locator_heights(height: u64, lowest_height: u64) -> Vec<u64> {
+        let mut heights = vec![];
+        let mut step = 1;
+        let mut next_height = cmp::max(height, lowest_height);
+        loop {
+            heights.push(next_height);
+            if next_height == lowest_height {
+                break;
+            }
+            if heights.len() >= 10 {
+                step *= 2;
+            }
+            next_height = cmp::max(next_height.saturating_sub(step), lowest_height);
+        }
+        heights
+    }
+
+    /// Make a block locator for our highest header, to ask a peer for the headers after it with
+    /// getheaders.  If the peer's chain has diverged from ours, it will answer with the headers
+    /// after the highest header in the locator that it does have, so we find the fork point in
+    /// one round trip.
+    pub fn make_block_locator(&self) -> Result<Vec<Sha256dHash>, btc_error> {
+        self.make_block_locator_from(self.get_highest_header_height()?)
+    }
+
+    /// Make a block locator for the header at `height`.  Headers we don't have (i.e. pruned
+    /// headers) are left out of it.
+    pub fn make_block_locator_from(&self, height: u64) -> Result<Vec<Sha256dHash>, btc_error> {
+        let mut locator = vec![];
+        for locator_height in
+            SpvClient::block_locator_heights(height, self.get_pruned_height()?).into_iter()
+        {
+            if let Some(hdr) = self.read_block_header(locator_height)? {
+                locator.push(hdr.header.bitcoin_hash());
+            }
+        }
+        if locator.len() == 0 {
+            return Err(btc_error::MissingHeader);
+        }
+        Ok(locator)
+    }
+}
+
+impl Drop for SpvClient {
+    /// Make the headers written since the last sync durable, so that a clean shutdown doesn't
+    /// lose them
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_unsynced_headers() {
+            warn!(
+                "Failed to sync headers written to {}: {:?}",
+                &self.headers_path, &e
+            );
+        }
+    }
+}
+
+impl BitcoinMessageHandler for SpvClient {
+    /// Trait message handler
+    /// initiate the conversation with the bitcoin peer
+    fn begin_session(&mut self, indexer: &mut BitcoinIndexer) -> Result<bool, btc_error> {
+        let start_height = self.cur_block_height;
+        self.end_block_height = Some(indexer.runtime.block_height);
+
+        if indexer.runtime.block_height <= start_height {
+            debug!("Have all headers up to {}", start_height);
+            return Ok(false);
+        }
+
+        debug!(
+            "Get headers {}-{} to {}",
+            self.cur_block_height,
+            self.end_block_height.unwrap(),
+            self.headers_path
+        );
+
+        indexer.runtime.last_getheaders_send_time = get_epoch_time_secs();
+        self.send_next_getheaders(indexer, start_height)
+            .and_then(|_r| Ok(true))
+    }
+
+    /// Trait message handler
+    /// Take headers, validate them, and ask for more
+    fn handle_message(
+        &mut self,
+        indexer: &mut BitcoinIndexer,
+        msg: PeerMessage,
+    ) -> Result<bool, btc_error> {
+        assert!(self.end_block_height.is_some());
+
+        let end_block_height = self.end_block_height.unwrap();
+
+        match msg {
+            btc_message::NetworkMessage::Headers(block_headers) => {
+                if self.cur_block_height >= end_block_height {
+                    // done
+                    return Ok(false);
+                }
+
+                self.absorb_headers(block_headers)?;
+
+                // clear timeout
+                indexer.runtime.last_getheaders_send_time = 0;
+
+                // ask for the next batch
+                let block_height = self.get_highest_header_height()?;
+                self.send_next_getheaders(indexer, block_height)
+                    .and_then(|_| Ok(true))
+            }
+            x => Err(btc_error::UnhandledMessage(x)),
+        }
+    }
+}
+
+/// Message handler that asks our p2p peer for one batch of headers
+struct HeadersRequest {
+    locator: Vec<Sha256dHash>,
+    headers: Option<Vec<LoneBlockHeader>>,
+}
+
+impl BitcoinMessageHandler for HeadersRequest {
+    /// Trait message handler
+    /// send the getheaders (again, if we reconnected)
+    fn begin_session(&mut self, indexer: &mut BitcoinIndexer) -> Result<bool, btc_error> {
+        indexer.runtime.last_getheaders_send_time = get_epoch_time_secs();
+        indexer
+            .send_getheaders(self.locator.clone())
+            .and_then(|_| Ok(true))
+    }
+
+    /// Trait message handler
+    /// take the headers, and stop
+    fn handle_message(
+        &mut self,
+        indexer: &mut BitcoinIndexer,
+        msg: PeerMessage,
+    ) -> Result<bool, btc_error> {
+        match msg {
+            btc_message::NetworkMessage::Headers(block_headers) => {
+                indexer.runtime.last_getheaders_send_time = 0;
+                self.headers = Some(block_headers);
+                Ok(false)
+            }
+            x => Err(btc_error::UnhandledMessage(x)),
+        }
+    }
+}
+
+impl HeadersRequest {
+    /// Ask the peer for the headers, connecting to it first if need be, and wait for them.
+    /// Fails with TimedOut if they don't arrive within `stall_timeout`.  Unlike
+    /// `peer_communicate()`, this doesn't reconnect after errors -- they're all returned.
+    fn run_until_stalled(
+        &mut self,
+        indexer: &mut BitcoinIndexer,
+        stall_timeout: Duration,
+    ) -> Result<(), btc_error> {
+        if !indexer.is_connected() {
+            indexer.connect_handshake_backoff()?;
+        }
+        self.begin_session(indexer)?;
+
+        let deadline = Instant::now() + stall_timeout;
+        while self.headers.is_none() {
+            let now = Instant::now();
+            if now >= deadline {
+                warn!(
+                    "No headers from {}:{} within {:?}",
+                    indexer.peer_host(),
+                    indexer.peer_port(),
+                    &stall_timeout
+                );
+                return Err(btc_error::TimedOut);
+            }
+            indexer.check_shutdown_deadline()?;
+            if !indexer.poll_message(cmp::min(deadline - now, SHUTDOWN_POLL_INTERVAL))? {
+                continue;
+            }
+
+            let msg = indexer.recv_message()?;
+            match indexer.handle_message(msg, Some(&mut *self)) {
+                Ok(_) => {}
+                Err(btc_error::UnhandledMessage(m)) => {
+                    debug!("Unhandled message {:?}", m);
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Headers from our p2p peer, via `getheaders`
+impl HeaderSource for BitcoinIndexer {
+    /// (Re)connect to the peer, and get the chain tip height it reports in its handshake
+    fn get_tip_height(&mut self) -> Result<u64, btc_error> {
+        self.connect_handshake_backoff()
+    }
+
+    /// If the config has a `header_stall_timeout`, a peer that doesn't answer within it is
+    /// given up on, and the headers are asked for again from the next peer (see
+    /// `BitcoinIndexer::switch_header_peer()`).
+    fn get_headers(
+        &mut self,
+        locator: Vec<Sha256dHash>,
+    ) -> Result<Vec<LoneBlockHeader>, btc_error> {
+        let mut request = HeadersRequest {
+            locator,
+            headers: None,
+        };
+        let stall_timeout = match self.config.header_stall_timeout {
+            Some(stall_timeout) => stall_timeout,
+            None => {
+                self.peer_communicate(&mut request, false)?;
+                return Ok(request.headers.unwrap_or(vec![]));
+            }
+        };
+
+        loop {
+            match request.run_until_stalled(self, stall_timeout) {
+                Ok(()) => {
+                    return Ok(request.headers.unwrap_or(vec![]));
+                }
+                Err(e) if e.is_transient() => {
+                    let context = self.log_context();
+                    warn!("Failed to get headers: {:?}", &e;
+                          "peer" => %context,
+                          "height" => context.height,
+                          "request_id" => context.request_id);
+                    match e {
+                        // we already waited out the stall
+                        btc_error::TimedOut => {}
+                        _ => self.schedule_reconnect(),
+                    }
+                    self.switch_header_peer();
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+impl HeaderChunk {
+    /// How many headers are in this chunk
+    pub fn len(&self) -> u64 {
+        self.end_height - self.start_height
+    }
+
+    /// Verify that `headers` are exactly the headers of this chunk:  they must build on the
+    /// start header, be contiguous, and end with the end header.
+    pub fn validate(&self, headers: &[LoneBlockHeader]) -> Result<(), btc_error> {
+        if headers.len() as u64 != self.len() {
+            warn!(
+                "Header chunk {}-{} has {} headers, expected {}",
+                self.start_height,
+                self.end_height,
+                headers.len(),
+                self.len()
+            );
+            return Err(btc_error::InvalidReply);
+        }
+        if headers[0].header.prev_blockhash != self.start_hash {
+            warn!(
+                "Header chunk {}-{} does not build on {}",
+                self.start_height, self.end_height, &self.start_hash
+            );
+            return Err(btc_error::NoncontiguousHeader);
+        }
+        SpvClient::validate_header_integrity(self.start_height, &headers.to_vec(), true)?;
+        if headers[headers.len() - 1].header.bitcoin_hash() != self.end_hash {
+            warn!(
+                "Header chunk {}-{} does not end at {}",
+                self.start_height, self.end_height, &self.end_hash
+            );
+            return Err(btc_error::CheckpointMismatch(self.end_height));
+        }
+        Ok(())
+    }
+}
+
+impl HeaderChunkFetcher {
+    pub fn new(chunk: HeaderChunk) -> HeaderChunkFetcher {
+        HeaderChunkFetcher {
+            chunk,
+            headers: vec![],
+        }
+    }
+
+    /// Hash of the last header we have in this chunk
+    fn last_hash(&self) -> Sha256dHash {
+        match self.headers.last() {
+            Some(hdr) => hdr.header.bitcoin_hash(),
+            None => self.chunk.start_hash,
+        }
+    }
+
+    /// Add a batch of headers from the peer to the chunk.
+    /// Headers past the end of the chunk are ignored.
+    /// Returns Ok(true) if we need more headers.
+    pub fn absorb_headers(
+        &mut self,
+        block_headers: Vec<LoneBlockHeader>,
+    ) -> Result<bool, btc_error> {
+        if block_headers.len() == 0 {
+            warn!(
+                "Peer has no headers after {} for chunk {}-{}",
+                &self.last_hash(),
+                self.chunk.start_height,
+                self.chunk.end_height
+            );
+            return Err(btc_error::InvalidReply);
+        }
+        for hdr in block_headers.into_iter() {
+            if (self.headers.len() as u64) >= self.chunk.len() {
+                break;
+            }
+            if hdr.header.prev_blockhash != self.last_hash() {
+                return Err(btc_error::NoncontiguousHeader);
+            }
+            self.headers.push(hdr);
+        }
+        Ok((self.headers.len() as u64) < self.chunk.len())
+    }
+
+    /// Get the downloaded chunk, if it's complete and valid
+    pub fn into_headers(self) -> Result<Vec<LoneBlockHeader>, btc_error> {
+        self.chunk.validate(&self.headers)?;
+        Ok(self.headers)
+    }
+}
+
+impl BitcoinMessageHandler for HeaderChunkFetcher {
+    /// Trait message handler
+    /// initiate the conversation with the bitcoin peer
+    fn begin_session(&mut self, indexer: &mut BitcoinIndexer) -> Result<bool, btc_error> {
+        if (self.headers.len() as u64) >= self.chunk.len() {
+            return Ok(false);
+        }
+        indexer
+            .send_getheaders_until(self.last_hash(), self.chunk.end_hash)
+            .and_then(|_| Ok(true))
+    }
+
+    /// Trait message handler
+    /// Take headers and ask for more until the chunk is complete
+    fn handle_message(
+        &mut self,
+        indexer: &mut BitcoinIndexer,
+        msg: PeerMessage,
+    ) -> Result<bool, btc_error> {
+        match msg {
+            btc_message::NetworkMessage::Headers(block_headers) => {
+                indexer.runtime.last_getheaders_send_time = 0;
+                if !self.absorb_headers(block_headers)? {
+                    return Ok(false);
+                }
+                indexer
+                    .send_getheaders_until(self.last_hash(), self.chunk.end_hash)
+                    .and_then(|_| Ok(true))
+            }
+            x => Err(btc_error::UnhandledMessage(x)),
+        }
+    }
+}
+
+/// Message handler that downloads the basic filters of a range of blocks whose headers we
+/// already have, along with the filter headers that authenticate them.  The peer's filter
+/// header checkpoints are fetched first, then the filter headers (checked against them), then
+/// the filters (checked against the filter headers).
+pub struct FilterSync<'a> {
+    spv_client: &'a mut SpvClient,
+    /// Download filters for the blocks from `start_height` up to `end_height`
+    pub start_height: u64,
+    pub end_height: u64,
+    /// The peer's filter header checkpoints up to `end_height`, once we have them
+    checkpoints: Option<Vec<Sha256dHash>>,
+    next_header_height: u64,
+    next_filter_height: u64,
+    /// Height of the last filter in the outstanding `getcfilters` request
+    filter_batch_end: u64,
+    /// Set if the peer does not serve compact filters
+    pub unsupported: bool,
+}
+
+impl<'a> FilterSync<'a> {
+    pub fn new(
+        spv_client: &'a mut SpvClient,
+        start_height: u64,
+        end_height: u64,
+    ) -> Result<FilterSync<'a>, btc_error> {
+        // without any filter headers, start from the checkpoint below the first block so the
+        // filter header chain has something to build on
+        let next_header_height = match spv_client.get_highest_filter_header_height()? {
+            Some(height) => height + 1,
+            None if start_height <= FILTER_CHECKPOINT_INTERVAL => 0,
+            None => {
+                ((start_height - 1) / FILTER_CHECKPOINT_INTERVAL) * FILTER_CHECKPOINT_INTERVAL + 1
+            }
+        };
+        let next_filter_height = match spv_client.get_highest_filter_height()? {
+            Some(height) => cmp::max(height + 1, start_height),
+            None => start_height,
+        };
+        Ok(FilterSync {
+            spv_client,
+            start_height,
+            end_height,
+            checkpoints: None,
+            next_header_height,
+            next_filter_height,
+            filter_batch_end: 0,
+            unsupported: false,
+        })
+    }
+
+    fn block_hash(&self, height: u64) -> Result<Sha256dHash, btc_error> {
+        self.spv_client
+            .read_block_header(height)?
+            .map(|hdr| hdr.header.bitcoin_hash())
+            .ok_or(btc_error::MissingHeader)
+    }
+
+    /// Ask for the next thing we need.
+    /// Returns Ok(false) if we're done.
+    fn send_next_request(&mut self, indexer: &mut BitcoinIndexer) -> Result<bool, btc_error> {
+        if self.checkpoints.is_none() {
+            let stop_hash = self.block_hash(self.end_height)?;
+            indexer.send_getcfcheckpt(stop_hash)?;
+            return Ok(true);
+        }
+        if self.next_header_height <= self.end_height {
+            let batch_end = cmp::min(
+                self.next_header_height + MAX_CFHEADERS_PER_REQUEST - 1,
+                self.end_height,
+            );
+            let stop_hash = self.block_hash(batch_end)?;
+            indexer.send_getcfheaders(self.next_header_height, stop_hash)?;
+            return Ok(true);
+        }
+        if self.next_filter_height <= self.end_height {
+            self.filter_batch_end = cmp::min(
+                self.next_filter_height + MAX_CFILTERS_PER_REQUEST - 1,
+                self.end_height,
+            );
+            let stop_hash = self.block_hash(self.filter_batch_end)?;
+            indexer.send_getcfilters(self.next_filter_height, stop_hash)?;
+            return Ok(true);
+        }
+        debug!(
+            "Have filters for blocks {}-{}",
+            self.start_height, self.end_height
+        );
+        Ok(false)
+    }
+}
+
+impl<'a> BitcoinMessageHandler for FilterSync<'a> {
+    /// Trait message handler
+    /// initiate the conversation with the bitcoin peer
+    fn begin_session(&mut self, indexer: &mut BitcoinIndexer) -> Result<bool, btc_error> {
+        if !indexer.peer_serves_compact_filters() {
+            debug!(
+                "Peer {}:{} does not serve compact filters",
+                indexer.peer_host(),
+                indexer.peer_port()
+            );
+            self.unsupported = true;
+            return Ok(false);
+        }
+        self.send_next_request(indexer)
+    }
+
+    /// Trait message handler
+    /// Store checkpoints, filter headers, and filters, and ask for more until we have them all
+    fn handle_message(
+        &mut self,
+        indexer: &mut BitcoinIndexer,
+        msg: PeerMessage,
+    ) -> Result<bool, btc_error> {
+        match msg {
+            btc_message::NetworkMessage::CFCheckpt(cfcheckpt) => {
+                indexer.runtime.last_getheaders_send_time = 0;
+                if cfcheckpt.filter_type != BASIC_FILTER_TYPE
+                    || cfcheckpt.stop_hash != self.block_hash(self.end_height)?
+                    || (cfcheckpt.filter_headers.len() as u64)
+                        != self.end_height / FILTER_CHECKPOINT_INTERVAL
+                {
+                    warn!(
+                        "Invalid filter header checkpoints up to block {}",
+                        &cfcheckpt.stop_hash
+                    );
+                    return Err(btc_error::InvalidReply);
+                }
+                self.checkpoints = Some(cfcheckpt.filter_headers);
+                self.send_next_request(indexer)
+            }
+            btc_message::NetworkMessage::CFHeaders(cfheaders) => {
+                indexer.runtime.last_getheaders_send_time = 0;
+                let checkpoints = self.checkpoints.as_ref().ok_or(btc_error::InvalidReply)?;
+                let end_height = self.spv_client.insert_filter_headers(
+                    self.next_header_height,
+                    &cfheaders,
+                    checkpoints,
+                )?;
+                self.next_header_height = end_height + 1;
+                self.send_next_request(indexer)
+            }
+            btc_message::NetworkMessage::CFilter(cfilter) => {
+                if self.next_filter_height > self.filter_batch_end {
+                    return Err(btc_error::InvalidReply);
+                }
+                self.spv_client
+                    .insert_filter(self.next_filter_height, &cfilter)?;
+                self.next_filter_height += 1;
+                if self.next_filter_height <= self.filter_batch_end {
+                    return Ok(true);
+                }
+                indexer.runtime.last_getdata_send_time = 0;
+                self.send_next_request(indexer)
+            }
+            x => Err(btc_error::UnhandledMessage(x)),
+        }
+    }
+}
+
+/// Message handler that stores the headers a peer announces to us after we send it
+/// `sendheaders` (BIP130).  Announced headers are validated just like the ones we ask for.
+pub struct HeaderAnnouncements<'a> {
+    spv_client: &'a mut SpvClient,
+    /// Number of announced headers stored
+    pub num_headers: u64,
+    /// Set if an announcement didn't connect to our headers, i.e. we missed some blocks and
+    /// need to ask for them
+    pub gap: bool,
+}
+
+impl<'a> HeaderAnnouncements<'a> {
+    pub fn new(spv_client: &'a mut SpvClient) -> HeaderAnnouncements<'a> {
+        HeaderAnnouncements {
+            spv_client,
+            num_headers: 0,
+            gap: false,
+        }
+    }
+}
+
+impl<'a> BitcoinMessageHandler for HeaderAnnouncements<'a> {
+    /// Trait message handler
+    /// Nothing to ask for; the peer sends announcements on its own
+    fn begin_session(&mut self, _indexer: &mut BitcoinIndexer) -> Result<bool, btc_error> {
+        Ok(true)
+    }
+
+    /// Trait message handler
+    /// Store announced headers that build on (or fork off of) our headers
+    fn handle_message(
+        &mut self,
+        indexer: &mut BitcoinIndexer,
+        msg: PeerMessage,
+    ) -> Result<bool, btc_error> {
+        match msg {
+            btc_message::NetworkMessage::Headers(block_headers) => {
+                indexer.runtime.last_getheaders_send_time = 0;
+                if block_headers.len() == 0 {
+                    return Ok(true);
+                }
+                indexer.runtime.last_headers_announcement_time = Some(Instant::now());
+
+                let num_headers = block_headers.len() as u64;
+                let tip_height = self.spv_client.get_highest_header_height()?;
+                match self.spv_client.handle_headers(tip_height, block_headers) {
+                    Ok(_) => {}
+                    Err(btc_error::NoncontiguousHeader) => {
+                        debug!(
+                            "Announced headers do not connect to our tip at {}",
+                            tip_height
+                        );
+                        self.gap = true;
+                        return Ok(false);
+                    }
+                    Err(e) => {
+                        return Err(e);
+                    }
+                }
+
+                self.num_headers += num_headers;
+                let tip_height = self.spv_client.get_highest_header_height()?;
+                indexer.runtime.block_height = cmp::max(indexer.runtime.block_height, tip_height);
+                debug!(
+                    "Stored {} announced headers; tip is now {}",
+                    num_headers, tip_height
+                );
+                Ok(true)
+            }
+            x => Err(btc_error::UnhandledMessage(x)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
 
     use super::*;
     use crate::burnchains::bitcoin::Error as btc_error;
     use crate::burnchains::bitcoin::*;
 
-    use std::fs::*;
+    use std::fs::*;
+
+    use stacks_common::deps_common::bitcoin::blockdata::block::{
+        Block, BlockHeader, LoneBlockHeader,
+    };
+    use stacks_common::deps_common::bitcoin::network::serialize::{
+        deserialize, serialize, BitcoinHash,
+    };
+    use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+
+    use stacks_common::util::log;
+
+    use std::env;
+
+    fn get_genesis_regtest_header() -> LoneBlockHeader {
+        let genesis_regtest_header = LoneBlockHeader {
+            header: BlockHeader {
+                bits: 545259519,
+                merkle_root: Sha256dHash::from_hex(
+                    "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+                )
+                .unwrap(),
+                nonce: 2,
+                prev_blockhash: Sha256dHash::from_hex(
+                    "0000000000000000000000000000000000000000000000000000000000000000",
+                )
+                .unwrap(),
+                time: 1296688602,
+                version: 1,
+            },
+            tx_count: VarInt(0),
+        };
+        genesis_regtest_header
+    }
+
+    /// Mainnet blocks 1-5
+    fn get_mainnet_headers_after_genesis() -> Vec<LoneBlockHeader> {
+        vec![
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 0x1d00ffff,
+                    merkle_root: Sha256dHash::from_hex(
+                        "0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098",
+                    )
+                    .unwrap(),
+                    nonce: 2573394689,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+                    )
+                    .unwrap(),
+                    time: 1231469665,
+                    version: 1,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 0x1d00ffff,
+                    merkle_root: Sha256dHash::from_hex(
+                        "9b0fc92260312ce44e74ef369f5c66bbb85848f2eddd5a7a1cde251e54ccfdd5",
+                    )
+                    .unwrap(),
+                    nonce: 1639830024,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048",
+                    )
+                    .unwrap(),
+                    time: 1231469744,
+                    version: 1,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 0x1d00ffff,
+                    merkle_root: Sha256dHash::from_hex(
+                        "999e1c837c76a1b7fbb7e57baf87b309960f5ffefbf2a9b95dd890602272f644",
+                    )
+                    .unwrap(),
+                    nonce: 1844305925,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "000000006a625f06636b8bb6ac7b960a8d03705d1ace08b1a19da3fdcc99ddbd",
+                    )
+                    .unwrap(),
+                    time: 1231470173,
+                    version: 1,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 0x1d00ffff,
+                    merkle_root: Sha256dHash::from_hex(
+                        "df2b060fa2e5e9c8ed5eaf6a45c13753ec8c63282b2688322eba40cd98ea067a",
+                    )
+                    .unwrap(),
+                    nonce: 2850094635,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "0000000082b5015589a3fdf2d4baff403e6f0be035a5d9742c1cae6295464449",
+                    )
+                    .unwrap(),
+                    time: 1231470988,
+                    version: 1,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 0x1d00ffff,
+                    merkle_root: Sha256dHash::from_hex(
+                        "63522845d294ee9b0188ae5cac91bf389a0c3723f084ca1025e7d9cdfe481ce1",
+                    )
+                    .unwrap(),
+                    nonce: 2011431709,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "000000004ebadb55ee9096c9a2f8880e09da59c0d68b1c228da88e48844a1485",
+                    )
+                    .unwrap(),
+                    time: 1231471428,
+                    version: 1,
+                },
+                tx_count: VarInt(0),
+            },
+        ]
+    }
+
+    fn get_regtest_headers_after_genesis() -> Vec<LoneBlockHeader> {
+        vec![
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "20bee96458517fc5082a9720ce6207b5742f2b18e4e0a7e7373342725d80f88c",
+                    )
+                    .unwrap(),
+                    nonce: 2,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+                    )
+                    .unwrap(),
+                    time: 1587626881,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "39d1a6f1ee7a5903797f92ec89e4c58549013f38114186fc2eb6e5218cb2d0ac",
+                    )
+                    .unwrap(),
+                    nonce: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "606d31daaaa5919f3720d8440dd99d31f2a4e4189c65879f19ae43268425e74b",
+                    )
+                    .unwrap(),
+                    time: 1587626882,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "a7e04ed25f589938eb5627abb7b5913dd77b8955bcdf72d7f111d0a71e346e47",
+                    )
+                    .unwrap(),
+                    nonce: 4,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "2fa2f451ac27f0e5cd3760ba6cdf34ef46adb76a44d96bc0f3bf3e713dd955f0",
+                    )
+                    .unwrap(),
+                    time: 1587626882,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+        ]
+    }
+
+    fn write_legacy_headers_file(path: &str, headers: &[LoneBlockHeader], trailing: &[u8]) {
+        let mut f = File::create(path).unwrap();
+        for hdr in headers.iter() {
+            let bytes = serialize(hdr).unwrap();
+            assert_eq!(bytes.len() as u64, BLOCK_HEADER_SIZE);
+            f.write_all(&bytes).unwrap();
+        }
+        f.write_all(trailing).unwrap();
+        f.sync_all().unwrap();
+    }
+
+    #[test]
+    fn test_spv_migrate_legacy_headers_file() {
+        let headers_path = "/tmp/test-spv-migrate_legacy_headers_file.dat";
+        let legacy_path = format!("{}.legacy", headers_path);
+        for path in [headers_path, legacy_path.as_str()].iter() {
+            if fs::metadata(path).is_ok() {
+                fs::remove_file(path).unwrap();
+            }
+        }
+
+        let mut all_headers = vec![get_genesis_regtest_header()];
+        all_headers.append(&mut get_regtest_headers_after_genesis());
+
+        // legacy file with a torn write at the end
+        write_legacy_headers_file(headers_path, &all_headers, &[0x01, 0x02, 0x03]);
+        assert!(SpvClient::is_legacy_headers_file(headers_path).unwrap());
+
+        // can't migrate in read-only mode
+        assert!(SpvClient::new(
+            headers_path,
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            false,
+            false
+        )
+        .is_err());
+
+        let spv_client = SpvClient::new(
+            headers_path,
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            true,
+            false,
+        )
+        .unwrap();
+
+        assert!(!SpvClient::is_legacy_headers_file(headers_path).unwrap());
+        assert!(fs::metadata(&legacy_path).is_err());
+        assert_eq!(spv_client.read_block_headers(0, 10).unwrap(), all_headers);
+
+        for (height, hdr) in all_headers.iter().enumerate() {
+            let hash = BurnchainHeaderHash::from_bitcoin_hash(&hdr.header.bitcoin_hash());
+            assert_eq!(
+                spv_client.get_header_by_hash(&hash).unwrap(),
+                Some((height as u64, hdr.clone()))
+            );
+        }
+        assert_eq!(
+            spv_client
+                .get_header_by_hash(&BurnchainHeaderHash([0x11; 32]))
+                .unwrap(),
+            None
+        );
+
+        // re-opening is a no-op
+        let spv_client = SpvClient::new(
+            headers_path,
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(spv_client.read_block_headers(0, 10).unwrap(), all_headers);
+    }
+
+    #[test]
+    fn test_spv_migrate_legacy_headers_file_resume() {
+        let headers_path = "/tmp/test-spv-migrate_legacy_headers_file_resume.dat";
+        let legacy_path = format!("{}.legacy", headers_path);
+        for path in [headers_path, legacy_path.as_str()].iter() {
+            if fs::metadata(path).is_ok() {
+                fs::remove_file(path).unwrap();
+            }
+        }
+
+        let mut all_headers = vec![get_genesis_regtest_header()];
+        all_headers.append(&mut get_regtest_headers_after_genesis());
+
+        // simulate a crash after moving the legacy file aside, but before the import committed
+        write_legacy_headers_file(&legacy_path, &all_headers, &[]);
+        {
+            let spv_client = SpvClient::new(
+                headers_path,
+                0,
+                None,
+                BitcoinNetworkType::Regtest,
+                true,
+                false,
+            )
+            .unwrap();
+            assert_eq!(spv_client.read_block_headers(0, 10).unwrap(), all_headers);
+        }
+        assert!(fs::metadata(&legacy_path).is_err());
+    }
+
+    #[test]
+    fn test_spv_migrate_legacy_headers_file_wrong_network() {
+        let headers_path = "/tmp/test-spv-migrate_legacy_headers_file_wrong_network.dat";
+        let legacy_path = format!("{}.legacy", headers_path);
+        for path in [headers_path, legacy_path.as_str()].iter() {
+            if fs::metadata(path).is_ok() {
+                fs::remove_file(path).unwrap();
+            }
+        }
+
+        let mut all_headers = vec![get_genesis_regtest_header()];
+        all_headers.append(&mut get_regtest_headers_after_genesis());
+        write_legacy_headers_file(headers_path, &all_headers, &[]);
+
+        match SpvClient::new(
+            headers_path,
+            0,
+            None,
+            BitcoinNetworkType::Mainnet,
+            true,
+            false,
+        ) {
+            Err(btc_error::WrongNetwork) => {}
+            Err(e) => panic!("Unexpected error {:?}", &e),
+            Ok(_) => panic!("Imported regtest headers into a mainnet headers DB"),
+        }
+
+        // legacy file is untouched
+        assert!(SpvClient::is_legacy_headers_file(headers_path).unwrap());
+        assert!(fs::metadata(&legacy_path).is_err());
+        assert_eq!(
+            SpvClient::read_legacy_headers_file(headers_path).unwrap(),
+            all_headers
+        );
+    }
+
+    #[test]
+    fn test_spv_migrate_legacy_headers_file_invalid_pow() {
+        let headers_path = "/tmp/test-spv-migrate_legacy_headers_file_invalid_pow.dat";
+        let legacy_path = format!("{}.legacy", headers_path);
+        let flat_path = "/tmp/test-spv-migrate_legacy_headers_file_invalid_pow.flat";
+        for path in [headers_path, legacy_path.as_str(), flat_path].iter() {
+            if fs::metadata(path).is_ok() {
+                fs::remove_file(path).unwrap();
+            }
+        }
+
+        let mut all_headers = vec![LoneBlockHeader {
+            header: SpvClient::get_genesis_header(BitcoinNetworkType::Testnet),
+            tx_count: VarInt(0),
+        }];
+        for header_hex in TESTNET3_HEADERS.iter() {
+            all_headers.push(LoneBlockHeader {
+                header: deserialize(&hex_bytes(header_hex).unwrap()).unwrap(),
+                tx_count: VarInt(0),
+            });
+        }
+
+        // the tip still builds on its parent, but no longer meets its target
+        let mut bad_headers = all_headers.clone();
+        bad_headers[2].header.nonce = bad_headers[2].header.nonce.wrapping_add(1);
+        write_legacy_headers_file(headers_path, &bad_headers, &[]);
+
+        match SpvClient::new(
+            headers_path,
+            0,
+            None,
+            BitcoinNetworkType::Testnet,
+            true,
+            false,
+        ) {
+            Err(btc_error::InvalidPoW) => {}
+            Err(e) => panic!("Unexpected error {:?}", &e),
+            Ok(_) => panic!("Migrated a header with insufficient work"),
+        }
+
+        // nothing was imported, and the legacy file is kept
+        let spv_client = SpvClient::new(
+            headers_path,
+            0,
+            None,
+            BitcoinNetworkType::Testnet,
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 0);
+        assert_eq!(
+            SpvClient::read_legacy_headers_file(&legacy_path).unwrap(),
+            bad_headers
+        );
+        fs::remove_file(headers_path).unwrap();
+        fs::remove_file(&legacy_path).unwrap();
+
+        // a valid flat file elsewhere can be imported into an existing headers DB, and is left
+        // in place
+        write_legacy_headers_file(flat_path, &all_headers, &[]);
+        let mut spv_client = SpvClient::new(
+            headers_path,
+            0,
+            None,
+            BitcoinNetworkType::Testnet,
+            true,
+            false,
+        )
+        .unwrap();
+        assert_eq!(spv_client.import_legacy_headers_file(flat_path).unwrap(), 2);
+        assert_eq!(spv_client.read_block_headers(0, 10).unwrap(), all_headers);
+        assert!(SpvClient::is_legacy_headers_file(flat_path).unwrap());
+
+        // ...but not one with an invalid header
+        fs::remove_file(headers_path).unwrap();
+        write_legacy_headers_file(flat_path, &bad_headers, &[]);
+        let mut spv_client = SpvClient::new(
+            headers_path,
+            0,
+            None,
+            BitcoinNetworkType::Testnet,
+            true,
+            false,
+        )
+        .unwrap();
+        match spv_client.import_legacy_headers_file(flat_path) {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("Unexpected result {:?}", &res),
+        }
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_spv_migrate_legacy_headers_file_corrupt() {
+        let headers_path = "/tmp/test-spv-migrate_legacy_headers_file_corrupt.dat";
+        let legacy_path = format!("{}.legacy", headers_path);
+        for path in [headers_path, legacy_path.as_str()].iter() {
+            if fs::metadata(path).is_ok() {
+                fs::remove_file(path).unwrap();
+            }
+        }
+
+        let mut all_headers = vec![get_genesis_regtest_header()];
+        all_headers.append(&mut make_unmined_headers(BitcoinNetworkType::Regtest, 40));
+
+        let mut bytes = vec![];
+        for hdr in all_headers.iter() {
+            bytes.append(&mut serialize(hdr).unwrap());
+        }
+        // prev_blockhash comes right after the 4-byte version
+        let flip_prev_blockhash = |bytes: &mut Vec<u8>, height: u64| {
+            bytes[(height * BLOCK_HEADER_SIZE + 4) as usize] ^= 0x01;
+        };
+
+        // killed in the middle of appending header 40
+        let mut truncated = bytes.clone();
+        truncated.truncate((40 * BLOCK_HEADER_SIZE + BLOCK_HEADER_SIZE / 2) as usize);
+        write_legacy_headers_file(headers_path, &[], &truncated);
+        {
+            let spv_client = SpvClient::new(
+                headers_path,
+                0,
+                None,
+                BitcoinNetworkType::Regtest,
+                true,
+                false,
+            )
+            .unwrap();
+            assert_eq!(spv_client.get_highest_header_height().unwrap(), 39);
+            assert_eq!(
+                spv_client.read_block_headers(0, 40).unwrap(),
+                all_headers[0..40].to_vec()
+            );
+        }
+        fs::remove_file(headers_path).unwrap();
+
+        // header 38 doesn't build on header 37, so headers 38-40 are discarded
+        let mut broken_tip = bytes.clone();
+        flip_prev_blockhash(&mut broken_tip, 38);
+        write_legacy_headers_file(headers_path, &[], &broken_tip);
+        {
+            let spv_client = SpvClient::new(
+                headers_path,
+                0,
+                None,
+                BitcoinNetworkType::Regtest,
+                true,
+                false,
+            )
+            .unwrap();
+            assert_eq!(spv_client.get_highest_header_height().unwrap(), 37);
+            assert_eq!(
+                spv_client.read_block_headers(0, 40).unwrap(),
+                all_headers[0..38].to_vec()
+            );
+        }
+        fs::remove_file(headers_path).unwrap();
+
+        // header 10 doesn't build on header 9, so everything from header 10 onwards is discarded
+        let mut broken_middle = bytes.clone();
+        flip_prev_blockhash(&mut broken_middle, 10);
+        write_legacy_headers_file(headers_path, &[], &broken_middle);
+        {
+            let spv_client = SpvClient::new(
+                headers_path,
+                0,
+                None,
+                BitcoinNetworkType::Regtest,
+                true,
+                false,
+            )
+            .unwrap();
+            assert_eq!(spv_client.get_highest_header_height().unwrap(), 9);
+            assert_eq!(
+                spv_client.read_block_headers(0, 40).unwrap(),
+                all_headers[0..10].to_vec()
+            );
+        }
+        assert!(fs::metadata(&legacy_path).is_err());
+    }
+
+    #[test]
+    fn test_spv_recover_legacy_headers_file() {
+        let headers_path = "/tmp/test-spv-recover_legacy_headers_file.dat";
+        let legacy_path = format!("{}.legacy", headers_path);
+        for path in [headers_path, legacy_path.as_str()].iter() {
+            if fs::metadata(path).is_ok() {
+                fs::remove_file(path).unwrap();
+            }
+        }
+
+        let mut all_headers = vec![get_genesis_regtest_header()];
+        all_headers.append(&mut make_unmined_headers(BitcoinNetworkType::Regtest, 40));
+
+        // the final record is full-length, but torn: its tx count is cut off mid-varint
+        let mut torn_record = serialize(&all_headers[40]).unwrap();
+        torn_record[BLOCK_HEADER_SIZE as usize - 1] = 0xfd;
+        write_legacy_headers_file(headers_path, &all_headers[0..40], &torn_record);
+
+        let headers =
+            SpvClient::recover_legacy_headers_file(headers_path, BitcoinNetworkType::Regtest)
+                .unwrap();
+        assert_eq!(headers, all_headers[0..40].to_vec());
+        assert_eq!(
+            fs::metadata(headers_path).unwrap().len(),
+            40 * BLOCK_HEADER_SIZE
+        );
+
+        // truncation never extends the file
+        SpvClient::truncate_legacy_headers_file(headers_path, 100).unwrap();
+        assert_eq!(
+            fs::metadata(headers_path).unwrap().len(),
+            40 * BLOCK_HEADER_SIZE
+        );
+
+        write_legacy_headers_file(headers_path, &all_headers[0..40], &torn_record);
+        {
+            let spv_client = SpvClient::new(
+                headers_path,
+                0,
+                None,
+                BitcoinNetworkType::Regtest,
+                true,
+                false,
+            )
+            .unwrap();
+            assert_eq!(spv_client.get_highest_header_height().unwrap(), 39);
+            assert_eq!(
+                spv_client.read_block_header(39).unwrap().unwrap(),
+                all_headers[39]
+            );
+        }
+        assert!(!SpvClient::is_legacy_headers_file(headers_path).unwrap());
+        assert!(fs::metadata(&legacy_path).is_err());
+        fs::remove_file(headers_path).unwrap();
+
+        // not even the genesis header survived, so we start over from genesis
+        write_legacy_headers_file(headers_path, &[], &torn_record[0..40]);
+        {
+            let spv_client = SpvClient::new(
+                headers_path,
+                0,
+                None,
+                BitcoinNetworkType::Regtest,
+                true,
+                false,
+            )
+            .unwrap();
+            assert_eq!(spv_client.get_highest_header_height().unwrap(), 0);
+            assert_eq!(
+                spv_client.read_block_header(0).unwrap().unwrap(),
+                get_genesis_regtest_header()
+            );
+        }
+        assert!(!SpvClient::is_legacy_headers_file(headers_path).unwrap());
+        assert!(fs::metadata(&legacy_path).is_err());
+    }
+
+    #[test]
+    fn test_spv_mainnet_genesis_header() {
+        let genesis_prev_blockhash = Sha256dHash::from_hex(
+            "0000000000000000000000000000000000000000000000000000000000000000",
+        )
+        .unwrap();
+        let genesis_merkle_root =
+            Sha256dHash::from_hex(BITCOIN_GENESIS_BLOCK_MERKLE_ROOT_MAINNET).unwrap();
+        let genesis_block_hash = Sha256dHash::from_hex(BITCOIN_GENESIS_BLOCK_HASH_MAINNET).unwrap();
+
+        let genesis_header = LoneBlockHeader {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: genesis_prev_blockhash,
+                merkle_root: genesis_merkle_root,
+                time: 1231006505,
+                bits: 0x1d00ffff,
+                nonce: 2083236893,
+            },
+            tx_count: VarInt(0),
+        };
+
+        test_debug!("\n");
+        test_debug!("genesis prev blockhash = {}", genesis_prev_blockhash);
+        test_debug!("genesis merkle root = {}", genesis_merkle_root);
+        test_debug!("genesis block hash = {}", genesis_block_hash);
+
+        assert_eq!(genesis_header.header.bitcoin_hash(), genesis_block_hash);
+    }
+
+    #[test]
+    fn test_spv_signet_genesis_header() {
+        let genesis_header = SpvClient::get_genesis_header(BitcoinNetworkType::Signet);
+        assert_eq!(
+            genesis_header.bitcoin_hash(),
+            Sha256dHash::from_hex(BITCOIN_GENESIS_BLOCK_HASH_SIGNET).unwrap()
+        );
+        assert_eq!(
+            genesis_header.merkle_root,
+            Sha256dHash::from_hex(BITCOIN_GENESIS_BLOCK_MERKLE_ROOT_MAINNET).unwrap()
+        );
+
+        // the genesis block is mined at signet's proof-of-work limit, which is easier than
+        // mainnet's
+        assert_eq!(
+            genesis_header.target(),
+            SpvClient::max_target(BitcoinNetworkType::Signet)
+        );
+        assert!(
+            SpvClient::max_target(BitcoinNetworkType::Signet)
+                > SpvClient::max_target(BitcoinNetworkType::Mainnet)
+        );
+    }
+
+    #[test]
+    fn test_spv_signet_headers() {
+        let db_path = "/tmp/test_spv_signet_headers.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+
+        // headers mined on top of signet's genesis block at its proof-of-work limit, which a
+        // mainnet-difficulty check would reject
+        let mut prev_blockhash =
+            SpvClient::get_genesis_header(BitcoinNetworkType::Signet).bitcoin_hash();
+        let mut headers = vec![];
+        for (merkle_root, time, nonce) in [
+            (
+                "705f425bfcb81942ec8db27abc2485c1322177233dac87d78445c704dccf129c",
+                1598919000,
+                11536696,
+            ),
+            (
+                "babb95b7a797b2e17dbc71c7b49dce0c15687d7704c03a4394fdeb40eaadc31c",
+                1598919600,
+                5499040,
+            ),
+            (
+                "45faf3a124b1edcf3e4f3599d2084217fb0a0288e8772602182c7c126ca042c9",
+                1598920200,
+                4389739,
+            ),
+        ]
+        .iter()
+        {
+            let header = BlockHeader {
+                version: 0x20000000,
+                prev_blockhash,
+                merkle_root: Sha256dHash::from_hex(merkle_root).unwrap(),
+                time: *time,
+                bits: 0x1e0377ae,
+                nonce: *nonce,
+            };
+            prev_blockhash = header.bitcoin_hash();
+            headers.push(LoneBlockHeader {
+                header,
+                tx_count: VarInt(0),
+            });
+        }
+        assert_eq!(
+            prev_blockhash,
+            Sha256dHash::from_hex(
+                "0000036bbb399ddafa5c4d528bbea3132ea9f5771848b39cc429ff14f5812d1e"
+            )
+            .unwrap()
+        );
+        assert!(headers[2].header.target() > SpvClient::max_target(BitcoinNetworkType::Mainnet));
+
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Signet, true, false).unwrap();
+        spv_client
+            .insert_block_headers_after(0, headers.clone())
+            .unwrap();
+        spv_client.validate_header_work(0, 1).unwrap();
+
+        // a header that misses the target is rejected
+        let mut bad_header = headers[2].clone();
+        bad_header.header.nonce += 1;
+        spv_client.drop_headers(2).unwrap();
+        spv_client
+            .insert_block_headers_after(2, vec![bad_header])
+            .unwrap();
+        match spv_client.validate_header_work(0, 1) {
+            Err(btc_error::InvalidPoW) => {}
+            x => panic!("Expected InvalidPoW, got {:?}", x),
+        }
+
+        // so is one mined at regtest's difficulty instead of signet's
+        let mut easy_header = headers[2].clone();
+        easy_header.header.bits = 0x207fffff;
+        while easy_header.header.bitcoin_hash().into_le() > easy_header.header.target() {
+            easy_header.header.nonce += 1;
+        }
+        spv_client.drop_headers(2).unwrap();
+        spv_client
+            .insert_block_headers_after(2, vec![easy_header])
+            .unwrap();
+        match spv_client.validate_header_work(0, 1) {
+            Err(btc_error::InvalidPoW) => {}
+            x => panic!("Expected InvalidPoW, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_spv_load_store_header() {
+        if fs::metadata("/tmp/test-spv-load_store_header.dat").is_ok() {
+            fs::remove_file("/tmp/test-spv-load_store_header.dat").unwrap();
+        }
+
+        let genesis_regtest_header = get_genesis_regtest_header();
+
+        let first_regtest_header = LoneBlockHeader {
+            header: BlockHeader {
+                bits: 545259519,
+                merkle_root: Sha256dHash::from_hex(
+                    "20bee96458517fc5082a9720ce6207b5742f2b18e4e0a7e7373342725d80f88c",
+                )
+                .unwrap(),
+                nonce: 2,
+                prev_blockhash: Sha256dHash::from_hex(
+                    "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+                )
+                .unwrap(),
+                time: 1587626881,
+                version: 0x20000000,
+            },
+            tx_count: VarInt(0),
+        };
+
+        let mut spv_client = SpvClient::new(
+            "/tmp/test-spv-load_store_header.dat",
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            true,
+            false,
+        )
+        .unwrap();
+        assert_eq!(spv_client.get_headers_height().unwrap(), 1);
+        assert_eq!(
+            spv_client.read_block_header(0).unwrap().unwrap(),
+            genesis_regtest_header
+        );
+
+        {
+            let mut tx = spv_client.tx_begin().unwrap();
+            SpvClient::insert_block_header(&mut tx, first_regtest_header.header.clone(), 1)
+                .unwrap();
+            tx.commit().unwrap();
+        }
+
+        assert_eq!(spv_client.get_headers_height().unwrap(), 2);
+        assert_eq!(
+            spv_client.read_block_header(0).unwrap().unwrap(),
+            genesis_regtest_header
+        );
+        assert_eq!(
+            spv_client.read_block_headers(0, 1).unwrap(),
+            vec![genesis_regtest_header.clone()]
+        );
+        assert_eq!(
+            spv_client.read_block_header(1).unwrap().unwrap(),
+            first_regtest_header
+        );
+        assert_eq!(
+            spv_client.read_block_headers(1, 2).unwrap(),
+            vec![first_regtest_header.clone()]
+        );
+        assert_eq!(
+            spv_client.read_block_headers(0, 10).unwrap(),
+            vec![genesis_regtest_header, first_regtest_header]
+        );
+    }
+
+    #[test]
+    fn test_spv_store_headers_after() {
+        if fs::metadata("/tmp/test-spv-store_headers_after.dat").is_ok() {
+            fs::remove_file("/tmp/test-spv-store_headers_after.dat").unwrap();
+        }
+        let genesis_regtest_header = get_genesis_regtest_header();
+        let headers = vec![
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "20bee96458517fc5082a9720ce6207b5742f2b18e4e0a7e7373342725d80f88c",
+                    )
+                    .unwrap(),
+                    nonce: 2,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+                    )
+                    .unwrap(),
+                    time: 1587626881,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "39d1a6f1ee7a5903797f92ec89e4c58549013f38114186fc2eb6e5218cb2d0ac",
+                    )
+                    .unwrap(),
+                    nonce: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "606d31daaaa5919f3720d8440dd99d31f2a4e4189c65879f19ae43268425e74b",
+                    )
+                    .unwrap(),
+                    time: 1587626882,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "a7e04ed25f589938eb5627abb7b5913dd77b8955bcdf72d7f111d0a71e346e47",
+                    )
+                    .unwrap(),
+                    nonce: 4,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "2fa2f451ac27f0e5cd3760ba6cdf34ef46adb76a44d96bc0f3bf3e713dd955f0",
+                    )
+                    .unwrap(),
+                    time: 1587626882,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+        ];
+
+        let mut spv_client = SpvClient::new(
+            "/tmp/test-spv-store_headers_after.dat",
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            true,
+            false,
+        )
+        .unwrap();
+        spv_client
+            .insert_block_headers_after(0, headers.clone())
+            .unwrap();
+
+        assert_eq!(spv_client.read_block_headers(1, 10).unwrap(), headers);
+
+        // should fail
+        if let Err(btc_error::NoncontiguousHeader) =
+            spv_client.insert_block_headers_after(1, headers.clone())
+        {
+        } else {
+            assert!(false);
+        }
+
+        // should fail
+        if let Err(btc_error::NoncontiguousHeader) =
+            spv_client.insert_block_headers_after(9, headers.clone())
+        {
+        } else {
+            assert!(false);
+        }
+
+        spv_client.drop_headers(1).unwrap();
+        assert_eq!(
+            spv_client.read_block_headers(0, 10).unwrap(),
+            vec![genesis_regtest_header, headers[0].clone()]
+        );
+    }
+
+    #[test]
+    fn test_spv_store_headers_before() {
+        if fs::metadata("/tmp/test-spv-store_headers_before.dat").is_ok() {
+            fs::remove_file("/tmp/test-spv-store_headers_before.dat").unwrap();
+        }
+        let genesis_regtest_header = get_genesis_regtest_header();
+        let headers = vec![
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "20bee96458517fc5082a9720ce6207b5742f2b18e4e0a7e7373342725d80f88c",
+                    )
+                    .unwrap(),
+                    nonce: 2,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+                    )
+                    .unwrap(),
+                    time: 1587626881,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "39d1a6f1ee7a5903797f92ec89e4c58549013f38114186fc2eb6e5218cb2d0ac",
+                    )
+                    .unwrap(),
+                    nonce: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "606d31daaaa5919f3720d8440dd99d31f2a4e4189c65879f19ae43268425e74b",
+                    )
+                    .unwrap(),
+                    time: 1587626882,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    bits: 545259519,
+                    merkle_root: Sha256dHash::from_hex(
+                        "a7e04ed25f589938eb5627abb7b5913dd77b8955bcdf72d7f111d0a71e346e47",
+                    )
+                    .unwrap(),
+                    nonce: 4,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "2fa2f451ac27f0e5cd3760ba6cdf34ef46adb76a44d96bc0f3bf3e713dd955f0",
+                    )
+                    .unwrap(),
+                    time: 1587626882,
+                    version: 0x20000000,
+                },
+                tx_count: VarInt(0),
+            },
+        ];
+
+        let mut spv_client = SpvClient::new(
+            "/tmp/test-spv-store_headers_before.dat",
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            true,
+            true,
+        )
+        .unwrap();
+        spv_client
+            .insert_block_headers_before(1, headers[1..].to_vec())
+            .unwrap();
+
+        assert_eq!(
+            spv_client.read_block_headers(2, 10).unwrap(),
+            headers[1..].to_vec()
+        );
+        assert_eq!(
+            spv_client.read_block_headers(0, 10).unwrap(),
+            vec![genesis_regtest_header.clone()]
+        ); // gap
+        assert_eq!(spv_client.read_block_headers(1, 10).unwrap(), vec![]); // gap
+
+        spv_client
+            .insert_block_headers_before(0, headers[0..1].to_vec())
+            .unwrap();
+        assert_eq!(spv_client.read_block_headers(1, 10).unwrap(), headers);
+
+        let mut all_headers = vec![genesis_regtest_header.clone()];
+        all_headers.append(&mut headers.clone());
+
+        assert_eq!(spv_client.read_block_headers(0, 10).unwrap(), all_headers);
+
+        // should succeed, since we only check that the last header connects
+        // to its child, if the child is stored at all
+        spv_client
+            .insert_block_headers_before(1, headers.clone())
+            .unwrap();
+        spv_client
+            .insert_block_headers_before(2, headers.clone())
+            .unwrap();
+
+        // should fail now, since there's a child to check
+        if let Err(btc_error::NoncontiguousHeader) =
+            spv_client.insert_block_headers_before(1, headers.clone())
+        {
+        } else {
+            assert!(false);
+        }
+
+        // should succeed
+        spv_client
+            .insert_block_headers_before(9, headers.clone())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_spv_check_pow() {
+        if !env::var("BLOCKSTACK_SPV_HEADERS_DB").is_ok() {
+            eprintln!("Skipping test_spv_check_pow -- no BLOCKSTACK_SPV_HEADERS_DB envar set");
+            return;
+        }
+        let db_path = env::var("BLOCKSTACK_SPV_HEADERS_DB").unwrap();
+        let spv_client =
+            SpvClient::new(&db_path, 0, None, BitcoinNetworkType::Mainnet, false, false).unwrap();
+
+        for i in 99..100 {
+            spv_client.validate_header_work(i, i + 1).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_spv_check_work_bad_blocks_rejected() {
+        if !env::var("BLOCKSTACK_SPV_HEADERS_DB").is_ok() {
+            eprintln!("Skipping test_spv_check_work_reorg_accepted -- no BLOCKSTACK_SPV_HEADERS_DB envar set");
+            return;
+        }
+        let db_path_source = env::var("BLOCKSTACK_SPV_HEADERS_DB").unwrap();
+        let db_path = "/tmp/test_spv_check_work_reorg_accepted.dat".to_string();
+
+        if fs::metadata(&db_path).is_ok() {
+            fs::remove_file(&db_path).unwrap();
+        }
+
+        fs::copy(&db_path_source, &db_path).unwrap();
+
+        // set up SPV client so we don't have chain work at first
+        let mut spv_client =
+            SpvClient::new(&db_path, 0, None, BitcoinNetworkType::Mainnet, true, false).unwrap();
+
+        assert!(
+            spv_client.get_headers_height().unwrap() >= 40317,
+            "This test needs headers up to 40317"
+        );
+        spv_client.drop_headers(40317).unwrap();
+
+        // update chain work
+        let total_work_before = spv_client.update_chain_work().unwrap();
+
+        // fake block headers for mainnet 40319-40320, which is on a difficulty adjustment boundary
+        let bad_headers = vec![
+            LoneBlockHeader {
+                header: BlockHeader {
+                    version: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "000000003ae696c44274e40817d4acaf40c1ff1853411d4f0573421caf5faa07",
+                    )
+                    .unwrap(),
+                    merkle_root: Sha256dHash::from_hex(
+                        "f1b2e16f74ee0e90cad3dc2e5be4806ff0581ed50a9cb3dfeee591dac76b17a7",
+                    )
+                    .unwrap(),
+                    time: 1654939798,
+                    bits: 386485098,
+                    nonce: 1,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    version: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "2c32da7fbdcef6ba34bcc5cc9707f2b351dbf094b4df5d3a2a38ea47b3e61f35",
+                    )
+                    .unwrap(),
+                    merkle_root: Sha256dHash::from_hex(
+                        "b4d736ca74838036ebd19b085c3eeb9ffec2307f6452347cdd8ddaa249686f39",
+                    )
+                    .unwrap(),
+                    time: 1654939798,
+                    bits: 486575299,
+                    nonce: 1,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    version: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "7c1d4fd424f626c13bcd5442db080df9000d8f3dbfa1809ba1b05ddcdba58dd5",
+                    )
+                    .unwrap(),
+                    merkle_root: Sha256dHash::from_hex(
+                        "cd71b0c247cfa777748c84d5376ad6a4a19e7802793dfd27c4d5834aa4393299",
+                    )
+                    .unwrap(),
+                    time: 1654996886,
+                    bits: 0x1d00ffff,
+                    nonce: 178162936,
+                },
+                tx_count: VarInt(0),
+            },
+            LoneBlockHeader {
+                header: BlockHeader {
+                    version: 1,
+                    prev_blockhash: Sha256dHash::from_hex(
+                        "000000008a8cab438b9c98599c5363c7a4eff5b246871dd7c3f46886da375170",
+                    )
+                    .unwrap(),
+                    merkle_root: Sha256dHash::from_hex(
+                        "cd71b0c247cfa777748c84d5376ad6a4a19e7802793dfd27c4d5834aa4393299",
+                    )
+                    .unwrap(),
+                    time: 1655018902,
+                    bits: 0x1d00ffff,
+                    nonce: 168408960,
+                },
+                tx_count: VarInt(0),
+            },
+        ];
+
+        // should fail
+        if let btc_error::InvalidPoW = spv_client
+            .handle_headers(40317, bad_headers.clone())
+            .unwrap_err()
+        {
+        } else {
+            panic!("Bad PoW headers accepted");
+        }
+    }
+
+    /// Make a run of fake testnet headers (no PoW) starting at `start_height`, one per `spacing`
+    /// seconds after `start_time`, with the given bits.
+    fn make_fake_testnet_headers(
+        start_height: u64,
+        count: u64,
+        start_time: u32,
+        spacing: u32,
+        bits: u32,
+    ) -> Vec<LoneBlockHeader> {
+        (0..count)
+            .map(|i| LoneBlockHeader {
+                header: BlockHeader {
+                    version: 0x20000000,
+                    prev_blockhash: Sha256dHash([0u8; 32]),
+                    merkle_root: Sha256dHash::from_data(&(start_height + i).to_be_bytes()),
+                    time: start_time + (i as u32) * spacing,
+                    bits,
+                    nonce: 0,
+                },
+                tx_count: VarInt(0),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_spv_testnet_min_difficulty_targets() {
+        let db_path = "/tmp/test_spv_testnet_min_difficulty_targets.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Testnet, true, false).unwrap();
+
+        let min_bits = 0x1d00ffff;
+        let normal_bits = 0x1c0fffff;
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Testnet);
+        let start_time = genesis.time + 600;
+
+        // first interval: 10-minute blocks at normal difficulty
+        let interval_1 = make_fake_testnet_headers(
+            1,
+            BLOCK_DIFFICULTY_CHUNK_SIZE - 1,
+            start_time,
+            600,
+            normal_bits,
+        );
+        spv_client.test_write_block_headers(1, interval_1).unwrap();
+        let first_header = spv_client.read_block_header(0).unwrap().unwrap();
+        let last_header = spv_client
+            .read_block_header(BLOCK_DIFFICULTY_CHUNK_SIZE - 1)
+            .unwrap()
+            .unwrap();
+        let last_time = last_header.header.time;
+
+        // retarget boundary: no 20-minute exception, even if the block is late
+        let (retarget_bits, retarget) =
+            SpvClient::get_target_between_headers(&first_header, &last_header);
+        assert!(retarget_bits != min_bits);
+        let late_boundary_header = make_fake_testnet_headers(
+            BLOCK_DIFFICULTY_CHUNK_SIZE,
+            1,
+            last_time + 3600,
+            600,
+            retarget_bits,
+        )[0]
+        .header;
+        assert_eq!(
+            spv_client
+                .get_target(
+                    BLOCK_DIFFICULTY_CHUNK_SIZE,
+                    &late_boundary_header,
+                    &VecDeque::new(),
+                    1
+                )
+                .unwrap(),
+            Some((retarget_bits, retarget))
+        );
+
+        // next interval: the retarget block, then two 20-minute min-difficulty blocks
+        let mut interval_2 = make_fake_testnet_headers(
+            BLOCK_DIFFICULTY_CHUNK_SIZE,
+            1,
+            last_time + 600,
+            600,
+            retarget_bits,
+        );
+        interval_2.append(&mut make_fake_testnet_headers(
+            BLOCK_DIFFICULTY_CHUNK_SIZE + 1,
+            2,
+            last_time + 600 + 1201,
+            1201,
+            min_bits,
+        ));
+        let mut headers_in_range: VecDeque<BlockHeader> = VecDeque::new();
+        for hdr in interval_2.iter() {
+            headers_in_range.push_front(hdr.header.clone());
+        }
+        let parent_time = interval_2.last().unwrap().header.time;
+        spv_client
+            .test_write_block_headers(BLOCK_DIFFICULTY_CHUNK_SIZE, interval_2)
+            .unwrap();
+
+        let next_height = BLOCK_DIFFICULTY_CHUNK_SIZE + 3;
+        let max_target = BlockHeader::compact_target_to_u256(min_bits);
+
+        // more than 20 minutes later: min-difficulty block allowed
+        let mut next_header =
+            make_fake_testnet_headers(next_height, 1, parent_time + 1201, 600, min_bits)[0].header;
+        assert_eq!(
+            spv_client
+                .get_target(next_height, &next_header, &headers_in_range, 1)
+                .unwrap(),
+            Some((min_bits, max_target))
+        );
+
+        // exactly 20 minutes later: must use the bits of the last non-min-difficulty block,
+        // whether or not the caller supplies the intermediate headers
+        next_header.time = parent_time + 1200;
+        next_header.bits = retarget_bits;
+        assert_eq!(
+            spv_client
+                .get_target(next_height, &next_header, &headers_in_range, 1)
+                .unwrap(),
+            Some((retarget_bits, retarget))
+        );
+        assert_eq!(
+            spv_client
+                .get_target(next_height, &next_header, &VecDeque::new(), 1)
+                .unwrap(),
+            Some((retarget_bits, retarget))
+        );
+
+        // if the retarget block itself was min-difficulty, the search stops there instead of
+        // looking into the previous interval
+        let mut boundary_header = spv_client
+            .read_block_header(BLOCK_DIFFICULTY_CHUNK_SIZE)
+            .unwrap()
+            .unwrap();
+        boundary_header.header.bits = min_bits;
+        headers_in_range.pop_back();
+        headers_in_range.push_back(boundary_header.header.clone());
+        spv_client
+            .test_write_block_headers(BLOCK_DIFFICULTY_CHUNK_SIZE, vec![boundary_header])
+            .unwrap();
+        assert_eq!(
+            spv_client
+                .get_target(next_height, &next_header, &headers_in_range, 1)
+                .unwrap(),
+            Some((min_bits, max_target))
+        );
+        assert_eq!(
+            spv_client
+                .get_target(next_height, &next_header, &VecDeque::new(), 1)
+                .unwrap(),
+            Some((min_bits, max_target))
+        );
+
+        // mainnet never gets the min-difficulty exception
+        let mainnet_db_path = "/tmp/test_spv_testnet_min_difficulty_targets_mainnet.dat";
+        if fs::metadata(mainnet_db_path).is_ok() {
+            fs::remove_file(mainnet_db_path).unwrap();
+        }
+        let mut mainnet_spv_client = SpvClient::new(
+            mainnet_db_path,
+            0,
+            None,
+            BitcoinNetworkType::Mainnet,
+            true,
+            false,
+        )
+        .unwrap();
+        let mainnet_headers = make_fake_testnet_headers(
+            1,
+            BLOCK_DIFFICULTY_CHUNK_SIZE + 2,
+            start_time,
+            600,
+            normal_bits,
+        );
+        mainnet_spv_client
+            .test_write_block_headers(1, mainnet_headers)
+            .unwrap();
+        let mainnet_first_header = mainnet_spv_client.read_block_header(0).unwrap().unwrap();
+        let mainnet_last_header = mainnet_spv_client
+            .read_block_header(BLOCK_DIFFICULTY_CHUNK_SIZE - 1)
+            .unwrap()
+            .unwrap();
+        next_header.time = parent_time + 3600;
+        assert_eq!(
+            mainnet_spv_client
+                .get_target(
+                    BLOCK_DIFFICULTY_CHUNK_SIZE + 3,
+                    &next_header,
+                    &VecDeque::new(),
+                    1
+                )
+                .unwrap(),
+            Some(SpvClient::get_target_between_headers(
+                &mainnet_first_header,
+                &mainnet_last_header
+            ))
+        );
+    }
+
+    /// The first blocks of the real testnet3 chain after its genesis block.
+    /// TODO: add real headers on either side of a retarget boundary, and a real min-difficulty
+    /// (20-minute rule) header.  They must be copied from a testnet3 node; until then, those
+    /// rules are only checked against synthetic headers (see
+    /// `test_spv_testnet_min_difficulty_targets()`).
+    const TESTNET3_HEADERS: [&str; 2] = [
+        "0100000043497fd7f826957108f4a30fd9cec3aeba79972084e90ead01ea330900000000bac8b0fa927c0ac8234287e33c5f74d38d354820e24756ad709d7038fc5f31f020e7494dffff001d03e4b672",
+        "0100000006128e87be8b1b4dea47a7247d5528d2702c96826c7a648497e773b800000000e241352e3bec0a95a6217e10c3abb54adfa05abb12c126695595580fb92e222032e7494dffff001d00d23534",
+    ];
+
+    #[test]
+    fn test_spv_check_pow_testnet3_headers() {
+        let db_path = "/tmp/test_spv_check_pow_testnet3_headers.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Testnet, true, false).unwrap();
+
+        let headers: Vec<LoneBlockHeader> = TESTNET3_HEADERS
+            .iter()
+            .map(|header_hex| LoneBlockHeader {
+                header: deserialize(&hex_bytes(header_hex).unwrap()).unwrap(),
+                tx_count: VarInt(0),
+            })
+            .collect();
+        assert_eq!(
+            headers[1].header.bitcoin_hash().to_string(),
+            "000000006c02c8ea6e4ff69651f7fcde348fb9d557a06e6957b65552002a7820"
+        );
+
+        // the real headers pass every testnet rule, proof-of-work included
+        spv_client.handle_headers(0, headers.clone()).unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 2);
+        spv_client.validate_header_work(0, 1).unwrap();
+
+        // and any change to one breaks its proof-of-work
+        let bad_db_path = "/tmp/test_spv_check_pow_testnet3_headers_bad.dat";
+        if fs::metadata(bad_db_path).is_ok() {
+            fs::remove_file(bad_db_path).unwrap();
+        }
+        let mut bad_spv_client = SpvClient::new(
+            bad_db_path,
+            0,
+            None,
+            BitcoinNetworkType::Testnet,
+            true,
+            false,
+        )
+        .unwrap();
+        let mut bad_headers = headers;
+        bad_headers[1].header.nonce += 1;
+        match bad_spv_client.handle_headers(0, bad_headers) {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("expected InvalidPoW, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_spv_check_pow_testnet() {
+        if !env::var("BLOCKSTACK_SPV_TESTNET_HEADERS_DB").is_ok() {
+            eprintln!("Skipping test_spv_check_pow_testnet -- no BLOCKSTACK_SPV_TESTNET_HEADERS_DB envar set");
+            return;
+        }
+        let db_path = env::var("BLOCKSTACK_SPV_TESTNET_HEADERS_DB").unwrap();
+        let spv_client =
+            SpvClient::new(&db_path, 0, None, BitcoinNetworkType::Testnet, false, false).unwrap();
+
+        // testnet3 intervals spanning retargets that follow a min-difficulty block, and
+        // 20-minute min-difficulty resets within an interval
+        for i in 1..4 {
+            spv_client.validate_header_work(i, i + 1).unwrap();
+        }
+    }
+
+    /// Make a chain of `count` headers on top of the given network's genesis block, without
+    /// mining them (so they almost certainly don't meet any real difficulty target).
+    fn make_unmined_headers(network_id: BitcoinNetworkType, count: u64) -> Vec<LoneBlockHeader> {
+        let genesis = SpvClient::get_genesis_header(network_id);
+        make_unmined_headers_after(&genesis, count, 0, 0x1d00ffff)
+    }
+
+    /// Make a chain of `count` unmined headers on top of `parent`.  Use a different `branch` to
+    /// get a different chain on top of the same parent.
+    fn make_unmined_headers_after(
+        parent: &BlockHeader,
+        count: u64,
+        branch: u64,
+        bits: u32,
+    ) -> Vec<LoneBlockHeader> {
+        let mut prev_blockhash = parent.bitcoin_hash();
+        let mut headers = vec![];
+        for i in 1..(count + 1) {
+            let mut merkle_data = branch.to_be_bytes().to_vec();
+            merkle_data.extend_from_slice(&i.to_be_bytes());
+            let header = BlockHeader {
+                version: 0x20000000,
+                prev_blockhash,
+                merkle_root: Sha256dHash::from_data(&merkle_data),
+                time: parent.time + (i as u32) * 700,
+                bits,
+                nonce: 0,
+            };
+            prev_blockhash = header.bitcoin_hash();
+            headers.push(LoneBlockHeader {
+                header,
+                tx_count: VarInt(0),
+            });
+        }
+        headers
+    }
+
+    #[test]
+    fn test_spv_regtest_skips_pow() {
+        // work is only checked from the first full difficulty interval before the insertion
+        // height onwards, so insert headers in two batches to check across a retarget.
+        let num_headers = BLOCK_DIFFICULTY_CHUNK_SIZE + 5;
+        let split = (BLOCK_DIFFICULTY_CHUNK_SIZE + 1) as usize;
+
+        let db_path = "/tmp/test_spv_regtest_skips_pow.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+
+        let headers = make_unmined_headers(BitcoinNetworkType::Regtest, num_headers);
+        spv_client
+            .handle_headers(0, headers[..split].to_vec())
+            .unwrap();
+        spv_client
+            .handle_headers(split as u64, headers[split..].to_vec())
+            .unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), num_headers);
+        assert_eq!(
+            spv_client
+                .read_block_header(num_headers)
+                .unwrap()
+                .unwrap()
+                .header,
+            headers.last().unwrap().header
+        );
+
+        // linkage is still checked
+        let mut bad_headers = make_unmined_headers(BitcoinNetworkType::Regtest, 3);
+        bad_headers[1].header.prev_blockhash = Sha256dHash([0x11; 32]);
+        let bad_db_path = "/tmp/test_spv_regtest_skips_pow_noncontiguous.dat";
+        if fs::metadata(bad_db_path).is_ok() {
+            fs::remove_file(bad_db_path).unwrap();
+        }
+        let mut bad_spv_client = SpvClient::new(
+            bad_db_path,
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            true,
+            false,
+        )
+        .unwrap();
+        assert!(bad_spv_client.handle_headers(0, bad_headers).is_err());
+
+        // the same headers on testnet are rejected
+        let testnet_db_path = "/tmp/test_spv_regtest_skips_pow_testnet.dat";
+        if fs::metadata(testnet_db_path).is_ok() {
+            fs::remove_file(testnet_db_path).unwrap();
+        }
+        let mut testnet_spv_client = SpvClient::new(
+            testnet_db_path,
+            0,
+            None,
+            BitcoinNetworkType::Testnet,
+            true,
+            false,
+        )
+        .unwrap();
+        testnet_spv_client.set_checkpoints(vec![]);
+        let testnet_headers = make_unmined_headers(BitcoinNetworkType::Testnet, num_headers);
+        match testnet_spv_client.handle_headers(0, testnet_headers[..split].to_vec()) {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("Unmined testnet headers not rejected: {:?}", res),
+        }
+        testnet_spv_client
+            .test_write_block_headers(1, testnet_headers[..split].to_vec())
+            .unwrap();
+        match testnet_spv_client.handle_headers(split as u64, testnet_headers[split..].to_vec()) {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("Unmined testnet headers not rejected: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_spv_find_insufficient_work() {
+        let headers = make_unmined_headers(BitcoinNetworkType::Testnet, 1000);
+        let mut pow_batch: Vec<_> = headers
+            .iter()
+            .enumerate()
+            .map(|(i, hdr)| ((i as u64) + 1, hdr.header.clone(), Uint256::max()))
+            .collect();
+
+        for num_threads in [1, 2, 4, 7, 64].iter() {
+            assert_eq!(
+                SpvClient::find_insufficient_work(pow_batch.clone(), *num_threads),
+                None
+            );
+        }
+
+        // no hash meets a zero target.  The first bad header is found no matter how the batch is
+        // split up.
+        pow_batch[700].2 = Uint256::from_u64(0);
+        pow_batch[299].2 = Uint256::from_u64(0);
+        pow_batch[999].2 = Uint256::from_u64(0);
+        for num_threads in [1, 2, 4, 7, 64].iter() {
+            assert_eq!(
+                SpvClient::find_insufficient_work(pow_batch.clone(), *num_threads),
+                Some(300)
+            );
+        }
+        assert_eq!(
+            SpvClient::find_insufficient_work(pow_batch[301..].to_vec(), 4),
+            Some(701)
+        );
+        assert_eq!(SpvClient::find_insufficient_work(vec![], 4), None);
+    }
+
+    #[test]
+    fn test_spv_assume_valid() {
+        let headers = make_unmined_headers(BitcoinNetworkType::Testnet, 20);
+        let assume_valid_hash =
+            BurnchainHeaderHash::from_bitcoin_hash(&headers[9].header.bitcoin_hash());
+        let new_testnet_client = |db_path: &str| {
+            if fs::metadata(db_path).is_ok() {
+                fs::remove_file(db_path).unwrap();
+            }
+            let mut spv_client =
+                SpvClient::new(db_path, 0, None, BitcoinNetworkType::Testnet, true, false).unwrap();
+            spv_client.set_checkpoints(vec![]);
+            spv_client
+        };
+
+        // unmined headers are only accepted up to the assume-valid height
+        let mut spv_client = new_testnet_client("/tmp/test_spv_assume_valid.dat");
+        spv_client.set_assume_valid(Some((10, assume_valid_hash.clone())));
+        spv_client
+            .handle_headers(0, headers[..10].to_vec())
+            .unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 10);
+        match spv_client.handle_headers(10, headers[10..].to_vec()) {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("Unmined testnet headers not rejected: {:?}", res),
+        }
+
+        // the header at the assume-valid height must have the expected hash
+        let mut spv_client = new_testnet_client("/tmp/test_spv_assume_valid_mismatch.dat");
+        spv_client.set_assume_valid(Some((
+            10,
+            BurnchainHeaderHash::from_bitcoin_hash(&headers[8].header.bitcoin_hash()),
+        )));
+        match spv_client.handle_headers(0, headers[..15].to_vec()) {
+            Err(btc_error::AssumeValidMismatch(10)) => {}
+            res => panic!("Wrong assume-valid header not rejected: {:?}", res),
+        }
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 0);
+
+        // by default, every header is checked
+        let mut spv_client = new_testnet_client("/tmp/test_spv_assume_valid_unset.dat");
+        match spv_client.handle_headers(0, headers[..10].to_vec()) {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("Unmined testnet headers not rejected: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_spv_assume_valid_checkpoints() {
+        let headers = make_unmined_headers(BitcoinNetworkType::Testnet, 20);
+        let new_testnet_client = |db_path: &str, checkpoint_hash: &Sha256dHash| {
+            if fs::metadata(db_path).is_ok() {
+                fs::remove_file(db_path).unwrap();
+            }
+            let mut spv_client =
+                SpvClient::new(db_path, 0, None, BitcoinNetworkType::Testnet, true, false).unwrap();
+            spv_client.set_checkpoints(vec![(
+                10,
+                BurnchainHeaderHash::from_bitcoin_hash(checkpoint_hash),
+            )]);
+            spv_client.set_assume_valid_checkpoints(true);
+            spv_client
+        };
+
+        // unmined headers are only accepted up to the highest checkpoint
+        let mut spv_client = new_testnet_client(
+            "/tmp/test_spv_assume_valid_checkpoints.dat",
+            &headers[9].header.bitcoin_hash(),
+        );
+        spv_client
+            .handle_headers(0, headers[..10].to_vec())
+            .unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 10);
+        match spv_client.handle_headers(10, headers[10..].to_vec()) {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("Unmined testnet headers not rejected: {:?}", res),
+        }
+
+        // the header at the checkpoint height must still match it
+        let mut spv_client = new_testnet_client(
+            "/tmp/test_spv_assume_valid_checkpoints_mismatch.dat",
+            &headers[8].header.bitcoin_hash(),
+        );
+        match spv_client.handle_headers(0, headers[..15].to_vec()) {
+            Err(btc_error::CheckpointMismatch(10)) => {}
+            res => panic!("Wrong checkpoint header not rejected: {:?}", res),
+        }
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 0);
+
+        // by default, every header is checked
+        let mut spv_client = new_testnet_client(
+            "/tmp/test_spv_assume_valid_checkpoints_unset.dat",
+            &headers[9].header.bitcoin_hash(),
+        );
+        spv_client.set_assume_valid_checkpoints(false);
+        match spv_client.handle_headers(0, headers[..10].to_vec()) {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("Unmined testnet headers not rejected: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_spv_median_time_past() {
+        let db_path = "/tmp/test_spv_median_time_past.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+
+        // timestamps (after genesis) that often go backwards, but always exceed the parent's
+        // median-time-past
+        let offsets: [u32; 20] = [
+            600, 1200, 900, 2400, 1800, 3000, 2700, 4200, 3600, 3300, 6000, 5400, 7200, 6600, 6300,
+            9000, 8400, 10800, 9600, 10200,
+        ];
+        let mut headers = make_unmined_headers_after(&genesis, 20, 0, genesis.bits);
+        let mut prev_blockhash = genesis.bitcoin_hash();
+        for (hdr, offset) in headers.iter_mut().zip(offsets.iter()) {
+            hdr.header.time = genesis.time + offset;
+            hdr.header.prev_blockhash = prev_blockhash;
+            prev_blockhash = hdr.header.bitcoin_hash();
+        }
+
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        spv_client.handle_headers(0, headers.clone()).unwrap();
+
+        let mut timestamps = vec![genesis.time];
+        timestamps.extend(headers.iter().map(|hdr| hdr.header.time));
+
+        let mut last_median_time_past = 0;
+        for height in 0..21 {
+            let times = spv_client.read_block_times(height).unwrap().unwrap();
+            assert_eq!(times.timestamp, timestamps[height as usize]);
+
+            let mut window =
+                timestamps[(height.saturating_sub(10) as usize)..((height + 1) as usize)].to_vec();
+            window.sort();
+            assert_eq!(times.median_time_past, window[window.len() / 2]);
+            assert_eq!(
+                spv_client.median_time_past(height).unwrap(),
+                Some(times.median_time_past)
+            );
+
+            assert!(times.median_time_past >= last_median_time_past);
+            last_median_time_past = times.median_time_past;
+        }
+
+        // near genesis, fewer than 11 headers are taken into account
+        assert_eq!(spv_client.median_time_past(0).unwrap(), Some(genesis.time));
+        assert_eq!(
+            spv_client.median_time_past(1).unwrap(),
+            Some(genesis.time + 600)
+        );
+        assert_eq!(
+            spv_client.median_time_past(2).unwrap(),
+            Some(genesis.time + 600)
+        );
+
+        // header 3's timestamp goes backwards, but its median-time-past doesn't
+        let times_2 = spv_client.read_block_times(2).unwrap().unwrap();
+        let times_3 = spv_client.read_block_times(3).unwrap().unwrap();
+        assert!(times_3.timestamp < times_2.timestamp);
+        assert!(times_3.median_time_past >= times_2.median_time_past);
+
+        assert_eq!(spv_client.read_block_times(21).unwrap(), None);
+        assert_eq!(spv_client.median_time_past(21).unwrap(), None);
+    }
+
+    #[test]
+    fn test_spv_header_timestamps() {
+        let new_client = |db_path: &str, network_id: BitcoinNetworkType| {
+            if fs::metadata(db_path).is_ok() {
+                fs::remove_file(db_path).unwrap();
+            }
+            SpvClient::new(db_path, 0, None, network_id, true, false).unwrap()
+        };
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let relink = |headers: &mut Vec<LoneBlockHeader>| {
+            let mut prev_blockhash = genesis.bitcoin_hash();
+            for hdr in headers.iter_mut() {
+                hdr.header.prev_blockhash = prev_blockhash;
+                prev_blockhash = hdr.header.bitcoin_hash();
+            }
+        };
+
+        // the start of the real mainnet chain is fine
+        let mainnet_headers = get_mainnet_headers_after_genesis();
+        let mut spv_client = new_client(
+            "/tmp/test_spv_header_timestamps_mainnet.dat",
+            BitcoinNetworkType::Mainnet,
+        );
+        spv_client.handle_headers(0, mainnet_headers).unwrap();
+        assert_eq!(
+            spv_client
+                .read_block_header(5)
+                .unwrap()
+                .unwrap()
+                .header
+                .bitcoin_hash(),
+            Sha256dHash::from_hex(
+                "000000009b7262315dbf071787ad3656097b892abffd1f95a1a022f896f533fc"
+            )
+            .unwrap()
+        );
+
+        // header 15 is timestamped at the median of headers 4-14
+        let mut headers = make_unmined_headers_after(&genesis, 20, 0, genesis.bits);
+        headers[14].header.time = headers[8].header.time;
+        relink(&mut headers);
+        let mut spv_client = new_client(
+            "/tmp/test_spv_header_timestamps_median.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        match spv_client.handle_headers(0, headers.clone()) {
+            Err(btc_error::TimestampTooOld(15)) => {}
+            res => panic!("Header at the median time past not rejected: {:?}", res),
+        }
+
+        // one second later is fine, even though it's earlier than its parent
+        headers[14].header.time = headers[8].header.time + 1;
+        relink(&mut headers);
+        spv_client.handle_headers(0, headers).unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 20);
+
+        // header 20 is timestamped more than two hours from now
+        let mut headers = make_unmined_headers_after(&genesis, 20, 1, genesis.bits);
+        headers[19].header.time = (get_epoch_time_secs() + MAX_FUTURE_BLOCK_TIME + 60) as u32;
+        relink(&mut headers);
+        let mut spv_client = new_client(
+            "/tmp/test_spv_header_timestamps_future.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        match spv_client.handle_headers(0, headers.clone()) {
+            Err(btc_error::TimestampTooNew(20)) => {}
+            res => panic!("Header from the future not rejected: {:?}", res),
+        }
+
+        // an hour from now is fine
+        headers[19].header.time = (get_epoch_time_secs() + 3600) as u32;
+        relink(&mut headers);
+        spv_client.handle_headers(0, headers).unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 20);
+
+        // headers far below the tip aren't checked against our clock
+        let mut headers = make_unmined_headers_after(
+            &genesis,
+            FUTURE_BLOCK_TIME_CHECK_DEPTH + 20,
+            2,
+            genesis.bits,
+        );
+        headers[9].header.time = (get_epoch_time_secs() + 86400) as u32;
+        relink(&mut headers);
+        let mut spv_client = new_client(
+            "/tmp/test_spv_header_timestamps_future_buried.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        spv_client.handle_headers(0, headers).unwrap();
+        assert_eq!(
+            spv_client.get_highest_header_height().unwrap(),
+            FUTURE_BLOCK_TIME_CHECK_DEPTH + 20
+        );
+    }
+
+    #[test]
+    fn test_spv_verify_retarget() {
+        let db_path = "/tmp/test_spv_verify_retarget.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Mainnet, true, false).unwrap();
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Mainnet);
+
+        // a first interval mined more than 4x faster than the target spacing
+        let mut headers =
+            make_unmined_headers_after(&genesis, BLOCK_DIFFICULTY_CHUNK_SIZE - 1, 0, genesis.bits);
+        let mut prev_blockhash = genesis.bitcoin_hash();
+        for (i, hdr) in headers.iter_mut().enumerate() {
+            hdr.header.time = genesis.time + 100 * (i as u32 + 1);
+            hdr.header.prev_blockhash = prev_blockhash;
+            prev_blockhash = hdr.header.bitcoin_hash();
+        }
+        spv_client
+            .test_write_block_headers(1, headers.clone())
+            .unwrap();
+
+        // first interval must use the genesis target
+        assert_eq!(
+            spv_client
+                .verify_retarget(5, &headers[4].header, &VecDeque::new())
+                .unwrap(),
+            Some(genesis.target())
+        );
+        let mut bad_header = headers[4].header;
+        bad_header.bits = 0x1c3fffc0;
+        match spv_client.verify_retarget(5, &bad_header, &VecDeque::new()) {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("Wrong first-interval bits not rejected: {:?}", res),
+        }
+
+        // retarget is clamped to a quarter of the previous target
+        let retarget_bits = 0x1c3fffc0;
+        let retarget_target = BlockHeader::compact_target_to_u256(retarget_bits);
+        assert_eq!(retarget_target, genesis.target() / Uint256::from_u64(4));
+
+        let mut retarget_headers =
+            make_unmined_headers_after(&headers.last().unwrap().header, 2, 0, retarget_bits);
+        assert_eq!(
+            spv_client
+                .verify_retarget(
+                    BLOCK_DIFFICULTY_CHUNK_SIZE,
+                    &retarget_headers[0].header,
+                    &VecDeque::new()
+                )
+                .unwrap(),
+            Some(retarget_target)
+        );
+
+        // no retarget at the boundary
+        let mut bad_header = retarget_headers[0].header;
+        bad_header.bits = genesis.bits;
+        match spv_client.verify_retarget(BLOCK_DIFFICULTY_CHUNK_SIZE, &bad_header, &VecDeque::new())
+        {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("Missing retarget not rejected: {:?}", res),
+        }
+
+        // can't verify the next block without its parent
+        assert_eq!(
+            spv_client
+                .verify_retarget(
+                    BLOCK_DIFFICULTY_CHUNK_SIZE + 1,
+                    &retarget_headers[1].header,
+                    &VecDeque::new()
+                )
+                .unwrap(),
+            None
+        );
+
+        spv_client
+            .test_write_block_headers(BLOCK_DIFFICULTY_CHUNK_SIZE, retarget_headers[..1].to_vec())
+            .unwrap();
+
+        // target stays constant within the interval
+        assert_eq!(
+            spv_client
+                .verify_retarget(
+                    BLOCK_DIFFICULTY_CHUNK_SIZE + 1,
+                    &retarget_headers[1].header,
+                    &VecDeque::new()
+                )
+                .unwrap(),
+            Some(retarget_target)
+        );
+
+        // mainnet has no 20-minute rule
+        retarget_headers[1].header.time += 3600;
+        retarget_headers[1].header.bits = genesis.bits;
+        match spv_client.verify_retarget(
+            BLOCK_DIFFICULTY_CHUNK_SIZE + 1,
+            &retarget_headers[1].header,
+            &VecDeque::new(),
+        ) {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("Mid-interval target change not rejected: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_spv_retarget_clamps() {
+        let mut first_header = LoneBlockHeader {
+            header: SpvClient::get_genesis_header(BitcoinNetworkType::Mainnet),
+            tx_count: VarInt(0),
+        };
+        let mut last_header = first_header.clone();
+        first_header.header.time = 1_600_000_000;
+        last_header.header.bits = 0x1b0404cb;
+        let last_target = last_header.header.target();
+
+        // a ten-week interval only lowers the difficulty by 4x
+        last_header.header.time = first_header.header.time + 10 * BLOCK_DIFFICULTY_INTERVAL;
+        let (bits, target) = SpvClient::get_target_between_headers(&first_header, &last_header);
+        assert_eq!(bits, 0x1b10132c);
+        assert_eq!(target, last_target * Uint256::from_u64(4));
+
+        // a one-day interval only raises the difficulty by 4x
+        last_header.header.time = first_header.header.time + 86400;
+        let (bits, target) = SpvClient::get_target_between_headers(&first_header, &last_header);
+        let expected_target = last_target / Uint256::from_u64(4);
+        assert_eq!(
+            bits,
+            BlockHeader::compact_target_from_u256(&expected_target)
+        );
+        assert_eq!(target, BlockHeader::compact_target_to_u256(bits));
+
+        // a two-week interval doesn't change the difficulty
+        last_header.header.time = first_header.header.time + BLOCK_DIFFICULTY_INTERVAL;
+        let (bits, target) = SpvClient::get_target_between_headers(&first_header, &last_header);
+        assert_eq!(bits, 0x1b0404cb);
+        assert_eq!(target, last_target);
+
+        // the difficulty never drops below the minimum
+        last_header.header.bits = 0x1d00ffff;
+        last_header.header.time = first_header.header.time + 3 * BLOCK_DIFFICULTY_INTERVAL;
+        let (bits, _) = SpvClient::get_target_between_headers(&first_header, &last_header);
+        assert_eq!(bits, 0x1d00ffff);
+
+        // ...but signet's minimum is lower, so a slow signet interval keeps getting easier
+        let (bits, target) = SpvClient::get_network_target_between_headers(
+            BitcoinNetworkType::Signet,
+            &first_header,
+            &last_header,
+        );
+        assert_eq!(bits, 0x1d02fffd);
+        assert_eq!(target, last_header.header.target() * Uint256::from_u64(3));
+
+        last_header.header.bits = 0x1e0377ae;
+        let (bits, _) = SpvClient::get_network_target_between_headers(
+            BitcoinNetworkType::Signet,
+            &first_header,
+            &last_header,
+        );
+        assert_eq!(bits, 0x1e0377ae);
+    }
+
+    /// TODO: embed the real mainnet headers at 32255-32257 (and the interval start, 30240) so
+    /// this runs without a synced headers DB.  They must be copied from a mainnet node.
+    #[test]
+    fn test_spv_verify_retarget_mainnet() {
+        if !env::var("BLOCKSTACK_SPV_HEADERS_DB").is_ok() {
+            eprintln!(
+                "Skipping test_spv_verify_retarget_mainnet -- no BLOCKSTACK_SPV_HEADERS_DB envar set"
+            );
+            return;
+        }
+        let db_path = env::var("BLOCKSTACK_SPV_HEADERS_DB").unwrap();
+        let spv_client =
+            SpvClient::new(&db_path, 0, None, BitcoinNetworkType::Mainnet, false, false).unwrap();
+
+        // block 32256 is the first mainnet retarget that raised the difficulty
+        let first_retarget_height = 16 * BLOCK_DIFFICULTY_CHUNK_SIZE;
+        assert!(
+            spv_client.get_highest_header_height().unwrap() > first_retarget_height + 1,
+            "This test needs headers up to {}",
+            first_retarget_height + 1
+        );
+
+        for height in (first_retarget_height - 1)..(first_retarget_height + 2) {
+            let header = spv_client
+                .read_block_header(height)
+                .unwrap()
+                .unwrap()
+                .header;
+            let target = spv_client
+                .verify_retarget(height, &header, &VecDeque::new())
+                .unwrap()
+                .unwrap();
+            assert_eq!(target, header.target());
+            if height < first_retarget_height {
+                assert_eq!(header.bits, 0x1d00ffff);
+            } else {
+                assert_eq!(header.bits, 0x1d00d86a);
+            }
+
+            // the pre-retarget difficulty is rejected after the boundary, and vice versa
+            let mut bad_header = header;
+            bad_header.bits = if height < first_retarget_height {
+                0x1d00d86a
+            } else {
+                0x1d00ffff
+            };
+            match spv_client.verify_retarget(height, &bad_header, &VecDeque::new()) {
+                Err(btc_error::InvalidPoW) => {}
+                res => panic!("Wrong bits at {} not rejected: {:?}", height, res),
+            }
+        }
+
+        // every retarget in the first 100 intervals checks out
+        for interval in 1..100 {
+            let height = interval * BLOCK_DIFFICULTY_CHUNK_SIZE;
+            let header = spv_client
+                .read_block_header(height)
+                .unwrap()
+                .unwrap()
+                .header;
+            spv_client
+                .verify_retarget(height, &header, &VecDeque::new())
+                .unwrap()
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_spv_reorg_to_higher_work_branch() {
+        let db_path = "/tmp/test_spv_reorg_to_higher_work_branch.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+
+        // our chain: 5 blocks
+        let chain_a = make_unmined_headers_after(&genesis, 5, 0, genesis.bits);
+        spv_client.handle_headers(0, chain_a.clone()).unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 5);
+        let work_a = spv_client.update_chain_work().unwrap();
+
+        // a competing branch that forks off of block 2, but is shorter than ours
+        let chain_b = make_unmined_headers_after(&chain_a[1].header, 2, 1, genesis.bits);
+        match spv_client.handle_headers(5, chain_b) {
+            Err(btc_error::NoncontiguousHeader) => {}
+            res => panic!("Lower-work branch not rejected: {:?}", res),
+        }
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 5);
+        assert_eq!(
+            spv_client.read_block_header(5).unwrap().unwrap().header,
+            chain_a[4].header
+        );
+        assert_eq!(spv_client.get_reorg_height(), None);
+
+        // a competing branch that forks off of block 2 and is longer than ours.  The peer
+        // doesn't know our tip, so it re-sends blocks 1 and 2 as well.
+        let chain_c = make_unmined_headers_after(&chain_a[1].header, 4, 2, genesis.bits);
+        let mut peer_headers = chain_a[0..2].to_vec();
+        peer_headers.extend_from_slice(&chain_c);
+
+        assert_eq!(spv_client.handle_headers(5, peer_headers).unwrap(), Some(2));
+        assert_eq!(spv_client.get_reorg_height(), Some(2));
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 6);
+        for (i, hdr) in chain_a[0..2].iter().chain(chain_c.iter()).enumerate() {
+            assert_eq!(
+                spv_client
+                    .read_block_header((i as u64) + 1)
+                    .unwrap()
+                    .unwrap()
+                    .header,
+                hdr.header
+            );
+        }
+        assert!(spv_client.update_chain_work().unwrap() > work_a);
+
+        // new headers build on the new branch
+        let chain_d = make_unmined_headers_after(&chain_c[3].header, 2, 3, genesis.bits);
+        assert_eq!(spv_client.handle_headers(6, chain_d).unwrap(), None);
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 8);
+        assert_eq!(spv_client.get_reorg_height(), Some(2));
+    }
+
+    #[test]
+    fn test_spv_torn_header_batch_recovery() {
+        let db_path = "/tmp/test_spv_torn_header_batch_recovery.dat";
+        let crash_path = "/tmp/test_spv_torn_header_batch_recovery_crash.dat";
+        let remove_db = |path: &str| {
+            for suffix in ["", "-wal", "-shm"] {
+                let path = format!("{}{}", path, suffix);
+                if fs::metadata(&path).is_ok() {
+                    fs::remove_file(&path).unwrap();
+                }
+            }
+        };
+        remove_db(db_path);
+
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let headers = make_unmined_headers_after(&genesis, 2010, 0, genesis.bits);
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        spv_client.set_fsync_interval(1, Duration::from_millis(0));
+
+        // the first batch is synced as soon as it's written...
+        spv_client
+            .insert_block_headers_after(0, headers[..10].to_vec())
+            .unwrap();
+
+        // ...but the second one is only in the write-ahead log when we "crash"
+        spv_client.set_fsync_interval(0, Duration::from_millis(0));
+        spv_client
+            .insert_block_headers_after(10, headers[10..].to_vec())
+            .unwrap();
+
+        let db_bytes = fs::read(db_path).unwrap();
+        let wal_bytes = fs::read(format!("{}-wal", db_path)).unwrap();
+        let wal_len = wal_bytes.len();
+        assert!(wal_len > 0);
+
+        // lose the end of the log at various points, including mid-header and mid-page
+        for torn_len in [
+            0,
+            17,
+            32,
+            1000,
+            wal_len / 3,
+            wal_len / 2,
+            wal_len - 1,
+            wal_len,
+        ] {
+            remove_db(crash_path);
+            fs::write(crash_path, &db_bytes).unwrap();
+            fs::write(format!("{}-wal", crash_path), &wal_bytes[..torn_len]).unwrap();
+
+            let recovered = SpvClient::new(
+                crash_path,
+                0,
+                None,
+                BitcoinNetworkType::Regtest,
+                true,
+                false,
+            )
+            .unwrap();
+            let height = recovered.get_highest_header_height().unwrap();
+            if torn_len == wal_len {
+                assert_eq!(height, 2010);
+            } else {
+                assert_eq!(height, 10, "torn at {} of {}", torn_len, wal_len);
+            }
+            assert_eq!(
+                recovered.read_block_headers(1, height + 1).unwrap(),
+                headers[..(height as usize)].to_vec()
+            );
+        }
+    }
+
+    /// Serves `headers` (as heights 1 and up), 2000 at a time
+    struct MockHeaderSource {
+        headers: Vec<LoneBlockHeader>,
+    }
+
+    impl HeaderSource for MockHeaderSource {
+        fn get_tip_height(&mut self) -> Result<u64, btc_error> {
+            Ok(self.headers.len() as u64)
+        }
+
+        fn get_headers(
+            &mut self,
+            locator: Vec<Sha256dHash>,
+        ) -> Result<Vec<LoneBlockHeader>, btc_error> {
+            let start = locator
+                .iter()
+                .find_map(|hash| {
+                    self.headers
+                        .iter()
+                        .position(|hdr| hdr.header.bitcoin_hash() == *hash)
+                })
+                .map(|i| i + 1)
+                .unwrap_or(0);
+            Ok(self.headers[start..].iter().take(2000).cloned().collect())
+        }
+    }
+
+    #[test]
+    fn test_spv_fsync_batching() {
+        let db_path = "/tmp/test_spv_fsync_batching.dat";
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let headers = make_unmined_headers_after(&genesis, 20_000, 0, genesis.bits);
+
+        // sync all the headers, in 10 batches, and get the number of fsyncs
+        let sync_with = |interval: u64, period: Duration| -> u64 {
+            for suffix in ["", "-wal", "-shm"] {
+                let path = format!("{}{}", db_path, suffix);
+                if fs::metadata(&path).is_ok() {
+                    fs::remove_file(&path).unwrap();
+                }
+            }
+            let mut spv_client =
+                SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+            spv_client.set_fsync_interval(interval, period);
+            let mut source = MockHeaderSource {
+                headers: headers.clone(),
+            };
+            spv_client.sync_from(&mut source).unwrap();
+            assert_eq!(spv_client.get_highest_header_height().unwrap(), 20_000);
+            let num_fsyncs = spv_client.get_num_fsyncs();
+            drop(spv_client);
+
+            let spv_client =
+                SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, false, false)
+                    .unwrap();
+            assert_eq!(spv_client.get_highest_header_height().unwrap(), 20_000);
+            assert_eq!(spv_client.read_block_headers(1, 20_001).unwrap(), headers);
+            num_fsyncs
+        };
+
+        // once per batch
+        assert_eq!(sync_with(1, Duration::from_millis(0)), 10);
+        assert_eq!(sync_with(0, Duration::from_nanos(1)), 10);
+
+        // every 8000 headers, plus the last 4000 once the sync is done
+        assert_eq!(sync_with(8000, Duration::from_secs(3600)), 3);
+
+        // only once the sync is done
+        assert_eq!(sync_with(0, Duration::from_secs(3600)), 1);
+        assert_eq!(sync_with(0, Duration::from_millis(0)), 1);
+    }
+
+    #[test]
+    fn test_spv_tip_subscription() {
+        let db_path = "/tmp/test_spv_tip_subscription.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let tip_updates = spv_client.subscribe_tip();
+        let dropped = spv_client.subscribe_tip();
+        drop(dropped);
+
+        let tip_update = |hdr: &LoneBlockHeader, height: u64, reorg_depth: u64| TipUpdate {
+            height,
+            hash: BurnchainHeaderHash::from_bitcoin_hash(&hdr.header.bitcoin_hash()),
+            reorg_depth,
+        };
+
+        // new headers extend the tip, and the dropped subscriber doesn't hold them up
+        let chain_a = make_unmined_headers_after(&genesis, 5, 0, genesis.bits);
+        spv_client.handle_headers(0, chain_a.clone()).unwrap();
+        assert_eq!(
+            tip_updates.try_iter().collect::<Vec<_>>(),
+            vec![tip_update(&chain_a[4], 5, 0)]
+        );
+        assert_eq!(spv_client.tip_subscribers.len(), 1);
+
+        // headers we already have don't change the tip
+        spv_client.handle_headers(2, chain_a[2..].to_vec()).unwrap();
+        assert!(tip_updates.try_recv().is_err());
+
+        // a higher-work branch that forks off of block 2 rolls back 3 headers
+        let chain_b = make_unmined_headers_after(&chain_a[1].header, 4, 1, genesis.bits);
+        let mut peer_headers = chain_a[0..2].to_vec();
+        peer_headers.extend_from_slice(&chain_b);
+        assert_eq!(spv_client.handle_headers(5, peer_headers).unwrap(), Some(2));
+        assert_eq!(
+            tip_updates.try_iter().collect::<Vec<_>>(),
+            vec![tip_update(&chain_b[3], 6, 3)]
+        );
+
+        // a lower-work branch is rejected, so the tip stays put
+        let chain_c = make_unmined_headers_after(&chain_a[1].header, 2, 2, genesis.bits);
+        assert!(spv_client.handle_headers(6, chain_c).is_err());
+        assert!(tip_updates.try_recv().is_err());
+
+        // once everyone has unsubscribed, headers are still handled
+        drop(tip_updates);
+        let chain_d = make_unmined_headers_after(&chain_b[3].header, 2, 3, genesis.bits);
+        spv_client.handle_headers(6, chain_d).unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 8);
+        assert!(spv_client.tip_subscribers.is_empty());
+    }
+
+    #[test]
+    fn test_spv_chain_work_fork_choice() {
+        let db_path = "/tmp/test_spv_chain_work_fork_choice.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+
+        // our chain: 5 blocks at the minimum difficulty
+        let chain_a = make_unmined_headers_after(&genesis, 5, 0, genesis.bits);
+        spv_client.handle_headers(0, chain_a.clone()).unwrap();
+        let work_a = spv_client.update_chain_work().unwrap();
+
+        let mut running_work = genesis.work();
+        assert_eq!(spv_client.get_chain_work_at(0).unwrap(), Some(running_work));
+        for (i, hdr) in chain_a.iter().enumerate() {
+            running_work = running_work + hdr.header.work();
+            assert_eq!(
+                spv_client.get_chain_work_at((i as u64) + 1).unwrap(),
+                Some(running_work)
+            );
+        }
+        assert_eq!(running_work, work_a);
+        assert_eq!(spv_client.get_chain_work_at(6).unwrap(), None);
+
+        // a competing branch that forks off of block 2 and is shorter than ours, but has more
+        // work since its blocks are much harder
+        let chain_b = make_unmined_headers_after(&chain_a[1].header, 2, 1, 0x1d00ffff);
+        assert!(chain_b[0].header.work() > work_a);
+
+        assert_eq!(
+            spv_client.handle_headers(5, chain_b.clone()).unwrap(),
+            Some(2)
+        );
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 4);
+        assert_eq!(
+            spv_client.read_block_header(4).unwrap().unwrap().header,
+            chain_b[1].header
+        );
+
+        let work_b = spv_client.update_chain_work().unwrap();
+        assert!(work_b > work_a);
+        assert_eq!(spv_client.get_chain_work_at(4).unwrap(), Some(work_b));
+        assert_eq!(
+            spv_client.get_chain_work_at(4).unwrap().unwrap(),
+            spv_client.get_chain_work_at(2).unwrap().unwrap()
+                + chain_b[0].header.work()
+                + chain_b[1].header.work()
+        );
+        assert_eq!(spv_client.get_chain_work_at(5).unwrap(), None);
+
+        // a competing branch that forks off of block 2 and is longer than ours, but has less work
+        let chain_c = make_unmined_headers_after(&chain_a[1].header, 4, 2, genesis.bits);
+        match spv_client.handle_headers(4, chain_c) {
+            Err(btc_error::NoncontiguousHeader) => {}
+            res => panic!("Lower-work branch not rejected: {:?}", res),
+        }
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 4);
+        assert_eq!(
+            spv_client.read_block_header(4).unwrap().unwrap().header,
+            chain_b[1].header
+        );
+        assert_eq!(spv_client.update_chain_work().unwrap(), work_b);
+        assert_eq!(spv_client.get_chain_work_at(4).unwrap(), Some(work_b));
+    }
+
+    #[test]
+    fn test_spv_find_common_ancestor_and_drop_headers_above() {
+        let db_path = "/tmp/test_spv_find_common_ancestor_and_drop_headers_above.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+
+        let chain_a = make_unmined_headers_after(&genesis, 10, 0, genesis.bits);
+        spv_client.handle_headers(0, chain_a.clone()).unwrap();
+
+        assert_eq!(spv_client.find_common_ancestor(&[]).unwrap(), None);
+
+        // headers we already have
+        assert_eq!(
+            spv_client.find_common_ancestor(&chain_a[3..6]).unwrap(),
+            Some(6)
+        );
+        assert_eq!(spv_client.find_common_ancestor(&chain_a).unwrap(), Some(10));
+
+        // headers that build on our tip
+        let chain_b = make_unmined_headers_after(&chain_a[9].header, 3, 1, genesis.bits);
+        assert_eq!(spv_client.find_common_ancestor(&chain_b).unwrap(), Some(10));
+
+        // headers that fork off of block 7, with and without headers we already have
+        let chain_c = make_unmined_headers_after(&chain_a[6].header, 3, 2, genesis.bits);
+        assert_eq!(spv_client.find_common_ancestor(&chain_c).unwrap(), Some(7));
+        let mut peer_headers = chain_a[0..7].to_vec();
+        peer_headers.extend_from_slice(&chain_c);
+        assert_eq!(
+            spv_client.find_common_ancestor(&peer_headers).unwrap(),
+            Some(7)
+        );
+
+        // headers that don't connect to our chain
+        let chain_d = make_unmined_headers_after(&chain_c[2].header, 3, 3, genesis.bits);
+        assert_eq!(spv_client.find_common_ancestor(&chain_d).unwrap(), None);
+
+        // roll back to the fork point and append the other branch
+        let work_before = spv_client.update_chain_work().unwrap();
+        spv_client.drop_headers_above(7).unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 7);
+        assert!(spv_client.read_block_header(8).unwrap().is_none());
+        assert!(spv_client.update_chain_work().unwrap() < work_before);
+
+        spv_client
+            .insert_block_headers_after(7, chain_c.clone())
+            .unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 10);
+        assert_eq!(
+            spv_client.read_block_header(10).unwrap().unwrap().header,
+            chain_c[2].header
+        );
+        assert_eq!(spv_client.update_chain_work().unwrap(), work_before);
+    }
+
+    #[test]
+    fn test_spv_reorg_depths() {
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let chain_a = make_unmined_headers_after(&genesis, 10, 0, genesis.bits);
+
+        for depth in [1, 6] {
+            let db_path = format!("/tmp/test_spv_reorg_depths_{}.dat", depth);
+            if fs::metadata(&db_path).is_ok() {
+                fs::remove_file(&db_path).unwrap();
+            }
+            let mut spv_client =
+                SpvClient::new(&db_path, 0, None, BitcoinNetworkType::Regtest, true, false)
+                    .unwrap();
+            spv_client.handle_headers(0, chain_a.clone()).unwrap();
+
+            // replace the last `depth` blocks with `depth + 1` blocks
+            let fork_height = 10 - depth;
+            let branch = make_unmined_headers_after(
+                &chain_a[(fork_height - 1) as usize].header,
+                depth + 1,
+                depth,
+                genesis.bits,
+            );
+            assert_eq!(
+                spv_client.find_common_ancestor(&branch).unwrap(),
+                Some(fork_height)
+            );
+
+            assert_eq!(
+                spv_client.handle_headers(10, branch.clone()).unwrap(),
+                Some(fork_height)
+            );
+            assert_eq!(spv_client.get_reorg_height(), Some(fork_height));
+            assert_eq!(spv_client.get_highest_header_height().unwrap(), 11);
+            assert_eq!(
+                spv_client
+                    .read_block_header(fork_height)
+                    .unwrap()
+                    .unwrap()
+                    .header,
+                chain_a[(fork_height - 1) as usize].header
+            );
+            for (i, hdr) in branch.iter().enumerate() {
+                assert_eq!(
+                    spv_client
+                        .read_block_header(fork_height + 1 + (i as u64))
+                        .unwrap()
+                        .unwrap()
+                        .header,
+                    hdr.header
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_spv_reorg_at_checkpoint() {
+        let db_path = "/tmp/test_spv_reorg_at_checkpoint.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+
+        let chain_a = make_unmined_headers_after(&genesis, 10, 0, genesis.bits);
+        let checkpoint_hash =
+            BurnchainHeaderHash::from_bitcoin_hash(&chain_a[4].header.bitcoin_hash());
+        spv_client.set_checkpoints(vec![(5, checkpoint_hash.clone())]);
+        spv_client.handle_headers(0, chain_a.clone()).unwrap();
+
+        // a reorg that would replace the checkpointed block is rejected
+        let bad_branch = make_unmined_headers_after(&chain_a[3].header, 10, 1, genesis.bits);
+        match spv_client.handle_headers(10, bad_branch) {
+            Err(btc_error::CheckpointMismatch(5)) => {}
+            res => panic!("Reorg below checkpoint not rejected: {:?}", res),
+        }
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 10);
+        assert_eq!(
+            spv_client.read_block_header(10).unwrap().unwrap().header,
+            chain_a[9].header
+        );
+        assert_eq!(spv_client.get_reorg_height(), None);
+
+        // a reorg that forks off of the checkpointed block is fine
+        let branch = make_unmined_headers_after(&chain_a[4].header, 10, 2, genesis.bits);
+        assert_eq!(
+            spv_client.handle_headers(10, branch.clone()).unwrap(),
+            Some(5)
+        );
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 15);
+        assert_eq!(
+            BurnchainHeaderHash::from_bitcoin_hash(
+                &spv_client
+                    .read_block_header(5)
+                    .unwrap()
+                    .unwrap()
+                    .header
+                    .bitcoin_hash()
+            ),
+            checkpoint_hash
+        );
+        assert_eq!(
+            spv_client.read_block_header(15).unwrap().unwrap().header,
+            branch[9].header
+        );
+    }
+
+    #[test]
+    fn test_spv_reorg_below_floor() {
+        let db_path = "/tmp/test_spv_reorg_below_floor.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+
+        let chain_a = make_unmined_headers_after(&genesis, 10, 0, genesis.bits);
+        spv_client.set_reorg_floor(Some(6));
+        spv_client.handle_headers(0, chain_a.clone()).unwrap();
+
+        // a reorg that forks off below the floor is refused, and leaves the headers alone
+        let deep_branch = make_unmined_headers_after(&chain_a[4].header, 10, 1, genesis.bits);
+        match spv_client.handle_headers(10, deep_branch.clone()) {
+            Err(btc_error::DeepReorg(5)) => {}
+            res => panic!("Reorg below floor not refused: {:?}", res),
+        }
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 10);
+        assert_eq!(
+            spv_client.read_block_header(10).unwrap().unwrap().header,
+            chain_a[9].header
+        );
+        assert_eq!(spv_client.get_reorg_height(), None);
+
+        // a reorg that forks off at the floor is fine
+        let branch = make_unmined_headers_after(&chain_a[5].header, 10, 2, genesis.bits);
+        assert_eq!(
+            spv_client.handle_headers(10, branch.clone()).unwrap(),
+            Some(6)
+        );
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 16);
+        assert_eq!(spv_client.get_reorg_height(), Some(6));
+
+        // without a floor, the deep reorg is taken once it has the most work
+        spv_client.set_reorg_floor(None);
+        let deep_branch = make_unmined_headers_after(&chain_a[4].header, 15, 3, genesis.bits);
+        assert_eq!(spv_client.handle_headers(16, deep_branch).unwrap(), Some(5));
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 20);
+    }
+
+    #[test]
+    fn test_spv_header_chunks() {
+        let db_path = "/tmp/test_spv_header_chunks.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let headers = make_unmined_headers_after(&genesis, 8, 0, genesis.bits);
+
+        // no checkpoints, so no chunks
+        assert_eq!(spv_client.plan_header_chunks(None, 1).unwrap(), vec![]);
+
+        let checkpoints: Vec<_> = [6, 3, 8]
+            .iter()
+            .map(|height| {
+                (
+                    *height,
+                    BurnchainHeaderHash::from_bitcoin_hash(
+                        &headers[(*height - 1) as usize].header.bitcoin_hash(),
+                    ),
+                )
+            })
+            .collect();
+        spv_client.set_checkpoints(checkpoints);
+
+        let chunks = spv_client.plan_header_chunks(None, 1).unwrap();
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| (c.start_height, c.end_height))
+                .collect::<Vec<_>>(),
+            vec![(0, 3), (3, 6), (6, 8)]
+        );
+        assert_eq!(chunks[0].start_hash, genesis.bitcoin_hash());
+        assert_eq!(chunks[0].end_hash, headers[2].header.bitcoin_hash());
+        assert_eq!(chunks[1].start_hash, headers[2].header.bitcoin_hash());
+        assert_eq!(
+            spv_client.plan_header_chunks(Some(7), 1).unwrap(),
+            chunks[0..2].to_vec()
+        );
+
+        // checkpoints closer together than the chunk size are merged, but the last chunk can
+        // be short
+        let merged_heights = |chunks: Vec<HeaderChunk>| {
+            chunks
+                .iter()
+                .map(|c| (c.start_height, c.end_height))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            merged_heights(spv_client.plan_header_chunks(None, 4).unwrap()),
+            vec![(0, 6), (6, 8)]
+        );
+        assert_eq!(
+            merged_heights(spv_client.plan_header_chunks(Some(7), 4).unwrap()),
+            vec![(0, 6)]
+        );
+        assert_eq!(
+            merged_heights(spv_client.plan_header_chunks(None, 100).unwrap()),
+            vec![(0, 8)]
+        );
+
+        // download chunk 1 in a few batches
+        let mut fetcher = HeaderChunkFetcher::new(chunks[1].clone());
+        assert!(fetcher.absorb_headers(headers[3..4].to_vec()).unwrap());
+        match fetcher.absorb_headers(vec![]) {
+            Err(btc_error::InvalidReply) => {}
+            res => panic!("Empty headers batch accepted: {:?}", res),
+        }
+        match fetcher.absorb_headers(headers[5..6].to_vec()) {
+            Err(btc_error::NoncontiguousHeader) => {}
+            res => panic!("Noncontiguous headers accepted: {:?}", res),
+        }
+        // headers past the end of the chunk are ignored
+        assert!(!fetcher.absorb_headers(headers[4..8].to_vec()).unwrap());
+        let chunk_1_headers = fetcher.into_headers().unwrap();
+        assert_eq!(chunk_1_headers, headers[3..6].to_vec());
+
+        // a chunk that doesn't end at its end header is invalid
+        match chunks[1].validate(&headers[4..7]) {
+            Err(btc_error::NoncontiguousHeader) => {}
+            res => panic!("Chunk with wrong start accepted: {:?}", res),
+        }
+        let mut bad_end = chunks[1].clone();
+        bad_end.end_hash = headers[6].header.bitcoin_hash();
+        match bad_end.validate(&headers[3..6]) {
+            Err(btc_error::CheckpointMismatch(6)) => {}
+            res => panic!("Chunk with wrong end accepted: {:?}", res),
+        }
+
+        // chunks must be stored in order
+        match spv_client.insert_header_chunk(&chunks[1], chunk_1_headers.clone()) {
+            Err(btc_error::NoncontiguousHeader) => {}
+            res => panic!("Out-of-order chunk accepted: {:?}", res),
+        }
+        spv_client
+            .insert_header_chunk(&chunks[0], headers[0..3].to_vec())
+            .unwrap();
+        spv_client
+            .insert_header_chunk(&chunks[1], chunk_1_headers)
+            .unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 6);
+        assert_eq!(
+            spv_client.read_block_header(6).unwrap().unwrap().header,
+            headers[5].header
+        );
+    }
+
+    #[test]
+    fn test_spv_default_checkpoints() {
+        for network_id in [
+            BitcoinNetworkType::Mainnet,
+            BitcoinNetworkType::Testnet,
+            BitcoinNetworkType::Regtest,
+        ]
+        .iter()
+        {
+            let checkpoints = SpvClient::default_checkpoints(*network_id);
+            for i in 1..checkpoints.len() {
+                assert!(checkpoints[i - 1].0 < checkpoints[i].0);
+            }
+        }
+        assert!(SpvClient::default_checkpoints(BitcoinNetworkType::Mainnet).len() > 0);
+        assert!(SpvClient::default_checkpoints(BitcoinNetworkType::Testnet).len() > 0);
+        assert_eq!(
+            SpvClient::default_checkpoints(BitcoinNetworkType::Regtest).len(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_spv_checkpoint_mismatch() {
+        let headers_path = "/tmp/test-spv-checkpoint_mismatch.dat";
+        if fs::metadata(headers_path).is_ok() {
+            fs::remove_file(headers_path).unwrap();
+        }
+
+        let headers = get_regtest_headers_after_genesis();
+        let hash_1 = BurnchainHeaderHash::from_bitcoin_hash(&headers[0].header.bitcoin_hash());
+        let hash_2 = BurnchainHeaderHash::from_bitcoin_hash(&headers[1].header.bitcoin_hash());
+
+        let mut spv_client = SpvClient::new(
+            headers_path,
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            true,
+            false,
+        )
+        .unwrap();
+
+        // checkpoint at height 2 does not match the header chain we're given
+        spv_client.set_checkpoints(vec![(2, hash_1.clone())]);
+        match spv_client.insert_block_headers_after(0, headers.clone()) {
+            Err(btc_error::CheckpointMismatch(2)) => {}
+            x => panic!("Expected checkpoint mismatch, got {:?}", &x),
+        }
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 0);
+
+        // checkpoints beyond the headers' range are not considered
+        spv_client.set_checkpoints(vec![(2, hash_2.clone()), (4, hash_1.clone())]);
+        spv_client
+            .insert_block_headers_after(0, headers.clone())
+            .unwrap();
+        assert_eq!(spv_client.read_block_headers(1, 10).unwrap(), headers);
+
+        // no checkpoints, no validation
+        spv_client.drop_headers(0).unwrap();
+        spv_client.set_checkpoints(vec![]);
+        spv_client
+            .insert_block_headers_after(0, headers.clone())
+            .unwrap();
+        assert_eq!(spv_client.read_block_headers(1, 10).unwrap(), headers);
+    }
+
+    #[test]
+    fn test_witness_size() {
+        use stacks_common::deps_common::bitcoin::blockdata::script::Script;
+        use stacks_common::deps_common::bitcoin::blockdata::transaction::OutPoint;
+        use stacks_common::deps_common::bitcoin::blockdata::transaction::TxIn;
+        use stacks_common::deps_common::bitcoin::blockdata::transaction::TxOut;
+        use std::mem;
+
+        println!("OutPoint size in memory {}", mem::size_of::<OutPoint>());
+        println!("TxIn in memory {}", mem::size_of::<TxIn>());
+        println!("TxOut size in memory {}", mem::size_of::<TxOut>());
+        println!("Script size in memory {}", mem::size_of::<Script>());
+        println!("tx size in memory {}", mem::size_of::<Transaction>());
+        println!("vector size in memory {}", mem::size_of::<Vec<u8>>());
+
+        // number of items in the witness stack for our attack vector
+        let length = 1_500_000;
+
+        let witness = {
+            let mut witness: Vec<Vec<u8>> = vec![];
+            for i in 0..length {
+                witness.push(vec![]);
+            }
+            witness
+        };
+        println!(
+            "witness size in memory {}",
+            mem::size_of::<Vec<u8>>() * witness.len()
+        );
+
+        // serialize witness
+        let encoded_tx = serialize(&witness).unwrap();
+
+        println!("witness size serialized {}", encoded_tx.len());
+
+        let deserialized: Vec<Vec<u8>> = deserialize(&encoded_tx).unwrap();
+    }
+
+    #[test]
+    fn test_handle_headers_empty() {
+        let headers_path = "/tmp/test-spv-handle_headers_empty.dat";
+        if fs::metadata(headers_path).is_ok() {
+            fs::remove_file(headers_path).unwrap();
+        }
+
+        let mut spv_client = SpvClient::new(
+            headers_path,
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            true,
+            false,
+        )
+        .unwrap();
+
+        spv_client.handle_headers(1, vec![]).unwrap();
+    }
+
+    fn new_snapshot_test_client(db_path: &str, network_id: BitcoinNetworkType) -> SpvClient {
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        SpvClient::new(db_path, 0, None, network_id, true, false).unwrap()
+    }
+
+    /// Prune as few headers below `max_height` as `prune_headers()` allows, without archiving
+    fn prune_below(max_height: u64) -> HeaderPruning {
+        HeaderPruning {
+            max_height: Some(max_height),
+            keep_headers: MIN_HEADER_RETENTION,
+            archive: false,
+        }
+    }
+
+    #[test]
+    fn test_spv_export_import_headers() {
+        let headers = make_unmined_headers(BitcoinNetworkType::Regtest, 10);
+        let mut spv_client = new_snapshot_test_client(
+            "/tmp/test_spv_export_import_headers.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        spv_client.handle_headers(0, headers.clone()).unwrap();
+
+        // partial export
+        let partial_path = "/tmp/test_spv_export_import_headers_partial.snapshot";
+        assert_eq!(spv_client.export_headers_file(partial_path, 6).unwrap(), 6);
+        let snapshot = fs::read(partial_path).unwrap();
+        assert_eq!(
+            snapshot.len(),
+            SNAPSHOT_PREAMBLE_SIZE + 7 * SNAPSHOT_HEADER_SIZE + SNAPSHOT_CHECKSUM_SIZE
+        );
+
+        // export past the tip stops at the tip
+        let full_path = "/tmp/test_spv_export_import_headers_full.snapshot";
+        assert_eq!(spv_client.export_headers_file(full_path, 1000).unwrap(), 10);
+
+        let mut import_client = new_snapshot_test_client(
+            "/tmp/test_spv_export_import_headers_import.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        assert_eq!(import_client.import_headers_file(partial_path).unwrap(), 6);
+        assert_eq!(import_client.get_highest_header_height().unwrap(), 6);
+
+        // importing the same headers again is a no-op
+        assert_eq!(import_client.import_headers_file(partial_path).unwrap(), 6);
+
+        // a longer snapshot extends the chain
+        assert_eq!(import_client.import_headers_file(full_path).unwrap(), 10);
+        assert_eq!(
+            import_client.read_block_headers(1, 11).unwrap(),
+            spv_client.read_block_headers(1, 11).unwrap()
+        );
+        assert_eq!(
+            import_client.get_chain_work().unwrap(),
+            spv_client.update_chain_work().unwrap()
+        );
+
+        // a shorter snapshot leaves the chain as-is
+        assert_eq!(import_client.import_headers_file(partial_path).unwrap(), 10);
+
+        // a snapshot that disagrees with our headers is rejected
+        let mut fork_client = new_snapshot_test_client(
+            "/tmp/test_spv_export_import_headers_fork.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        fork_client
+            .handle_headers(0, make_unmined_headers_after(&genesis, 3, 1, 0x1d00ffff))
+            .unwrap();
+        match fork_client.import_headers_file(full_path) {
+            Err(btc_error::NoncontiguousHeader) => {}
+            res => panic!("expected NoncontiguousHeader, got {:?}", res),
+        }
+        assert_eq!(fork_client.get_highest_header_height().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_spv_import_headers_corrupt() {
+        let headers = make_unmined_headers(BitcoinNetworkType::Regtest, 5);
+        let mut spv_client = new_snapshot_test_client(
+            "/tmp/test_spv_import_headers_corrupt.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        spv_client.handle_headers(0, headers).unwrap();
+
+        let path = "/tmp/test_spv_import_headers_corrupt.snapshot";
+        spv_client.export_headers_file(path, 5).unwrap();
+        let snapshot = fs::read(path).unwrap();
+
+        let mut import_client = new_snapshot_test_client(
+            "/tmp/test_spv_import_headers_corrupt_import.dat",
+            BitcoinNetworkType::Regtest,
+        );
+
+        let bad_path = "/tmp/test_spv_import_headers_corrupt_bad.snapshot";
+        let mut flipped = snapshot.clone();
+        flipped[SNAPSHOT_PREAMBLE_SIZE + 100] ^= 0x01;
+        let bad_snapshots = vec![
+            snapshot[..snapshot.len() - 1].to_vec(),
+            snapshot[..SNAPSHOT_PREAMBLE_SIZE + 2 * SNAPSHOT_HEADER_SIZE].to_vec(),
+            snapshot[..10].to_vec(),
+            vec![],
+            flipped,
+        ];
+        for bad_snapshot in bad_snapshots.into_iter() {
+            fs::write(bad_path, &bad_snapshot).unwrap();
+            match import_client.import_headers_file(bad_path) {
+                Err(btc_error::InvalidHeaderSnapshot(_)) => {}
+                res => panic!("expected InvalidHeaderSnapshot, got {:?}", res),
+            }
+            assert_eq!(import_client.get_highest_header_height().unwrap(), 0);
+        }
+
+        // header count that doesn't match the payload, with a valid checksum
+        let mut miscounted = snapshot[..snapshot.len() - SNAPSHOT_CHECKSUM_SIZE].to_vec();
+        miscounted[4..SNAPSHOT_PREAMBLE_SIZE].copy_from_slice(&u64::MAX.to_le_bytes());
+        let checksum = Sha256Sum::from_data(&miscounted);
+        miscounted.extend_from_slice(checksum.as_bytes());
+        fs::write(bad_path, &miscounted).unwrap();
+        match import_client.import_headers_file(bad_path) {
+            Err(btc_error::InvalidHeaderSnapshot(_)) => {}
+            res => panic!("expected InvalidHeaderSnapshot, got {:?}", res),
+        }
+
+        match import_client.import_headers_file("/tmp/test_spv_import_headers_corrupt_missing") {
+            Err(btc_error::FilesystemError(_)) => {}
+            res => panic!("expected FilesystemError, got {:?}", res),
+        }
+
+        // snapshots from other networks are refused
+        let mut testnet_client = new_snapshot_test_client(
+            "/tmp/test_spv_import_headers_corrupt_testnet.dat",
+            BitcoinNetworkType::Testnet,
+        );
+        match testnet_client.import_headers_file(path) {
+            Err(btc_error::WrongNetwork) => {}
+            res => panic!("expected WrongNetwork, got {:?}", res),
+        }
+        assert_eq!(testnet_client.get_highest_header_height().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_spv_import_headers_revalidates() {
+        // unmined testnet headers can be written directly, but not imported
+        let headers = make_unmined_headers(BitcoinNetworkType::Testnet, 5);
+        let mut spv_client = new_snapshot_test_client(
+            "/tmp/test_spv_import_headers_revalidates.dat",
+            BitcoinNetworkType::Testnet,
+        );
+        spv_client.test_write_block_headers(1, headers).unwrap();
+
+        let path = "/tmp/test_spv_import_headers_revalidates.snapshot";
+        assert_eq!(spv_client.export_headers_file(path, 5).unwrap(), 5);
+
+        let mut import_client = new_snapshot_test_client(
+            "/tmp/test_spv_import_headers_revalidates_import.dat",
+            BitcoinNetworkType::Testnet,
+        );
+        match import_client.import_headers_file(path) {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("expected InvalidPoW, got {:?}", res),
+        }
+        assert_eq!(import_client.get_highest_header_height().unwrap(), 0);
+
+        // broken linkage is rejected, even on regtest
+        let mut bad_headers = make_unmined_headers(BitcoinNetworkType::Regtest, 5);
+        bad_headers[2].header.prev_blockhash = Sha256dHash([0x11; 32]);
+        let mut regtest_client = new_snapshot_test_client(
+            "/tmp/test_spv_import_headers_revalidates_regtest.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        regtest_client
+            .test_write_block_headers(1, bad_headers)
+            .unwrap();
+        regtest_client.export_headers_file(path, 5).unwrap();
+
+        let mut regtest_import_client = new_snapshot_test_client(
+            "/tmp/test_spv_import_headers_revalidates_regtest_import.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        match regtest_import_client.import_headers_file(path) {
+            Err(btc_error::NoncontiguousHeader) => {}
+            res => panic!("expected NoncontiguousHeader, got {:?}", res),
+        }
+        assert_eq!(
+            regtest_import_client.get_highest_header_height().unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_spv_export_import_headers_stream() {
+        let headers = make_unmined_headers(BitcoinNetworkType::Regtest, 2020);
+        let mut spv_client = new_snapshot_test_client(
+            "/tmp/test_spv_export_import_headers_stream.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        spv_client.handle_headers(0, headers.clone()).unwrap();
+
+        let mut snapshot = vec![];
+        assert_eq!(
+            spv_client.export_headers(&mut snapshot, 2020).unwrap(),
+            2020
+        );
+        let mut short_snapshot = vec![];
+        assert_eq!(
+            spv_client
+                .export_headers(&mut short_snapshot, 2010)
+                .unwrap(),
+            2010
+        );
+
+        // a fresh store gets the same chain
+        let mut import_client = new_snapshot_test_client(
+            "/tmp/test_spv_export_import_headers_stream_import.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        assert_eq!(
+            import_client
+                .import_headers(&mut std::io::Cursor::new(&snapshot))
+                .unwrap(),
+            2020
+        );
+        assert_eq!(
+            import_client.get_tip().unwrap(),
+            spv_client.get_tip().unwrap()
+        );
+        assert_eq!(
+            import_client.get_chain_work().unwrap(),
+            spv_client.update_chain_work().unwrap()
+        );
+
+        // a store started from an anchor takes the snapshot's headers above it...
+        let anchor = headers[2015].header;
+        let mut anchored_client = new_snapshot_test_client(
+            "/tmp/test_spv_export_import_headers_stream_anchored.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        anchored_client
+            .init_from_anchor(2016, anchor, anchor.work())
+            .unwrap();
+        assert_eq!(
+            anchored_client
+                .import_headers(&mut std::io::Cursor::new(&snapshot))
+                .unwrap(),
+            2020
+        );
+        assert_eq!(
+            anchored_client.get_tip().unwrap(),
+            spv_client.get_tip().unwrap()
+        );
+
+        // ...but can't export them, since it lacks the ones below the anchor
+        assert!(anchored_client.export_headers(&mut vec![], 2020).is_err());
+
+        // a snapshot that stops short of our anchor, or doesn't have it, is refused
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let other_anchor = make_unmined_headers_after(&genesis, 2016, 1, 0x1d00ffff)[2015].header;
+        let mut other_client = new_snapshot_test_client(
+            "/tmp/test_spv_export_import_headers_stream_other.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        other_client
+            .init_from_anchor(2016, other_anchor, other_anchor.work())
+            .unwrap();
+        for bad_snapshot in [&short_snapshot, &snapshot] {
+            match other_client.import_headers(&mut std::io::Cursor::new(bad_snapshot)) {
+                Err(btc_error::NoncontiguousHeader) => {}
+                res => panic!("expected NoncontiguousHeader, got {:?}", res),
+            }
+            assert_eq!(other_client.get_highest_header_height().unwrap(), 2016);
+        }
+    }
+
+    #[test]
+    fn test_spv_validate_last_headers() {
+        // unmined testnet headers fail the work check
+        let mut testnet_client = new_snapshot_test_client(
+            "/tmp/test_spv_validate_last_headers_testnet.dat",
+            BitcoinNetworkType::Testnet,
+        );
+        testnet_client
+            .test_write_block_headers(1, make_unmined_headers(BitcoinNetworkType::Testnet, 5))
+            .unwrap();
+        match testnet_client.validate_last_headers(5) {
+            Err(btc_error::InvalidPoW) => {}
+            res => panic!("expected InvalidPoW, got {:?}", res),
+        }
+
+        // broken linkage is only caught if it's among the last `count` headers
+        let mut bad_headers = make_unmined_headers(BitcoinNetworkType::Regtest, 5);
+        bad_headers[2].header.prev_blockhash = Sha256dHash([0x11; 32]);
+        let mut regtest_client = new_snapshot_test_client(
+            "/tmp/test_spv_validate_last_headers_regtest.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        regtest_client
+            .test_write_block_headers(1, bad_headers)
+            .unwrap();
+        match regtest_client.validate_last_headers(5) {
+            Err(btc_error::NoncontiguousHeader) => {}
+            res => panic!("expected NoncontiguousHeader, got {:?}", res),
+        }
+        assert_eq!(regtest_client.validate_last_headers(2).unwrap(), 4);
+
+        // asking for more headers than we have checks them all
+        let mut good_client = new_snapshot_test_client(
+            "/tmp/test_spv_validate_last_headers_good.dat",
+            BitcoinNetworkType::Regtest,
+        );
+        good_client
+            .test_write_block_headers(1, make_unmined_headers(BitcoinNetworkType::Regtest, 5))
+            .unwrap();
+        assert_eq!(good_client.validate_last_headers(100).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_spv_prune_headers() {
+        let db_path = "/tmp/test_spv_prune_headers.dat";
+        let mut spv_client = new_snapshot_test_client(db_path, BitcoinNetworkType::Regtest);
+
+        let num_headers = 3 * BLOCK_DIFFICULTY_CHUNK_SIZE + 100;
+        let headers = make_unmined_headers(BitcoinNetworkType::Regtest, num_headers);
+        spv_client
+            .test_write_block_headers(1, headers.clone())
+            .unwrap();
+        let work_before = spv_client.update_chain_work().unwrap();
+
+        // the two intervals before the one containing the prune height are kept
+        assert_eq!(spv_client.prune_headers(&prune_below(0)).unwrap(), 0);
+        assert_eq!(
+            spv_client
+                .prune_headers(&prune_below(2 * BLOCK_DIFFICULTY_CHUNK_SIZE + 5))
+                .unwrap(),
+            0
+        );
+        assert_eq!(spv_client.get_pruned_height().unwrap(), 0);
+        assert!(spv_client.read_block_header(0).unwrap().is_some());
+
+        // can't prune past the chain tip
+        assert_eq!(
+            spv_client
+                .prune_headers(&prune_below(100 * num_headers))
+                .unwrap(),
+            BLOCK_DIFFICULTY_CHUNK_SIZE
+        );
+        assert_eq!(
+            spv_client.get_pruned_height().unwrap(),
+            BLOCK_DIFFICULTY_CHUNK_SIZE
+        );
+
+        // heights are unchanged
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), num_headers);
+        assert_eq!(spv_client.get_headers_height().unwrap(), num_headers + 1);
+        assert_eq!(
+            spv_client
+                .read_block_header(BLOCK_DIFFICULTY_CHUNK_SIZE)
+                .unwrap()
+                .unwrap()
+                .header,
+            headers[(BLOCK_DIFFICULTY_CHUNK_SIZE - 1) as usize].header
+        );
+        assert_eq!(
+            spv_client
+                .read_block_headers(BLOCK_DIFFICULTY_CHUNK_SIZE, BLOCK_DIFFICULTY_CHUNK_SIZE + 4)
+                .unwrap()
+                .len(),
+            4
+        );
+
+        // pruned headers are missing
+        for height in [0, 1, BLOCK_DIFFICULTY_CHUNK_SIZE - 1].iter() {
+            match spv_client.read_block_header(*height) {
+                Err(btc_error::MissingHeader) => {}
+                res => panic!("expected MissingHeader at {}, got {:?}", height, res),
+            }
+            match spv_client.read_block_headers(*height, num_headers) {
+                Err(btc_error::MissingHeader) => {}
+                res => panic!("expected MissingHeader from {}, got {:?}", height, res),
+            }
+        }
+        let pruned_hash =
+            BurnchainHeaderHash::from_bitcoin_hash(&headers[99].header.bitcoin_hash());
+        assert_eq!(
+            spv_client.find_block_header_height(&pruned_hash).unwrap(),
+            None
+        );
+        match spv_client.test_write_block_headers(5, headers[4..6].to_vec()) {
+            Err(btc_error::MissingHeader) => {}
+            res => panic!("expected MissingHeader, got {:?}", res),
+        }
+
+        // pruning is persistent, and lower prune heights are a no-op
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        assert_eq!(
+            spv_client.prune_headers(&prune_below(10)).unwrap(),
+            BLOCK_DIFFICULTY_CHUNK_SIZE
+        );
+        assert_eq!(spv_client.get_chain_work().unwrap(), work_before);
+
+        // the chain still grows from its tip
+        let more_headers =
+            make_unmined_headers_after(&headers.last().unwrap().header, 10, 0, 0x207fffff);
+        spv_client
+            .handle_headers(num_headers, more_headers.clone())
+            .unwrap();
+        assert_eq!(
+            spv_client.get_highest_header_height().unwrap(),
+            num_headers + 10
+        );
+        assert!(spv_client.update_chain_work().unwrap() > work_before);
 
-    use stacks_common::deps_common::bitcoin::blockdata::block::{BlockHeader, LoneBlockHeader};
-    use stacks_common::deps_common::bitcoin::network::serialize::{
-        deserialize, serialize, BitcoinHash,
-    };
-    use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+        // can't roll back into the interval after the pruned ones, since its chain work would
+        // have to be recalculated
+        match spv_client.drop_headers_above(BLOCK_DIFFICULTY_CHUNK_SIZE) {
+            Err(btc_error::MissingHeader) => {}
+            res => panic!("expected MissingHeader, got {:?}", res),
+        }
+        spv_client
+            .drop_headers_above(2 * BLOCK_DIFFICULTY_CHUNK_SIZE + 10)
+            .unwrap();
+        assert_eq!(
+            spv_client.get_highest_header_height().unwrap(),
+            2 * BLOCK_DIFFICULTY_CHUNK_SIZE + 10
+        );
+        spv_client.update_chain_work().unwrap();
+        spv_client
+            .handle_headers(
+                2 * BLOCK_DIFFICULTY_CHUNK_SIZE + 10,
+                headers[(2 * BLOCK_DIFFICULTY_CHUNK_SIZE + 10) as usize..].to_vec(),
+            )
+            .unwrap();
+        assert_eq!(spv_client.update_chain_work().unwrap(), work_before);
+    }
 
-    use stacks_common::util::log;
+    #[test]
+    fn test_spv_header_retention() {
+        let db_path = "/tmp/test_spv_header_retention.dat";
+        let mut spv_client = new_snapshot_test_client(db_path, BitcoinNetworkType::Signet);
+        let archive_path = spv_client.get_header_archive_path();
+        if fs::metadata(&archive_path).is_ok() {
+            fs::remove_file(&archive_path).unwrap();
+        }
 
-    use std::env;
+        // headers[i] is at height i + 1
+        let num_headers = 3 * BLOCK_DIFFICULTY_CHUNK_SIZE + 100;
+        let headers = make_unmined_headers(BitcoinNetworkType::Signet, num_headers);
+        spv_client
+            .test_write_block_headers(1, headers.clone())
+            .unwrap();
+        let checkpoint_height = 1000;
+        spv_client.set_checkpoints(vec![(
+            checkpoint_height,
+            BurnchainHeaderHash::from_bitcoin_hash(&headers[999].header.bitcoin_hash()),
+        )]);
+
+        let retarget_height = 3 * BLOCK_DIFFICULTY_CHUNK_SIZE;
+        let retarget_header = headers[(retarget_height - 1) as usize].header;
+        let target = spv_client
+            .get_target(retarget_height, &retarget_header, &VecDeque::new(), 3)
+            .unwrap()
+            .unwrap();
+        assert!(target.0 != headers[0].header.bits);
 
-    fn get_genesis_regtest_header() -> LoneBlockHeader {
-        let genesis_regtest_header = LoneBlockHeader {
-            header: BlockHeader {
-                bits: 545259519,
-                merkle_root: Sha256dHash::from_hex(
-                    "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
-                )
+        // keeping fewer than MIN_HEADER_RETENTION headers keeps MIN_HEADER_RETENTION of them
+        assert_eq!(
+            spv_client
+                .prune_headers(&HeaderPruning {
+                    max_height: None,
+                    keep_headers: 10,
+                    archive: false,
+                })
                 .unwrap(),
-                nonce: 2,
-                prev_blockhash: Sha256dHash::from_hex(
-                    "0000000000000000000000000000000000000000000000000000000000000000",
+            BLOCK_DIFFICULTY_CHUNK_SIZE
+        );
+        assert_eq!(
+            spv_client
+                .get_target(retarget_height, &retarget_header, &VecDeque::new(), 3)
+                .unwrap(),
+            Some(target)
+        );
+
+        assert_eq!(
+            spv_client
+                .get_header_availability(BLOCK_DIFFICULTY_CHUNK_SIZE)
+                .unwrap(),
+            HeaderAvailability::Available
+        );
+        assert_eq!(
+            spv_client.get_header_availability(num_headers).unwrap(),
+            HeaderAvailability::Available
+        );
+        assert_eq!(
+            spv_client.get_header_availability(num_headers + 1).unwrap(),
+            HeaderAvailability::NotSynced
+        );
+        for height in [1, 999, 1001, BLOCK_DIFFICULTY_CHUNK_SIZE - 1].iter() {
+            assert_eq!(
+                spv_client.get_header_availability(*height).unwrap(),
+                HeaderAvailability::NotRetained
+            );
+            assert_eq!(spv_client.read_retained_header(*height).unwrap(), None);
+            match spv_client.read_block_header(*height) {
+                Err(btc_error::MissingHeader) => {}
+                res => panic!("expected MissingHeader at {}, got {:?}", height, res),
+            }
+        }
+
+        // the first header of the interval and the checkpoint are kept
+        assert_eq!(
+            spv_client.get_header_availability(0).unwrap(),
+            HeaderAvailability::Retained
+        );
+        assert_eq!(
+            spv_client.read_retained_header(0).unwrap(),
+            Some(SpvClient::get_genesis_header(BitcoinNetworkType::Signet))
+        );
+        assert_eq!(
+            spv_client
+                .get_header_availability(checkpoint_height)
+                .unwrap(),
+            HeaderAvailability::Retained
+        );
+        assert_eq!(
+            spv_client.read_retained_header(checkpoint_height).unwrap(),
+            Some(headers[999].header)
+        );
+        assert!(fs::metadata(&archive_path).is_err());
+
+        // with archiving on, the next interval pruned goes to the archive
+        let more_headers = make_unmined_headers_after(
+            &headers.last().unwrap().header,
+            BLOCK_DIFFICULTY_CHUNK_SIZE,
+            0,
+            headers[0].header.bits,
+        );
+        spv_client
+            .test_write_block_headers(num_headers + 1, more_headers)
+            .unwrap();
+        let next_retarget_height = 4 * BLOCK_DIFFICULTY_CHUNK_SIZE;
+        let next_retarget_header = spv_client
+            .read_block_header(next_retarget_height)
+            .unwrap()
+            .unwrap()
+            .header;
+        let next_target = spv_client
+            .get_target(
+                next_retarget_height,
+                &next_retarget_header,
+                &VecDeque::new(),
+                4,
+            )
+            .unwrap()
+            .unwrap();
+
+        // a max height above the tip is the same as none
+        assert_eq!(
+            spv_client
+                .prune_headers(&HeaderPruning {
+                    max_height: Some(100 * num_headers),
+                    keep_headers: MIN_HEADER_RETENTION,
+                    archive: true,
+                })
+                .unwrap(),
+            2 * BLOCK_DIFFICULTY_CHUNK_SIZE
+        );
+        assert_eq!(
+            spv_client
+                .get_target(
+                    next_retarget_height,
+                    &next_retarget_header,
+                    &VecDeque::new(),
+                    4
                 )
                 .unwrap(),
-                time: 1296688602,
-                version: 1,
-            },
+            Some(next_target)
+        );
+        assert_eq!(
+            fs::metadata(&archive_path).unwrap().len(),
+            8 + BLOCK_DIFFICULTY_CHUNK_SIZE * 80
+        );
+
+        assert_eq!(
+            spv_client.get_header_availability(3000).unwrap(),
+            HeaderAvailability::Archived
+        );
+        assert_eq!(
+            spv_client.read_archived_header(3000).unwrap(),
+            Some(headers[2999].header)
+        );
+        assert_eq!(
+            spv_client
+                .read_archived_header(BLOCK_DIFFICULTY_CHUNK_SIZE)
+                .unwrap(),
+            Some(headers[(BLOCK_DIFFICULTY_CHUNK_SIZE - 1) as usize].header)
+        );
+        assert_eq!(
+            spv_client
+                .read_archived_header(2 * BLOCK_DIFFICULTY_CHUNK_SIZE - 1)
+                .unwrap(),
+            Some(headers[(2 * BLOCK_DIFFICULTY_CHUNK_SIZE - 2) as usize].header)
+        );
+        assert_eq!(
+            spv_client
+                .read_archived_header(2 * BLOCK_DIFFICULTY_CHUNK_SIZE)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            spv_client
+                .read_archived_header(BLOCK_DIFFICULTY_CHUNK_SIZE - 1)
+                .unwrap(),
+            None
+        );
+
+        // headers pruned before archiving was turned on are still gone, and everything pruned
+        // stays pruned when the headers DB is reopened
+        let spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Signet, true, false).unwrap();
+        assert_eq!(
+            spv_client
+                .get_header_availability(BLOCK_DIFFICULTY_CHUNK_SIZE - 1)
+                .unwrap(),
+            HeaderAvailability::NotRetained
+        );
+        assert_eq!(
+            spv_client.get_header_availability(3000).unwrap(),
+            HeaderAvailability::Archived
+        );
+        assert_eq!(
+            spv_client
+                .get_header_availability(2 * BLOCK_DIFFICULTY_CHUNK_SIZE)
+                .unwrap(),
+            HeaderAvailability::Available
+        );
+    }
+
+    #[test]
+    fn test_spv_header_read_api() {
+        let db_path = "/tmp/test_spv_header_read_api.dat";
+        let mut spv_client = new_snapshot_test_client(db_path, BitcoinNetworkType::Regtest);
+
+        let num_headers = 3 * BLOCK_DIFFICULTY_CHUNK_SIZE + 100;
+        let headers = make_unmined_headers(BitcoinNetworkType::Regtest, num_headers);
+        spv_client
+            .test_write_block_headers(1, headers.clone())
+            .unwrap();
+
+        for height in [1, 2, BLOCK_DIFFICULTY_CHUNK_SIZE, num_headers].iter() {
+            let header = &headers[(*height - 1) as usize];
+            let hash = BurnchainHeaderHash::from_bitcoin_hash(&header.header.bitcoin_hash());
+            assert_eq!(
+                spv_client.get_header(*height).unwrap(),
+                Some(header.clone())
+            );
+            assert_eq!(spv_client.get_height_of(&hash).unwrap(), Some(*height));
+            assert!(spv_client.contains(&hash).unwrap());
+        }
+        assert_eq!(spv_client.get_header(num_headers + 1).unwrap(), None);
+
+        let unknown_hash = BurnchainHeaderHash([0x11; 32]);
+        assert_eq!(spv_client.get_height_of(&unknown_hash).unwrap(), None);
+        assert!(!spv_client.contains(&unknown_hash).unwrap());
+
+        // ranges are truncated at the chain tip
+        assert_eq!(
+            spv_client.get_headers_range(1, 10).unwrap(),
+            headers[0..10].to_vec()
+        );
+        assert_eq!(
+            spv_client.get_headers_range(num_headers - 1, 10).unwrap(),
+            headers[(num_headers - 2) as usize..].to_vec()
+        );
+        assert_eq!(
+            spv_client.get_headers_range(num_headers + 1, 10).unwrap(),
+            vec![]
+        );
+        assert_eq!(spv_client.get_headers_range(1, u64::MAX).unwrap(), headers);
+
+        // hash lookups go through the index, not a table scan
+        let plan: Vec<String> = {
+            let mut stmt = spv_client
+                .conn()
+                .prepare("EXPLAIN QUERY PLAN SELECT height FROM headers WHERE hash = ?1")
+                .unwrap();
+            let rows = stmt
+                .query_map(&[&unknown_hash], |row| row.get::<_, String>(3))
+                .unwrap();
+            rows.map(|row| row.unwrap()).collect()
+        };
+        assert!(
+            plan.iter()
+                .any(|step| step.contains("index_headers_by_hash")),
+            "{:?}",
+            &plan
+        );
+
+        // pruned headers are absent, not an error
+        spv_client.prune_headers(&prune_below(num_headers)).unwrap();
+        let pruned_hash = BurnchainHeaderHash::from_bitcoin_hash(&headers[0].header.bitcoin_hash());
+        assert_eq!(spv_client.get_header(1).unwrap(), None);
+        assert!(!spv_client.contains(&pruned_hash).unwrap());
+        assert_eq!(
+            spv_client
+                .get_header(BLOCK_DIFFICULTY_CHUNK_SIZE)
+                .unwrap()
+                .unwrap(),
+            headers[(BLOCK_DIFFICULTY_CHUNK_SIZE - 1) as usize]
+        );
+    }
+
+    #[test]
+    fn test_spv_concurrent_readers() {
+        let db_path = "/tmp/test_spv_concurrent_readers.dat";
+        let mut spv_client = new_snapshot_test_client(db_path, BitcoinNetworkType::Regtest);
+
+        let num_batches = 50;
+        let batch_size = 20;
+        let mut all_headers = vec![LoneBlockHeader {
+            header: SpvClient::get_genesis_header(BitcoinNetworkType::Regtest),
             tx_count: VarInt(0),
+        }];
+        all_headers.append(&mut make_unmined_headers(
+            BitcoinNetworkType::Regtest,
+            num_batches * batch_size,
+        ));
+        let tip_height = num_batches * batch_size;
+
+        let mut readers = vec![];
+        for _ in 0..4 {
+            let reader = spv_client.reader().unwrap();
+            let all_headers = all_headers.clone();
+            readers.push(thread::spawn(move || {
+                let mut last_height = 0;
+                let mut num_reads = 0;
+                while last_height < tip_height {
+                    let (height, header) = reader.get_tip().unwrap().unwrap();
+                    num_reads += 1;
+
+                    // heights only go up, and we only ever see whole headers
+                    assert!(height >= last_height, "{} < {}", height, last_height);
+                    assert_eq!(header, all_headers[height as usize]);
+
+                    let hash =
+                        BurnchainHeaderHash::from_bitcoin_hash(&header.header.bitcoin_hash());
+                    assert_eq!(
+                        reader.get_header_by_hash(&hash).unwrap(),
+                        Some((height, header))
+                    );
+
+                    // everything below the tip is there too
+                    let headers = reader.get_headers_range(0, height + 1).unwrap();
+                    assert!(headers.len() as u64 >= height + 1);
+                    assert_eq!(
+                        headers[..(height + 1) as usize],
+                        all_headers[..(height + 1) as usize]
+                    );
+
+                    last_height = height;
+                }
+                num_reads
+            }));
+        }
+
+        for batch in 0..num_batches {
+            let start = (batch * batch_size + 1) as usize;
+            spv_client
+                .test_write_block_headers(
+                    start as u64,
+                    all_headers[start..start + batch_size as usize].to_vec(),
+                )
+                .unwrap();
+        }
+
+        for reader in readers.into_iter() {
+            assert!(reader.join().unwrap() > 0);
+        }
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), tip_height);
+    }
+
+    #[test]
+    fn test_spv_mmap_headers() {
+        let db_path = "/tmp/test_spv_mmap_headers.dat";
+        let mut spv_client = new_snapshot_test_client(db_path, BitcoinNetworkType::Regtest);
+        let headers = make_unmined_headers(BitcoinNetworkType::Regtest, 20);
+        spv_client
+            .handle_headers(0, headers[..10].to_vec())
+            .unwrap();
+
+        // mmap may be unavailable, in which case SQLite reads the file as usual
+        let mmap_size = spv_client.set_mmap_size(1 << 20).unwrap();
+        assert!(mmap_size == 1 << 20 || mmap_size == 0);
+
+        let readonly_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, false, false).unwrap();
+        readonly_client.set_mmap_size(1 << 20).unwrap();
+        assert_eq!(
+            readonly_client.read_block_headers(1, 11).unwrap(),
+            headers[..10].to_vec()
+        );
+
+        // the DB grows while it's mapped
+        spv_client
+            .handle_headers(10, headers[10..].to_vec())
+            .unwrap();
+        for client in [&spv_client, &readonly_client].iter() {
+            assert_eq!(client.get_highest_header_height().unwrap(), 20);
+            assert_eq!(client.read_block_headers(1, 21).unwrap(), headers);
+            assert_eq!(client.read_block_header(20).unwrap().unwrap(), headers[19]);
+            assert!(client.read_block_header(21).unwrap().is_none());
+        }
+
+        assert_eq!(readonly_client.set_mmap_size(0).unwrap(), 0);
+        assert_eq!(readonly_client.read_block_headers(1, 21).unwrap(), headers);
+    }
+
+    #[test]
+    fn test_spv_filter_headers() {
+        let db_path = "/tmp/test_spv_filter_headers.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        let headers = make_unmined_headers(BitcoinNetworkType::Regtest, 1005);
+        spv_client.handle_headers(0, headers.clone()).unwrap();
+        let mut block_hashes =
+            vec![SpvClient::get_genesis_header(BitcoinNetworkType::Regtest).bitcoin_hash()];
+        block_hashes.extend(headers.iter().map(|hdr| hdr.header.bitcoin_hash()));
+
+        let filter_hashes: Vec<_> = (0..1006u64)
+            .map(|h| Sha256dHash::from_data(&h.to_be_bytes()))
+            .collect();
+        let mut filter_headers = vec![];
+        let mut prev_header = Sha256dHash::default();
+        for filter_hash in filter_hashes.iter() {
+            prev_header = filter_header(filter_hash, &prev_header);
+            filter_headers.push(prev_header.clone());
+        }
+        let checkpoints = vec![filter_headers[1000].clone()];
+
+        let make_cfheaders = |start: usize, end: usize, prev: Sha256dHash| CFHeaders {
+            filter_type: BASIC_FILTER_TYPE,
+            stop_hash: block_hashes[end].clone(),
+            previous_filter_header: prev,
+            filter_hashes: filter_hashes[start..=end].to_vec(),
         };
-        genesis_regtest_header
+
+        let cfheaders = make_cfheaders(0, 999, Sha256dHash::default());
+        let cfheaders_tail = make_cfheaders(1000, 1005, filter_headers[999].clone());
+        let cfheaders_checkpoint = make_cfheaders(1001, 1005, filter_headers[1000].clone());
+
+        // the chain has to start from the zero hash
+        let mut bad_cfheaders = cfheaders.clone();
+        bad_cfheaders.previous_filter_header = Sha256dHash::from_data(&[1]);
+        match spv_client.insert_filter_headers(0, &bad_cfheaders, &checkpoints) {
+            Err(btc_error::FilterHeaderMismatch(0)) => {}
+            x => panic!("Unexpected {:?}", &x),
+        }
+
+        // the reply has to end where we asked it to
+        let mut bad_cfheaders = cfheaders.clone();
+        bad_cfheaders.stop_hash = block_hashes[1000].clone();
+        match spv_client.insert_filter_headers(0, &bad_cfheaders, &checkpoints) {
+            Err(btc_error::InvalidReply) => {}
+            x => panic!("Unexpected {:?}", &x),
+        }
+
+        // can't build on filter headers we don't have
+        match spv_client.insert_filter_headers(1000, &cfheaders_tail, &checkpoints) {
+            Err(btc_error::MissingHeader) => {}
+            x => panic!("Unexpected {:?}", &x),
+        }
+        assert_eq!(spv_client.get_highest_filter_header_height().unwrap(), None);
+
+        assert_eq!(
+            spv_client
+                .insert_filter_headers(0, &cfheaders, &checkpoints)
+                .unwrap(),
+            999
+        );
+        assert_eq!(
+            spv_client.read_filter_header(999).unwrap(),
+            Some((filter_hashes[999].clone(), filter_headers[999].clone()))
+        );
+
+        // the chain has to pass through the checkpoints
+        let bad_checkpoints = vec![Sha256dHash::from_data(&[2])];
+        match spv_client.insert_filter_headers(1000, &cfheaders_tail, &bad_checkpoints) {
+            Err(btc_error::FilterHeaderMismatch(1000)) => {}
+            x => panic!("Unexpected {:?}", &x),
+        }
+        let mut bad_cfheaders = cfheaders_tail.clone();
+        bad_cfheaders.filter_hashes[0] = Sha256dHash::from_data(&[3]);
+        match spv_client.insert_filter_headers(1000, &bad_cfheaders, &checkpoints) {
+            Err(btc_error::FilterHeaderMismatch(1000)) => {}
+            x => panic!("Unexpected {:?}", &x),
+        }
+        assert_eq!(
+            spv_client.get_highest_filter_header_height().unwrap(),
+            Some(999)
+        );
+
+        assert_eq!(
+            spv_client
+                .insert_filter_headers(1000, &cfheaders_tail, &checkpoints)
+                .unwrap(),
+            1005
+        );
+        assert_eq!(
+            spv_client.read_filter_header(1005).unwrap(),
+            Some((filter_hashes[1005].clone(), filter_headers[1005].clone()))
+        );
+
+        // without earlier filter headers, a checkpoint can anchor the chain
+        spv_client
+            .conn()
+            .execute("DELETE FROM filter_headers", NO_PARAMS)
+            .unwrap();
+        assert_eq!(
+            spv_client
+                .insert_filter_headers(1001, &cfheaders_checkpoint, &checkpoints)
+                .unwrap(),
+            1005
+        );
+        assert!(spv_client.read_filter_header(1000).unwrap().is_none());
+
+        // filter headers go away with their block headers
+        spv_client.drop_headers(1002).unwrap();
+        assert_eq!(
+            spv_client.get_highest_filter_header_height().unwrap(),
+            Some(1002)
+        );
+        assert!(spv_client.read_filter_header(1003).unwrap().is_none());
     }
 
     #[test]
-    fn test_spv_mainnet_genesis_header() {
-        let genesis_prev_blockhash = Sha256dHash::from_hex(
-            "0000000000000000000000000000000000000000000000000000000000000000",
-        )
-        .unwrap();
-        let genesis_merkle_root =
-            Sha256dHash::from_hex(BITCOIN_GENESIS_BLOCK_MERKLE_ROOT_MAINNET).unwrap();
-        let genesis_block_hash = Sha256dHash::from_hex(BITCOIN_GENESIS_BLOCK_HASH_MAINNET).unwrap();
+    fn test_spv_block_filters() {
+        let db_path = "/tmp/test_spv_block_filters.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let mut spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        let headers = make_unmined_headers(BitcoinNetworkType::Regtest, 2);
+        spv_client.handle_headers(0, headers.clone()).unwrap();
+        let block_hash = headers[0].header.bitcoin_hash();
+        assert_eq!(
+            block_hash,
+            Sha256dHash::from_hex(
+                "602902b7e5fb1106c562ddfd956a2bb8fdfc9832cd6912f9a126c305e4ddc492"
+            )
+            .unwrap()
+        );
 
-        let genesis_header = LoneBlockHeader {
-            header: BlockHeader {
-                version: 1,
-                prev_blockhash: genesis_prev_blockhash,
-                merkle_root: genesis_merkle_root,
-                time: 1231006505,
-                bits: 0x1d00ffff,
-                nonce: 2083236893,
-            },
-            tx_count: VarInt(0),
+        // the filter of block 1 has the burn address and a p2wpkh output script
+        let burn_script = hex_bytes("76a914000000000000000000000000000000000000000088ac").unwrap();
+        let p2wpkh_script = hex_bytes("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        let other_script = hex_bytes("76a914111111111111111111111111111111111111111188ac").unwrap();
+        let filter = BlockFilter::new(hex_bytes("021a8f8d580040").unwrap());
+        let cfilter = CFilter {
+            filter_type: BASIC_FILTER_TYPE,
+            block_hash: block_hash.clone(),
+            filter: filter.content.clone(),
+        };
+
+        let cfheaders = CFHeaders {
+            filter_type: BASIC_FILTER_TYPE,
+            stop_hash: block_hash.clone(),
+            previous_filter_header: Sha256dHash::default(),
+            filter_hashes: vec![Sha256dHash::from_data(&[0]), filter.filter_hash()],
         };
 
-        test_debug!("\n");
-        test_debug!("genesis prev blockhash = {}", genesis_prev_blockhash);
-        test_debug!("genesis merkle root = {}", genesis_merkle_root);
-        test_debug!("genesis block hash = {}", genesis_block_hash);
+        // need the filter header first
+        match spv_client.insert_filter(1, &cfilter) {
+            Err(btc_error::MissingHeader) => {}
+            x => panic!("Unexpected {:?}", &x),
+        }
+        spv_client
+            .insert_filter_headers(0, &cfheaders, &[])
+            .unwrap();
+
+        let mut bad_cfilter = cfilter.clone();
+        bad_cfilter.filter.push(0);
+        match spv_client.insert_filter(1, &bad_cfilter) {
+            Err(btc_error::FilterHeaderMismatch(1)) => {}
+            x => panic!("Unexpected {:?}", &x),
+        }
+        let mut bad_cfilter = cfilter.clone();
+        bad_cfilter.block_hash = headers[1].header.bitcoin_hash();
+        match spv_client.insert_filter(1, &bad_cfilter) {
+            Err(btc_error::InvalidReply) => {}
+            x => panic!("Unexpected {:?}", &x),
+        }
+
+        spv_client.insert_filter(1, &cfilter).unwrap();
+        assert_eq!(spv_client.read_filter(1).unwrap(), Some(filter));
+        assert_eq!(spv_client.get_highest_filter_height().unwrap(), Some(1));
+
+        // without scripts, every block may have burn ops
+        assert!(spv_client.block_may_contain_burn_ops(1).unwrap());
+
+        spv_client.set_filter_scripts(vec![burn_script.clone()]);
+        assert!(spv_client.block_may_contain_burn_ops(1).unwrap());
+        spv_client.set_filter_scripts(vec![other_script.clone()]);
+        assert!(!spv_client.block_may_contain_burn_ops(1).unwrap());
+        spv_client.set_filter_scripts(vec![other_script.clone(), p2wpkh_script]);
+        assert!(spv_client.block_may_contain_burn_ops(1).unwrap());
+
+        // without a filter, every block may have burn ops
+        spv_client.set_filter_scripts(vec![other_script]);
+        assert!(spv_client.block_may_contain_burn_ops(2).unwrap());
+
+        // filters go away with their block headers
+        spv_client.drop_headers(0).unwrap();
+        assert!(spv_client.read_filter(1).unwrap().is_none());
+        assert!(spv_client.block_may_contain_burn_ops(1).unwrap());
+    }
+
+    #[test]
+    fn test_spv_repair_headers_tail() {
+        let db_path = "/tmp/test_spv_repair_headers_tail.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let headers = make_unmined_headers(BitcoinNetworkType::Regtest, 40);
+        {
+            let mut spv_client =
+                SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+            spv_client.handle_headers(0, headers.clone()).unwrap();
+            assert_eq!(spv_client.get_highest_header_height().unwrap(), 40);
+
+            // header 40 was only partially written
+            spv_client
+                .conn()
+                .execute(
+                    "UPDATE headers SET merkle_root = 'torn' WHERE height = 40",
+                    NO_PARAMS,
+                )
+                .unwrap();
+        }
+
+        // reopening read-only leaves the headers alone
+        {
+            let spv_client =
+                SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, false, false)
+                    .unwrap();
+            assert_eq!(spv_client.get_highest_header_height().unwrap(), 40);
+        }
+
+        {
+            let mut spv_client =
+                SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+            assert_eq!(spv_client.get_highest_header_height().unwrap(), 39);
+            assert_eq!(
+                spv_client.read_block_header(39).unwrap().unwrap().header,
+                headers[38].header
+            );
+
+            // headers 38 and 39 were overwritten by a branch that doesn't build on header 37
+            let branch =
+                make_unmined_headers_after(&headers[33].header, 2, 1, headers[0].header.bits);
+            spv_client.test_write_block_headers(38, branch).unwrap();
+        }
 
-        assert_eq!(genesis_header.header.bitcoin_hash(), genesis_block_hash);
+        let spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 37);
+        assert_eq!(
+            spv_client.read_block_header(37).unwrap().unwrap().header,
+            headers[36].header
+        );
     }
 
     #[test]
-    fn test_spv_load_store_header() {
-        if fs::metadata("/tmp/test-spv-load_store_header.dat").is_ok() {
-            fs::remove_file("/tmp/test-spv-load_store_header.dat").unwrap();
+    fn test_spv_repair_headers_tail_corrupt() {
+        let db_path = "/tmp/test_spv_repair_headers_tail_corrupt.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
+        let headers = make_unmined_headers(BitcoinNetworkType::Regtest, 40);
+        {
+            let mut spv_client =
+                SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+            spv_client.handle_headers(0, headers.clone()).unwrap();
+
+            // flip a byte in header 35's prev_blockhash, so it no longer matches its stored hash
+            let mut prev_blockhash = headers[34].header.prev_blockhash;
+            prev_blockhash.0[0] ^= 0x01;
+            spv_client
+                .conn()
+                .execute(
+                    "UPDATE headers SET prev_blockhash = ?1 WHERE height = 35",
+                    &[&prev_blockhash],
+                )
+                .unwrap();
         }
 
-        let genesis_regtest_header = get_genesis_regtest_header();
+        match SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false) {
+            Err(btc_error::CorruptHeaders(35)) => {}
+            Err(e) => panic!("Unexpected error {:?}", &e),
+            Ok(_) => panic!("Opened corrupt headers"),
+        }
 
-        let first_regtest_header = LoneBlockHeader {
-            header: BlockHeader {
-                bits: 545259519,
-                merkle_root: Sha256dHash::from_hex(
-                    "20bee96458517fc5082a9720ce6207b5742f2b18e4e0a7e7373342725d80f88c",
-                )
-                .unwrap(),
-                nonce: 2,
-                prev_blockhash: Sha256dHash::from_hex(
-                    "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
-                )
-                .unwrap(),
-                time: 1587626881,
-                version: 0x20000000,
-            },
-            tx_count: VarInt(0),
-        };
+        // nothing was discarded
+        let spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, false, false).unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 40);
+    }
 
-        let mut spv_client = SpvClient::new(
-            "/tmp/test-spv-load_store_header.dat",
-            0,
-            None,
-            BitcoinNetworkType::Regtest,
-            true,
-            false,
-        )
-        .unwrap();
-        assert_eq!(spv_client.get_headers_height().unwrap(), 1);
-        assert_eq!(
-            spv_client.read_block_header(0).unwrap().unwrap(),
-            genesis_regtest_header
-        );
+    #[test]
+    fn test_spv_chain_work_at_checkpoint() {
+        let db_path = "/tmp/test_spv_chain_work_at_checkpoint.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
 
+        // every mainnet block up to the first checkpoint has the minimum difficulty, so the work
+        // only depends on the height and not on the actual headers
+        let (checkpoint_height, _) = BITCOIN_MAINNET_CHECKPOINTS[0];
+        let headers = make_unmined_headers(BitcoinNetworkType::Mainnet, checkpoint_height);
         {
-            let mut tx = spv_client.tx_begin().unwrap();
-            SpvClient::insert_block_header(&mut tx, first_regtest_header.header.clone(), 1)
-                .unwrap();
-            tx.commit().unwrap();
+            let mut spv_client =
+                SpvClient::new(db_path, 0, None, BitcoinNetworkType::Mainnet, true, false).unwrap();
+            spv_client.test_write_block_headers(1, headers).unwrap();
+            let total_work = spv_client.update_chain_work().unwrap();
+            assert_eq!(spv_client.get_tip_chain_work().unwrap(), Some(total_work));
+
+            let mut last_work = Uint256::from_u64(0);
+            for height in 0..(checkpoint_height + 1) {
+                let work = spv_client.get_chain_work_at(height).unwrap().unwrap();
+                assert!(work > last_work);
+                last_work = work;
+            }
+            assert_eq!(
+                spv_client.get_chain_work_at(checkpoint_height + 1).unwrap(),
+                None
+            );
+
+            // progress reports include the work so far and an estimate of the work to go
+            struct WorkProgress(std::sync::Mutex<Vec<(Uint256, Uint256)>>);
+            impl HeaderSyncProgress for WorkProgress {
+                fn on_progress(&self, _: u64, _: u64, _: f64) -> Result<(), String> {
+                    Ok(())
+                }
+                fn on_work_progress(
+                    &self,
+                    work: Uint256,
+                    remaining: Uint256,
+                ) -> Result<(), String> {
+                    self.0.lock().unwrap().push((work, remaining));
+                    Ok(())
+                }
+            }
+            let progress = Arc::new(WorkProgress(std::sync::Mutex::new(vec![])));
+            spv_client.set_progress_handler(progress.clone(), 1);
+            spv_client.report_progress(checkpoint_height + 10).unwrap();
+            assert_eq!(
+                *progress.0.lock().unwrap(),
+                vec![(
+                    total_work,
+                    Uint256::from_u64(
+                        10 * SpvClient::get_genesis_header(BitcoinNetworkType::Mainnet)
+                            .work()
+                            .low_u64()
+                    )
+                )]
+            );
         }
 
-        assert_eq!(spv_client.get_headers_height().unwrap(), 2);
+        // the running totals are still there after a restart, and match mainnet's
+        let spv_client =
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Mainnet, false, false).unwrap();
         assert_eq!(
-            spv_client.read_block_header(0).unwrap().unwrap(),
-            genesis_regtest_header
+            spv_client.get_chain_work_at(checkpoint_height).unwrap(),
+            Uint256::from_hex_be(
+                "00000000000000000000000000000000000000000000000000002b682b682b68"
+            )
         );
         assert_eq!(
-            spv_client.read_block_headers(0, 1).unwrap(),
-            vec![genesis_regtest_header.clone()]
+            spv_client.get_tip_chain_work().unwrap(),
+            spv_client.get_chain_work_at(checkpoint_height).unwrap()
         );
+    }
+
+    #[test]
+    fn test_spv_block_locator() {
         assert_eq!(
-            spv_client.read_block_header(1).unwrap().unwrap(),
-            first_regtest_header
+            SpvClient::block_locator_heights(5, 0),
+            vec![5, 4, 3, 2, 1, 0]
         );
         assert_eq!(
-            spv_client.read_block_headers(1, 2).unwrap(),
-            vec![first_regtest_header.clone()]
+            SpvClient::block_locator_heights(12, 0),
+            vec![12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 1, 0]
         );
         assert_eq!(
-            spv_client.read_block_headers(0, 10).unwrap(),
-            vec![genesis_regtest_header, first_regtest_header]
+            SpvClient::block_locator_heights(600000, 0),
+            vec![
+                600000, 599999, 599998, 599997, 599996, 599995, 599994, 599993, 599992, 599991,
+                599989, 599985, 599977, 599961, 599929, 599865, 599737, 599481, 598969, 597945,
+                595897, 591801, 583609, 567225, 534457, 468921, 337849, 75705, 0
+            ]
+        );
+
+        // a pruned chain's locator ends at its lowest header
+        assert_eq!(
+            SpvClient::block_locator_heights(600000, 598752),
+            vec![
+                600000, 599999, 599998, 599997, 599996, 599995, 599994, 599993, 599992, 599991,
+                599989, 599985, 599977, 599961, 599929, 599865, 599737, 599481, 598969, 598752
+            ]
+        );
+
+        let db_path_a = "/tmp/test_spv_block_locator_a.dat";
+        let db_path_b = "/tmp/test_spv_block_locator_b.dat";
+        for path in [db_path_a, db_path_b].iter() {
+            if fs::metadata(path).is_ok() {
+                fs::remove_file(path).unwrap();
+            }
+        }
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let headers = make_unmined_headers_after(&genesis, 40, 0, genesis.bits);
+
+        let mut spv_client_a =
+            SpvClient::new(db_path_a, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        spv_client_a
+            .handle_headers(0, headers[0..5].to_vec())
+            .unwrap();
+        assert_eq!(
+            spv_client_a.make_block_locator().unwrap(),
+            vec![
+                headers[4].header.bitcoin_hash(),
+                headers[3].header.bitcoin_hash(),
+                headers[2].header.bitcoin_hash(),
+                headers[1].header.bitcoin_hash(),
+                headers[0].header.bitcoin_hash(),
+                genesis.bitcoin_hash()
+            ]
         );
+        spv_client_a
+            .handle_headers(5, headers[5..].to_vec())
+            .unwrap();
+
+        // another chain that forked off of ours at height 20, below the locator's 10 consecutive
+        // headers.  Its highest header in the locator is at height 17, so a peer on it answers
+        // with headers from just below the fork point.
+        let mut spv_client_b =
+            SpvClient::new(db_path_b, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        spv_client_b
+            .handle_headers(0, headers[0..20].to_vec())
+            .unwrap();
+        let branch = make_unmined_headers_after(&headers[19].header, 30, 1, genesis.bits);
+        spv_client_b.handle_headers(20, branch).unwrap();
+
+        let locator = spv_client_a.make_block_locator().unwrap();
+        let fork_height = locator
+            .iter()
+            .find_map(|hash| {
+                spv_client_b
+                    .find_block_header_height(&BurnchainHeaderHash::from_bitcoin_hash(hash))
+                    .unwrap()
+            })
+            .unwrap();
+        assert_eq!(fork_height, 17);
     }
 
     #[test]
-    fn test_spv_store_headers_after() {
-        if fs::metadata("/tmp/test-spv-store_headers_after.dat").is_ok() {
-            fs::remove_file("/tmp/test-spv-store_headers_after.dat").unwrap();
+    fn test_spv_custom_genesis() {
+        let db_path = "/tmp/test_spv_custom_genesis.dat";
+        let wellknown_db_path = "/tmp/test_spv_custom_genesis_wellknown.dat";
+        for path in [db_path, wellknown_db_path] {
+            if fs::metadata(path).is_ok() {
+                fs::remove_file(path).unwrap();
+            }
         }
-        let genesis_regtest_header = get_genesis_regtest_header();
-        let headers = vec![
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "20bee96458517fc5082a9720ce6207b5742f2b18e4e0a7e7373342725d80f88c",
-                    )
-                    .unwrap(),
-                    nonce: 2,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
-                    )
-                    .unwrap(),
-                    time: 1587626881,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "39d1a6f1ee7a5903797f92ec89e4c58549013f38114186fc2eb6e5218cb2d0ac",
-                    )
-                    .unwrap(),
-                    nonce: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "606d31daaaa5919f3720d8440dd99d31f2a4e4189c65879f19ae43268425e74b",
-                    )
-                    .unwrap(),
-                    time: 1587626882,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "a7e04ed25f589938eb5627abb7b5913dd77b8955bcdf72d7f111d0a71e346e47",
-                    )
-                    .unwrap(),
-                    nonce: 4,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "2fa2f451ac27f0e5cd3760ba6cdf34ef46adb76a44d96bc0f3bf3e713dd955f0",
-                    )
-                    .unwrap(),
-                    time: 1587626882,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-        ];
 
-        let mut spv_client = SpvClient::new(
-            "/tmp/test-spv-store_headers_after.dat",
+        let mut genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        genesis.time = 1600000000;
+        genesis.nonce = 1;
+
+        // only regtest can have a custom genesis header
+        for network_id in [BitcoinNetworkType::Mainnet, BitcoinNetworkType::Testnet] {
+            match SpvClient::new_with_genesis(
+                db_path,
+                0,
+                None,
+                network_id,
+                Some(genesis),
+                true,
+                false,
+            ) {
+                Err(btc_error::ConfigError(_)) => {}
+                x => panic!("Expected ConfigError, got {:?}", x.map(|_| ())),
+            }
+            assert!(fs::metadata(db_path).is_err());
+        }
+
+        let mut spv_client = SpvClient::new_with_genesis(
+            db_path,
             0,
             None,
             BitcoinNetworkType::Regtest,
+            Some(genesis),
             true,
             false,
         )
         .unwrap();
-        spv_client
-            .insert_block_headers_after(0, headers.clone())
-            .unwrap();
-
-        assert_eq!(spv_client.read_block_headers(1, 10).unwrap(), headers);
-
-        // should fail
-        if let Err(btc_error::NoncontiguousHeader) =
-            spv_client.insert_block_headers_after(1, headers.clone())
-        {
-        } else {
-            assert!(false);
-        }
-
-        // should fail
-        if let Err(btc_error::NoncontiguousHeader) =
-            spv_client.insert_block_headers_after(9, headers.clone())
-        {
-        } else {
-            assert!(false);
-        }
-
-        spv_client.drop_headers(1).unwrap();
         assert_eq!(
-            spv_client.read_block_headers(0, 10).unwrap(),
-            vec![genesis_regtest_header, headers[0].clone()]
+            spv_client.read_block_header(0).unwrap().unwrap().header,
+            genesis
         );
-    }
 
-    #[test]
-    fn test_spv_store_headers_before() {
-        if fs::metadata("/tmp/test-spv-store_headers_before.dat").is_ok() {
-            fs::remove_file("/tmp/test-spv-store_headers_before.dat").unwrap();
-        }
-        let genesis_regtest_header = get_genesis_regtest_header();
-        let headers = vec![
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "20bee96458517fc5082a9720ce6207b5742f2b18e4e0a7e7373342725d80f88c",
-                    )
-                    .unwrap(),
-                    nonce: 2,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
-                    )
-                    .unwrap(),
-                    time: 1587626881,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "39d1a6f1ee7a5903797f92ec89e4c58549013f38114186fc2eb6e5218cb2d0ac",
-                    )
-                    .unwrap(),
-                    nonce: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "606d31daaaa5919f3720d8440dd99d31f2a4e4189c65879f19ae43268425e74b",
-                    )
-                    .unwrap(),
-                    time: 1587626882,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    bits: 545259519,
-                    merkle_root: Sha256dHash::from_hex(
-                        "a7e04ed25f589938eb5627abb7b5913dd77b8955bcdf72d7f111d0a71e346e47",
-                    )
-                    .unwrap(),
-                    nonce: 4,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "2fa2f451ac27f0e5cd3760ba6cdf34ef46adb76a44d96bc0f3bf3e713dd955f0",
-                    )
-                    .unwrap(),
-                    time: 1587626882,
-                    version: 0x20000000,
-                },
-                tx_count: VarInt(0),
-            },
-        ];
+        // sync a short chain built on the custom genesis header
+        let headers = make_unmined_headers_after(&genesis, 10, 0, genesis.bits);
+        spv_client.handle_headers(0, headers.clone()).unwrap();
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 10);
+        assert_eq!(spv_client.read_block_headers(1, 11).unwrap(), headers);
 
-        let mut spv_client = SpvClient::new(
-            "/tmp/test-spv-store_headers_before.dat",
+        // headers built on the well-known genesis header don't connect
+        let wellknown_genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let mut spv_client = SpvClient::new_with_genesis(
+            wellknown_db_path,
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            Some(genesis),
+            true,
+            false,
+        )
+        .unwrap();
+        assert!(spv_client
+            .handle_headers(
+                0,
+                make_unmined_headers_after(&wellknown_genesis, 10, 0, wellknown_genesis.bits)
+            )
+            .is_err());
+
+        // reopening with the same genesis header (or none) is fine, but not with another one
+        SpvClient::new_with_genesis(
+            db_path,
             0,
             None,
             BitcoinNetworkType::Regtest,
+            Some(genesis),
             true,
-            true,
+            false,
         )
         .unwrap();
-        spv_client
-            .insert_block_headers_before(1, headers[1..].to_vec())
-            .unwrap();
-
-        assert_eq!(
-            spv_client.read_block_headers(2, 10).unwrap(),
-            headers[1..].to_vec()
-        );
-        assert_eq!(
-            spv_client.read_block_headers(0, 10).unwrap(),
-            vec![genesis_regtest_header.clone()]
-        ); // gap
-        assert_eq!(spv_client.read_block_headers(1, 10).unwrap(), vec![]); // gap
-
-        spv_client
-            .insert_block_headers_before(0, headers[0..1].to_vec())
-            .unwrap();
-        assert_eq!(spv_client.read_block_headers(1, 10).unwrap(), headers);
-
-        let mut all_headers = vec![genesis_regtest_header.clone()];
-        all_headers.append(&mut headers.clone());
+        SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, false, false).unwrap();
+        match SpvClient::new_with_genesis(
+            db_path,
+            0,
+            None,
+            BitcoinNetworkType::Regtest,
+            Some(wellknown_genesis),
+            true,
+            false,
+        ) {
+            Err(btc_error::WrongNetwork) => {}
+            x => panic!("Expected WrongNetwork, got {:?}", x.map(|_| ())),
+        }
+    }
 
-        assert_eq!(spv_client.read_block_headers(0, 10).unwrap(), all_headers);
+    #[test]
+    fn test_spv_check_network() {
+        let db_path = "/tmp/test_spv_check_network.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
+        }
 
-        // should succeed, since we only check that the last header connects
-        // to its child, if the child is stored at all
-        spv_client
-            .insert_block_headers_before(1, headers.clone())
-            .unwrap();
-        spv_client
-            .insert_block_headers_before(2, headers.clone())
-            .unwrap();
+        let set_network = |magic: Option<i64>| {
+            let spv_client =
+                SpvClient::new(db_path, 0, None, BitcoinNetworkType::Mainnet, true, false).unwrap();
+            spv_client
+                .conn()
+                .execute("UPDATE db_config SET network = ?1", &[&magic])
+                .unwrap();
+        };
+        let get_network = || {
+            let spv_client =
+                SpvClient::new(db_path, 0, None, BitcoinNetworkType::Mainnet, false, false)
+                    .unwrap();
+            SpvClient::db_get_network(spv_client.conn()).unwrap()
+        };
+        let expect_config_error =
+            |network_id: BitcoinNetworkType, readwrite: bool| match SpvClient::new(
+                db_path, 0, None, network_id, readwrite, false,
+            ) {
+                Err(btc_error::ConfigError(msg)) => {
+                    assert!(msg.contains("Mainnet"));
+                    assert!(msg.contains(&format!("{:?}", network_id)));
+                }
+                x => panic!("Expected ConfigError, got {:?}", x.map(|_| ())),
+            };
 
-        // should fail now, since there's a child to check
-        if let Err(btc_error::NoncontiguousHeader) =
-            spv_client.insert_block_headers_before(1, headers.clone())
+        // the network is recorded when the headers DB is created, and checked on every open
+        SpvClient::new(db_path, 0, None, BitcoinNetworkType::Mainnet, true, false).unwrap();
+        assert_eq!(
+            get_network(),
+            Some(network_id_to_bytes(BitcoinNetworkType::Mainnet))
+        );
+        SpvClient::new(db_path, 0, None, BitcoinNetworkType::Mainnet, false, false).unwrap();
+        for network_id in [
+            BitcoinNetworkType::Testnet,
+            BitcoinNetworkType::Regtest,
+            BitcoinNetworkType::Signet,
+        ]
+        .iter()
         {
-        } else {
-            assert!(false);
+            expect_config_error(*network_id, true);
+            expect_config_error(*network_id, false);
         }
 
-        // should succeed
-        spv_client
-            .insert_block_headers_before(9, headers.clone())
-            .unwrap();
-    }
+        // headers DBs from before the network was recorded are identified by their genesis header
+        set_network(None);
+        expect_config_error(BitcoinNetworkType::Testnet, false);
+        assert_eq!(get_network(), None);
+        expect_config_error(BitcoinNetworkType::Testnet, true);
+        assert_eq!(
+            get_network(),
+            Some(network_id_to_bytes(BitcoinNetworkType::Mainnet))
+        );
 
-    #[test]
-    fn test_spv_check_pow() {
-        if !env::var("BLOCKSTACK_SPV_HEADERS_DB").is_ok() {
-            eprintln!("Skipping test_spv_check_pow -- no BLOCKSTACK_SPV_HEADERS_DB envar set");
-            return;
-        }
-        let db_path = env::var("BLOCKSTACK_SPV_HEADERS_DB").unwrap();
-        let spv_client =
-            SpvClient::new(&db_path, 0, None, BitcoinNetworkType::Mainnet, false, false).unwrap();
+        set_network(None);
+        SpvClient::new(db_path, 0, None, BitcoinNetworkType::Mainnet, true, false).unwrap();
+        assert_eq!(
+            get_network(),
+            Some(network_id_to_bytes(BitcoinNetworkType::Mainnet))
+        );
 
-        for i in 99..100 {
-            spv_client.validate_header_work(i, i + 1).unwrap();
+        // unknown network magic
+        set_network(Some(0x12345678));
+        match SpvClient::new(db_path, 0, None, BitcoinNetworkType::Mainnet, true, false) {
+            Err(btc_error::ConfigError(msg)) => assert!(msg.contains("0x12345678")),
+            x => panic!("Expected ConfigError, got {:?}", x.map(|_| ())),
         }
     }
 
     #[test]
-    fn test_spv_check_work_bad_blocks_rejected() {
-        if !env::var("BLOCKSTACK_SPV_HEADERS_DB").is_ok() {
-            eprintln!("Skipping test_spv_check_work_reorg_accepted -- no BLOCKSTACK_SPV_HEADERS_DB envar set");
-            return;
-        }
-        let db_path_source = env::var("BLOCKSTACK_SPV_HEADERS_DB").unwrap();
-        let db_path = "/tmp/test_spv_check_work_reorg_accepted.dat".to_string();
-
-        if fs::metadata(&db_path).is_ok() {
-            fs::remove_file(&db_path).unwrap();
+    fn test_spv_overlapping_headers() {
+        let db_path = "/tmp/test_spv_overlapping_headers.dat";
+        if fs::metadata(db_path).is_ok() {
+            fs::remove_file(db_path).unwrap();
         }
-
-        fs::copy(&db_path_source, &db_path).unwrap();
-
-        // set up SPV client so we don't have chain work at first
         let mut spv_client =
-            SpvClient::new(&db_path, 0, None, BitcoinNetworkType::Mainnet, true, false).unwrap();
+            SpvClient::new(db_path, 0, None, BitcoinNetworkType::Regtest, true, false).unwrap();
+        let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+        let tip_updates = spv_client.subscribe_tip();
 
-        assert!(
-            spv_client.get_headers_height().unwrap() >= 40317,
-            "This test needs headers up to 40317"
-        );
-        spv_client.drop_headers(40317).unwrap();
+        let chain_a = make_unmined_headers_after(&genesis, 10, 0, genesis.bits);
+        spv_client.handle_headers(0, chain_a[..6].to_vec()).unwrap();
+        assert_eq!(tip_updates.try_iter().count(), 1);
 
-        // update chain work
-        let total_work_before = spv_client.update_chain_work().unwrap();
+        // headers we already have are ignored, wherever the peer starts them from
+        assert_eq!(
+            spv_client
+                .handle_headers(6, chain_a[2..6].to_vec())
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            spv_client.handle_headers(0, chain_a[..6].to_vec()).unwrap(),
+            None
+        );
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 6);
+        assert!(tip_updates.try_recv().is_err());
 
-        // fake block headers for mainnet 40319-40320, which is on a difficulty adjustment boundary
-        let bad_headers = vec![
-            LoneBlockHeader {
-                header: BlockHeader {
-                    version: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "000000003ae696c44274e40817d4acaf40c1ff1853411d4f0573421caf5faa07",
-                    )
-                    .unwrap(),
-                    merkle_root: Sha256dHash::from_hex(
-                        "f1b2e16f74ee0e90cad3dc2e5be4806ff0581ed50a9cb3dfeee591dac76b17a7",
-                    )
-                    .unwrap(),
-                    time: 1654939798,
-                    bits: 386485098,
-                    nonce: 1,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    version: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "2c32da7fbdcef6ba34bcc5cc9707f2b351dbf094b4df5d3a2a38ea47b3e61f35",
-                    )
-                    .unwrap(),
-                    merkle_root: Sha256dHash::from_hex(
-                        "b4d736ca74838036ebd19b085c3eeb9ffec2307f6452347cdd8ddaa249686f39",
-                    )
-                    .unwrap(),
-                    time: 1654939798,
-                    bits: 486575299,
-                    nonce: 1,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    version: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "7c1d4fd424f626c13bcd5442db080df9000d8f3dbfa1809ba1b05ddcdba58dd5",
-                    )
-                    .unwrap(),
-                    merkle_root: Sha256dHash::from_hex(
-                        "cd71b0c247cfa777748c84d5376ad6a4a19e7802793dfd27c4d5834aa4393299",
-                    )
-                    .unwrap(),
-                    time: 1654996886,
-                    bits: 0x1d00ffff,
-                    nonce: 178162936,
-                },
-                tx_count: VarInt(0),
-            },
-            LoneBlockHeader {
-                header: BlockHeader {
-                    version: 1,
-                    prev_blockhash: Sha256dHash::from_hex(
-                        "000000008a8cab438b9c98599c5363c7a4eff5b246871dd7c3f46886da375170",
-                    )
-                    .unwrap(),
-                    merkle_root: Sha256dHash::from_hex(
-                        "cd71b0c247cfa777748c84d5376ad6a4a19e7802793dfd27c4d5834aa4393299",
-                    )
-                    .unwrap(),
-                    time: 1655018902,
-                    bits: 0x1d00ffff,
-                    nonce: 168408960,
-                },
-                tx_count: VarInt(0),
-            },
-        ];
+        // only the new suffix of a partially-known batch is appended, and it's not a reorg
+        assert_eq!(
+            spv_client
+                .handle_headers(6, chain_a[3..8].to_vec())
+                .unwrap(),
+            None
+        );
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 8);
+        assert_eq!(
+            spv_client.read_block_headers(1, 9).unwrap(),
+            chain_a[..8].to_vec()
+        );
+        assert_eq!(spv_client.get_reorg_height(), None);
+        assert_eq!(
+            tip_updates
+                .try_iter()
+                .map(|u| u.reorg_depth)
+                .collect::<Vec<_>>(),
+            vec![0]
+        );
 
-        // should fail
-        if let btc_error::InvalidPoW = spv_client
-            .handle_headers(40317, bad_headers.clone())
-            .unwrap_err()
-        {
-        } else {
-            panic!("Bad PoW headers accepted");
-        }
+        // a batch that diverges from ours after the known headers is handled as a fork
+        let chain_b = make_unmined_headers_after(&chain_a[5].header, 4, 1, genesis.bits);
+        let mut peer_headers = chain_a[3..6].to_vec();
+        peer_headers.extend_from_slice(&chain_b);
+        assert_eq!(spv_client.handle_headers(8, peer_headers).unwrap(), Some(6));
+        assert_eq!(spv_client.get_reorg_height(), Some(6));
+        assert_eq!(spv_client.get_highest_header_height().unwrap(), 10);
+        assert_eq!(
+            spv_client.read_block_headers(1, 7).unwrap(),
+            chain_a[..6].to_vec()
+        );
+        assert_eq!(spv_client.read_block_headers(7, 11).unwrap(), chain_b);
     }
 
     #[test]
-    fn test_witness_size() {
-        use stacks_common::deps_common::bitcoin::blockdata::script::Script;
-        use stacks_common::deps_common::bitcoin::blockdata::transaction::OutPoint;
-        use stacks_common::deps_common::bitcoin::blockdata::transaction::TxIn;
-        use stacks_common::deps_common::bitcoin::blockdata::transaction::TxOut;
-        use std::mem;
-
-        println!("OutPoint size in memory {}", mem::size_of::<OutPoint>());
-        println!("TxIn in memory {}", mem::size_of::<TxIn>());
-        println!("TxOut size in memory {}", mem::size_of::<TxOut>());
-        println!("Script size in memory {}", mem::size_of::<Script>());
-        println!("tx size in memory {}", mem::size_of::<Transaction>());
-        println!("vector size in memory {}", mem::size_of::<Vec<u8>>());
-
-        // number of items in the witness stack for our attack vector
-        let length = 1_500_000;
-
-        let witness = {
-            let mut witness: Vec<Vec<u8>> = vec![];
-            for i in 0..length {
-                witness.push(vec![]);
+    fn test_spv_strict_header_versions() {
+        let new_client = |db_path: &str, network_id: BitcoinNetworkType| {
+            if fs::metadata(db_path).is_ok() {
+                fs::remove_file(db_path).unwrap();
             }
-            witness
+            SpvClient::new(db_path, 0, None, network_id, true, false).unwrap()
+        };
+        // headers with the given versions, with the last one before BIP66 first
+        let straddle_bip66 = |bip66_height: u64, versions: &[u32]| {
+            let mut headers = make_unmined_headers_after(
+                &SpvClient::get_genesis_header(BitcoinNetworkType::Mainnet),
+                versions.len() as u64,
+                0,
+                0x1d00ffff,
+            );
+            for (header, version) in headers.iter_mut().zip(versions.iter()) {
+                header.header.version = *version;
+            }
+            (bip66_height - 1, headers)
         };
-        println!(
-            "witness size in memory {}",
-            mem::size_of::<Vec<u8>>() * witness.len()
-        );
 
-        // serialize witness
-        let encoded_tx = serialize(&witness).unwrap();
+        // on by default on mainnet, where BIP66 activated at 363725
+        let mut spv_client = new_client(
+            "/tmp/test_spv_strict_header_versions_mainnet.dat",
+            BitcoinNetworkType::Mainnet,
+        );
+        let (start, headers) = straddle_bip66(363725, &[2, 3, 3, 0x20000000]);
+        spv_client
+            .validate_header_versions(start, &headers)
+            .unwrap();
 
-        println!("witness size serialized {}", encoded_tx.len());
+        for (versions, bad_height, bad_version) in [
+            (vec![2, 2, 3], 363725, 2),
+            (vec![2, 3, 1], 363726, 1),
+            // negative as a signed integer
+            (vec![2, 0x80000004], 363725, 0x80000004),
+        ]
+        .iter()
+        {
+            let (start, headers) = straddle_bip66(363725, &versions[..]);
+            match spv_client.validate_header_versions(start, &headers) {
+                Err(btc_error::ObsoleteHeaderVersion(height, version)) => {
+                    assert_eq!(height, *bad_height);
+                    assert_eq!(version, *bad_version);
+                }
+                res => panic!("Expected ObsoleteHeaderVersion, got {:?}", res),
+            }
+        }
 
-        let deserialized: Vec<Vec<u8>> = deserialize(&encoded_tx).unwrap();
-    }
+        // version 2 is fine just before BIP66, but version 1 is not (BIP34)
+        let (start, headers) = straddle_bip66(363725, &[2]);
+        spv_client
+            .validate_header_versions(start, &headers)
+            .unwrap();
+        let (start, headers) = straddle_bip66(363725, &[1]);
+        assert!(spv_client
+            .validate_header_versions(start, &headers)
+            .is_err());
+
+        // and can be turned off
+        spv_client.set_strict_header_versions(false);
+        let (start, headers) = straddle_bip66(363725, &[1, 1]);
+        spv_client
+            .validate_header_versions(start, &headers)
+            .unwrap();
 
-    #[test]
-    fn test_handle_headers_empty() {
-        let headers_path = "/tmp/test-spv-handle_headers_empty.dat";
-        if fs::metadata(headers_path).is_ok() {
-            fs::remove_file(headers_path).unwrap();
+        // off by default on testnet, where BIP66 activated at 330776
+        let mut spv_client = new_client(
+            "/tmp/test_spv_strict_header_versions_testnet.dat",
+            BitcoinNetworkType::Testnet,
+        );
+        let (start, headers) = straddle_bip66(330776, &[2, 2]);
+        spv_client
+            .validate_header_versions(start, &headers)
+            .unwrap();
+        spv_client.set_strict_header_versions(true);
+        match spv_client.validate_header_versions(start, &headers) {
+            Err(btc_error::ObsoleteHeaderVersion(330776, 2)) => {}
+            res => panic!("Expected ObsoleteHeaderVersion, got {:?}", res),
         }
 
-        let mut spv_client = SpvClient::new(
-            headers_path,
-            0,
-            None,
-            BitcoinNetworkType::Regtest,
-            true,
-            false,
-        )
-        .unwrap();
-
-        spv_client.handle_headers(1, vec![]).unwrap();
+        // never enforced on regtest or signet
+        for network_id in [BitcoinNetworkType::Regtest, BitcoinNetworkType::Signet].iter() {
+            let mut spv_client = new_client(
+                &format!("/tmp/test_spv_strict_header_versions_{:?}.dat", network_id),
+                *network_id,
+            );
+            spv_client.set_strict_header_versions(true);
+            let (start, headers) = straddle_bip66(363725, &[1, 1]);
+            spv_client
+                .validate_header_versions(start, &headers)
+                .unwrap();
+        }
     }
 }
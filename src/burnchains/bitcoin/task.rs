@@ -0,0 +1,528 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The indexer as a background task.
+//!
+//! `BitcoinIndexer::spawn()` runs an indexer on its own thread, which syncs headers from the
+//! peer and downloads and parses the block of each new header, in height order, handing the
+//! blocks to an `IndexerHandle`.  The handle steers the task with commands, which the task
+//! obeys between units of work (a header sync, or one block), so it never stops halfway
+//! through a block.
+
+use std::cmp;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{
+    channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError,
+    TrySendError,
+};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::burnchains::bitcoin::blocks::{BitcoinBlockDownloader, BitcoinBlockParser};
+use crate::burnchains::bitcoin::indexer::{
+    BitcoinIndexer, BitcoinIndexerConfig, BitcoinIndexerRuntime, SyncStats, SHUTDOWN_POLL_INTERVAL,
+};
+use crate::burnchains::bitcoin::Error as btc_error;
+use crate::burnchains::bitcoin::{BitcoinBlock, BitcoinNetworkType};
+use crate::burnchains::indexer::{
+    BurnchainBlockDownloader, BurnchainBlockParser, BurnchainIndexer,
+};
+use crate::burnchains::BurnchainBlock;
+use crate::burnchains::Error as burnchain_error;
+use crate::core::StacksEpoch;
+
+/// How often a spawned indexer that has caught up with the peer asks it for new headers, and
+/// how long it waits before retrying after a transient error
+pub const SPAWNED_INDEXER_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A command to a spawned indexer, obeyed between units of work
+#[derive(Debug, Clone, PartialEq)]
+enum IndexerCommand {
+    Pause,
+    Resume,
+    SyncTo(u64),
+    Shutdown,
+}
+
+/// What a spawned indexer is up to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndexerState {
+    /// Syncing headers or downloading blocks
+    Syncing,
+    /// Has every block up to the target height (or the peer's chain tip, if there is no target)
+    Idle,
+    /// Paused by `IndexerHandle::pause()`
+    Paused,
+    /// Shut down, or stopped by an error that retrying won't fix
+    Stopped,
+}
+
+/// A snapshot of a spawned indexer's progress (see `IndexerHandle::stats()`)
+#[derive(Debug, Clone)]
+pub struct IndexerStats {
+    pub state: IndexerState,
+    /// Height of our highest header
+    pub headers_height: u64,
+    /// Height of the last block handed to the `IndexerHandle`
+    pub block_height: u64,
+    /// Height to sync up to, or None to keep up with the peer's chain tip
+    pub target_height: Option<u64>,
+    /// The indexer's sync stats
+    pub sync: SyncStats,
+}
+
+/// Controls an indexer running in the background (see `BitcoinIndexer::spawn()`), and receives
+/// its blocks.  Dropping the handle shuts the indexer down.
+pub struct IndexerHandle {
+    commands: Sender<IndexerCommand>,
+    blocks: Receiver<BitcoinBlock>,
+    stats: Arc<Mutex<IndexerStats>>,
+    sync_stats: Arc<Mutex<SyncStats>>,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<Result<(), burnchain_error>>>,
+}
+
+/// The sync loop of a spawned indexer
+struct IndexerTask {
+    indexer: BitcoinIndexer,
+    /// Downloads blocks on its own connection to the peer, made the first time it's needed
+    downloader: Option<BitcoinBlockDownloader>,
+    parser: BitcoinBlockParser,
+    commands: Receiver<IndexerCommand>,
+    blocks: SyncSender<BitcoinBlock>,
+    stats: Arc<Mutex<IndexerStats>>,
+    paused: bool,
+    target_height: Option<u64>,
+    headers_height: u64,
+    block_height: u64,
+    /// Set when our headers may be behind the peer's, so the next unit of work syncs them
+    headers_stale: bool,
+    /// When we last synced headers
+    last_poll_time: Instant,
+    /// When to carry on after a transient error
+    retry_time: Option<Instant>,
+}
+
+impl BitcoinIndexer {
+    /// Run an indexer with `config` on its own thread.  It syncs headers from the peer, and
+    /// downloads the block of each header past `config.first_block`, in height order, which
+    /// the returned handle receives (see `IndexerHandle::blocks()`).  If a reorg replaces
+    /// blocks it has already handed over, it hands over the new branch's blocks from the fork
+    /// height up.  It keeps up with the peer's chain tip until told otherwise with
+    /// `IndexerHandle::sync_to()`.  At most `config.block_download_window` blocks wait to be
+    /// received at once; the indexer waits for room before handing over another.
+    pub fn spawn(
+        config: BitcoinIndexerConfig,
+        network_id: BitcoinNetworkType,
+    ) -> Result<IndexerHandle, btc_error> {
+        let indexer = BitcoinIndexer::new(config, BitcoinIndexerRuntime::new(network_id));
+        let headers_height = indexer
+            .get_highest_header_height()
+            // no headers DB yet
+            .unwrap_or(0);
+        let block_height = indexer.config.first_block;
+
+        let (command_sender, command_receiver) = channel();
+        let (block_sender, block_receiver) =
+            sync_channel(cmp::max(indexer.config.block_download_window, 1));
+        let stats = Arc::new(Mutex::new(IndexerStats {
+            state: IndexerState::Syncing,
+            headers_height,
+            block_height,
+            target_height: None,
+            sync: indexer.sync_stats(),
+        }));
+        let sync_stats = indexer.sync_stats_handle();
+        let shutdown = indexer.shutdown_handle();
+
+        let task = IndexerTask {
+            parser: indexer.parser(),
+            indexer,
+            downloader: None,
+            commands: command_receiver,
+            blocks: block_sender,
+            stats: stats.clone(),
+            paused: false,
+            target_height: None,
+            headers_height,
+            block_height,
+            headers_stale: true,
+            last_poll_time: Instant::now(),
+            retry_time: None,
+        };
+        let thread = thread::Builder::new()
+            .name("bitcoin-indexer".to_string())
+            .spawn(move || task.run())
+            .map_err(btc_error::Io)?;
+
+        Ok(IndexerHandle {
+            commands: command_sender,
+            blocks: block_receiver,
+            stats,
+            sync_stats,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl IndexerHandle {
+    /// Stop syncing once the current unit of work is done (e.g. once the block being downloaded
+    /// is handed over), until `resume()`.  A block waiting for room in a full queue is dropped
+    /// instead, and downloaded again after `resume()`.
+    pub fn pause(&self) {
+        self.send(IndexerCommand::Pause);
+    }
+
+    /// Carry on syncing after `pause()`, starting with asking the peer for new headers
+    pub fn resume(&self) {
+        self.send(IndexerCommand::Resume);
+    }
+
+    /// Sync up to `height`, and then wait there, instead of keeping up with the peer's chain
+    /// tip.  Blocks already handed over are not taken back if `height` is lower.
+    pub fn sync_to(&self, height: u64) {
+        self.send(IndexerCommand::SyncTo(height));
+    }
+
+    /// Get a snapshot of the indexer's progress.  It reflects commands only once the indexer
+    /// has gotten to them.
+    pub fn stats(&self) -> IndexerStats {
+        let mut stats = self
+            .stats
+            .lock()
+            .expect("BUG: indexer stats lock poisoned")
+            .clone();
+        stats.sync = self
+            .sync_stats
+            .lock()
+            .expect("BUG: sync stats lock poisoned")
+            .clone();
+        stats
+    }
+
+    /// Get the receiver of the indexer's blocks, in the order it downloads them.  Blocks queue
+    /// up here until they're received, and the indexer stops downloading while the queue is
+    /// full (see `BitcoinIndexer::spawn()`).
+    pub fn blocks(&self) -> &Receiver<BitcoinBlock> {
+        &self.blocks
+    }
+
+    /// Stop the indexer once the current unit of work is done (giving up on the peer after
+    /// `shutdown_timeout`, if it's waiting on it), and wait for its thread to exit.  Every
+    /// block it downloaded has been handed over by then, except one still waiting for room in
+    /// a full queue, and every header it synced is stored.
+    /// Returns the error that stopped the indexer, if one did.
+    pub fn shutdown(mut self) -> Result<(), burnchain_error> {
+        self.stop()
+    }
+
+    fn send(&self, command: IndexerCommand) {
+        // the indexer only hangs up once it has stopped, which the stats show
+        let _ = self.commands.send(command);
+    }
+
+    fn stop(&mut self) -> Result<(), burnchain_error> {
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return Ok(()),
+        };
+        self.send(IndexerCommand::Shutdown);
+        self.shutdown.store(true, Ordering::SeqCst);
+        thread.join().expect("BUG: indexer thread panicked")
+    }
+}
+
+impl Drop for IndexerHandle {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop() {
+            warn!("Indexer stopped with an error: {:?}", &e);
+        }
+    }
+}
+
+impl IndexerTask {
+    fn run(mut self) -> Result<(), burnchain_error> {
+        let res = self.sync_loop();
+        if let Err(ref e) = res {
+            error!("Indexer stopped: {:?}", e);
+        }
+        self.set_state(IndexerState::Stopped);
+        // hang up on the peer
+        self.downloader = None;
+        self.indexer.disconnect();
+        res
+    }
+
+    fn sync_loop(&mut self) -> Result<(), burnchain_error> {
+        loop {
+            // obey every command that came in during the last unit of work, and wait for the
+            // next one if there's nothing to do until it comes
+            let command = match self.idle_time() {
+                Some(wait) => match self.commands.recv_timeout(wait) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.headers_stale = true;
+                        None
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                },
+                None => match self.commands.try_recv() {
+                    Ok(command) => Some(command),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                },
+            };
+            if let Some(command) = command {
+                if !self.obey(command) {
+                    return Ok(());
+                }
+                continue;
+            }
+            if self.indexer.is_shutdown_requested() {
+                return Ok(());
+            }
+
+            let res = if self.headers_stale && self.wants_headers() {
+                self.sync_headers()
+            } else if self.block_height < self.last_wanted_height() {
+                self.download_next_block()
+            } else {
+                Ok(())
+            };
+            match res {
+                Ok(()) => {}
+                Err(burnchain_error::Bitcoin(btc_error::ShutdownRequested))
+                | Err(burnchain_error::DownloadError(btc_error::ShutdownRequested)) => {
+                    return Ok(());
+                }
+                Err(e) if IndexerTask::is_retryable(&e) => {
+                    warn!(
+                        "Indexer failed, will retry in {:?}: {:?}",
+                        SPAWNED_INDEXER_POLL_INTERVAL, &e
+                    );
+                    // start over on a new connection, and with the peer's latest headers
+                    self.downloader = None;
+                    self.headers_stale = true;
+                    self.retry_time = Some(Instant::now() + SPAWNED_INDEXER_POLL_INTERVAL);
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+            self.update_stats();
+        }
+    }
+
+    /// Obey a command from the handle.  Returns false if it's time to shut down.
+    fn obey(&mut self, command: IndexerCommand) -> bool {
+        debug!("Indexer command: {:?}", &command);
+        match command {
+            IndexerCommand::Pause => {
+                self.paused = true;
+            }
+            IndexerCommand::Resume => {
+                self.paused = false;
+                self.headers_stale = true;
+                self.retry_time = None;
+            }
+            IndexerCommand::SyncTo(height) => {
+                self.target_height = Some(height);
+            }
+            IndexerCommand::Shutdown => {
+                return false;
+            }
+        }
+        self.update_stats();
+        true
+    }
+
+    /// How long to wait for a command before doing any more work, or None to get on with it
+    fn idle_time(&self) -> Option<Duration> {
+        if self.paused {
+            return Some(SPAWNED_INDEXER_POLL_INTERVAL);
+        }
+        if let Some(retry_time) = self.retry_time {
+            let now = Instant::now();
+            if retry_time > now {
+                return Some(retry_time - now);
+            }
+        }
+        if self.headers_stale && self.wants_headers() {
+            return None;
+        }
+        if self.block_height < self.last_wanted_height() {
+            return None;
+        }
+        if !self.wants_headers() {
+            // we have everything up to the target height
+            return Some(SPAWNED_INDEXER_POLL_INTERVAL);
+        }
+        // poll the peer for new headers when it's time
+        let next_poll_time = self.last_poll_time + SPAWNED_INDEXER_POLL_INTERVAL;
+        Some(next_poll_time.saturating_duration_since(Instant::now()))
+    }
+
+    /// Do we need headers past the ones we have?
+    fn wants_headers(&self) -> bool {
+        match self.target_height {
+            Some(target_height) => self.headers_height < target_height,
+            None => true,
+        }
+    }
+
+    /// Height of the last block to download, given the headers we have
+    fn last_wanted_height(&self) -> u64 {
+        match self.target_height {
+            Some(target_height) => cmp::min(target_height, self.headers_height),
+            None => self.headers_height,
+        }
+    }
+
+    /// Get any new headers from the peer, and go back to the fork height if they reorged the
+    /// blocks we've handed over
+    fn sync_headers(&mut self) -> Result<(), burnchain_error> {
+        self.set_state(IndexerState::Syncing);
+        self.last_poll_time = Instant::now();
+        self.indexer
+            .sync_last_headers(self.headers_height, None)
+            .map_err(burnchain_error::Bitcoin)?;
+        self.headers_height = self.indexer.get_highest_header_height()?;
+        self.headers_stale = false;
+
+        if let Some(fork_height) = self.indexer.take_headers_reorg_height() {
+            if fork_height < self.block_height {
+                warn!(
+                    "Reorg at height {}; hand over blocks {}-{} again",
+                    fork_height,
+                    fork_height + 1,
+                    self.block_height
+                );
+                self.block_height = fork_height;
+            }
+        }
+        Ok(())
+    }
+
+    /// Download and parse the next block, and hand it over
+    fn download_next_block(&mut self) -> Result<(), burnchain_error> {
+        self.set_state(IndexerState::Syncing);
+        let height = self.block_height + 1;
+        let header = match self.indexer.read_headers(height, height + 1)?.pop() {
+            Some(header) => header,
+            None => {
+                // the headers were reorged or dropped under us
+                self.headers_height = self.indexer.get_highest_header_height()?;
+                self.headers_stale = true;
+                return Ok(());
+            }
+        };
+        if self.downloader.is_none() {
+            self.downloader = Some(self.indexer.downloader());
+        }
+        let ipc_block = self
+            .downloader
+            .as_mut()
+            .expect("BUG: no downloader")
+            .download(&header)?;
+
+        let epochs = self.indexer.get_stacks_epochs();
+        let epoch_id = match StacksEpoch::find_epoch(&epochs, height) {
+            Some(index) => epochs[index].epoch_id,
+            None => epochs[epochs.len() - 1].epoch_id,
+        };
+        let block = match self.parser.parse(&ipc_block, epoch_id)? {
+            BurnchainBlock::Bitcoin(block) => block,
+        };
+        if self.hand_over(block)? {
+            self.block_height = height;
+        }
+        Ok(())
+    }
+
+    /// Hand a block over to the handle, waiting for room in its queue, and obeying the commands
+    /// that come in meanwhile.  A block that's still waiting when the indexer is paused or told
+    /// to shut down is dropped, and downloaded again next time.
+    /// Returns whether or not the block was handed over.
+    fn hand_over(&mut self, mut block: BitcoinBlock) -> Result<bool, burnchain_error> {
+        loop {
+            match self.blocks.try_send(block) {
+                Ok(()) => return Ok(true),
+                Err(TrySendError::Full(unsent)) => {
+                    if self.indexer.is_shutdown_requested() {
+                        return Err(burnchain_error::Bitcoin(btc_error::ShutdownRequested));
+                    }
+                    match self.commands.recv_timeout(SHUTDOWN_POLL_INTERVAL) {
+                        Ok(command) => {
+                            if !self.obey(command) {
+                                return Err(burnchain_error::Bitcoin(btc_error::ShutdownRequested));
+                            }
+                            if self.paused {
+                                debug!(
+                                    "Paused while block {} waits for room; drop it",
+                                    unsent.block_height
+                                );
+                                return Ok(false);
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => {
+                            return Err(burnchain_error::Bitcoin(btc_error::ShutdownRequested));
+                        }
+                    }
+                    block = unsent;
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    // nobody wants the blocks any more
+                    return Err(burnchain_error::Bitcoin(btc_error::ShutdownRequested));
+                }
+            }
+        }
+    }
+
+    /// Is this error worth retrying after a while, rather than stopping over?
+    fn is_retryable(e: &burnchain_error) -> bool {
+        match e {
+            burnchain_error::TrySyncAgain => true,
+            burnchain_error::Bitcoin(e) | burnchain_error::DownloadError(e) => e.is_transient(),
+            _ => false,
+        }
+    }
+
+    fn set_state(&mut self, state: IndexerState) {
+        self.stats
+            .lock()
+            .expect("BUG: indexer stats lock poisoned")
+            .state = state;
+    }
+
+    /// Publish where we're at, and what we'll do next
+    fn update_stats(&mut self) {
+        let state = if self.paused {
+            IndexerState::Paused
+        } else if self.idle_time().is_some() {
+            IndexerState::Idle
+        } else {
+            IndexerState::Syncing
+        };
+        let mut stats = self.stats.lock().expect("BUG: indexer stats lock poisoned");
+        stats.state = state;
+        stats.headers_height = self.headers_height;
+        stats.block_height = self.block_height;
+        stats.target_height = self.target_height;
+    }
+}
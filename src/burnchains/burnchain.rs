@@ -14,16 +14,18 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cmp;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::convert::TryFrom;
+use std::fmt;
 use std::fs;
 use std::marker::Send;
 use std::path::PathBuf;
-use std::sync::mpsc::sync_channel;
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender};
 use std::sync::{
     atomic::{AtomicBool, AtomicU64, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 use std::thread;
 use std::time::{Duration, Instant};
@@ -36,7 +38,8 @@ use crate::burnchains::bitcoin::BitcoinNetworkType;
 use crate::burnchains::bitcoin::{BitcoinInputType, BitcoinTxInput, BitcoinTxOutput};
 use crate::burnchains::db::{BurnchainDB, BurnchainHeaderReader};
 use crate::burnchains::indexer::{
-    BurnBlockIPC, BurnHeaderIPC, BurnchainBlockDownloader, BurnchainBlockParser, BurnchainIndexer,
+    BurnBlockIPC, BurnHeaderIPC, BurnchainBlockDownloader, BurnchainBlockParser,
+    BurnchainEventObservers, BurnchainIndexer, BurnchainTip,
 };
 use crate::burnchains::Address;
 use crate::burnchains::Burnchain;
@@ -44,8 +47,8 @@ use crate::burnchains::PublicKey;
 use crate::burnchains::Txid;
 use crate::burnchains::{
     BurnchainBlock, BurnchainBlockHeader, BurnchainParameters, BurnchainRecipient, BurnchainSigner,
-    BurnchainStateTransition, BurnchainStateTransitionOps, BurnchainTransaction,
-    Error as burnchain_error, PoxConstants,
+    BurnchainStateTransition, BurnchainStateTransitionOps, BurnchainTransaction, DryRunReport,
+    Error as burnchain_error, PoxConstants, RejectedBurnchainOp,
 };
 use crate::chainstate::burn::db::sortdb::SortitionHandle;
 use crate::chainstate::burn::db::sortdb::{SortitionDB, SortitionHandleConn, SortitionHandleTx};
@@ -89,6 +92,10 @@ use crate::types::chainstate::{BurnchainHeaderHash, PoxId};
 
 use crate::chainstate::stacks::address::StacksAddressExtensions;
 
+/// How many parsed blocks can be waiting on slower blocks before them, per parser thread (see
+/// `Burnchain::parse_blocks()`)
+pub const MAX_PENDING_BLOCKS_PER_PARSER: usize = 4;
+
 impl BurnchainStateTransitionOps {
     pub fn noop() -> BurnchainStateTransitionOps {
         BurnchainStateTransitionOps {
@@ -397,6 +404,91 @@ impl BurnchainBlock {
     }
 }
 
+impl DryRunReport {
+    /// All the Blockstack operations' opcodes, in the order a report lists them
+    const OPCODES: [Opcodes; 7] = [
+        Opcodes::LeaderKeyRegister,
+        Opcodes::LeaderBlockCommit,
+        Opcodes::UserBurnSupport,
+        Opcodes::PreStx,
+        Opcodes::StackStx,
+        Opcodes::TransferStx,
+        Opcodes::DelegateStx,
+    ];
+
+    /// Name of the Blockstack operation type with `opcode`
+    pub fn op_type_name(opcode: u8) -> String {
+        match opcode {
+            x if x == Opcodes::LeaderKeyRegister as u8 => "leader_key_register".to_string(),
+            x if x == Opcodes::LeaderBlockCommit as u8 => "leader_block_commit".to_string(),
+            x if x == Opcodes::UserBurnSupport as u8 => "user_burn_support".to_string(),
+            x if x == Opcodes::PreStx as u8 => "pre_stx".to_string(),
+            x if x == Opcodes::StackStx as u8 => "stack_stx".to_string(),
+            x if x == Opcodes::TransferStx as u8 => "transfer_stx".to_string(),
+            x if x == Opcodes::DelegateStx as u8 => "delegate_stx".to_string(),
+            x => format!("unknown_{:02x}", x),
+        }
+    }
+
+    /// Count a checked block's valid operations, and add its rejected ones
+    pub fn add_block(
+        &mut self,
+        block_height: u64,
+        ops: &[BlockstackOperationType],
+        mut rejected_ops: Vec<RejectedBurnchainOp>,
+    ) {
+        if self.first_height.is_none() {
+            self.first_height = Some(block_height);
+        }
+        self.last_height = Some(block_height);
+        self.num_blocks += 1;
+        for op in ops.iter() {
+            *self
+                .op_counts
+                .entry(DryRunReport::op_type_name(op.opcode() as u8))
+                .or_insert(0) += 1;
+        }
+        rejected_ops.sort_by_key(|op| op.vtxindex);
+        self.rejected_ops.append(&mut rejected_ops);
+    }
+
+    /// Did the dry run reject any operations?
+    pub fn has_rejected_ops(&self) -> bool {
+        !self.rejected_ops.is_empty()
+    }
+}
+
+impl fmt::Display for DryRunReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.first_height, self.last_height) {
+            (Some(first_height), Some(last_height)) => writeln!(
+                f,
+                "blocks {}-{} ({} checked)",
+                first_height, last_height, self.num_blocks
+            )?,
+            _ => writeln!(f, "no blocks checked")?,
+        }
+        for opcode in DryRunReport::OPCODES.iter() {
+            let name = DryRunReport::op_type_name(opcode.clone() as u8);
+            let count = self.op_counts.get(&name).copied().unwrap_or(0);
+            writeln!(f, "ops {} {}", name, count)?;
+        }
+        writeln!(f, "rejected {}", self.rejected_ops.len())?;
+        for op in self.rejected_ops.iter() {
+            writeln!(
+                f,
+                "rejected {} {} {} {}: {}",
+                op.block_height,
+                op.vtxindex,
+                &op.txid,
+                DryRunReport::op_type_name(op.opcode),
+                &op.reason
+            )?;
+        }
+        Ok(())
+    }
+}
+
 impl Burnchain {
     pub fn new(
         working_dir: &str,
@@ -419,6 +511,11 @@ impl Burnchain {
                 PoxConstants::regtest_default(),
                 PEER_VERSION_TESTNET,
             ),
+            ("bitcoin", "signet") => (
+                BurnchainParameters::bitcoin_signet(),
+                PoxConstants::testnet_default(),
+                PEER_VERSION_TESTNET,
+            ),
             (_, _) => {
                 return Err(burnchain_error::UnsupportedBurnchain);
             }
@@ -639,6 +736,16 @@ impl Burnchain {
                 e
             })?;
         }
+
+        // there's nothing to process until the burnchain reaches the first block
+        let first_block_height = indexer.get_first_block_height();
+        let headers_height = indexer.get_highest_header_height()?;
+        if headers_height < first_block_height {
+            return Err(burnchain_error::FirstBlockNotReached(
+                first_block_height,
+                headers_height,
+            ));
+        }
         Ok(())
     }
 
@@ -740,163 +847,132 @@ impl Burnchain {
         burn_tx: &BurnchainTransaction,
         pre_stx_op_map: &HashMap<Txid, PreStxOp>,
     ) -> Option<BlockstackOperationType> {
-        match burn_tx.opcode() {
+        match Burnchain::check_transaction(
+            burnchain,
+            indexer,
+            burnchain_db,
+            block_header,
+            epoch_id,
+            burn_tx,
+            pre_stx_op_map,
+        ) {
+            Ok(op_opt) => op_opt,
+            Err(reason) => {
+                warn!(
+                    "Rejected burnchain transaction";
+                    "txid" => %burn_tx.txid(),
+                    "data" => %to_hex(&burn_tx.data()),
+                    "reason" => %reason,
+                );
+                None
+            }
+        }
+    }
+
+    /// Like `classify_transaction()`, but say why a transaction with a Blockstack operation's
+    /// opcode isn't a valid operation instead of logging it.
+    /// Returns Ok(None) if the transaction doesn't have a Blockstack operation's opcode.
+    pub fn check_transaction<B: BurnchainHeaderReader>(
+        burnchain: &Burnchain,
+        indexer: &B,
+        burnchain_db: &BurnchainDB,
+        block_header: &BurnchainBlockHeader,
+        epoch_id: StacksEpochId,
+        burn_tx: &BurnchainTransaction,
+        pre_stx_op_map: &HashMap<Txid, PreStxOp>,
+    ) -> Result<Option<BlockstackOperationType>, String> {
+        let op = match burn_tx.opcode() {
             x if x == Opcodes::LeaderKeyRegister as u8 => {
-                match LeaderKeyRegisterOp::from_tx(block_header, burn_tx) {
-                    Ok(op) => Some(BlockstackOperationType::LeaderKeyRegister(op)),
-                    Err(e) => {
-                        warn!(
-                            "Failed to parse leader key register tx";
-                            "txid" => %burn_tx.txid(),
-                            "data" => %to_hex(&burn_tx.data()),
-                            "error" => ?e,
-                        );
-                        None
-                    }
-                }
+                LeaderKeyRegisterOp::from_tx(block_header, burn_tx)
+                    .map(BlockstackOperationType::LeaderKeyRegister)
+                    .map_err(|e| format!("Failed to parse leader key register tx: {:?}", e))?
             }
             x if x == Opcodes::LeaderBlockCommit as u8 => {
-                match LeaderBlockCommitOp::from_tx(burnchain, block_header, epoch_id, burn_tx) {
-                    Ok(op) => Some(BlockstackOperationType::LeaderBlockCommit(op)),
-                    Err(e) => {
-                        warn!(
-                            "Failed to parse leader block commit tx";
-                            "txid" => %burn_tx.txid(),
-                            "data" => %to_hex(&burn_tx.data()),
-                            "error" => ?e,
-                        );
-                        None
-                    }
-                }
+                LeaderBlockCommitOp::from_tx(burnchain, block_header, epoch_id, burn_tx)
+                    .map(BlockstackOperationType::LeaderBlockCommit)
+                    .map_err(|e| format!("Failed to parse leader block commit tx: {:?}", e))?
             }
             x if x == Opcodes::UserBurnSupport as u8 => {
-                match UserBurnSupportOp::from_tx(block_header, burn_tx) {
-                    Ok(op) => Some(BlockstackOperationType::UserBurnSupport(op)),
-                    Err(e) => {
-                        warn!(
-                            "Failed to parse user burn support tx";
-                            "txid" => %burn_tx.txid(),
-                            "data" => %to_hex(&burn_tx.data()),
-                            "error" => ?e,
-                        );
-                        None
-                    }
-                }
-            }
-            x if x == Opcodes::PreStx as u8 => {
-                match PreStxOp::from_tx(
-                    block_header,
-                    epoch_id,
-                    burn_tx,
-                    burnchain.pox_constants.sunset_end,
-                ) {
-                    Ok(op) => Some(BlockstackOperationType::PreStx(op)),
-                    Err(e) => {
-                        warn!(
-                            "Failed to parse pre stack stx tx";
-                            "txid" => %burn_tx.txid(),
-                            "data" => %to_hex(&burn_tx.data()),
-                            "error" => ?e,
-                        );
-                        None
-                    }
-                }
+                UserBurnSupportOp::from_tx(block_header, burn_tx)
+                    .map(BlockstackOperationType::UserBurnSupport)
+                    .map_err(|e| format!("Failed to parse user burn support tx: {:?}", e))?
             }
+            x if x == Opcodes::PreStx as u8 => PreStxOp::from_tx(
+                block_header,
+                epoch_id,
+                burn_tx,
+                burnchain.pox_constants.sunset_end,
+            )
+            .map(BlockstackOperationType::PreStx)
+            .map_err(|e| format!("Failed to parse pre stack stx tx: {:?}", e))?,
             x if x == Opcodes::TransferStx as u8 => {
-                let pre_stx_txid = TransferStxOp::get_sender_txid(burn_tx).ok()?;
+                let pre_stx_txid = TransferStxOp::get_sender_txid(burn_tx)
+                    .map_err(|e| format!("Failed to get transfer stx sender: {:?}", e))?;
                 let pre_stx_tx = match pre_stx_op_map.get(&pre_stx_txid) {
                     Some(tx_ref) => Some(BlockstackOperationType::PreStx(tx_ref.clone())),
                     None => burnchain_db.find_burnchain_op(indexer, pre_stx_txid),
                 };
                 if let Some(BlockstackOperationType::PreStx(pre_stx)) = pre_stx_tx {
                     let sender = &pre_stx.output;
-                    match TransferStxOp::from_tx(block_header, burn_tx, sender) {
-                        Ok(op) => Some(BlockstackOperationType::TransferStx(op)),
-                        Err(e) => {
-                            warn!(
-                                "Failed to parse transfer stx tx";
-                                "txid" => %burn_tx.txid(),
-                                "data" => %to_hex(&burn_tx.data()),
-                                "error" => ?e,
-                            );
-                            None
-                        }
-                    }
+                    TransferStxOp::from_tx(block_header, burn_tx, sender)
+                        .map(BlockstackOperationType::TransferStx)
+                        .map_err(|e| format!("Failed to parse transfer stx tx: {:?}", e))?
                 } else {
-                    warn!(
-                        "Failed to find corresponding input to TransferStxOp";
-                        "txid" => %burn_tx.txid(),
-                        "pre_stx_txid" => %pre_stx_txid
-                    );
-                    None
+                    return Err(format!(
+                        "Failed to find corresponding input to TransferStxOp {}",
+                        &pre_stx_txid
+                    ));
                 }
             }
             x if x == Opcodes::StackStx as u8 => {
-                let pre_stx_txid = StackStxOp::get_sender_txid(burn_tx).ok()?;
+                let pre_stx_txid = StackStxOp::get_sender_txid(burn_tx)
+                    .map_err(|e| format!("Failed to get stack stx sender: {:?}", e))?;
                 let pre_stx_tx = match pre_stx_op_map.get(&pre_stx_txid) {
                     Some(tx_ref) => Some(BlockstackOperationType::PreStx(tx_ref.clone())),
                     None => burnchain_db.find_burnchain_op(indexer, pre_stx_txid),
                 };
                 if let Some(BlockstackOperationType::PreStx(pre_stack_stx)) = pre_stx_tx {
                     let sender = &pre_stack_stx.output;
-                    match StackStxOp::from_tx(
+                    StackStxOp::from_tx(
                         block_header,
                         epoch_id,
                         burn_tx,
                         sender,
                         burnchain.pox_constants.sunset_end,
-                    ) {
-                        Ok(op) => Some(BlockstackOperationType::StackStx(op)),
-                        Err(e) => {
-                            warn!(
-                                "Failed to parse stack stx tx";
-                                "txid" => %burn_tx.txid(),
-                                "data" => %to_hex(&burn_tx.data()),
-                                "error" => ?e,
-                            );
-                            None
-                        }
-                    }
+                    )
+                    .map(BlockstackOperationType::StackStx)
+                    .map_err(|e| format!("Failed to parse stack stx tx: {:?}", e))?
                 } else {
-                    warn!(
-                        "Failed to find corresponding input to StackStxOp";
-                        "txid" => %burn_tx.txid().to_string(),
-                        "pre_stx_txid" => %pre_stx_txid.to_string()
-                    );
-                    None
+                    return Err(format!(
+                        "Failed to find corresponding input to StackStxOp {}",
+                        &pre_stx_txid
+                    ));
                 }
             }
             x if x == Opcodes::DelegateStx as u8 => {
-                let pre_stx_txid = DelegateStxOp::get_sender_txid(burn_tx).ok()?;
+                let pre_stx_txid = DelegateStxOp::get_sender_txid(burn_tx)
+                    .map_err(|e| format!("Failed to get delegate stx sender: {:?}", e))?;
                 let pre_stx_tx = match pre_stx_op_map.get(&pre_stx_txid) {
                     Some(tx_ref) => Some(BlockstackOperationType::PreStx(tx_ref.clone())),
                     None => burnchain_db.find_burnchain_op(indexer, pre_stx_txid),
                 };
                 if let Some(BlockstackOperationType::PreStx(pre_stx)) = pre_stx_tx {
                     let sender = &pre_stx.output;
-                    match DelegateStxOp::from_tx(block_header, burn_tx, sender) {
-                        Ok(op) => Some(BlockstackOperationType::DelegateStx(op)),
-                        Err(e) => {
-                            warn!(
-                                "Failed to parse delegate stx tx";
-                                "txid" => %burn_tx.txid(),
-                                "data" => %to_hex(&burn_tx.data()),
-                                "error" => ?e,
-                            );
-                            None
-                        }
-                    }
+                    DelegateStxOp::from_tx(block_header, burn_tx, sender)
+                        .map(BlockstackOperationType::DelegateStx)
+                        .map_err(|e| format!("Failed to parse delegate stx tx: {:?}", e))?
                 } else {
-                    warn!(
-                        "Failed to find corresponding input to DelegateStxOp";
-                        "txid" => %burn_tx.txid().to_string(),
-                        "pre_stx_txid" => %pre_stx_txid.to_string()
-                    );
-                    None
+                    return Err(format!(
+                        "Failed to find corresponding input to DelegateStxOp {}",
+                        &pre_stx_txid
+                    ));
                 }
             }
-            _ => None,
-        }
+            _ => {
+                return Ok(None);
+            }
+        };
+        Ok(Some(op))
     }
 
     /// Sanity check -- a list of checked ops is sorted and all vtxindexes are unique
@@ -961,8 +1037,23 @@ impl Burnchain {
             &block.block_hash()
         );
 
-        let _blockstack_txs =
-            burnchain_db.store_new_burnchain_block(burnchain, indexer, &block, epoch_id)?;
+        // a block whose operations were committed before we were last stopped, but which was
+        // not marked as processed, only needs its affirmation maps brought up-to-date.
+        if BurnchainDB::has_burnchain_block(burnchain_db.conn(), &block.block_hash())? {
+            debug!(
+                "Already stored block {} {}",
+                block.block_height(),
+                &block.block_hash()
+            );
+        } else if !Burnchain::affirmation_maps_due(burnchain, block.block_height()) {
+            // nothing more to do once the block is stored, so mark it processed as it's stored
+            let _blockstack_txs = burnchain_db
+                .store_processed_burnchain_block(burnchain, indexer, &block, epoch_id)?;
+            return Ok(block.header());
+        } else {
+            let _blockstack_txs =
+                burnchain_db.store_new_burnchain_block(burnchain, indexer, &block, epoch_id)?;
+        }
         Burnchain::process_affirmation_maps(
             burnchain,
             burnchain_db,
@@ -971,9 +1062,38 @@ impl Burnchain {
         )?;
 
         let header = block.header();
+        burnchain_db.set_processed_block(&header)?;
         Ok(header)
     }
 
+    /// Get the Blockstack operations in `block`, exactly as `process_block()` would store them,
+    /// but without writing anything to `burnchain_db` or moving its processed height.
+    pub fn dry_run_block<B: BurnchainHeaderReader>(
+        burnchain: &Burnchain,
+        burnchain_db: &BurnchainDB,
+        indexer: &B,
+        block: &BurnchainBlock,
+        epoch_id: StacksEpochId,
+    ) -> Vec<BlockstackOperationType> {
+        debug!(
+            "Dry-run block {} {}",
+            block.block_height(),
+            &block.block_hash()
+        );
+        burnchain_db.get_new_burnchain_block_ops(burnchain, indexer, block, epoch_id)
+    }
+
+    /// Does processing the block at `block_height` update affirmation maps (see
+    /// `process_affirmation_maps()`)?  Only if it's the first block of a reward cycle.
+    fn affirmation_maps_due(burnchain: &Burnchain, block_height: u64) -> bool {
+        burnchain
+            .block_height_to_reward_cycle(block_height)
+            .unwrap_or(0)
+            != burnchain
+                .block_height_to_reward_cycle(block_height.saturating_sub(1))
+                .unwrap_or(0)
+    }
+
     /// Update the affirmation maps for the previous reward cycle's commits.
     /// This is a no-op unless the given burnchain block height falls on a reward cycle boundary.  In that
     /// case, the previous reward cycle's block commits' affirmation maps are all re-calculated.
@@ -1050,6 +1170,59 @@ impl Burnchain {
         )
     }
 
+    /// Check that a reorg to a fork at `fork_height`, which would take the stored blocks above it
+    /// up to `old_tip` off the canonical chain, is no deeper than `indexer.max_reorg_depth()`.
+    /// A reorg that deep is more likely an attack or a misconfigured network than bitcoin at
+    /// work, so unless an operator already accepted it, it's refused with `Error::DeepReorg`
+    /// and reported to the event observers.  The caller must leave the burnchain DB alone in
+    /// that case, so the fork is found again when the sync is retried.
+    pub fn check_reorg_depth<I: BurnchainIndexer>(
+        indexer: &mut I,
+        event_observers: &BurnchainEventObservers,
+        old_tip: &BurnchainTip,
+        fork_height: u64,
+    ) -> Result<(), burnchain_error> {
+        let depth = old_tip.0.saturating_sub(fork_height);
+        let max_depth = match indexer.max_reorg_depth() {
+            Some(max_depth) if depth > max_depth => max_depth,
+            _ => return Ok(()),
+        };
+        if indexer.take_deep_reorg_acceptance() {
+            warn!("Processing accepted burnchain reorg deeper than the maximum";
+                  "fork_height" => fork_height,
+                  "depth" => depth,
+                  "max_reorg_depth" => max_depth);
+            return Ok(());
+        }
+        Err(Burnchain::refuse_deep_reorg(
+            event_observers,
+            old_tip,
+            fork_height,
+            max_depth,
+        ))
+    }
+
+    /// Refuse a reorg to a fork at `fork_height` that is deeper than `max_depth` blocks below
+    /// `old_tip` (see `check_reorg_depth()`):  log it, report it to the event observers, and
+    /// get the `Error::DeepReorg` to halt the sync with.  An indexer that finds such a reorg
+    /// while syncing headers refuses it here too, before it changes them.
+    pub fn refuse_deep_reorg(
+        event_observers: &BurnchainEventObservers,
+        old_tip: &BurnchainTip,
+        fork_height: u64,
+        max_depth: u64,
+    ) -> burnchain_error {
+        let depth = old_tip.0.saturating_sub(fork_height);
+        error!("Refusing burnchain reorg deeper than the maximum; accept it to resume the sync";
+               "old_tip_height" => old_tip.0,
+               "old_tip_hash" => %old_tip.1,
+               "fork_height" => fork_height,
+               "depth" => depth,
+               "max_reorg_depth" => max_depth);
+        event_observers.deep_reorg_refused(old_tip, fork_height, depth, max_depth);
+        burnchain_error::DeepReorg(depth, max_depth)
+    }
+
     /// Determine if there has been a chain reorg, given our current canonical burnchain tip.
     /// Return the new chain tip and a boolean signaling the presence of a reorg
     fn sync_reorg<I: BurnchainIndexer>(indexer: &mut I) -> Result<(u64, bool), burnchain_error> {
@@ -1088,6 +1261,87 @@ impl Burnchain {
         }
     }
 
+    /// Determine if syncing headers reorged them below `sync_height`.
+    /// Return the height to sync from -- the fork height if so, and `sync_height` otherwise.
+    fn sync_headers_reorg<I: BurnchainIndexer>(indexer: &mut I, sync_height: u64) -> u64 {
+        match indexer.take_headers_reorg_height() {
+            Some(reorg_height) if reorg_height < sync_height => {
+                warn!(
+                    "Burnchain reorg detected while syncing headers: highest common ancestor at height {}",
+                    reorg_height
+                );
+                reorg_height
+            }
+            _ => sync_height,
+        }
+    }
+
+    /// After a burnchain reorg whose highest common ancestor is at `fork_height`, drop the
+    /// burnchain DB's blocks above it, which are on the old branch.  The new branch's blocks are
+    /// downloaded and processed in their place by the next sync.  `sync_with_indexer()` only
+    /// does this if `indexer.drop_reorged_blocks()` opts in:  the burnchain DB is forkable, so
+    /// by default the old branch's blocks are kept, and everything that reads the DB follows
+    /// the canonical chain tip, which moves to the new branch once it's the longer one.
+    /// Returns the lowest and highest heights dropped, or None if there were none.
+    pub fn invalidate_reorged_blocks(
+        &self,
+        burnchain_db: &mut BurnchainDB,
+        fork_height: u64,
+    ) -> Result<Option<(u64, u64)>, burnchain_error> {
+        // we never have blocks below the first block to fall back on
+        let fork_height = cmp::max(fork_height, self.first_block_height);
+        let tip_height = burnchain_db.get_canonical_chain_tip()?.block_height;
+        if tip_height <= fork_height {
+            return Ok(None);
+        }
+
+        warn!(
+            "Invalidate burnchain blocks {} through {}, which a reorg took off the canonical chain",
+            fork_height + 1,
+            tip_height
+        );
+        burnchain_db.drop_blocks_above(fork_height)?;
+        Ok(Some((fork_height + 1, tip_height)))
+    }
+
+    /// Check that the highest block `burnchain_db` has processed is still on the chain of
+    /// `headers`.  It won't be if the headers were reorged after the blocks above the fork were
+    /// processed, but before they could be invalidated (e.g. because we were stopped in
+    /// between).  If so, returns the height of the highest block in `burnchain_db` that is on
+    /// the chain of `headers` -- the fork height; otherwise returns None.
+    pub fn find_processed_block_fork<B: BurnchainHeaderReader>(
+        &self,
+        burnchain_db: &BurnchainDB,
+        headers: &B,
+    ) -> Result<Option<u64>, burnchain_error> {
+        let (processed_height, processed_hash) = match burnchain_db.get_processed_block()? {
+            (height, Some(hash)) if height > self.first_block_height => (height, hash),
+            // the first block is never reorged, and there's nothing to check if the hash wasn't
+            // recorded
+            _ => return Ok(None),
+        };
+        match headers.read_burnchain_header(processed_height)? {
+            Some(header) if header.block_hash != processed_hash => {}
+            // either still on the chain, or we don't have the header at its height to tell
+            _ => return Ok(None),
+        }
+
+        let mut fork_height = processed_height - 1;
+        while fork_height > self.first_block_height {
+            if let Some(header) = headers.read_burnchain_header(fork_height)? {
+                if BurnchainDB::has_burnchain_block(burnchain_db.conn(), &header.block_hash)? {
+                    break;
+                }
+            }
+            fork_height -= 1;
+        }
+        warn!(
+            "Last processed burnchain block {} at height {} is no longer on the canonical chain; highest common ancestor at height {}",
+            &processed_hash, processed_height, fork_height
+        );
+        Ok(Some(fork_height))
+    }
+
     /// Top-level burnchain sync.
     /// Returns new latest block height.
     pub fn sync<I: BurnchainIndexer + BurnchainHeaderReader + 'static + Send>(
@@ -1160,6 +1414,7 @@ impl Burnchain {
 
         debug!("Sync headers from {}", highest_header);
         let end_block = indexer.sync_headers(highest_header, None)?;
+        let sync_height = Burnchain::sync_headers_reorg(indexer, sync_height);
         let mut start_block = sync_height;
         if db_height < start_block {
             start_block = db_height;
@@ -1270,6 +1525,7 @@ impl Burnchain {
                     &parser_indexer,
                     &burnchain_block,
                 )?;
+                parser_indexer.report_block_processed(&burnchain_block.header());
                 last_processed = (tip, Some(transition));
                 let insert_end = get_epoch_time_ms();
 
@@ -1365,11 +1621,140 @@ impl Burnchain {
         Ok(Some(burn_chain_tip))
     }
 
+    /// Parse the blocks received from `block_recv` on one thread per parser in `parsers`, and
+    /// send the parsed blocks to `block_send` in the order they were received (i.e. by height),
+    /// just as a single parser thread would.  Blocks that finish parsing before the blocks ahead
+    /// of them are held until those are sent, and at most `max_pending` blocks are handed out to
+    /// the parsers but not yet sent, so a slow block holds up the blocks after it instead of
+    /// letting them pile up.  Each block is parsed in its epoch in `epochs`.
+    /// Once the last block is sent (or `block_recv` hangs up), None is sent.  If a block fails to
+    /// parse, the blocks before it are sent, and its error is returned instead.
+    pub fn parse_blocks<P>(
+        parsers: Vec<P>,
+        epochs: Vec<StacksEpoch>,
+        block_recv: Receiver<
+            Option<<<P as BurnchainBlockParser>::D as BurnchainBlockDownloader>::B>,
+        >,
+        block_send: SyncSender<Option<BurnchainBlock>>,
+        max_pending: usize,
+    ) -> Result<(), burnchain_error>
+    where
+        P: BurnchainBlockParser + Send + 'static,
+        <<P as BurnchainBlockParser>::D as BurnchainBlockDownloader>::B: 'static,
+    {
+        assert!(!parsers.is_empty(), "No burnchain block parsers");
+
+        let (work_send, work_recv) = channel::<(
+            u64,
+            <<P as BurnchainBlockParser>::D as BurnchainBlockDownloader>::B,
+        )>();
+        let work_recv = Arc::new(Mutex::new(work_recv));
+        let (parsed_send, parsed_recv) = channel();
+        // holds one token for each block handed out but not yet sent
+        let (pending_send, pending_recv) = sync_channel::<()>(cmp::max(max_pending, 1));
+        let epochs = Arc::new(epochs);
+
+        let mut parse_threads = vec![];
+        for (i, mut parser) in parsers.into_iter().enumerate() {
+            let work_recv = work_recv.clone();
+            let parsed_send = parsed_send.clone();
+            let epochs = epochs.clone();
+            let parse_thread = thread::Builder::new()
+                .name(format!("burnchain-parser-{}", i))
+                .spawn(move || loop {
+                    let next_block = work_recv
+                        .lock()
+                        .expect("FATAL: burnchain parser queue lock poisoned")
+                        .recv();
+                    let (seq, ipc_block) = match next_block {
+                        Ok(x) => x,
+                        Err(_) => break,
+                    };
+
+                    let block_height = ipc_block.height();
+                    let epoch_index = StacksEpoch::find_epoch(&epochs, block_height).expect(
+                        &format!("FATAL: no epoch defined for height {}", block_height),
+                    );
+                    let epoch_id = epochs[epoch_index].epoch_id;
+
+                    let parse_start = get_epoch_time_ms();
+                    let parsed = parser.parse(&ipc_block, epoch_id);
+                    let parse_end = get_epoch_time_ms();
+
+                    debug!(
+                        "Parsed block {} (in epoch {}) in {}ms",
+                        block_height,
+                        epoch_id,
+                        parse_end.saturating_sub(parse_start)
+                    );
+
+                    if parsed_send.send((seq, parsed)).is_err() {
+                        break;
+                    }
+                })
+                .unwrap();
+            parse_threads.push(parse_thread);
+        }
+        drop(parsed_send);
+
+        let sequencer_thread: thread::JoinHandle<Result<(), burnchain_error>> =
+            thread::Builder::new()
+                .name("burnchain-parse-sequencer".to_string())
+                .spawn(move || {
+                    let mut parsed_blocks = HashMap::new();
+                    let mut next_seq: u64 = 0;
+                    while let Ok((seq, parsed)) = parsed_recv.recv() {
+                        parsed_blocks.insert(seq, parsed);
+                        while let Some(parsed) = parsed_blocks.remove(&next_seq) {
+                            block_send
+                                .send(Some(parsed?))
+                                .map_err(|_e| burnchain_error::ThreadChannelError)?;
+                            next_seq += 1;
+                            pending_recv
+                                .recv()
+                                .map_err(|_e| burnchain_error::ThreadChannelError)?;
+                        }
+                    }
+                    if !parsed_blocks.is_empty() {
+                        // a parser thread died with a block
+                        return Err(burnchain_error::ThreadChannelError);
+                    }
+                    block_send
+                        .send(None)
+                        .map_err(|_e| burnchain_error::ThreadChannelError)?;
+                    Ok(())
+                })
+                .unwrap();
+
+        let mut seq: u64 = 0;
+        while let Ok(Some(ipc_block)) = block_recv.recv() {
+            debug!("Try recv next block");
+
+            // wait for a free slot.  This fails only if the sequencer stopped.
+            if pending_send.send(()).is_err() {
+                break;
+            }
+            if work_send.send((seq, ipc_block)).is_err() {
+                break;
+            }
+            seq += 1;
+        }
+        drop(work_send);
+
+        for parse_thread in parse_threads.into_iter() {
+            parse_thread.join().unwrap();
+        }
+        sequencer_thread.join().unwrap()
+    }
+
     /// Top-level burnchain sync.
     /// Returns the burnchain block header for the new burnchain tip, which will be _at least_ as
     /// high as target_block_height_opt (if given), or whatever is currently at the tip of the
     /// burnchain DB.
     /// If this method returns Err(burnchain_error::TrySyncAgain), then call this method again.
+    /// If `indexer.dry_run()` is set, the burnchain DB (which must already exist) is only read:
+    /// the blocks above its processed height are checked but not stored, what was found in them
+    /// goes to `indexer.report_dry_run()`, and the returned header is the highest one checked.
     pub fn sync_with_indexer<I>(
         &mut self,
         indexer: &mut I,
@@ -1382,12 +1767,18 @@ impl Burnchain {
         I: BurnchainIndexer + BurnchainHeaderReader + 'static + Send,
     {
         self.setup_chainstate(indexer)?;
-        let (sortdb, mut burnchain_db) = self.connect_db(
-            true,
-            indexer.get_first_block_header_hash()?,
-            indexer.get_first_block_header_timestamp()?,
-            indexer.get_stacks_epochs(),
-        )?;
+        let dry_run = indexer.dry_run();
+        let event_observers = indexer.event_observers();
+        let (sortdb, mut burnchain_db) = if dry_run {
+            self.open_db(false)?
+        } else {
+            self.connect_db(
+                true,
+                indexer.get_first_block_header_hash()?,
+                indexer.get_first_block_header_timestamp()?,
+                indexer.get_stacks_epochs(),
+            )?
+        };
 
         let burn_chain_tip = burnchain_db.get_canonical_chain_tip().map_err(|e| {
             error!("Failed to query burn chain tip from burn DB: {}", e);
@@ -1395,8 +1786,14 @@ impl Burnchain {
         })?;
 
         let db_height = burn_chain_tip.block_height;
+        let processed_height = burnchain_db.get_processed_height()?;
 
-        // handle reorgs (which also updates our best-known chain work and headers DB)
+        // handle reorgs (which also updates our best-known chain work and headers DB).  The
+        // indexer measures them against our tip, so it keeps its headers off of a branch we'd
+        // refuse to follow.  A dry run follows any branch.
+        if !dry_run {
+            indexer.set_burnchain_tip(&(db_height, burn_chain_tip.block_hash.clone()));
+        }
         let (sync_height, did_reorg) = Burnchain::sync_reorg(indexer)?;
         if did_reorg {
             // a reorg happened
@@ -1412,7 +1809,71 @@ impl Burnchain {
         // fetch all new headers
         let highest_header_height = indexer.get_highest_header_height()?;
         let mut end_block = indexer.sync_headers(highest_header_height, None)?;
-        if did_reorg && sync_height > 0 {
+        let headers_sync_height = Burnchain::sync_headers_reorg(indexer, sync_height);
+        let did_reorg = did_reorg || headers_sync_height < sync_height;
+        let sync_height = headers_sync_height;
+
+        // the blocks we processed last time may be on a branch that was reorged away since,
+        // even if our headers didn't just reorg (e.g. if we were stopped before we could
+        // invalidate them)
+        let fork_height = match self.find_processed_block_fork(&burnchain_db, indexer)? {
+            Some(processed_fork_height) => Some(cmp::min(processed_fork_height, sync_height)),
+            None if did_reorg => Some(sync_height),
+            None => None,
+        };
+        let (burn_chain_tip, db_height, processed_height) = match fork_height {
+            // a dry run checks the new branch's blocks, but leaves the old branch's stored
+            Some(fork_height) if dry_run => (
+                burn_chain_tip,
+                cmp::min(db_height, fork_height),
+                cmp::min(processed_height, fork_height),
+            ),
+            // the burnchain DB's blocks above the fork are on the old branch
+            Some(fork_height) => {
+                let old_tip = (db_height, burn_chain_tip.block_hash.clone());
+                Burnchain::check_reorg_depth(indexer, &event_observers, &old_tip, fork_height)?;
+
+                // we never have blocks below the first block to fall back on
+                let first_height = cmp::max(fork_height, self.first_block_height) + 1;
+                if first_height <= db_height {
+                    indexer.report_burn_blocks_invalidated(first_height, db_height);
+                    if let Some(hdr) = indexer.read_headers(end_block, end_block + 1)?.pop() {
+                        let new_tip = (
+                            end_block,
+                            BurnchainHeaderHash::from_bitcoin_hash(&BitcoinSha256dHash(
+                                hdr.header_hash(),
+                            )),
+                        );
+                        event_observers.reorg_detected(
+                            &old_tip,
+                            &new_tip,
+                            db_height + 1 - first_height,
+                        );
+                    }
+                }
+
+                if indexer.drop_reorged_blocks()
+                    && self
+                        .invalidate_reorged_blocks(&mut burnchain_db, fork_height)?
+                        .is_some()
+                {
+                    let burn_chain_tip = burnchain_db.get_canonical_chain_tip()?;
+                    let db_height = burn_chain_tip.block_height;
+                    (
+                        burn_chain_tip,
+                        db_height,
+                        burnchain_db.get_processed_height()?,
+                    )
+                } else {
+                    // the old branch's blocks stay, and the canonical chain tip stays on them
+                    // until the new branch outgrows them
+                    (burn_chain_tip, db_height, processed_height)
+                }
+            }
+            None => (burn_chain_tip, db_height, processed_height),
+        };
+
+        if fork_height.is_some() && sync_height > 0 {
             // a reorg happened, and the last header fetched
             // is on a smaller fork than the one we just
             // invalidated. Wait for more blocks.
@@ -1434,9 +1895,18 @@ impl Burnchain {
             start_block = db_height;
         }
 
+        // resume from the last block we finished processing, which may be one block behind the
+        // DB tip if we were stopped before its affirmation maps were updated.
+        if processed_height < start_block {
+            start_block = processed_height;
+        }
+
+        // blocks at and below the first block are never downloaded
+        start_block = cmp::max(start_block, indexer.get_first_block_height());
+
         debug!(
-            "Sync'ed headers from {} to {}. DB at {}",
-            highest_header_height, end_block, db_height
+            "Sync'ed headers from {} to {}. DB at {}, processed up to {}",
+            highest_header_height, end_block, db_height, processed_height
         );
 
         if let Some(target_block_height) = target_block_height_opt {
@@ -1480,13 +1950,23 @@ impl Burnchain {
                 let bhh =
                     BurnchainHeaderHash::from_bitcoin_hash(&BitcoinSha256dHash(hdr.header_hash()));
 
-                return BurnchainDB::get_burnchain_block(burnchain_db.conn(), &bhh)
-                    .map(|block_data| block_data.header);
+                let header = BurnchainDB::get_burnchain_block(burnchain_db.conn(), &bhh)?.header;
+                if !dry_run {
+                    event_observers
+                        .sync_completed(&(header.block_height, header.block_hash.clone()));
+                }
+                return Ok(header);
             }
         }
 
         if start_block == db_height && db_height == end_block {
             // all caught up
+            if !dry_run {
+                event_observers.sync_completed(&(
+                    burn_chain_tip.block_height,
+                    burn_chain_tip.block_hash.clone(),
+                ));
+            }
             return Ok(burn_chain_tip);
         }
 
@@ -1503,16 +1983,18 @@ impl Burnchain {
         let (db_send, db_recv) = sync_channel(1);
 
         let mut downloader = indexer.downloader();
-        let mut parser = indexer.parser();
+        let num_parser_threads = cmp::max(indexer.num_parser_threads(), 1);
+        let parsers: Vec<_> = (0..num_parser_threads).map(|_| indexer.parser()).collect();
 
         let myself = self.clone();
         let input_headers = indexer.read_headers(start_block + 1, end_block + 1)?;
         let parser_indexer = indexer.reader();
+        let shutdown = indexer.shutdown_handle();
+        let downloader_shutdown = shutdown.clone();
 
-        let epochs = {
-            let (sortdb, _) = self.open_db(false)?;
-            SortitionDB::get_stacks_epochs(sortdb.conn())?
-        };
+        let epochs = SortitionDB::get_stacks_epochs(sortdb.conn())?;
+        let parser_epochs = epochs.clone();
+        let db_event_observers = event_observers.clone();
 
         // TODO: don't re-process blocks.  See if the block hash is already present in the burn db,
         // and if so, do nothing.
@@ -1520,32 +2002,49 @@ impl Burnchain {
             thread::Builder::new()
                 .name("burnchain-downloader".to_string())
                 .spawn(move || {
-                    while let Ok(Some(ipc_header)) = downloader_recv.recv() {
+                    let mut stop_result = Ok(());
+                    let mut headers = std::iter::from_fn(|| {
                         debug!("Try recv next header");
+                        let ipc_header = downloader_recv.recv().ok()??;
 
                         match should_keep_running {
                             Some(ref should_keep_running)
                                 if !should_keep_running.load(Ordering::SeqCst) =>
                             {
-                                return Err(burnchain_error::CoordinatorClosed);
+                                stop_result = Err(burnchain_error::CoordinatorClosed);
+                                return None;
                             }
                             _ => {}
                         };
 
-                        let download_start = get_epoch_time_ms();
-                        let ipc_block = downloader.download(&ipc_header)?;
-                        let download_end = get_epoch_time_ms();
+                        if downloader_shutdown.load(Ordering::SeqCst) {
+                            // let the parser and DB threads finish the blocks already downloaded
+                            info!(
+                                "Shutdown requested; stop downloading burnchain blocks at {}",
+                                ipc_header.height()
+                            );
+                            return None;
+                        }
+                        Some(ipc_header)
+                    });
 
+                    let mut download_start = get_epoch_time_ms();
+                    downloader.download_all(&mut headers, &mut |ipc_block| {
+                        let download_end = get_epoch_time_ms();
                         debug!(
                             "Downloaded block {} in {}ms",
                             ipc_block.height(),
                             download_end.saturating_sub(download_start)
                         );
+                        download_start = download_end;
 
                         parser_send
                             .send(Some(ipc_block))
-                            .map_err(|_e| burnchain_error::ThreadChannelError)?;
-                    }
+                            .map_err(|_e| burnchain_error::ThreadChannelError)
+                    })?;
+                    drop(headers);
+                    stop_result?;
+
                     parser_send
                         .send(None)
                         .map_err(|_e| burnchain_error::ThreadChannelError)?;
@@ -1556,79 +2055,100 @@ impl Burnchain {
         let parse_thread: thread::JoinHandle<Result<(), burnchain_error>> = thread::Builder::new()
             .name("burnchain-parser".to_string())
             .spawn(move || {
-                while let Ok(Some(ipc_block)) = parser_recv.recv() {
-                    debug!("Try recv next block");
-
-                    let cur_epoch =
-                        SortitionDB::get_stacks_epoch(sortdb.conn(), ipc_block.height())?.expect(
-                            &format!("FATAL: no stacks epoch defined for {}", ipc_block.height()),
-                        );
-
-                    let parse_start = get_epoch_time_ms();
-                    let burnchain_block = parser.parse(&ipc_block, cur_epoch.epoch_id)?;
-                    let parse_end = get_epoch_time_ms();
-
-                    debug!(
-                        "Parsed block {} (in epoch {}) in {}ms",
-                        burnchain_block.block_height(),
-                        cur_epoch.epoch_id,
-                        parse_end.saturating_sub(parse_start)
-                    );
-
-                    db_send
-                        .send(Some(burnchain_block))
-                        .map_err(|_e| burnchain_error::ThreadChannelError)?;
-                }
-                db_send
-                    .send(None)
-                    .map_err(|_e| burnchain_error::ThreadChannelError)?;
-                Ok(())
+                Burnchain::parse_blocks(
+                    parsers,
+                    parser_epochs,
+                    parser_recv,
+                    db_send,
+                    num_parser_threads * MAX_PENDING_BLOCKS_PER_PARSER,
+                )
             })
             .unwrap();
 
-        let db_thread: thread::JoinHandle<Result<BurnchainBlockHeader, burnchain_error>> =
-            thread::Builder::new()
-                .name("burnchain-db".to_string())
-                .spawn(move || {
-                    let mut last_processed = burn_chain_tip;
-                    while let Ok(Some(burnchain_block)) = db_recv.recv() {
-                        debug!("Try recv next parsed block");
-
-                        let block_height = burnchain_block.block_height();
-                        if block_height == 0 {
-                            continue;
-                        }
+        let db_thread: thread::JoinHandle<
+            Result<(BurnchainBlockHeader, Option<DryRunReport>), burnchain_error>,
+        > = thread::Builder::new()
+            .name("burnchain-db".to_string())
+            .spawn(move || {
+                let mut last_processed = burn_chain_tip;
+                let mut dry_run_report = if dry_run {
+                    Some(DryRunReport::default())
+                } else {
+                    None
+                };
+                while let Ok(Some(burnchain_block)) = db_recv.recv() {
+                    debug!("Try recv next parsed block");
 
-                        let epoch_index = StacksEpoch::find_epoch(&epochs, block_height).expect(
-                            &format!("FATAL: no epoch defined for height {}", block_height),
-                        );
+                    let block_height = burnchain_block.block_height();
+                    if block_height == 0 {
+                        continue;
+                    }
 
-                        let epoch_id = epochs[epoch_index].epoch_id;
+                    let epoch_index = StacksEpoch::find_epoch(&epochs, block_height).expect(
+                        &format!("FATAL: no epoch defined for height {}", block_height),
+                    );
 
-                        let insert_start = get_epoch_time_ms();
+                    let epoch_id = epochs[epoch_index].epoch_id;
 
-                        last_processed = Burnchain::process_block(
-                            &myself,
-                            &mut burnchain_db,
-                            &parser_indexer,
-                            &burnchain_block,
-                            epoch_id,
-                        )?;
+                    let insert_start = get_epoch_time_ms();
 
-                        if !coord_comm.announce_new_burn_block() {
-                            return Err(burnchain_error::CoordinatorClosed);
+                    last_processed = match dry_run_report {
+                        Some(ref mut report) => {
+                            let (ops, rejected_ops) = burnchain_db.check_new_burnchain_block_ops(
+                                &myself,
+                                &parser_indexer,
+                                &burnchain_block,
+                                epoch_id,
+                            );
+                            report.add_block(block_height, &ops, rejected_ops);
+                            let header = burnchain_block.header();
+                            parser_indexer.report_block_processed(&header);
+                            header
+                        }
+                        None => {
+                            let header = Burnchain::process_block(
+                                &myself,
+                                &mut burnchain_db,
+                                &parser_indexer,
+                                &burnchain_block,
+                                epoch_id,
+                            )?;
+                            parser_indexer.report_block_processed(&header);
+                            if !coord_comm.announce_new_burn_block() {
+                                return Err(burnchain_error::CoordinatorClosed);
+                            }
+                            if !db_event_observers.is_empty() {
+                                // the block may have been stored by an earlier sync, so count
+                                // the operations the DB has for it
+                                match BurnchainDB::get_burnchain_block(
+                                    burnchain_db.conn(),
+                                    &header.block_hash,
+                                ) {
+                                    Ok(block_data) => db_event_observers.burn_block_processed(
+                                        header.block_height,
+                                        &header.block_hash,
+                                        block_data.ops.len(),
+                                    ),
+                                    Err(e) => warn!(
+                                        "Failed to load burn block {} for event observers: {:?}",
+                                        &header.block_hash, &e
+                                    ),
+                                }
+                            }
+                            header
                         }
-                        let insert_end = get_epoch_time_ms();
+                    };
+                    let insert_end = get_epoch_time_ms();
 
-                        debug!(
-                            "Inserted block {} in {}ms",
-                            burnchain_block.block_height(),
-                            insert_end.saturating_sub(insert_start)
-                        );
-                    }
-                    Ok(last_processed)
-                })
-                .unwrap();
+                    debug!(
+                        "Inserted block {} in {}ms",
+                        burnchain_block.block_height(),
+                        insert_end.saturating_sub(insert_start)
+                    );
+                }
+                Ok((last_processed, dry_run_report))
+            })
+            .unwrap();
 
         // feed the pipeline!
         let mut downloader_result: Result<(), burnchain_error> = Ok(());
@@ -1659,7 +2179,7 @@ impl Burnchain {
         // join up
         let _ = download_thread.join().unwrap();
         let _ = parse_thread.join().unwrap();
-        let block_header = match db_thread.join().unwrap() {
+        let (block_header, dry_run_report) = match db_thread.join().unwrap() {
             Ok(x) => x,
             Err(e) => {
                 warn!("Failed to join burnchain download thread: {:?}", &e);
@@ -1671,6 +2191,26 @@ impl Burnchain {
             }
         };
 
+        if let Some(report) = dry_run_report {
+            info!(
+                "Dry run checked {} burnchain blocks and rejected {} operations",
+                report.num_blocks,
+                report.rejected_ops.len()
+            );
+            indexer.report_dry_run(report);
+        }
+
+        if shutdown.load(Ordering::SeqCst) {
+            info!(
+                "Shutdown requested; stopped burnchain sync at {}",
+                block_header.block_height
+            );
+            if !dry_run {
+                update_burnchain_height(block_header.block_height as i64);
+            }
+            return Ok(block_header);
+        }
+
         if block_header.block_height < end_block {
             warn!(
                 "Try synchronizing the burn chain again: final snapshot {} < {}",
@@ -1682,7 +2222,56 @@ impl Burnchain {
         if let Err(e) = downloader_result {
             return Err(e);
         }
-        update_burnchain_height(block_header.block_height as i64);
+        if !dry_run {
+            update_burnchain_height(block_header.block_height as i64);
+            event_observers
+                .sync_completed(&(block_header.block_height, block_header.block_hash.clone()));
+        }
         Ok(block_header)
     }
+
+    /// Download and parse the burnchain blocks in heights `start_block` through `end_block`
+    /// (inclusive), and extract their Blockstack operations the same way
+    /// `sync_with_indexer()` does -- but return them instead of storing them.  The burnchain and
+    /// sortition DBs are only opened read-only, so neither they nor the processed height change.
+    /// The blocks' headers must already have been synced.
+    /// Returns each block's header and operations, in height order.
+    pub fn dry_run_with_indexer<I>(
+        &self,
+        indexer: &mut I,
+        start_block: u64,
+        end_block: u64,
+    ) -> Result<Vec<(BurnchainBlockHeader, Vec<BlockstackOperationType>)>, burnchain_error>
+    where
+        I: BurnchainIndexer + BurnchainHeaderReader,
+    {
+        let (sortdb, burnchain_db) = self.open_db(false)?;
+        let epochs = SortitionDB::get_stacks_epochs(sortdb.conn())?;
+
+        let mut downloader = indexer.downloader();
+        let mut parser = indexer.parser();
+        let input_headers = indexer.read_headers(start_block, end_block + 1)?;
+
+        let mut results = Vec::with_capacity(input_headers.len());
+        for ipc_header in input_headers.iter() {
+            let ipc_block = downloader.download(ipc_header)?;
+            let block_height = ipc_block.height();
+            let epoch_index = StacksEpoch::find_epoch(&epochs, block_height).expect(&format!(
+                "FATAL: no epoch defined for height {}",
+                block_height
+            ));
+            let epoch_id = epochs[epoch_index].epoch_id;
+
+            let burnchain_block = parser.parse(&ipc_block, epoch_id)?;
+            let ops = Burnchain::dry_run_block(
+                self,
+                &burnchain_db,
+                &*indexer,
+                &burnchain_block,
+                epoch_id,
+            );
+            results.push((burnchain_block.header(), ops));
+        }
+        Ok(results)
+    }
 }
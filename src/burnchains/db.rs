@@ -26,7 +26,9 @@ use serde_json;
 
 use crate::burnchains::affirmation::*;
 use crate::burnchains::Txid;
-use crate::burnchains::{Burnchain, BurnchainBlock, BurnchainBlockHeader, Error as BurnchainError};
+use crate::burnchains::{
+    Burnchain, BurnchainBlock, BurnchainBlockHeader, Error as BurnchainError, RejectedBurnchainOp,
+};
 use crate::chainstate::burn::operations::BlockstackOperationType;
 use crate::chainstate::burn::operations::LeaderBlockCommitOp;
 use crate::chainstate::burn::BlockSnapshot;
@@ -71,6 +73,10 @@ pub trait BurnchainHeaderReader {
         let mut hdrs = self.read_burnchain_headers(height, height.saturating_add(1))?;
         Ok(hdrs.pop())
     }
+
+    fn has_burnchain_header(&self, header_hash: &BurnchainHeaderHash) -> Result<bool, DBError> {
+        Ok(self.find_burnchain_header_height(header_hash)?.is_some())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -211,7 +217,7 @@ impl FromRow<BlockstackOperationType> for BlockstackOperationType {
     }
 }
 
-pub const BURNCHAIN_DB_VERSION: &'static str = "2";
+pub const BURNCHAIN_DB_VERSION: &'static str = "4";
 
 const BURNCHAIN_DB_SCHEMA: &'static str = r#"
 CREATE TABLE burnchain_db_block_headers (
@@ -298,6 +304,22 @@ CREATE TABLE db_config(version TEXT NOT NULL);
 INSERT INTO affirmation_maps(affirmation_id,weight,affirmation_map) VALUES (0,0,"");
 "#;
 
+const BURNCHAIN_DB_SCHEMA_3: &'static str = r#"
+-- height of the highest burnchain block whose operations have been committed and whose
+-- affirmation maps have been updated.  Burnchain sync resumes from the block after this one.
+CREATE TABLE processed_height (
+    -- there is only ever one row
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    block_height INTEGER NOT NULL
+);
+"#;
+
+const BURNCHAIN_DB_SCHEMA_4: &'static str = r#"
+-- hash of the block at the processed height, so that a sync can tell whether the block is still
+-- on the canonical chain before resuming after it.
+ALTER TABLE processed_height ADD COLUMN block_hash TEXT;
+"#;
+
 const LAST_BURNCHAIN_DB_INDEX: &'static str =
     "index_block_commit_metadata_burn_block_hash_anchor_block";
 const BURNCHAIN_DB_INDEXES: &'static [&'static str] = &[
@@ -918,6 +940,62 @@ impl<'a> BurnchainDBTransaction<'a> {
         Ok(())
     }
 
+    /// Record that every burnchain block up to and including `block_height` has been fully
+    /// processed, where the block at `block_height` is the stored one.
+    fn set_processed_height(&self, block_height: u64) -> Result<(), BurnchainError> {
+        self.sql_tx.execute(
+            "INSERT OR REPLACE INTO processed_height (id, block_height, block_hash) VALUES (0, ?1, \
+             (SELECT block_hash FROM burnchain_db_block_headers WHERE block_height = ?1 ORDER BY block_hash ASC LIMIT 1))",
+            &[&u64_to_sql(block_height)?],
+        )?;
+        Ok(())
+    }
+
+    /// Record that every burnchain block up to and including `block_header`'s has been fully
+    /// processed.
+    fn set_processed_block(
+        &self,
+        block_header: &BurnchainBlockHeader,
+    ) -> Result<(), BurnchainError> {
+        let args: &[&dyn ToSql] = &[
+            &u64_to_sql(block_header.block_height)?,
+            &block_header.block_hash,
+        ];
+        self.sql_tx.execute(
+            "INSERT OR REPLACE INTO processed_height (id, block_height, block_hash) VALUES (0, ?1, ?2)",
+            args,
+        )?;
+        Ok(())
+    }
+
+    /// Forget every burnchain block above `block_height` and its operations, so that sync picks
+    /// up again from the block after `block_height` and stores them anew.
+    ///
+    /// The PoX tables (`block_commit_metadata`, `anchor_blocks`, `affirmation_maps`) are left
+    /// alone.  Like the rest of this DB, they're keyed by burn block hash and can hold rows from
+    /// any fork:  every lookup goes through the canonical chain of headers, and processing a
+    /// block again rewrites its block-commits' rows (see `insert_block_commit_metadata()`) and
+    /// re-chooses its reward cycle's anchor block.  So this only needs to drop what would stop
+    /// the blocks from being stored again.
+    fn drop_blocks_above(&self, block_height: u64) -> Result<(), BurnchainError> {
+        let height_arg: &[&dyn ToSql] = &[&u64_to_sql(block_height)?];
+        self.sql_tx.execute(
+            "DELETE FROM burnchain_db_block_ops WHERE block_hash IN (SELECT block_hash FROM burnchain_db_block_headers WHERE block_height > ?1)",
+            height_arg,
+        )?;
+        self.sql_tx.execute(
+            "DELETE FROM burnchain_db_block_headers WHERE block_height > ?1",
+            height_arg,
+        )?;
+        self.sql_tx.execute(
+            "UPDATE processed_height SET block_height = ?1, \
+             block_hash = (SELECT block_hash FROM burnchain_db_block_headers WHERE block_height = ?1 ORDER BY block_hash ASC LIMIT 1) \
+             WHERE id = 0 AND block_height > ?1",
+            height_arg,
+        )?;
+        Ok(())
+    }
+
     pub fn commit(self) -> Result<(), BurnchainError> {
         self.sql_tx.commit().map_err(BurnchainError::from)
     }
@@ -974,6 +1052,53 @@ impl BurnchainDB {
         Ok(())
     }
 
+    fn get_schema_version(conn: &DBConn) -> Result<Option<String>, BurnchainError> {
+        let version = conn
+            .query_row("SELECT MAX(version) FROM db_config", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(version)
+    }
+
+    /// Bring an existing database up to the current schema version.
+    fn apply_migrations(&mut self) -> Result<(), BurnchainError> {
+        let version = BurnchainDB::get_schema_version(&self.conn)?;
+        if version.as_deref() == Some("2") {
+            // nodes before schema 3 resumed from the canonical chain tip, so treat it as
+            // processed.
+            let tip = self.get_canonical_chain_tip()?;
+            debug!(
+                "Migrate burnchain DB to schema 4; processed height is {}",
+                tip.block_height
+            );
+
+            let db_tx = self.tx_begin()?;
+            db_tx.sql_tx.execute_batch(BURNCHAIN_DB_SCHEMA_3)?;
+            db_tx.sql_tx.execute_batch(BURNCHAIN_DB_SCHEMA_4)?;
+            db_tx.set_processed_block(&tip)?;
+            db_tx
+                .sql_tx
+                .execute("UPDATE db_config SET version = ?1", &[&"4"])?;
+            db_tx.commit()?;
+        } else if version.as_deref() == Some("3") {
+            let processed_height = self.get_processed_height()?;
+            debug!(
+                "Migrate burnchain DB to schema 4; processed height is {}",
+                processed_height
+            );
+
+            let db_tx = self.tx_begin()?;
+            db_tx.sql_tx.execute_batch(BURNCHAIN_DB_SCHEMA_4)?;
+            db_tx.set_processed_height(processed_height)?;
+            db_tx
+                .sql_tx
+                .execute("UPDATE db_config SET version = ?1", &[&"4"])?;
+            db_tx.commit()?;
+        }
+        Ok(())
+    }
+
     pub fn connect(
         path: &str,
         burnchain: &Burnchain,
@@ -1017,6 +1142,8 @@ impl BurnchainDB {
         if create_flag {
             let db_tx = db.tx_begin()?;
             db_tx.sql_tx.execute_batch(BURNCHAIN_DB_SCHEMA)?;
+            db_tx.sql_tx.execute_batch(BURNCHAIN_DB_SCHEMA_3)?;
+            db_tx.sql_tx.execute_batch(BURNCHAIN_DB_SCHEMA_4)?;
             db_tx.sql_tx.execute(
                 "INSERT INTO db_config (version) VALUES (?1)",
                 &[&BURNCHAIN_DB_VERSION],
@@ -1035,6 +1162,7 @@ impl BurnchainDB {
                 path, &first_block_header
             );
             db_tx.store_burnchain_db_entry(&first_block_header)?;
+            db_tx.set_processed_block(&first_block_header)?;
 
             let first_snapshot = BlockSnapshot::initial(
                 burnchain.first_block_height,
@@ -1055,6 +1183,7 @@ impl BurnchainDB {
         }
 
         if readwrite {
+            db.apply_migrations()?;
             db.add_indexes()?;
         }
         Ok(db)
@@ -1070,6 +1199,7 @@ impl BurnchainDB {
         let mut db = BurnchainDB { conn };
 
         if readwrite {
+            db.apply_migrations()?;
             db.add_indexes()?;
         }
         Ok(db)
@@ -1096,6 +1226,53 @@ impl BurnchainDB {
         BurnchainDB::inner_get_canonical_chain_tip(&self.conn)
     }
 
+    /// Get the height of the highest burnchain block that has been fully processed -- i.e. whose
+    /// operations are committed and whose affirmation maps are up-to-date.  Burnchain sync
+    /// resumes from the block after this one.
+    pub fn get_processed_height(&self) -> Result<u64, BurnchainError> {
+        let qry = "SELECT block_height FROM processed_height WHERE id = 0";
+        let height: Option<i64> = query_row(&self.conn, qry, NO_PARAMS)?;
+        let height = height.expect("CORRUPTION: no processed burnchain block height");
+        Ok(height as u64)
+    }
+
+    /// Get the height and hash of the highest burnchain block that has been fully processed (see
+    /// `get_processed_height()`).  The hash is None if it wasn't recorded, which is only the
+    /// case for DBs last written to before schema 4 whose processed block has since been dropped.
+    pub fn get_processed_block(
+        &self,
+    ) -> Result<(u64, Option<BurnchainHeaderHash>), BurnchainError> {
+        let qry = "SELECT block_height, block_hash FROM processed_height WHERE id = 0";
+        let row = self
+            .conn
+            .query_row(qry, NO_PARAMS, |row| {
+                let height: i64 = row.get(0)?;
+                let hash: Option<BurnchainHeaderHash> = row.get(1)?;
+                Ok((height as u64, hash))
+            })
+            .optional()?;
+        Ok(row.expect("CORRUPTION: no processed burnchain block height"))
+    }
+
+    /// Record that every burnchain block up to and including `block_header`'s has been fully
+    /// processed.
+    pub fn set_processed_block(
+        &mut self,
+        block_header: &BurnchainBlockHeader,
+    ) -> Result<(), BurnchainError> {
+        let db_tx = self.tx_begin()?;
+        db_tx.set_processed_block(block_header)?;
+        db_tx.commit()
+    }
+
+    /// Roll the DB back to `block_height`, so that the burnchain blocks above it get downloaded
+    /// and processed again on the next sync (see `BurnchainDBTransaction::drop_blocks_above()`).
+    pub fn drop_blocks_above(&mut self, block_height: u64) -> Result<(), BurnchainError> {
+        let db_tx = self.tx_begin()?;
+        db_tx.drop_blocks_above(block_height)?;
+        db_tx.commit()
+    }
+
     #[cfg(test)]
     pub fn get_first_header(&self) -> Result<BurnchainBlockHeader, BurnchainError> {
         let qry = "SELECT * FROM burnchain_db_block_headers ORDER BY block_height ASC, block_hash DESC LIMIT 1";
@@ -1113,6 +1290,16 @@ impl BurnchainDB {
         Ok(res.is_some())
     }
 
+    pub fn has_burnchain_block(
+        conn: &DBConn,
+        block: &BurnchainHeaderHash,
+    ) -> Result<bool, BurnchainError> {
+        let qry = "SELECT 1 FROM burnchain_db_block_headers WHERE block_hash = ?1";
+        let args: &[&dyn ToSql] = &[block];
+        let res: Option<i64> = query_row(conn, qry, args)?;
+        Ok(res.is_some())
+    }
+
     pub fn get_burnchain_header(
         conn: &DBConn,
         height: u64,
@@ -1171,8 +1358,8 @@ impl BurnchainDB {
         let ops: Vec<BlockstackOperationType> =
             query_rows(&self.conn, qry, args).expect("FATAL: burnchain DB query error");
         for op in ops {
-            if let Some(_) = indexer
-                .find_burnchain_header_height(&op.burn_header_hash())
+            if indexer
+                .has_burnchain_header(&op.burn_header_hash())
                 .expect("FATAL: burnchain DB query error")
             {
                 // this is the op on the canonical fork
@@ -1183,7 +1370,9 @@ impl BurnchainDB {
     }
 
     /// Filter out the burnchain block's transactions that could be blockstack transactions.
-    /// Return the ordered list of blockstack operations by vtxindex
+    /// Return the ordered list of blockstack operations by vtxindex.
+    /// If `rejected_ops` is given, the transactions that have a blockstack operation's opcode
+    /// but aren't valid operations are added to it, instead of being logged.
     fn get_blockstack_transactions<B: BurnchainHeaderReader>(
         &self,
         burnchain: &Burnchain,
@@ -1191,6 +1380,7 @@ impl BurnchainDB {
         block: &BurnchainBlock,
         block_header: &BurnchainBlockHeader,
         epoch_id: StacksEpochId,
+        mut rejected_ops: Option<&mut Vec<RejectedBurnchainOp>>,
     ) -> Vec<BlockstackOperationType> {
         debug!(
             "Extract Blockstack transactions from block {} {} ({} txs)",
@@ -1203,15 +1393,40 @@ impl BurnchainDB {
         let mut pre_stx_ops = HashMap::new();
 
         for tx in block.txs().iter() {
-            let result = Burnchain::classify_transaction(
-                burnchain,
-                indexer,
-                self,
-                block_header,
-                epoch_id,
-                &tx,
-                &pre_stx_ops,
-            );
+            let result = match rejected_ops {
+                Some(ref mut rejected) => {
+                    match Burnchain::check_transaction(
+                        burnchain,
+                        indexer,
+                        self,
+                        block_header,
+                        epoch_id,
+                        &tx,
+                        &pre_stx_ops,
+                    ) {
+                        Ok(op_opt) => op_opt,
+                        Err(reason) => {
+                            rejected.push(RejectedBurnchainOp {
+                                block_height: block_header.block_height,
+                                txid: tx.txid(),
+                                vtxindex: tx.vtxindex(),
+                                opcode: tx.opcode(),
+                                reason,
+                            });
+                            None
+                        }
+                    }
+                }
+                None => Burnchain::classify_transaction(
+                    burnchain,
+                    indexer,
+                    self,
+                    block_header,
+                    epoch_id,
+                    &tx,
+                    &pre_stx_ops,
+                ),
+            };
             if let Some(classified_tx) = result {
                 if let BlockstackOperationType::PreStx(pre_stx_op) = classified_tx {
                     pre_stx_ops.insert(pre_stx_op.txid.clone(), pre_stx_op);
@@ -1377,6 +1592,25 @@ impl BurnchainDB {
         indexer: &B,
         block_header: &BurnchainBlockHeader,
         blockstack_ops: &[BlockstackOperationType],
+    ) -> Result<(), BurnchainError> {
+        self.inner_store_new_burnchain_block_ops(
+            burnchain,
+            indexer,
+            block_header,
+            blockstack_ops,
+            false,
+        )
+    }
+
+    /// Store a block's header and operations, and, if `mark_processed` is set, record it as the
+    /// highest processed block in the same transaction
+    fn inner_store_new_burnchain_block_ops<B: BurnchainHeaderReader>(
+        &mut self,
+        burnchain: &Burnchain,
+        indexer: &B,
+        block_header: &BurnchainBlockHeader,
+        blockstack_ops: &[BlockstackOperationType],
+        mark_processed: bool,
     ) -> Result<(), BurnchainError> {
         let db_tx = self.tx_begin()?;
 
@@ -1388,11 +1622,53 @@ impl BurnchainDB {
         );
         db_tx.store_burnchain_db_entry(block_header)?;
         db_tx.store_blockstack_ops(burnchain, indexer, &block_header, blockstack_ops)?;
+        if mark_processed {
+            db_tx.set_processed_block(block_header)?;
+        }
 
         db_tx.commit()?;
         Ok(())
     }
 
+    /// Get the Blockstack operations in a burnchain block, exactly as
+    /// `store_new_burnchain_block()` would store them, but without storing anything
+    pub fn get_new_burnchain_block_ops<B: BurnchainHeaderReader>(
+        &self,
+        burnchain: &Burnchain,
+        indexer: &B,
+        block: &BurnchainBlock,
+        epoch_id: StacksEpochId,
+    ) -> Vec<BlockstackOperationType> {
+        let header = block.header();
+        let mut blockstack_ops =
+            self.get_blockstack_transactions(burnchain, indexer, block, &header, epoch_id, None);
+        apply_blockstack_txs_safety_checks(header.block_height, &mut blockstack_ops);
+        blockstack_ops
+    }
+
+    /// Like `get_new_burnchain_block_ops()`, but also return the transactions that have a
+    /// Blockstack operation's opcode but aren't valid operations, and why
+    pub fn check_new_burnchain_block_ops<B: BurnchainHeaderReader>(
+        &self,
+        burnchain: &Burnchain,
+        indexer: &B,
+        block: &BurnchainBlock,
+        epoch_id: StacksEpochId,
+    ) -> (Vec<BlockstackOperationType>, Vec<RejectedBurnchainOp>) {
+        let header = block.header();
+        let mut rejected_ops = vec![];
+        let mut blockstack_ops = self.get_blockstack_transactions(
+            burnchain,
+            indexer,
+            block,
+            &header,
+            epoch_id,
+            Some(&mut rejected_ops),
+        );
+        apply_blockstack_txs_safety_checks(header.block_height, &mut blockstack_ops);
+        (blockstack_ops, rejected_ops)
+    }
+
     pub fn store_new_burnchain_block<B: BurnchainHeaderReader>(
         &mut self,
         burnchain: &Burnchain,
@@ -1403,14 +1679,38 @@ impl BurnchainDB {
         let header = block.header();
         debug!("Storing new burnchain block";
               "burn_header_hash" => %header.block_hash.to_string());
-        let mut blockstack_ops =
-            self.get_blockstack_transactions(burnchain, indexer, block, &header, epoch_id);
-        apply_blockstack_txs_safety_checks(header.block_height, &mut blockstack_ops);
+        let blockstack_ops = self.get_new_burnchain_block_ops(burnchain, indexer, block, epoch_id);
 
         self.store_new_burnchain_block_ops_unchecked(burnchain, indexer, &header, &blockstack_ops)?;
         Ok(blockstack_ops)
     }
 
+    /// Store a new burnchain block like `store_new_burnchain_block()`, and record it as the
+    /// highest processed block in the same transaction, so that a crash can never leave it
+    /// stored but not processed, or vice versa.  Only for blocks that need no more processing
+    /// once they're stored (see `Burnchain::process_block()`).
+    pub fn store_processed_burnchain_block<B: BurnchainHeaderReader>(
+        &mut self,
+        burnchain: &Burnchain,
+        indexer: &B,
+        block: &BurnchainBlock,
+        epoch_id: StacksEpochId,
+    ) -> Result<Vec<BlockstackOperationType>, BurnchainError> {
+        let header = block.header();
+        debug!("Storing new processed burnchain block";
+              "burn_header_hash" => %header.block_hash.to_string());
+        let blockstack_ops = self.get_new_burnchain_block_ops(burnchain, indexer, block, epoch_id);
+
+        self.inner_store_new_burnchain_block_ops(
+            burnchain,
+            indexer,
+            &header,
+            &blockstack_ops,
+            true,
+        )?;
+        Ok(blockstack_ops)
+    }
+
     #[cfg(test)]
     pub fn raw_store_burnchain_block<B: BurnchainHeaderReader>(
         &mut self,
@@ -14,6 +14,12 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
 use crate::burnchains::BurnchainBlock;
 use crate::burnchains::Error as burnchain_error;
 use crate::burnchains::*;
@@ -44,6 +50,21 @@ pub trait BurnchainBlockDownloader {
     type B: BurnBlockIPC + Sync + Send + Clone;
 
     fn download(&mut self, header: &Self::H) -> Result<Self::B, burnchain_error>;
+
+    /// Download the block of each header in `headers`, handing the blocks to `deliver` in the
+    /// order of their headers.  Downloaders that can have several requests outstanding at once
+    /// override this; by default, blocks are downloaded one at a time.
+    fn download_all(
+        &mut self,
+        headers: &mut dyn Iterator<Item = Self::H>,
+        deliver: &mut dyn FnMut(Self::B) -> Result<(), burnchain_error>,
+    ) -> Result<(), burnchain_error> {
+        for header in headers {
+            let block = self.download(&header)?;
+            deliver(block)?;
+        }
+        Ok(())
+    }
 }
 
 pub trait BurnchainBlockParser {
@@ -56,6 +77,272 @@ pub trait BurnchainBlockParser {
     ) -> Result<BurnchainBlock, burnchain_error>;
 }
 
+/// A burnchain block height and the hash of the block there
+pub type BurnchainTip = (u64, BurnchainHeaderHash);
+
+/// Receives the burnchain events of `Burnchain::sync_with_indexer()`, e.g. to notify an external
+/// service of new burn blocks.  Observers are called on the syncing threads, in the order the
+/// events happen, so they shouldn't block for long.  An error or a panic in an observer is
+/// logged and otherwise ignored:  it never stops the sync.
+pub trait BurnchainEventObserver: Send + Sync {
+    /// The burnchain block `hash` at `height` was stored, with `num_ops` Blockstack operations
+    fn burn_block_processed(
+        &self,
+        _height: u64,
+        _hash: &BurnchainHeaderHash,
+        _num_ops: usize,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+    /// A reorg took the stored blocks above `old_tip`'s height minus `depth` off the canonical
+    /// chain.  The blocks of the new branch, up to `new_tip`, are processed next.
+    fn reorg_detected(
+        &self,
+        _old_tip: &BurnchainTip,
+        _new_tip: &BurnchainTip,
+        _depth: u64,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+    /// A sync finished, and the burnchain DB's tip is now `tip`
+    fn sync_completed(&self, _tip: &BurnchainTip) -> Result<(), String> {
+        Ok(())
+    }
+    /// A reorg to a fork at `fork_height` would take the `depth` stored blocks up to `old_tip`
+    /// off the canonical chain, which is more than `max_depth`.  The sync halted without
+    /// changing anything, and retries halt the same way until an operator accepts the reorg
+    /// (see `BurnchainIndexer::max_reorg_depth()`).
+    fn deep_reorg_refused(
+        &self,
+        _old_tip: &BurnchainTip,
+        _fork_height: u64,
+        _depth: u64,
+        _max_depth: u64,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for dyn BurnchainEventObserver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BurnchainEventObserver")
+    }
+}
+
+/// A burnchain event, as forwarded by `ChannelEventObserver`
+#[derive(Debug, Clone, PartialEq)]
+pub enum BurnchainEvent {
+    BurnBlockProcessed {
+        height: u64,
+        hash: BurnchainHeaderHash,
+        num_ops: usize,
+    },
+    ReorgDetected {
+        old_tip: BurnchainTip,
+        new_tip: BurnchainTip,
+        depth: u64,
+    },
+    SyncCompleted {
+        tip: BurnchainTip,
+    },
+    DeepReorgRefused {
+        old_tip: BurnchainTip,
+        fork_height: u64,
+        depth: u64,
+        max_depth: u64,
+    },
+}
+
+/// Logs each burnchain event at info level
+#[derive(Debug, Clone, Default)]
+pub struct LoggingEventObserver;
+
+impl BurnchainEventObserver for LoggingEventObserver {
+    fn burn_block_processed(
+        &self,
+        height: u64,
+        hash: &BurnchainHeaderHash,
+        num_ops: usize,
+    ) -> Result<(), String> {
+        info!("Processed burn block";
+              "height" => height,
+              "hash" => %hash,
+              "num_ops" => num_ops);
+        Ok(())
+    }
+
+    fn reorg_detected(
+        &self,
+        old_tip: &BurnchainTip,
+        new_tip: &BurnchainTip,
+        depth: u64,
+    ) -> Result<(), String> {
+        info!("Burnchain reorg";
+              "old_tip_height" => old_tip.0,
+              "old_tip_hash" => %old_tip.1,
+              "new_tip_height" => new_tip.0,
+              "new_tip_hash" => %new_tip.1,
+              "depth" => depth);
+        Ok(())
+    }
+
+    fn sync_completed(&self, tip: &BurnchainTip) -> Result<(), String> {
+        info!("Burnchain sync completed";
+              "tip_height" => tip.0,
+              "tip_hash" => %tip.1);
+        Ok(())
+    }
+
+    fn deep_reorg_refused(
+        &self,
+        old_tip: &BurnchainTip,
+        fork_height: u64,
+        depth: u64,
+        max_depth: u64,
+    ) -> Result<(), String> {
+        error!("Burnchain reorg deeper than the maximum was refused";
+               "old_tip_height" => old_tip.0,
+               "old_tip_hash" => %old_tip.1,
+               "fork_height" => fork_height,
+               "depth" => depth,
+               "max_depth" => max_depth);
+        Ok(())
+    }
+}
+
+/// Forwards each burnchain event to a channel, e.g. for another thread to act on.  Once the
+/// receiver is gone, each event fails to forward (which is logged).
+#[derive(Debug, Clone)]
+pub struct ChannelEventObserver {
+    sender: Sender<BurnchainEvent>,
+}
+
+impl ChannelEventObserver {
+    pub fn new(sender: Sender<BurnchainEvent>) -> ChannelEventObserver {
+        ChannelEventObserver { sender }
+    }
+
+    fn forward(&self, event: BurnchainEvent) -> Result<(), String> {
+        self.sender
+            .send(event)
+            .map_err(|_e| "event receiver hung up".to_string())
+    }
+}
+
+impl BurnchainEventObserver for ChannelEventObserver {
+    fn burn_block_processed(
+        &self,
+        height: u64,
+        hash: &BurnchainHeaderHash,
+        num_ops: usize,
+    ) -> Result<(), String> {
+        self.forward(BurnchainEvent::BurnBlockProcessed {
+            height,
+            hash: hash.clone(),
+            num_ops,
+        })
+    }
+
+    fn reorg_detected(
+        &self,
+        old_tip: &BurnchainTip,
+        new_tip: &BurnchainTip,
+        depth: u64,
+    ) -> Result<(), String> {
+        self.forward(BurnchainEvent::ReorgDetected {
+            old_tip: old_tip.clone(),
+            new_tip: new_tip.clone(),
+            depth,
+        })
+    }
+
+    fn sync_completed(&self, tip: &BurnchainTip) -> Result<(), String> {
+        self.forward(BurnchainEvent::SyncCompleted { tip: tip.clone() })
+    }
+
+    fn deep_reorg_refused(
+        &self,
+        old_tip: &BurnchainTip,
+        fork_height: u64,
+        depth: u64,
+        max_depth: u64,
+    ) -> Result<(), String> {
+        self.forward(BurnchainEvent::DeepReorgRefused {
+            old_tip: old_tip.clone(),
+            fork_height,
+            depth,
+            max_depth,
+        })
+    }
+}
+
+/// The `BurnchainEventObserver`s registered on an indexer (see
+/// `BurnchainIndexer::event_observers()`), which can be handed to the sync's threads
+#[derive(Debug, Clone, Default)]
+pub struct BurnchainEventObservers {
+    observers: Vec<Arc<dyn BurnchainEventObserver>>,
+}
+
+impl BurnchainEventObservers {
+    pub fn add(&mut self, observer: Box<dyn BurnchainEventObserver>) {
+        self.observers.push(Arc::from(observer));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    pub fn burn_block_processed(&self, height: u64, hash: &BurnchainHeaderHash, num_ops: usize) {
+        self.notify("burn_block_processed", |observer| {
+            observer.burn_block_processed(height, hash, num_ops)
+        });
+    }
+
+    pub fn reorg_detected(&self, old_tip: &BurnchainTip, new_tip: &BurnchainTip, depth: u64) {
+        self.notify("reorg_detected", |observer| {
+            observer.reorg_detected(old_tip, new_tip, depth)
+        });
+    }
+
+    pub fn sync_completed(&self, tip: &BurnchainTip) {
+        self.notify("sync_completed", |observer| observer.sync_completed(tip));
+    }
+
+    pub fn deep_reorg_refused(
+        &self,
+        old_tip: &BurnchainTip,
+        fork_height: u64,
+        depth: u64,
+        max_depth: u64,
+    ) {
+        self.notify("deep_reorg_refused", |observer| {
+            observer.deep_reorg_refused(old_tip, fork_height, depth, max_depth)
+        });
+    }
+
+    /// Call `callback` on each observer in the order they were added, logging (but otherwise
+    /// ignoring) any that fail or panic
+    fn notify<F>(&self, name: &str, callback: F)
+    where
+        F: Fn(&dyn BurnchainEventObserver) -> Result<(), String>,
+    {
+        for observer in self.observers.iter() {
+            match panic::catch_unwind(AssertUnwindSafe(|| callback(observer.as_ref()))) {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    warn!("Burnchain event observer failed";
+                          "callback" => name,
+                          "error" => %e);
+                }
+                Err(_) => {
+                    error!("Burnchain event observer panicked";
+                           "callback" => name);
+                }
+            }
+        }
+    }
+}
+
 pub trait BurnchainIndexer {
     type P: BurnchainBlockParser + Send + Sync;
 
@@ -75,15 +362,73 @@ pub trait BurnchainIndexer {
         start_height: u64,
         end_height: Option<u64>,
     ) -> Result<u64, burnchain_error>;
+    /// If syncing headers replaced some of them with a higher-work fork, return the lowest fork
+    /// height (the highest header height both forks had in common) and clear it.  Anything
+    /// processed from above this height is no longer on the canonical chain.
+    fn take_headers_reorg_height(&mut self) -> Option<u64>;
     fn drop_headers(&mut self, new_height: u64) -> Result<(), burnchain_error>;
+    /// Tell the indexer's consumers that a reorg took the burnchain DB's blocks at heights
+    /// `first_height` through `last_height` off the canonical chain, so their burn operations
+    /// no longer count.
+    fn report_burn_blocks_invalidated(&self, first_height: u64, last_height: u64);
+    /// Tell the indexer's consumers that the block of `header` is now in the burnchain DB (or
+    /// was checked, in a dry run).
+    fn report_block_processed(&self, header: &BurnchainBlockHeader);
     /// Return headers that fall within the range. If end_block extends beyond the downloaded header range, then the result is truncated.
     fn read_headers(&self, start_block: u64, end_block: u64) -> Result<Vec<<<<Self as BurnchainIndexer>::P as BurnchainBlockParser>::D as BurnchainBlockDownloader>::H>, burnchain_error>;
 
     fn downloader(&self) -> <<Self as BurnchainIndexer>::P as BurnchainBlockParser>::D;
     fn parser(&self) -> Self::P;
+    /// How many threads to parse downloaded blocks on, each with its own `parser()` (see
+    /// `Burnchain::parse_blocks()`).  Blocks are still processed in height order.
+    fn num_parser_threads(&self) -> usize {
+        1
+    }
+
+    /// Should `Burnchain::sync_with_indexer()` only check the blocks it downloads, instead of
+    /// storing them?  If so, it extracts and validates their Blockstack operations as usual, but
+    /// hands them to `report_dry_run()` and leaves the burnchain DB alone.  Headers are still
+    /// synced.
+    fn dry_run(&self) -> bool {
+        false
+    }
+    /// Receive what a dry run sync found (see `dry_run()`)
+    fn report_dry_run(&mut self, _report: DryRunReport) {}
+    /// Should `Burnchain::sync_with_indexer()` drop the burnchain DB's blocks above a reorg's
+    /// fork (see `Burnchain::invalidate_reorged_blocks()`)?  If not, the old branch's blocks are
+    /// kept alongside the new branch's, and the sync waits for the new branch to outgrow the
+    /// old one before processing it.
+    fn drop_reorged_blocks(&self) -> bool {
+        false
+    }
+    /// Get the observers to report `Burnchain::sync_with_indexer()`'s burn blocks, reorgs, and
+    /// completed syncs to
+    fn event_observers(&self) -> BurnchainEventObservers {
+        BurnchainEventObservers::default()
+    }
+    /// Most stored blocks a reorg may take off the canonical chain without an operator's say-so.
+    /// `Burnchain::sync_with_indexer()` refuses a deeper reorg with `Error::DeepReorg`, leaving
+    /// the burnchain DB as it was, unless `take_deep_reorg_acceptance()` says it was accepted.
+    /// If None, reorgs of any depth are processed.
+    fn max_reorg_depth(&self) -> Option<u64> {
+        None
+    }
+    /// Has an operator accepted the next reorg deeper than `max_reorg_depth()`?  The acceptance
+    /// is used up by this call, so each deep reorg must be accepted on its own.
+    fn take_deep_reorg_acceptance(&mut self) -> bool {
+        false
+    }
+    /// Tell the indexer the burnchain DB's canonical tip before a sync looks for reorgs, so it
+    /// can refuse one deeper than `max_reorg_depth()` before touching its headers, rather than
+    /// leave the headers on a branch the burnchain DB won't follow.
+    fn set_burnchain_tip(&mut self, _tip: &BurnchainTip) {}
 
     /// Make an instance of the indexer to be consumed by a burnchain indexer thread, for reading
     /// local state (but not downloading or parsing it).
     /// This is different from `clone()` in that not all state needs to be copied.
     fn reader(&self) -> Self;
+
+    /// Get the flag that, once set, stops the indexer's syncs at the next safe point (e.g.
+    /// between header batches or block downloads), keeping what was synced so far.
+    fn shutdown_handle(&self) -> Arc<AtomicBool>;
 }
@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::default::Default;
@@ -51,6 +52,7 @@ use stacks_common::util::hash::Sha512Trunc256Sum;
 use self::bitcoin::indexer::{
     BITCOIN_MAINNET as BITCOIN_NETWORK_ID_MAINNET, BITCOIN_MAINNET_NAME,
     BITCOIN_REGTEST as BITCOIN_NETWORK_ID_REGTEST, BITCOIN_REGTEST_NAME,
+    BITCOIN_SIGNET as BITCOIN_NETWORK_ID_SIGNET, BITCOIN_SIGNET_NAME,
     BITCOIN_TESTNET as BITCOIN_NETWORK_ID_TESTNET, BITCOIN_TESTNET_NAME,
 };
 use self::bitcoin::Error as btc_error;
@@ -110,6 +112,7 @@ impl BurnchainParameters {
             ("bitcoin", "mainnet") => Some(BurnchainParameters::bitcoin_mainnet()),
             ("bitcoin", "testnet") => Some(BurnchainParameters::bitcoin_testnet()),
             ("bitcoin", "regtest") => Some(BurnchainParameters::bitcoin_regtest()),
+            ("bitcoin", "signet") => Some(BurnchainParameters::bitcoin_signet()),
             _ => None,
         }
     }
@@ -159,9 +162,26 @@ impl BurnchainParameters {
         }
     }
 
+    pub fn bitcoin_signet() -> BurnchainParameters {
+        BurnchainParameters {
+            chain_name: "bitcoin".to_string(),
+            network_name: BITCOIN_SIGNET_NAME.to_string(),
+            network_id: BITCOIN_NETWORK_ID_SIGNET,
+            stable_confirmations: 7,
+            consensus_hash_lifetime: 24,
+            first_block_height: BITCOIN_SIGNET_FIRST_BLOCK_HEIGHT,
+            first_block_hash: BurnchainHeaderHash::from_hex(BITCOIN_SIGNET_FIRST_BLOCK_HASH)
+                .unwrap(),
+            first_block_timestamp: BITCOIN_SIGNET_FIRST_BLOCK_TIMESTAMP,
+            initial_reward_start_block: BITCOIN_SIGNET_FIRST_BLOCK_HEIGHT,
+        }
+    }
+
     pub fn is_testnet(network_id: u32) -> bool {
         match network_id {
-            BITCOIN_NETWORK_ID_TESTNET | BITCOIN_NETWORK_ID_REGTEST => true,
+            BITCOIN_NETWORK_ID_TESTNET | BITCOIN_NETWORK_ID_REGTEST | BITCOIN_NETWORK_ID_SIGNET => {
+                true
+            }
             _ => false,
         }
     }
@@ -575,6 +595,32 @@ pub struct BurnchainStateTransitionOps {
     pub consumed_leader_keys: Vec<LeaderKeyRegisterOp>,
 }
 
+/// A burnchain transaction with a Blockstack operation's opcode that isn't a valid operation,
+/// as found by a dry run of the burnchain sync
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedBurnchainOp {
+    pub block_height: u64,
+    pub txid: Txid,
+    pub vtxindex: u32,
+    pub opcode: u8,
+    pub reason: String,
+}
+
+/// What a dry run of the burnchain sync found in the blocks it checked, in place of storing them
+/// (see `BurnchainIndexer::dry_run()`).  Its `Display` form lists everything in a fixed order, so
+/// two runs over the same blocks (e.g. before and after a parser change) can be diffed.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DryRunReport {
+    /// Heights of the first and last blocks checked, if any were
+    pub first_height: Option<u64>,
+    pub last_height: Option<u64>,
+    pub num_blocks: u64,
+    /// Number of valid operations found, by operation type (see `DryRunReport::op_type_name()`)
+    pub op_counts: BTreeMap<String, u64>,
+    /// Rejected operations, in block and then vtxindex order
+    pub rejected_ops: Vec<RejectedBurnchainOp>,
+}
+
 #[derive(Debug)]
 pub enum Error {
     /// Unsupported burn chain
@@ -604,6 +650,13 @@ pub enum Error {
     UnknownBlock(BurnchainHeaderHash),
     NonCanonicalPoxId(PoxId, PoxId),
     CoordinatorClosed,
+    /// The burnchain hasn't reached the first block yet: (first block height, highest header
+    /// height).  Headers were synced, but there are no blocks to process.
+    FirstBlockNotReached(u64, u64),
+    /// A reorg would take this many stored blocks off the canonical chain, which is more than
+    /// the indexer's maximum reorg depth (the second value).  Nothing was changed; the sync
+    /// halts until the reorg is accepted (see `BurnchainIndexer::max_reorg_depth()`).
+    DeepReorg(u64, u64),
 }
 
 impl fmt::Display for Error {
@@ -628,6 +681,16 @@ impl fmt::Display for Error {
                 parent, child
             ),
             Error::CoordinatorClosed => write!(f, "ChainsCoordinator channel hung up"),
+            Error::FirstBlockNotReached(first_block_height, headers_height) => write!(
+                f,
+                "Burnchain has not reached the first block {} (highest header is {})",
+                first_block_height, headers_height
+            ),
+            Error::DeepReorg(depth, max_depth) => write!(
+                f,
+                "Refusing a burnchain reorg {} blocks deep (the maximum is {}) until it is accepted",
+                depth, max_depth
+            ),
         }
     }
 }
@@ -650,6 +713,8 @@ impl error::Error for Error {
             Error::UnknownBlock(_) => None,
             Error::NonCanonicalPoxId(_, _) => None,
             Error::CoordinatorClosed => None,
+            Error::FirstBlockNotReached(..) => None,
+            Error::DeepReorg(..) => None,
         }
     }
 }
@@ -54,6 +54,22 @@ use stacks_common::util::vrf::VRFPublicKey;
 
 use crate::types::chainstate::{BlockHeaderHash, BurnchainHeaderHash, PoxId, SortitionId, VRFSeed};
 
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::burnchains::bitcoin::blocks::{
+    BitcoinBlockDownloader, BitcoinBlockIPC, BitcoinHeaderIPC,
+};
+use crate::burnchains::indexer::{BurnBlockIPC, BurnchainBlockParser};
+use crate::burnchains::Error as burnchain_error;
+use crate::core::{StacksEpochId, STACKS_EPOCHS_REGTEST};
+use stacks_common::deps_common::bitcoin::blockdata::block::{BlockHeader, LoneBlockHeader};
+use stacks_common::deps_common::bitcoin::network::encodable::VarInt;
+use stacks_common::deps_common::bitcoin::network::message::NetworkMessage;
+use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+
 #[test]
 fn test_process_block_ops() {
     let first_burn_hash = BurnchainHeaderHash::from_hex(
@@ -1082,3 +1098,173 @@ fn test_burn_snapshot_sequence() {
         prev_snapshot = snapshot;
     }
 }
+
+/// Parses each block into an empty burnchain block after a delay that depends on its height, so
+/// that blocks finish parsing out of order.  Block 0 takes much longer than the rest.
+#[derive(Clone)]
+struct DelayedBlockParser {
+    /// Heights of the blocks, in the order they finished parsing
+    finished: Arc<Mutex<Vec<u64>>>,
+    /// Height of the block that fails to parse, if any
+    fail_height: Option<u64>,
+}
+
+impl DelayedBlockParser {
+    fn new(fail_height: Option<u64>) -> DelayedBlockParser {
+        DelayedBlockParser {
+            finished: Arc::new(Mutex::new(vec![])),
+            fail_height,
+        }
+    }
+}
+
+impl BurnchainBlockParser for DelayedBlockParser {
+    type D = BitcoinBlockDownloader;
+
+    fn parse(
+        &mut self,
+        ipc_block: &BitcoinBlockIPC,
+        _epoch_id: StacksEpochId,
+    ) -> Result<BurnchainBlock, burnchain_error> {
+        let height = ipc_block.height();
+        let delay_ms = if height == 0 {
+            300
+        } else {
+            (height * 7 % 5) * 5
+        };
+        thread::sleep(Duration::from_millis(delay_ms));
+
+        self.finished.lock().unwrap().push(height);
+        if Some(height) == self.fail_height {
+            return Err(burnchain_error::ParseError);
+        }
+        Ok(BurnchainBlock::Bitcoin(BitcoinBlock::new(
+            height,
+            &BurnchainHeaderHash([(height % 256) as u8; 32]),
+            &BurnchainHeaderHash([0u8; 32]),
+            vec![],
+            0,
+        )))
+    }
+}
+
+fn make_parse_test_block(height: u64) -> BitcoinBlockIPC {
+    BitcoinBlockIPC {
+        header_data: BitcoinHeaderIPC {
+            block_header: LoneBlockHeader {
+                header: BlockHeader {
+                    version: 0x20000000,
+                    prev_blockhash: Sha256dHash([0u8; 32]),
+                    merkle_root: Sha256dHash::from_data(&height.to_be_bytes()),
+                    time: 0,
+                    bits: 0x207fffff,
+                    nonce: 0,
+                },
+                tx_count: VarInt(0),
+            },
+            block_height: height,
+        },
+        block_message: NetworkMessage::Ping(height),
+        matched_txs: vec![],
+    }
+}
+
+/// Feed blocks 0 through `num_blocks - 1` to `Burnchain::parse_blocks()`, and collect the heights
+/// of the parsed blocks it sends, up to the None after the last one (if it's sent)
+fn run_parse_blocks(
+    parser: &DelayedBlockParser,
+    num_parsers: usize,
+    num_blocks: u64,
+    max_pending: usize,
+) -> (Vec<u64>, bool, Result<(), burnchain_error>) {
+    let (block_send, block_recv) = sync_channel(1);
+    let (parsed_send, parsed_recv) = sync_channel(1);
+
+    let feeder = thread::spawn(move || {
+        for height in 0..num_blocks {
+            if block_send
+                .send(Some(make_parse_test_block(height)))
+                .is_err()
+            {
+                return;
+            }
+        }
+        let _ = block_send.send(None);
+    });
+
+    let parsers = (0..num_parsers).map(|_| parser.clone()).collect();
+    let pool = thread::spawn(move || {
+        Burnchain::parse_blocks(
+            parsers,
+            STACKS_EPOCHS_REGTEST.to_vec(),
+            block_recv,
+            parsed_send,
+            max_pending,
+        )
+    });
+
+    let mut heights = vec![];
+    let mut finished = false;
+    while let Ok(parsed) = parsed_recv.recv() {
+        match parsed {
+            Some(block) => heights.push(block.block_height()),
+            None => {
+                finished = true;
+                break;
+            }
+        }
+    }
+    drop(parsed_recv);
+
+    feeder.join().unwrap();
+    (heights, finished, pool.join().unwrap())
+}
+
+#[test]
+fn test_parse_blocks_in_order() {
+    let num_blocks = 64;
+    let max_pending = 8;
+    let parser = DelayedBlockParser::new(None);
+
+    let (heights, finished, res) = run_parse_blocks(&parser, 4, num_blocks, max_pending);
+    res.unwrap();
+    assert!(finished);
+    assert_eq!(heights, (0..num_blocks).collect::<Vec<_>>());
+
+    // blocks finished parsing out of order, but were sent in order
+    let finished_order = parser.finished.lock().unwrap().clone();
+    assert_eq!(finished_order.len(), num_blocks as usize);
+    assert!(finished_order[0] != 0);
+
+    // the slow first block held up the rest:  only the blocks after it that fit in the reorder
+    // buffer were handed out before it finished
+    let first_done = finished_order.iter().position(|h| *h == 0).unwrap();
+    assert!(finished_order[..first_done]
+        .iter()
+        .all(|h| *h < max_pending as u64));
+
+    // a single parser sends blocks in order too
+    let parser = DelayedBlockParser::new(None);
+    let (heights, finished, res) = run_parse_blocks(&parser, 1, 16, 1);
+    res.unwrap();
+    assert!(finished);
+    assert_eq!(heights, (0..16).collect::<Vec<_>>());
+    assert_eq!(
+        *parser.finished.lock().unwrap(),
+        (0..16).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_parse_blocks_stops_at_error() {
+    let parser = DelayedBlockParser::new(Some(10));
+    let (heights, finished, res) = run_parse_blocks(&parser, 4, 64, 8);
+
+    // the blocks before the bad one are sent, and nothing after it
+    assert_eq!(heights, (0..10).collect::<Vec<_>>());
+    assert!(!finished);
+    match res {
+        Err(burnchain_error::ParseError) => {}
+        res => panic!("expected ParseError, got {:?}", res),
+    }
+}
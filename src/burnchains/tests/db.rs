@@ -17,10 +17,12 @@
 use crate::chainstate::stacks::address::StacksAddressExtensions;
 use std::cmp;
 use std::convert::TryInto;
+use std::fs;
 
 use crate::burnchains::affirmation::AffirmationMap;
 use crate::burnchains::bitcoin::address::*;
 use crate::burnchains::bitcoin::blocks::*;
+use crate::burnchains::bitcoin::indexer::BurnTip;
 use crate::burnchains::bitcoin::*;
 use crate::burnchains::PoxConstants;
 use crate::burnchains::BLOCKSTACK_MAGIC_MAINNET;
@@ -1336,3 +1338,769 @@ fn test_classify_delegate_stx() {
         panic!("EXPECTED to parse a delegate stx op");
     }
 }
+
+#[test]
+fn test_resume_from_processed_height() {
+    let path = "/tmp/test-burnchain-db-resume-from-processed-height.sqlite";
+    if fs::metadata(path).is_ok() {
+        fs::remove_file(path).unwrap();
+    }
+
+    let first_bhh = BurnchainHeaderHash::from_hex(BITCOIN_REGTEST_FIRST_BLOCK_HASH).unwrap();
+    let mut burnchain = Burnchain::regtest(":memory:");
+    burnchain.pox_constants = PoxConstants::test_default();
+
+    let mut burnchain_db = BurnchainDB::connect(path, &burnchain, true).unwrap();
+    assert_eq!(burnchain_db.get_processed_height().unwrap(), 0);
+
+    let first_block_header = burnchain_db.get_canonical_chain_tip().unwrap();
+    let mut headers = vec![first_block_header];
+    let mut blocks = vec![];
+    let mut parent_hash = first_bhh.clone();
+    for i in 1..6 {
+        let block_hash = BurnchainHeaderHash([i as u8; 32]);
+
+        // give block 4 some operations, so we can tell if they get stored twice
+        let txs = if i == 4 {
+            let parser =
+                BitcoinBlockParser::new(BitcoinNetworkType::Testnet, BLOCKSTACK_MAGIC_MAINNET);
+            operations::leader_key_register::tests::get_test_fixtures(1, i, block_hash.clone())
+                .iter()
+                .enumerate()
+                .map(|(ix, fixture)| {
+                    parser
+                        .parse_tx(&make_tx(&fixture.txstr), ix + 1, StacksEpochId::Epoch2_05)
+                        .unwrap()
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let block = BurnchainBlock::Bitcoin(BitcoinBlock::new(
+            i,
+            &block_hash,
+            &parent_hash,
+            txs,
+            i * 600,
+        ));
+        headers.push(block.header());
+        blocks.push(block);
+        parent_hash = block_hash;
+    }
+
+    // blocks 1-3 are fully processed
+    for block in blocks[0..3].iter() {
+        Burnchain::process_block(
+            &burnchain,
+            &mut burnchain_db,
+            &headers,
+            block,
+            StacksEpochId::Epoch21,
+        )
+        .unwrap();
+    }
+    assert_eq!(burnchain_db.get_processed_height().unwrap(), 3);
+
+    // block 4's operations get committed, but we're stopped before it is marked processed
+    let block_4_ops = burnchain_db
+        .store_new_burnchain_block(&burnchain, &headers, &blocks[3], StacksEpochId::Epoch21)
+        .unwrap();
+    assert!(!block_4_ops.is_empty());
+    drop(burnchain_db);
+
+    // restart
+    let mut burnchain_db = BurnchainDB::connect(path, &burnchain, true).unwrap();
+    assert_eq!(
+        burnchain_db.get_canonical_chain_tip().unwrap().block_height,
+        4
+    );
+    let processed_height = burnchain_db.get_processed_height().unwrap();
+    assert_eq!(processed_height, 3);
+
+    // resume after the last processed block
+    for block in blocks[(processed_height as usize)..].iter() {
+        assert!(block.block_height() > 3);
+        Burnchain::process_block(
+            &burnchain,
+            &mut burnchain_db,
+            &headers,
+            block,
+            StacksEpochId::Epoch21,
+        )
+        .unwrap();
+    }
+    assert_eq!(burnchain_db.get_processed_height().unwrap(), 5);
+    assert_eq!(
+        burnchain_db.get_canonical_chain_tip().unwrap().block_height,
+        5
+    );
+
+    // nothing was skipped, and block 4's operations were not stored twice
+    for block in blocks.iter() {
+        let block_data =
+            BurnchainDB::get_burnchain_block(burnchain_db.conn(), &block.block_hash()).unwrap();
+        assert_eq!(block_data.header, block.header());
+    }
+    let block_data =
+        BurnchainDB::get_burnchain_block(burnchain_db.conn(), &blocks[3].block_hash()).unwrap();
+    assert_eq!(block_data.ops.len(), block_4_ops.len());
+}
+
+#[test]
+fn test_dry_run_leaves_db_unchanged() {
+    let path = "/tmp/test-burnchain-db-dry-run.sqlite";
+    if fs::metadata(path).is_ok() {
+        fs::remove_file(path).unwrap();
+    }
+
+    let first_bhh = BurnchainHeaderHash::from_hex(BITCOIN_REGTEST_FIRST_BLOCK_HASH).unwrap();
+    let mut burnchain = Burnchain::regtest(":memory:");
+    burnchain.pox_constants = PoxConstants::test_default();
+
+    let mut burnchain_db = BurnchainDB::connect(path, &burnchain, true).unwrap();
+    let first_block_header = burnchain_db.get_canonical_chain_tip().unwrap();
+    let mut headers = vec![first_block_header];
+    let mut blocks = vec![];
+    let mut parent_hash = first_bhh.clone();
+    for i in 1..6 {
+        let block_hash = BurnchainHeaderHash([i as u8; 32]);
+
+        // give block 4 some operations
+        let txs = if i == 4 {
+            let parser =
+                BitcoinBlockParser::new(BitcoinNetworkType::Testnet, BLOCKSTACK_MAGIC_MAINNET);
+            operations::leader_key_register::tests::get_test_fixtures(1, i, block_hash.clone())
+                .iter()
+                .enumerate()
+                .map(|(ix, fixture)| {
+                    parser
+                        .parse_tx(&make_tx(&fixture.txstr), ix + 1, StacksEpochId::Epoch2_05)
+                        .unwrap()
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let block = BurnchainBlock::Bitcoin(BitcoinBlock::new(
+            i,
+            &block_hash,
+            &parent_hash,
+            txs,
+            i * 600,
+        ));
+        headers.push(block.header());
+        blocks.push(block);
+        parent_hash = block_hash;
+    }
+
+    for block in blocks[0..3].iter() {
+        Burnchain::process_block(
+            &burnchain,
+            &mut burnchain_db,
+            &headers,
+            block,
+            StacksEpochId::Epoch21,
+        )
+        .unwrap();
+    }
+    drop(burnchain_db);
+    let db_bytes = fs::read(path).unwrap();
+
+    // dry-run the rest of the blocks
+    let burnchain_db = BurnchainDB::open(path, false).unwrap();
+    let dry_run_ops: Vec<_> = blocks[3..]
+        .iter()
+        .map(|block| {
+            Burnchain::dry_run_block(
+                &burnchain,
+                &burnchain_db,
+                &headers,
+                block,
+                StacksEpochId::Epoch21,
+            )
+        })
+        .collect();
+    assert!(!dry_run_ops[0].is_empty());
+    assert!(dry_run_ops[1].is_empty());
+    drop(burnchain_db);
+
+    // nothing was written
+    assert_eq!(fs::read(path).unwrap(), db_bytes);
+    let mut burnchain_db = BurnchainDB::connect(path, &burnchain, true).unwrap();
+    assert_eq!(burnchain_db.get_processed_height().unwrap(), 3);
+    assert_eq!(
+        burnchain_db.get_canonical_chain_tip().unwrap().block_height,
+        3
+    );
+
+    // ...and the operations are the ones that processing the blocks stores
+    for (block, ops) in blocks[3..].iter().zip(dry_run_ops.iter()) {
+        let stored_ops = burnchain_db
+            .store_new_burnchain_block(&burnchain, &headers, block, StacksEpochId::Epoch21)
+            .unwrap();
+        assert_eq!(&stored_ops, ops);
+    }
+}
+
+#[test]
+fn test_dry_run_report() {
+    let first_bhh = BurnchainHeaderHash::from_hex(BITCOIN_REGTEST_FIRST_BLOCK_HASH).unwrap();
+    let mut burnchain = Burnchain::regtest(":memory:");
+    burnchain.pox_constants = PoxConstants::test_default();
+
+    let burnchain_db = BurnchainDB::connect(":memory:", &burnchain, true).unwrap();
+    let first_block_header = burnchain_db.get_canonical_chain_tip().unwrap();
+
+    // every leader key register fixture, of which some are invalid
+    let block_hash = BurnchainHeaderHash([1; 32]);
+    let parser = BitcoinBlockParser::new(BitcoinNetworkType::Testnet, BLOCKSTACK_MAGIC_MAINNET);
+    let fixtures =
+        operations::leader_key_register::tests::get_test_fixtures(1, 1, block_hash.clone());
+    let txs: Vec<_> = fixtures
+        .iter()
+        .enumerate()
+        .map(|(ix, fixture)| {
+            parser
+                .parse_tx(&make_tx(&fixture.txstr), ix + 1, StacksEpochId::Epoch2_05)
+                .unwrap()
+        })
+        .collect();
+    let block = BurnchainBlock::Bitcoin(BitcoinBlock::new(1, &block_hash, &first_bhh, txs, 600));
+    let headers = vec![first_block_header, block.header()];
+
+    let (ops, rejected_ops) = burnchain_db.check_new_burnchain_block_ops(
+        &burnchain,
+        &headers,
+        &block,
+        StacksEpochId::Epoch21,
+    );
+    assert_eq!(
+        ops,
+        burnchain_db.get_new_burnchain_block_ops(
+            &burnchain,
+            &headers,
+            &block,
+            StacksEpochId::Epoch21
+        )
+    );
+    let num_valid = fixtures.iter().filter(|f| f.result.is_some()).count();
+    assert_eq!(
+        ops.iter()
+            .filter(|op| op.opcode() == Opcodes::LeaderKeyRegister)
+            .count(),
+        num_valid
+    );
+    // every transaction has a Blockstack operation's opcode, so it's either valid or rejected
+    assert_eq!(ops.len() + rejected_ops.len(), fixtures.len());
+    assert!(rejected_ops.len() >= fixtures.len() - num_valid - 1);
+    for rejected in rejected_ops.iter() {
+        assert_eq!(rejected.block_height, 1);
+        assert!(!rejected.reason.is_empty());
+        assert!(ops.iter().all(|op| op.txid() != rejected.txid));
+        assert!(fixtures[rejected.vtxindex as usize - 1].result.is_none());
+    }
+
+    let mut report = DryRunReport::default();
+    assert!(!report.has_rejected_ops());
+    assert_eq!(
+        report.to_string().lines().next().unwrap(),
+        "no blocks checked"
+    );
+
+    report.add_block(1, &ops, rejected_ops.clone());
+    report.add_block(2, &[], vec![]);
+    assert_eq!(report.first_height, Some(1));
+    assert_eq!(report.last_height, Some(2));
+    assert_eq!(report.num_blocks, 2);
+    assert_eq!(
+        report.op_counts.get("leader_key_register").copied(),
+        Some(num_valid as u64)
+    );
+    assert_eq!(report.rejected_ops, rejected_ops);
+    assert!(report.has_rejected_ops());
+
+    // the summary is the same for the same blocks, and lists every op type
+    let summary = report.to_string();
+    let mut same_report = DryRunReport::default();
+    same_report.add_block(1, &ops, rejected_ops.clone());
+    same_report.add_block(2, &[], vec![]);
+    assert_eq!(same_report, report);
+    assert_eq!(same_report.to_string(), summary);
+
+    let lines: Vec<&str> = summary.lines().collect();
+    assert_eq!(lines[0], "blocks 1-2 (2 checked)");
+    assert!(lines.contains(&format!("ops leader_key_register {}", num_valid).as_str()));
+    assert!(lines.contains(&"ops delegate_stx 0"));
+    assert!(lines.contains(&format!("rejected {}", rejected_ops.len()).as_str()));
+    assert_eq!(lines.len(), 1 + 7 + 1 + rejected_ops.len());
+}
+
+#[test]
+fn test_drop_blocks_above() {
+    let first_bhh = BurnchainHeaderHash::from_hex(BITCOIN_REGTEST_FIRST_BLOCK_HASH).unwrap();
+    let mut burnchain = Burnchain::regtest(":memory:");
+    burnchain.pox_constants = PoxConstants::test_default();
+
+    let mut burnchain_db = BurnchainDB::connect(":memory:", &burnchain, true).unwrap();
+    let first_block_header = burnchain_db.get_canonical_chain_tip().unwrap();
+    let mut headers = vec![first_block_header];
+    let mut blocks = vec![];
+    let mut parent_hash = first_bhh.clone();
+    for i in 1..6 {
+        let block_hash = BurnchainHeaderHash([i as u8; 32]);
+
+        // give block 4 some operations
+        let txs = if i == 4 {
+            let parser =
+                BitcoinBlockParser::new(BitcoinNetworkType::Testnet, BLOCKSTACK_MAGIC_MAINNET);
+            operations::leader_key_register::tests::get_test_fixtures(1, i, block_hash.clone())
+                .iter()
+                .enumerate()
+                .map(|(ix, fixture)| {
+                    parser
+                        .parse_tx(&make_tx(&fixture.txstr), ix + 1, StacksEpochId::Epoch2_05)
+                        .unwrap()
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let block = BurnchainBlock::Bitcoin(BitcoinBlock::new(
+            i,
+            &block_hash,
+            &parent_hash,
+            txs,
+            i * 600,
+        ));
+        headers.push(block.header());
+        blocks.push(block);
+        parent_hash = block_hash;
+    }
+
+    for block in blocks.iter() {
+        Burnchain::process_block(
+            &burnchain,
+            &mut burnchain_db,
+            &headers,
+            block,
+            StacksEpochId::Epoch21,
+        )
+        .unwrap();
+    }
+    let block_4_ops =
+        BurnchainDB::get_burnchain_block(burnchain_db.conn(), &blocks[3].block_hash())
+            .unwrap()
+            .ops;
+    assert!(!block_4_ops.is_empty());
+
+    burnchain_db.drop_blocks_above(2).unwrap();
+    assert_eq!(
+        burnchain_db.get_canonical_chain_tip().unwrap().block_height,
+        2
+    );
+    assert_eq!(burnchain_db.get_processed_height().unwrap(), 2);
+    for block in blocks[0..2].iter() {
+        assert!(
+            BurnchainDB::has_burnchain_block(burnchain_db.conn(), &block.block_hash()).unwrap()
+        );
+    }
+    for block in blocks[2..].iter() {
+        assert!(
+            !BurnchainDB::has_burnchain_block(burnchain_db.conn(), &block.block_hash()).unwrap()
+        );
+    }
+    for op in block_4_ops.iter() {
+        assert!(burnchain_db
+            .find_burnchain_op(&headers, &op.txid())
+            .is_none());
+    }
+
+    // the dropped blocks are processed again from scratch
+    for block in blocks[2..].iter() {
+        Burnchain::process_block(
+            &burnchain,
+            &mut burnchain_db,
+            &headers,
+            block,
+            StacksEpochId::Epoch21,
+        )
+        .unwrap();
+    }
+    assert_eq!(burnchain_db.get_processed_height().unwrap(), 5);
+    assert_eq!(
+        BurnchainDB::get_burnchain_block(burnchain_db.conn(), &blocks[3].block_hash())
+            .unwrap()
+            .ops,
+        block_4_ops
+    );
+}
+
+/// Make the burnchain blocks at heights `start_height` through `end_height` of a branch that
+/// descends from `parent_hash`, with hashes `[hash_prefix + height; 32]`.  The first block
+/// carries the leader key register from fixture `fixture_index`.
+fn make_reorg_branch(
+    start_height: u64,
+    end_height: u64,
+    parent_hash: &BurnchainHeaderHash,
+    hash_prefix: u8,
+    fixture_index: usize,
+) -> Vec<BurnchainBlock> {
+    let parser = BitcoinBlockParser::new(BitcoinNetworkType::Testnet, BLOCKSTACK_MAGIC_MAINNET);
+    let mut blocks = vec![];
+    let mut parent_hash = parent_hash.clone();
+    for i in start_height..(end_height + 1) {
+        let block_hash = BurnchainHeaderHash([hash_prefix + i as u8; 32]);
+        let txs = if i == start_height {
+            let fixture = &operations::leader_key_register::tests::get_test_fixtures(
+                1,
+                i,
+                block_hash.clone(),
+            )[fixture_index];
+            vec![parser
+                .parse_tx(&make_tx(&fixture.txstr), 1, StacksEpochId::Epoch2_05)
+                .unwrap()]
+        } else {
+            vec![]
+        };
+        blocks.push(BurnchainBlock::Bitcoin(BitcoinBlock::new(
+            i,
+            &block_hash,
+            &parent_hash,
+            txs,
+            i * 600,
+        )));
+        parent_hash = block_hash;
+    }
+    blocks
+}
+
+#[test]
+fn test_invalidate_reorged_blocks() {
+    for reorg_depth in [2, 10] {
+        let first_bhh = BurnchainHeaderHash::from_hex(BITCOIN_REGTEST_FIRST_BLOCK_HASH).unwrap();
+        let mut burnchain = Burnchain::regtest(":memory:");
+        burnchain.pox_constants = PoxConstants::test_default();
+
+        let mut burnchain_db = BurnchainDB::connect(":memory:", &burnchain, true).unwrap();
+        let first_block_header = burnchain_db.get_canonical_chain_tip().unwrap();
+        let tip_height = 12;
+        let fork_height = tip_height - reorg_depth;
+
+        // the old branch, with an operation in the first block above the fork
+        let old_blocks = make_reorg_branch(1, tip_height, &first_bhh, 0, 0);
+        let mut headers = vec![first_block_header];
+        headers.extend(old_blocks.iter().map(|block| block.header()));
+        for block in old_blocks.iter() {
+            Burnchain::process_block(
+                &burnchain,
+                &mut burnchain_db,
+                &headers,
+                block,
+                StacksEpochId::Epoch21,
+            )
+            .unwrap();
+        }
+        let old_ops = BurnchainDB::get_burnchain_block(
+            burnchain_db.conn(),
+            &headers[fork_height as usize + 1].block_hash,
+        )
+        .unwrap()
+        .ops;
+        assert_eq!(old_ops.len(), 1);
+
+        assert_eq!(
+            burnchain
+                .invalidate_reorged_blocks(&mut burnchain_db, fork_height)
+                .unwrap(),
+            Some((fork_height + 1, tip_height))
+        );
+        assert_eq!(
+            burnchain_db.get_canonical_chain_tip().unwrap().block_height,
+            fork_height
+        );
+        assert_eq!(burnchain_db.get_processed_height().unwrap(), fork_height);
+        assert!(burnchain_db
+            .find_burnchain_op(&headers, &old_ops[0].txid())
+            .is_none());
+
+        // nothing more to invalidate
+        assert_eq!(
+            burnchain
+                .invalidate_reorged_blocks(&mut burnchain_db, fork_height)
+                .unwrap(),
+            None
+        );
+
+        // the new branch has a different operation at the same height
+        let new_blocks = make_reorg_branch(
+            fork_height + 1,
+            tip_height,
+            &headers[fork_height as usize].block_hash,
+            0x80,
+            1,
+        );
+        headers.truncate(fork_height as usize + 1);
+        headers.extend(new_blocks.iter().map(|block| block.header()));
+        for block in new_blocks.iter() {
+            Burnchain::process_block(
+                &burnchain,
+                &mut burnchain_db,
+                &headers,
+                block,
+                StacksEpochId::Epoch21,
+            )
+            .unwrap();
+        }
+
+        let tip = burnchain_db.get_canonical_chain_tip().unwrap();
+        assert_eq!(tip.block_height, tip_height);
+        assert_eq!(tip.block_hash, new_blocks.last().unwrap().block_hash());
+        assert_eq!(burnchain_db.get_processed_height().unwrap(), tip_height);
+
+        let new_ops =
+            BurnchainDB::get_burnchain_block(burnchain_db.conn(), &new_blocks[0].block_hash())
+                .unwrap()
+                .ops;
+        assert_eq!(new_ops.len(), 1);
+        assert_ne!(new_ops[0].txid(), old_ops[0].txid());
+        assert!(burnchain_db
+            .find_burnchain_op(&headers, &new_ops[0].txid())
+            .is_some());
+        assert!(burnchain_db
+            .find_burnchain_op(&headers, &old_ops[0].txid())
+            .is_none());
+        for block in old_blocks[fork_height as usize..].iter() {
+            assert!(
+                !BurnchainDB::has_burnchain_block(burnchain_db.conn(), &block.block_hash())
+                    .unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_resume_after_restart() {
+    let path = "/tmp/test-burnchain-db-resume-after-restart.sqlite";
+    let first_bhh = BurnchainHeaderHash::from_hex(BITCOIN_REGTEST_FIRST_BLOCK_HASH).unwrap();
+    let mut burnchain = Burnchain::regtest(":memory:");
+    burnchain.pox_constants = PoxConstants::test_default();
+    let tip_height = 15;
+    let blocks = make_reorg_branch(1, tip_height, &first_bhh, 0, 0);
+
+    for kill_height in [1, 7, 10, 14] {
+        if fs::metadata(path).is_ok() {
+            fs::remove_file(path).unwrap();
+        }
+        let mut burnchain_db = BurnchainDB::connect(path, &burnchain, true).unwrap();
+        let mut headers = vec![burnchain_db.get_canonical_chain_tip().unwrap()];
+        headers.extend(blocks.iter().map(|block| block.header()));
+
+        let mut processed = vec![];
+        for block in blocks[0..(kill_height as usize)].iter() {
+            Burnchain::process_block(
+                &burnchain,
+                &mut burnchain_db,
+                &headers,
+                block,
+                StacksEpochId::Epoch21,
+            )
+            .unwrap();
+            processed.push(block.block_height());
+        }
+        drop(burnchain_db);
+
+        // restart, and pick up exactly one block after the last processed one
+        let mut burnchain_db = BurnchainDB::connect(path, &burnchain, true).unwrap();
+        let (processed_height, processed_hash) = burnchain_db.get_processed_block().unwrap();
+        assert_eq!(processed_height, kill_height);
+        assert_eq!(
+            processed_hash,
+            Some(headers[kill_height as usize].block_hash.clone())
+        );
+        assert_eq!(
+            burnchain
+                .find_processed_block_fork(&burnchain_db, &headers)
+                .unwrap(),
+            None
+        );
+
+        for block in blocks[(processed_height as usize)..].iter() {
+            Burnchain::process_block(
+                &burnchain,
+                &mut burnchain_db,
+                &headers,
+                block,
+                StacksEpochId::Epoch21,
+            )
+            .unwrap();
+            processed.push(block.block_height());
+        }
+
+        // every block was processed exactly once, in order
+        assert_eq!(processed, (1..(tip_height + 1)).collect::<Vec<_>>());
+        assert_eq!(
+            burnchain_db.get_processed_block().unwrap(),
+            (
+                tip_height,
+                Some(headers[tip_height as usize].block_hash.clone())
+            )
+        );
+        for block in blocks.iter() {
+            let block_data =
+                BurnchainDB::get_burnchain_block(burnchain_db.conn(), &block.block_hash()).unwrap();
+            assert_eq!(block_data.header, block.header());
+        }
+        let block_data =
+            BurnchainDB::get_burnchain_block(burnchain_db.conn(), &blocks[0].block_hash()).unwrap();
+        assert_eq!(block_data.ops.len(), 1);
+    }
+
+    // a reorg happens while we're stopped
+    if fs::metadata(path).is_ok() {
+        fs::remove_file(path).unwrap();
+    }
+    let kill_height = 10;
+    let fork_height = 6;
+    let mut burnchain_db = BurnchainDB::connect(path, &burnchain, true).unwrap();
+    let mut headers = vec![burnchain_db.get_canonical_chain_tip().unwrap()];
+    headers.extend(blocks.iter().map(|block| block.header()));
+    for block in blocks[0..(kill_height as usize)].iter() {
+        Burnchain::process_block(
+            &burnchain,
+            &mut burnchain_db,
+            &headers,
+            block,
+            StacksEpochId::Epoch21,
+        )
+        .unwrap();
+    }
+    drop(burnchain_db);
+
+    let new_blocks = make_reorg_branch(
+        fork_height + 1,
+        tip_height,
+        &headers[fork_height as usize].block_hash,
+        0x80,
+        1,
+    );
+    headers.truncate(fork_height as usize + 1);
+    headers.extend(new_blocks.iter().map(|block| block.header()));
+
+    // the recorded hash no longer matches the headers, so we don't blindly resume
+    let mut burnchain_db = BurnchainDB::connect(path, &burnchain, true).unwrap();
+    assert_eq!(
+        burnchain_db.get_processed_block().unwrap(),
+        (
+            kill_height,
+            Some(blocks[kill_height as usize - 1].block_hash())
+        )
+    );
+    assert_eq!(
+        burnchain
+            .find_processed_block_fork(&burnchain_db, &headers)
+            .unwrap(),
+        Some(fork_height)
+    );
+    assert_eq!(
+        burnchain
+            .invalidate_reorged_blocks(&mut burnchain_db, fork_height)
+            .unwrap(),
+        Some((fork_height + 1, kill_height))
+    );
+    assert_eq!(
+        burnchain_db.get_processed_block().unwrap(),
+        (
+            fork_height,
+            Some(headers[fork_height as usize].block_hash.clone())
+        )
+    );
+
+    for block in new_blocks.iter() {
+        Burnchain::process_block(
+            &burnchain,
+            &mut burnchain_db,
+            &headers,
+            block,
+            StacksEpochId::Epoch21,
+        )
+        .unwrap();
+    }
+    let tip = burnchain_db.get_canonical_chain_tip().unwrap();
+    assert_eq!(tip.block_hash, new_blocks.last().unwrap().block_hash());
+    assert_eq!(
+        burnchain_db.get_processed_block().unwrap(),
+        (tip_height, Some(tip.block_hash.clone()))
+    );
+    assert_eq!(
+        burnchain
+            .find_processed_block_fork(&burnchain_db, &headers)
+            .unwrap(),
+        None
+    );
+    for block in blocks[(fork_height as usize)..(kill_height as usize)].iter() {
+        assert!(
+            !BurnchainDB::has_burnchain_block(burnchain_db.conn(), &block.block_hash()).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_get_burn_tip() {
+    let working_dir = "/tmp/test-burnchain-db-get-burn-tip";
+    if fs::metadata(working_dir).is_ok() {
+        fs::remove_dir_all(working_dir).unwrap();
+    }
+    let indexer = BitcoinIndexer::new_unit_test(working_dir);
+    let first_bhh = BurnchainHeaderHash::from_hex(BITCOIN_REGTEST_FIRST_BLOCK_HASH).unwrap();
+    let mut burnchain = Burnchain::regtest(working_dir);
+    burnchain.pox_constants = PoxConstants::test_default();
+    let blocks = make_reorg_branch(1, 4, &first_bhh, 0, 0);
+
+    let mut burnchain_db =
+        BurnchainDB::connect(&burnchain.get_burnchaindb_path(), &burnchain, true).unwrap();
+    let mut headers = vec![burnchain_db.get_canonical_chain_tip().unwrap()];
+    headers.extend(blocks.iter().map(|block| block.header()));
+
+    // nothing processed yet:  the first block is the tip
+    let tip = indexer.get_burn_tip(&burnchain).unwrap();
+    assert_eq!(tip.block_height, headers[0].block_height);
+    assert_eq!(tip.block_hash, headers[0].block_hash);
+
+    // the tip follows each block as it's committed
+    for block in blocks[0..3].iter() {
+        Burnchain::process_block(
+            &burnchain,
+            &mut burnchain_db,
+            &headers,
+            block,
+            StacksEpochId::Epoch21,
+        )
+        .unwrap();
+        let header = block.header();
+        assert_eq!(
+            indexer.get_burn_tip(&burnchain).unwrap(),
+            BurnTip {
+                block_height: header.block_height,
+                block_hash: header.block_hash,
+                parent_block_hash: header.parent_block_hash,
+                timestamp: header.timestamp,
+            }
+        );
+    }
+
+    // a block that's stored but not yet processed isn't the tip, even though it's the DB's
+    // canonical tip
+    burnchain_db
+        .store_new_burnchain_block(&burnchain, &headers, &blocks[3], StacksEpochId::Epoch21)
+        .unwrap();
+    assert_eq!(
+        burnchain_db.get_canonical_chain_tip().unwrap().block_height,
+        4
+    );
+    let tip = indexer.get_burn_tip(&burnchain).unwrap();
+    assert_eq!(tip.block_height, 3);
+    assert_eq!(tip.block_hash, blocks[2].block_hash());
+}
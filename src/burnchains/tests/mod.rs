@@ -745,6 +745,83 @@ impl TestBurnchainNode {
     }
 }
 
+/// A regtest chain of `count` headers on top of the genesis block, at its difficulty and ten
+/// minutes apart
+pub fn make_regtest_header_chain(
+    count: u64,
+) -> Vec<stacks_common::deps_common::bitcoin::blockdata::block::LoneBlockHeader> {
+    use crate::burnchains::bitcoin::spv::SpvClient;
+    use crate::burnchains::bitcoin::BitcoinNetworkType;
+    use stacks_common::deps_common::bitcoin::blockdata::block::{BlockHeader, LoneBlockHeader};
+    use stacks_common::deps_common::bitcoin::network::encodable::VarInt;
+    use stacks_common::deps_common::bitcoin::util::hash::Sha256dHash;
+
+    let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+    let mut prev_blockhash = genesis.bitcoin_hash();
+    let mut headers = vec![];
+    for i in 1..(count + 1) {
+        let header = BlockHeader {
+            version: 0x20000000,
+            prev_blockhash,
+            merkle_root: Sha256dHash::from_data(&i.to_be_bytes()),
+            time: genesis.time + (i as u32) * 600,
+            bits: genesis.bits,
+            nonce: 0,
+        };
+        prev_blockhash = header.bitcoin_hash();
+        headers.push(LoneBlockHeader {
+            header,
+            tx_count: VarInt(0),
+        });
+    }
+    headers
+}
+
+/// A regtest chain of `count` blocks with one transaction each, on top of the genesis block
+pub fn make_regtest_block_chain(
+    count: u64,
+) -> Vec<stacks_common::deps_common::bitcoin::blockdata::block::Block> {
+    use crate::burnchains::bitcoin::spv::SpvClient;
+    use crate::burnchains::bitcoin::BitcoinNetworkType;
+    use stacks_common::deps_common::bitcoin::blockdata::block::{Block, BlockHeader};
+    use stacks_common::deps_common::bitcoin::blockdata::script::Script;
+    use stacks_common::deps_common::bitcoin::blockdata::transaction::{
+        OutPoint, Transaction, TxIn,
+    };
+    use stacks_common::deps_common::bitcoin::util::hash::bitcoin_merkle_root;
+
+    let genesis = SpvClient::get_genesis_header(BitcoinNetworkType::Regtest);
+    let mut prev_blockhash = genesis.bitcoin_hash();
+    let mut blocks = vec![];
+    for i in 1..(count + 1) {
+        let tx = Transaction {
+            version: 1,
+            lock_time: i as u32,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::from(vec![0x51]),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+        let header = BlockHeader {
+            version: 0x20000000,
+            prev_blockhash,
+            merkle_root: bitcoin_merkle_root(vec![tx.txid()]),
+            time: genesis.time + (i as u32) * 600,
+            bits: genesis.bits,
+            nonce: 0,
+        };
+        prev_blockhash = header.bitcoin_hash();
+        blocks.push(Block {
+            header,
+            txdata: vec![tx],
+        });
+    }
+    blocks
+}
+
 fn process_next_sortition(
     node: &mut TestBurnchainNode,
     fork: &mut TestBurnchainFork,
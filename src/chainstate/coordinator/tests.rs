@@ -153,18 +153,31 @@ fn get_burn_distribution(conn: &Connection, sortition: &SortitionId) -> Vec<Burn
 }
 
 fn produce_burn_block_do_not_set_height<'a, I: Iterator<Item = &'a mut BurnchainDB>>(
+    burnchain_conf: &Burnchain,
+    burnchain_db: &mut BurnchainDB,
+    par: &BurnchainHeaderHash,
+    ops: Vec<BlockstackOperationType>,
+    others: I,
+) -> BurnchainHeaderHash {
+    produce_burn_block_after_delay(burnchain_conf, burnchain_db, par, ops, others, 1)
+}
+
+/// Like `produce_burn_block_do_not_set_height()`, but the block is mined `delay` seconds after
+/// its parent, so that sibling blocks (i.e. burnchain forks) get different hashes
+fn produce_burn_block_after_delay<'a, I: Iterator<Item = &'a mut BurnchainDB>>(
     burnchain_conf: &Burnchain,
     burnchain_db: &mut BurnchainDB,
     par: &BurnchainHeaderHash,
     mut ops: Vec<BlockstackOperationType>,
     others: I,
+    delay: u64,
 ) -> BurnchainHeaderHash {
     let BurnchainBlockData {
         header: par_header, ..
     } = BurnchainDB::get_burnchain_block(&burnchain_db.conn(), par).unwrap();
     assert_eq!(&par_header.block_hash, par);
     let block_height = par_header.block_height + 1;
-    let timestamp = par_header.timestamp + 1;
+    let timestamp = par_header.timestamp + delay;
     let num_txs = ops.len() as u64;
 
     let bitcoin_header = BlockHeader {
@@ -6344,6 +6357,113 @@ fn test_pox_fork_out_of_order() {
     );
 }
 
+// A burnchain reorg leaves the old branch's blocks in the burnchain DB, and the coordinator
+// follows the canonical chain tip onto the new branch once it's the longer one
+#[test]
+fn test_burnchain_reorg_keeps_old_branch() {
+    let path = "/tmp/stacks-blockchain.test.burnchain_reorg_keeps_old_branch";
+    let _r = std::fs::remove_dir_all(path);
+
+    let vrf_keys: Vec<_> = (0..2).map(|_| VRFPrivateKey::new()).collect();
+    let committers: Vec<_> = (0..2).map(|_| StacksPrivateKey::new()).collect();
+
+    setup_states(
+        &[path],
+        &vrf_keys,
+        &committers,
+        None,
+        None,
+        StacksEpochId::Epoch2_05,
+    );
+
+    let mut coord = make_coordinator(path, None);
+    coord.handle_new_burnchain_block().unwrap();
+
+    let sort_db = get_sortition_db(path, None);
+    let b = get_burnchain(path, None);
+    let mut burnchain = get_burnchain_db(path, None);
+    let fork_tip = burnchain.get_canonical_chain_tip().unwrap();
+    let fork_sortition = SortitionDB::get_canonical_burn_chain_tip(sort_db.conn()).unwrap();
+    assert_eq!(fork_sortition.burn_header_hash, fork_tip.block_hash);
+    let key_height = fork_tip.block_height + 1;
+
+    // the old branch is two blocks long, and registers a VRF key in its first block
+    let key_register = BlockstackOperationType::LeaderKeyRegister(LeaderKeyRegisterOp {
+        public_key: VRFPublicKey::from_private(&VRFPrivateKey::new()),
+        consensus_hash: fork_sortition.consensus_hash.clone(),
+        memo: vec![0],
+        vtxindex: 1,
+        block_height: 0,
+        burn_header_hash: BurnchainHeaderHash([0; 32]),
+        txid: next_txid(),
+    });
+    let mut old_branch = vec![];
+    let mut parent = fork_tip.block_hash.clone();
+    for ix in 0..2 {
+        let ops = if ix == 0 {
+            vec![key_register.clone()]
+        } else {
+            vec![]
+        };
+        parent = produce_burn_block(&b, &mut burnchain, &parent, ops, vec![].iter_mut());
+        coord.handle_new_burnchain_block().unwrap();
+        old_branch.push(parent.clone());
+    }
+
+    let tip = SortitionDB::get_canonical_burn_chain_tip(sort_db.conn()).unwrap();
+    assert_eq!(tip.burn_header_hash, old_branch[1]);
+    assert!(SortitionDB::get_leader_key_at(
+        &sort_db.index_conn(),
+        key_height,
+        1,
+        &tip.sortition_id
+    )
+    .unwrap()
+    .is_some());
+
+    // the new branch forks off the same block, and outgrows the old one
+    let mut new_branch = vec![];
+    let mut parent = fork_tip.block_hash.clone();
+    for _ in 0..3 {
+        parent = produce_burn_block_after_delay(
+            &b,
+            &mut burnchain,
+            &parent,
+            vec![],
+            vec![].iter_mut(),
+            2,
+        );
+        coord.handle_new_burnchain_block().unwrap();
+        new_branch.push(parent.clone());
+    }
+
+    let tip = SortitionDB::get_canonical_burn_chain_tip(sort_db.conn()).unwrap();
+    assert_eq!(tip.burn_header_hash, new_branch[2]);
+    assert_eq!(tip.block_height, fork_tip.block_height + 3);
+    assert_eq!(
+        burnchain.get_canonical_chain_tip().unwrap().block_hash,
+        new_branch[2]
+    );
+
+    // the old branch's blocks are still stored, but its key register doesn't count any more
+    for block_hash in old_branch.iter() {
+        assert!(BurnchainDB::has_burnchain_block(burnchain.conn(), block_hash).unwrap());
+    }
+    let ancestor =
+        SortitionDB::get_ancestor_snapshot(&sort_db.index_conn(), key_height, &tip.sortition_id)
+            .unwrap()
+            .unwrap();
+    assert_eq!(ancestor.burn_header_hash, new_branch[0]);
+    assert!(SortitionDB::get_leader_key_at(
+        &sort_db.index_conn(),
+        key_height,
+        1,
+        &tip.sortition_id
+    )
+    .unwrap()
+    .is_none());
+}
+
 fn eval_at_chain_tip(chainstate_path: &str, sort_db: &SortitionDB, eval: &str) -> Value {
     let stacks_tip = SortitionDB::get_canonical_stacks_chain_tip_hash(sort_db.conn()).unwrap();
     test_debug!(
@@ -136,6 +136,11 @@ pub const BITCOIN_REGTEST_FIRST_BLOCK_TIMESTAMP: u32 = 0;
 pub const BITCOIN_REGTEST_FIRST_BLOCK_HASH: &str =
     "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
 
+pub const BITCOIN_SIGNET_FIRST_BLOCK_HEIGHT: u64 = 0;
+pub const BITCOIN_SIGNET_FIRST_BLOCK_TIMESTAMP: u32 = 1598918400;
+pub const BITCOIN_SIGNET_FIRST_BLOCK_HASH: &str =
+    "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6";
+
 pub const FIRST_STACKS_BLOCK_HASH: BlockHeaderHash = BlockHeaderHash([0u8; 32]);
 pub const EMPTY_MICROBLOCK_PARENT_HASH: BlockHeaderHash = BlockHeaderHash([0u8; 32]);
 
@@ -700,6 +700,74 @@ check if the associated microblocks can be downloaded
         process::exit(0);
     }
 
+    if argv[1] == "reindex-burnchain" {
+        if argv.len() < 7 {
+            eprintln!(
+                "Usage: {} reindex-burnchain mainnet|testnet|regtest|signet BURNCHAIN_DIR HEADERS_PATH HEIGHT PEER_HOST:PEER_PORT [MAGIC_BYTES]",
+                argv[0]
+            );
+            process::exit(1);
+        }
+
+        let network_name = &argv[2];
+        let network_id = match network_name.as_str() {
+            "mainnet" => BitcoinNetworkType::Mainnet,
+            "testnet" => BitcoinNetworkType::Testnet,
+            "regtest" => BitcoinNetworkType::Regtest,
+            "signet" => BitcoinNetworkType::Signet,
+            _ => {
+                eprintln!("Invalid network {}", network_name);
+                process::exit(1);
+            }
+        };
+        let height: u64 = argv[5].parse().expect("Invalid block height");
+        let (peer_host, peer_port) = match argv[6].rsplit_once(':') {
+            Some((host, port)) => (host.to_string(), port.parse().expect("Invalid peer port")),
+            None => {
+                eprintln!("Invalid peer address {}", &argv[6]);
+                process::exit(1);
+            }
+        };
+        let magic_bytes = match argv.get(7) {
+            Some(magic) if magic.len() == 2 => {
+                blockstack_lib::burnchains::MagicBytes([magic.as_bytes()[0], magic.as_bytes()[1]])
+            }
+            Some(magic) => {
+                eprintln!("Invalid magic bytes {}", magic);
+                process::exit(1);
+            }
+            None => BLOCKSTACK_MAGIC_MAINNET.clone(),
+        };
+
+        let mut burnchain = Burnchain::new(&argv[3], "bitcoin", network_name)
+            .expect("Failed to instantiate burnchain");
+        let mut config = BitcoinIndexerConfig::default(burnchain.first_block_height);
+        config.peer_host = peer_host;
+        config.peer_port = peer_port;
+        config.spv_headers_path = argv[4].clone();
+        config.magic_bytes = magic_bytes;
+        let mut indexer = BitcoinIndexer::new(config, BitcoinIndexerRuntime::new(network_id));
+
+        let (_coord_recv, coord_comm) =
+            blockstack_lib::chainstate::coordinator::comm::CoordinatorChannels::instantiate();
+        match indexer.reindex_from(&mut burnchain, coord_comm, height) {
+            Ok(tip) => {
+                println!(
+                    "Reindexed burnchain up to {} ({})",
+                    tip.block_height, &tip.block_hash
+                );
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to reindex burnchain from height {}: {:?}",
+                    height, &e
+                );
+                process::exit(1);
+            }
+        }
+    }
+
     if argv[1] == "try-mine" {
         if argv.len() < 3 {
             eprintln!(
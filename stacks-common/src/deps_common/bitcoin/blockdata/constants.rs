@@ -135,6 +135,20 @@ pub fn genesis_block(network: Network) -> Block {
                 txdata: txdata,
             }
         }
+        Network::Signet => {
+            let txdata = vec![bitcoin_genesis_tx()];
+            Block {
+                header: BlockHeader {
+                    version: 1,
+                    prev_blockhash: Default::default(),
+                    merkle_root: txdata.merkle_root(),
+                    time: 1598918400,
+                    bits: 0x1e0377ae,
+                    nonce: 52613770,
+                },
+                txdata: txdata,
+            }
+        }
     }
 }
 
@@ -208,4 +222,22 @@ mod test {
             "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943".to_string()
         );
     }
+
+    #[test]
+    fn signet_genesis_full_block() {
+        let gen = genesis_block(Network::Signet);
+        assert_eq!(gen.header.version, 1);
+        assert_eq!(gen.header.prev_blockhash, Default::default());
+        assert_eq!(
+            gen.header.merkle_root.be_hex_string(),
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b".to_string()
+        );
+        assert_eq!(gen.header.time, 1598918400);
+        assert_eq!(gen.header.bits, 0x1e0377ae);
+        assert_eq!(gen.header.nonce, 52613770);
+        assert_eq!(
+            gen.header.bitcoin_hash().be_hex_string(),
+            "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6".to_string()
+        );
+    }
 }
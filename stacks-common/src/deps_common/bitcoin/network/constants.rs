@@ -41,9 +41,17 @@ use crate::deps_common::bitcoin::network::encodable::{ConsensusDecodable, Consen
 use crate::deps_common::bitcoin::network::serialize::{self, SimpleDecoder, SimpleEncoder};
 
 /// Version of the protocol as appearing in network message headers
-pub const PROTOCOL_VERSION: u32 = 70001;
+pub const PROTOCOL_VERSION: u32 = 70012;
+/// Lowest protocol version that supports BIP130 `sendheaders`
+pub const SENDHEADERS_VERSION: u32 = 70012;
 /// Bitfield of services provided by this node
 pub const SERVICES: u64 = 0;
+/// Service bit for nodes that serve the full block chain
+pub const NODE_NETWORK: u64 = 1 << 0;
+/// Service bit for nodes that serve BIP37 bloom filters (and so answer `mempool`)
+pub const NODE_BLOOM: u64 = 1 << 2;
+/// Service bit for nodes that serve BIP157 compact block filters (BIP158 basic filters)
+pub const NODE_COMPACT_FILTERS: u64 = 1 << 6;
 /// User agent as it appears in the version message
 pub const USER_AGENT: &'static str = "bitcoin-rust v0.1";
 
@@ -56,7 +64,9 @@ user_enum! {
         /// Bitcoin's testnet
         Testnet <-> "testnet",
         /// Bitcoin's regtest
-        Regtest <-> "regtest"
+        Regtest <-> "regtest",
+        /// Bitcoin's default signet
+        Signet <-> "signet"
     }
 }
 
@@ -77,6 +87,7 @@ impl Network {
             0xD9B4BEF9 => Some(Network::Bitcoin),
             0x0709110B => Some(Network::Testnet),
             0xDAB5BFFA => Some(Network::Regtest),
+            0x40CF030A => Some(Network::Signet),
             _ => None,
         }
     }
@@ -98,6 +109,7 @@ impl Network {
             Network::Bitcoin => 0xD9B4BEF9,
             Network::Testnet => 0x0709110B,
             Network::Regtest => 0xDAB5BFFA,
+            Network::Signet => 0x40CF030A,
         }
     }
 }
@@ -138,6 +150,10 @@ mod tests {
             serialize(&Network::Regtest).unwrap(),
             vec![0xfa, 0xbf, 0xb5, 0xda]
         );
+        assert_eq!(
+            serialize(&Network::Signet).unwrap(),
+            vec![0x0a, 0x03, 0xcf, 0x40]
+        );
 
         assert_eq!(
             deserialize(&[0xf9, 0xbe, 0xb4, 0xd9]).ok(),
@@ -151,6 +167,10 @@ mod tests {
             deserialize(&[0xfa, 0xbf, 0xb5, 0xda]).ok(),
             Some(Network::Regtest)
         );
+        assert_eq!(
+            deserialize(&[0x0a, 0x03, 0xcf, 0x40]).ok(),
+            Some(Network::Signet)
+        );
 
         let bad: Result<Network, _> = deserialize("fakenet".as_bytes());
         assert!(bad.is_err());
@@ -161,10 +181,12 @@ mod tests {
         assert_eq!(Network::Bitcoin.to_string(), "bitcoin");
         assert_eq!(Network::Testnet.to_string(), "testnet");
         assert_eq!(Network::Regtest.to_string(), "regtest");
+        assert_eq!(Network::Signet.to_string(), "signet");
 
         assert_eq!("bitcoin".parse::<Network>().unwrap(), Network::Bitcoin);
         assert_eq!("testnet".parse::<Network>().unwrap(), Network::Testnet);
         assert_eq!("regtest".parse::<Network>().unwrap(), Network::Regtest);
+        assert_eq!("signet".parse::<Network>().unwrap(), Network::Signet);
         assert!("fakenet".parse::<Network>().is_err());
     }
 }
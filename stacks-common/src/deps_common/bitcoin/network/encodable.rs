@@ -31,7 +31,7 @@
 
 use std::collections::HashMap;
 use std::hash::Hash;
-use std::{mem, u32};
+use std::{cmp, mem, u32};
 
 use crate::deps_common::bitcoin::network::serialize::{self, SimpleDecoder, SimpleEncoder};
 use crate::deps_common::bitcoin::util::hash::Sha256dHash;
@@ -347,7 +347,8 @@ impl<D: SimpleDecoder> ConsensusDecodable<D> for CheckedData {
     fn consensus_decode(d: &mut D) -> Result<CheckedData, serialize::Error> {
         let len: u32 = ConsensusDecodable::consensus_decode(d)?;
         let checksum: [u8; 4] = ConsensusDecodable::consensus_decode(d)?;
-        let mut ret = Vec::with_capacity(len as usize);
+        // don't trust `len` with the allocation; the vector grows as the payload arrives
+        let mut ret = Vec::with_capacity(cmp::min(len as usize, MAX_VEC_SIZE));
         for _ in 0..len {
             ret.push(ConsensusDecodable::consensus_decode(d)?);
         }
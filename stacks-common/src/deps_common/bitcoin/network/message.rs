@@ -28,6 +28,8 @@ use crate::deps_common::bitcoin::network::address::Address;
 use crate::deps_common::bitcoin::network::encodable::CheckedData;
 use crate::deps_common::bitcoin::network::encodable::{ConsensusDecodable, ConsensusEncodable};
 use crate::deps_common::bitcoin::network::message_blockdata;
+use crate::deps_common::bitcoin::network::message_bloom;
+use crate::deps_common::bitcoin::network::message_filter;
 use crate::deps_common::bitcoin::network::message_network;
 use crate::deps_common::bitcoin::network::serialize::{
     self, serialize, RawDecoder, SimpleDecoder, SimpleEncoder,
@@ -104,6 +106,8 @@ pub enum NetworkMessage {
     Block(block::Block),
     /// `headers`
     Headers(Vec<block::LoneBlockHeader>),
+    /// BIP130 `sendheaders`
+    SendHeaders,
     /// `getaddr`
     GetAddr,
     /// `ping`
@@ -112,6 +116,26 @@ pub enum NetworkMessage {
     Pong(u64),
     /// `alert`
     Alert(Vec<u8>),
+    /// BIP157 getcfilters
+    GetCFilters(message_filter::GetCFilters),
+    /// BIP157 cfilter
+    CFilter(message_filter::CFilter),
+    /// BIP157 getcfheaders
+    GetCFHeaders(message_filter::GetCFHeaders),
+    /// BIP157 cfheaders
+    CFHeaders(message_filter::CFHeaders),
+    /// BIP157 getcfcheckpt
+    GetCFCheckpt(message_filter::GetCFCheckpt),
+    /// BIP157 cfcheckpt
+    CFCheckpt(message_filter::CFCheckpt),
+    /// BIP37 filterload
+    FilterLoad(message_bloom::FilterLoad),
+    /// BIP37 filteradd
+    FilterAdd(message_bloom::FilterAdd),
+    /// BIP37 filterclear
+    FilterClear,
+    /// BIP37 merkleblock
+    MerkleBlock(message_bloom::MerkleBlock),
 }
 
 impl RawNetworkMessage {
@@ -130,10 +154,21 @@ impl RawNetworkMessage {
             NetworkMessage::Tx(_) => "tx",
             NetworkMessage::Block(_) => "block",
             NetworkMessage::Headers(_) => "headers",
+            NetworkMessage::SendHeaders => "sendheaders",
             NetworkMessage::GetAddr => "getaddr",
             NetworkMessage::Ping(_) => "ping",
             NetworkMessage::Pong(_) => "pong",
             NetworkMessage::Alert(_) => "alert",
+            NetworkMessage::GetCFilters(_) => "getcfilters",
+            NetworkMessage::CFilter(_) => "cfilter",
+            NetworkMessage::GetCFHeaders(_) => "getcfheaders",
+            NetworkMessage::CFHeaders(_) => "cfheaders",
+            NetworkMessage::GetCFCheckpt(_) => "getcfcheckpt",
+            NetworkMessage::CFCheckpt(_) => "cfcheckpt",
+            NetworkMessage::FilterLoad(_) => "filterload",
+            NetworkMessage::FilterAdd(_) => "filteradd",
+            NetworkMessage::FilterClear => "filterclear",
+            NetworkMessage::MerkleBlock(_) => "merkleblock",
         }
         .to_owned()
     }
@@ -157,10 +192,21 @@ impl<S: SimpleEncoder> ConsensusEncodable<S> for RawNetworkMessage {
                 NetworkMessage::Tx(ref dat) => serialize(dat),
                 NetworkMessage::Block(ref dat) => serialize(dat),
                 NetworkMessage::Headers(ref dat) => serialize(dat),
+                NetworkMessage::SendHeaders => Ok(vec![]),
                 NetworkMessage::GetAddr => Ok(vec![]),
                 NetworkMessage::Ping(ref dat) => serialize(dat),
                 NetworkMessage::Pong(ref dat) => serialize(dat),
                 NetworkMessage::Alert(ref dat) => serialize(dat),
+                NetworkMessage::GetCFilters(ref dat) => serialize(dat),
+                NetworkMessage::CFilter(ref dat) => serialize(dat),
+                NetworkMessage::GetCFHeaders(ref dat) => serialize(dat),
+                NetworkMessage::CFHeaders(ref dat) => serialize(dat),
+                NetworkMessage::GetCFCheckpt(ref dat) => serialize(dat),
+                NetworkMessage::CFCheckpt(ref dat) => serialize(dat),
+                NetworkMessage::FilterLoad(ref dat) => serialize(dat),
+                NetworkMessage::FilterAdd(ref dat) => serialize(dat),
+                NetworkMessage::FilterClear => Ok(vec![]),
+                NetworkMessage::MerkleBlock(ref dat) => serialize(dat),
             }
             .unwrap(),
         )
@@ -194,11 +240,38 @@ impl<D: SimpleDecoder> ConsensusDecodable<D> for RawNetworkMessage {
             "mempool" => NetworkMessage::MemPool,
             "block" => NetworkMessage::Block(ConsensusDecodable::consensus_decode(&mut mem_d)?),
             "headers" => NetworkMessage::Headers(ConsensusDecodable::consensus_decode(&mut mem_d)?),
+            "sendheaders" => NetworkMessage::SendHeaders,
             "getaddr" => NetworkMessage::GetAddr,
             "ping" => NetworkMessage::Ping(ConsensusDecodable::consensus_decode(&mut mem_d)?),
             "pong" => NetworkMessage::Pong(ConsensusDecodable::consensus_decode(&mut mem_d)?),
             "tx" => NetworkMessage::Tx(ConsensusDecodable::consensus_decode(&mut mem_d)?),
             "alert" => NetworkMessage::Alert(ConsensusDecodable::consensus_decode(&mut mem_d)?),
+            "getcfilters" => {
+                NetworkMessage::GetCFilters(ConsensusDecodable::consensus_decode(&mut mem_d)?)
+            }
+            "cfilter" => NetworkMessage::CFilter(ConsensusDecodable::consensus_decode(&mut mem_d)?),
+            "getcfheaders" => {
+                NetworkMessage::GetCFHeaders(ConsensusDecodable::consensus_decode(&mut mem_d)?)
+            }
+            "cfheaders" => {
+                NetworkMessage::CFHeaders(ConsensusDecodable::consensus_decode(&mut mem_d)?)
+            }
+            "getcfcheckpt" => {
+                NetworkMessage::GetCFCheckpt(ConsensusDecodable::consensus_decode(&mut mem_d)?)
+            }
+            "cfcheckpt" => {
+                NetworkMessage::CFCheckpt(ConsensusDecodable::consensus_decode(&mut mem_d)?)
+            }
+            "filterload" => {
+                NetworkMessage::FilterLoad(ConsensusDecodable::consensus_decode(&mut mem_d)?)
+            }
+            "filteradd" => {
+                NetworkMessage::FilterAdd(ConsensusDecodable::consensus_decode(&mut mem_d)?)
+            }
+            "filterclear" => NetworkMessage::FilterClear,
+            "merkleblock" => {
+                NetworkMessage::MerkleBlock(ConsensusDecodable::consensus_decode(&mut mem_d)?)
+            }
             _ => return Err(serialize::Error::UnrecognizedNetworkCommand(cmd)),
         };
         Ok(RawNetworkMessage {
@@ -212,6 +285,9 @@ impl<D: SimpleDecoder> ConsensusDecodable<D> for RawNetworkMessage {
 mod test {
     use super::{CommandString, NetworkMessage, RawNetworkMessage};
 
+    use crate::deps_common::bitcoin::network::message_bloom;
+    use crate::deps_common::bitcoin::network::message_filter;
+
     use crate::deps_common::bitcoin::network::serialize::{deserialize, serialize};
 
     #[test]
@@ -281,6 +357,77 @@ mod test {
         );
     }
 
+    #[test]
+    fn serialize_sendheaders_test() {
+        let msg = RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::SendHeaders,
+        };
+        let bytes = serialize(&msg).unwrap();
+        assert_eq!(
+            bytes,
+            vec![
+                0xf9, 0xbe, 0xb4, 0xd9, 0x73, 0x65, 0x6e, 0x64, 0x68, 0x65, 0x61, 0x64, 0x65, 0x72,
+                0x73, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5d, 0xf6, 0xe0, 0xe2
+            ]
+        );
+        assert_eq!(
+            deserialize::<RawNetworkMessage>(&bytes).unwrap().payload,
+            NetworkMessage::SendHeaders
+        );
+    }
+
+    #[test]
+    fn serialize_getcfcheckpt_test() {
+        let msg = RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::GetCFCheckpt(message_filter::GetCFCheckpt {
+                filter_type: 0,
+                stop_hash: Default::default(),
+            }),
+        };
+        let bytes = serialize(&msg).unwrap();
+        assert_eq!(&bytes[4..16], b"getcfcheckpt");
+        let decoded: RawNetworkMessage = deserialize(&bytes).unwrap();
+        assert_eq!(decoded.payload, msg.payload);
+    }
+
+    #[test]
+    fn serialize_filterload_test() {
+        let msg = RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::FilterLoad(message_bloom::FilterLoad {
+                filter: vec![0xb5, 0x0f],
+                hash_funcs: 11,
+                tweak: 0,
+                flags: 1,
+            }),
+        };
+        let bytes = serialize(&msg).unwrap();
+        assert_eq!(&bytes[4..14], b"filterload");
+        assert_eq!(
+            &bytes[24..],
+            &[0x02, 0xb5, 0x0f, 11, 0, 0, 0, 0, 0, 0, 0, 1]
+        );
+        let decoded: RawNetworkMessage = deserialize(&bytes).unwrap();
+        assert_eq!(decoded.payload, msg.payload);
+    }
+
+    #[test]
+    fn serialize_filteradd_test() {
+        let msg = RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::FilterAdd(message_bloom::FilterAdd {
+                data: vec![0xfd, 0xac, 0xf9],
+            }),
+        };
+        let bytes = serialize(&msg).unwrap();
+        assert_eq!(&bytes[4..13], b"filteradd");
+        assert_eq!(&bytes[24..], &[0x03, 0xfd, 0xac, 0xf9]);
+        let decoded: RawNetworkMessage = deserialize(&bytes).unwrap();
+        assert_eq!(decoded.payload, msg.payload);
+    }
+
     #[test]
     fn serialize_getaddr_test() {
         assert_eq!(
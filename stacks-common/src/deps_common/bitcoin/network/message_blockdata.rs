@@ -36,6 +36,8 @@ pub enum InvType {
     WitnessBlock,
     /// Witness Transaction
     WitnessTransaction,
+    /// BIP37 filtered block, sent as a `merkleblock`
+    FilteredBlock,
 }
 
 // Some simple messages
@@ -110,6 +112,7 @@ impl<S: SimpleEncoder> ConsensusEncodable<S> for Inventory {
             InvType::Block => 2,
             InvType::WitnessBlock => 0x40000002,
             InvType::WitnessTransaction => 0x40000001,
+            InvType::FilteredBlock => 3,
         }
         .consensus_encode(s)?;
         self.hash.consensus_encode(s)
@@ -125,6 +128,7 @@ impl<D: SimpleDecoder> ConsensusDecodable<D> for Inventory {
                 0 => InvType::Error,
                 1 => InvType::Transaction,
                 2 => InvType::Block,
+                3 => InvType::FilteredBlock,
                 // TODO do not fail here
                 _ => panic!("bad inventory type field"),
             },
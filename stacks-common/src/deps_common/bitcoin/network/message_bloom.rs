@@ -0,0 +1,114 @@
+// Rust Bitcoin Library
+// Written in 2014 by
+//     Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! BIP37 Connection Bloom filtering network messages
+//!
+//! This module describes the network messages which are used for
+//! loading a bloom filter into a peer and receiving filtered blocks.
+//!
+
+use crate::deps_common::bitcoin::blockdata::block::BlockHeader;
+use crate::deps_common::bitcoin::util::hash::Sha256dHash;
+
+/// The `filterload` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct FilterLoad {
+    /// The filter's bit field
+    pub filter: Vec<u8>,
+    /// Number of hash functions used for each element
+    pub hash_funcs: u32,
+    /// Random value added to the seed of each hash function
+    pub tweak: u32,
+    /// How the peer updates the filter as transactions match it
+    pub flags: u8,
+}
+impl_consensus_encoding!(FilterLoad, filter, hash_funcs, tweak, flags);
+
+/// The `filteradd` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct FilterAdd {
+    /// The element to add to the loaded filter (at most 520 bytes)
+    pub data: Vec<u8>,
+}
+impl_consensus_encoding!(FilterAdd, data);
+
+/// The `merkleblock` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct MerkleBlock {
+    /// The block's header
+    pub header: BlockHeader,
+    /// Number of transactions in the block
+    pub total_transactions: u32,
+    /// Hashes of the partial merkle tree, in depth-first order
+    pub hashes: Vec<Sha256dHash>,
+    /// Flag bits of the partial merkle tree, in depth-first order, packed least significant bit
+    /// first
+    pub flags: Vec<u8>,
+}
+impl_consensus_encoding!(MerkleBlock, header, total_transactions, hashes, flags);
+
+#[cfg(test)]
+mod tests {
+    use super::{FilterAdd, FilterLoad, MerkleBlock};
+
+    use crate::deps_common::bitcoin::blockdata::block::BlockHeader;
+    use crate::deps_common::bitcoin::network::serialize::{deserialize, serialize};
+    use crate::deps_common::bitcoin::util::hash::Sha256dHash;
+
+    #[test]
+    fn filterload_message_test() {
+        let msg = FilterLoad {
+            filter: vec![0xb5, 0x0f],
+            hash_funcs: 11,
+            tweak: 5,
+            flags: 0,
+        };
+        let bytes = serialize(&msg).unwrap();
+        assert_eq!(bytes, vec![0x02, 0xb5, 0x0f, 11, 0, 0, 0, 5, 0, 0, 0, 0]);
+        assert_eq!(deserialize::<FilterLoad>(&bytes).unwrap(), msg);
+    }
+
+    #[test]
+    fn filteradd_message_test() {
+        let msg = FilterAdd {
+            data: vec![0x99; 20],
+        };
+        let bytes = serialize(&msg).unwrap();
+        assert_eq!(bytes.len(), 21);
+        assert_eq!(bytes[0], 20);
+        assert_eq!(deserialize::<FilterAdd>(&bytes).unwrap(), msg);
+    }
+
+    #[test]
+    fn merkleblock_message_test() {
+        let msg = MerkleBlock {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: Default::default(),
+                merkle_root: Sha256dHash::from_data(&[1]),
+                time: 1231006505,
+                bits: 0x1d00ffff,
+                nonce: 2083236893,
+            },
+            total_transactions: 7,
+            hashes: vec![Sha256dHash::from_data(&[2]), Sha256dHash::from_data(&[3])],
+            flags: vec![0x1d],
+        };
+        let bytes = serialize(&msg).unwrap();
+        assert_eq!(bytes.len(), 80 + 4 + 1 + 2 * 32 + 1 + 1);
+        assert_eq!(&bytes[80..85], &[7, 0, 0, 0, 2]);
+        assert_eq!(&bytes[bytes.len() - 2..], &[1, 0x1d]);
+        assert_eq!(deserialize::<MerkleBlock>(&bytes).unwrap(), msg);
+    }
+}
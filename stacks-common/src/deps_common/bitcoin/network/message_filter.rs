@@ -0,0 +1,175 @@
+// Rust Bitcoin Library
+// Written in 2014 by
+//     Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! BIP157 Client Side Block Filtering network messages
+//!
+//! This module describes the network messages which are used for
+//! fetching compact block filters and their filter headers.
+//!
+
+use crate::deps_common::bitcoin::util::hash::Sha256dHash;
+
+/// The `getcfilters` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetCFilters {
+    /// Filter type for which filters are requested
+    pub filter_type: u8,
+    /// The height of the first block in the requested range
+    pub start_height: u32,
+    /// The hash of the last block in the requested range
+    pub stop_hash: Sha256dHash,
+}
+impl_consensus_encoding!(GetCFilters, filter_type, start_height, stop_hash);
+
+/// The `cfilter` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CFilter {
+    /// Byte identifying the type of filter being returned
+    pub filter_type: u8,
+    /// Block hash of the Bitcoin block for which the filter is being returned
+    pub block_hash: Sha256dHash,
+    /// The serialized compact filter for this block
+    pub filter: Vec<u8>,
+}
+impl_consensus_encoding!(CFilter, filter_type, block_hash, filter);
+
+/// The `getcfheaders` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetCFHeaders {
+    /// Byte identifying the type of filter being returned
+    pub filter_type: u8,
+    /// The height of the first block in the requested range
+    pub start_height: u32,
+    /// The hash of the last block in the requested range
+    pub stop_hash: Sha256dHash,
+}
+impl_consensus_encoding!(GetCFHeaders, filter_type, start_height, stop_hash);
+
+/// The `cfheaders` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CFHeaders {
+    /// Filter type for which headers are requested
+    pub filter_type: u8,
+    /// The hash of the last block in the requested range
+    pub stop_hash: Sha256dHash,
+    /// The filter header preceding the first block in the requested range
+    pub previous_filter_header: Sha256dHash,
+    /// The filter hashes for each block in the requested range
+    pub filter_hashes: Vec<Sha256dHash>,
+}
+impl_consensus_encoding!(
+    CFHeaders,
+    filter_type,
+    stop_hash,
+    previous_filter_header,
+    filter_hashes
+);
+
+/// The `getcfcheckpt` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetCFCheckpt {
+    /// Filter type for which headers are requested
+    pub filter_type: u8,
+    /// The hash of the last block in the requested range
+    pub stop_hash: Sha256dHash,
+}
+impl_consensus_encoding!(GetCFCheckpt, filter_type, stop_hash);
+
+/// The `cfcheckpt` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CFCheckpt {
+    /// Filter type for which headers are requested
+    pub filter_type: u8,
+    /// The hash of the last block in the requested range
+    pub stop_hash: Sha256dHash,
+    /// The filter headers at intervals of 1,000
+    pub filter_headers: Vec<Sha256dHash>,
+}
+impl_consensus_encoding!(CFCheckpt, filter_type, stop_hash, filter_headers);
+
+#[cfg(test)]
+mod tests {
+    use super::{CFCheckpt, CFHeaders, CFilter, GetCFHeaders, GetCFilters};
+
+    use crate::util::hash::hex_bytes as hex_decode;
+
+    use crate::deps_common::bitcoin::network::serialize::{deserialize, serialize};
+    use crate::deps_common::bitcoin::util::hash::Sha256dHash;
+
+    #[test]
+    fn getcfilters_message_test() {
+        let from_sat = hex_decode(
+            "00e803000043497fd7f826957108f4a30fd9cec3aeba79972084e90ead01ea330900000000",
+        )
+        .unwrap();
+
+        let decode: Result<GetCFilters, _> = deserialize(&from_sat);
+        assert!(decode.is_ok());
+        let real_decode = decode.unwrap();
+        assert_eq!(real_decode.filter_type, 0);
+        assert_eq!(real_decode.start_height, 1000);
+        assert_eq!(
+            real_decode.stop_hash,
+            Sha256dHash::from_hex(
+                "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943"
+            )
+            .unwrap()
+        );
+
+        assert_eq!(serialize(&real_decode).ok(), Some(from_sat));
+
+        // same layout as getcfilters
+        let decode: GetCFHeaders = deserialize(&serialize(&real_decode).unwrap()).unwrap();
+        assert_eq!(decode.start_height, 1000);
+    }
+
+    #[test]
+    fn cfilter_message_test() {
+        let msg = CFilter {
+            filter_type: 0,
+            block_hash: Sha256dHash::from_hex(
+                "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+            )
+            .unwrap(),
+            filter: hex_decode("019dfca8").unwrap(),
+        };
+        let bytes = serialize(&msg).unwrap();
+        assert_eq!(bytes.len(), 1 + 32 + 1 + 4);
+        assert_eq!(&bytes[33..], &[0x04, 0x01, 0x9d, 0xfc, 0xa8]);
+        assert_eq!(deserialize::<CFilter>(&bytes).unwrap(), msg);
+    }
+
+    #[test]
+    fn cfheaders_cfcheckpt_message_test() {
+        let hash = Sha256dHash::from_data(&[1, 2, 3]);
+        let msg = CFHeaders {
+            filter_type: 0,
+            stop_hash: hash,
+            previous_filter_header: Default::default(),
+            filter_hashes: vec![hash, hash],
+        };
+        let bytes = serialize(&msg).unwrap();
+        assert_eq!(bytes.len(), 1 + 32 + 32 + 1 + 64);
+        assert_eq!(deserialize::<CFHeaders>(&bytes).unwrap(), msg);
+
+        let msg = CFCheckpt {
+            filter_type: 0,
+            stop_hash: hash,
+            filter_headers: vec![],
+        };
+        let bytes = serialize(&msg).unwrap();
+        assert_eq!(bytes.len(), 1 + 32 + 1);
+        assert_eq!(deserialize::<CFCheckpt>(&bytes).unwrap(), msg);
+    }
+}
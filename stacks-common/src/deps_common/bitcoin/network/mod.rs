@@ -29,6 +29,8 @@ pub mod serialize;
 
 pub mod message;
 pub mod message_blockdata;
+pub mod message_bloom;
+pub mod message_filter;
 pub mod message_network;
 
 /// Network error
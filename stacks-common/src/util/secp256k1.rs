@@ -19,11 +19,13 @@ use secp256k1::constants as LibSecp256k1Constants;
 use secp256k1::ecdsa::RecoverableSignature as LibSecp256k1RecoverableSignature;
 use secp256k1::ecdsa::RecoveryId as LibSecp256k1RecoveryID;
 use secp256k1::ecdsa::Signature as LibSecp256k1Signature;
+use secp256k1::schnorr::Signature as LibSecp256k1SchnorrSignature;
 use secp256k1::Error as LibSecp256k1Error;
 use secp256k1::Message as LibSecp256k1Message;
 use secp256k1::PublicKey as LibSecp256k1PublicKey;
 use secp256k1::Secp256k1;
 use secp256k1::SecretKey as LibSecp256k1PrivateKey;
+use secp256k1::XOnlyPublicKey as LibSecp256k1XOnlyPublicKey;
 
 use crate::types::PrivateKey;
 use crate::types::PublicKey;
@@ -156,6 +158,26 @@ impl Secp256k1PublicKey {
         self.key.serialize().to_vec()
     }
 
+    pub fn to_bytes_uncompressed(&self) -> Vec<u8> {
+        self.key.serialize_uncompressed().to_vec()
+    }
+
+    /// Verify a DER-encoded signature over a 32-byte hash the way Bitcoin script does: the
+    /// encoding is decoded leniently, and high-S signatures are accepted.
+    pub fn verify_der(&self, data_hash: &[u8], der_sig: &[u8]) -> Result<bool, &'static str> {
+        _secp256k1.with(|ctx| {
+            let msg = LibSecp256k1Message::from_slice(data_hash).map_err(|_e| {
+                "Invalid message: failed to decode data hash: must be a 32-byte hash"
+            })?;
+
+            let mut secp256k1_sig = LibSecp256k1Signature::from_der_lax(der_sig)
+                .map_err(|_e| "Invalid signature: failed to decode DER signature")?;
+            secp256k1_sig.normalize_s();
+
+            Ok(ctx.verify_ecdsa(&msg, &secp256k1_sig, &self.key).is_ok())
+        })
+    }
+
     pub fn compressed(&self) -> bool {
         self.compressed
     }
@@ -402,6 +424,19 @@ pub fn secp256k1_verify(
     })
 }
 
+pub fn secp256k1_verify_schnorr(
+    message_arr: &[u8],
+    signature_arr: &[u8],
+    xonly_pubkey_arr: &[u8],
+) -> Result<(), LibSecp256k1Error> {
+    _secp256k1.with(|ctx| {
+        let message = LibSecp256k1Message::from_slice(message_arr)?;
+        let signature = LibSecp256k1SchnorrSignature::from_slice(signature_arr)?;
+        let pubkey = LibSecp256k1XOnlyPublicKey::from_slice(xonly_pubkey_arr)?;
+        ctx.verify_schnorr(&signature, &message, &pubkey)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
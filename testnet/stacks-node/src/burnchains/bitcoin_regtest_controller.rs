@@ -2,13 +2,16 @@ use async_h1::client;
 use async_std::io::ReadExt;
 use async_std::net::TcpStream;
 use base64::encode;
-use http_types::{Method, Request, Url};
-use std::io::Cursor;
+use http_types::{Method, Request, StatusCode, Url};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use serde::Serialize;
 use serde_json::value::RawValue;
@@ -19,11 +22,24 @@ use super::super::operations::BurnchainOpSigner;
 use super::super::Config;
 use super::{BurnchainController, BurnchainTip, Error as BurnchainControllerError};
 
+use stacks::burnchains::bitcoin::bloom::DEFAULT_BLOOM_FP_RATE;
 use stacks::burnchains::bitcoin::indexer::{
-    BitcoinIndexer, BitcoinIndexerConfig, BitcoinIndexerRuntime,
+    AddressPreference, BitcoinIndexer, BitcoinIndexerConfig, BitcoinIndexerRuntime,
+    HeaderSourceType, KeepalivePolicy, ReconnectPolicy, RpcEndpoint, SyncMode,
+    DEFAULT_BLOCK_DOWNLOAD_WINDOW, DEFAULT_BLOCK_PARSE_THREADS, DEFAULT_BLOCK_REQUEST_TIMEOUT,
+    DEFAULT_HEADER_STALL_TIMEOUT, DEFAULT_HEADER_SYNC_CHUNK_SIZE, DEFAULT_HEADER_SYNC_NUM_PEERS,
+    DEFAULT_PEER_SOCKET_TIMEOUT, DEFAULT_SENDHEADERS_GRACE, DEFAULT_SHUTDOWN_TIMEOUT,
+    DEFAULT_SYNC_PROGRESS_LOG_INTERVAL,
+};
+use stacks::burnchains::bitcoin::messages::{DEFAULT_MAX_BLOCK_SIZE, DEFAULT_MAX_MESSAGE_SIZE};
+use stacks::burnchains::bitcoin::rpc::{
+    retry_rpc_call, split_batch_replies, BatchReply, RpcCredentials, DEFAULT_RPC_MAX_CONNECTIONS,
+};
+use stacks::burnchains::bitcoin::spv::{
+    SpvClient, DEFAULT_HEADER_FSYNC_INTERVAL, DEFAULT_HEADER_FSYNC_PERIOD,
 };
-use stacks::burnchains::bitcoin::spv::SpvClient;
 use stacks::burnchains::bitcoin::BitcoinNetworkType;
+use stacks::burnchains::bitcoin::Error as btc_error;
 use stacks::burnchains::db::BurnchainDB;
 use stacks::burnchains::indexer::BurnchainIndexer;
 use stacks::burnchains::BurnchainStateTransitionOps;
@@ -148,17 +164,72 @@ pub fn make_bitcoin_indexer(config: &Config) -> BitcoinIndexer {
             timeout: burnchain_config.timeout,
             spv_headers_path: config.get_spv_headers_file_path(),
             first_block: burnchain_params.first_block_height,
+            first_block_hash: Some(burnchain_params.first_block_hash.clone()),
             magic_bytes: burnchain_config.magic_bytes,
             epochs: burnchain_config.epochs,
+            checkpoints: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            connect_timeout: None,
+            address_preference: AddressPreference::System,
+            socks_proxy: None,
+            read_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+            write_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+            header_sync_peers: vec![],
+            header_sync_num_peers: DEFAULT_HEADER_SYNC_NUM_PEERS,
+            header_sync_chunk_size: DEFAULT_HEADER_SYNC_CHUNK_SIZE,
+            header_stall_timeout: Some(Duration::from_secs(DEFAULT_HEADER_STALL_TIMEOUT)),
+            mempool_ttl: None,
+            header_pruning: None,
+            spv_mmap_size: 0,
+            filter_scripts: vec![],
+            custom_network: None,
+            assume_valid: None,
+            assume_valid_checkpoints: false,
+            strict_header_versions: config.burnchain.get_bitcoin_network().1
+                == BitcoinNetworkType::Mainnet,
+            custom_genesis: None,
+            first_burn_block: None,
+            block_download_window: DEFAULT_BLOCK_DOWNLOAD_WINDOW,
+            block_parse_threads: DEFAULT_BLOCK_PARSE_THREADS,
+            sig_verify_threads: 0,
+            block_request_timeout: Duration::from_secs(DEFAULT_BLOCK_REQUEST_TIMEOUT),
+            shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT),
+            sync_progress_log_interval: Some(Duration::from_secs(
+                DEFAULT_SYNC_PROGRESS_LOG_INTERVAL,
+            )),
+            block_download_rate_limit: None,
+            keepalive: Some(KeepalivePolicy::default()),
+            sendheaders_grace: Some(Duration::from_secs(DEFAULT_SENDHEADERS_GRACE)),
+            header_source: HeaderSourceType::Peer,
+            sync_mode: SyncMode::P2p,
+            pruned_block_fallback: None,
+            request_limit: None,
+            rpc_max_connections: DEFAULT_RPC_MAX_CONNECTIONS,
+            rpc_retries: config.burnchain.rpc_retries,
+            header_fsync_interval: DEFAULT_HEADER_FSYNC_INTERVAL,
+            header_fsync_period: DEFAULT_HEADER_FSYNC_PERIOD,
+            filter_outpoints: vec![],
+            bloom_filter_fp_rate: DEFAULT_BLOOM_FP_RATE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+            dry_run: false,
+            drop_reorged_blocks: false,
+            max_reorg_depth: burnchain_config.max_reorg_depth,
         }
     };
 
     let (_, network_type) = config.burnchain.get_bitcoin_network();
     let indexer_runtime = BitcoinIndexerRuntime::new(network_type);
-    let burnchain_indexer = BitcoinIndexer {
+    let mut burnchain_indexer = BitcoinIndexer {
         config: indexer_config.clone(),
         runtime: indexer_runtime,
     };
+    burnchain_indexer.set_rpc_credentials(Arc::new(BitcoindRpcCredentials {
+        config: config.clone(),
+    }));
+    if config.burnchain.accept_deep_reorg {
+        burnchain_indexer.accept_deep_reorg();
+    }
     burnchain_indexer
 }
 
@@ -297,17 +368,72 @@ impl BitcoinRegtestController {
                 timeout: burnchain_config.timeout,
                 spv_headers_path: config.get_spv_headers_file_path(),
                 first_block: burnchain_params.first_block_height,
+                first_block_hash: Some(burnchain_params.first_block_hash.clone()),
                 magic_bytes: burnchain_config.magic_bytes,
                 epochs: burnchain_config.epochs,
+                checkpoints: None,
+                reconnect_policy: ReconnectPolicy::default(),
+                connect_timeout: None,
+                address_preference: AddressPreference::System,
+                socks_proxy: None,
+                read_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+                write_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+                header_sync_peers: vec![],
+                header_sync_num_peers: DEFAULT_HEADER_SYNC_NUM_PEERS,
+                header_sync_chunk_size: DEFAULT_HEADER_SYNC_CHUNK_SIZE,
+                header_stall_timeout: Some(Duration::from_secs(DEFAULT_HEADER_STALL_TIMEOUT)),
+                mempool_ttl: None,
+                header_pruning: None,
+                spv_mmap_size: 0,
+                filter_scripts: vec![],
+                custom_network: None,
+                assume_valid: None,
+                assume_valid_checkpoints: false,
+                strict_header_versions: config.burnchain.get_bitcoin_network().1
+                    == BitcoinNetworkType::Mainnet,
+                custom_genesis: None,
+                first_burn_block: None,
+                block_download_window: DEFAULT_BLOCK_DOWNLOAD_WINDOW,
+                block_parse_threads: DEFAULT_BLOCK_PARSE_THREADS,
+                sig_verify_threads: 0,
+                block_request_timeout: Duration::from_secs(DEFAULT_BLOCK_REQUEST_TIMEOUT),
+                shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT),
+                sync_progress_log_interval: Some(Duration::from_secs(
+                    DEFAULT_SYNC_PROGRESS_LOG_INTERVAL,
+                )),
+                block_download_rate_limit: None,
+                keepalive: Some(KeepalivePolicy::default()),
+                sendheaders_grace: Some(Duration::from_secs(DEFAULT_SENDHEADERS_GRACE)),
+                header_source: HeaderSourceType::Peer,
+                sync_mode: SyncMode::P2p,
+                pruned_block_fallback: None,
+                request_limit: None,
+                rpc_max_connections: DEFAULT_RPC_MAX_CONNECTIONS,
+                rpc_retries: config.burnchain.rpc_retries,
+                header_fsync_interval: DEFAULT_HEADER_FSYNC_INTERVAL,
+                header_fsync_period: DEFAULT_HEADER_FSYNC_PERIOD,
+                filter_outpoints: vec![],
+                bloom_filter_fp_rate: DEFAULT_BLOOM_FP_RATE,
+                max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+                max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+                dry_run: false,
+                drop_reorged_blocks: false,
+                max_reorg_depth: burnchain_config.max_reorg_depth,
             }
         };
 
         let (_, network_type) = config.burnchain.get_bitcoin_network();
         let indexer_runtime = BitcoinIndexerRuntime::new(network_type);
-        let burnchain_indexer = BitcoinIndexer {
+        let mut burnchain_indexer = BitcoinIndexer {
             config: indexer_config.clone(),
             runtime: indexer_runtime,
         };
+        burnchain_indexer.set_rpc_credentials(Arc::new(BitcoindRpcCredentials {
+            config: config.clone(),
+        }));
+        if config.burnchain.accept_deep_reorg {
+            burnchain_indexer.accept_deep_reorg();
+        }
 
         Self {
             use_coordinator: coordinator_channel,
@@ -342,17 +468,69 @@ impl BitcoinRegtestController {
                 timeout: burnchain_config.timeout,
                 spv_headers_path: config.get_spv_headers_file_path(),
                 first_block: burnchain_params.first_block_height,
+                first_block_hash: Some(burnchain_params.first_block_hash.clone()),
                 magic_bytes: burnchain_config.magic_bytes,
                 epochs: burnchain_config.epochs,
+                checkpoints: None,
+                reconnect_policy: ReconnectPolicy::default(),
+                connect_timeout: None,
+                address_preference: AddressPreference::System,
+                socks_proxy: None,
+                read_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+                write_timeout: Duration::from_secs(DEFAULT_PEER_SOCKET_TIMEOUT),
+                header_sync_peers: vec![],
+                header_sync_num_peers: DEFAULT_HEADER_SYNC_NUM_PEERS,
+                header_sync_chunk_size: DEFAULT_HEADER_SYNC_CHUNK_SIZE,
+                header_stall_timeout: Some(Duration::from_secs(DEFAULT_HEADER_STALL_TIMEOUT)),
+                mempool_ttl: None,
+                header_pruning: None,
+                spv_mmap_size: 0,
+                filter_scripts: vec![],
+                custom_network: None,
+                assume_valid: None,
+                assume_valid_checkpoints: false,
+                strict_header_versions: config.burnchain.get_bitcoin_network().1
+                    == BitcoinNetworkType::Mainnet,
+                custom_genesis: None,
+                first_burn_block: None,
+                block_download_window: DEFAULT_BLOCK_DOWNLOAD_WINDOW,
+                block_parse_threads: DEFAULT_BLOCK_PARSE_THREADS,
+                sig_verify_threads: 0,
+                block_request_timeout: Duration::from_secs(DEFAULT_BLOCK_REQUEST_TIMEOUT),
+                shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT),
+                sync_progress_log_interval: Some(Duration::from_secs(
+                    DEFAULT_SYNC_PROGRESS_LOG_INTERVAL,
+                )),
+                block_download_rate_limit: None,
+                keepalive: Some(KeepalivePolicy::default()),
+                sendheaders_grace: Some(Duration::from_secs(DEFAULT_SENDHEADERS_GRACE)),
+                header_source: HeaderSourceType::Peer,
+                sync_mode: SyncMode::P2p,
+                pruned_block_fallback: None,
+                request_limit: None,
+                rpc_max_connections: DEFAULT_RPC_MAX_CONNECTIONS,
+                rpc_retries: config.burnchain.rpc_retries,
+                header_fsync_interval: DEFAULT_HEADER_FSYNC_INTERVAL,
+                header_fsync_period: DEFAULT_HEADER_FSYNC_PERIOD,
+                filter_outpoints: vec![],
+                bloom_filter_fp_rate: DEFAULT_BLOOM_FP_RATE,
+                max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+                max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+                dry_run: false,
+                drop_reorged_blocks: false,
+                max_reorg_depth: burnchain_config.max_reorg_depth,
             }
         };
 
         let (_, network_type) = config.burnchain.get_bitcoin_network();
         let indexer_runtime = BitcoinIndexerRuntime::new(network_type);
-        let burnchain_indexer = BitcoinIndexer {
+        let mut burnchain_indexer = BitcoinIndexer {
             config: indexer_config.clone(),
             runtime: indexer_runtime,
         };
+        burnchain_indexer.set_rpc_credentials(Arc::new(BitcoindRpcCredentials {
+            config: config.clone(),
+        }));
 
         Self {
             use_coordinator: None,
@@ -416,6 +594,14 @@ impl BitcoinRegtestController {
                 Ok(x) => {
                     break x;
                 }
+                Err(burnchain_error::FirstBlockNotReached(first_block_height, headers_height)) => {
+                    // not an error -- idle until the burnchain reaches the first block
+                    info!("Waiting for the burnchain to reach the first block";
+                          "first_block_height" => first_block_height,
+                          "headers_height" => headers_height);
+                    sleep_ms(5000);
+                    continue;
+                }
                 Err(e) => {
                     // keep trying
                     error!("Unable to sync with burnchain: {}", e);
@@ -430,6 +616,12 @@ impl BitcoinRegtestController {
                             sleep_ms(5000);
                             continue;
                         }
+                        burnchain_error::DeepReorg(..) => {
+                            // halted until an operator accepts the reorg (e.g. by restarting
+                            // with --accept-deep-reorg); nothing was changed in the meantime
+                            sleep_ms(5000);
+                            continue;
+                        }
                         _ => {
                             // delay and try again
                             sleep_ms(5000);
@@ -527,6 +719,14 @@ impl BitcoinRegtestController {
                         .map_err(BurnchainControllerError::IndexerError)?;
                     break (snapshot, burnchain_height, state_transition);
                 }
+                Err(burnchain_error::FirstBlockNotReached(first_block_height, headers_height)) => {
+                    // not an error -- idle until the burnchain reaches the first block
+                    info!("Waiting for the burnchain to reach the first block";
+                          "first_block_height" => first_block_height,
+                          "headers_height" => headers_height);
+                    sleep_ms(5000);
+                    continue;
+                }
                 Err(e) => {
                     // keep trying
                     error!("Unable to sync with burnchain: {}", e);
@@ -544,6 +744,12 @@ impl BitcoinRegtestController {
                             sleep_ms(5000);
                             continue;
                         }
+                        burnchain_error::DeepReorg(..) => {
+                            // halted until an operator accepts the reorg (e.g. by restarting
+                            // with --accept-deep-reorg); nothing was changed in the meantime
+                            sleep_ms(5000);
+                            continue;
+                        }
                         _ => {
                             // delay and try again
                             sleep_ms(5000);
@@ -1746,16 +1952,10 @@ impl BitcoinRegtestController {
 
     #[cfg(test)]
     pub fn get_block_hash(&self, height: u64) -> BurnchainHeaderHash {
-        let request = BitcoinRPCRequest {
-            method: "getblockhash".into(),
-            params: vec![json!(height)],
-            id: "stacks-forker".into(),
-            jsonrpc: "2.0".into(),
-        };
-        match BitcoinRPCRequest::send(&self.config, request) {
-            Ok(v) => {
-                BurnchainHeaderHash::from_hex(v.get("result").unwrap().as_str().unwrap()).unwrap()
-            }
+        match BitcoinRPCRequest::get_block_hashes(&self.config, &[height])
+            .and_then(|mut hashes| hashes.pop().unwrap())
+        {
+            Ok(hash) => hash,
             Err(e) => {
                 error!("Bitcoin RPC failure: error invalidating block {:?}", e);
                 panic!();
@@ -2112,6 +2312,25 @@ impl ParsedUTXO {
     }
 }
 
+/// Credentials for the indexer's bitcoind RPC client:  an endpoint's own username and password
+/// if it has them, or else the ones `BitcoinRPCRequest` uses.
+struct BitcoindRpcCredentials {
+    config: Config,
+}
+
+impl RpcCredentials for BitcoindRpcCredentials {
+    fn authorization(&self, endpoint: &RpcEndpoint) -> Result<Option<String>, btc_error> {
+        if let (Some(username), Some(password)) = (&endpoint.username, &endpoint.password) {
+            return Ok(Some(format!(
+                "Basic {}",
+                encode(format!("{}:{}", username, password))
+            )));
+        }
+        BitcoinRPCRequest::authorization(&self.config)
+            .map_err(|e| btc_error::ConfigError(format!("{:?}", e)))
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BitcoinRPCRequest {
     /// The name of the RPC call
@@ -2129,12 +2348,14 @@ pub enum RPCError {
     Network(String),
     Parsing(String),
     Bitcoind(String),
+    /// RPC credentials are missing or malformed
+    Config(String),
 }
 
 type RPCResult<T> = Result<T, RPCError>;
 
 impl BitcoinRPCRequest {
-    fn build_rpc_request(config: &Config, payload: &BitcoinRPCRequest) -> Request {
+    fn build_rpc_request(config: &Config, payload: &BitcoinRPCRequest) -> RPCResult<Request> {
         let url = {
             // some methods require a wallet ID
             let wallet_id: Option<String> = match payload.method.as_str() {
@@ -2150,15 +2371,51 @@ impl BitcoinRPCRequest {
         );
 
         let mut req = Request::new(Method::Post, url);
+        if let Some(auth_token) = BitcoinRPCRequest::authorization(config)? {
+            req.append_header("Authorization", auth_token);
+        }
+        Ok(req)
+    }
 
-        match (&config.burnchain.username, &config.burnchain.password) {
-            (Some(username), Some(password)) => {
-                let auth_token = format!("Basic {}", encode(format!("{}:{}", username, password)));
-                req.append_header("Authorization", auth_token);
-            }
-            (_, _) => {}
+    /// Get the `Authorization` header value for requests to bitcoind, if any:  basic auth with
+    /// the configured username and password, or else with bitcoind's RPC cookie if its datadir
+    /// is configured.  The cookie file is read anew every time.
+    fn authorization(config: &Config) -> RPCResult<Option<String>> {
+        let (username, password) = match (&config.burnchain.username, &config.burnchain.password) {
+            (Some(username), Some(password)) => (username.clone(), password.clone()),
+            (_, _) => match config.burnchain.get_rpc_cookie_path() {
+                Some(cookie_path) => BitcoinRPCRequest::read_rpc_cookie(&cookie_path)?,
+                None => {
+                    return Ok(None);
+                }
+            },
         };
-        req
+        Ok(Some(format!(
+            "Basic {}",
+            encode(format!("{}:{}", username, password))
+        )))
+    }
+
+    /// Read the (username, password) that bitcoind wrote to its RPC cookie file, which has the
+    /// form `__cookie__:<password>`.
+    fn read_rpc_cookie(cookie_path: &Path) -> RPCResult<(String, String)> {
+        let cookie = fs::read_to_string(cookie_path).map_err(|e| {
+            RPCError::Config(format!(
+                "Failed to read bitcoind RPC cookie file {}: {:?}",
+                cookie_path.display(),
+                &e
+            ))
+        })?;
+        let mut parts = cookie.trim_end().splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(username), Some(password)) if username == "__cookie__" && password.len() > 0 => {
+                Ok((username.to_string(), password.to_string()))
+            }
+            _ => Err(RPCError::Config(format!(
+                "Malformed bitcoind RPC cookie file {}",
+                cookie_path.display()
+            ))),
+        }
     }
 
     #[cfg(test)]
@@ -2170,7 +2427,7 @@ impl BitcoinRPCRequest {
             id: "stacks".to_string(),
             jsonrpc: "2.0".to_string(),
         };
-        let res = BitcoinRPCRequest::send(&config, payload)?;
+        let res = BitcoinRPCRequest::send_with_retries(&config, payload)?;
         debug!("Got raw transaction {}: {:?}", txid, &res);
         Ok(res.get("result").unwrap().as_str().unwrap().to_string())
     }
@@ -2200,27 +2457,9 @@ impl BitcoinRPCRequest {
         utxos_to_exclude: &Option<UTXOSet>,
         block_height: u64,
     ) -> RPCResult<UTXOSet> {
-        let payload = BitcoinRPCRequest {
-            method: "getblockhash".to_string(),
-            params: vec![block_height.into()],
-            id: "stacks".to_string(),
-            jsonrpc: "2.0".to_string(),
-        };
-
-        let mut res = BitcoinRPCRequest::send(&config, payload)?;
-        let bhh = match res.as_object_mut() {
-            Some(res) => {
-                let res = res
-                    .get("result")
-                    .ok_or(RPCError::Parsing("Failed to get bestblockhash".to_string()))?;
-                let bhh: String = serde_json::from_value(res.to_owned())
-                    .map_err(|_| RPCError::Parsing("Failed to get bestblockhash".to_string()))?;
-                let bhh = BurnchainHeaderHash::from_hex(&bhh)
-                    .map_err(|_| RPCError::Parsing("Failed to get bestblockhash".to_string()))?;
-                Ok(bhh)
-            }
-            _ => return Err(RPCError::Parsing("Failed to get UTXOs".to_string())),
-        }?;
+        let bhh = BitcoinRPCRequest::get_block_hashes(config, &[block_height])?
+            .pop()
+            .ok_or(RPCError::Parsing("Failed to get block hash".to_string()))??;
 
         let min_conf = 0i64;
         let max_conf = 9999999i64;
@@ -2239,7 +2478,7 @@ impl BitcoinRPCRequest {
             jsonrpc: "2.0".to_string(),
         };
 
-        let mut res = BitcoinRPCRequest::send(&config, payload)?;
+        let mut res = BitcoinRPCRequest::send_with_retries(&config, payload)?;
         let txids_to_filter = if let Some(utxos_to_exclude) = utxos_to_exclude {
             utxos_to_exclude
                 .utxos
@@ -2380,7 +2619,7 @@ impl BitcoinRPCRequest {
             jsonrpc: "2.0".to_string(),
         };
 
-        let mut res = BitcoinRPCRequest::send(&config, payload)?;
+        let mut res = BitcoinRPCRequest::send_with_retries(&config, payload)?;
         let mut wallets = Vec::new();
         match res.as_object_mut() {
             Some(ref mut object) => match object.get_mut("result") {
@@ -2422,70 +2661,611 @@ impl BitcoinRPCRequest {
         Ok(())
     }
 
-    fn send(config: &Config, payload: BitcoinRPCRequest) -> RPCResult<serde_json::Value> {
-        let mut request = BitcoinRPCRequest::build_rpc_request(&config, &payload);
+    /// Get the hashes of the blocks at the given heights in one batched RPC request.
+    /// The hashes are returned in the same order as `heights`; a height bitcoind doesn't have a
+    /// block for yields an error for that height alone.
+    pub fn get_block_hashes(
+        config: &Config,
+        heights: &[u64],
+    ) -> RPCResult<Vec<RPCResult<BurnchainHeaderHash>>> {
+        let payloads = heights
+            .iter()
+            .map(|height| BitcoinRPCRequest {
+                method: "getblockhash".to_string(),
+                params: vec![(*height).into()],
+                id: format!("stacks-{}", height),
+                jsonrpc: "2.0".to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        let results = BitcoinRPCRequest::with_retries(config, || {
+            BitcoinRPCRequest::batch_call(config, payloads.clone())
+        })?;
+        Ok(results
+            .into_iter()
+            .map(|res| {
+                let result = res?;
+                let hash_str = result.as_str().ok_or_else(|| {
+                    RPCError::Parsing(format!("Bitcoin RPC: expected block hash, got {}", &result))
+                })?;
+                BurnchainHeaderHash::from_hex(hash_str).map_err(|_| {
+                    RPCError::Parsing(format!("Bitcoin RPC: invalid block hash {}", hash_str))
+                })
+            })
+            .collect())
+    }
+
+    /// Send several RPC requests to bitcoind in one JSON-RPC batch, and match up the responses
+    /// by ID.  The outer result fails only if the batch as a whole fails; each request's
+    /// `result`, or its `error` as an `RPCError::Bitcoind`, is returned in request order.
+    /// Request IDs must be unique within the batch.  The first request determines which wallet
+    /// endpoint (if any) the batch is sent to.
+    pub fn batch_call(
+        config: &Config,
+        payloads: Vec<BitcoinRPCRequest>,
+    ) -> RPCResult<Vec<RPCResult<serde_json::Value>>> {
+        if payloads.len() == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut seen_ids = HashSet::new();
+        for payload in payloads.iter() {
+            if !seen_ids.insert(payload.id.clone()) {
+                return Err(RPCError::Parsing(format!(
+                    "Bitcoin RPC: duplicate request ID '{}' in batch",
+                    &payload.id
+                )));
+            }
+        }
+
+        let body = match serde_json::to_vec(&json!(payloads)) {
+            Ok(body) => body,
+            Err(err) => {
+                return Err(RPCError::Network(format!("RPC Error: {}", err)));
+            }
+        };
+
+        let response = BitcoinRPCRequest::send_body(config, &payloads[0], body)?;
+        let ids: Vec<serde_json::Value> = payloads
+            .iter()
+            .map(|payload| payload.id.clone().into())
+            .collect();
+        let responses = split_batch_replies(&ids, response)
+            .map_err(|e| RPCError::Parsing(format!("Bitcoin RPC: {}", e)))?;
 
+        Ok(responses
+            .into_iter()
+            .zip(payloads.iter())
+            .map(|(response, payload)| match response {
+                BatchReply::Result(result) => Ok(result),
+                BatchReply::Error(error) => Err(RPCError::Bitcoind(error.to_string())),
+                BatchReply::Missing => Err(RPCError::Parsing(format!(
+                    "Bitcoin RPC: no response for request ID '{}' in batch",
+                    &payload.id
+                ))),
+            })
+            .collect())
+    }
+
+    /// Run an idempotent RPC operation, retrying it with exponential backoff up to
+    /// `rpc_retries` times if it fails in transit.  Errors from bitcoind itself (e.g. a block
+    /// that doesn't exist) and unparseable responses are not retried.
+    fn with_retries<T, F>(config: &Config, op: F) -> RPCResult<T>
+    where
+        F: FnMut() -> RPCResult<T>,
+    {
+        retry_rpc_call(
+            config.burnchain.rpc_retries,
+            |e| matches!(e, RPCError::Network(_)),
+            op,
+        )
+    }
+
+    /// Send an idempotent RPC request to bitcoind, retrying it if it fails in transit
+    fn send_with_retries(
+        config: &Config,
+        payload: BitcoinRPCRequest,
+    ) -> RPCResult<serde_json::Value> {
+        BitcoinRPCRequest::with_retries(config, || BitcoinRPCRequest::send(config, payload.clone()))
+    }
+
+    fn send(config: &Config, payload: BitcoinRPCRequest) -> RPCResult<serde_json::Value> {
         let body = match serde_json::to_vec(&json!(payload)) {
             Ok(body) => body,
             Err(err) => {
                 return Err(RPCError::Network(format!("RPC Error: {}", err)));
             }
         };
-        request.append_header("Content-Type", "application/json");
-        request.set_body(body);
-
-        let mut response = async_std::task::block_on(async move {
-            let stream = match TcpStream::connect(config.burnchain.get_rpc_socket_addr()).await {
-                Ok(stream) => stream,
-                Err(err) => {
-                    return Err(RPCError::Network(format!(
-                        "Bitcoin RPC: connection failed - {:?}",
-                        err
-                    )))
-                }
-            };
+        BitcoinRPCRequest::send_body(config, &payload, body)
+    }
 
-            match client::connect(stream, request).await {
-                Ok(response) => Ok(response),
-                Err(err) => {
-                    return Err(RPCError::Network(format!(
-                        "Bitcoin RPC: invoking procedure failed - {:?}",
-                        err
+    /// Send an RPC request body to bitcoind, at the endpoint and with the credentials for
+    /// `payload`.
+    /// With cookie authentication, the cookie file is read for every request, and a request
+    /// that bitcoind rejects as unauthorized is retried once -- bitcoind writes a new cookie
+    /// when it restarts.
+    fn send_body(
+        config: &Config,
+        payload: &BitcoinRPCRequest,
+        body: Vec<u8>,
+    ) -> RPCResult<serde_json::Value> {
+        let rpc_timeout = config.burnchain.get_rpc_timeout();
+        let mut can_retry_auth =
+            config.burnchain.username.is_none() || config.burnchain.password.is_none();
+        loop {
+            let mut request = BitcoinRPCRequest::build_rpc_request(&config, payload)?;
+            request.append_header("Content-Type", "application/json");
+            request.set_body(body.clone());
+
+            let mut response = async_std::task::block_on(async move {
+                async_std::future::timeout(rpc_timeout, async move {
+                    let stream =
+                        match TcpStream::connect(config.burnchain.get_rpc_socket_addr()).await {
+                            Ok(stream) => stream,
+                            Err(err) => {
+                                return Err(RPCError::Network(format!(
+                                    "Bitcoin RPC: connection failed - {:?}",
+                                    err
+                                )))
+                            }
+                        };
+
+                    match client::connect(stream, request).await {
+                        Ok(response) => Ok(response),
+                        Err(err) => {
+                            return Err(RPCError::Network(format!(
+                                "Bitcoin RPC: invoking procedure failed - {:?}",
+                                err
+                            )))
+                        }
+                    }
+                })
+                .await
+                .unwrap_or_else(|_| {
+                    Err(RPCError::Network(format!(
+                        "Bitcoin RPC: no response within {:?}",
+                        rpc_timeout
                     )))
+                })
+            })?;
+
+            let status = response.status();
+            if status == StatusCode::Unauthorized
+                && can_retry_auth
+                && config.burnchain.get_rpc_cookie_path().is_some()
+            {
+                warn!("Bitcoin RPC: cookie authentication failed; re-reading the cookie file");
+                can_retry_auth = false;
+                continue;
+            }
+
+            let (res, buffer) = async_std::task::block_on(async move {
+                let mut buffer = Vec::new();
+                let mut body = response.take_body();
+                let res = async_std::future::timeout(rpc_timeout, body.read_to_end(&mut buffer))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("no response body within {:?}", rpc_timeout),
+                        ))
+                    });
+                (res, buffer)
+            });
+
+            if !status.is_success() {
+                // bitcoind reports some application errors (e.g. a missing block) with an
+                // HTTP error status and a JSON-RPC error body
+                if let Ok(json_resp) = serde_json::from_slice::<serde_json::Value>(&buffer[..]) {
+                    if let Some(e) = json_resp.get("error") {
+                        if !e.is_null() {
+                            return Err(RPCError::Bitcoind(e.to_string()));
+                        }
+                    }
                 }
+                return Err(RPCError::Network(format!(
+                    "Bitcoin RPC: status({}) != success, body is '{:?}'",
+                    status,
+                    match serde_json::from_slice::<serde_json::Value>(&buffer[..]) {
+                        Ok(v) => v,
+                        Err(_e) => serde_json::from_str("\"(unparseable)\"")
+                            .expect("Failed to parse JSON literal"),
+                    }
+                )));
             }
-        })?;
 
-        let status = response.status();
+            if res.is_err() {
+                return Err(RPCError::Network(format!(
+                    "Bitcoin RPC: unable to read body - {:?}",
+                    res
+                )));
+            }
 
-        let (res, buffer) = async_std::task::block_on(async move {
-            let mut buffer = Vec::new();
-            let mut body = response.take_body();
-            let res = body.read_to_end(&mut buffer).await;
-            (res, buffer)
-        });
+            let payload = serde_json::from_slice::<serde_json::Value>(&buffer[..])
+                .map_err(|e| RPCError::Parsing(format!("Bitcoin RPC: {}", e)))?;
+            return Ok(payload);
+        }
+    }
+}
 
-        if !status.is_success() {
-            return Err(RPCError::Network(format!(
-                "Bitcoin RPC: status({}) != success, body is '{:?}'",
-                status,
-                match serde_json::from_slice::<serde_json::Value>(&buffer[..]) {
-                    Ok(v) => v,
-                    Err(_e) => serde_json::from_str("\"(unparseable)\"")
-                        .expect("Failed to parse JSON literal"),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use std::time::Duration;
+
+    fn make_cookie_config(test_name: &str, cookie: &str) -> Config {
+        let datadir = PathBuf::from(format!("/tmp/{}", test_name));
+        if fs::metadata(&datadir).is_ok() {
+            fs::remove_dir_all(&datadir).unwrap();
+        }
+        let mut cookie_dir = datadir.clone();
+        cookie_dir.push("regtest");
+        fs::create_dir_all(&cookie_dir).unwrap();
+        cookie_dir.push(".cookie");
+        fs::write(&cookie_dir, cookie).unwrap();
+
+        let mut config = Config::default();
+        config.burnchain.mode = "mocknet".to_string();
+        config.burnchain.username = None;
+        config.burnchain.password = None;
+        config.burnchain.bitcoind_datadir = Some(datadir.to_str().unwrap().to_string());
+        config
+    }
+
+    fn make_payload() -> BitcoinRPCRequest {
+        BitcoinRPCRequest {
+            method: "getblockchaininfo".to_string(),
+            params: vec![],
+            id: "stacks".to_string(),
+            jsonrpc: "2.0".to_string(),
+        }
+    }
+
+    fn get_auth_header(config: &Config) -> RPCResult<Option<String>> {
+        let request = BitcoinRPCRequest::build_rpc_request(config, &make_payload())?;
+        Ok(request
+            .header("Authorization")
+            .map(|values| values.last().as_str().to_string()))
+    }
+
+    #[test]
+    fn test_rpc_cookie_auth() {
+        let config = make_cookie_config(
+            "test_rpc_cookie_auth",
+            "__cookie__:4c3b2a1f0e9d8c7b6a5f4e3d2c1b0a9f\n",
+        );
+        assert_eq!(
+            config.burnchain.get_rpc_cookie_path().unwrap(),
+            PathBuf::from("/tmp/test_rpc_cookie_auth/regtest/.cookie")
+        );
+        assert_eq!(
+            get_auth_header(&config).unwrap(),
+            Some(format!(
+                "Basic {}",
+                encode("__cookie__:4c3b2a1f0e9d8c7b6a5f4e3d2c1b0a9f")
+            ))
+        );
+
+        // a new cookie is picked up on the next request
+        fs::write(
+            config.burnchain.get_rpc_cookie_path().unwrap(),
+            "__cookie__:0123456789abcdef",
+        )
+        .unwrap();
+        assert_eq!(
+            get_auth_header(&config).unwrap(),
+            Some(format!("Basic {}", encode("__cookie__:0123456789abcdef")))
+        );
+
+        // a static username and password take precedence
+        let mut static_config = config.clone();
+        static_config.burnchain.username = Some("blockstack".to_string());
+        static_config.burnchain.password = Some("blockstacksystem".to_string());
+        assert_eq!(
+            get_auth_header(&static_config).unwrap(),
+            Some(format!("Basic {}", encode("blockstack:blockstacksystem")))
+        );
+
+        // no credentials at all
+        let mut no_auth_config = config.clone();
+        no_auth_config.burnchain.bitcoind_datadir = None;
+        assert_eq!(get_auth_header(&no_auth_config).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rpc_cookie_auth_errors() {
+        let mut config = make_cookie_config("test_rpc_cookie_auth_errors", "");
+        for bad_cookie in ["", "__cookie__", "__cookie__:", "alice:hunter2"].iter() {
+            fs::write(config.burnchain.get_rpc_cookie_path().unwrap(), bad_cookie).unwrap();
+            match get_auth_header(&config) {
+                Err(RPCError::Config(_)) => {}
+                res => panic!("Malformed cookie {:?} accepted: {:?}", bad_cookie, res),
+            }
+        }
+
+        config.burnchain.bitcoind_datadir =
+            Some("/tmp/test_rpc_cookie_auth_errors/nonexistent".to_string());
+        match get_auth_header(&config) {
+            Err(RPCError::Config(_)) => {}
+            res => panic!("Missing cookie file accepted: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_indexer_rpc_credentials() {
+        let config = make_cookie_config("test_indexer_rpc_credentials", "__cookie__:0123456789\n");
+        let credentials = BitcoindRpcCredentials {
+            config: config.clone(),
+        };
+        let mut endpoint = RpcEndpoint {
+            host: "127.0.0.1".to_string(),
+            port: 18443,
+            username: None,
+            password: None,
+        };
+
+        // the indexer authenticates like BitcoinRPCRequest does...
+        assert_eq!(
+            credentials.authorization(&endpoint).unwrap(),
+            get_auth_header(&config).unwrap()
+        );
+        assert_eq!(
+            credentials.authorization(&endpoint).unwrap(),
+            Some(format!("Basic {}", encode("__cookie__:0123456789")))
+        );
+
+        // ...unless the endpoint has credentials of its own
+        endpoint.username = Some("alice".to_string());
+        endpoint.password = Some("hunter2".to_string());
+        assert_eq!(
+            credentials.authorization(&endpoint).unwrap(),
+            Some(format!("Basic {}", encode("alice:hunter2")))
+        );
+
+        fs::remove_file(config.burnchain.get_rpc_cookie_path().unwrap()).unwrap();
+        endpoint.username = None;
+        match credentials.authorization(&endpoint) {
+            Err(btc_error::ConfigError(_)) => {}
+            res => panic!("Missing cookie file accepted: {:?}", res),
+        }
+    }
+
+    /// Read an HTTP request from `sock`, and return its body
+    fn read_mock_rpc_request(sock: &mut TcpStream) -> Vec<u8> {
+        let mut request = vec![];
+        let mut buf = [0u8; 4096];
+        let body_start = loop {
+            let nread = sock.read(&mut buf).unwrap();
+            assert!(nread > 0, "connection closed before end of headers");
+            request.extend_from_slice(&buf[..nread]);
+            if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+        let headers = String::from_utf8(request[..body_start].to_vec()).unwrap();
+        let content_length: usize = headers
+            .lines()
+            .find_map(|line| {
+                let mut parts = line.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) if name.eq_ignore_ascii_case("content-length") => {
+                        Some(value.trim().parse().unwrap())
+                    }
+                    _ => None,
                 }
-            )));
+            })
+            .expect("no Content-Length in request");
+        while request.len() < body_start + content_length {
+            let nread = sock.read(&mut buf).unwrap();
+            assert!(nread > 0, "connection closed before end of body");
+            request.extend_from_slice(&buf[..nread]);
         }
+        request[body_start..body_start + content_length].to_vec()
+    }
 
-        if res.is_err() {
-            return Err(RPCError::Network(format!(
-                "Bitcoin RPC: unable to read body - {:?}",
-                res
-            )));
+    fn write_mock_rpc_response(sock: &mut TcpStream, status: &str, response: &str) {
+        let reply = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            response.len(),
+            response
+        );
+        sock.write_all(reply.as_bytes()).unwrap();
+        sock.flush().unwrap();
+    }
+
+    /// Serve one HTTP request on a local port with the given JSON response body, and hand back
+    /// the JSON request body it received.
+    fn spawn_mock_rpc_server(response: String) -> (u16, thread::JoinHandle<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let body = read_mock_rpc_request(&mut sock);
+            write_mock_rpc_response(&mut sock, "200 OK", &response);
+            serde_json::from_slice(&body).unwrap()
+        });
+        (port, handle)
+    }
+
+    /// Serve HTTP requests on a local port forever, handing each connection and its index to
+    /// `handler`.  Returns the port, and the number of connections accepted so far.
+    fn spawn_counting_rpc_server<F>(handler: F) -> (u16, Arc<AtomicUsize>)
+    where
+        F: Fn(usize, &mut TcpStream) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let count = Arc::new(AtomicUsize::new(0));
+        let thread_count = count.clone();
+        thread::spawn(move || loop {
+            let (mut sock, _) = listener.accept().unwrap();
+            let i = thread_count.fetch_add(1, Ordering::SeqCst);
+            handler(i, &mut sock);
+        });
+        (port, count)
+    }
+
+    fn make_mock_rpc_config(port: u16) -> Config {
+        let mut config = Config::default();
+        config.burnchain.peer_host = "127.0.0.1".to_string();
+        config.burnchain.rpc_port = port;
+        config.burnchain.username = Some("blockstack".to_string());
+        config.burnchain.password = Some("blockstacksystem".to_string());
+        config
+    }
+
+    #[test]
+    fn test_rpc_batch_call() {
+        let hash_1 = "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
+        let hash_3 = "6f2e4ba7b6efc4e3aa1cf0ba5c22d2b7e2a6c8d9e5a7f2c3b4d5e6f708192a3b";
+        // responses arrive out of order, with an error for height 2 and an unknown ID
+        let response = json!([
+            { "result": hash_3, "error": null, "id": "stacks-3" },
+            { "result": null, "error": { "code": -8, "message": "Block height out of range" }, "id": "stacks-2" },
+            { "result": "00", "error": null, "id": "not-ours" },
+            { "result": hash_1, "error": null, "id": "stacks-1" }
+        ]);
+        let (port, server) = spawn_mock_rpc_server(response.to_string());
+        let config = make_mock_rpc_config(port);
+
+        let hashes = BitcoinRPCRequest::get_block_hashes(&config, &[1, 2, 3]).unwrap();
+
+        // the requests went out in one batch
+        let request = server.join().unwrap();
+        let requests = request.as_array().unwrap();
+        assert_eq!(requests.len(), 3);
+        for (request, height) in requests.iter().zip([1u64, 2, 3].iter()) {
+            assert_eq!(request["method"], "getblockhash");
+            assert_eq!(request["params"], json!([height]));
+            assert_eq!(request["id"], format!("stacks-{}", height));
         }
 
-        let payload = serde_json::from_slice::<serde_json::Value>(&buffer[..])
-            .map_err(|e| RPCError::Parsing(format!("Bitcoin RPC: {}", e)))?;
-        Ok(payload)
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(
+            hashes[0].as_ref().unwrap(),
+            &BurnchainHeaderHash::from_hex(hash_1).unwrap()
+        );
+        match &hashes[1] {
+            Err(RPCError::Bitcoind(e)) => assert!(e.contains("Block height out of range")),
+            res => panic!("expected an error for height 2, got {:?}", res),
+        }
+        assert_eq!(
+            hashes[2].as_ref().unwrap(),
+            &BurnchainHeaderHash::from_hex(hash_3).unwrap()
+        );
+
+        // a missing response fails just that request
+        let response = json!([{ "result": hash_1, "error": null, "id": "stacks-1" }]);
+        let (port, server) = spawn_mock_rpc_server(response.to_string());
+        let hashes =
+            BitcoinRPCRequest::get_block_hashes(&make_mock_rpc_config(port), &[1, 2]).unwrap();
+        server.join().unwrap();
+        assert!(hashes[0].is_ok());
+        match &hashes[1] {
+            Err(RPCError::Parsing(_)) => {}
+            res => panic!("expected a missing response, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_rpc_batch_call_errors() {
+        let config = make_mock_rpc_config(1);
+        assert_eq!(
+            BitcoinRPCRequest::batch_call(&config, vec![])
+                .unwrap()
+                .len(),
+            0
+        );
+        match BitcoinRPCRequest::batch_call(&config, vec![make_payload(), make_payload()]) {
+            Err(RPCError::Parsing(_)) => {}
+            res => panic!("expected duplicate IDs to be rejected, got {:?}", res),
+        }
+
+        // bitcoind rejects the whole batch
+        let response = json!({
+            "result": null,
+            "error": { "code": -32700, "message": "Parse error" },
+            "id": null
+        });
+        let (port, server) = spawn_mock_rpc_server(response.to_string());
+        match BitcoinRPCRequest::get_block_hashes(&make_mock_rpc_config(port), &[1, 2]) {
+            Err(RPCError::Parsing(_)) => {}
+            res => panic!("expected a non-batch response to fail, got {:?}", res),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_rpc_timeout() {
+        // bitcoind hangs after reading the request
+        let (port, count) = spawn_counting_rpc_server(|_, sock| {
+            read_mock_rpc_request(sock);
+            thread::sleep(Duration::from_secs(10));
+        });
+        let mut config = make_mock_rpc_config(port);
+        config.burnchain.rpc_timeout_secs = 1;
+        config.burnchain.rpc_retries = 0;
+
+        let start = Instant::now();
+        match BitcoinRPCRequest::send(&config, make_payload()) {
+            Err(RPCError::Network(_)) => {}
+            res => panic!("expected a timeout, got {:?}", res),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_rpc_retries() {
+        let response = json!({ "result": 123, "error": null, "id": "stacks" }).to_string();
+
+        // the first two connections drop before responding
+        let (port, count) = spawn_counting_rpc_server(move |i, sock| {
+            read_mock_rpc_request(sock);
+            if i >= 2 {
+                write_mock_rpc_response(sock, "200 OK", &response);
+            }
+        });
+        let config = make_mock_rpc_config(port);
+        assert_eq!(config.burnchain.rpc_retries, 3);
+        let res = BitcoinRPCRequest::send_with_retries(&config, make_payload()).unwrap();
+        assert_eq!(res["result"], 123);
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+
+        // give up after the configured number of retries
+        let (port, count) = spawn_counting_rpc_server(|_, sock| {
+            read_mock_rpc_request(sock);
+        });
+        let mut config = make_mock_rpc_config(port);
+        config.burnchain.rpc_retries = 2;
+        match BitcoinRPCRequest::send_with_retries(&config, make_payload()) {
+            Err(RPCError::Network(_)) => {}
+            res => panic!("expected a network error, got {:?}", res),
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+
+        // errors from bitcoind are not retried
+        let not_found = json!({
+            "result": null,
+            "error": { "code": -5, "message": "Block not found" },
+            "id": "stacks"
+        })
+        .to_string();
+        let (port, count) = spawn_counting_rpc_server(move |_, sock| {
+            read_mock_rpc_request(sock);
+            write_mock_rpc_response(sock, "404 Not Found", &not_found);
+        });
+        match BitcoinRPCRequest::send_with_retries(&make_mock_rpc_config(port), make_payload()) {
+            Err(RPCError::Bitcoind(e)) => assert!(e.contains("Block not found")),
+            res => panic!("expected a bitcoind error, got {:?}", res),
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 1);
     }
 }
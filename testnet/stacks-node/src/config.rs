@@ -4,9 +4,12 @@ use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 
 use rand::RngCore;
 
+use stacks::burnchains::bitcoin::indexer::DEFAULT_MAX_REORG_DEPTH;
+use stacks::burnchains::bitcoin::rpc::DEFAULT_RPC_RETRIES;
 use stacks::burnchains::bitcoin::BitcoinNetworkType;
 use stacks::burnchains::Burnchain;
 use stacks::burnchains::{MagicBytes, BLOCKSTACK_MAGIC_MAINNET};
@@ -521,7 +524,9 @@ impl Config {
             BitcoinNetworkType::Mainnet => {
                 Err("Cannot configure epochs in mainnet mode".to_string())
             }
-            BitcoinNetworkType::Testnet => Ok(stacks::core::STACKS_EPOCHS_TESTNET.to_vec()),
+            BitcoinNetworkType::Testnet | BitcoinNetworkType::Signet => {
+                Ok(stacks::core::STACKS_EPOCHS_TESTNET.to_vec())
+            }
             BitcoinNetworkType::Regtest => Ok(stacks::core::STACKS_EPOCHS_REGTEST.to_vec()),
         }?;
         let mut matched_epochs = vec![];
@@ -757,6 +762,16 @@ impl Config {
                     }
                 }
 
+                // bitcoind listens on different default ports on signet
+                let (default_peer_port, default_rpc_port) = if &burnchain_mode == "signet" {
+                    (38333, 38332)
+                } else {
+                    (
+                        default_burnchain_config.peer_port,
+                        default_burnchain_config.rpc_port,
+                    )
+                };
+
                 let mut result = BurnchainConfig {
                     chain: burnchain.chain.unwrap_or(default_burnchain_config.chain),
                     chain_id: if &burnchain_mode == "mainnet" {
@@ -797,17 +812,20 @@ impl Config {
                         }
                         None => default_burnchain_config.peer_host,
                     },
-                    peer_port: burnchain
-                        .peer_port
-                        .unwrap_or(default_burnchain_config.peer_port),
-                    rpc_port: burnchain
-                        .rpc_port
-                        .unwrap_or(default_burnchain_config.rpc_port),
+                    peer_port: burnchain.peer_port.unwrap_or(default_peer_port),
+                    rpc_port: burnchain.rpc_port.unwrap_or(default_rpc_port),
                     rpc_ssl: burnchain
                         .rpc_ssl
                         .unwrap_or(default_burnchain_config.rpc_ssl),
                     username: burnchain.username,
                     password: burnchain.password,
+                    bitcoind_datadir: burnchain.bitcoind_datadir,
+                    rpc_timeout_secs: burnchain
+                        .rpc_timeout_secs
+                        .unwrap_or(default_burnchain_config.rpc_timeout_secs),
+                    rpc_retries: burnchain
+                        .rpc_retries
+                        .unwrap_or(default_burnchain_config.rpc_retries),
                     timeout: burnchain
                         .timeout
                         .unwrap_or(default_burnchain_config.timeout),
@@ -852,6 +870,15 @@ impl Config {
                     wallet_name: burnchain
                         .wallet_name
                         .unwrap_or(default_burnchain_config.wallet_name.clone()),
+                    // 0 means no maximum
+                    max_reorg_depth: match burnchain.max_reorg_depth {
+                        Some(0) => None,
+                        Some(max_reorg_depth) => Some(max_reorg_depth),
+                        None => default_burnchain_config.max_reorg_depth,
+                    },
+                    accept_deep_reorg: burnchain
+                        .accept_deep_reorg
+                        .unwrap_or(default_burnchain_config.accept_deep_reorg),
                 };
 
                 if let BitcoinNetworkType::Mainnet = result.get_bitcoin_network().1 {
@@ -915,7 +942,7 @@ impl Config {
         };
 
         let supported_modes = vec![
-            "mocknet", "helium", "neon", "argon", "krypton", "xenon", "mainnet",
+            "mocknet", "helium", "neon", "argon", "krypton", "xenon", "signet", "mainnet",
         ];
 
         if !supported_modes.contains(&burnchain.mode.as_str()) {
@@ -1334,6 +1361,13 @@ pub struct BurnchainConfig {
     pub rpc_ssl: bool,
     pub username: Option<String>,
     pub password: Option<String>,
+    /// bitcoind's data directory.  If set (and `username` and `password` are not), RPC requests
+    /// authenticate with the `.cookie` file bitcoind writes there.
+    pub bitcoind_datadir: Option<String>,
+    /// How long to wait to connect to bitcoind's RPC interface, and for each response
+    pub rpc_timeout_secs: u64,
+    /// How many times to retry an idempotent RPC request that failed in transit
+    pub rpc_retries: u32,
     pub timeout: u32,
     pub magic_bytes: MagicBytes,
     pub local_mining_public_key: Option<String>,
@@ -1352,6 +1386,11 @@ pub struct BurnchainConfig {
     pub sunset_end: Option<u32>,
     pub wallet_name: String,
     pub ast_precheck_size_height: Option<u64>,
+    /// Most burnchain blocks a reorg may undo before the node stops processing the burnchain
+    /// until the reorg is accepted.  None means no maximum.
+    pub max_reorg_depth: Option<u64>,
+    /// Accept the next reorg deeper than `max_reorg_depth` (e.g. with `--accept-deep-reorg`)
+    pub accept_deep_reorg: bool,
 }
 
 impl BurnchainConfig {
@@ -1369,6 +1408,9 @@ impl BurnchainConfig {
             rpc_ssl: false,
             username: None,
             password: None,
+            bitcoind_datadir: None,
+            rpc_timeout_secs: 60,
+            rpc_retries: DEFAULT_RPC_RETRIES,
             timeout: 300,
             magic_bytes: BLOCKSTACK_MAGIC_MAINNET.clone(),
             local_mining_public_key: None,
@@ -1385,6 +1427,8 @@ impl BurnchainConfig {
             sunset_end: None,
             wallet_name: "".to_string(),
             ast_precheck_size_height: None,
+            max_reorg_depth: Some(DEFAULT_MAX_REORG_DEPTH),
+            accept_deep_reorg: false,
         }
     }
 
@@ -1404,6 +1448,10 @@ impl BurnchainConfig {
         )
     }
 
+    pub fn get_rpc_timeout(&self) -> Duration {
+        Duration::from_secs(self.rpc_timeout_secs)
+    }
+
     pub fn get_rpc_socket_addr(&self) -> SocketAddr {
         let mut addrs_iter = format!("{}:{}", self.peer_host, self.rpc_port)
             .to_socket_addrs()
@@ -1412,14 +1460,29 @@ impl BurnchainConfig {
         sock_addr
     }
 
+    /// Path to bitcoind's RPC cookie file, if `bitcoind_datadir` is set.  bitcoind writes it to
+    /// a network-specific subdirectory of its datadir on testnet, regtest, and signet.
+    pub fn get_rpc_cookie_path(&self) -> Option<PathBuf> {
+        let mut path = PathBuf::from(self.bitcoind_datadir.as_ref()?);
+        match self.get_bitcoin_network().1 {
+            BitcoinNetworkType::Mainnet => {}
+            BitcoinNetworkType::Testnet => path.push("testnet3"),
+            BitcoinNetworkType::Regtest => path.push("regtest"),
+            BitcoinNetworkType::Signet => path.push("signet"),
+        }
+        path.push(".cookie");
+        Some(path)
+    }
+
     pub fn get_bitcoin_network(&self) -> (String, BitcoinNetworkType) {
         match self.mode.as_str() {
             "mainnet" => ("mainnet".to_string(), BitcoinNetworkType::Mainnet),
             "xenon" => ("testnet".to_string(), BitcoinNetworkType::Testnet),
+            "signet" => ("signet".to_string(), BitcoinNetworkType::Signet),
             "helium" | "neon" | "argon" | "krypton" | "mocknet" => {
                 ("regtest".to_string(), BitcoinNetworkType::Regtest)
             }
-            _ => panic!("Invalid bitcoin mode -- expected mainnet, testnet, or regtest"),
+            _ => panic!("Invalid bitcoin mode -- expected mainnet, testnet, regtest, or signet"),
         }
     }
 }
@@ -1450,6 +1513,9 @@ pub struct BurnchainConfigFile {
     pub rpc_ssl: Option<bool>,
     pub username: Option<String>,
     pub password: Option<String>,
+    pub bitcoind_datadir: Option<String>,
+    pub rpc_timeout_secs: Option<u64>,
+    pub rpc_retries: Option<u32>,
     pub timeout: Option<u32>,
     pub magic_bytes: Option<String>,
     pub local_mining_public_key: Option<String>,
@@ -1466,6 +1532,8 @@ pub struct BurnchainConfigFile {
     pub sunset_end: Option<u32>,
     pub wallet_name: Option<String>,
     pub ast_precheck_size_height: Option<u64>,
+    pub max_reorg_depth: Option<u64>,
+    pub accept_deep_reorg: Option<bool>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -84,6 +84,7 @@ fn main() {
     let mine_start: Option<u64> = args
         .opt_value_from_str("--mine-at-height")
         .expect("Failed to parse --mine-at-height argument");
+    let accept_deep_reorg = args.contains("--accept-deep-reorg");
 
     if let Some(mine_start) = mine_start {
         info!(
@@ -185,13 +186,16 @@ fn main() {
         }
     };
 
-    let conf = match Config::from_config_file(config_file) {
+    let mut conf = match Config::from_config_file(config_file) {
         Ok(conf) => conf,
         Err(e) => {
             warn!("Invalid config: {}", e);
             process::exit(1);
         }
     };
+    if accept_deep_reorg {
+        conf.burnchain.accept_deep_reorg = true;
+    }
     debug!("node configuration {:?}", &conf.node);
     debug!("burnchain configuration {:?}", &conf.burnchain);
     debug!("connection configuration {:?}", &conf.connection_options);
@@ -206,6 +210,7 @@ fn main() {
         }
     } else if conf.burnchain.mode == "neon"
         || conf.burnchain.mode == "xenon"
+        || conf.burnchain.mode == "signet"
         || conf.burnchain.mode == "krypton"
         || conf.burnchain.mode == "mainnet"
     {
@@ -273,6 +278,8 @@ OPTIONAL ARGUMENTS:
 
 \t\t--mine-at-height=<height>: optional argument for a miner to not attempt mining until Stacks block has sync'ed to <height>
 
+\t\t--accept-deep-reorg: optional argument to process the next burnchain reorg deeper than burnchain.max_reorg_depth, which the node otherwise halts on
+
 ", argv[0]);
 }
 
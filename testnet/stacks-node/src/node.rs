@@ -312,7 +312,9 @@ impl Node {
             .collect();
         let pox_constants = match config.burnchain.get_bitcoin_network() {
             (_, BitcoinNetworkType::Mainnet) => PoxConstants::mainnet_default(),
-            (_, BitcoinNetworkType::Testnet) => PoxConstants::testnet_default(),
+            (_, BitcoinNetworkType::Testnet) | (_, BitcoinNetworkType::Signet) => {
+                PoxConstants::testnet_default()
+            }
             (_, BitcoinNetworkType::Regtest) => PoxConstants::regtest_default(),
         };
 